@@ -0,0 +1,51 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+fn main() {
+    // builds without a git checkout (e.g. from a source tarball or in the nix sandbox) can pass
+    // the SHA in explicitly
+    let git_sha = std::env::var("RUNRS_GIT_SHA")
+        .ok()
+        .or_else(|| {
+            Command::new("git")
+                .args(["rev-parse", "--short=12", "HEAD"])
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .and_then(|output| String::from_utf8(output.stdout).ok())
+                .map(|sha| sha.trim().to_string())
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+
+    // honor SOURCE_DATE_EPOCH for reproducible builds
+    let build_timestamp = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs())
+        });
+
+    // every enabled feature, as named in Cargo.toml; Cargo passes them as `CARGO_FEATURE_<NAME>`
+    let mut features: Vec<String> = std::env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .filter(|feature| feature != "default")
+        .collect();
+    features.sort();
+
+    println!("cargo:rustc-env=RUNRS_GIT_SHA={git_sha}");
+    println!("cargo:rustc-env=RUNRS_BUILD_TIMESTAMP={build_timestamp}");
+    println!("cargo:rustc-env=RUNRS_FEATURES={}", features.join(","));
+    println!("cargo:rerun-if-env-changed=RUNRS_GIT_SHA");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
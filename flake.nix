@@ -82,6 +82,13 @@
 
         runrs-test = craneLib.cargoTest (commonArgs // { inherit cargoArtifacts; });
 
+        # The OpenAPI document of the API, generated at build time, so that clients can be
+        # generated from it without running the server.
+        runrs-openapi = pkgs.runCommand "runrs-openapi" { } ''
+          mkdir -p $out
+          ${runrs}/bin/runrs --openapi-json > $out/openapi.json
+        '';
+
         runrs-docker-image = pkgs.dockerTools.buildLayeredImage {
           name = "ghcr.io/bmc-labs/runrs";
           tag =
@@ -121,7 +128,7 @@
       {
         packages = {
           default = runrs;
-          inherit runrs runrs-docker-image;
+          inherit runrs runrs-openapi runrs-docker-image;
         };
 
         checks = {
@@ -0,0 +1,46 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use glrcfg::{runner::Runner, Config};
+
+/// Numbers of runners to serialize; runrs is meant to manage a few thousand of them.
+const RUNNER_COUNTS: [usize; 4] = [10, 100, 1_000, 10_000];
+
+fn config(runners: usize) -> Config {
+    Config::builder()
+        .with_runners(
+            (0..runners)
+                .map(|idx| Runner {
+                    id: idx as u32,
+                    name: format!("runner-{idx}"),
+                    ..Default::default()
+                })
+                .collect(),
+        )
+        .build()
+}
+
+fn serialize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("serialize");
+
+    for runners in RUNNER_COUNTS {
+        let config = config(runners);
+        group.throughput(Throughput::Elements(runners as u64));
+
+        group.bench_with_input(
+            BenchmarkId::new("to_toml_string", runners),
+            &config,
+            |b, config| b.iter(|| config.to_toml_string()),
+        );
+        group.bench_with_input(
+            BenchmarkId::new("write_to", runners),
+            &config,
+            |b, config| b.iter(|| config.write_to(std::io::sink())),
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, serialize);
+criterion_main!(benches);
@@ -0,0 +1,42 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+//! Prints an example config for the given executor, with most sections and keys populated, to show
+//! the exact TOML the types produce:
+//!
+//! ```sh
+//! cargo run -p glrcfg --example glrcfg-gen -- docker
+//! ```
+//!
+//! The examples are built by the `example` constructors of each section, e.g.
+//! [`Docker::example`](glrcfg::runner::Docker::example).
+
+use std::process::ExitCode;
+
+use glrcfg::{runner::Executor, Config};
+
+/// Executors examples are generated for, in the order they are listed in the usage.
+const EXECUTORS: [&str; 5] = [
+    "shell",
+    "docker",
+    "docker-windows",
+    "docker-autoscaler",
+    "kubernetes",
+];
+
+fn main() -> ExitCode {
+    let Some(name) = std::env::args().nth(1) else {
+        eprintln!("usage: glrcfg-gen <{}>", EXECUTORS.join("|"));
+        return ExitCode::from(2);
+    };
+
+    let executor = match Executor::example(&name) {
+        Ok(executor) => executor,
+        Err(err) => {
+            eprintln!("{err}; must be one of {}", EXECUTORS.join(", "));
+            return ExitCode::from(2);
+        }
+    };
+    print!("{}", Config::example(executor).to_toml_string());
+
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,182 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, PoisonError,
+    },
+};
+
+/// Serializes the atomic writes of the process, so that concurrent writes of a file replace it
+/// one after the other.
+static WRITE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Tells apart the temporary files of the writes of the process.
+static WRITE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Writes the file at `path` atomically: `write` fills a temporary file next to `path`, which is
+/// synced to disk and then renamed to `path`, so readers like `gitlab-runner` never see a
+/// partially written file. [`Config::write`](crate::Config::write) is built on this; use it
+/// directly for configs which are already serialized.
+///
+/// The temporary file has a name of its own for every write and is created with mode 0600, since
+/// configs hold runner tokens. If `path` exists, the file takes over its mode and, as far as the
+/// process may change it, its owner. If `path` is a symlink, the file it points to is replaced
+/// rather than the symlink. Writes within the process are serialized.
+///
+/// `write` may read back what it wrote, e.g. to validate it; the file is open for reading and
+/// writing.
+pub fn write_atomically<F>(path: &Path, write: F) -> io::Result<()>
+where
+    F: FnOnce(&mut fs::File) -> io::Result<()>,
+{
+    let _serialized = WRITE_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+
+    let path = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => fs::canonicalize(path)?,
+        _ => path.to_path_buf(),
+    };
+    let replaced = fs::metadata(&path).ok();
+    let tmp_path = tmp_path(&path);
+
+    let written = create_private(&tmp_path).and_then(|mut file| {
+        if let Some(replaced) = &replaced {
+            take_over(&file, replaced)?;
+        }
+        write(&mut file)?;
+        file.sync_all()
+    });
+    if let Err(err) = written.and_then(|()| fs::rename(&tmp_path, &path)) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(err);
+    }
+
+    // the rename is only durable once the directory entry is; failing to sync the directory
+    // doesn't undo it, so this is not an error
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let _synced = fs::File::open(dir).and_then(|dir| dir.sync_all());
+    #[cfg(feature = "tracing")]
+    if let Err(err) = _synced {
+        tracing::warn!(%err, ?dir, "syncing directory failed");
+    }
+
+    Ok(())
+}
+
+/// Temporary file for writing `path` atomically; it lives in the same directory, so that renaming
+/// it to `path` doesn't cross file systems.
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut file_name = std::ffi::OsString::from(".");
+    file_name.push(path.file_name().unwrap_or_default());
+    file_name.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        WRITE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    path.with_file_name(file_name)
+}
+
+/// Creates a file only the owner may read, failing if it exists already.
+fn create_private(path: &Path) -> io::Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+    options.read(true).write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options.open(path)
+}
+
+/// Gives `file` the owner and mode of the file it is going to replace.
+fn take_over(file: &fs::File, replaced: &fs::Metadata) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt as _;
+
+        // only privileged processes may hand files over to others; anyone else writes their own
+        // files, as they would have without the temporary file
+        let _ = std::os::unix::fs::fchown(file, Some(replaced.uid()), Some(replaced.gid()));
+    }
+
+    file.set_permissions(replaced.permissions())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Write as _, path::Path};
+
+    use pretty_assertions::assert_eq;
+
+    use super::write_atomically;
+
+    fn entries(dir: &Path) -> Vec<String> {
+        let mut entries: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    #[test]
+    fn concurrent_writes_replace_whole_files() {
+        let dir = std::env::temp_dir().join(format!("glrcfg-atomic-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+
+        let contents: Vec<String> = (0..8)
+            .map(|idx| format!("concurrent = {idx}\n").repeat(1_000))
+            .collect();
+        std::thread::scope(|scope| {
+            for content in &contents {
+                let path = &path;
+                scope.spawn(move || {
+                    write_atomically(path, |file| file.write_all(content.as_bytes())).unwrap()
+                });
+            }
+        });
+
+        assert!(contents.contains(&std::fs::read_to_string(&path).unwrap()));
+        assert_eq!(entries(&dir), vec!["config.toml"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keep_mode_and_symlinks() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let dir = std::env::temp_dir().join(format!("glrcfg-mode-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (path, link) = (dir.join("config.toml"), dir.join("link.toml"));
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        // new files are only readable by their owner, whatever the umask
+        write_atomically(&path, |file| file.write_all(b"concurrent = 1\n")).unwrap();
+        assert_eq!(mode(&path), 0o600);
+
+        // existing files keep their mode, and symlinks keep pointing at them
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        std::os::unix::fs::symlink(&path, &link).unwrap();
+        write_atomically(&link, |file| file.write_all(b"concurrent = 2\n")).unwrap();
+        assert_eq!(mode(&path), 0o640);
+        assert!(std::fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "concurrent = 2\n");
+
+        // a failed write leaves the file as it was, and no temporary file behind
+        let failed = write_atomically(&path, |_| Err(std::io::ErrorKind::InvalidData.into()));
+        assert!(failed.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "concurrent = 2\n");
+        assert_eq!(entries(&dir), vec!["config.toml", "link.toml"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
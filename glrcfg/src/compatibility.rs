@@ -0,0 +1,312 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{fmt, str::FromStr};
+
+use thiserror::Error;
+
+/// Configuration keys which were introduced after GitLab Runner 15.0, together with the version
+/// of `gitlab-runner` which first understands them. Paths descend into arrays of tables
+/// transparently, i.e. `["runners", "docker", "allowed_pull_policies"]` matches the key in every
+/// `[runners.docker]` section.
+///
+/// Sources are the "Introduced in GitLab Runner x.y" notes in [the GitLab
+/// docs](https://docs.gitlab.com/runner/configuration/advanced-configuration.html).
+static INTRODUCED_KEYS: &[IntroducedKey] = &[
+    IntroducedKey {
+        path: &["connection_max_age"],
+        since: RunnerVersion::new(15, 8),
+    },
+    IntroducedKey {
+        path: &["runners", "docker", "allowed_pull_policies"],
+        since: RunnerVersion::new(15, 1),
+    },
+];
+
+struct IntroducedKey {
+    path: &'static [&'static str],
+    since: RunnerVersion,
+}
+
+/// Configuration keys which were renamed after GitLab Runner 15.0, together with their previous
+/// name and the version of `gitlab-runner` which first understands the current name. For older
+/// targets the value is written under the previous name instead of being left out.
+///
+/// Sources are the deprecation notes in [the GitLab
+/// docs](https://docs.gitlab.com/runner/configuration/advanced-configuration.html).
+static RENAMED_KEYS: &[RenamedKey] = &[RenamedKey {
+    path: &["runners", "cache", "s3", "RoleARN"],
+    previous: "UploadRoleARN",
+    since: RunnerVersion::new(17, 4),
+}];
+
+struct RenamedKey {
+    path: &'static [&'static str],
+    previous: &'static str,
+    since: RunnerVersion,
+}
+
+#[derive(Debug, PartialEq, Eq, Error)]
+#[error("invalid GitLab Runner version `{0}`; must look like 16 or 16.4")]
+pub struct RunnerVersionParseError(String);
+
+/// The version of `gitlab-runner` a [`Config`](crate::Config) is generated for. Configuration keys
+/// differ between the 15.x, 16.x and 17.x releases; when a target version is set on the
+/// [`ConfigBuilder`](crate::ConfigBuilder), keys which the target knows under a previous name are
+/// written under that name, and keys which the target does not know about at all are left out of
+/// the serialized configuration and reported as [`CompatibilityWarning`]s instead.
+///
+/// Only major and minor version are taken into account, since configuration keys are never
+/// introduced in patch releases.
+///
+/// # Example
+///
+/// ```
+/// # use glrcfg::RunnerVersion;
+/// let version = RunnerVersion::parse("v16.4.1").unwrap();
+/// assert_eq!(version, RunnerVersion::new(16, 4));
+/// assert!(version > RunnerVersion::V16);
+/// assert!(version < RunnerVersion::V17);
+/// assert!(RunnerVersion::parse("sixteen").is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RunnerVersion {
+    major: u16,
+    minor: u16,
+}
+
+impl RunnerVersion {
+    pub const V15: Self = Self::new(15, 0);
+    pub const V16: Self = Self::new(16, 0);
+    pub const V17: Self = Self::new(17, 0);
+    /// The newest version whose configuration keys are known to this crate. A
+    /// [`Config`](crate::Config) without a target version is compatible with this version.
+    pub const LATEST: Self = Self::V17;
+
+    /// Creates a version from its major and minor components.
+    pub const fn new(major: u16, minor: u16) -> Self {
+        Self { major, minor }
+    }
+
+    /// Parses a version like `16`, `16.4` or `v16.4.1` from a string slice. Anything past the
+    /// minor version is ignored.
+    pub fn parse(version: &str) -> Result<Self, RunnerVersionParseError> {
+        let err = || RunnerVersionParseError(version.to_string());
+
+        let mut components = version.strip_prefix('v').unwrap_or(version).split('.');
+        let major = components
+            .next()
+            .and_then(|major| major.parse().ok())
+            .ok_or_else(err)?;
+        let minor = match components.next() {
+            Some(minor) => minor.parse().map_err(|_| err())?,
+            None => 0,
+        };
+
+        Ok(Self { major, minor })
+    }
+
+    pub fn major(&self) -> u16 {
+        self.major
+    }
+
+    pub fn minor(&self) -> u16 {
+        self.minor
+    }
+
+    /// Renames all keys in the serialized `config` which this version knows under their previous
+    /// name, removes all keys which it does not support at all and returns a warning for each
+    /// removed key.
+    pub(crate) fn downgrade(self, config: &mut toml::Value) -> Vec<CompatibilityWarning> {
+        self.downgrade_at(config, &[], "")
+    }
+
+    /// Like [`RunnerVersion::downgrade`], but for the part of the config found at `prefix`, e.g.
+    /// a single runner at `["runners"]`, whose keys are reported relative to `location`.
+    pub(crate) fn downgrade_at(
+        self,
+        value: &mut toml::Value,
+        prefix: &[&str],
+        location: &str,
+    ) -> Vec<CompatibilityWarning> {
+        let mut warnings = Vec::new();
+
+        for renamed in RENAMED_KEYS.iter().filter(|key| key.since > self) {
+            let Some(path) = renamed.path.strip_prefix(prefix) else {
+                continue;
+            };
+            walk(value, path, location, &mut |table, key, _| {
+                if let Some(value) = table.remove(key) {
+                    table.insert(renamed.previous.to_string(), value);
+                }
+            });
+        }
+
+        for introduced in INTRODUCED_KEYS.iter().filter(|key| key.since > self) {
+            let Some(path) = introduced.path.strip_prefix(prefix) else {
+                continue;
+            };
+            walk(value, path, location, &mut |table, key, location| {
+                table.remove(key);
+                warnings.push(CompatibilityWarning {
+                    key: location,
+                    since: introduced.since,
+                    target: self,
+                });
+            });
+        }
+
+        warnings
+    }
+}
+
+impl fmt::Display for RunnerVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+impl FromStr for RunnerVersion {
+    type Err = RunnerVersionParseError;
+
+    fn from_str(version: &str) -> Result<Self, Self::Err> {
+        Self::parse(version)
+    }
+}
+
+/// A configuration key which was left out of the serialized configuration because the target
+/// [`RunnerVersion`] does not support it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompatibilityWarning {
+    /// Location of the key within the configuration, e.g. `runners[0].docker.allowed_pull_policies`.
+    pub key: String,
+    /// Version of `gitlab-runner` which introduced the key.
+    pub since: RunnerVersion,
+    /// Version of `gitlab-runner` the configuration was generated for.
+    pub target: RunnerVersion,
+}
+
+impl fmt::Display for CompatibilityWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`{}` requires GitLab Runner {} but the target is {}; omitting it",
+            self.key, self.since, self.target
+        )
+    }
+}
+
+/// Walks `value` along `path`, descending into arrays of tables transparently, and calls `f` with
+/// every table containing the last segment of `path`, the key itself and its dotted location.
+pub(crate) fn walk<F>(value: &mut toml::Value, path: &[&str], location: &str, f: &mut F)
+where
+    F: FnMut(&mut toml::Table, &str, String),
+{
+    match value {
+        toml::Value::Array(items) => {
+            for (idx, item) in items.iter_mut().enumerate() {
+                walk(item, path, &format!("{location}[{idx}]"), f);
+            }
+        }
+        toml::Value::Table(table) => match path {
+            [key] if table.contains_key(*key) => f(table, key, join(location, key)),
+            [head, rest @ ..] if !rest.is_empty() => {
+                if let Some(next) = table.get_mut(*head) {
+                    walk(next, rest, &join(location, head), f);
+                }
+            }
+            _ => {}
+        },
+        _ => {}
+    }
+}
+
+fn join(location: &str, key: &str) -> String {
+    if location.is_empty() {
+        key.to_string()
+    } else {
+        format!("{location}.{key}")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::RunnerVersion;
+    use crate::{
+        runner::{Cache, Runner},
+        Config,
+    };
+
+    #[test]
+    fn parse_versions() {
+        assert_eq!(RunnerVersion::parse("15"), Ok(RunnerVersion::V15));
+        assert_eq!(RunnerVersion::parse("16.4"), Ok(RunnerVersion::new(16, 4)));
+        assert_eq!(
+            RunnerVersion::parse("v17.2.1"),
+            Ok(RunnerVersion::new(17, 2))
+        );
+        assert!(RunnerVersion::parse("").is_err());
+        assert!(RunnerVersion::parse("16.x").is_err());
+        assert!(RunnerVersion::new(15, 11) < RunnerVersion::V16);
+    }
+
+    #[test]
+    fn downgrade_omits_unsupported_keys() {
+        let config = Config::builder()
+            .with_runners(vec![Runner::default()])
+            .with_target(RunnerVersion::new(15, 0))
+            .build();
+
+        let toml = config.to_toml_string();
+        assert!(!toml.contains("connection_max_age"));
+        assert!(!toml.contains("allowed_pull_policies"));
+
+        let warnings = config.compatibility_warnings();
+        assert_eq!(
+            warnings.iter().map(|w| w.key.as_str()).collect::<Vec<_>>(),
+            vec![
+                "connection_max_age",
+                "runners[0].docker.allowed_pull_policies"
+            ]
+        );
+    }
+
+    #[test]
+    fn downgrade_renames_keys() {
+        let runners = || {
+            let mut cache = Cache::default();
+            cache.s3.role_arn = Some("arn:aws:iam::123456789012:role/cache".to_string());
+            vec![Runner {
+                cache: Some(cache),
+                ..Default::default()
+            }]
+        };
+
+        let config = Config::builder()
+            .with_runners(runners())
+            .with_target(RunnerVersion::new(17, 0))
+            .build();
+        let toml = config.to_toml_string();
+        assert!(toml.contains("UploadRoleARN = \"arn:aws:iam::123456789012:role/cache\""));
+        assert!(!toml.replace("UploadRoleARN", "").contains("RoleARN"));
+        assert!(config.compatibility_warnings().is_empty());
+
+        let toml = Config::builder()
+            .with_runners(runners())
+            .build()
+            .to_toml_string();
+        assert!(toml.contains("RoleARN = \"arn:aws:iam::123456789012:role/cache\""));
+        assert!(!toml.contains("UploadRoleARN"));
+    }
+
+    #[test]
+    fn no_target_emits_everything() {
+        let config = Config::builder()
+            .with_runners(vec![Runner::default()])
+            .build();
+
+        assert!(config.to_toml_string().contains("connection_max_age"));
+        assert!(config.compatibility_warnings().is_empty());
+    }
+}
@@ -0,0 +1,296 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::compatibility::walk;
+
+/// Keys whose default value is only written by the `gitlab-runner` CLI when it registers a runner,
+/// while the GitLab docs leave them out. Paths descend into arrays of tables transparently, like
+/// those of the compatibility checks.
+static CLI_DEFAULTS: &[DefaultKey] = &[
+    DefaultKey {
+        path: &["runners", "cache", "MaxUploadedArchiveSize"],
+        value: Literal::Integer(0),
+    },
+    DefaultKey {
+        path: &["runners", "cache", "s3"],
+        value: Literal::EmptyTable,
+    },
+    DefaultKey {
+        path: &["runners", "cache", "gcs"],
+        value: Literal::EmptyTable,
+    },
+    DefaultKey {
+        path: &["runners", "cache", "azure"],
+        value: Literal::EmptyTable,
+    },
+    DefaultKey {
+        path: &["runners", "docker", "disable_cache"],
+        value: Literal::Bool(false),
+    },
+    DefaultKey {
+        path: &["runners", "docker", "disable_entrypoint_overwrite"],
+        value: Literal::Bool(false),
+    },
+    DefaultKey {
+        path: &["runners", "docker", "network_mtu"],
+        value: Literal::Integer(0),
+    },
+    DefaultKey {
+        path: &["runners", "docker", "oom_kill_disable"],
+        value: Literal::Bool(false),
+    },
+    DefaultKey {
+        path: &["runners", "docker", "privileged"],
+        value: Literal::Bool(false),
+    },
+    DefaultKey {
+        path: &["runners", "docker", "smg_size"],
+        value: Literal::Integer(0),
+    },
+];
+
+/// Keys whose value is the default given in [the GitLab
+/// docs](https://docs.gitlab.com/runner/configuration/advanced-configuration.html), i.e. which
+/// `gitlab-runner` assumes if they are missing.
+static DOCS_DEFAULTS: &[DefaultKey] = &[
+    DefaultKey {
+        path: &["concurrent"],
+        value: Literal::Integer(1),
+    },
+    DefaultKey {
+        path: &["check_interval"],
+        value: Literal::Integer(3),
+    },
+    DefaultKey {
+        path: &["connection_max_age"],
+        value: Literal::String("15m"),
+    },
+    DefaultKey {
+        path: &["shutdown_timeout"],
+        value: Literal::Integer(30),
+    },
+    DefaultKey {
+        path: &["runners", "limit"],
+        value: Literal::Integer(0),
+    },
+    DefaultKey {
+        path: &["runners", "request_concurrency"],
+        value: Literal::Integer(1),
+    },
+    DefaultKey {
+        path: &["runners", "output_limit"],
+        value: Literal::Integer(4096),
+    },
+    DefaultKey {
+        path: &["runners", "docker", "cpu_shares"],
+        value: Literal::Integer(1024),
+    },
+    DefaultKey {
+        path: &["runners", "docker", "pull_policy"],
+        value: Literal::String("always"),
+    },
+    DefaultKey {
+        path: &["runners", "docker", "tls_verify"],
+        value: Literal::Bool(false),
+    },
+    DefaultKey {
+        path: &["runners", "docker", "wait_for_service_timeout"],
+        value: Literal::Integer(30),
+    },
+    DefaultKey {
+        path: &["session_server", "session_timeout"],
+        value: Literal::Integer(1800),
+    },
+];
+
+struct DefaultKey {
+    path: &'static [&'static str],
+    value: Literal,
+}
+
+/// A default value, comparable to serialized values.
+enum Literal {
+    Bool(bool),
+    Integer(i64),
+    String(&'static str),
+    EmptyTable,
+}
+
+impl Literal {
+    fn matches(&self, value: &toml::Value) -> bool {
+        match (self, value) {
+            (Self::Bool(literal), toml::Value::Boolean(value)) => literal == value,
+            (Self::Integer(literal), toml::Value::Integer(value)) => literal == value,
+            (Self::String(literal), toml::Value::String(value)) => literal == value,
+            (Self::EmptyTable, toml::Value::Table(value)) => value.is_empty(),
+            _ => false,
+        }
+    }
+}
+
+/// Which default values are written to the config. The structs of this crate always default to
+/// what the `gitlab-runner` CLI writes when it registers a runner; the profile set on the
+/// [`ConfigBuilder`](crate::ConfigBuilder) decides which keys still holding those defaults are
+/// left out during serialization. Keys set to anything else are always written.
+///
+/// # Example
+///
+/// ```
+/// # use glrcfg::{runner::Runner, Config, Defaults};
+/// let config = Config::builder()
+///     .with_runners(vec![Runner::default()])
+///     .with_defaults(Defaults::Minimal)
+///     .build();
+/// let toml = config.to_toml_string();
+/// assert!(!toml.contains("network_mtu"));
+/// assert!(!toml.contains("check_interval"));
+/// assert!(toml.contains("image = \"alpine:latest\""));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "kebab-case")]
+pub enum Defaults {
+    /// Everything the `gitlab-runner` CLI writes, including keys the GitLab docs don't mention
+    /// like `network_mtu` and the empty `[runners.cache.s3]` sections; the output matches configs
+    /// generated by `gitlab-runner register`.
+    #[default]
+    CliParity,
+    /// Like [`Defaults::CliParity`], but without the default values only the CLI writes, so the
+    /// output matches the examples in the GitLab docs.
+    DocsParity,
+    /// Only the keys which differ from what `gitlab-runner` assumes anyway, plus those it needs,
+    /// e.g. the URL and token of every runner.
+    Minimal,
+}
+
+#[derive(Debug, PartialEq, Eq, Error)]
+#[error("invalid defaults profile `{0}`; must be one of cli-parity, docs-parity, minimal")]
+pub struct DefaultsParseError(String);
+
+impl FromStr for Defaults {
+    type Err = DefaultsParseError;
+
+    fn from_str(defaults: &str) -> Result<Self, Self::Err> {
+        match defaults {
+            "cli-parity" => Ok(Self::CliParity),
+            "docs-parity" => Ok(Self::DocsParity),
+            "minimal" => Ok(Self::Minimal),
+            _ => Err(DefaultsParseError(defaults.to_string())),
+        }
+    }
+}
+
+impl Defaults {
+    /// Removes the keys holding default values this profile leaves out from the part of the
+    /// serialized config found at `prefix`, e.g. a single runner at `["runners"]`.
+    pub(crate) fn strip_at(self, value: &mut toml::Value, prefix: &[&str]) {
+        let docs_defaults: &[DefaultKey] = match self {
+            Self::CliParity => return,
+            Self::DocsParity => &[],
+            Self::Minimal => DOCS_DEFAULTS,
+        };
+
+        for default in CLI_DEFAULTS.iter().chain(docs_defaults) {
+            let Some(path) = default.path.strip_prefix(prefix) else {
+                continue;
+            };
+            walk(value, path, "", &mut |table, key, _location| {
+                if table
+                    .get(key)
+                    .is_some_and(|value| default.value.matches(value))
+                {
+                    table.remove(key);
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::Defaults;
+    use crate::{
+        runner::{Cache, Docker, Executor, Runner},
+        session_server::SessionServer,
+        Config,
+    };
+
+    fn config(defaults: Defaults) -> toml::Table {
+        let runner = Runner {
+            cache: Some(Cache::default()),
+            executor: Executor::Docker {
+                docker: Docker {
+                    privileged: true,
+                    ..Default::default()
+                },
+            },
+            ..Default::default()
+        };
+        let config = Config::builder()
+            .with_runners(vec![runner])
+            .with_session_server(SessionServer::default())
+            .with_defaults(defaults)
+            .build();
+
+        toml::from_str(&config.to_toml_string()).unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("minimal".parse(), Ok(Defaults::Minimal));
+        assert_eq!(
+            serde_json::to_string(&Defaults::DocsParity).unwrap(),
+            r#""docs-parity""#
+        );
+        assert!("docs".parse::<Defaults>().is_err());
+    }
+
+    #[test]
+    fn cli_parity_emits_everything() {
+        let config = config(Defaults::CliParity);
+        let runner = &config["runners"][0];
+
+        assert_eq!(config["check_interval"].as_integer(), Some(3));
+        assert_eq!(runner["docker"]["network_mtu"].as_integer(), Some(0));
+        assert!(runner["cache"]["s3"].as_table().unwrap().is_empty());
+    }
+
+    #[test]
+    fn docs_parity_omits_cli_defaults() {
+        let config = config(Defaults::DocsParity);
+        let runner = &config["runners"][0];
+
+        assert_eq!(config["check_interval"].as_integer(), Some(3));
+        assert_eq!(runner["docker"]["cpu_shares"].as_integer(), Some(1024));
+        assert!(runner["docker"].get("network_mtu").is_none());
+        assert!(runner["docker"].get("oom_kill_disable").is_none());
+        assert!(runner["cache"].as_table().unwrap().is_empty());
+        // values which differ from the default are kept
+        assert_eq!(runner["docker"]["privileged"].as_bool(), Some(true));
+    }
+
+    #[test]
+    fn minimal_omits_all_defaults() {
+        let config = config(Defaults::Minimal);
+        let runner = &config["runners"][0];
+
+        for key in ["concurrent", "check_interval", "connection_max_age"] {
+            assert!(config.get(key).is_none(), "{key} must be omitted");
+        }
+        assert!(config["session_server"].as_table().unwrap().is_empty());
+        assert!(runner.get("output_limit").is_none());
+        assert!(runner["docker"].get("pull_policy").is_none());
+        // keys `gitlab-runner` needs are kept
+        for key in ["name", "url", "token", "executor"] {
+            assert!(runner.get(key).is_some(), "{key} must be kept");
+        }
+        assert_eq!(runner["docker"]["image"].as_str(), Some("alpine:latest"));
+        assert_eq!(runner["docker"]["privileged"].as_bool(), Some(true));
+    }
+}
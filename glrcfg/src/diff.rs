@@ -0,0 +1,322 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{collections::BTreeMap, fmt};
+
+use crate::Config;
+
+/// A single field which differs between two configurations. The key is the dotted location of the
+/// field within its section, e.g. `docker.image` within a runner; values are rendered as TOML.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldChange {
+    pub key: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// Identifies a runner within a [`ConfigDiff`]. Runners are matched up between configurations by
+/// their GitLab URL and ID; tokens may be shared between runners, e.g. when one registration is
+/// served by several executors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunnerRef {
+    pub id: u32,
+    pub name: String,
+    pub url: String,
+}
+
+/// A runner which is present in both configurations, but with different values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunnerChange {
+    pub runner: RunnerRef,
+    pub fields: Vec<FieldChange>,
+}
+
+/// The structural difference between two [`Config`]s, as returned by [`Config::diff`].
+///
+/// The [`Display`](fmt::Display) implementation renders the difference as human-readable text,
+/// which is what you want for change previews and drift reports.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConfigDiff {
+    /// Changed fields of the global and `[session_server]` sections.
+    pub global: Vec<FieldChange>,
+    pub added: Vec<RunnerRef>,
+    pub removed: Vec<RunnerRef>,
+    pub changed: Vec<RunnerChange>,
+}
+
+impl ConfigDiff {
+    /// Returns `true` if both configurations are structurally identical.
+    pub fn is_empty(&self) -> bool {
+        self.global.is_empty()
+            && self.added.is_empty()
+            && self.removed.is_empty()
+            && self.changed.is_empty()
+    }
+}
+
+impl Config {
+    /// Compares this configuration (the old one) to `other` (the new one).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use std::num::NonZeroU32;
+    /// # use glrcfg::Config;
+    /// let old = Config::builder().build();
+    /// let mut new = Config::builder().build();
+    /// new.global.concurrent = NonZeroU32::new(4).unwrap();
+    ///
+    /// let diff = old.diff(&new);
+    /// assert_eq!(diff.to_string(), "~ concurrent: 1 -> 4\n");
+    /// ```
+    pub fn diff(&self, other: &Config) -> ConfigDiff {
+        let (old_global, old_runners) = split(self.to_toml_value());
+        let (new_global, new_runners) = split(other.to_toml_value());
+
+        let mut diff = ConfigDiff {
+            global: compare(&old_global, &new_global),
+            ..Default::default()
+        };
+
+        for (key, (runner, old_fields)) in &old_runners {
+            match new_runners.get(key) {
+                None => diff.removed.push(runner.clone()),
+                Some((runner, new_fields)) => {
+                    let fields = compare(old_fields, new_fields);
+                    if !fields.is_empty() {
+                        diff.changed.push(RunnerChange {
+                            runner: runner.clone(),
+                            fields,
+                        });
+                    }
+                }
+            }
+        }
+
+        diff.added = new_runners
+            .iter()
+            .filter(|(key, _)| !old_runners.contains_key(*key))
+            .map(|(_, (runner, _))| runner.clone())
+            .collect();
+
+        diff
+    }
+}
+
+impl fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) => write!(f, "~ {}: {old} -> {new}", self.key),
+            (None, Some(new)) => write!(f, "+ {}: {new}", self.key),
+            (Some(old), None) => write!(f, "- {}: {old}", self.key),
+            (None, None) => write!(f, "  {}", self.key),
+        }
+    }
+}
+
+impl fmt::Display for RunnerRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "runner \"{}\" ({})", self.name, self.url)
+    }
+}
+
+impl fmt::Display for ConfigDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.global {
+            writeln!(f, "{change}")?;
+        }
+        for runner in &self.added {
+            writeln!(f, "+ {runner}")?;
+        }
+        for runner in &self.removed {
+            writeln!(f, "- {runner}")?;
+        }
+        for change in &self.changed {
+            writeln!(f, "~ {}", change.runner)?;
+            for field in &change.fields {
+                writeln!(f, "    {field}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+type Fields = BTreeMap<String, toml::Value>;
+
+/// Splits a serialized config into the flattened global fields and the flattened fields of each
+/// runner, keyed by GitLab URL and runner ID.
+fn split(value: toml::Value) -> (Fields, BTreeMap<(String, u32), (RunnerRef, Fields)>) {
+    let toml::Value::Table(mut table) = value else {
+        return Default::default();
+    };
+
+    let runners = match table.remove("runners") {
+        Some(toml::Value::Array(runners)) => runners
+            .into_iter()
+            .map(|runner| {
+                let field = |key: &str| {
+                    runner
+                        .get(key)
+                        .and_then(toml::Value::as_str)
+                        .unwrap_or_default()
+                        .to_string()
+                };
+                let runner_ref = RunnerRef {
+                    id: runner
+                        .get("id")
+                        .and_then(toml::Value::as_integer)
+                        .and_then(|id| u32::try_from(id).ok())
+                        .unwrap_or_default(),
+                    name: field("name"),
+                    url: field("url"),
+                };
+
+                let mut fields = Fields::new();
+                flatten(&runner, "", &mut fields);
+                let key = (runner_ref.url.clone(), runner_ref.id);
+                (key, (runner_ref, fields))
+            })
+            .collect(),
+        _ => BTreeMap::new(),
+    };
+
+    let mut global = Fields::new();
+    flatten(&toml::Value::Table(table), "", &mut global);
+
+    (global, runners)
+}
+
+fn flatten(value: &toml::Value, prefix: &str, fields: &mut Fields) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{prefix}.{key}")
+                };
+                flatten(value, &key, fields);
+            }
+        }
+        value => {
+            fields.insert(prefix.to_string(), value.clone());
+        }
+    }
+}
+
+fn compare(old: &Fields, new: &Fields) -> Vec<FieldChange> {
+    let mut keys: Vec<&String> = old.keys().chain(new.keys()).collect();
+    keys.sort();
+    keys.dedup();
+
+    keys.into_iter()
+        .filter_map(|key| {
+            let (old, new) = (old.get(key), new.get(key));
+            (old != new).then(|| FieldChange {
+                key: key.clone(),
+                old: old.map(toml::Value::to_string),
+                new: new.map(toml::Value::to_string),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use crate::{
+        runner::{DateTime, Docker, Executor, ImageReference, Runner, RunnerToken},
+        Config,
+    };
+
+    fn runner(id: u32, name: &str, token: &str, image: &str) -> Runner {
+        Runner {
+            id,
+            name: name.to_string(),
+            token: RunnerToken::parse(token).unwrap(),
+            token_obtained_at: DateTime::parse("2024-02-02T22:02:06Z").unwrap(),
+            executor: Executor::Docker {
+                docker: Docker {
+                    image: ImageReference::parse(image).unwrap(),
+                    ..Default::default()
+                },
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn identical_configs() {
+        let runners = || {
+            vec![runner(
+                1,
+                "warbl",
+                "glrt-warblwarblwarblwarbl",
+                "alpine:latest",
+            )]
+        };
+        let old = Config::builder().with_runners(runners()).build();
+        let new = Config::builder().with_runners(runners()).build();
+
+        assert!(old.diff(&new).is_empty());
+    }
+
+    #[test]
+    fn added_removed_changed_runners() {
+        let old = Config::builder()
+            .with_runners(vec![
+                runner(1, "warbl", "glrt-warblwarblwarblwarbl", "alpine:latest"),
+                runner(2, "garbl", "glrt-garblgarblgarblgarbl", "alpine:latest"),
+            ])
+            .build();
+        let new = Config::builder()
+            .with_runners(vec![
+                runner(1, "warbl", "glrt-warblwarblwarblwarbl", "rust:latest"),
+                runner(3, "wagarbl", "glrt-wagarblwagarblwagarbl", "alpine:latest"),
+            ])
+            .build();
+
+        let diff = old.diff(&new);
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].name, "wagarbl");
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.removed[0].name, "garbl");
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].fields.len(), 1);
+        assert_eq!(diff.changed[0].fields[0].key, "docker.image");
+
+        assert_eq!(
+            diff.to_string(),
+            indoc::indoc! {r#"
+                + runner "wagarbl" (https://gitlab.com/)
+                - runner "garbl" (https://gitlab.com/)
+                ~ runner "warbl" (https://gitlab.com/)
+                    ~ docker.image: "alpine:latest" -> "rust:latest"
+            "#}
+        );
+    }
+
+    #[test]
+    fn runners_sharing_a_token() {
+        let token = "glrt-warblwarblwarblwarbl";
+        let old = Config::builder()
+            .with_runners(vec![
+                runner(1, "warbl", token, "alpine:latest"),
+                runner(2, "garbl", token, "alpine:latest"),
+            ])
+            .build();
+        let new = Config::builder()
+            .with_runners(vec![
+                runner(1, "warbl", token, "alpine:latest"),
+                runner(2, "garbl", token, "rust:latest"),
+            ])
+            .build();
+
+        let diff = old.diff(&new);
+        assert!(diff.added.is_empty());
+        assert!(diff.removed.is_empty());
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].runner.name, "garbl");
+    }
+}
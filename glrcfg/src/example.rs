@@ -0,0 +1,243 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+//! Example values of each section, with most keys populated, to show the exact TOML the types
+//! produce; e.g. `cargo run -p glrcfg --example glrcfg-gen -- docker` prints them.
+
+use std::num::NonZeroU32;
+
+use maybe_multiple::MaybeMultiple;
+
+use crate::{
+    runner::{
+        AbsolutePath, Autoscaler, AutoscalerPolicy, Cache, CacheS3, CacheType, ConnectorConfig,
+        DateTime, Docker, DockerHost, Executor, ExecutorNameError, ImageReference, Isolation,
+        Kubernetes, PullPolicy, Runner, RunnerToken, S3AuthenticationType, SecurityOpt, Service,
+        Shell, Url, WINDOWS_DOCKER_HOST,
+    },
+    session_server::SessionServer,
+    Config, GlobalSection, GolangDuration, ListenAddress, LogFormat, LogLevel,
+};
+
+impl Config {
+    /// An example config with a single runner using `executor`, see [`Runner::example`].
+    pub fn example(executor: Executor) -> Self {
+        Self::builder()
+            .with_global(GlobalSection::example())
+            .with_session_server(SessionServer::example())
+            .with_runners(vec![Runner::example(executor)])
+            .build()
+    }
+}
+
+impl GlobalSection {
+    /// An example global section, exposing metrics.
+    pub fn example() -> Self {
+        Self {
+            concurrent: NonZeroU32::new(4).expect("4 is not zero"),
+            log_level: LogLevel::Info,
+            log_format: LogFormat::Text,
+            listen_address: Some(ListenAddress::parse(":9252").expect("valid listen address")),
+            ..Default::default()
+        }
+    }
+}
+
+impl SessionServer {
+    /// An example session server, listening on all interfaces.
+    pub fn example() -> Self {
+        Self {
+            listen_address: Some(ListenAddress::parse("[::]:8093").expect("valid listen address")),
+            advertise_address: Some(
+                ListenAddress::parse("runner.your-company.com:8093")
+                    .expect("valid advertise address"),
+            ),
+            ..Default::default()
+        }
+    }
+}
+
+impl Runner {
+    /// An example runner using `executor`, with an S3 cache; it runs PowerShell on Windows and
+    /// Bash everywhere else.
+    pub fn example(executor: Executor) -> Self {
+        let shell = match &executor {
+            Executor::DockerWindows { .. } => Shell::Pwsh,
+            _ => Shell::Bash,
+        };
+
+        Self {
+            id: 42,
+            name: "example".to_string(),
+            url: Url::parse("https://gitlab.your-company.com").expect("valid URL"),
+            token: RunnerToken::parse("glrt-0123456789_abcdefXYZ").expect("valid token"),
+            token_obtained_at: DateTime::parse("2024-07-07T09:00:00Z").expect("valid timestamp"),
+            limit: 2,
+            cache: Some(Cache::example()),
+            executor,
+            shell: Some(shell),
+            environment: vec!["GIT_DEPTH=10".to_string()],
+            unhealthy_requests_limit: Some(5),
+            unhealthy_interval: Some(GolangDuration::parse("30m").expect("valid duration")),
+            ..Default::default()
+        }
+    }
+}
+
+impl Cache {
+    /// An example cache in an S3 bucket, accessed with the IAM role of the instance.
+    pub fn example() -> Self {
+        Self {
+            cache_type: Some(CacheType::S3),
+            shared: Some(true),
+            s3: CacheS3 {
+                server_address: Some("s3.amazonaws.com".to_string()),
+                bucket_name: Some("runner-cache".to_string()),
+                bucket_location: Some("eu-central-1".to_string()),
+                authentication_type: Some(S3AuthenticationType::Iam),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+}
+
+impl Executor {
+    /// An example of the executor called `name`, with its sections populated; unlike
+    /// [`from_name`](Self::from_name), this includes `docker-autoscaler`, using the AWS plugin.
+    pub fn example(name: &str) -> Result<Self, ExecutorNameError> {
+        Ok(match name {
+            "shell" => Self::Shell,
+            "docker" => Self::Docker {
+                docker: Docker::example(),
+            },
+            "docker-windows" => Self::DockerWindows {
+                docker: Docker::windows_example(),
+            },
+            "docker-autoscaler" => Self::DockerAutoscaler {
+                docker: Docker::example(),
+                autoscaler: Autoscaler::example(),
+            },
+            "kubernetes" => Self::Kubernetes {
+                kubernetes: Kubernetes::example(),
+            },
+            _ => return Err(ExecutorNameError::Unknown(name.to_string())),
+        })
+    }
+}
+
+impl Docker {
+    /// An example Docker section for Linux hosts, with a database service.
+    pub fn example() -> Self {
+        Self {
+            image: ImageReference::parse("ubuntu:24.04").expect("valid image reference"),
+            pull_policy: MaybeMultiple::from_vec(vec![
+                PullPolicy::Always,
+                PullPolicy::IfNotPresent,
+            ]),
+            allowed_pull_policies: Some(vec![PullPolicy::Always, PullPolicy::IfNotPresent]),
+            security_opt: vec![SecurityOpt::parse("no-new-privileges:true").expect("valid option")],
+            memory: Some("4g".to_string()),
+            cpus: Some("2".to_string()),
+            volumes: vec!["/cache".to_string(), "/certs/client".to_string()],
+            cache_dir: Some(AbsolutePath::parse("/cache").expect("valid path")),
+            container_labels: vec!["team=platform".to_string()],
+            services: vec![Service {
+                name: ImageReference::parse("postgres:16").expect("valid image reference"),
+                alias: Some("db".to_string()),
+                entrypoint: None,
+                command: None,
+                environment: Some(vec!["POSTGRES_PASSWORD=postgres".to_string()]),
+            }],
+            ..Default::default()
+        }
+    }
+
+    /// An example Docker section for Windows hosts, isolating containers as processes.
+    pub fn windows_example() -> Self {
+        Self {
+            host: Some(DockerHost::parse(WINDOWS_DOCKER_HOST).expect("valid Docker host")),
+            isolation: Some(Isolation::Process),
+            ..Docker::windows()
+        }
+    }
+}
+
+impl Autoscaler {
+    /// An example autoscaler for an AWS autoscaling group, keeping more instances idle during
+    /// office hours.
+    pub fn example() -> Self {
+        Self {
+            plugin: "gitlab/fleeting-plugin-aws:latest".to_string(),
+            capacity_per_instance: 2,
+            max_use_count: 10,
+            max_instances: 20,
+            plugin_config: toml::Table::from_iter([
+                ("name".to_string(), toml::Value::from("docker-asg")),
+                ("region".to_string(), toml::Value::from("eu-central-1")),
+            ]),
+            connector_config: ConnectorConfig {
+                username: Some("ec2-user".to_string()),
+                use_external_addr: Some(true),
+                ..Default::default()
+            },
+            policy: vec![
+                AutoscalerPolicy::default(),
+                AutoscalerPolicy {
+                    periods: vec!["* 7-19 * * mon-fri".to_string()],
+                    timezone: Some("Europe/Berlin".to_string()),
+                    idle_count: 4,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        }
+    }
+}
+
+impl Kubernetes {
+    /// An example Kubernetes section, running jobs in the `gitlab-ci` namespace on amd64 nodes.
+    pub fn example() -> Self {
+        Self {
+            image: ImageReference::parse("ubuntu:24.04").expect("valid image reference"),
+            namespace: Some("gitlab-ci".to_string()),
+            service_account: Some("gitlab-runner".to_string()),
+            cpu_request: Some("500m".to_string()),
+            cpu_limit: Some("2".to_string()),
+            memory_request: Some("1Gi".to_string()),
+            memory_limit: Some("4Gi".to_string()),
+            pull_policy: vec![PullPolicy::IfNotPresent],
+            poll_timeout: Some(600),
+            node_selector: [("kubernetes.io/arch".to_string(), "amd64".to_string())].into(),
+            pod_labels: [("team".to_string(), "platform".to_string())].into(),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::{Config, Executor};
+
+    #[test]
+    fn every_executor() {
+        for name in [
+            "shell",
+            "docker",
+            "docker-windows",
+            "docker-autoscaler",
+            "kubernetes",
+        ] {
+            let executor = Executor::example(name).unwrap();
+            assert_eq!(executor.name(), name);
+
+            let config = Config::example(executor).to_toml_string();
+            assert!(config.contains(&format!("executor = \"{name}\"")));
+            let parsed: toml::Table = toml::from_str(&config).unwrap();
+            assert!(parsed.contains_key("runners"));
+        }
+
+        assert!(Executor::example("docker+machine").is_err());
+    }
+}
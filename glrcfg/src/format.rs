@@ -0,0 +1,246 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+/// Delimiters of multi-line strings, whose lines must be copied verbatim since indenting them would
+/// change their value.
+const MULTI_LINE_DELIMITERS: [&str; 2] = ["'''", "\"\"\""];
+
+/// Serializes a part of the config, laid out like the configs the `gitlab-runner` CLI writes so
+/// that diffs against those don't show every line: arrays are written inline, e.g.
+/// `volumes = ["/cache"]`, and sections are [indented](indent).
+pub(crate) fn layout(value: &toml::Value) -> String {
+    indent(&toml::to_string(value).expect("could not serialize to TOML"))
+}
+
+/// Lays out TOML written by [`toml::to_string`] the way the `gitlab-runner` CLI does: keys are
+/// indented by two spaces per level of nesting of their table, table headers by two spaces less
+/// than their keys, and nested tables directly follow their parent without a blank line, e.g.
+///
+/// ```toml
+/// [[runners]]
+///   name = "example"
+///   [runners.docker]
+///     image = "alpine:latest"
+/// ```
+pub(crate) fn indent(toml: &str) -> String {
+    let mut indented = String::with_capacity(toml.len() + toml.len() / 4);
+    let mut depth = 0;
+    let mut blank_lines = 0;
+    let mut open_string = None;
+
+    for line in toml.split_inclusive('\n') {
+        if let Some(delimiter) = open_string {
+            indented.push_str(line);
+            if line.matches(delimiter).count() % 2 == 1 {
+                open_string = None;
+            }
+            continue;
+        }
+
+        if line.trim().is_empty() {
+            blank_lines += 1;
+            continue;
+        }
+
+        let level = match header_depth(line) {
+            Some(header_depth) => {
+                depth = header_depth;
+                if depth > 1 {
+                    blank_lines = 0;
+                }
+                depth - 1
+            }
+            None => depth,
+        };
+
+        indented.extend(std::iter::repeat('\n').take(blank_lines));
+        blank_lines = 0;
+        indented.extend(std::iter::repeat("  ").take(level));
+        indented.push_str(line);
+
+        open_string = MULTI_LINE_DELIMITERS
+            .into_iter()
+            .find(|delimiter| line.matches(delimiter).count() % 2 == 1);
+    }
+    indented.extend(std::iter::repeat('\n').take(blank_lines));
+
+    indented
+}
+
+/// Number of keys in the name of the table whose header is `line`, or `None` if `line` is no table
+/// header. Headers always start at the beginning of the line, unlike the elements of arrays.
+fn header_depth(line: &str) -> Option<usize> {
+    let name = line.strip_prefix("[[").or_else(|| line.strip_prefix('['))?;
+
+    let mut depth = 1;
+    let mut quote = None;
+    let mut escaped = false;
+    for c in name.chars() {
+        match quote {
+            Some('"') if c == '\\' && !escaped => {
+                escaped = true;
+                continue;
+            }
+            Some(delimiter) if c == delimiter && !escaped => quote = None,
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '.' => depth += 1,
+            None if c == ']' => break,
+            None => {}
+        }
+        escaped = false;
+    }
+
+    Some(depth)
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::{header_depth, indent, layout};
+
+    /// `config.toml` as written by `gitlab-runner register` (v17.1) for a runner using the Docker
+    /// executor.
+    const REGISTERED: &str = indoc::indoc! {r#"
+        concurrent = 1
+        check_interval = 0
+        connection_max_age = "15m0s"
+        shutdown_timeout = 0
+
+        [session_server]
+          session_timeout = 1800
+
+        [[runners]]
+          name = "example"
+          url = "https://gitlab.your-company.com"
+          id = 42
+          token = "glrt-0123456789_abcdefXYZ"
+          token_obtained_at = 2024-07-07T09:00:00Z
+          token_expires_at = 0001-01-01T00:00:00Z
+          executor = "docker"
+          [runners.custom_build_dir]
+          [runners.cache]
+            MaxUploadedArchiveSize = 0
+            [runners.cache.s3]
+            [runners.cache.gcs]
+            [runners.cache.azure]
+          [runners.docker]
+            tls_verify = false
+            image = "alpine:latest"
+            privileged = false
+            disable_entrypoint_overwrite = false
+            oom_kill_disable = false
+            disable_cache = false
+            volumes = ["/cache"]
+            shm_size = 0
+            network_mtu = 0
+    "#};
+
+    #[test]
+    fn registered_config_verbatim() {
+        let mut config: toml::Table = toml::from_str(REGISTERED).unwrap();
+        let session_server = config.remove("session_server").unwrap();
+        let runners = config.remove("runners").unwrap();
+
+        // laid out section by section, like `Config::write_to` does
+        let mut sections = vec![
+            layout(&config.into()),
+            layout(
+                &toml::Table::from_iter([("session_server".to_string(), session_server)]).into(),
+            ),
+        ];
+        for runner in runners.as_array().unwrap() {
+            let runners = toml::Value::Array(vec![runner.clone()]);
+            sections.push(layout(
+                &toml::Table::from_iter([("runners".to_string(), runners)]).into(),
+            ));
+        }
+
+        assert_eq!(sections.join("\n"), REGISTERED);
+    }
+
+    #[test]
+    fn nested_sections() {
+        let toml = indoc::indoc! {r#"
+            concurrent = 1
+
+            [[runners]]
+            name = "example"
+            volumes = ["/cache", "/certs/client"]
+
+            [runners.cache]
+            MaxUploadedArchiveSize = 0
+
+            [runners.cache.s3]
+
+            [runners.docker]
+            image = "alpine:latest"
+
+            [[runners.autoscaler.policy]]
+            idle_count = 5
+
+            [session_server]
+            session_timeout = 1800
+        "#};
+
+        assert_eq!(
+            indent(toml),
+            indoc::indoc! {r#"
+                concurrent = 1
+
+                [[runners]]
+                  name = "example"
+                  volumes = ["/cache", "/certs/client"]
+                  [runners.cache]
+                    MaxUploadedArchiveSize = 0
+                    [runners.cache.s3]
+                  [runners.docker]
+                    image = "alpine:latest"
+                    [[runners.autoscaler.policy]]
+                      idle_count = 5
+
+                [session_server]
+                  session_timeout = 1800
+            "#}
+        );
+        // indenting doesn't change the value
+        assert_eq!(
+            toml::from_str::<toml::Table>(&indent(toml)).unwrap(),
+            toml::from_str::<toml::Table>(toml).unwrap()
+        );
+    }
+
+    #[test]
+    fn multi_line_strings_verbatim() {
+        let toml = indoc::indoc! {r#"
+            [[runners]]
+            pre_build_script = '''
+            echo "warbl"
+
+            ls -la
+            '''
+            limit = 2
+        "#};
+
+        assert_eq!(
+            indent(toml),
+            indoc::indoc! {r#"
+                [[runners]]
+                  pre_build_script = '''
+                echo "warbl"
+
+                ls -la
+                '''
+                  limit = 2
+            "#}
+        );
+    }
+
+    #[test]
+    fn quoted_header_keys() {
+        assert_eq!(header_depth("[runners.docker.sysctls]\n"), Some(3));
+        assert_eq!(header_depth("[runners.\"a.b\"]\n"), Some(2));
+        assert_eq!(header_depth("    [1, 2],\n"), None);
+        assert_eq!(header_depth("name = \"[runners]\"\n"), None);
+    }
+}
@@ -4,7 +4,7 @@ use std::{fmt, num::NonZeroU32, str::FromStr};
 
 use once_cell::sync::Lazy;
 use regex::Regex;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use url::Url;
 
@@ -20,7 +20,8 @@ static GOLANG_DURATION_REGEX: Lazy<Regex> = Lazy::new(|| {
 ///
 /// Further documentation found in [the GitLab
 /// docs](https://docs.gitlab.com/runner/configuration/advanced-configuration.html#the-global-section).
-#[derive(Debug, PartialEq, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
 #[serde(rename_all = "lowercase")]
 pub enum LogLevel {
     Debug,
@@ -31,13 +32,34 @@ pub enum LogLevel {
     Panic,
 }
 
+#[derive(Debug, PartialEq, Eq, Error)]
+#[error("invalid log level `{0}`; must be one of debug, info, warn, error, fatal, panic")]
+pub struct LogLevelParseError(String);
+
+impl FromStr for LogLevel {
+    type Err = LogLevelParseError;
+
+    fn from_str(level: &str) -> Result<Self, Self::Err> {
+        match level {
+            "debug" => Ok(Self::Debug),
+            "info" => Ok(Self::Info),
+            "warn" => Ok(Self::Warn),
+            "error" => Ok(Self::Error),
+            "fatal" => Ok(Self::Fatal),
+            "panic" => Ok(Self::Panic),
+            _ => Err(LogLevelParseError(level.to_string())),
+        }
+    }
+}
+
 /// Specifies the log format. Options are `runner`, `text`, and `json`. This setting has lower
 /// priority than the format set by command-line argument `--log-format`. The default value is
 /// `runner`, which contains ANSI escape codes for coloring.
 ///
 /// Further documentation found in [the GitLab
 /// docs](https://docs.gitlab.com/runner/configuration/advanced-configuration.html#the-global-section).
-#[derive(Debug, PartialEq, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
 #[serde(rename_all = "lowercase")]
 pub enum LogFormat {
     Runner,
@@ -45,6 +67,23 @@ pub enum LogFormat {
     Json,
 }
 
+#[derive(Debug, PartialEq, Eq, Error)]
+#[error("invalid log format `{0}`; must be one of runner, text, json")]
+pub struct LogFormatParseError(String);
+
+impl FromStr for LogFormat {
+    type Err = LogFormatParseError;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "runner" => Ok(Self::Runner),
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(LogFormatParseError(format.to_string())),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Error)]
 #[error("invalid Golang duration (which look like 15m, 1h, 1h15m, etc.)")]
 pub struct GolangDurationParseError;
@@ -102,6 +141,162 @@ impl FromStr for GolangDuration {
     }
 }
 
+impl<'a> Deserialize<'a> for GolangDuration {
+    fn deserialize<D>(deserializer: D) -> Result<GolangDuration, D::Error>
+    where
+        D: serde::Deserializer<'a>,
+    {
+        let duration = String::deserialize(deserializer)?;
+        GolangDuration::parse(duration).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Error)]
+#[error("invalid listen address `{0}`; must look like host:port, e.g. :9252 or [::1]:9252")]
+pub struct ListenAddressParseError(String);
+
+/// Address `gitlab-runner` listens on, given as `host:port`. The host may be a hostname, an IPv4
+/// address, an IPv6 address in square brackets, or empty to listen on all interfaces.
+///
+/// # Example
+///
+/// ```
+/// # use glrcfg::ListenAddress;
+/// assert_eq!(ListenAddress::parse(":9252").unwrap().as_str(), ":9252");
+/// assert!(ListenAddress::parse("[::1]:9252").is_ok());
+/// assert!(ListenAddress::parse("http://localhost:9252").is_err());
+/// assert!(ListenAddress::parse("localhost").is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct ListenAddress(String);
+
+impl ListenAddress {
+    /// Parses a listen address from an `Into<String>`, e.g. a `&str` or `String`.
+    pub fn parse<S>(address: S) -> Result<Self, ListenAddressParseError>
+    where
+        S: Into<String>,
+    {
+        let address = address.into();
+
+        let valid = address.rsplit_once(':').is_some_and(|(host, port)| {
+            let valid_host = if let Some(ip) = host.strip_prefix('[') {
+                ip.strip_suffix(']')
+                    .is_some_and(|ip| ip.parse::<std::net::Ipv6Addr>().is_ok())
+            } else {
+                host.chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+            };
+
+            valid_host && port.parse::<u16>().is_ok()
+        });
+
+        if !valid {
+            #[cfg(feature = "tracing")]
+            tracing::error!("invalid listen address: {address}");
+            return Err(ListenAddressParseError(address));
+        }
+
+        Ok(Self(address))
+    }
+
+    /// Returns the listen address as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for ListenAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for ListenAddress {
+    type Err = ListenAddressParseError;
+
+    fn from_str(address: &str) -> Result<Self, Self::Err> {
+        Self::parse(address)
+    }
+}
+
+impl<'a> Deserialize<'a> for ListenAddress {
+    fn deserialize<D>(deserializer: D) -> Result<ListenAddress, D::Error>
+    where
+        D: serde::Deserializer<'a>,
+    {
+        let address = String::deserialize(deserializer)?;
+        ListenAddress::parse(address).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Error)]
+#[error("invalid Sentry DSN; must look like https://<public key>@<host>/<project id>")]
+pub struct SentryDsnParseError;
+
+/// Data source name `gitlab-runner` reports errors to [Sentry](https://sentry.io) with. Valid DSNs
+/// are HTTP(S) URLs carrying the public key as user name and the project ID as path.
+///
+/// # Example
+///
+/// ```
+/// # use glrcfg::SentryDsn;
+/// let dsn = SentryDsn::parse("https://0123456789abcdef@o42.ingest.sentry.io/4711").unwrap();
+/// assert_eq!(dsn.as_str(), "https://0123456789abcdef@o42.ingest.sentry.io/4711");
+/// assert!(SentryDsn::parse("https://o42.ingest.sentry.io/4711").is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct SentryDsn(Url);
+
+impl SentryDsn {
+    /// Parses a Sentry DSN from a string slice.
+    pub fn parse(dsn: &str) -> Result<Self, SentryDsnParseError> {
+        let url = Url::parse(dsn).map_err(|_| SentryDsnParseError)?;
+
+        let valid = matches!(url.scheme(), "http" | "https")
+            && !url.username().is_empty()
+            && url.host_str().is_some()
+            && !url.path().trim_matches('/').is_empty();
+        if !valid {
+            #[cfg(feature = "tracing")]
+            tracing::error!("invalid Sentry DSN for host {:?}", url.host_str());
+            return Err(SentryDsnParseError);
+        }
+
+        Ok(Self(url))
+    }
+
+    /// Returns the Sentry DSN as a string slice.
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl fmt::Display for SentryDsn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for SentryDsn {
+    type Err = SentryDsnParseError;
+
+    fn from_str(dsn: &str) -> Result<Self, Self::Err> {
+        Self::parse(dsn)
+    }
+}
+
+impl<'a> Deserialize<'a> for SentryDsn {
+    fn deserialize<D>(deserializer: D) -> Result<SentryDsn, D::Error>
+    where
+        D: serde::Deserializer<'a>,
+    {
+        let dsn = String::deserialize(deserializer)?;
+        SentryDsn::parse(&dsn).map_err(serde::de::Error::custom)
+    }
+}
+
 /// These settings are global. They apply to all runners.
 ///
 /// See the [`Default` implementation](Self::default) for the default values.
@@ -114,11 +309,13 @@ pub struct GlobalSection {
     pub log_level: LogLevel,
     pub log_format: LogFormat,
     pub check_interval: u32,
+    /// Enables tracking of all system level errors to Sentry.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub sentry_dsn: Option<Url>,
+    pub sentry_dsn: Option<SentryDsn>,
     pub connection_max_age: GolangDuration,
+    /// Address (`host:port`) the Prometheus metrics HTTP server listens on.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub listen_address: Option<Url>,
+    pub listen_address: Option<ListenAddress>,
     pub shutdown_timeout: u32,
 }
 
@@ -142,7 +339,10 @@ mod test {
     use pretty_assertions::assert_eq;
     use test_strategy::proptest;
 
-    use super::{GlobalSection, GolangDuration, GOLANG_DURATION_REGEX, GOLANG_DURATION_REGEX_STR};
+    use super::{
+        GlobalSection, GolangDuration, ListenAddress, LogFormat, LogLevel, SentryDsn,
+        GOLANG_DURATION_REGEX, GOLANG_DURATION_REGEX_STR,
+    };
 
     #[test]
     fn test_default() {
@@ -163,6 +363,65 @@ mod test {
         );
     }
 
+    #[test]
+    fn parse_log_settings() {
+        assert_eq!("warn".parse(), Ok(LogLevel::Warn));
+        assert_eq!("panic".parse(), Ok(LogLevel::Panic));
+        assert!("WARN".parse::<LogLevel>().is_err());
+
+        assert_eq!("text".parse(), Ok(LogFormat::Text));
+        assert!("journald".parse::<LogFormat>().is_err());
+    }
+
+    #[test]
+    fn parse_listen_addresses() {
+        for valid in [
+            ":9252",
+            "0.0.0.0:9252",
+            "localhost:9252",
+            "[::]:9252",
+            "[::1]:9252",
+        ] {
+            assert_eq!(valid, ListenAddress::parse(valid).unwrap().as_str());
+        }
+
+        for invalid in [
+            "9252",
+            "localhost",
+            ":92520",
+            "http://localhost:9252",
+            "[::1:9252",
+            "[garbl]:9252",
+            "local host:9252",
+        ] {
+            assert!(ListenAddress::parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn parse_sentry_dsns() {
+        assert!(SentryDsn::parse("https://0123456789abcdef@o42.ingest.sentry.io/4711").is_ok());
+        assert!(SentryDsn::parse("http://key@sentry.internal:9000/2").is_ok());
+        assert!(SentryDsn::parse("https://o42.ingest.sentry.io/4711").is_err());
+        assert!(SentryDsn::parse("https://key@o42.ingest.sentry.io/").is_err());
+        assert!(SentryDsn::parse("ftp://key@o42.ingest.sentry.io/4711").is_err());
+        assert!(SentryDsn::parse("warbl").is_err());
+    }
+
+    #[test]
+    fn serialize_sentry_dsn_and_listen_address() {
+        let global_section = GlobalSection {
+            sentry_dsn: Some(SentryDsn::parse("https://key@o42.ingest.sentry.io/4711").unwrap()),
+            listen_address: Some(ListenAddress::parse(":9252").unwrap()),
+            ..Default::default()
+        };
+
+        let toml = toml::to_string_pretty(&global_section).expect("could not serialize to TOML");
+
+        assert!(toml.contains("sentry_dsn = \"https://key@o42.ingest.sentry.io/4711\"\n"));
+        assert!(toml.contains("listen_address = \":9252\"\n"));
+    }
+
     #[proptest]
     fn parse_valid_golang_durations(#[strategy(GOLANG_DURATION_REGEX_STR)] duration: String) {
         assert_eq!(duration, GolangDuration::parse(&duration).unwrap().as_str());
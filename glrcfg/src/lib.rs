@@ -1,12 +1,26 @@
 // Copyright 2024 bmc::labs GmbH. All rights reserved.
 
+mod atomic;
+mod compatibility;
+mod defaults;
+mod diff;
+mod example;
+mod format;
 mod global;
 pub mod runner;
 pub mod session_server;
 
-use std::path;
+use std::{io, path};
 
-pub use global::{GlobalSection, GolangDuration, GolangDurationParseError, LogFormat, LogLevel};
+pub use atomic::write_atomically;
+pub use compatibility::{CompatibilityWarning, RunnerVersion, RunnerVersionParseError};
+pub use defaults::{Defaults, DefaultsParseError};
+pub use diff::{ConfigDiff, FieldChange, RunnerChange, RunnerRef};
+pub use global::{
+    GlobalSection, GolangDuration, GolangDurationParseError, ListenAddress,
+    ListenAddressParseError, LogFormat, LogFormatParseError, LogLevel, LogLevelParseError,
+    SentryDsn, SentryDsnParseError,
+};
 use runner::Runner;
 use serde::Serialize;
 use session_server::SessionServer;
@@ -17,8 +31,19 @@ use session_server::SessionServer;
 pub struct Config {
     #[serde(flatten)]
     pub global: GlobalSection,
-    pub session_server: SessionServer,
+    /// The `[session_server]` section is emitted with its defaults unless it is turned off with
+    /// [`ConfigBuilder::without_session_server`]. `gitlab-runner` disables the session server
+    /// (and with it the interactive web terminal) if the section is missing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_server: Option<SessionServer>,
     pub runners: Vec<Runner>,
+    /// Version of `gitlab-runner` to generate the configuration for. If set, keys the target
+    /// doesn't support are omitted during serialization; if not, all keys are emitted.
+    #[serde(skip)]
+    pub target: Option<RunnerVersion>,
+    /// Which default values are written; see [`Defaults`].
+    #[serde(skip)]
+    pub defaults: Defaults,
 }
 
 impl Config {
@@ -26,36 +51,302 @@ impl Config {
         ConfigBuilder::default()
     }
 
+    /// Writes the config to `path` atomically with [`write_atomically`]: it is streamed into a
+    /// temporary file next to `path` first, which then replaces `path`. `gitlab-runner` hence
+    /// never reads a partially written config.
     pub fn write<P>(&self, path: P) -> std::io::Result<()>
     where
         P: Into<path::PathBuf> + AsRef<path::Path>,
     {
-        let config_toml = toml::to_string_pretty(&self).expect("could not serialize to TOML");
-
         #[cfg(feature = "tracing")]
-        tracing::debug!(?config_toml, "writing config to disk");
-        std::fs::write(path, config_toml)
+        tracing::debug!(
+            runners = self.runners.len(),
+            path = ?path.as_ref(),
+            "writing config to disk"
+        );
+        write_atomically(path.as_ref(), |file| {
+            let mut file = io::BufWriter::new(file);
+            self.write_to(&mut file)?;
+            io::Write::flush(&mut file)
+        })
+    }
+
+    /// Serializes the config to a TOML string, omitting keys unsupported by the target version.
+    pub fn to_toml_string(&self) -> String {
+        let mut config_toml = Vec::new();
+        self.write_to(&mut config_toml)
+            .expect("writing to a Vec never fails");
+        String::from_utf8(config_toml).expect("TOML is valid UTF-8")
+    }
+
+    /// Serializes the config as TOML into `writer`, like [`Config::to_toml_string`]. The runners
+    /// are serialized one at a time, so that large configs are never held in memory as a whole.
+    pub fn write_to<W: io::Write>(&self, mut writer: W) -> io::Result<()> {
+        // without runners, `toml` writes an inline `runners = []`, which sections can't express
+        if self.runners.is_empty() {
+            return writer.write_all(format::layout(&self.to_toml_value()).as_bytes());
+        }
+
+        // like the `gitlab-runner` CLI, write the global keys first, then the session server, then
+        // the runners
+        let mut global = toml::Value::try_from(&self.global).expect("could not serialize to TOML");
+        if let Some(target) = self.target {
+            for _warning in target.downgrade(&mut global) {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(%_warning, "omitting unsupported key");
+            }
+        }
+        self.defaults.strip_at(&mut global, &[]);
+        writer.write_all(format::layout(&global).as_bytes())?;
+
+        if let Some(session_server) = &self.session_server {
+            let mut session_server =
+                toml::Value::try_from(session_server).expect("could not serialize to TOML");
+            self.defaults
+                .strip_at(&mut session_server, &["session_server"]);
+            let section = toml::Table::from_iter([("session_server".to_string(), session_server)]);
+            writer.write_all(b"\n")?;
+            writer.write_all(format::layout(&section.into()).as_bytes())?;
+        }
+
+        for (idx, runner) in self.runners.iter().enumerate() {
+            let mut runner = toml::Value::try_from(runner).expect("could not serialize to TOML");
+            if let Some(target) = self.target {
+                let location = format!("runners[{idx}]");
+                for _warning in target.downgrade_at(&mut runner, &["runners"], &location) {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(%_warning, "omitting unsupported key");
+                }
+            }
+            self.defaults.strip_at(&mut runner, &["runners"]);
+
+            let section =
+                toml::Table::from_iter([("runners".to_string(), toml::Value::Array(vec![runner]))]);
+            writer.write_all(b"\n")?;
+            writer.write_all(format::layout(&section.into()).as_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Serializes the config to a pretty-printed JSON string, with the same structure and keys as
+    /// the TOML output. Requires the `json` feature.
+    ///
+    /// Note that `gitlab-runner` only consumes TOML; this representation is intended for tooling
+    /// which prefers JSON as an intermediate format.
+    #[cfg(feature = "json")]
+    pub fn to_json_string(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(&self.to_toml_value())
+    }
+
+    /// Serializes the config to a YAML string, with the same structure and keys as the TOML
+    /// output. Requires the `yaml` feature.
+    ///
+    /// Note that `gitlab-runner` only consumes TOML; this representation is intended for tooling
+    /// (e.g. Ansible or Kubernetes manifests) which prefers YAML as an intermediate format.
+    #[cfg(feature = "yaml")]
+    pub fn to_yaml_string(&self) -> Result<String, serde_yaml::Error> {
+        serde_yaml::to_string(&self.to_toml_value())
+    }
+
+    /// Returns a warning for each key which is omitted from the serialized config because the
+    /// target version does not support it.
+    pub fn compatibility_warnings(&self) -> Vec<CompatibilityWarning> {
+        let Some(target) = self.target else {
+            return Vec::new();
+        };
+
+        let mut value = toml::Value::try_from(self).expect("could not serialize to TOML");
+        target.downgrade(&mut value)
+    }
+
+    fn to_toml_value(&self) -> toml::Value {
+        let mut value = toml::Value::try_from(self).expect("could not serialize to TOML");
+
+        if let Some(target) = self.target {
+            for _warning in target.downgrade(&mut value) {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(%_warning, "omitting unsupported key");
+            }
+        }
+        self.defaults.strip_at(&mut value, &[]);
+
+        value
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct ConfigBuilder {
     global: GlobalSection,
-    session_server: SessionServer,
+    session_server: Option<SessionServer>,
     runners: Vec<Runner>,
+    target: Option<RunnerVersion>,
+    defaults: Defaults,
+}
+
+impl Default for ConfigBuilder {
+    fn default() -> Self {
+        Self {
+            global: GlobalSection::default(),
+            session_server: Some(SessionServer::default()),
+            runners: Vec::new(),
+            target: None,
+            defaults: Defaults::default(),
+        }
+    }
 }
 
 impl ConfigBuilder {
+    /// Use the given global settings instead of the [defaults](GlobalSection::default).
+    pub fn with_global(mut self, global: GlobalSection) -> Self {
+        self.global = global;
+        self
+    }
+
     pub fn with_runners(mut self, runners: Vec<Runner>) -> Self {
         self.runners = runners;
         self
     }
 
+    /// Emit the `[session_server]` section with the given settings instead of the
+    /// [defaults](SessionServer::default).
+    pub fn with_session_server(mut self, session_server: SessionServer) -> Self {
+        self.session_server = Some(session_server);
+        self
+    }
+
+    /// Leave the `[session_server]` section out, e.g. to avoid config noise on hosts which don't
+    /// use the interactive web terminal.
+    pub fn without_session_server(mut self) -> Self {
+        self.session_server = None;
+        self
+    }
+
+    /// Generate the config for the given version of `gitlab-runner`.
+    pub fn with_target(mut self, target: RunnerVersion) -> Self {
+        self.target = Some(target);
+        self
+    }
+
+    /// Write the default values of the given profile; by default, the config matches what the
+    /// `gitlab-runner` CLI writes.
+    pub fn with_defaults(mut self, defaults: Defaults) -> Self {
+        self.defaults = defaults;
+        self
+    }
+
     pub fn build(self) -> Config {
         Config {
             global: self.global,
             session_server: self.session_server,
             runners: self.runners,
+            target: self.target,
+            defaults: self.defaults,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::{runner::Runner, session_server::SessionServer, Config, Defaults, RunnerVersion};
+
+    #[test]
+    fn streamed_toml_matches_value() {
+        let runners = || {
+            (0..3)
+                .map(|idx| Runner {
+                    name: format!("runner-{idx}"),
+                    ..Default::default()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for defaults in [Defaults::CliParity, Defaults::Minimal] {
+            for target in [None, Some(RunnerVersion::new(15, 0))] {
+                let mut builder = Config::builder()
+                    .with_runners(runners())
+                    .with_session_server(SessionServer::default())
+                    .with_defaults(defaults);
+                if let Some(target) = target {
+                    builder = builder.with_target(target);
+                }
+                let config = builder.build();
+
+                let streamed = config.to_toml_string();
+                assert_eq!(
+                    toml::from_str::<toml::Value>(&streamed).unwrap(),
+                    config.to_toml_value()
+                );
+                // the session server comes before the runners, like in configs of the CLI
+                assert!(streamed.find("[session_server]") < streamed.find("[[runners]]"));
+            }
         }
+
+        // without runners, the whole document is written at once
+        let config = Config::builder().build();
+        assert!(config.to_toml_string().contains("runners = []"));
+    }
+
+    #[test]
+    fn session_server_emitted_by_default() {
+        let config = Config::builder().build();
+        assert!(config.to_toml_string().contains("[session_server]"));
+
+        let config = Config::builder().without_session_server().build();
+        assert!(!config.to_toml_string().contains("session_server"));
+    }
+
+    #[test]
+    fn write_atomically() {
+        let dir = std::env::temp_dir().join(format!("glrcfg-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+
+        let config = Config::builder()
+            .with_runners(vec![Runner::default()])
+            .build();
+        config.write(&path).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            config.to_toml_string()
+        );
+        // the config is moved into place, no temporary file is left behind
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        let missing = dir.join("missing").join("config.toml");
+        assert!(config.write(&missing).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_matches_toml() {
+        let config = Config::builder()
+            .with_runners(vec![Runner::default()])
+            .with_target(RunnerVersion::new(15, 0))
+            .build();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&config.to_json_string().unwrap()).unwrap();
+        assert_eq!(json, serde_json::to_value(config.to_toml_value()).unwrap());
+        assert_eq!(json["runners"][0]["name"], "default");
+        assert!(json.get("connection_max_age").is_none());
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn yaml_matches_toml() {
+        let config = Config::builder()
+            .with_runners(vec![Runner::default()])
+            .build();
+
+        let yaml: serde_yaml::Value =
+            serde_yaml::from_str(&config.to_yaml_string().unwrap()).unwrap();
+        assert_eq!(yaml, serde_yaml::to_value(config.to_toml_value()).unwrap());
+        assert_eq!(yaml["runners"][0]["name"], "default");
+        assert_eq!(yaml["concurrent"], 1);
     }
 }
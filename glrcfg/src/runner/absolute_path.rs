@@ -0,0 +1,169 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{fmt, str::FromStr};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+static ABSOLUTE_PATH_REGEX_STR: &str = r"(/|[A-Za-z]:[\\/]|\\\\)[^\x00]*";
+static ABSOLUTE_PATH_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(&format!("^{ABSOLUTE_PATH_REGEX_STR}$"))
+        .expect("instantiating ABSOLUTE_PATH_REGEX from given static string must not fail")
+});
+
+#[derive(Debug, PartialEq, Eq, Error)]
+#[error("invalid path `{0}`; must be an absolute path like /builds or C:\\builds")]
+pub struct AbsolutePathParseError(String);
+
+/// An absolute path on the host (or in the container) `gitlab-runner` executes jobs on. Used for
+/// the `builds_dir` and `cache_dir` fields, which `gitlab-runner` misinterprets when given a
+/// relative path. Both Unix paths (`/builds`) and Windows paths with a drive letter
+/// (`C:\GitLab-Runner\builds`) or UNC prefix (`\\server\share`) are accepted, since the host
+/// may run either OS.
+///
+/// Note that this is deliberately _not_ a [`std::path::PathBuf`]: the path is interpreted by
+/// `gitlab-runner` on its host, not by the system generating the configuration.
+///
+/// # Example
+///
+/// ```rust
+/// # use glrcfg::runner::AbsolutePath;
+/// let path = AbsolutePath::parse("/builds").unwrap();
+/// assert_eq!(path.as_str(), "/builds");
+/// assert!(AbsolutePath::parse(r"C:\GitLab-Runner\builds").is_ok());
+/// assert!(AbsolutePath::parse("builds").is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct AbsolutePath(String);
+
+impl AbsolutePath {
+    /// Parses an absolute path from an `Into<String>`, e.g. a `&str` or `String`.
+    pub fn parse<S>(path: S) -> Result<Self, AbsolutePathParseError>
+    where
+        S: Into<String>,
+    {
+        let path = path.into();
+
+        if !ABSOLUTE_PATH_REGEX.is_match(&path) {
+            #[cfg(feature = "tracing")]
+            tracing::error!("invalid absolute path: {path}");
+            return Err(AbsolutePathParseError(path));
+        }
+
+        Ok(Self(path))
+    }
+
+    /// Returns the path as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for AbsolutePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for AbsolutePath {
+    type Err = AbsolutePathParseError;
+
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        Self::parse(path)
+    }
+}
+
+impl<'a> Deserialize<'a> for AbsolutePath {
+    fn deserialize<D>(deserializer: D) -> Result<AbsolutePath, D::Error>
+    where
+        D: serde::Deserializer<'a>,
+    {
+        let path = String::deserialize(deserializer)?;
+        AbsolutePath::parse(path).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(feature = "sqlx")]
+impl<DB> sqlx::Type<DB> for AbsolutePath
+where
+    DB: sqlx::Database,
+    String: sqlx::Type<DB>,
+{
+    fn type_info() -> DB::TypeInfo {
+        <String as sqlx::Type<DB>>::type_info()
+    }
+
+    fn compatible(ty: &DB::TypeInfo) -> bool {
+        <String as sqlx::Type<DB>>::compatible(ty)
+    }
+}
+
+#[cfg(feature = "sqlx")]
+impl<'a, DB> sqlx::Encode<'a, DB> for AbsolutePath
+where
+    DB: sqlx::Database,
+    String: sqlx::Encode<'a, DB>,
+{
+    fn encode_by_ref(
+        &self,
+        buf: &mut <DB as sqlx::database::HasArguments<'a>>::ArgumentBuffer,
+    ) -> sqlx::encode::IsNull {
+        self.0.encode_by_ref(buf)
+    }
+}
+
+#[cfg(feature = "sqlx")]
+impl<'a, DB> sqlx::Decode<'a, DB> for AbsolutePath
+where
+    DB: sqlx::Database,
+    String: sqlx::Decode<'a, DB>,
+{
+    fn decode(
+        value: <DB as sqlx::database::HasValueRef<'a>>::ValueRef,
+    ) -> Result<Self, Box<dyn std::error::Error + 'static + Send + Sync>> {
+        let value = <String as sqlx::Decode<DB>>::decode(value)?;
+        Ok(AbsolutePath::parse(value)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+    use test_strategy::proptest;
+
+    use super::{AbsolutePath, ABSOLUTE_PATH_REGEX, ABSOLUTE_PATH_REGEX_STR};
+
+    #[proptest]
+    fn parse_valid_absolute_paths(#[strategy(ABSOLUTE_PATH_REGEX_STR)] path: String) {
+        assert_eq!(path, AbsolutePath::parse(&path).unwrap().as_str());
+    }
+
+    #[proptest]
+    fn parse_invalid_absolute_paths(#[filter(|p| !ABSOLUTE_PATH_REGEX.is_match(p))] path: String) {
+        assert!(AbsolutePath::parse(path).is_err());
+    }
+
+    #[test]
+    fn parse_relative_paths() {
+        assert!(AbsolutePath::parse("").is_err());
+        assert!(AbsolutePath::parse("builds").is_err());
+        assert!(AbsolutePath::parse("./cache").is_err());
+        assert!(AbsolutePath::parse("~/cache").is_err());
+        assert!(AbsolutePath::parse("C:builds").is_err());
+        assert!(AbsolutePath::parse(r"builds\cache").is_err());
+    }
+
+    #[test]
+    fn parse_windows_paths() {
+        for path in [
+            r"C:\GitLab-Runner\builds",
+            "c:/cache",
+            r"\\fileserver\gitlab-cache",
+        ] {
+            assert_eq!(AbsolutePath::parse(path).unwrap().as_str(), path);
+        }
+    }
+}
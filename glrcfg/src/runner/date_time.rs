@@ -2,7 +2,10 @@
 
 use std::{fmt, str::FromStr};
 
-use serde::{Deserialize, Serialize};
+use serde::{
+    de::{self, value::MapAccessDeserializer, MapAccess, Visitor},
+    Deserialize, Serialize,
+};
 
 /// A datetime type that serializes to and from ISO8601 strings using Zulu timezone, i.e. with no
 /// offset and the letter `Z` instead of an offset. Based on [`chrono::DateTime<chrono::Utc>`].
@@ -16,7 +19,17 @@ use serde::{Deserialize, Serialize};
 /// let iso8601 = "2023-08-23T23:23:23Z";
 /// assert_eq!(iso8601, DateTime::parse(iso8601).unwrap().to_iso8601());
 /// ```
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// It converts from and into [`chrono::DateTime<chrono::Utc>`], and - with the `time` feature
+/// enabled - from and into [`time::OffsetDateTime`](https://docs.rs/time/latest/time/struct.OffsetDateTime.html).
+///
+/// ```rust
+/// # use glrcfg::runner::DateTime;
+/// let expires_at = DateTime::from(chrono::Utc::now() + chrono::TimeDelta::hours(1));
+/// assert!(!expires_at.is_past());
+/// assert!(expires_at.duration_until() > chrono::TimeDelta::minutes(59));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct DateTime(chrono::DateTime<chrono::Utc>);
 
 impl DateTime {
@@ -39,6 +52,54 @@ impl DateTime {
     pub fn to_iso8601(&self) -> String {
         self.0.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
     }
+
+    /// Returns a reference to the underlying [`chrono::DateTime<chrono::Utc>`].
+    pub fn as_chrono(&self) -> &chrono::DateTime<chrono::Utc> {
+        &self.0
+    }
+
+    /// Returns `true` if the datetime lies in the past.
+    pub fn is_past(&self) -> bool {
+        self.0 < chrono::Utc::now()
+    }
+
+    /// Returns the time left until the datetime is reached; negative if it lies in the past.
+    pub fn duration_until(&self) -> chrono::TimeDelta {
+        self.0 - chrono::Utc::now()
+    }
+}
+
+impl From<chrono::DateTime<chrono::Utc>> for DateTime {
+    fn from(date_time: chrono::DateTime<chrono::Utc>) -> Self {
+        Self(date_time)
+    }
+}
+
+impl From<DateTime> for chrono::DateTime<chrono::Utc> {
+    fn from(date_time: DateTime) -> Self {
+        date_time.0
+    }
+}
+
+#[cfg(feature = "time")]
+impl From<time::OffsetDateTime> for DateTime {
+    fn from(date_time: time::OffsetDateTime) -> Self {
+        Self(
+            chrono::DateTime::from_timestamp(date_time.unix_timestamp(), date_time.nanosecond())
+                .expect("the range of time::OffsetDateTime lies within the range of chrono"),
+        )
+    }
+}
+
+// The range of `chrono::DateTime` exceeds the range of `time::OffsetDateTime`, hence `TryFrom`.
+#[cfg(feature = "time")]
+impl TryFrom<DateTime> for time::OffsetDateTime {
+    type Error = time::error::ComponentRange;
+
+    fn try_from(date_time: DateTime) -> Result<Self, Self::Error> {
+        time::OffsetDateTime::from_unix_timestamp(date_time.0.timestamp())?
+            .replace_nanosecond(date_time.0.timestamp_subsec_nanos())
+    }
 }
 
 impl fmt::Display for DateTime {
@@ -69,8 +130,29 @@ impl<'a> Deserialize<'a> for DateTime {
     where
         D: serde::Deserializer<'a>,
     {
-        let date_time = String::deserialize(deserializer)?;
-        DateTime::parse(date_time).map_err(serde::de::Error::custom)
+        deserializer.deserialize_any(DateTimeVisitor)
+    }
+}
+
+/// Reads ISO8601 strings as well as TOML datetimes, which `gitlab-runner` writes the timestamps
+/// of runners as.
+struct DateTimeVisitor;
+
+impl<'a> Visitor<'a> for DateTimeVisitor {
+    type Value = DateTime;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("an ISO8601 timestamp")
+    }
+
+    fn visit_str<E: de::Error>(self, date_time: &str) -> Result<DateTime, E> {
+        DateTime::parse(date_time).map_err(E::custom)
+    }
+
+    // `toml` hands its datetimes over as a map with a single, private key
+    fn visit_map<A: MapAccess<'a>>(self, map: A) -> Result<DateTime, A::Error> {
+        let date_time = toml::value::Datetime::deserialize(MapAccessDeserializer::new(map))?;
+        self.visit_str(&date_time.to_string())
     }
 }
 
@@ -0,0 +1,246 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    runner::{runner_token::mask, AbsolutePath},
+    GolangDuration,
+};
+
+/// The `[runners.autoscaler]` section of the `docker-autoscaler` executor, the successor of
+/// `docker+machine`. Instances are provisioned through a [fleeting
+/// plugin](https://gitlab.com/gitlab-org/fleeting/plugins), e.g. for AWS autoscaling groups or GCP
+/// instance groups, and jobs are run on them via Docker.
+///
+/// See the [`Default` implementation](Self::default) for the default values; note that
+/// [`plugin`](Self::plugin) has no meaningful default and must always be set.
+///
+/// Further documentation found in [the GitLab
+/// docs](https://docs.gitlab.com/runner/configuration/advanced-configuration.html#the-runnersautoscaler-section).
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Autoscaler {
+    /// The fleeting plugin, either the name of an installed binary like `aws` or, as of GitLab
+    /// Runner 16.11, an OCI image like `gitlab/fleeting-plugin-aws:latest`.
+    pub plugin: String,
+    /// Number of jobs run concurrently on a single instance.
+    pub capacity_per_instance: u32,
+    /// Number of jobs an instance runs before it is removed; 0 means no limit.
+    pub max_use_count: u32,
+    /// Maximum number of instances, regardless of the idle policies.
+    pub max_instances: u32,
+    /// Whether instances are removed when `gitlab-runner` shuts down.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delete_instances_on_shutdown: Option<bool>,
+    /// Command run on every instance before it takes jobs, e.g. to wait for it to finish booting.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance_ready_command: Option<String>,
+    /// Passed to the plugin as is; which keys are understood depends on the plugin, e.g. `name`
+    /// of the autoscaling group for AWS, or `project`, `zone` and `name` of the instance group for
+    /// GCP.
+    pub plugin_config: toml::Table,
+    pub connector_config: ConnectorConfig,
+    /// Idle policies, serialized as `[[runners.autoscaler.policy]]`. If several apply at a given
+    /// time, the last one wins.
+    pub policy: Vec<AutoscalerPolicy>,
+}
+
+impl Default for Autoscaler {
+    fn default() -> Self {
+        Self {
+            plugin: String::new(),
+            capacity_per_instance: 1,
+            max_use_count: 0,
+            max_instances: 0,
+            delete_instances_on_shutdown: None,
+            instance_ready_command: None,
+            plugin_config: toml::Table::new(),
+            connector_config: ConnectorConfig::default(),
+            policy: vec![AutoscalerPolicy::default()],
+        }
+    }
+}
+
+/// The `[runners.autoscaler.connector_config]` section, i.e. how `gitlab-runner` connects to the
+/// instances. Unset keys are determined by the plugin, which knows the operating system and
+/// credentials of the instances in most cases.
+///
+/// Further documentation found in [the GitLab
+/// docs](https://docs.gitlab.com/runner/configuration/advanced-configuration.html#the-runnersautoscalerconnector_config-section).
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConnectorConfig {
+    /// Operating system of the instances, e.g. `linux` or `windows`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub os: Option<String>,
+    /// Architecture of the instances, e.g. `amd64` or `arm64`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arch: Option<String>,
+    /// Either `ssh` or `winrm`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// Private key used to connect instead of the credentials provided by the plugin.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_path: Option<AbsolutePath>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub use_static_credentials: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keepalive: Option<GolangDuration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<GolangDuration>,
+    /// Whether instances are reached via their external address instead of their internal one,
+    /// e.g. if `gitlab-runner` runs outside of the cloud network.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub use_external_addr: Option<bool>,
+}
+
+/// Like the secrets of the cache sections, the password is masked, so that it can be logged.
+impl fmt::Debug for ConnectorConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectorConfig")
+            .field("os", &self.os)
+            .field("arch", &self.arch)
+            .field("protocol", &self.protocol)
+            .field("username", &self.username)
+            .field(
+                "password",
+                &self.password.as_deref().map(|password| mask("", password)),
+            )
+            .field("key_path", &self.key_path)
+            .field("use_static_credentials", &self.use_static_credentials)
+            .field("keepalive", &self.keepalive)
+            .field("timeout", &self.timeout)
+            .field("use_external_addr", &self.use_external_addr)
+            .finish()
+    }
+}
+
+/// A `[[runners.autoscaler.policy]]` section, i.e. how many idle instances are kept during the
+/// given periods.
+///
+/// See the [`Default` implementation](Self::default) for the default values.
+///
+/// Further documentation found in [the GitLab
+/// docs](https://docs.gitlab.com/runner/configuration/advanced-configuration.html#the-runnersautoscalerpolicy-sections).
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AutoscalerPolicy {
+    /// Cron expressions of when the policy applies, e.g. `* 7-19 * * mon-fri`.
+    pub periods: Vec<String>,
+    /// Time zone the periods are evaluated in, e.g. `Europe/Berlin`; UTC if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    /// Number of idle job slots kept available, i.e. capacity which isn't running a job.
+    pub idle_count: u32,
+    /// How long an idle instance is kept before it is removed.
+    pub idle_time: GolangDuration,
+    /// Idle capacity as a factor of the capacity in use, used instead of `idle_count` if greater.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale_factor: Option<f64>,
+    /// Upper bound of the idle capacity resulting from `scale_factor`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale_factor_limit: Option<u32>,
+}
+
+impl Default for AutoscalerPolicy {
+    fn default() -> Self {
+        Self {
+            periods: vec!["* * * * *".to_string()],
+            timezone: None,
+            idle_count: 0,
+            idle_time: GolangDuration::parse("20m0s").expect("given string is a valid duration"),
+            scale_factor: None,
+            scale_factor_limit: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::{Autoscaler, AutoscalerPolicy, ConnectorConfig};
+    use crate::{
+        runner::{Executor, Runner},
+        Config, GolangDuration,
+    };
+
+    #[test]
+    fn aws_fleet() {
+        let autoscaler = Autoscaler {
+            plugin: "aws".to_string(),
+            max_use_count: 1,
+            max_instances: 10,
+            plugin_config: toml::Table::from_iter([(
+                "name".to_string(),
+                toml::Value::from("docker-asg"),
+            )]),
+            connector_config: ConnectorConfig {
+                username: Some("ec2-user".to_string()),
+                use_external_addr: Some(true),
+                ..Default::default()
+            },
+            policy: vec![AutoscalerPolicy {
+                idle_count: 5,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let runner = Runner {
+            executor: Executor::DockerAutoscaler {
+                docker: Default::default(),
+                autoscaler,
+            },
+            ..Default::default()
+        };
+
+        let serialized = toml::Value::try_from(&runner).unwrap();
+        assert_eq!(serialized["executor"].as_str(), Some("docker-autoscaler"));
+
+        let autoscaler = &serialized["autoscaler"];
+        assert_eq!(autoscaler["plugin"].as_str(), Some("aws"));
+        assert_eq!(autoscaler["max_instances"].as_integer(), Some(10));
+        assert_eq!(
+            autoscaler["plugin_config"]["name"].as_str(),
+            Some("docker-asg")
+        );
+        assert_eq!(
+            autoscaler["connector_config"]["username"].as_str(),
+            Some("ec2-user")
+        );
+        assert_eq!(autoscaler["policy"][0]["idle_count"].as_integer(), Some(5));
+        assert_eq!(autoscaler["policy"][0]["idle_time"].as_str(), Some("20m0s"));
+
+        let config = Config::builder().with_runners(vec![runner]).build();
+        assert!(config
+            .to_toml_string()
+            .contains("[[runners.autoscaler.policy]]"));
+    }
+
+    #[test]
+    fn debug_masks_password() {
+        let connector_config = ConnectorConfig {
+            username: Some("Administrator".to_string()),
+            password: Some("hunter2-but-longer".to_string()),
+            ..Default::default()
+        };
+
+        let debug = format!("{connector_config:?}");
+        assert!(debug.contains("Administrator"));
+        assert!(debug.contains("****ger"));
+        assert!(!debug.contains("hunter2"));
+    }
+
+    #[test]
+    fn policy_defaults() {
+        let policy = AutoscalerPolicy::default();
+        assert_eq!(policy.periods, vec!["* * * * *".to_string()]);
+        assert_eq!(policy.idle_time, GolangDuration::parse("20m0s").unwrap());
+    }
+}
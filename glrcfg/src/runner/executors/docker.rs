@@ -8,12 +8,23 @@ use regex::Regex;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::runner::{AbsolutePath, ImageReference};
+
 static SECURITY_OPT_REGEX_STR: &str = r".+:.+";
 static SECURITY_OPT_REGEX: Lazy<Regex> = Lazy::new(|| {
     Regex::new(&format!("^{SECURITY_OPT_REGEX_STR}$"))
         .expect("instantiating SECURITY_OPT_REGEX from given static string must not fail")
 });
 
+static DOCKER_HOST_REGEX_STR: &str = r"(unix|tcp|ssh|npipe)://[^\s]+";
+static DOCKER_HOST_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(&format!("^{DOCKER_HOST_REGEX_STR}$"))
+        .expect("instantiating DOCKER_HOST_REGEX from given static string must not fail")
+});
+
+/// Named pipe of the Docker engine on Windows hosts.
+pub const WINDOWS_DOCKER_HOST: &str = "npipe:////./pipe/docker_engine";
+
 macro_rules! stringvec {
     ($($x:expr),*) => (vec![$($x.to_string()),*]);
 }
@@ -32,7 +43,8 @@ macro_rules! stringvec {
 ///
 /// Further documentation found in [the GitLab
 /// docs](https://docs.gitlab.com/runner/configuration/advanced-configuration.html#the-runnersdocker-section).
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Docker {
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub allowed_images: Vec<String>,
@@ -45,7 +57,7 @@ pub struct Docker {
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub allowed_privileged_services: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub cache_dir: Option<String>,
+    pub cache_dir: Option<AbsolutePath>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub cap_add: Vec<String>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
@@ -78,17 +90,19 @@ pub struct Docker {
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub group_add: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub helper_image: Option<String>,
+    pub helper_image: Option<ImageReference>,
+    /// Flavor of the Linux helper image, e.g. `alpine` or `ubuntu`; on Windows, the helper image
+    /// follows the Windows version of the host instead.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub helper_image_flavor: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub helper_image_autoset_arch_and_os: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub host: Option<String>,
+    pub host: Option<DockerHost>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub hostname: Option<String>,
     /// Default determined from `gitlab-runner` CLI runner creation.
-    pub image: String,
+    pub image: ImageReference,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub links: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -115,8 +129,9 @@ pub struct Docker {
     pub pull_policy: MaybeMultiple<PullPolicy>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub runtime: Option<String>,
+    /// Windows only; if unset, Docker picks [`Isolation::Default`].
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub isolation: Option<String>,
+    pub isolation: Option<Isolation>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub security_opt: Vec<SecurityOpt>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -179,7 +194,8 @@ impl Default for Docker {
             helper_image_autoset_arch_and_os: None,
             host: None,
             hostname: None,
-            image: "alpine:latest".to_string(),
+            image: ImageReference::parse("alpine:latest")
+                .expect("given string is a valid image reference"),
             links: Vec::new(),
             memory: None,
             memory_swap: None,
@@ -211,8 +227,71 @@ impl Default for Docker {
     }
 }
 
+impl Docker {
+    /// Returns the defaults `gitlab-runner` uses for the `docker-windows` executor, which differ
+    /// from the Linux ones in the image and the cache volume.
+    pub fn windows() -> Self {
+        Self {
+            image: ImageReference::parse("mcr.microsoft.com/windows/servercore:ltsc2022")
+                .expect("given string is a valid image reference"),
+            volumes: stringvec!["c:\\cache"],
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Error)]
+#[error("unknown isolation `{0}`; must be one of default, hyperv, process")]
+pub struct UnknownIsolationError(String);
+
+/// How containers are isolated on Windows hosts. Process isolation requires the container image
+/// to match the Windows version of the host; Hyper-V isolation runs each container in a utility
+/// VM, which allows older images at the cost of startup time.
+///
+/// Further documentation found in [the Microsoft
+/// docs](https://learn.microsoft.com/en-us/virtualization/windowscontainers/manage-containers/hyperv-container).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Isolation {
+    /// Whatever the Docker daemon is configured to use, which is `process` on Windows Server
+    #[default]
+    Default,
+    Hyperv,
+    Process,
+}
+
+impl Isolation {
+    /// Returns the name of the isolation as it appears in the `isolation` key.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Default => "default",
+            Self::Hyperv => "hyperv",
+            Self::Process => "process",
+        }
+    }
+}
+
+impl fmt::Display for Isolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.name().fmt(f)
+    }
+}
+
+impl FromStr for Isolation {
+    type Err = UnknownIsolationError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "default" => Ok(Self::Default),
+            "hyperv" => Ok(Self::Hyperv),
+            "process" => Ok(Self::Process),
+            _ => Err(UnknownIsolationError(name.to_string())),
+        }
+    }
+}
+
 /// sysctl options for docker
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Sysctls {}
 
 /// Specify additional services that should be run with the job.
@@ -220,9 +299,9 @@ pub struct Sysctls {}
 /// Visit the [Docker Registry](https://hub.docker.com/) for the list of available images.
 /// Each service runs in a separate container and is linked to the job.
 /// Further documentation found in the [GitLab Docs](https://archives.docs.gitlab.com/15.11/runner/configuration/advanced-configuration.html#the-runnersdockerservices-section)
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Service {
-    pub name: String,
+    pub name: ImageReference,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub alias: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -244,8 +323,8 @@ pub struct Service {
 /// pull](https://docs.gitlab.com/runner/executors/docker.html#retry-a-failed-pull), or [restrict
 /// pull
 /// policies](https://docs.gitlab.com/runner/executors/docker.html#allow-docker-pull-policies).
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
-#[serde(rename_all(serialize = "kebab-case"))]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum PullPolicy {
     Always,       // "always"
     IfNotPresent, // "if-not-present"
@@ -358,13 +437,80 @@ where
     }
 }
 
+#[derive(Debug, PartialEq, Eq, Error)]
+#[error("invalid Docker host `{0}`; must be a unix://, tcp://, ssh:// or npipe:// address")]
+pub struct DockerHostParseError(String);
+
+/// Address of the Docker daemon (`DOCKER_HOST`), e.g. `unix:///var/run/docker.sock` on Linux or
+/// `npipe:////./pipe/docker_engine` on Windows.
+///
+/// # Example
+///
+/// ```rust
+/// # use glrcfg::runner::DockerHost;
+/// let host = DockerHost::parse("npipe:////./pipe/docker_engine").unwrap();
+/// assert_eq!(host.as_str(), "npipe:////./pipe/docker_engine");
+/// assert!(DockerHost::parse("/var/run/docker.sock").is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct DockerHost(String);
+
+impl DockerHost {
+    /// Parses a Docker host from an `Into<String>`, e.g. a `&str` or `String`.
+    pub fn parse<S>(host: S) -> Result<Self, DockerHostParseError>
+    where
+        S: Into<String>,
+    {
+        let host = host.into();
+
+        if !DOCKER_HOST_REGEX.is_match(&host) {
+            #[cfg(feature = "tracing")]
+            tracing::error!("invalid Docker host: {host}");
+            return Err(DockerHostParseError(host));
+        }
+
+        Ok(Self(host))
+    }
+
+    /// Returns the Docker host as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for DockerHost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for DockerHost {
+    type Err = DockerHostParseError;
+
+    fn from_str(host: &str) -> Result<Self, Self::Err> {
+        Self::parse(host)
+    }
+}
+
+impl<'a> Deserialize<'a> for DockerHost {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'a>,
+    {
+        let host = String::deserialize(deserializer)?;
+        Self::parse(host).map_err(serde::de::Error::custom)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use pretty_assertions::assert_eq;
     use test_strategy::proptest;
 
     use super::{
-        MaybeMultiple, PullPolicy, SecurityOpt, SECURITY_OPT_REGEX, SECURITY_OPT_REGEX_STR,
+        Docker, DockerHost, Isolation, MaybeMultiple, PullPolicy, SecurityOpt, DOCKER_HOST_REGEX,
+        DOCKER_HOST_REGEX_STR, SECURITY_OPT_REGEX, SECURITY_OPT_REGEX_STR, WINDOWS_DOCKER_HOST,
     };
 
     #[proptest]
@@ -377,6 +523,41 @@ mod test {
         assert!(SecurityOpt::parse(opt).is_err());
     }
 
+    #[proptest]
+    fn parse_valid_docker_hosts(#[strategy(DOCKER_HOST_REGEX_STR)] host: String) {
+        assert_eq!(host, DockerHost::parse(&host).unwrap().as_str());
+    }
+
+    #[proptest]
+    fn parse_invalid_docker_hosts(#[filter(|h| !DOCKER_HOST_REGEX.is_match(h))] host: String) {
+        assert!(DockerHost::parse(host).is_err());
+    }
+
+    #[test]
+    fn parse_docker_hosts() {
+        assert!(DockerHost::parse("unix:///var/run/docker.sock").is_ok());
+        assert!(DockerHost::parse("tcp://10.0.0.2:2376").is_ok());
+        assert!(DockerHost::parse(WINDOWS_DOCKER_HOST).is_ok());
+        assert!(DockerHost::parse("").is_err());
+        assert!(DockerHost::parse("npipe://").is_err());
+        assert!(DockerHost::parse(r"\\.\pipe\docker_engine").is_err());
+    }
+
+    #[test]
+    fn windows_isolation() {
+        assert_eq!("hyperv".parse(), Ok(Isolation::Hyperv));
+        assert!("hyper-v".parse::<Isolation>().is_err());
+
+        let docker = Docker {
+            isolation: Some(Isolation::Process),
+            ..Docker::windows()
+        };
+        let serialized = toml::to_string(&docker).unwrap();
+        assert!(serialized.contains("isolation = \"process\"\n"));
+        // left to the Docker daemon by default
+        assert_eq!(Docker::windows().isolation, None);
+    }
+
     #[test]
     fn pull_policy_serialization() {
         let policy = PullPolicy::Always;
@@ -0,0 +1,132 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::PullPolicy;
+use crate::runner::ImageReference;
+
+/// The `[runners.kubernetes]` section of the `kubernetes` executor, which runs each job in a pod
+/// of a Kubernetes cluster. Only the commonly used keys are supported so far; like with
+/// [`Docker`](super::Docker), all those which are unset don't show up when serializing a config
+/// file, so `gitlab-runner` falls back to its own defaults for them.
+///
+/// See the [`Default` implementation](Self::default) for the default values.
+///
+/// Further documentation found in [the GitLab
+/// docs](https://docs.gitlab.com/runner/executors/kubernetes/#configuration-settings).
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Kubernetes {
+    /// URL of the Kubernetes API server; `gitlab-runner` uses the in-cluster config if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// Default determined from `gitlab-runner` CLI runner creation.
+    pub image: ImageReference,
+    /// Namespace the job pods are created in.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    /// Whether each job gets a namespace of its own, `ci-job-<job ID>`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace_per_job: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub privileged: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_privilege_escalation: Option<bool>,
+    /// Service account the job pods run as.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service_account: Option<String>,
+    /// CPU requested for the build container, e.g. `500m`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_request: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_limit: Option<String>,
+    /// Memory requested for the build container, e.g. `1Gi`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_request: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_limit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub helper_image: Option<ImageReference>,
+    /// Pull policies, tried in order until one succeeds.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pull_policy: Vec<PullPolicy>,
+    /// Seconds to wait for a job pod to start before the job fails.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub poll_timeout: Option<u32>,
+    /// Labels a node must have for the job pods to be scheduled on it, serialized as
+    /// `[runners.kubernetes.node_selector]`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub node_selector: BTreeMap<String, String>,
+    /// Labels of the job pods, serialized as `[runners.kubernetes.pod_labels]`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub pod_labels: BTreeMap<String, String>,
+}
+
+impl Default for Kubernetes {
+    fn default() -> Self {
+        Self {
+            host: None,
+            image: ImageReference::parse("alpine:latest")
+                .expect("given string is a valid image reference"),
+            namespace: None,
+            namespace_per_job: None,
+            privileged: None,
+            allow_privilege_escalation: None,
+            service_account: None,
+            cpu_request: None,
+            cpu_limit: None,
+            memory_request: None,
+            memory_limit: None,
+            helper_image: None,
+            pull_policy: Vec::new(),
+            poll_timeout: None,
+            node_selector: BTreeMap::new(),
+            pod_labels: BTreeMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::Kubernetes;
+    use crate::runner::{Executor, Runner};
+
+    #[test]
+    fn pods_in_namespace() {
+        let runner = Runner {
+            executor: Executor::Kubernetes {
+                kubernetes: Kubernetes {
+                    namespace: Some("gitlab-ci".to_string()),
+                    cpu_request: Some("500m".to_string()),
+                    node_selector: [("kubernetes.io/arch".to_string(), "amd64".to_string())].into(),
+                    ..Default::default()
+                },
+            },
+            ..Default::default()
+        };
+
+        let serialized = toml::Value::try_from(&runner).unwrap();
+        assert_eq!(serialized["executor"].as_str(), Some("kubernetes"));
+
+        let kubernetes = &serialized["kubernetes"];
+        assert_eq!(kubernetes["image"].as_str(), Some("alpine:latest"));
+        assert_eq!(kubernetes["namespace"].as_str(), Some("gitlab-ci"));
+        assert_eq!(kubernetes["cpu_request"].as_str(), Some("500m"));
+        assert_eq!(
+            kubernetes["node_selector"]["kubernetes.io/arch"].as_str(),
+            Some("amd64")
+        );
+        assert!(kubernetes.get("pull_policy").is_none());
+        assert!(kubernetes.get("pod_labels").is_none());
+
+        let deserialized: Runner = toml::from_str(&toml::to_string(&runner).unwrap()).unwrap();
+        let Executor::Kubernetes { kubernetes } = deserialized.executor else {
+            panic!("executor is not kubernetes");
+        };
+        assert_eq!(kubernetes.namespace.as_deref(), Some("gitlab-ci"));
+    }
+}
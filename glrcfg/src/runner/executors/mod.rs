@@ -1,9 +1,29 @@
 // Copyright 2024 bmc::labs GmbH. All rights reserved.
 
+mod autoscaler;
 mod docker;
+mod kubernetes;
 
-pub use docker::{Docker, PullPolicy, SecurityOpt, Service, Sysctls};
-use serde::Serialize;
+use std::{fmt, str::FromStr};
+
+pub use autoscaler::{Autoscaler, AutoscalerPolicy, ConnectorConfig};
+pub use docker::{
+    Docker, DockerHost, DockerHostParseError, Isolation, PullPolicy, SecurityOpt, Service, Sysctls,
+    UnknownIsolationError, WINDOWS_DOCKER_HOST,
+};
+pub use kubernetes::Kubernetes;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Debug, PartialEq, Eq, Error)]
+pub enum ExecutorNameError {
+    #[error("unknown or unsupported executor `{0}`")]
+    Unknown(String),
+    /// `gitlab-runner` rejects a `docker-autoscaler` runner without a fleeting plugin, which has
+    /// no meaningful default.
+    #[error("executor `docker-autoscaler` needs a plugin; use `Executor::docker_autoscaler`")]
+    MissingPlugin,
+}
 
 /// The following executors are available.
 ///
@@ -13,9 +33,9 @@ use serde::Serialize;
 /// ### Note
 ///
 /// Perhaps you noticed we don't support all executors from the list in the GitLab docs. That is
-/// intentional. The executor `docker-windows` is on the roadmap, both `parallels` and `virtualbox`
-/// are still up for debate. We don't plan to ever support `docker+machine`, since the underlying
-/// technology - "Docker Machine" - is deprecated.
+/// intentional. Both `parallels` and `virtualbox` are still up for debate. We don't plan to ever
+/// support `docker+machine`, since the underlying technology - "Docker Machine" - is deprecated;
+/// use `docker-autoscaler` instead.
 //
 // This `#[allow]` turning off the clippy warning for large size differences between enum variants
 // is needed because `Docker` is huge, but using `Box<Docker>` would mean that users would have to
@@ -23,9 +43,113 @@ use serde::Serialize;
 //
 // TODO(@florian): When other variants are added, check if the clippy warning can be turned back on.
 #[allow(clippy::large_enum_variant)]
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "executor", rename_all = "lowercase")]
 pub enum Executor {
     Shell,
-    Docker { docker: Docker },
+    Docker {
+        #[serde(default)]
+        docker: Docker,
+    },
+    /// Docker on Windows hosts, configured in the same `[runners.docker]` section.
+    #[serde(rename = "docker-windows")]
+    DockerWindows {
+        #[serde(default = "Docker::windows")]
+        docker: Docker,
+    },
+    /// Docker on instances provisioned on demand by a fleeting plugin, configured in the
+    /// `[runners.docker]` and `[runners.autoscaler]` sections.
+    #[serde(rename = "docker-autoscaler")]
+    DockerAutoscaler {
+        #[serde(default)]
+        docker: Docker,
+        autoscaler: Autoscaler,
+    },
+    /// Each job in a pod of a Kubernetes cluster, configured in the `[runners.kubernetes]`
+    /// section.
+    Kubernetes {
+        #[serde(default)]
+        kubernetes: Kubernetes,
+    },
+}
+
+impl Executor {
+    /// Returns the name of the executor as it appears in the `executor` key of a runner.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Shell => "shell",
+            Self::Docker { .. } => "docker",
+            Self::DockerWindows { .. } => "docker-windows",
+            Self::DockerAutoscaler { .. } => "docker-autoscaler",
+            Self::Kubernetes { .. } => "kubernetes",
+        }
+    }
+
+    /// Creates an executor with default settings from its name.
+    ///
+    /// This is the replacement for the former stringly-typed `executor` field of
+    /// [`Runner`](crate::runner::Runner): code which used to set the executor by name can call this
+    /// instead, and then adjust the executor-specific section if needed. `docker-autoscaler` is
+    /// refused, since it is unusable without a plugin; use
+    /// [`docker_autoscaler`](Self::docker_autoscaler) instead.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use glrcfg::runner::Executor;
+    /// let executor = Executor::from_name("docker").unwrap();
+    /// assert_eq!(executor.name(), "docker");
+    /// assert!(Executor::from_name("docker+machine").is_err());
+    /// assert!(Executor::from_name("docker-autoscaler").is_err());
+    /// ```
+    pub fn from_name(name: &str) -> Result<Self, ExecutorNameError> {
+        match name {
+            "shell" => Ok(Self::Shell),
+            "docker" => Ok(Self::Docker {
+                docker: Default::default(),
+            }),
+            "docker-windows" => Ok(Self::DockerWindows {
+                docker: Docker::windows(),
+            }),
+            "docker-autoscaler" => Err(ExecutorNameError::MissingPlugin),
+            "kubernetes" => Ok(Self::Kubernetes {
+                kubernetes: Default::default(),
+            }),
+            _ => Err(ExecutorNameError::Unknown(name.to_string())),
+        }
+    }
+
+    /// Creates a `docker-autoscaler` executor with default settings, provisioning instances
+    /// through the given fleeting plugin.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use glrcfg::runner::Executor;
+    /// let executor = Executor::docker_autoscaler("aws");
+    /// assert_eq!(executor.name(), "docker-autoscaler");
+    /// ```
+    pub fn docker_autoscaler(plugin: impl Into<String>) -> Self {
+        Self::DockerAutoscaler {
+            docker: Default::default(),
+            autoscaler: Autoscaler {
+                plugin: plugin.into(),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl fmt::Display for Executor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.name().fmt(f)
+    }
+}
+
+impl FromStr for Executor {
+    type Err = ExecutorNameError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::from_name(name)
+    }
 }
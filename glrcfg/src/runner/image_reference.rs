@@ -0,0 +1,281 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{fmt, str::FromStr};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Maximum length of the name part of an image reference, i.e. registry and repository.
+const NAME_MAX_LENGTH: usize = 255;
+
+/// Maximum length of the tag of an image reference.
+const TAG_MAX_LENGTH: usize = 128;
+
+static IMAGE_REFERENCE_REGEX: Lazy<Regex> = Lazy::new(|| {
+    let domain_component = r"[a-zA-Z0-9](?:[a-zA-Z0-9-]*[a-zA-Z0-9])?";
+    let path_component = r"[a-z0-9]+(?:(?:[._]|__|-+)[a-z0-9]+)*";
+    // `gitlab-runner` expands variables like `${CI_RUNNER_REVISION}` in tags of helper images
+    let variable = r"\$\{[a-zA-Z0-9_]+\}";
+
+    Regex::new(&format!(
+        "^(?:(?P<registry>{domain_component}(?:\\.{domain_component})*(?::[0-9]+)?)/)?\
+         (?P<repository>{path_component}(?:/{path_component})*)\
+         (?::(?P<tag>(?:[a-zA-Z0-9_]|{variable})(?:[a-zA-Z0-9_.-]|{variable})*))?\
+         (?:@(?P<digest>[a-z0-9]+(?:[+._-][a-z0-9]+)*:[0-9a-fA-F]{{32,}}))?$"
+    ))
+    .expect("instantiating IMAGE_REFERENCE_REGEX from given static string must not fail")
+});
+
+#[derive(Debug, PartialEq, Eq, Error)]
+#[error("invalid image reference `{0}`; must look like [registry/]repository[:tag][@digest]")]
+pub struct ImageReferenceParseError(String);
+
+/// A reference to a container image, as used for the job image, the helper image and service
+/// images: `[registry[:port]/]repository[:tag][@digest]`, e.g. `alpine:3.20` or
+/// `registry.your-company.com:5000/ci/rust:1.79@sha256:...`.
+///
+/// The reference is validated following the grammar Docker uses, so typos like `alpine latest`
+/// are caught when the config is written rather than when a job pulls the image. As with Docker,
+/// the first path component is only taken as the registry if it contains a `.` or a `:`, or is
+/// `localhost`.
+///
+/// # Example
+///
+/// ```rust
+/// # use glrcfg::runner::ImageReference;
+/// let image = ImageReference::parse("registry.local:5000/ci/rust:1.79").unwrap();
+/// assert_eq!(image.registry(), Some("registry.local:5000"));
+/// assert_eq!(image.repository(), "ci/rust");
+/// assert_eq!(image.tag(), Some("1.79"));
+/// assert_eq!(image.digest(), None);
+/// assert!(ImageReference::parse("alpine latest").is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(transparent)]
+pub struct ImageReference(String);
+
+impl ImageReference {
+    /// Parses an image reference from an `Into<String>`, e.g. a `&str` or `String`.
+    pub fn parse<S>(reference: S) -> Result<Self, ImageReferenceParseError>
+    where
+        S: Into<String>,
+    {
+        let reference = Self(reference.into());
+
+        if !reference.is_valid() {
+            #[cfg(feature = "tracing")]
+            tracing::error!("invalid image reference: {reference}");
+            return Err(ImageReferenceParseError(reference.0));
+        }
+
+        Ok(reference)
+    }
+
+    /// Returns `true` if the image reference follows the grammar. Only references read from a
+    /// database may be invalid, since they are decoded as they are.
+    pub fn is_valid(&self) -> bool {
+        let Some(captures) = IMAGE_REFERENCE_REGEX.captures(&self.0) else {
+            return false;
+        };
+
+        let name_length = captures
+            .name("repository")
+            .map_or(0, |repository| repository.end());
+        let tag_length = captures.name("tag").map_or(0, |tag| tag.len());
+        name_length <= NAME_MAX_LENGTH && tag_length <= TAG_MAX_LENGTH
+    }
+
+    /// Returns the image reference as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the registry the image is pulled from, if it's not the default registry.
+    pub fn registry(&self) -> Option<&str> {
+        self.parts().0
+    }
+
+    /// Returns the repository of the image, e.g. `library/alpine` or `alpine`.
+    pub fn repository(&self) -> &str {
+        self.parts().1
+    }
+
+    /// Returns the tag of the image, if it has one.
+    pub fn tag(&self) -> Option<&str> {
+        self.part("tag")
+    }
+
+    /// Returns the digest of the image, if it has one.
+    pub fn digest(&self) -> Option<&str> {
+        self.part("digest")
+    }
+
+    /// Returns the registry and the repository, telling apart registries from the first path
+    /// component of the repository, e.g. `library` in `library/alpine`.
+    fn parts(&self) -> (Option<&str>, &str) {
+        let repository = self.part("repository").unwrap_or_default();
+
+        match self.part("registry") {
+            Some(registry)
+                if registry.contains(['.', ':'])
+                    || registry == "localhost"
+                    || registry.chars().any(|c| c.is_ascii_uppercase()) =>
+            {
+                (Some(registry), repository)
+            }
+            Some(registry) => (None, &self.0[..registry.len() + 1 + repository.len()]),
+            None => (None, repository),
+        }
+    }
+
+    fn part(&self, name: &str) -> Option<&str> {
+        IMAGE_REFERENCE_REGEX
+            .captures(&self.0)
+            .and_then(|captures| captures.name(name))
+            .map(|part| &self.0[part.range()])
+    }
+}
+
+impl fmt::Display for ImageReference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for ImageReference {
+    type Err = ImageReferenceParseError;
+
+    fn from_str(reference: &str) -> Result<Self, Self::Err> {
+        Self::parse(reference)
+    }
+}
+
+impl<'a> Deserialize<'a> for ImageReference {
+    fn deserialize<D>(deserializer: D) -> Result<ImageReference, D::Error>
+    where
+        D: serde::Deserializer<'a>,
+    {
+        let reference = String::deserialize(deserializer)?;
+        ImageReference::parse(reference).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(feature = "sqlx")]
+impl<DB> sqlx::Type<DB> for ImageReference
+where
+    DB: sqlx::Database,
+    String: sqlx::Type<DB>,
+{
+    fn type_info() -> DB::TypeInfo {
+        <String as sqlx::Type<DB>>::type_info()
+    }
+
+    fn compatible(ty: &DB::TypeInfo) -> bool {
+        <String as sqlx::Type<DB>>::compatible(ty)
+    }
+}
+
+#[cfg(feature = "sqlx")]
+impl<'a, DB> sqlx::Encode<'a, DB> for ImageReference
+where
+    DB: sqlx::Database,
+    String: sqlx::Encode<'a, DB>,
+{
+    fn encode_by_ref(
+        &self,
+        buf: &mut <DB as sqlx::database::HasArguments<'a>>::ArgumentBuffer,
+    ) -> sqlx::encode::IsNull {
+        self.0.encode_by_ref(buf)
+    }
+}
+
+#[cfg(feature = "sqlx")]
+impl<'a, DB> sqlx::Decode<'a, DB> for ImageReference
+where
+    DB: sqlx::Database,
+    String: sqlx::Decode<'a, DB>,
+{
+    /// Takes the stored value as it is, even if it's invalid: it may have been stored before image
+    /// references were validated, and refusing it would make the whole row unreadable. Use
+    /// [`ImageReference::is_valid`] to find such references.
+    fn decode(
+        value: <DB as sqlx::database::HasValueRef<'a>>::ValueRef,
+    ) -> Result<Self, Box<dyn std::error::Error + 'static + Send + Sync>> {
+        let reference = Self(<String as sqlx::Decode<DB>>::decode(value)?);
+
+        #[cfg(feature = "tracing")]
+        if !reference.is_valid() {
+            tracing::warn!("invalid image reference read from database: {reference}");
+        }
+
+        Ok(reference)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::ImageReference;
+
+    #[test]
+    fn parse_valid_image_references() {
+        for (reference, registry, repository, tag) in [
+            ("alpine", None, "alpine", None),
+            ("alpine:latest", None, "alpine", Some("latest")),
+            ("library/alpine:3.20", None, "library/alpine", Some("3.20")),
+            ("my-org/my_app__x:v1.0-rc.1", None, "my-org/my_app__x", Some("v1.0-rc.1")),
+            ("localhost/alpine", Some("localhost"), "alpine", None),
+            ("registry.local:5000/alpine", Some("registry.local:5000"), "alpine", None),
+            (
+                "mcr.microsoft.com/windows/servercore:ltsc2022",
+                Some("mcr.microsoft.com"),
+                "windows/servercore",
+                Some("ltsc2022"),
+            ),
+            (
+                "registry.gitlab.com/gitlab-org/gitlab-runner/gitlab-runner-helper:x86_64-${CI_RUNNER_REVISION}",
+                Some("registry.gitlab.com"),
+                "gitlab-org/gitlab-runner/gitlab-runner-helper",
+                Some("x86_64-${CI_RUNNER_REVISION}"),
+            ),
+        ] {
+            let image = ImageReference::parse(reference).unwrap();
+            assert_eq!(image.as_str(), reference);
+            assert_eq!(image.registry(), registry, "{reference}");
+            assert_eq!(image.repository(), repository, "{reference}");
+            assert_eq!(image.tag(), tag, "{reference}");
+        }
+
+        let digest = "sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+        let image = ImageReference::parse(format!("alpine:3.20@{digest}")).unwrap();
+        assert_eq!(image.tag(), Some("3.20"));
+        assert_eq!(image.digest(), Some(digest));
+    }
+
+    #[test]
+    fn parse_invalid_image_references() {
+        for reference in [
+            "",
+            "alpine latest",
+            "Alpine",
+            "alpine:",
+            "alpine:-latest",
+            "alpine@sha256:abc",
+            "-alpine",
+            "alpine/",
+            "/alpine",
+            "registry.local:port/alpine",
+            "alpine:${CI_RUNNER_REVISION",
+        ] {
+            assert!(ImageReference::parse(reference).is_err(), "{reference}");
+        }
+
+        let long = format!("alpine/{}", "a".repeat(250));
+        assert!(ImageReference::parse(long).is_err());
+        let long = format!("alpine:{}", "a".repeat(129));
+        assert!(ImageReference::parse(long).is_err());
+    }
+}
@@ -1,24 +1,45 @@
 // Copyright 2024 bmc::labs GmbH. All rights reserved.
 
+mod absolute_path;
+mod cache;
 mod date_time;
 mod executors;
+mod image_reference;
+mod redact;
+mod registration_token;
 mod runner_token;
+mod shell;
 mod url;
 
+pub use absolute_path::{AbsolutePath, AbsolutePathParseError};
+pub use cache::{Cache, CacheAzure, CacheGcs, CacheS3, CacheType, S3AuthenticationType};
 pub use date_time::DateTime;
-pub use executors::{Docker, Executor, PullPolicy, SecurityOpt, Service, Sysctls};
+pub use executors::{
+    Autoscaler, AutoscalerPolicy, ConnectorConfig, Docker, DockerHost, DockerHostParseError,
+    Executor, ExecutorNameError, Isolation, Kubernetes, PullPolicy, SecurityOpt, Service, Sysctls,
+    UnknownIsolationError, WINDOWS_DOCKER_HOST,
+};
+pub use image_reference::{ImageReference, ImageReferenceParseError};
+pub use redact::redact_tokens;
+pub use registration_token::{RegistrationToken, RegistrationTokenParseError};
 pub use runner_token::{RunnerToken, RunnerTokenParseError};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+pub use shell::{Shell, UnknownShellError};
 pub use url::Url;
 
+use crate::GolangDuration;
+
 /// Defines one runner.
 ///
-/// See the [`Default` implementation](Self::default) for the default values.
+/// See the [`Default` implementation](Self::default) for the default values. When deserializing
+/// a `[[runners]]` entry, e.g. one written by `gitlab-runner register`, keys which are left out
+/// take the same defaults, except for those identifying the runner; unknown keys are ignored.
 ///
 /// Further documentation found in [the GitLab
 /// docs](https://docs.gitlab.com/runner/configuration/advanced-configuration.html#the-runners-section).
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct Runner {
+    #[serde(default)]
     pub id: u32,
     pub name: String,
     pub url: Url,
@@ -30,21 +51,62 @@ pub struct Runner {
     /// but it is present in the configuration files generated by the `gitlab-runner` binary. It's
     /// the timestamp of when the token was first handed to the service processing it (and using
     /// this library).
+    #[serde(default = "DateTime::now")]
     pub token_obtained_at: DateTime,
     /// Timestamp of when the token will "expire". While being undocumented in the GitLab Runner
     /// docs for the configuration file, it can be obtained through [the GitLab
     /// API](https://docs.gitlab.com/ee/api/runners.html#verify-authentication-for-a-registered-runner)
     /// and can be set here.
+    #[serde(default = "never_expires")]
     pub token_expires_at: DateTime,
+    #[serde(default)]
     pub limit: u32,
+    /// The distributed cache shared by jobs on different hosts, serialized as `[runners.cache]`.
+    /// If unset, the section is left out and caches are kept on the runner's host only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache: Option<Cache>,
+    /// The executor and its settings, serialized as the `executor` key plus the executor-specific
+    /// section, e.g. `executor = "docker"` and `[runners.docker]`.
     #[serde(flatten)]
     pub executor: Executor,
-    pub builds_dir: String,
-    pub cache_dir: String,
+    /// Shell to generate job scripts for. If unset, `gitlab-runner` uses the default of its host.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shell: Option<Shell>,
+    /// Absolute path to a directory where builds are stored in the context of the selected
+    /// executor. If unset, `gitlab-runner` uses its default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub builds_dir: Option<AbsolutePath>,
+    /// Absolute path to a directory where build caches are stored in the context of the selected
+    /// executor. If unset, `gitlab-runner` uses its default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_dir: Option<AbsolutePath>,
     /// Used to set environment variables for a runner or job. Example: `["FOO=bar", "BAZ=qux"]`
+    #[serde(default)]
     pub environment: Vec<String>,
+    #[serde(default = "default_request_concurrency")]
     pub request_concurrency: u32,
+    #[serde(default = "default_output_limit")]
     pub output_limit: u32,
+    /// Overwrites the URL of the GitLab instance for cloning repositories, e.g. for runners behind
+    /// split-horizon DNS. See [the GitLab
+    /// docs](https://docs.gitlab.com/runner/configuration/advanced-configuration.html#how-clone_url-works).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clone_url: Option<Url>,
+    /// Disables the `CI_DEBUG_TRACE` feature, i.e. jobs can't enable debug tracing even if they
+    /// set the variable to `true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug_trace_disabled: Option<bool>,
+    /// Runs `git config --global --add safe.directory` for the build directory before cloning,
+    /// which is needed when the build directory is owned by a different user.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub safe_directory_checkout: Option<bool>,
+    /// Number of consecutive failed requests to GitLab after which the runner is considered
+    /// unhealthy and stops requesting jobs for `unhealthy_interval`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unhealthy_requests_limit: Option<u32>,
+    /// Duration for which an unhealthy runner stops requesting jobs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unhealthy_interval: Option<GolangDuration>,
 }
 
 impl Default for Runner {
@@ -56,17 +118,125 @@ impl Default for Runner {
             token: RunnerToken::parse("glrt-0123456789_abcdefXYZ")
                 .expect("given string is a valid token"),
             token_obtained_at: DateTime::now(),
-            token_expires_at: DateTime::parse("0001-01-01T00:00:00Z")
-                .expect("given string is a valid ISO8601 timestamp"),
+            token_expires_at: never_expires(),
             limit: 0,
+            cache: None,
             executor: Executor::Docker {
                 docker: Default::default(),
             },
-            builds_dir: "".to_string(),
-            cache_dir: "".to_string(),
+            shell: None,
+            builds_dir: None,
+            cache_dir: None,
             environment: vec![],
-            request_concurrency: 1,
-            output_limit: 4096,
+            request_concurrency: default_request_concurrency(),
+            output_limit: default_output_limit(),
+            clone_url: None,
+            debug_trace_disabled: None,
+            safe_directory_checkout: None,
+            unhealthy_requests_limit: None,
+            unhealthy_interval: None,
         }
     }
 }
+
+/// Expiry `gitlab-runner` writes for tokens which don't expire.
+fn never_expires() -> DateTime {
+    DateTime::parse("0001-01-01T00:00:00Z").expect("given string is a valid ISO8601 timestamp")
+}
+
+fn default_request_concurrency() -> u32 {
+    1
+}
+
+/// Output limit of `gitlab-runner` in KiB, unless configured otherwise.
+fn default_output_limit() -> u32 {
+    4096
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+
+    use super::{Executor, Runner, Url};
+    use crate::GolangDuration;
+
+    #[test]
+    fn optional_runner_options() {
+        let toml = toml::to_string(&Runner::default()).unwrap();
+        for key in [
+            "clone_url",
+            "debug_trace_disabled",
+            "safe_directory_checkout",
+            "unhealthy_requests_limit",
+            "unhealthy_interval",
+        ] {
+            assert!(!toml.contains(key), "{key} is unset but serialized");
+        }
+
+        let runner = Runner {
+            clone_url: Some(Url::parse("https://gitlab.internal/").unwrap()),
+            debug_trace_disabled: Some(true),
+            safe_directory_checkout: Some(true),
+            unhealthy_requests_limit: Some(3),
+            unhealthy_interval: Some(GolangDuration::parse("1h").unwrap()),
+            ..Default::default()
+        };
+        let toml = toml::to_string(&runner).unwrap();
+        assert!(toml.contains("clone_url = \"https://gitlab.internal/\""));
+        assert!(toml.contains("debug_trace_disabled = true"));
+        assert!(toml.contains("safe_directory_checkout = true"));
+        assert!(toml.contains("unhealthy_requests_limit = 3"));
+        assert!(toml.contains("unhealthy_interval = \"1h\""));
+    }
+
+    #[test]
+    fn deserialize_registered_runner() {
+        #[derive(serde::Deserialize)]
+        struct Runners {
+            runners: Vec<Runner>,
+        }
+
+        // as written by `gitlab-runner register`, with TOML datetimes and keys glrcfg doesn't know
+        let Runners { mut runners } = toml::from_str(indoc::indoc! {r#"
+            [[runners]]
+              name = "usain-bolt"
+              url = "https://gitlab.your-company.com"
+              id = 42
+              token = "glrt-0123456789_abcdefXYZ"
+              token_obtained_at = 2024-06-26T10:00:00Z
+              token_expires_at = 0001-01-01T00:00:00Z
+              executor = "docker"
+              [runners.custom_build_dir]
+              [runners.docker]
+                tls_verify = false
+                image = "rust:1.79"
+                privileged = true
+                volumes = ["/cache"]
+                shm_size = 0
+                network_mtu = 0
+        "#})
+        .unwrap();
+        let runner = runners.pop().unwrap();
+
+        assert_eq!(runner.id, 42);
+        assert_eq!(runner.token.as_str(), "glrt-0123456789_abcdefXYZ");
+        assert_eq!(
+            runner.token_obtained_at.to_iso8601(),
+            "2024-06-26T10:00:00Z"
+        );
+        assert_eq!(runner.token_expires_at, Runner::default().token_expires_at);
+        assert_eq!(runner.output_limit, 4096);
+        let Executor::Docker { docker } = &runner.executor else {
+            panic!("expected the docker executor, got {}", runner.executor);
+        };
+        assert_eq!(docker.image.as_str(), "rust:1.79");
+        assert!(docker.privileged);
+
+        // what glrcfg writes, it reads back
+        let toml = toml::to_string(&runner).unwrap();
+        let read: Runner = toml::from_str(&toml).unwrap();
+        assert_eq!(toml::to_string(&read).unwrap(), toml);
+
+        assert!(toml::from_str::<Runner>("name = \"no-token\"").is_err());
+    }
+}
@@ -0,0 +1,64 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::borrow::Cow;
+
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+
+use super::runner_token::mask;
+
+// deliberately laxer than the token types: anything that looks like a token is masked, whether
+// or not it would parse as one
+static TOKEN_LIKE_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(glrtr?-|GR1348941)[\w-]{16,}")
+        .expect("instantiating TOKEN_LIKE_REGEX from given static string must not fail")
+});
+
+/// Masks every runner token and registration token in `text` the way
+/// [`RunnerToken::masked`](super::RunnerToken::masked) does, so that the text can end up in logs.
+/// Returns `text` unchanged if it doesn't contain any tokens.
+///
+/// # Example
+///
+/// ```rust
+/// # use glrcfg::runner::redact_tokens;
+/// assert_eq!(
+///     redact_tokens(r#"{"token":"glrt-0123456789_abcdefXYZ"}"#),
+///     r#"{"token":"glrt-****XYZ"}"#
+/// );
+/// assert_eq!(redact_tokens("no secrets here"), "no secrets here");
+/// ```
+pub fn redact_tokens(text: &str) -> Cow<'_, str> {
+    TOKEN_LIKE_REGEX.replace_all(text, |captures: &Captures| mask(&captures[1], &captures[0]))
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+    use test_strategy::proptest;
+
+    use super::redact_tokens;
+    use crate::runner::RunnerToken;
+
+    #[test]
+    fn redact_all_kinds_of_tokens() {
+        assert_eq!(
+            redact_tokens(
+                "glrt-0123456789_abcdefXYZ, glrtr-0123456789-abcdefUVW and \
+                 GR1348941abcdefghij0123456789"
+            ),
+            "glrt-****XYZ, glrtr-****UVW and GR1348941****789"
+        );
+    }
+
+    #[test]
+    fn keep_short_lookalikes() {
+        assert_eq!(redact_tokens("glrt-tooshort"), "glrt-tooshort");
+    }
+
+    #[proptest]
+    fn redact_like_masked(#[strategy(r"glrtr?-[\w-]{16,32}")] token: String) {
+        let masked = RunnerToken::parse(&token).unwrap().masked();
+        assert_eq!(redact_tokens(&token), masked);
+    }
+}
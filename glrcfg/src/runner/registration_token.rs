@@ -0,0 +1,121 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{fmt, str::FromStr};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::runner_token::mask;
+
+static REGISTRATION_TOKEN_PREFIX: &str = "GR1348941";
+static REGISTRATION_TOKEN_REGEX_STR: &str = r"GR1348941[\w-]{20}";
+static REGISTRATION_TOKEN_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(&format!("^{REGISTRATION_TOKEN_REGEX_STR}$"))
+        .expect("instantiating REGISTRATION_TOKEN_REGEX from given static string must not fail")
+});
+
+#[derive(Debug, PartialEq, Eq, Error)]
+#[error("invalid registration token; must look like GR1348941abcdefghij0123456789")]
+pub struct RegistrationTokenParseError;
+
+/// Before GitLab introduced runner authentication tokens (see [`RunnerToken`](super::RunnerToken)),
+/// runners were registered using a registration token per project, group or instance. This flow
+/// is deprecated, but older GitLab instances still use it: the registration token is sent to the
+/// GitLab API (`POST /api/v4/runners`), which in turn responds with a runner token.
+///
+/// A registration token never ends up in the configuration file. Valid tokens start with
+/// `GR1348941`, followed by exactly 20 alphanumeric characters, plus underscore and hyphen.
+///
+/// # Example
+///
+/// ```rust
+/// # use glrcfg::runner::RegistrationToken;
+/// let token = RegistrationToken::parse("GR1348941abcdefghij0123456789").unwrap();
+/// assert_eq!(token.as_str(), "GR1348941abcdefghij0123456789");
+/// assert_eq!(token.masked(), "GR1348941****789");
+/// assert!(RegistrationToken::parse("glrt-0123456789_abcdefXYZ").is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct RegistrationToken(String);
+
+impl RegistrationToken {
+    /// Parses a registration token from an `Into<String>`, e.g. a `&str` or `String`.
+    pub fn parse<S>(token: S) -> Result<Self, RegistrationTokenParseError>
+    where
+        S: Into<String>,
+    {
+        let token = token.into();
+
+        if !REGISTRATION_TOKEN_REGEX.is_match(&token) {
+            #[cfg(feature = "tracing")]
+            tracing::error!("invalid registration token: {}", mask("", &token));
+            return Err(RegistrationTokenParseError);
+        }
+
+        Ok(Self(token))
+    }
+
+    /// Returns the registration token as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns the registration token with everything but its prefix and last three characters
+    /// masked, e.g. `GR1348941****XYZ`.
+    pub fn masked(&self) -> String {
+        mask(REGISTRATION_TOKEN_PREFIX, &self.0)
+    }
+}
+
+impl fmt::Display for RegistrationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for RegistrationToken {
+    type Err = RegistrationTokenParseError;
+
+    fn from_str(token: &str) -> Result<Self, Self::Err> {
+        Self::parse(token)
+    }
+}
+
+impl<'a> Deserialize<'a> for RegistrationToken {
+    fn deserialize<D>(deserializer: D) -> Result<RegistrationToken, D::Error>
+    where
+        D: serde::Deserializer<'a>,
+    {
+        let token = String::deserialize(deserializer)?;
+        RegistrationToken::parse(token).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pretty_assertions::assert_eq;
+    use test_strategy::proptest;
+
+    use super::{RegistrationToken, REGISTRATION_TOKEN_REGEX, REGISTRATION_TOKEN_REGEX_STR};
+
+    #[proptest]
+    fn parse_valid_registration_tokens(#[strategy(REGISTRATION_TOKEN_REGEX_STR)] token: String) {
+        assert_eq!(token, RegistrationToken::parse(&token).unwrap().as_str());
+    }
+
+    #[proptest]
+    fn parse_invalid_registration_tokens(
+        #[filter(|t| !REGISTRATION_TOKEN_REGEX.is_match(t))] token: String,
+    ) {
+        assert!(RegistrationToken::parse(token).is_err());
+    }
+
+    #[test]
+    fn runner_tokens_are_not_registration_tokens() {
+        assert!(RegistrationToken::parse("glrt-ZJAbdjMq-ViUVE_zd1VD").is_err());
+        assert!(RegistrationToken::parse("GR1348941").is_err());
+    }
+}
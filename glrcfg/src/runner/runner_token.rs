@@ -7,24 +7,28 @@ use regex::Regex;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-static RUNNER_TOKEN_REGEX_STR: &str = r"glrt-[\w-]{16,32}"; // note the hyphen
+static RUNNER_TOKEN_REGEX_STR: &str = r"glrtr?-[\w-]{16,32}"; // note the hyphen
 static RUNNER_TOKEN_REGEX: Lazy<Regex> = Lazy::new(|| {
     Regex::new(&format!("^{RUNNER_TOKEN_REGEX_STR}$"))
         .expect("instantiating RUNNER_TOKEN_REGEX from given static string must not fail")
 });
 
 #[derive(Debug, PartialEq, Eq, Error)]
-#[error("invalid runner token `{0}`; must look like glrt-0123456789_abcdefXYZ")]
-pub struct RunnerTokenParseError(String);
+#[error("invalid runner token; must look like glrt-0123456789_abcdefXYZ")]
+pub struct RunnerTokenParseError;
 
 /// GitLab uses various kinds of tokens for authentication. When registering a runner via the
 /// GitLab UI, a runner token is generated and presented to the user. It must then be provided to
 /// the `gitlab-runner`  binary via the `--token` argument, or, as is the intention here, via the
 /// configuration file.
 ///
-/// Valid tokens start with `glrt-`, followed by at least 16 and at most 32 alphanumeric
-/// characters, plus underscore. An alphanumeric character is one which matches the regular
-/// expression `[a-zA-Z0-9_]` (note the underscore being part of the allowed characters).
+/// Valid tokens start with `glrt-` (created in the GitLab UI) or `glrtr-` (created through the
+/// legacy registration flow, see [`RegistrationToken`](super::RegistrationToken)), followed by at
+/// least 16 and at most 32 alphanumeric characters, plus underscore. An alphanumeric character is
+/// one which matches the regular expression `[a-zA-Z0-9_]` (note the underscore being part of the
+/// allowed characters).
+///
+/// Since the token is a secret, use [`RunnerToken::masked`] whenever it ends up in logs.
 ///
 /// # Example
 ///
@@ -32,6 +36,8 @@ pub struct RunnerTokenParseError(String);
 /// # use glrcfg::runner::RunnerToken;
 /// let runner_token = RunnerToken::parse("glrt-0123456789_abcdefXYZ").unwrap();
 /// assert_eq!(runner_token.as_str(), "glrt-0123456789_abcdefXYZ");
+/// assert_eq!(runner_token.masked(), "glrt-****XYZ");
+/// assert!(RunnerToken::parse("glrtr-0123456789_abcdefXYZ").is_ok());
 /// assert!(RunnerToken::parse("warblgarbl").is_err());
 /// ```
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
@@ -48,8 +54,8 @@ impl RunnerToken {
 
         if !RUNNER_TOKEN_REGEX.is_match(&token) {
             #[cfg(feature = "tracing")]
-            tracing::error!("invalid runner token: {token}");
-            return Err(RunnerTokenParseError(token));
+            tracing::error!("invalid runner token: {}", mask("", &token));
+            return Err(RunnerTokenParseError);
         }
 
         Ok(Self(token))
@@ -59,6 +65,29 @@ impl RunnerToken {
     pub fn as_str(&self) -> &str {
         &self.0
     }
+
+    /// Returns the prefix of the runner token, i.e. `glrt-` or `glrtr-`.
+    pub fn prefix(&self) -> &str {
+        if self.0.starts_with("glrtr-") {
+            "glrtr-"
+        } else {
+            "glrt-"
+        }
+    }
+
+    /// Returns the runner token with everything but its prefix and last three characters masked,
+    /// e.g. `glrt-****XYZ`.
+    pub fn masked(&self) -> String {
+        mask(self.prefix(), &self.0)
+    }
+}
+
+/// Masks `token` except for its `prefix` and its last three characters.
+pub(super) fn mask(prefix: &str, token: &str) -> String {
+    let mut suffix: Vec<char> = token.chars().rev().take(3).collect();
+    suffix.reverse();
+
+    format!("{prefix}****{}", suffix.into_iter().collect::<String>())
 }
 
 impl fmt::Display for RunnerToken {
@@ -145,6 +174,12 @@ mod test {
         assert!(RunnerToken::parse(token).is_err());
     }
 
+    #[test]
+    fn parse_error_hides_token() {
+        let err = RunnerToken::parse("glrt-tooshort").unwrap_err();
+        assert!(!err.to_string().contains("glrt-tooshort"));
+    }
+
     #[test]
     fn parse_known_valid_runner_tokens() {
         let token = "glrt-ZJAbdjMq-ViUVE_zd1VD";
@@ -171,4 +206,13 @@ mod test {
         let token = "glrt-t1_CkM3EZEjJ84ts_tYyVCB";
         assert_eq!(token, RunnerToken::parse(token).unwrap().as_str());
     }
+
+    #[test]
+    fn masked_runner_tokens() {
+        let token = RunnerToken::parse("glrt-ZJAbdjMq-ViUVE_zd1VD").unwrap();
+        assert_eq!(token.masked(), "glrt-****1VD");
+
+        let token = RunnerToken::parse("glrtr-ZJAbdjMq-ViUVE_zd1VD").unwrap();
+        assert_eq!(token.masked(), "glrtr-****1VD");
+    }
 }
@@ -0,0 +1,58 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Debug, PartialEq, Eq, Error)]
+#[error("unknown shell `{0}`; must be one of bash, sh, powershell, pwsh")]
+pub struct UnknownShellError(String);
+
+/// The shell `gitlab-runner` generates job scripts for. If unset, `gitlab-runner` picks the
+/// default for the host OS, which is `bash` on Linux and `pwsh` on Windows.
+///
+/// Further documentation found in [the GitLab
+/// docs](https://docs.gitlab.com/runner/shells/).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Shell {
+    Bash,
+    Sh,
+    /// Windows PowerShell, i.e. `powershell.exe`
+    Powershell,
+    /// PowerShell Core
+    Pwsh,
+}
+
+impl Shell {
+    /// Returns the name of the shell as it appears in the `shell` key of a runner.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Bash => "bash",
+            Self::Sh => "sh",
+            Self::Powershell => "powershell",
+            Self::Pwsh => "pwsh",
+        }
+    }
+}
+
+impl fmt::Display for Shell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.name().fmt(f)
+    }
+}
+
+impl FromStr for Shell {
+    type Err = UnknownShellError;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "bash" => Ok(Self::Bash),
+            "sh" => Ok(Self::Sh),
+            "powershell" => Ok(Self::Powershell),
+            "pwsh" => Ok(Self::Pwsh),
+            _ => Err(UnknownShellError(name.to_string())),
+        }
+    }
+}
@@ -31,6 +31,33 @@ impl Url {
     pub fn as_str(&self) -> &str {
         self.0.as_str()
     }
+
+    /// Returns the URL in a canonical form, so that URLs of the same GitLab instance compare
+    /// equal. Parsing already lowercases scheme and host and drops default ports; this also
+    /// removes trailing slashes from the path as well as query and fragment.
+    ///
+    /// ```rust
+    /// # use glrcfg::runner::Url;
+    /// let url = Url::parse("HTTPS://GitLab.Example.com:443/gitlab/?tab=runners").unwrap();
+    /// assert_eq!("https://gitlab.example.com/gitlab", url.normalized().as_str());
+    /// let url = Url::parse("https://gitlab.example.com").unwrap();
+    /// assert_eq!(url.normalized(), Url::parse("https://gitlab.example.com/").unwrap());
+    /// ```
+    pub fn normalized(&self) -> Self {
+        let mut url = self.0.clone();
+        url.set_query(None);
+        url.set_fragment(None);
+
+        let path = url.path().trim_end_matches('/').to_string();
+        url.set_path(&path);
+
+        Self(url)
+    }
+
+    /// Returns the host of the URL, if it has one.
+    pub fn host_str(&self) -> Option<&str> {
+        self.0.host_str()
+    }
 }
 
 impl fmt::Display for Url {
@@ -1,7 +1,8 @@
 // Copyright 2024 bmc::labs GmbH. All rights reserved.
 
 use serde::Serialize;
-use url::Url;
+
+use crate::ListenAddress;
 
 /// The `[session_server]` section lets users interact with jobs, for example, in the interactive
 /// web terminal.
@@ -15,10 +16,12 @@ use url::Url;
 /// docs](https://docs.gitlab.com/runner/configuration/advanced-configuration.html#the-session_server-section).
 #[derive(Debug, Serialize)]
 pub struct SessionServer {
+    /// Address the session server listens on, given as `host:port`, e.g. `[::]:8093`.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub listen_address: Option<Url>,
+    pub listen_address: Option<ListenAddress>,
+    /// Address GitLab reaches the session server at, also given as `host:port`.
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub advertise_address: Option<Url>,
+    pub advertise_address: Option<ListenAddress>,
     pub session_timeout: u32,
 }
 
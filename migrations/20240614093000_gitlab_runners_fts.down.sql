@@ -0,0 +1,6 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+DROP TRIGGER IF EXISTS gitlab_runners_fts_update;
+DROP TRIGGER IF EXISTS gitlab_runners_fts_delete;
+DROP TRIGGER IF EXISTS gitlab_runners_fts_insert;
+DROP TABLE IF EXISTS gitlab_runners_fts;
@@ -0,0 +1,32 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+-- Full-text index over the fields support teams search by. The trigram tokenizer allows matching
+-- arbitrary fragments, e.g. the last few characters of a token.
+CREATE VIRTUAL TABLE IF NOT EXISTS gitlab_runners_fts USING fts5(
+    name,
+    url,
+    token,
+    docker_image,
+    content = 'gitlab_runners',
+    content_rowid = 'rowid',
+    tokenize = 'trigram'
+);
+
+INSERT INTO gitlab_runners_fts (gitlab_runners_fts) VALUES ('rebuild');
+
+CREATE TRIGGER IF NOT EXISTS gitlab_runners_fts_insert AFTER INSERT ON gitlab_runners BEGIN
+    INSERT INTO gitlab_runners_fts (rowid, name, url, token, docker_image)
+    VALUES (new.rowid, new.name, new.url, new.token, new.docker_image);
+END;
+
+CREATE TRIGGER IF NOT EXISTS gitlab_runners_fts_delete AFTER DELETE ON gitlab_runners BEGIN
+    INSERT INTO gitlab_runners_fts (gitlab_runners_fts, rowid, name, url, token, docker_image)
+    VALUES ('delete', old.rowid, old.name, old.url, old.token, old.docker_image);
+END;
+
+CREATE TRIGGER IF NOT EXISTS gitlab_runners_fts_update AFTER UPDATE ON gitlab_runners BEGIN
+    INSERT INTO gitlab_runners_fts (gitlab_runners_fts, rowid, name, url, token, docker_image)
+    VALUES ('delete', old.rowid, old.name, old.url, old.token, old.docker_image);
+    INSERT INTO gitlab_runners_fts (rowid, name, url, token, docker_image)
+    VALUES (new.rowid, new.name, new.url, new.token, new.docker_image);
+END;
@@ -0,0 +1,3 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+DROP TABLE IF EXISTS tasks;
@@ -0,0 +1,10 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+CREATE TABLE IF NOT EXISTS tasks (
+    key             TEXT    PRIMARY KEY,
+    kind            TEXT    NOT NULL,
+    attempts        INTEGER NOT NULL,
+    last_error      TEXT,
+    created_at      TEXT    NOT NULL,
+    next_attempt_at TEXT    NOT NULL
+) STRICT;
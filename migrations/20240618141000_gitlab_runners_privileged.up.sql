@@ -0,0 +1,3 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+ALTER TABLE gitlab_runners ADD COLUMN privileged INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+ALTER TABLE gitlab_runners DROP COLUMN notes;
+ALTER TABLE gitlab_runners DROP COLUMN labels;
@@ -0,0 +1,4 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+ALTER TABLE gitlab_runners ADD COLUMN labels TEXT NOT NULL DEFAULT '{}';
+ALTER TABLE gitlab_runners ADD COLUMN notes TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,7 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+DROP TABLE IF EXISTS expiry_notices;
+
+ALTER TABLE gitlab_runners DROP COLUMN paused;
+ALTER TABLE gitlab_runners DROP COLUMN expires_at;
+ALTER TABLE gitlab_runners DROP COLUMN owner_email;
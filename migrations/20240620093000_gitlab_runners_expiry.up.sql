@@ -0,0 +1,10 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+ALTER TABLE gitlab_runners ADD COLUMN owner_email TEXT;
+ALTER TABLE gitlab_runners ADD COLUMN expires_at TEXT;
+ALTER TABLE gitlab_runners ADD COLUMN paused INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE IF NOT EXISTS expiry_notices (
+    uuid       BLOB PRIMARY KEY,
+    expires_at TEXT NOT NULL
+) STRICT;
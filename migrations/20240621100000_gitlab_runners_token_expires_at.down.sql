@@ -0,0 +1,3 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+ALTER TABLE gitlab_runners DROP COLUMN token_expires_at;
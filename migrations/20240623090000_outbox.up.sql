@@ -0,0 +1,11 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+CREATE TABLE IF NOT EXISTS outbox (
+    id              INTEGER PRIMARY KEY AUTOINCREMENT,
+    dedup_key       TEXT    NOT NULL UNIQUE,
+    payload         TEXT    NOT NULL,
+    attempts        INTEGER NOT NULL,
+    last_error      TEXT,
+    created_at      TEXT    NOT NULL,
+    next_attempt_at TEXT    NOT NULL
+) STRICT;
@@ -0,0 +1,4 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+DROP INDEX IF EXISTS audit_log_at;
+DROP TABLE IF EXISTS audit_log;
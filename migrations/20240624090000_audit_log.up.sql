@@ -0,0 +1,12 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+CREATE TABLE IF NOT EXISTS audit_log (
+    id     INTEGER PRIMARY KEY AUTOINCREMENT,
+    at     TEXT    NOT NULL,
+    actor  TEXT,
+    method TEXT    NOT NULL,
+    path   TEXT    NOT NULL,
+    status INTEGER NOT NULL
+) STRICT;
+
+CREATE INDEX IF NOT EXISTS audit_log_at ON audit_log (at);
@@ -0,0 +1,3 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+-- Normalized URLs are valid URLs as well; there is nothing to revert.
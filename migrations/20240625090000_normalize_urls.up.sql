@@ -0,0 +1,47 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+-- Runner URLs are normalized when runners are stored. Parsing already lowercased scheme and host
+-- and dropped default ports, so only trailing slashes after a path, queries and fragments remain
+-- to be removed from existing runners. The root path keeps its slash, e.g. `https://gitlab.com/`.
+CREATE TEMP TABLE normalized_urls AS
+WITH stripped AS (
+    SELECT uuid, id, token, url,
+           substr(url, 1, min(instr(url || '?', '?'), instr(url || '#', '#')) - 1) AS base
+    FROM gitlab_runners
+)
+SELECT uuid, id, token, url,
+       CASE
+           WHEN base LIKE '%/'
+            AND instr(substr(base, instr(base, '://') + 3), '/')
+              < length(rtrim(substr(base, instr(base, '://') + 3), '/'))
+           THEN rtrim(base, '/')
+           ELSE base
+       END AS normalized
+FROM stripped;
+
+-- Runners with the same id and token whose URLs only differ in a trailing slash, query or fragment
+-- become duplicates once their URLs are normalized, violating UNIQUE(id, url, token). Which of them
+-- to keep can't be decided here, so the migration fails until all but one of them are deleted.
+CREATE TEMP TABLE normalized_url_collisions (uuid BLOB NOT NULL);
+CREATE TEMP TRIGGER normalized_url_collisions_abort
+BEFORE INSERT ON normalized_url_collisions
+BEGIN
+    SELECT RAISE(
+        ABORT,
+        'normalizing URLs makes runners with the same id and token duplicates; delete all but one of them before migrating'
+    );
+END;
+
+INSERT INTO normalized_url_collisions
+SELECT a.uuid
+FROM normalized_urls a
+JOIN normalized_urls b
+  ON a.id = b.id AND a.token = b.token AND a.normalized = b.normalized AND a.uuid != b.uuid;
+
+UPDATE gitlab_runners
+SET url = (SELECT normalized FROM normalized_urls WHERE normalized_urls.uuid = gitlab_runners.uuid)
+WHERE uuid IN (SELECT uuid FROM normalized_urls WHERE normalized != url);
+
+DROP TRIGGER normalized_url_collisions_abort;
+DROP TABLE normalized_url_collisions;
+DROP TABLE normalized_urls;
@@ -0,0 +1,6 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+-- SQLite only allows constant defaults for added columns, so existing runners are stamped with the
+-- time of the migration afterwards
+ALTER TABLE gitlab_runners ADD COLUMN updated_at TEXT NOT NULL DEFAULT '1970-01-01T00:00:00Z';
+UPDATE gitlab_runners SET updated_at = strftime('%Y-%m-%dT%H:%M:%SZ', 'now');
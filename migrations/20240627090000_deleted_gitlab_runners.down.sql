@@ -0,0 +1,4 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+DROP INDEX IF EXISTS deleted_gitlab_runners_deleted_at;
+DROP TABLE IF EXISTS deleted_gitlab_runners;
@@ -0,0 +1,12 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+-- deleted runners are kept as JSON, so the recycle bin needn't follow changes to gitlab_runners
+CREATE TABLE IF NOT EXISTS deleted_gitlab_runners (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid       BLOB    NOT NULL,
+    runner     TEXT    NOT NULL,
+    deleted_at TEXT    NOT NULL,
+    deleted_by TEXT
+) STRICT;
+
+CREATE INDEX IF NOT EXISTS deleted_gitlab_runners_deleted_at ON deleted_gitlab_runners (deleted_at);
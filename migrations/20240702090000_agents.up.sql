@@ -0,0 +1,10 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+-- tokens are only stored as HMAC, so a copy of the database doesn't let anyone connect as agent
+CREATE TABLE IF NOT EXISTS agents (
+    uuid          BLOB PRIMARY KEY,
+    name          TEXT NOT NULL UNIQUE,
+    token_hash    TEXT NOT NULL UNIQUE,
+    registered_at TEXT NOT NULL,
+    last_seen_at  TEXT
+) STRICT;
@@ -0,0 +1,4 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+ALTER TABLE gitlab_runners DROP COLUMN next_transition_at;
+ALTER TABLE gitlab_runners DROP COLUMN schedule;
@@ -0,0 +1,4 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+ALTER TABLE gitlab_runners ADD COLUMN schedule TEXT;
+ALTER TABLE gitlab_runners ADD COLUMN next_transition_at TEXT;
@@ -0,0 +1,7 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+DROP INDEX IF EXISTS config_revisions_at;
+DROP TABLE IF EXISTS config_revisions;
+DROP INDEX IF EXISTS gitlab_runner_revisions_at;
+DROP INDEX IF EXISTS gitlab_runner_revisions_uuid;
+DROP TABLE IF EXISTS gitlab_runner_revisions;
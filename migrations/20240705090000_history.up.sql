@@ -0,0 +1,21 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+-- every state of a runner is kept as JSON like in the recycle bin, NULL once it was deleted
+CREATE TABLE IF NOT EXISTS gitlab_runner_revisions (
+    id     INTEGER PRIMARY KEY AUTOINCREMENT,
+    uuid   BLOB    NOT NULL,
+    runner TEXT,
+    at     TEXT    NOT NULL
+) STRICT;
+
+CREATE INDEX IF NOT EXISTS gitlab_runner_revisions_uuid ON gitlab_runner_revisions (uuid);
+CREATE INDEX IF NOT EXISTS gitlab_runner_revisions_at ON gitlab_runner_revisions (at);
+
+-- every config written to disk, as written
+CREATE TABLE IF NOT EXISTS config_revisions (
+    id     INTEGER PRIMARY KEY AUTOINCREMENT,
+    config TEXT    NOT NULL,
+    at     TEXT    NOT NULL
+) STRICT;
+
+CREATE INDEX IF NOT EXISTS config_revisions_at ON config_revisions (at);
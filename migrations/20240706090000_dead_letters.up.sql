@@ -0,0 +1,13 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+-- work which exhausted its retries, kept along with why it failed until it is replayed
+CREATE TABLE IF NOT EXISTS dead_letters (
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    kind       TEXT    NOT NULL,
+    dedup_key  TEXT    NOT NULL UNIQUE,
+    payload    TEXT    NOT NULL,
+    attempts   INTEGER NOT NULL,
+    last_error TEXT    NOT NULL,
+    created_at TEXT    NOT NULL,
+    failed_at  TEXT    NOT NULL
+) STRICT;
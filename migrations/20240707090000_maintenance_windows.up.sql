@@ -0,0 +1,8 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+-- GitLab instances in maintenance, by normalized URL; integration tasks skip them until ends_at
+CREATE TABLE IF NOT EXISTS maintenance_windows (
+    url     TEXT PRIMARY KEY NOT NULL,
+    ends_at TEXT NOT NULL,
+    reason  TEXT
+) STRICT;
@@ -0,0 +1,8 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+-- Keys of the global section of the config set via the API, as JSON; there is at most one row
+CREATE TABLE IF NOT EXISTS global_config (
+    id         INTEGER PRIMARY KEY NOT NULL CHECK (id = 1),
+    config     TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+) STRICT;
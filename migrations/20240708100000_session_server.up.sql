@@ -0,0 +1,8 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+-- Session server settings set via the API, as JSON; there is at most one row
+CREATE TABLE IF NOT EXISTS session_server (
+    id         INTEGER PRIMARY KEY NOT NULL CHECK (id = 1),
+    config     TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+) STRICT;
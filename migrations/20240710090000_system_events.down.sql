@@ -0,0 +1,8 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+INSERT INTO audit_log (at, actor, method, path, status)
+SELECT at, actor, upper(kind), subject, CASE WHEN error IS NULL THEN 200 ELSE 500 END
+FROM system_events
+ORDER BY id;
+
+DROP TABLE IF EXISTS system_events;
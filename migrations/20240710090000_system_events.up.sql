@@ -0,0 +1,22 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+CREATE TABLE IF NOT EXISTS system_events (
+    id      INTEGER PRIMARY KEY AUTOINCREMENT,
+    at      TEXT    NOT NULL,
+    kind    TEXT    NOT NULL,
+    actor   TEXT    NOT NULL,
+    subject TEXT    NOT NULL,
+    error   TEXT
+) STRICT;
+
+CREATE INDEX IF NOT EXISTS system_events_at ON system_events (at);
+
+-- things runrs did on its own used to be recorded in the audit log, which is meant for requests
+INSERT INTO system_events (at, kind, actor, subject, error)
+SELECT at, lower(method), coalesce(actor, 'runrs'), path,
+       CASE WHEN status >= 400 THEN 'failed with status ' || status END
+FROM audit_log
+WHERE method IN ('SHUTDOWN', 'PURGE', 'MIGRATE', 'RECONCILE')
+ORDER BY id;
+
+DELETE FROM audit_log WHERE method IN ('SHUTDOWN', 'PURGE', 'MIGRATE', 'RECONCILE');
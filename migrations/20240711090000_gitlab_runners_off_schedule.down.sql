@@ -0,0 +1,4 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+UPDATE gitlab_runners SET paused = 1 WHERE off_schedule;
+ALTER TABLE gitlab_runners DROP COLUMN off_schedule;
@@ -0,0 +1,9 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+ALTER TABLE gitlab_runners ADD COLUMN off_schedule BOOLEAN NOT NULL DEFAULT 0;
+
+-- schedules used to pause runners by setting `paused`, which they took precedence over, so for
+-- runners with a schedule `paused` was the schedule's doing, unless the runner expired
+UPDATE gitlab_runners SET off_schedule = paused, paused = 0
+WHERE schedule IS NOT NULL
+AND (expires_at IS NULL OR datetime(expires_at) > datetime('now'));
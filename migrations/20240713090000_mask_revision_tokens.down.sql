@@ -0,0 +1,3 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+-- Masked tokens can't be unmasked; there is nothing to revert.
@@ -0,0 +1,18 @@
+-- Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+-- The runner history is kept longer than the runners themselves, so it keeps their tokens masked
+-- like the recycle bin does: the prefix and the last three characters. Secret references are no
+-- secrets themselves and are kept as they are.
+UPDATE gitlab_runner_revisions
+SET runner = json_set(
+    runner,
+    '$.token',
+    CASE
+        WHEN json_extract(runner, '$.token') LIKE 'glrtr-%'
+        THEN 'glrtr-****' || substr(json_extract(runner, '$.token'), -3)
+        WHEN json_extract(runner, '$.token') LIKE 'glrt-%'
+        THEN 'glrt-****' || substr(json_extract(runner, '$.token'), -3)
+        ELSE json_extract(runner, '$.token')
+    END
+)
+WHERE runner IS NOT NULL;
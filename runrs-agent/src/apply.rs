@@ -0,0 +1,106 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{io::Write as _, path::Path};
+
+use miette::IntoDiagnostic;
+
+use crate::settings::ReloadStrategy;
+
+/// Writes the config and makes `gitlab-runner` pick it up.
+pub async fn apply(
+    config_path: &Path,
+    reload: &ReloadStrategy,
+    config: &str,
+) -> miette::Result<()> {
+    write_atomically(config_path, config)?;
+    tracing::info!(?config_path, "config written");
+
+    match reload {
+        ReloadStrategy::Watch => Ok(()),
+        ReloadStrategy::Command(command) => {
+            tracing::debug!(command, "running reload command");
+            let status = tokio::process::Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .status()
+                .await
+                .into_diagnostic()?;
+            if !status.success() {
+                miette::bail!("reload command '{command}' failed with {status}");
+            }
+
+            Ok(())
+        }
+    }
+}
+
+/// Writes the config with [`glrcfg::write_atomically`], like runrs itself does: `gitlab-runner`
+/// never reads a partially written config, and the file stays private to its owner.
+fn write_atomically(path: &Path, config: &str) -> miette::Result<()> {
+    glrcfg::write_atomically(path, |file| file.write_all(config.as_bytes())).into_diagnostic()
+}
+
+#[cfg(test)]
+mod tests {
+    use miette::IntoDiagnostic;
+    use pretty_assertions::assert_eq;
+
+    use super::apply;
+    use crate::settings::ReloadStrategy;
+
+    #[tokio::test]
+    async fn write_and_reload() -> miette::Result<()> {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("runrs-agent-config-{}.toml", std::process::id()));
+        let marker = dir.join(format!("runrs-agent-reloaded-{}", std::process::id()));
+
+        let reload = ReloadStrategy::Command(format!("touch {}", marker.display()));
+        apply(&path, &reload, "concurrent = 4\n").await?;
+        assert_eq!(
+            std::fs::read_to_string(&path).into_diagnostic()?,
+            "concurrent = 4\n"
+        );
+        assert!(marker.exists());
+        // no temporary file is left behind, and the config is only readable by its owner
+        let tmp_prefix = format!(".runrs-agent-config-{}.toml.", std::process::id());
+        assert_eq!(
+            std::fs::read_dir(&dir)
+                .into_diagnostic()?
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.file_name().to_string_lossy().starts_with(&tmp_prefix))
+                .count(),
+            0
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt as _;
+            assert_eq!(
+                std::fs::metadata(&path)
+                    .into_diagnostic()?
+                    .permissions()
+                    .mode()
+                    & 0o777,
+                0o600
+            );
+        }
+
+        let failing = ReloadStrategy::Command("exit 3".to_string());
+        assert!(apply(&path, &failing, "concurrent = 8\n").await.is_err());
+        // the config is written even if reloading fails, gitlab-runner picks it up on restart
+        assert_eq!(
+            std::fs::read_to_string(&path).into_diagnostic()?,
+            "concurrent = 8\n"
+        );
+
+        apply(&path, &ReloadStrategy::Watch, "concurrent = 2\n").await?;
+        assert_eq!(
+            std::fs::read_to_string(&path).into_diagnostic()?,
+            "concurrent = 2\n"
+        );
+
+        std::fs::remove_file(&path).into_diagnostic()?;
+        std::fs::remove_file(&marker).into_diagnostic()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,98 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+mod apply;
+mod protocol;
+mod session;
+mod settings;
+
+use std::time::{Duration, Instant};
+
+/// Initial delay before reconnecting; it doubles with every failed attempt.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+/// Upper bound of the delay before reconnecting.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+#[tokio::main]
+async fn main() -> miette::Result<()> {
+    logging::init()?;
+
+    let settings = settings::Settings::from_env()?;
+    tracing::info!(url = %settings.url, config_path = ?settings.config_path, "starting agent");
+
+    tokio::select! {
+        result = reconnect(&settings) => result,
+        _ = signals::handle_sigint_sigterm() => {
+            tracing::info!("shutting down");
+            Ok(())
+        }
+    }
+}
+
+/// Keeps the control channel open, reconnecting with exponential backoff.
+async fn reconnect(settings: &settings::Settings) -> miette::Result<()> {
+    let mut backoff = MIN_BACKOFF;
+
+    loop {
+        let started = Instant::now();
+        match session::run(settings).await {
+            Ok(session::Outcome::Rejected) => {
+                miette::bail!("runrs rejected the agent token; was the agent deleted?")
+            }
+            Ok(session::Outcome::Closed) => tracing::warn!("control channel closed"),
+            Err(err) => tracing::error!(%err, "control channel failed"),
+        }
+
+        // a session that lasted a while was healthy, so start over with a short delay
+        if started.elapsed() > MAX_BACKOFF {
+            backoff = MIN_BACKOFF;
+        }
+        tracing::info!(?backoff, "reconnecting");
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+mod logging {
+    use miette::IntoDiagnostic;
+    use tracing_subscriber::{util::SubscriberInitExt, EnvFilter};
+
+    /// Initializes tracing, defaulting to ERROR level except for the agent itself.
+    pub fn init() -> miette::Result<()> {
+        let filter = EnvFilter::try_from_default_env()
+            .unwrap_or(EnvFilter::try_new("error,runrs_agent=warn").into_diagnostic()?);
+
+        let subscriber = tracing_subscriber::fmt().with_env_filter(filter);
+
+        match std::env::var("LOG_FMT") {
+            Ok(fmt) if fmt == "json" => subscriber.json().finish().init(),
+            _ => subscriber.finish().init(),
+        }
+
+        Ok(())
+    }
+}
+
+mod signals {
+    use tokio::signal;
+
+    pub async fn handle_sigint_sigterm() {
+        let sigint = async {
+            signal::unix::signal(signal::unix::SignalKind::interrupt())
+                .expect("installing SIGINT (Ctrl+C) handler should never fail")
+                .recv()
+                .await;
+        };
+
+        let sigterm = async {
+            signal::unix::signal(signal::unix::SignalKind::terminate())
+                .expect("installing SIGTERM handler should never fail")
+                .recv()
+                .await;
+        };
+
+        tokio::select! {
+            _ = sigint => {}
+            _ = sigterm => {}
+        }
+    }
+}
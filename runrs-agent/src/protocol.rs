@@ -0,0 +1,63 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+//! Messages of the control channel, sent as JSON text frames; they mirror the ones in runrs.
+
+use serde::{Deserialize, Serialize};
+
+/// Messages runrs sends to agents.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// The config changed; it's written for `gitlab-runner` and the version acknowledged.
+    ConfigUpdated { version: u64, config: String },
+}
+
+/// Messages agents send to runrs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AgentMessage {
+    /// The config of the given version was applied on the host.
+    Ack { version: u64 },
+    /// Health of `gitlab-runner` on the host.
+    Health {
+        healthy: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::{AgentMessage, ServerMessage};
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[test]
+    fn wire_format() -> Result<()> {
+        assert_eq!(
+            serde_json::from_str::<ServerMessage>(
+                r#"{"type":"config_updated","version":3,"config":"concurrent = 4\n"}"#
+            )?,
+            ServerMessage::ConfigUpdated {
+                version: 3,
+                config: "concurrent = 4\n".to_string()
+            }
+        );
+
+        assert_eq!(
+            serde_json::to_string(&AgentMessage::Ack { version: 3 })?,
+            r#"{"type":"ack","version":3}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&AgentMessage::Health {
+                healthy: true,
+                message: None
+            })?,
+            r#"{"type":"health","healthy":true}"#
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use miette::IntoDiagnostic;
+use tokio_tungstenite::tungstenite::{
+    client::IntoClientRequest,
+    http::{header, HeaderValue, StatusCode},
+    Error as WsError, Message,
+};
+
+use crate::{
+    apply::apply,
+    protocol::{AgentMessage, ServerMessage},
+    settings::Settings,
+};
+
+/// Interval at which the health of `gitlab-runner` is reported.
+const HEALTH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How a session with runrs ended.
+#[derive(Debug)]
+pub enum Outcome {
+    /// runrs closed the control channel or the connection dropped; reconnecting is worth it.
+    Closed,
+    /// runrs rejected the token, e.g. because the agent was deleted; reconnecting is futile.
+    Rejected,
+}
+
+/// Connects to the control channel and applies pushed configs until the connection ends.
+pub async fn run(settings: &Settings) -> miette::Result<Outcome> {
+    let mut request = settings
+        .url
+        .as_str()
+        .into_client_request()
+        .into_diagnostic()?;
+    request.headers_mut().insert(
+        header::AUTHORIZATION,
+        HeaderValue::from_str(&format!("Bearer {}", settings.token)).into_diagnostic()?,
+    );
+
+    let socket = match tokio_tungstenite::connect_async(request).await {
+        Ok((socket, _)) => socket,
+        Err(WsError::Http(response)) if response.status() == StatusCode::FORBIDDEN => {
+            return Ok(Outcome::Rejected);
+        }
+        Err(err) => return Err(err).into_diagnostic(),
+    };
+    tracing::info!(url = %settings.url, "connected to control channel");
+
+    let (mut outgoing, mut incoming) = socket.split();
+    let http = reqwest::Client::new();
+    let mut health = tokio::time::interval(HEALTH_INTERVAL);
+
+    loop {
+        let reply = tokio::select! {
+            message = incoming.next() => match message {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                    Ok(ServerMessage::ConfigUpdated { version, config }) => {
+                        tracing::debug!(version, "received config");
+                        match apply(&settings.config_path, &settings.reload, &config).await {
+                            Ok(()) => AgentMessage::Ack { version },
+                            Err(err) => {
+                                tracing::error!(version, %err, "failed to apply config");
+                                AgentMessage::Health {
+                                    healthy: false,
+                                    message: Some(format!("applying config failed: {err}")),
+                                }
+                            }
+                        }
+                    }
+                    Err(err) => {
+                        tracing::warn!(%err, "ignoring unknown message");
+                        continue;
+                    }
+                },
+                Some(Ok(Message::Close(_))) | None => return Ok(Outcome::Closed),
+                Some(Ok(_)) => continue,
+                Some(Err(err)) => return Err(err).into_diagnostic(),
+            },
+            _ = health.tick(), if settings.metrics_url.is_some() => {
+                check_health(&http, settings).await
+            }
+        };
+
+        let text = serde_json::to_string(&reply).into_diagnostic()?;
+        outgoing.send(Message::Text(text)).await.into_diagnostic()?;
+    }
+}
+
+/// Checks whether the metrics endpoint of `gitlab-runner` responds successfully.
+async fn check_health(http: &reqwest::Client, settings: &Settings) -> AgentMessage {
+    let Some(metrics_url) = &settings.metrics_url else {
+        return AgentMessage::Health {
+            healthy: true,
+            message: None,
+        };
+    };
+
+    match http.get(metrics_url.clone()).send().await {
+        Ok(response) if response.status().is_success() => AgentMessage::Health {
+            healthy: true,
+            message: None,
+        },
+        Ok(response) => AgentMessage::Health {
+            healthy: false,
+            message: Some(format!(
+                "metrics endpoint responded with {}",
+                response.status()
+            )),
+        },
+        Err(err) => AgentMessage::Health {
+            healthy: false,
+            message: Some(format!("metrics endpoint unreachable: {err}")),
+        },
+    }
+}
@@ -0,0 +1,138 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{fmt::Display, path::PathBuf, str::FromStr};
+
+use miette::IntoDiagnostic;
+use url::Url;
+
+pub static DEFAULT_CONFIG_PATH: &str = "/etc/gitlab-runner/config.toml";
+
+/// How `gitlab-runner` picks up a newly written config.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReloadStrategy {
+    /// `gitlab-runner` watches its config file and reloads it by itself.
+    Watch,
+    /// The command is run with `sh -c`, e.g. `systemctl reload gitlab-runner` or
+    /// `docker kill --signal HUP gitlab-runner`.
+    Command(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// URL of the control channel, derived from `RUNRS_URL`
+    pub url: Url,
+    pub token: String,
+    pub config_path: PathBuf,
+    pub reload: ReloadStrategy,
+    /// Metrics endpoint of `gitlab-runner`; health is only reported if it's set
+    pub metrics_url: Option<Url>,
+}
+
+impl Settings {
+    pub fn from_env() -> miette::Result<Self> {
+        let Some(runrs_url) = env_opt::<Url>("RUNRS_URL")? else {
+            miette::bail!("RUNRS_URL not set in environment");
+        };
+        let Some(token) = env_opt::<String>("AGENT_TOKEN")? else {
+            miette::bail!("AGENT_TOKEN not set in environment");
+        };
+
+        let reload = match env_or("RELOAD_STRATEGY", "watch".to_string())?.as_str() {
+            "watch" => ReloadStrategy::Watch,
+            "command" => match env_opt("RELOAD_COMMAND")? {
+                Some(command) => ReloadStrategy::Command(command),
+                None => miette::bail!("RELOAD_STRATEGY=command requires RELOAD_COMMAND"),
+            },
+            strategy => miette::bail!(
+                "invalid value for RELOAD_STRATEGY: '{strategy}'; must be one of watch, command"
+            ),
+        };
+
+        Ok(Self {
+            url: control_channel_url(&runrs_url)?,
+            token,
+            config_path: env_or("CONFIG_PATH", PathBuf::from(DEFAULT_CONFIG_PATH))?,
+            reload,
+            metrics_url: env_opt("RUNNER_METRICS_URL")?,
+        })
+    }
+}
+
+/// Turns the URL runrs is reachable at into the URL of its control channel for agents.
+fn control_channel_url(runrs_url: &Url) -> miette::Result<Url> {
+    let scheme = match runrs_url.scheme() {
+        "http" | "ws" => "ws",
+        "https" | "wss" => "wss",
+        scheme => miette::bail!("invalid scheme '{scheme}' in RUNRS_URL; must be http or https"),
+    };
+
+    let mut url = runrs_url.clone();
+    url.set_scheme(scheme)
+        .map_err(|()| miette::miette!("cannot use RUNRS_URL '{runrs_url}'"))?;
+    url.path_segments_mut()
+        .map_err(|()| miette::miette!("cannot use RUNRS_URL '{runrs_url}'"))?
+        .pop_if_empty()
+        .extend(["agents", "connect"]);
+
+    Ok(url)
+}
+
+/// Parses the environment variable `key`, or returns `default` if it isn't set.
+fn env_or<T>(key: &str, default: T) -> miette::Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    Ok(env_opt(key)?.unwrap_or(default))
+}
+
+/// Parses the environment variable `key`, or returns `None` if it isn't set.
+fn env_opt<T>(key: &str) -> miette::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match std::env::var(key) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|err| miette::miette!("invalid value for {key}: {err}")),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(err) => Err(err).into_diagnostic(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use miette::IntoDiagnostic;
+    use pretty_assertions::assert_eq;
+    use url::Url;
+
+    use super::control_channel_url;
+
+    #[test]
+    fn derive_control_channel_url() -> miette::Result<()> {
+        for (runrs_url, expected) in [
+            (
+                "http://localhost:3000",
+                "ws://localhost:3000/agents/connect",
+            ),
+            (
+                "https://runrs.your-company.com/",
+                "wss://runrs.your-company.com/agents/connect",
+            ),
+            (
+                "https://your-company.com/runrs",
+                "wss://your-company.com/runrs/agents/connect",
+            ),
+        ] {
+            let runrs_url = Url::parse(runrs_url).into_diagnostic()?;
+            assert_eq!(control_channel_url(&runrs_url)?.as_str(), expected);
+        }
+
+        let ftp = Url::parse("ftp://runrs.your-company.com").into_diagnostic()?;
+        assert!(control_channel_url(&ftp).is_err());
+
+        Ok(())
+    }
+}
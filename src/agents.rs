@@ -0,0 +1,509 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Display,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use axum::extract::ws::Message;
+use chrono::{DateTime, Utc};
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    error::Error,
+    models::{Agent, GitLabRunner, GitLabRunnerFilter, LabelSelector},
+    rollout::{ConfigRollout, Release},
+};
+
+/// How often sessions check whether the config or the runners assigned to the agent changed, and
+/// whether the agent was revoked.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Label assigning runners to the agent of the host they run on; its value is the agent's name.
+pub const AGENT_LABEL: &str = "agent";
+
+/// Runners assigned to an agent, by the URL of their GitLab instance and their ID, which is how
+/// they are told apart in the rendered config.
+type Assigned = BTreeSet<(String, i64)>;
+
+async fn assigned_runners(pool: &atmosphere::Pool, agent: &str) -> Result<Assigned, Error> {
+    let filter = GitLabRunnerFilter {
+        label: Some(LabelSelector {
+            key: AGENT_LABEL.to_string(),
+            value: Some(agent.to_string()),
+        }),
+        ..Default::default()
+    };
+
+    Ok(GitLabRunner::list(pool, &filter)
+        .await?
+        .iter()
+        .map(|runner| (runner.url().as_str().to_string(), i64::from(runner.id())))
+        .collect())
+}
+
+/// Leaves only the assigned runners in the rendered `config`, so that agents don't learn the
+/// tokens of runners on other hosts.
+fn scope_config(config: &str, assigned: &Assigned) -> Result<String, Error> {
+    let mut config: toml::Table = config.parse().map_err(Error::internal_error)?;
+    if let Some(toml::Value::Array(runners)) = config.get_mut("runners") {
+        runners.retain(|runner| {
+            let url = runner.get("url").and_then(toml::Value::as_str);
+            let id = runner.get("id").and_then(toml::Value::as_integer);
+            url.zip(id)
+                .is_some_and(|(url, id)| assigned.contains(&(url.to_string(), id)))
+        });
+    }
+
+    toml::to_string(&config).map_err(Error::internal_error)
+}
+
+/// Messages runrs sends to agents, as JSON text frames.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// The config changed; the agent writes it for `gitlab-runner` and acknowledges the version.
+    /// Sent right after connecting as well.
+    ConfigUpdated { version: u64, config: String },
+}
+
+/// Messages agents send to runrs, as JSON text frames.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AgentMessage {
+    /// The config of the given version was applied on the host.
+    Ack { version: u64 },
+    /// Health of `gitlab-runner` on the host.
+    Health {
+        healthy: bool,
+        #[serde(default)]
+        message: Option<String>,
+    },
+}
+
+/// Health of `gitlab-runner` on a host, as reported by its agent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct RunnerHealth {
+    healthy: bool,
+    /// Details the agent reported, e.g. why `gitlab-runner` is unhealthy
+    message: Option<String>,
+    #[schema(value_type = String, format = DateTime, example = "2024-07-02T09:30:00Z")]
+    reported_at: DateTime<Utc>,
+}
+
+/// State of the control channel of a connected agent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct AgentConnection {
+    #[schema(value_type = String, format = DateTime, example = "2024-07-02T09:00:00Z")]
+    connected_at: DateTime<Utc>,
+    /// Version of the config last pushed to the agent
+    pushed_version: Option<u64>,
+    /// Version of the config the agent last acknowledged applying
+    applied_version: Option<u64>,
+    /// Health of `gitlab-runner` on the host, as last reported by the agent
+    runner_health: Option<RunnerHealth>,
+}
+
+impl AgentConnection {
+    pub fn applied_version(&self) -> Option<u64> {
+        self.applied_version
+    }
+
+    /// Returns what the agent reported if it reported `gitlab-runner` unhealthy since `since`.
+    pub fn failure_since(&self, since: DateTime<Utc>) -> Option<String> {
+        self.runner_health
+            .as_ref()
+            .filter(|health| !health.healthy && health.reported_at >= since)
+            .map(|health| {
+                health
+                    .message
+                    .clone()
+                    .unwrap_or_else(|| "gitlab-runner is unhealthy".to_string())
+            })
+    }
+}
+
+/// An agent along with the state of its control channel.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct AgentStatus {
+    #[serde(flatten)]
+    agent: Agent,
+    /// Whether the agent is connected
+    connected: bool,
+    /// Whether the agent applied the latest config pushed to it
+    in_sync: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connection: Option<AgentConnection>,
+}
+
+/// Keeps track of the control channels of connected agents. If an agent connects again, e.g.
+/// after a network hiccup, the new session supersedes the old one, which then closes.
+#[derive(Debug, Default)]
+pub struct AgentRegistry {
+    connections: Mutex<HashMap<Uuid, (Uuid, AgentConnection)>>,
+}
+
+impl AgentRegistry {
+    /// Registers a new session of the agent and returns its ID.
+    fn connect(&self, agent: &Uuid) -> Uuid {
+        let session = Uuid::new_v4();
+        let connection = AgentConnection {
+            connected_at: Utc::now(),
+            pushed_version: None,
+            applied_version: None,
+            runner_health: None,
+        };
+
+        self.lock().insert(*agent, (session, connection));
+        session
+    }
+
+    /// Updates the connection of the session; returns `false` if the session was superseded or
+    /// the agent revoked, in which case the session must close.
+    fn update(
+        &self,
+        agent: &Uuid,
+        session: &Uuid,
+        update: impl FnOnce(&mut AgentConnection),
+    ) -> bool {
+        match self.lock().get_mut(agent) {
+            Some((current, connection)) if current == session => {
+                update(connection);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Forgets the session, unless it was superseded already.
+    fn disconnect(&self, agent: &Uuid, session: &Uuid) {
+        let mut connections = self.lock();
+        if connections
+            .get(agent)
+            .is_some_and(|(current, _)| current == session)
+        {
+            connections.remove(agent);
+        }
+    }
+
+    /// Closes the control channel of a revoked agent, if it's connected.
+    pub fn revoke(&self, agent: &Uuid) {
+        self.lock().remove(agent);
+    }
+
+    /// Returns the state of the control channel of the agent, if it's connected.
+    pub fn connection(&self, agent: &Uuid) -> Option<AgentConnection> {
+        self.lock()
+            .get(agent)
+            .map(|(_, connection)| connection.clone())
+    }
+
+    /// Returns the status of the agent.
+    pub fn status(&self, agent: Agent) -> AgentStatus {
+        let connection = self.connection(agent.uuid());
+        let in_sync = connection.as_ref().is_some_and(|connection| {
+            connection.pushed_version.is_some()
+                && connection.applied_version == connection.pushed_version
+        });
+
+        AgentStatus {
+            agent,
+            connected: connection.is_some(),
+            in_sync,
+            connection,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Uuid, (Uuid, AgentConnection)>> {
+        self.connections
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// The control channel of a connected agent: pushes the config whenever it changes and records
+/// what the agent reports.
+#[derive(Debug)]
+pub struct Session {
+    pool: atmosphere::Pool,
+    rollout: Arc<ConfigRollout>,
+    registry: Arc<AgentRegistry>,
+    agent: Agent,
+}
+
+impl Session {
+    pub fn new(
+        pool: atmosphere::Pool,
+        rollout: Arc<ConfigRollout>,
+        registry: Arc<AgentRegistry>,
+        agent: Agent,
+    ) -> Self {
+        Self {
+            pool,
+            rollout,
+            registry,
+            agent,
+        }
+    }
+
+    /// Runs the session until the agent disconnects, connects again or is revoked.
+    pub async fn run<W, R>(self, mut outgoing: W, mut incoming: R) -> Result<(), Error>
+    where
+        W: Sink<Message> + Unpin,
+        W::Error: Display,
+        R: Stream<Item = Result<Message, axum::Error>> + Unpin,
+    {
+        let uuid = *self.agent.uuid();
+        Agent::touch(&self.pool, &uuid).await?;
+        let session = self.registry.connect(&uuid);
+        tracing::info!(agent = self.agent.name(), "agent connected");
+
+        let result = self.serve(&session, &mut outgoing, &mut incoming).await;
+
+        self.registry.disconnect(&uuid, &session);
+        tracing::info!(agent = self.agent.name(), "agent disconnected");
+        Agent::touch(&self.pool, &uuid).await?;
+
+        // the agent might be gone already, so failing to close the socket is fine
+        let _ = outgoing.close().await;
+        result
+    }
+
+    async fn serve<W, R>(
+        &self,
+        session: &Uuid,
+        outgoing: &mut W,
+        incoming: &mut R,
+    ) -> Result<(), Error>
+    where
+        W: Sink<Message> + Unpin,
+        W::Error: Display,
+        R: Stream<Item = Result<Message, axum::Error>> + Unpin,
+    {
+        let uuid = self.agent.uuid();
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        let mut pushed: Option<(String, Assigned)> = None;
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    // canaries of a rollout in progress receive the changed config first
+                    let release = self.rollout.release_for(uuid);
+                    let Some(Release { version, config }) = release else {
+                        if !self.registry.update(uuid, session, |_| ()) {
+                            return Ok(());
+                        }
+                        continue;
+                    };
+                    let assigned = assigned_runners(&self.pool, self.agent.name()).await?;
+                    let unchanged = pushed.as_ref().is_some_and(|(pushed, pushed_assigned)| {
+                        *pushed == config && *pushed_assigned == assigned
+                    });
+                    if unchanged {
+                        if !self.registry.update(uuid, session, |_| ()) {
+                            return Ok(());
+                        }
+                        continue;
+                    }
+
+                    if !self.registry.update(uuid, session, |connection| {
+                        connection.pushed_version = Some(version);
+                    }) {
+                        return Ok(());
+                    }
+
+                    tracing::debug!(
+                        agent = self.agent.name(),
+                        version,
+                        "pushing config to agent"
+                    );
+                    let message = ServerMessage::ConfigUpdated {
+                        version,
+                        config: scope_config(&config, &assigned)?,
+                    };
+                    let text = serde_json::to_string(&message).map_err(Error::internal_error)?;
+                    outgoing
+                        .send(Message::Text(text))
+                        .await
+                        .map_err(Error::connection_failed)?;
+                    pushed = Some((config, assigned));
+                }
+                message = incoming.next() => {
+                    let text = match message {
+                        Some(Ok(Message::Text(text))) => text,
+                        Some(Ok(Message::Close(_))) | None => return Ok(()),
+                        // pings are answered by the WebSocket implementation
+                        Some(Ok(_)) => continue,
+                        Some(Err(err)) => return Err(Error::connection_failed(err)),
+                    };
+
+                    let message = match serde_json::from_str::<AgentMessage>(&text) {
+                        Ok(message) => message,
+                        Err(err) => {
+                            tracing::warn!(
+                                agent = self.agent.name(),
+                                %err,
+                                "ignoring invalid agent message"
+                            );
+                            continue;
+                        }
+                    };
+                    if !self.handle(session, message) {
+                        return Ok(());
+                    }
+                    Agent::touch(&self.pool, uuid).await?;
+                }
+            }
+        }
+    }
+
+    /// Records a message of the agent; returns `false` if the session must close.
+    fn handle(&self, session: &Uuid, message: AgentMessage) -> bool {
+        let agent = self.agent.name();
+
+        self.registry
+            .update(self.agent.uuid(), session, |connection| match message {
+                AgentMessage::Ack { version } => {
+                    tracing::debug!(agent, version, "agent applied config");
+                    connection.applied_version = Some(version);
+                }
+                AgentMessage::Health { healthy, message } => {
+                    if !healthy {
+                        tracing::warn!(agent, ?message, "agent reports gitlab-runner unhealthy");
+                    }
+                    connection.runner_health = Some(RunnerHealth {
+                        healthy,
+                        message,
+                        reported_at: Utc::now(),
+                    });
+                }
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use atmosphere::Create as _;
+    use axum::extract::ws::Message;
+    use futures::{channel::mpsc, StreamExt};
+    use pretty_assertions::assert_eq;
+
+    use super::{AgentMessage, AgentRegistry, ServerMessage, Session, AGENT_LABEL};
+    use crate::{
+        models::{Agent, AgentRegistration, ConfigCache, GitLabRunner, Labels},
+        rollout::ConfigRollout,
+        settings::SettingsStore,
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    async fn next_push(outgoing: &mut mpsc::UnboundedReceiver<Message>) -> Result<(u64, String)> {
+        let message = tokio::time::timeout(Duration::from_secs(5), outgoing.next())
+            .await?
+            .ok_or("session closed")?;
+        let Message::Text(text) = message else {
+            return Err("expected a text message".into());
+        };
+        let ServerMessage::ConfigUpdated { version, config } = serde_json::from_str(&text)?;
+
+        Ok((version, config))
+    }
+
+    async fn send(
+        incoming: &mpsc::UnboundedSender<std::result::Result<Message, axum::Error>>,
+        message: &AgentMessage,
+    ) -> Result<()> {
+        incoming.unbounded_send(Ok(Message::Text(serde_json::to_string(message)?)))?;
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    #[tracing_test::traced_test]
+    async fn push_config_and_record_reports(pool: atmosphere::Pool) -> Result<()> {
+        let config_cache = Arc::<ConfigCache>::default();
+        let settings = SettingsStore::default();
+        let registry = Arc::<AgentRegistry>::default();
+        let rollout = Arc::<ConfigRollout>::default();
+        let agent =
+            Agent::register(&pool, &AgentRegistration::for_testing("ci-host-01"), "hash").await?;
+        rollout
+            .reconcile(&pool, &config_cache, &settings, &registry)
+            .await?;
+
+        let (outgoing, mut pushes) = mpsc::unbounded();
+        let (reports, incoming) = mpsc::unbounded();
+        let session = Session::new(
+            pool.clone(),
+            rollout.clone(),
+            registry.clone(),
+            agent.clone(),
+        );
+        let running = tokio::spawn(session.run(outgoing, incoming));
+
+        // the config is pushed right after connecting
+        let (version, config) = next_push(&mut pushes).await?;
+        assert_eq!(version, 0);
+        assert!(!config.contains("[[runners]]"));
+        assert!(registry.status(agent.clone()).connected);
+        assert!(!registry.status(agent.clone()).in_sync);
+
+        send(&reports, &AgentMessage::Ack { version }).await?;
+        send(
+            &reports,
+            &AgentMessage::Health {
+                healthy: false,
+                message: Some("no runners".to_string()),
+            },
+        )
+        .await?;
+
+        // the session handles the reports in the background
+        for _ in 0..50 {
+            if registry.status(agent.clone()).in_sync {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let status = registry.status(agent.clone());
+        assert!(status.in_sync);
+        let health = status
+            .connection
+            .and_then(|connection| connection.runner_health);
+        assert_eq!(health.map(|health| health.healthy), Some(false));
+
+        // the config is pushed again once it changed, with the runners assigned to the agent only
+        let mut runner = GitLabRunner::for_testing();
+        runner.set_labels(Labels::from([(AGENT_LABEL, "ci-host-01")]));
+        runner.create(&pool).await?;
+        let mut other = GitLabRunner::for_testing();
+        other.set_name("elsewhere");
+        other.set_url("https://gitlab.other-company.com");
+        other.set_token("glrt-warblgarblwarblgarbl");
+        other.create(&pool).await?;
+        let bumped = config_cache.bump();
+        // without canaries, the change is rolled out to all agents at once
+        rollout
+            .reconcile(&pool, &config_cache, &settings, &registry)
+            .await?;
+
+        let (version, config) = next_push(&mut pushes).await?;
+        assert_eq!(version, bumped);
+        assert!(config.contains(runner.name()));
+        assert!(!config.contains("glrt-warblgarblwarblgarbl"));
+        assert!(!registry.status(agent.clone()).in_sync);
+
+        // revoking the agent closes the session
+        registry.revoke(agent.uuid());
+        tokio::time::timeout(Duration::from_secs(5), running).await???;
+        assert!(!registry.status(agent.clone()).connected);
+
+        Ok(())
+    }
+}
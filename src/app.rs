@@ -1,11 +1,11 @@
 // Copyright 2024 bmc::labs GmbH. All rights reserved.
 
-use std::{fs::File, path::PathBuf, time::Duration};
+use std::{fs::File, path::PathBuf, sync::Arc, time::Duration};
 
 use axum::{
     middleware,
-    routing::{get, post},
-    Router,
+    routing::{delete, get, post},
+    Extension, Router,
 };
 use miette::IntoDiagnostic;
 use tower_http::{timeout::TimeoutLayer, trace::TraceLayer};
@@ -13,12 +13,37 @@ use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
 use crate::{
-    auth::{authenticate, SecurityAddon},
+    agents::{self, AgentRegistry},
+    audit::{record_denials, record_mutations, DenialLimiter},
+    auth::{self, authenticate, bypass_authentication, AuthMode, SecurityAddon, ShareLinks},
+    autoscaling::{self, ScalingLog},
+    body_logging::log_bodies,
     error,
-    handlers::gitlab_runners,
-    models,
+    freeze::enforce_freeze,
+    gitlab::{self, GitLabClient},
+    gitops::{self, enforce_gitops, GitOpsLog},
+    handlers::{
+        admin, agents as agent_handlers, audit_log, config, error_codes, export, gitlab_runners,
+        gitops as gitops_handlers, health, import, instances, metrics, plan as plan_handlers,
+        rollout as rollout_handlers, runtime_settings, stats, tasks, version,
+    },
+    metrics::Metrics,
+    models::{self, ConfigCache, ConfigTarget, ReadCache},
+    notifications,
+    plan::{self, Plans},
+    rollout::{self, ConfigRollout},
+    secrets::Secrets,
+    settings::{self, SettingsStore},
+    subsystems::{self, Supervisor},
+};
+#[cfg(feature = "chaos")]
+use crate::{
+    chaos::{self, Faults},
+    handlers::chaos as chaos_handlers,
 };
 
+pub static DEFAULT_LISTEN_ADDR: &str = "0.0.0.0";
+pub static DEFAULT_PORT: u16 = 3000;
 pub static DEFAULT_DATABASE_URL: &str = "/etc/runrs/database.sqlite";
 pub static DEFAULT_CONFIG_PATH: &str = "/etc/gitlab-runner/config.toml";
 pub static REQUEST_TIMEOUT_SECS: u64 = 15;
@@ -27,16 +52,163 @@ pub static REQUEST_TIMEOUT_SECS: u64 = 15;
 #[openapi(
     paths(
         gitlab_runners::create,
+        gitlab_runners::register,
         gitlab_runners::list,
+        gitlab_runners::stream,
+        gitlab_runners::deleted,
+        gitlab_runners::search,
+        gitlab_runners::verify,
         gitlab_runners::read,
+        gitlab_runners::lint,
+        gitlab_runners::share,
+        gitlab_runners::jobs,
         gitlab_runners::update,
         gitlab_runners::delete,
+        gitlab_runners::delete_by_filter,
+        gitlab_runners::batch_update,
+        gitlab_runners::bulk,
+        config::status,
+        config::target,
+        config::switch_target,
+        config::reset_target,
+        config::global,
+        config::update_global,
+        config::session_server,
+        config::update_session_server,
+        config::orphans,
+        config::adopt_orphans,
+        config::purge_orphans,
+        config::revision_at,
+        export::export,
+        import::import,
+        stats::stats,
+        gitops_handlers::status,
+        plan_handlers::plan,
+        plan_handlers::apply,
+        version::version,
+        health::healthz,
+        health::readyz,
+        error_codes::error_codes,
+        metrics::metrics,
+        admin::subsystems,
+        agent_handlers::register,
+        agent_handlers::list,
+        agent_handlers::read,
+        agent_handlers::delete,
+        agent_handlers::connect,
+        rollout_handlers::status,
+        rollout_handlers::promote,
+        rollout_handlers::roll_back,
+        audit_log::list,
+        audit_log::denials,
+        audit_log::system_events,
+        runtime_settings::read,
+        runtime_settings::update,
+        tasks::dead_letters,
+        tasks::replay,
+        instances::maintenance,
+        instances::start_maintenance,
+        instances::end_maintenance,
     ),
     components(
         schemas(
             error::Error,
             error::ErrorType,
+            error::ErrorCode,
+            error::ErrorCodeInfo,
+            health::Health,
+            health::HealthCheck,
+            health::HealthStatus,
             models::GitLabRunner,
+            gitlab_runners::RunnerResponse,
+            gitlab_runners::Links,
+            models::GitLabRunnerPatch,
+            models::BulkResult,
+            models::GlobalConfig,
+            models::SessionServerConfig,
+            models::DeletedRunner,
+            gitlab::RunnerJob,
+            models::Lint,
+            models::VerificationReport,
+            models::RunnerVerification,
+            models::VerificationStatus,
+            models::MaintenanceWindow,
+            models::RunnerRegistration,
+            models::Registration,
+            models::RegistrationDetails,
+            models::LegacyRegistration,
+            models::Os,
+            models::OutputLimit,
+            models::Preset,
+            models::PresetConfig,
+            models::Task,
+            models::DeadLetter,
+            models::ConfigSync,
+            models::ConfigSyncStatus,
+            config::ConfigStatus,
+            config::ConfigTargetStatus,
+            config::ConfigTargetUpdate,
+            models::OrphanRunner,
+            models::Adoption,
+            models::ImportProgress,
+            models::ImportFailure,
+            export::ExportFormat,
+            stats::Stats,
+            models::QuotaUsage,
+            models::Usage,
+            models::InstanceUsage,
+            autoscaling::AutoscalingReport,
+            gitops_handlers::GitOpsStatus,
+            gitops::ReconcileReport,
+            gitops::ReconciledRunner,
+            gitops::ReconcileFailure,
+            autoscaling::ScalingDecision,
+            plan::DesiredRunners,
+            plan::PlanApply,
+            plan::Plan,
+            version::VersionInfo,
+            subsystems::SubsystemStatus,
+            subsystems::SubsystemState,
+            models::Agent,
+            models::AgentRegistration,
+            agent_handlers::RegisteredAgent,
+            agents::AgentStatus,
+            agents::AgentConnection,
+            agents::RunnerHealth,
+            rollout::RolloutOverview,
+            rollout::RolloutStatus,
+            rollout::RolloutPhase,
+            models::AuditEntry,
+            audit_log::AuditLogPage,
+            models::SystemEvent,
+            models::SystemEventKind,
+            audit_log::SystemEventPage,
+            settings::Settings,
+            settings::NameUniqueness,
+            settings::Quotas,
+            settings::BodyLogging,
+            settings::RenderOptions,
+            settings::RunnerOrder,
+            glrcfg::LogLevel,
+            glrcfg::LogFormat,
+            glrcfg::Defaults,
+            settings::Expiry,
+            settings::ExpiryAction,
+            settings::Events,
+            settings::AuditRetention,
+            settings::RecycleBin,
+            settings::HistoryRetention,
+            settings::Verification,
+            settings::RolloutPolicy,
+            settings::Autoscaling,
+            settings::Notifications,
+            settings::GitOps,
+            settings::Operator,
+            notifications::ChannelConfig,
+            notifications::SmtpTls,
+            notifications::NotificationKind,
+            auth::AuthMode,
+            auth::ShareLink,
         )
     ),
     tags(
@@ -52,48 +224,237 @@ pub static REQUEST_TIMEOUT_SECS: u64 = 15;
 )]
 struct ApiDoc;
 
+/// Returns the OpenAPI document, including the chaos endpoints if they are compiled in.
+fn api_doc() -> utoipa::openapi::OpenApi {
+    let doc = ApiDoc::openapi();
+    #[cfg(feature = "chaos")]
+    let doc = {
+        let mut doc = doc;
+        doc.merge(chaos_handlers::ChaosApi::openapi());
+        doc
+    };
+    doc
+}
+
+/// Renders the OpenAPI document as pretty-printed JSON. The output only depends on the source (and
+/// the enabled features), so it can be diffed and used to generate clients without a running
+/// server.
+pub fn openapi_json() -> miette::Result<String> {
+    api_doc().to_pretty_json().into_diagnostic()
+}
+
 /// Initializes the API router
 pub async fn router(secret: String, app_state: AppState) -> Router {
-    Router::new()
-        .merge(SwaggerUi::new("/api-docs").url("/api-docs/runrs-api.json", ApiDoc::openapi()))
-        .merge(
-            Router::new()
-                .route("/gitlab-runners", post(gitlab_runners::create))
-                .route("/gitlab-runners/list", get(gitlab_runners::list))
-                .route(
-                    "/gitlab-runners/:id",
-                    get(gitlab_runners::read)
-                        .put(gitlab_runners::update)
-                        .delete(gitlab_runners::delete),
-                )
-                .layer(middleware::from_fn_with_state(secret, authenticate)),
+    let api = Router::new()
+        .route(
+            "/gitlab-runners",
+            post(gitlab_runners::create).delete(gitlab_runners::delete_by_filter),
+        )
+        .route("/gitlab-runners/register", post(gitlab_runners::register))
+        .route("/gitlab-runners/list", get(gitlab_runners::list))
+        .route("/gitlab-runners/stream", get(gitlab_runners::stream))
+        .route("/gitlab-runners/deleted", get(gitlab_runners::deleted))
+        .route("/gitlab-runners/search", get(gitlab_runners::search))
+        .route("/gitlab-runners/verify", get(gitlab_runners::verify))
+        .route(
+            "/gitlab-runners/batch-update",
+            post(gitlab_runners::batch_update),
+        )
+        .route("/gitlab-runners/bulk", post(gitlab_runners::bulk))
+        .route("/config/status", get(config::status))
+        .route(
+            "/config/target",
+            get(config::target)
+                .put(config::switch_target)
+                .delete(config::reset_target),
+        )
+        .route(
+            "/config/global",
+            get(config::global).put(config::update_global),
         )
-        .layer((
-            // outer tracing layer
-            TraceLayer::new_for_http(),
-            // set timeout for all requests
-            TimeoutLayer::new(Duration::from_secs(REQUEST_TIMEOUT_SECS)),
+        .route(
+            "/config/session-server",
+            get(config::session_server).put(config::update_session_server),
+        )
+        .route("/config/orphans", get(config::orphans))
+        .route("/config/orphans/adopt", post(config::adopt_orphans))
+        .route("/config/orphans/purge", post(config::purge_orphans))
+        .route("/config/revisions/at/:timestamp", get(config::revision_at))
+        .route("/export", get(export::export))
+        .route("/import", post(import::import))
+        .route("/stats", get(stats::stats))
+        .route("/gitops", get(gitops_handlers::status))
+        .route("/plan", post(plan_handlers::plan))
+        .route("/apply", post(plan_handlers::apply))
+        .route("/admin/subsystems", get(admin::subsystems))
+        .route(
+            "/agents",
+            get(agent_handlers::list).post(agent_handlers::register),
+        )
+        .route(
+            "/agents/:id",
+            get(agent_handlers::read).delete(agent_handlers::delete),
+        )
+        .route("/rollout", get(rollout_handlers::status))
+        .route("/rollout/promote", post(rollout_handlers::promote))
+        .route("/rollout/rollback", post(rollout_handlers::roll_back))
+        .route("/audit-log", get(audit_log::list))
+        .route("/audit-log/denials", get(audit_log::denials))
+        .route("/audit-log/system-events", get(audit_log::system_events))
+        .route("/tasks/dead-letter", get(tasks::dead_letters))
+        .route("/tasks/dead-letter/:id/replay", post(tasks::replay))
+        .route(
+            "/instances/maintenance",
+            get(instances::maintenance)
+                .put(instances::start_maintenance)
+                .delete(instances::end_maintenance),
+        )
+        .route(
+            "/settings/runtime",
+            get(runtime_settings::read).put(runtime_settings::update),
+        )
+        .route(
+            "/gitlab-runners/:id",
+            get(gitlab_runners::read)
+                .put(gitlab_runners::update)
+                .delete(gitlab_runners::delete),
+        )
+        .route("/gitlab-runners/:id/lint", get(gitlab_runners::lint))
+        .route("/gitlab-runners/:id/jobs", get(gitlab_runners::jobs))
+        .route("/gitlab-runners/:id/share", post(gitlab_runners::share));
+    #[cfg(feature = "chaos")]
+    let api = api.route(
+        "/chaos",
+        get(chaos_handlers::read)
+            .put(chaos_handlers::replace)
+            .delete(chaos_handlers::reset),
+    );
+
+    let api = api
+        // all run after authentication, which provides the claims; requests rejected during a
+        // change freeze or while GitOps is on are audited as well
+        .layer(middleware::from_fn_with_state(
+            app_state.settings.clone(),
+            enforce_gitops,
+        ))
+        .layer(middleware::from_fn_with_state(
+            app_state.settings.clone(),
+            enforce_freeze,
+        ))
+        .layer(middleware::from_fn_with_state(
+            app_state.pool.clone(),
+            record_mutations,
         ))
-        .with_state(app_state)
+        .layer(Extension(ShareLinks::new(&secret)));
+
+    let api = match app_state.settings.load().auth_mode {
+        AuthMode::Jwt => api.layer(middleware::from_fn_with_state(secret, authenticate)),
+        AuthMode::Disabled => api.layer(middleware::from_fn(bypass_authentication)),
+    };
+    // runs before authentication, so requests without a valid token are recorded as well
+    let denials = middleware::from_fn_with_state(
+        (app_state.pool.clone(), Arc::new(DenialLimiter::default())),
+        record_denials,
+    );
+    let api = api.layer(denials.clone());
+    // agents authenticate with agent tokens instead of JWTs; denied ones are recorded all the same
+    let agent_channel = Router::new()
+        .route("/agents/connect", get(agent_handlers::connect))
+        .layer(denials);
+
+    let app = Router::new()
+        .merge(SwaggerUi::new("/api-docs").url("/api-docs/runrs-api.json", api_doc()))
+        .route("/version", get(version::version))
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .route("/error-codes", get(error_codes::error_codes))
+        .route("/metrics", get(metrics::metrics))
+        .merge(agent_channel)
+        .merge(api.layer(middleware::from_fn_with_state(
+            app_state.settings.clone(),
+            log_bodies,
+        )));
+    // within the timeout, so injected latency beyond it makes requests time out
+    #[cfg(feature = "chaos")]
+    let app = app.layer(middleware::from_fn_with_state(
+        app_state.faults.clone(),
+        chaos::inject_latency,
+    ));
+
+    app.layer((
+        // outer tracing layer
+        TraceLayer::new_for_http(),
+        // set timeout for all requests
+        TimeoutLayer::new(Duration::from_secs(REQUEST_TIMEOUT_SECS)),
+    ))
+    .with_state(app_state)
 }
 
 /// Holds the state for the API router
 #[derive(Debug, Clone)]
 pub struct AppState {
     pub pool: atmosphere::Pool,
-    pub config_path: PathBuf,
+    pub config_target: Arc<ConfigTarget>,
+    pub config_cache: Arc<ConfigCache>,
+    pub settings: Arc<SettingsStore>,
+    pub supervisor: Arc<Supervisor>,
+    pub gitlab: GitLabClient,
+    pub secrets: Secrets,
+    pub metrics: Arc<Metrics>,
+    pub read_cache: Arc<ReadCache>,
+    pub agents: Arc<AgentRegistry>,
+    pub rollout: Arc<ConfigRollout>,
+    pub autoscaling: Arc<ScalingLog>,
+    pub gitops: Arc<GitOpsLog>,
+    pub plans: Arc<Plans>,
+    #[cfg(feature = "chaos")]
+    pub faults: Arc<Faults>,
 }
 
 impl AppState {
     pub async fn init() -> miette::Result<Self> {
+        let secrets = Secrets::init().await?;
+        let config_cache = ConfigCache::new(secrets.clone());
+        let gitlab = GitLabClient::from_env()?;
+        // one set of faults, shared by everything they affect
+        #[cfg(feature = "chaos")]
+        let faults = Arc::<Faults>::default();
+        #[cfg(feature = "chaos")]
+        let (config_cache, gitlab) = (
+            config_cache.with_faults(faults.clone()),
+            gitlab.with_faults(faults.clone()),
+        );
+        let config_cache = Arc::new(config_cache);
+        let metrics = Arc::<Metrics>::default();
+
         Ok(Self {
             pool: init_database().await?,
-            config_path: init_config_path()?,
+            config_target: Arc::new(
+                ConfigTarget::new(init_config_path()?).with_allowed_dirs(
+                    std::env::var_os("CONFIG_TARGET_DIRS")
+                        .map(|dirs| std::env::split_paths(&dirs).collect::<Vec<_>>())
+                        .unwrap_or_default(),
+                ),
+            ),
+            read_cache: Arc::new(ReadCache::new(config_cache.clone(), metrics.clone())),
+            config_cache,
+            settings: Arc::new(SettingsStore::init()?),
+            supervisor: Arc::default(),
+            gitlab,
+            secrets,
+            metrics,
+            agents: Arc::default(),
+            rollout: Arc::default(),
+            autoscaling: Arc::default(),
+            gitops: Arc::default(),
+            plans: Arc::default(),
+            #[cfg(feature = "chaos")]
+            faults,
         })
     }
 }
 
-#[cfg(test)]
+#[cfg(any(test, feature = "fuzzing"))]
 impl AppState {
     pub fn for_testing(pool: atmosphere::Pool) -> Self {
         let config_path = PathBuf::from(format!(
@@ -101,7 +462,36 @@ impl AppState {
             uuid::Uuid::new_v4()
         ));
 
-        Self { pool, config_path }
+        let config_cache = ConfigCache::default();
+        let gitlab = GitLabClient::default();
+        #[cfg(feature = "chaos")]
+        let faults = Arc::<Faults>::default();
+        #[cfg(feature = "chaos")]
+        let (config_cache, gitlab) = (
+            config_cache.with_faults(faults.clone()),
+            gitlab.with_faults(faults.clone()),
+        );
+        let config_cache = Arc::new(config_cache);
+        let metrics = Arc::<Metrics>::default();
+
+        Self {
+            pool,
+            config_target: Arc::new(ConfigTarget::new(config_path)),
+            read_cache: Arc::new(ReadCache::new(config_cache.clone(), metrics.clone())),
+            config_cache,
+            settings: Arc::default(),
+            supervisor: Arc::default(),
+            gitlab,
+            secrets: Secrets::default(),
+            metrics,
+            agents: Arc::default(),
+            rollout: Arc::default(),
+            autoscaling: Arc::default(),
+            gitops: Arc::default(),
+            plans: Arc::default(),
+            #[cfg(feature = "chaos")]
+            faults,
+        }
     }
 }
 
@@ -176,3 +566,23 @@ fn init_config_path() -> miette::Result<PathBuf> {
 
     Ok(config_path)
 }
+
+#[cfg(test)]
+mod tests {
+    use miette::IntoDiagnostic;
+    use pretty_assertions::assert_eq;
+
+    use super::openapi_json;
+
+    #[test]
+    fn openapi_json_is_deterministic() -> miette::Result<()> {
+        let openapi = openapi_json()?;
+        assert_eq!(openapi, openapi_json()?);
+
+        let document: serde_json::Value = serde_json::from_str(&openapi).into_diagnostic()?;
+        assert!(document["paths"]["/gitlab-runners"].is_object());
+        assert!(document["paths"]["/version"].is_object());
+
+        Ok(())
+    }
+}
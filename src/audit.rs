@@ -0,0 +1,220 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{header, Method, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{
+    auth::{self, Claims},
+    models::AuditEntry,
+};
+
+/// Maximum number of denied requests recorded per minute and client address; beyond that,
+/// denials are only logged, so clients hammering the API with bad tokens can't flood the audit
+/// log, nor keep the denials of others from being recorded.
+const MAX_DENIALS_PER_MINUTE: u32 = 60;
+
+/// Maximum number of client addresses whose denials are counted at a time, so that the limiter
+/// itself can't be grown without bound.
+const MAX_DENIAL_SOURCES: usize = 10_000;
+
+/// Why a request was denied; attached to 401 and 403 responses, so the reason can be recorded in
+/// the audit log.
+#[derive(Debug, Clone)]
+pub struct DenialReason(pub String);
+
+/// Marks responses whose request was recorded in the audit log already.
+#[derive(Debug, Clone, Copy)]
+struct Audited;
+
+/// Limits how many denied requests are recorded per client address, in windows of one minute.
+/// Requests without a known address, e.g. on a Unix socket, share a window.
+#[derive(Debug, Default)]
+pub struct DenialLimiter {
+    windows: Mutex<HashMap<Option<IpAddr>, (Instant, u32)>>,
+}
+
+impl DenialLimiter {
+    /// Returns `true` if another denial of `source` may be recorded in its current window.
+    fn admit(&self, source: Option<IpAddr>) -> bool {
+        let mut windows = self.windows.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        let current = |start: &Instant| now.duration_since(*start) < Duration::from_secs(60);
+
+        if windows.len() >= MAX_DENIAL_SOURCES && !windows.contains_key(&source) {
+            windows.retain(|_, (start, _)| current(start));
+            if windows.len() >= MAX_DENIAL_SOURCES {
+                return false;
+            }
+        }
+
+        match windows.get_mut(&source) {
+            Some((start, count)) if current(start) => {
+                *count += 1;
+                *count <= MAX_DENIALS_PER_MINUTE
+            }
+            _ => {
+                windows.insert(source, (now, 1));
+                true
+            }
+        }
+    }
+}
+
+/// Requests count as denied if they were rejected for lack of authentication or permission;
+/// those are marked with a [`DenialReason`]. Other 403s, e.g. for an exceeded quota, are not.
+fn is_denial(response: &Response) -> bool {
+    matches!(
+        response.status(),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN
+    ) && response.extensions().get::<DenialReason>().is_some()
+}
+
+fn denial_reason(response: &Response) -> String {
+    response.extensions().get::<DenialReason>().map_or_else(
+        || {
+            let status = response.status();
+            status
+                .canonical_reason()
+                .unwrap_or(status.as_str())
+                .to_string()
+        },
+        |reason| reason.0.clone(),
+    )
+}
+
+/// Records mutating requests, i.e. all but `GET`, `HEAD` and `OPTIONS`, in the audit log once
+/// they were handled. Runs after authentication, so requests without a valid token are not
+/// recorded here, but by [`record_denials`].
+pub async fn record_mutations(
+    State(pool): State<atmosphere::Pool>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    if matches!(method, Method::GET | Method::HEAD | Method::OPTIONS) {
+        return next.run(request).await;
+    }
+
+    let path = request.uri().path().to_string();
+    let actor = request
+        .extensions()
+        .get::<Claims>()
+        .map(|claims| claims.issuer().to_string());
+
+    let mut response = next.run(request).await;
+
+    // the change was made already, so failing to record it must not fail the request
+    let status = response.status().as_u16();
+    let recorded = if is_denial(&response) {
+        let reason = denial_reason(&response);
+        response.extensions_mut().insert(Audited);
+        AuditEntry::record_denial(
+            &pool,
+            actor.as_deref(),
+            method.as_str(),
+            &path,
+            status,
+            &reason,
+        )
+        .await
+    } else {
+        AuditEntry::record(&pool, actor.as_deref(), method.as_str(), &path, status).await
+    };
+    if let Err(err) = recorded {
+        tracing::error!(%err, %method, path, "recording audit log entry failed");
+    }
+
+    response
+}
+
+/// Records requests denied with 401 or 403 in the audit log, along with the reason and the
+/// issuer the token claims, if it can be decoded. Runs before authentication, so requests without
+/// a valid token are recorded as well; since the issuer of those is unverified, it's only a hint.
+/// At most [`MAX_DENIALS_PER_MINUTE`] denials are recorded per client address.
+pub async fn record_denials(
+    State((pool, limiter)): State<(atmosphere::Pool, Arc<DenialLimiter>)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let source = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+    let actor = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .and_then(auth::token_issuer);
+
+    let response = next.run(request).await;
+    if !is_denial(&response) || response.extensions().get::<Audited>().is_some() {
+        return response;
+    }
+
+    let reason = denial_reason(&response);
+    if !limiter.admit(source) {
+        tracing::warn!(
+            %method,
+            path,
+            ?source,
+            reason,
+            "not recording denied request, too many denials"
+        );
+        return response;
+    }
+
+    let status = response.status().as_u16();
+    if let Err(err) = AuditEntry::record_denial(
+        &pool,
+        actor.as_deref(),
+        method.as_str(),
+        &path,
+        status,
+        &reason,
+    )
+    .await
+    {
+        tracing::error!(%err, %method, path, "recording denied request failed");
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::{DenialLimiter, MAX_DENIALS_PER_MINUTE};
+
+    #[test]
+    fn denials_are_rate_limited_per_source() {
+        let limiter = DenialLimiter::default();
+        let (attacker, other) = (
+            Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))),
+            Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2))),
+        );
+
+        for _ in 0..MAX_DENIALS_PER_MINUTE {
+            assert!(limiter.admit(attacker));
+        }
+        assert!(!limiter.admit(attacker));
+
+        // one client exhausting its limit doesn't keep the denials of others from being recorded
+        assert!(limiter.admit(other));
+        assert!(limiter.admit(None));
+    }
+}
@@ -1,34 +1,125 @@
 // Copyright 2024 bmc::labs GmbH. All rights reserved.
 
+use std::{fmt::Display, net::SocketAddr, str::FromStr, sync::Arc};
+
 use axum::{
-    extract::{Request, State},
-    http::{header, HeaderMap, StatusCode},
+    extract::{Query, Request, State},
+    http::{header, HeaderMap, Method, StatusCode},
     middleware::Next,
     response::{IntoResponse, Response},
     Json,
 };
-use chrono::{TimeDelta, Utc};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use chrono::{DateTime, TimeDelta, Utc};
+use jsonwebtoken::{
+    crypto, decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
 use miette::IntoDiagnostic;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
 use utoipa::{
     openapi::{
         security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
         OpenApi,
     },
-    Modify,
+    Modify, ToSchema,
 };
 
+use crate::{audit::DenialReason, error::Error};
+
 const DEFAULT_VALIDITY_PERIOD_HOURS: i64 = 12;
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Signed along with path and expiry of share links, so their signatures can't be mistaken for
+/// anything else signed with the secret.
+const SHARE_LINK_CONTEXT: &str = "runrs share link";
+
+/// Agent tokens start with this, so they're easy to tell from JWTs, e.g. in secret scanners.
+const AGENT_TOKEN_PREFIX: &str = "runrs-agent-";
+
+/// Whether requests must carry a valid token.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMode {
+    /// Requests must carry a JWT signed with `SECRET`.
+    #[default]
+    Jwt,
+    /// Every request is treated as coming from the operator. Only for local development; the
+    /// service refuses to start in this mode unless it listens on a loopback address.
+    Disabled,
+}
+
+impl FromStr for AuthMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "jwt" => Ok(Self::Jwt),
+            "disabled" => Ok(Self::Disabled),
+            _ => Err(format!(
+                "invalid auth mode '{mode}'; must be one of jwt, disabled"
+            )),
+        }
+    }
+}
+
+impl Display for AuthMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Jwt => "jwt".fmt(f),
+            Self::Disabled => "disabled".fmt(f),
+        }
+    }
+}
+
+/// Refuses to serve without authentication unless the service is only reachable from this host.
+pub fn ensure_auth_mode_allowed(auth_mode: AuthMode, addr: SocketAddr) -> miette::Result<()> {
+    if auth_mode == AuthMode::Disabled && !addr.ip().is_loopback() {
+        let err_msg = format!(
+            "AUTH_MODE=disabled requires a loopback address, e.g. LISTEN_ADDR=127.0.0.1, \
+             but the service listens on {addr}"
+        );
+
+        tracing::error!(err_msg);
+        miette::bail!(err_msg);
+    }
+
+    Ok(())
+}
+
+/// Permissions a token grants on top of the regular CRUD operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Operations affecting many runners at once or the service itself
+    Admin,
+    /// Changes during a change freeze
+    #[serde(rename = "freeze_override")]
+    FreezeOverride,
+}
+
+impl FromStr for Scope {
+    type Err = String;
+
+    fn from_str(scope: &str) -> Result<Self, Self::Err> {
+        match scope {
+            "admin" => Ok(Self::Admin),
+            "freeze_override" => Ok(Self::FreezeOverride),
+            _ => Err(format!(
+                "invalid scope '{scope}'; must be one of admin, freeze_override"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     iss: String, // issuer
     exp: usize,  // expiration time - UTC timestamp in seconds
+    #[serde(default)]
+    scopes: Vec<Scope>,
 }
 
 impl Claims {
-    pub fn new(validity_period_days: Option<i64>) -> miette::Result<Self> {
+    pub fn new(validity_period_days: Option<i64>, scopes: Vec<Scope>) -> miette::Result<Self> {
         let iss = "peripheral".to_string();
         let exp = Utc::now()
             .checked_add_signed(TimeDelta::hours(
@@ -37,7 +128,24 @@ impl Claims {
             .ok_or(miette::miette!("could not calculate expiration time"))?
             .timestamp() as usize;
 
-        Ok(Self { iss, exp })
+        Ok(Self { iss, exp, scopes })
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.iss
+    }
+
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+
+    /// Returns a `Forbidden` error unless the token grants the given scope.
+    pub fn require_scope(&self, scope: Scope) -> Result<(), Error> {
+        if !self.has_scope(scope) {
+            return Err(Error::forbidden(format!("token lacks the {scope:?} scope")));
+        }
+
+        Ok(())
     }
 }
 
@@ -52,16 +160,15 @@ pub fn init_secret() -> miette::Result<String> {
     Ok(secret)
 }
 
-pub fn encode_token(secret: &str) -> miette::Result<String> {
-    let token = encode(
+/// Encodes a token granting the given scopes on top of the regular CRUD operations; a token
+/// without scopes can read and write runners, but not administer the service.
+pub fn encode_token(secret: &str, scopes: Vec<Scope>) -> miette::Result<String> {
+    encode(
         &Header::default(),
-        &Claims::new(None)?,
+        &Claims::new(None, scopes)?,
         &EncodingKey::from_secret(secret.as_ref()),
     )
-    .into_diagnostic()?;
-
-    tracing::info!(?token, "generated token");
-    Ok(token)
+    .into_diagnostic()
 }
 
 pub fn validate_token(secret: &str, token: &str) -> miette::Result<Claims> {
@@ -76,37 +183,193 @@ pub fn validate_token(secret: &str, token: &str) -> miette::Result<Claims> {
     Ok(token_data.claims)
 }
 
-/// Authenticate middleware checks the request headers for a valid JWT token.
+/// Returns the issuer a token claims without validating the token, so that denied requests can be
+/// attributed in the audit log.
+pub fn token_issuer(token: &str) -> Option<String> {
+    let mut validation = Validation::default();
+    validation.insecure_disable_signature_validation();
+    validation.validate_exp = false;
+    validation.required_spec_claims.clear();
+
+    decode::<Claims>(token, &DecodingKey::from_secret(&[]), &validation)
+        .ok()
+        .map(|token_data| token_data.claims.iss)
+}
+
+/// Authenticate middleware checks the request headers for a valid JWT token. Requests without a
+/// valid token are rejected with 401, so that they are audited as denials.
 pub async fn authenticate(
     headers: HeaderMap,
     State(secret): State<String>,
-    request: Request,
+    mut request: Request,
     next: Next,
 ) -> Response {
     tracing::debug!(?headers, "authenticating request");
-    let err_response = (
-        StatusCode::FORBIDDEN,
-        Json("unable to authenticate request"),
-    )
-        .into_response();
+    let err_response = |reason: String| {
+        let mut response = (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            Json("unable to authenticate request"),
+        )
+            .into_response();
+        response.extensions_mut().insert(DenialReason(reason));
+        response
+    };
 
     let Some(token) = headers
         .get(header::AUTHORIZATION)
         .and_then(|value| value.to_str().ok())
         .and_then(|value| value.strip_prefix("Bearer "))
     else {
+        if ShareLinks::new(&secret).verify(&request) {
+            tracing::debug!(
+                path = request.uri().path(),
+                "request carries a valid share link"
+            );
+            request.extensions_mut().insert(SharedAccess);
+            return next.run(request).await;
+        }
+
         tracing::warn!(?headers, "no token found in request headers");
-        return err_response;
+        return err_response("no token in request".to_string());
     };
 
-    if validate_token(&secret, token).is_err() {
-        tracing::warn!(?token, "unable to validate token");
-        return err_response;
+    let claims = match validate_token(&secret, token) {
+        Ok(claims) => claims,
+        Err(err) => {
+            tracing::warn!(?token, "unable to validate token");
+            return err_response(format!("invalid token: {err}"));
+        }
     };
 
+    // make the claims available to handlers which check scopes
+    request.extensions_mut().insert(claims);
+
     next.run(request).await
 }
 
+/// Signs and verifies share links: URLs which grant read-only access to a single resource until
+/// they expire, for people without a token. Instead of a token, they carry their expiry and an
+/// HMAC of path and expiry, made with the secret tokens are signed with.
+#[derive(Debug, Clone)]
+pub struct ShareLinks {
+    secret: Arc<str>,
+}
+
+/// A signed URL granting read-only access to a single resource until it expires.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ShareLink {
+    /// Path and query of the link, relative to the URL runrs is reachable at
+    #[schema(
+        example = "/gitlab-runners/be924fdd-fb28-468c-8c70-1f0ed3af4485?expires=1719568800&signature=..."
+    )]
+    pub url: String,
+    /// When the link stops working
+    #[schema(value_type = String, format = DateTime, example = "2024-06-28T10:00:00Z")]
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ShareLinkParams {
+    expires: i64,
+    signature: String,
+}
+
+/// Marks requests authenticated with a share link instead of a token; they carry no claims.
+#[derive(Debug, Clone, Copy)]
+pub struct SharedAccess;
+
+impl ShareLinks {
+    pub fn new(secret: &str) -> Self {
+        Self {
+            secret: secret.into(),
+        }
+    }
+
+    /// Signs a link to the given path which works until `expires_at`.
+    pub fn sign(&self, path: &str, expires_at: DateTime<Utc>) -> Result<ShareLink, Error> {
+        let expires = expires_at.timestamp();
+        let signature = crypto::sign(
+            Self::message(path, expires).as_bytes(),
+            &EncodingKey::from_secret(self.secret.as_bytes()),
+            Algorithm::HS256,
+        )
+        .map_err(Error::internal_error)?;
+
+        Ok(ShareLink {
+            url: format!("{path}?expires={expires}&signature={signature}"),
+            expires_at,
+        })
+    }
+
+    /// Returns `true` if the request reads the path of a share link which is signed correctly
+    /// and hasn't expired yet.
+    pub fn verify(&self, request: &Request) -> bool {
+        if request.method() != Method::GET {
+            return false;
+        }
+
+        let Ok(Query(params)) = Query::<ShareLinkParams>::try_from_uri(request.uri()) else {
+            return false;
+        };
+        if params.expires <= Utc::now().timestamp() {
+            tracing::warn!(path = request.uri().path(), "share link expired");
+            return false;
+        }
+
+        crypto::verify(
+            &params.signature,
+            Self::message(request.uri().path(), params.expires).as_bytes(),
+            &DecodingKey::from_secret(self.secret.as_bytes()),
+            Algorithm::HS256,
+        )
+        .unwrap_or(false)
+    }
+
+    fn message(path: &str, expires: i64) -> String {
+        format!("{SHARE_LINK_CONTEXT}\n{path}\n{expires}")
+    }
+}
+
+/// Issues and hashes the tokens agents on runner hosts connect with. Unlike JWTs, they don't
+/// expire and can be revoked one by one. They're random and only stored as SHA-256 hash; since
+/// they're long enough not to be guessed, the hash needs no key, so rotating the secret JWTs are
+/// signed with leaves them valid.
+#[derive(Debug)]
+pub struct AgentTokens;
+
+impl AgentTokens {
+    /// Generates a new token; returns it along with its hash, which is what gets stored.
+    pub fn issue() -> (String, String) {
+        let token = format!(
+            "{AGENT_TOKEN_PREFIX}{}{}",
+            uuid::Uuid::new_v4().simple(),
+            uuid::Uuid::new_v4().simple()
+        );
+        let hash = Self::hash(&token);
+
+        (token, hash)
+    }
+
+    /// Hashes a token presented by an agent, for looking it up.
+    pub fn hash(token: &str) -> String {
+        format!("{:x}", Sha256::digest(token.as_bytes()))
+    }
+}
+
+/// Stands in for [`authenticate`] with `AUTH_MODE=disabled`: every request gets the operator's
+/// claims with every scope, so handlers checking scopes work as usual and changes can be made
+/// during a change freeze, as they can with the operator's override token.
+pub async fn bypass_authentication(mut request: Request, next: Next) -> Response {
+    match Claims::new(None, vec![Scope::Admin, Scope::FreezeOverride]) {
+        Ok(claims) => {
+            request.extensions_mut().insert(claims);
+            next.run(request).await
+        }
+        Err(err) => Error::internal_error(err).into_response(),
+    }
+}
+
 /// SecurityAddon is a modifier that adds a security scheme to the OpenAPI spec.
 pub(super) struct SecurityAddon;
 
@@ -127,3 +390,64 @@ impl Modify for SecurityAddon {
             );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{
+        body::Body,
+        http::{self, Request, StatusCode},
+    };
+    use pretty_assertions::assert_eq;
+    use tower::ServiceExt; // for `call`, `oneshot`, and `ready`
+
+    use super::{ensure_auth_mode_allowed, AuthMode};
+    use crate::{
+        app::{router, AppState},
+        settings::{Settings, SettingsStore},
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[test]
+    fn auth_mode_requires_loopback() -> Result<()> {
+        assert!(ensure_auth_mode_allowed(AuthMode::Disabled, "127.0.0.1:3000".parse()?).is_ok());
+        assert!(ensure_auth_mode_allowed(AuthMode::Disabled, "[::1]:3000".parse()?).is_ok());
+        assert!(ensure_auth_mode_allowed(AuthMode::Disabled, "0.0.0.0:3000".parse()?).is_err());
+        assert!(ensure_auth_mode_allowed(AuthMode::Jwt, "0.0.0.0:3000".parse()?).is_ok());
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    #[tracing_test::traced_test]
+    async fn disabled_auth_mode(pool: atmosphere::Pool) -> Result<()> {
+        let mut app_state = AppState::for_testing(pool);
+
+        let request = || {
+            Request::builder()
+                .method(http::Method::GET)
+                .uri("/gitlab-runners/list")
+                .body(Body::empty())
+        };
+
+        let response = router(String::new(), app_state.clone())
+            .await
+            .oneshot(request()?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        app_state.settings = Arc::new(SettingsStore::new(Settings {
+            auth_mode: AuthMode::Disabled,
+            ..Default::default()
+        }));
+        let response = router(String::new(), app_state)
+            .await
+            .oneshot(request()?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,373 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{
+    collections::VecDeque,
+    num::NonZeroU32,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use atmosphere::Read as _;
+use chrono::{DateTime, Utc};
+use futures::{future::BoxFuture, FutureExt};
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    error::Error,
+    gitlab::{GitLabClient, JobCounts},
+    models::{Change, ConfigCache, ConfigTarget, GitLabRunner, GitLabRunnerConfig, Maintenance},
+    secrets::Secrets,
+    settings::{Autoscaling, SettingsStore},
+    subsystems::{Shutdown, Subsystem},
+};
+
+/// How often the autoscaler checks whether it was turned on while it is off.
+const IDLE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Number of decisions kept for `/stats`.
+const RECENT_DECISIONS: usize = 20;
+
+/// A change of a runner's job limit, or of `concurrent`, made by the autoscaler.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ScalingDecision {
+    /// Runner whose job limit changed; not set if `concurrent` changed
+    #[schema(value_type = Option<String>, format = Uuid)]
+    runner: Option<Uuid>,
+    /// Job limit, or `concurrent`, before the change; not set for runners which had no limit
+    #[schema(example = 2)]
+    from: Option<u32>,
+    #[schema(example = 5)]
+    to: u32,
+    /// Jobs waiting for the runner, or for all scaled runners, when the change was made
+    #[schema(example = 3)]
+    pending: u32,
+    /// Jobs running on the runner, or on all scaled runners, when the change was made
+    #[schema(example = 2)]
+    running: u32,
+    #[schema(value_type = String, format = DateTime, example = "2024-07-04T09:00:00Z")]
+    decided_at: DateTime<Utc>,
+}
+
+/// State of autoscaling as shown by `/stats`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct AutoscalingReport {
+    enabled: bool,
+    /// Current `concurrent` of the config
+    #[schema(example = 8)]
+    concurrent: u32,
+    /// Most recent decisions, newest first
+    decisions: Vec<ScalingDecision>,
+}
+
+/// The most recent decisions of the autoscaler.
+#[derive(Debug, Default)]
+pub struct ScalingLog(Mutex<VecDeque<ScalingDecision>>);
+
+impl ScalingLog {
+    fn record(&self, decision: ScalingDecision) {
+        tracing::info!(?decision, "autoscaling");
+
+        let mut decisions = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        decisions.push_front(decision);
+        decisions.truncate(RECENT_DECISIONS);
+    }
+
+    /// Reports the current state of autoscaling along with the most recent decisions.
+    pub fn report(&self, policy: &Autoscaling, concurrent: NonZeroU32) -> AutoscalingReport {
+        AutoscalingReport {
+            enabled: policy.interval().is_some(),
+            concurrent: concurrent.get(),
+            decisions: self
+                .0
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .iter()
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// Returns the job limit a runner with the given limit is to be scaled to, if it is to be scaled.
+/// The demand of a runner is the number of jobs waiting for it plus those running on it; the
+/// limit only follows the demand once they differ by more than the hysteresis.
+pub fn decide(limit: Option<u32>, counts: JobCounts, policy: &Autoscaling) -> Option<u32> {
+    let (min, max) = policy.bounds();
+    let demand = counts.pending.saturating_add(counts.running);
+    let target = demand.clamp(min, max);
+
+    let scale = match limit {
+        // runners without a limit, or with one the bounds changed under, are brought within them
+        None => true,
+        Some(limit) if !(min..=max).contains(&limit) => true,
+        Some(limit) => {
+            demand > limit.saturating_add(policy.hysteresis)
+                || demand.saturating_add(policy.hysteresis) < limit
+        }
+    };
+
+    (scale && limit != Some(target)).then_some(target)
+}
+
+/// Scales the job limits of the `autoscaled` runners to their job queues in GitLab, sets
+/// `concurrent` to the sum of the job limits, and rewrites the config accordingly.
+#[derive(Debug, Clone)]
+pub struct Autoscaler {
+    pool: atmosphere::Pool,
+    target: Arc<ConfigTarget>,
+    cache: Arc<ConfigCache>,
+    settings: Arc<SettingsStore>,
+    gitlab: GitLabClient,
+    secrets: Secrets,
+    log: Arc<ScalingLog>,
+}
+
+impl Autoscaler {
+    pub fn new(
+        pool: atmosphere::Pool,
+        target: Arc<ConfigTarget>,
+        cache: Arc<ConfigCache>,
+        settings: Arc<SettingsStore>,
+        gitlab: GitLabClient,
+        secrets: Secrets,
+        log: Arc<ScalingLog>,
+    ) -> Self {
+        Self {
+            pool,
+            target,
+            cache,
+            settings,
+            gitlab,
+            secrets,
+            log,
+        }
+    }
+
+    async fn scale(&self) -> Result<(), Error> {
+        let settings = self.settings.load();
+        if settings.freeze_windows.defers("autoscaler") {
+            return Ok(());
+        }
+
+        let maintenance = Maintenance::active(&self.pool, Utc::now()).await?;
+
+        let mut total = JobCounts::default();
+        let mut changed = false;
+        for mut runner in GitLabRunner::read_all(&self.pool).await? {
+            // job limits of runners which didn't opt in are up to their owners
+            if !runner.autoscaled() || runner.paused() || !self.gitlab.has_api_token(runner.url()) {
+                continue;
+            }
+            // the job limit is kept until the instance is back
+            if let Some(window) = maintenance.get(runner.url()) {
+                tracing::debug!(
+                    uuid = %runner.uuid(),
+                    ends_at = %window.ends_at(),
+                    "instance in maintenance, not scaling runner"
+                );
+                continue;
+            }
+
+            // an unreachable instance must not keep the runners of other instances from scaling
+            let counts = match self
+                .gitlab
+                .job_counts(runner.url(), runner.id(), &self.secrets)
+                .await
+            {
+                Ok(counts) => counts,
+                Err(err) => {
+                    tracing::warn!(uuid = %runner.uuid(), %err, "reading job queue failed");
+                    continue;
+                }
+            };
+            total.pending = total.pending.saturating_add(counts.pending);
+            total.running = total.running.saturating_add(counts.running);
+
+            let Some(limit) = decide(runner.job_limit(), counts, &settings.autoscaling) else {
+                continue;
+            };
+            self.log.record(ScalingDecision {
+                runner: Some(*runner.uuid()),
+                from: runner.job_limit(),
+                to: limit,
+                pending: counts.pending,
+                running: counts.running,
+                decided_at: Utc::now(),
+            });
+            runner.set_job_limit(limit);
+            runner
+                .apply(&self.pool, Change::Updated, settings.events.enabled())
+                .await?;
+            changed = true;
+        }
+
+        changed |= self.adjust_concurrent(total).await?;
+        if !changed {
+            return Ok(());
+        }
+
+        self.cache.bump();
+        let path = self.target.load();
+        GitLabRunnerConfig::write_or_queue(
+            &self.pool,
+            &path,
+            &self.cache,
+            &self.settings.load().render,
+        )
+        .await
+        .map(drop)
+    }
+
+    /// Sets `concurrent` to the sum of the job limits of the active runners, so that no runner is
+    /// kept from reaching its limit; returns whether it changed. `concurrent` is left alone while
+    /// no runner has a job limit.
+    async fn adjust_concurrent(&self, total: JobCounts) -> Result<bool, Error> {
+        let (limits,): (i64,) = sqlx::query_as(
+            "SELECT COALESCE(SUM(job_limit), 0) FROM gitlab_runners WHERE NOT paused AND NOT off_schedule",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        let Some(concurrent) = NonZeroU32::new(u32::try_from(limits).unwrap_or(u32::MAX)) else {
+            return Ok(false);
+        };
+
+        let settings = self.settings.load();
+        if concurrent == settings.render.concurrent {
+            return Ok(false);
+        }
+
+        self.log.record(ScalingDecision {
+            runner: None,
+            from: Some(settings.render.concurrent.get()),
+            to: concurrent.get(),
+            pending: total.pending,
+            running: total.running,
+            decided_at: Utc::now(),
+        });
+        self.settings.set_autoscaled_concurrent(concurrent);
+
+        Ok(true)
+    }
+}
+
+impl Subsystem for Autoscaler {
+    fn name(&self) -> &'static str {
+        "autoscaler"
+    }
+
+    fn run(&self, mut shutdown: Shutdown) -> BoxFuture<'static, Result<(), Error>> {
+        let this = self.clone();
+
+        async move {
+            loop {
+                // the interval is read anew each time, so autoscaling can be turned on and off by
+                // reloading the settings
+                let interval = this.settings.load().autoscaling.interval();
+                if interval.is_some() {
+                    this.scale().await?;
+                }
+
+                tokio::select! {
+                    _ = tokio::time::sleep(interval.unwrap_or(IDLE_INTERVAL)) => {}
+                    _ = shutdown.requested() => return Ok(()),
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{num::NonZeroU32, sync::Arc};
+
+    use atmosphere::{Create as _, Pool};
+    use pretty_assertions::assert_eq;
+
+    use super::{decide, Autoscaler, ScalingLog};
+    use crate::{
+        gitlab::{GitLabClient, JobCounts},
+        models::{ConfigCache, ConfigTarget, GitLabRunner},
+        secrets::Secrets,
+        settings::Autoscaling,
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    fn counts(pending: u32, running: u32) -> JobCounts {
+        JobCounts { pending, running }
+    }
+
+    #[test]
+    fn scale_within_bounds() {
+        let policy = Autoscaling {
+            interval_secs: 30,
+            min_limit: 1,
+            max_limit: 10,
+            hysteresis: 2,
+        };
+
+        // runners without a limit get one right away
+        assert_eq!(decide(None, counts(0, 0), &policy), Some(1));
+        assert_eq!(decide(None, counts(3, 1), &policy), Some(4));
+
+        // within the hysteresis, the limit stays
+        assert_eq!(decide(Some(4), counts(2, 4), &policy), None);
+        assert_eq!(decide(Some(4), counts(0, 2), &policy), None);
+
+        // beyond it, the limit follows the demand, within the bounds
+        assert_eq!(decide(Some(4), counts(3, 4), &policy), Some(7));
+        assert_eq!(decide(Some(4), counts(40, 4), &policy), Some(10));
+        assert_eq!(decide(Some(10), counts(40, 10), &policy), None);
+        assert_eq!(decide(Some(4), counts(0, 1), &policy), Some(1));
+
+        // limits outside of changed bounds are brought within them
+        let narrower = Autoscaling {
+            max_limit: 3,
+            ..policy
+        };
+        assert_eq!(decide(Some(4), counts(2, 2), &narrower), Some(3));
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn adjust_concurrent(pool: Pool) -> Result<()> {
+        let log = Arc::new(ScalingLog::default());
+        let autoscaler = Autoscaler::new(
+            pool.clone(),
+            Arc::new(ConfigTarget::new(std::env::temp_dir().join("unused.toml"))),
+            Arc::new(ConfigCache::default()),
+            Arc::default(),
+            GitLabClient::default(),
+            Secrets::default(),
+            log.clone(),
+        );
+
+        // without job limits, concurrent is left alone
+        let mut runner = GitLabRunner::for_testing();
+        runner.create(&pool).await?;
+        assert!(!autoscaler.adjust_concurrent(counts(0, 0)).await?);
+
+        let mut other = GitLabRunner::for_testing();
+        other.set_url("https://gitlab.bmc-labs.com");
+        other.set_job_limit(3);
+        other.create(&pool).await?;
+        assert!(autoscaler.adjust_concurrent(counts(2, 1)).await?);
+        assert_eq!(
+            autoscaler.settings.load().render.concurrent,
+            NonZeroU32::new(3).ok_or("zero")?
+        );
+        assert!(!autoscaler.adjust_concurrent(counts(2, 1)).await?);
+
+        let settings = autoscaler.settings.load();
+        let report = log.report(&settings.autoscaling, settings.render.concurrent);
+        assert_eq!(
+            serde_json::to_value(&report)?["decisions"][0]["to"],
+            serde_json::json!(3)
+        );
+        assert_eq!(report.decisions.len(), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+//! Criterion benchmarks of compiling the `gitlab-runner` config from the database, at 10 to
+//! 10,000 runners. runrs is a binary, so `benches/` can't reach its internals; instead, the
+//! benchmarks are gated behind the `benchmarks` feature and run with `runrs --bench`, e.g. via
+//! `cargo run --release --features benchmarks -- --bench`. Criterion's own arguments, like a
+//! filter or `--save-baseline`, may follow.
+
+use atmosphere::Create as _;
+use criterion::{BenchmarkId, Criterion, Throughput};
+use serde_json::json;
+use sqlx::sqlite::SqlitePoolOptions;
+use tokio::runtime::Runtime;
+
+use crate::{
+    models::{GitLabRunner, GitLabRunnerConfig},
+    secrets::Secrets,
+    settings::RenderOptions,
+};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Numbers of runners to compile; runrs is meant to manage a few thousand of them.
+const RUNNER_COUNTS: [usize; 4] = [10, 100, 1_000, 10_000];
+
+pub fn run() -> Result<()> {
+    let runtime = Runtime::new()?;
+    let secrets = Secrets::default();
+    let options = RenderOptions::default();
+
+    let mut criterion = Criterion::default().configure_from_args();
+    let mut group = criterion.benchmark_group("compile");
+    for runners in RUNNER_COUNTS {
+        let pool = runtime.block_on(seeded(runners))?;
+        group.throughput(Throughput::Elements(runners as u64));
+
+        group.bench_with_input(BenchmarkId::from_parameter(runners), &pool, |b, pool| {
+            b.iter(|| runtime.block_on(GitLabRunnerConfig::compile(pool, &secrets, &options)))
+        });
+    }
+    group.finish();
+    criterion.final_summary();
+
+    Ok(())
+}
+
+/// An in-memory database holding `runners` runners.
+async fn seeded(runners: usize) -> Result<atmosphere::Pool> {
+    // a single connection, so that every query sees the same in-memory database
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await?;
+    crate::MIGRATOR.run(&pool).await?;
+
+    let mut tx = pool.begin().await?;
+    for idx in 0..runners {
+        let mut runner: GitLabRunner = serde_json::from_value(json!({
+            "id": idx,
+            "name": format!("runner-{idx}"),
+            "url": "https://gitlab.your-company.com",
+            "token": "glrt-0123456789_abcdefXYZ",
+            "token_obtained_at": "2024-07-01T09:00:00Z",
+            "docker_image": "alpine:3.20",
+        }))?;
+        runner.create(&mut *tx).await?;
+    }
+    tx.commit().await?;
+
+    Ok(pool)
+}
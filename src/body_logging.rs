@@ -0,0 +1,179 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{borrow::Cow, sync::Arc};
+
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    extract::{Request, State},
+    http::{header, HeaderMap},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use glrcfg::runner::redact_tokens;
+use serde_json::Value;
+
+use crate::{error::Error, settings::SettingsStore};
+
+/// Bodies are buffered in full to be logged; this matches axum's default request body limit.
+const BODY_BUFFER_LIMIT: usize = 2 * 1024 * 1024;
+
+/// Object keys whose string values are secrets, no matter what they look like.
+static SECRET_KEYS: &[&str] = &[
+    "access_token",
+    "authorization",
+    "password",
+    "private_token",
+    "registration_token",
+    "secret",
+    "token",
+];
+
+/// Logs request and response bodies at debug level, with secrets redacted and the output capped
+/// at the configured size. Streamed requests and responses are not buffered, and thus not logged.
+pub async fn log_bodies(
+    State(settings): State<Arc<SettingsStore>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let settings = settings.load();
+    if !settings.body_logging.enabled || !tracing::enabled!(tracing::Level::DEBUG) {
+        return next.run(request).await;
+    }
+    let max_bytes = settings.body_logging.max_bytes;
+
+    // buffering a streamed request would defeat its purpose, e.g. for large imports
+    if is_streamed(request.headers()) {
+        return next.run(request).await;
+    }
+
+    let (parts, body) = request.into_parts();
+    let bytes = match to_bytes(body, BODY_BUFFER_LIMIT).await {
+        Ok(bytes) => bytes,
+        Err(err) => return Error::bad_request(err).into_response(),
+    };
+    tracing::debug!(
+        method = %parts.method,
+        uri = %parts.uri,
+        body = %redact_body(&bytes, max_bytes),
+        "request body"
+    );
+
+    let response = next
+        .run(Request::from_parts(parts, Body::from(bytes)))
+        .await;
+
+    if is_streamed(response.headers()) {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let bytes = match to_bytes(body, BODY_BUFFER_LIMIT).await {
+        Ok(bytes) => bytes,
+        Err(err) => return Error::internal_error(err).into_response(),
+    };
+    tracing::debug!(
+        status = %parts.status,
+        body = %redact_body(&bytes, max_bytes),
+        "response body"
+    );
+
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+fn is_streamed(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| {
+            content_type == "application/x-ndjson" || content_type == "text/event-stream"
+        })
+}
+
+/// Renders a body for the logs: secrets in JSON bodies are replaced by their masked form (or
+/// `****`, if they don't look like tokens), tokens anywhere else are masked, and the result is
+/// truncated to `max_bytes`. Truncating last ensures that no token is cut short of being
+/// recognized.
+fn redact_body(bytes: &Bytes, max_bytes: usize) -> String {
+    if bytes.is_empty() {
+        return String::new();
+    }
+
+    let text = match serde_json::from_slice::<Value>(bytes) {
+        Ok(mut json) => {
+            redact_secret_keys(&mut json);
+            json.to_string()
+        }
+        Err(_) => String::from_utf8_lossy(bytes).into_owned(),
+    };
+    let mut text = redact_tokens(&text).into_owned();
+
+    if text.len() > max_bytes {
+        let mut end = max_bytes;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        text.push_str(&format!("... ({} bytes total)", bytes.len()));
+    }
+
+    text
+}
+
+fn redact_secret_keys(json: &mut Value) {
+    match json {
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                match value {
+                    Value::String(secret) if SECRET_KEYS.contains(&key.to_lowercase().as_str()) => {
+                        let redacted = match redact_tokens(secret) {
+                            // doesn't look like a token, so there's nothing worth keeping
+                            Cow::Borrowed(_) => "****".to_string(),
+                            Cow::Owned(redacted) => redacted,
+                        };
+                        *secret = redacted;
+                    }
+                    _ => redact_secret_keys(value),
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact_secret_keys),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Bytes;
+    use pretty_assertions::assert_eq;
+
+    use super::redact_body;
+
+    #[test]
+    fn redact_json_secrets() {
+        let body = Bytes::from(
+            r#"{"name":"usain-bolt","token":"glrt-0123456789_abcdefXYZ","nested":[{"password":"hunter2"}]}"#,
+        );
+        assert_eq!(
+            redact_body(&body, 1024),
+            r#"{"name":"usain-bolt","nested":[{"password":"****"}],"token":"glrt-****XYZ"}"#
+        );
+    }
+
+    #[test]
+    fn redact_tokens_in_text() {
+        let body = Bytes::from("invalid runner token `glrt-0123456789_abcdefXYZ`");
+        assert_eq!(
+            redact_body(&body, 1024),
+            "invalid runner token `glrt-****XYZ`"
+        );
+    }
+
+    #[test]
+    fn truncate_after_redacting() {
+        let body = Bytes::from("glrt-0123456789_abcdefXYZ and more");
+        assert_eq!(redact_body(&body, 12), "glrt-****XYZ... (34 bytes total)");
+
+        // truncation respects character boundaries
+        let body = Bytes::from("wärbl");
+        assert_eq!(redact_body(&body, 2), "w... (6 bytes total)");
+    }
+}
@@ -0,0 +1,98 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{sync::Arc, time::Duration};
+
+use arc_swap::ArcSwap;
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::error::Error;
+
+/// Maximum delay added to responses, in milliseconds.
+const MAX_LATENCY_MS: u64 = 60_000;
+
+/// Faults injected on purpose, e.g. to check that alerts fire and runbooks work, without breaking
+/// the config on disk or GitLab. Faults which are not given are turned off.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct FaultSet {
+    /// Config writes fail as if the disk were full; they are queued and retried as usual
+    pub config_write_failure: bool,
+    /// Calls to GitLab fail as if GitLab were unreachable
+    pub gitlab_outage: bool,
+    /// Milliseconds every API response is delayed by, at most 60000
+    #[schema(example = 2000)]
+    pub latency_ms: u64,
+}
+
+impl FaultSet {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// The faults currently injected, shared by the parts of runrs they affect.
+#[derive(Debug, Default)]
+pub struct Faults(ArcSwap<FaultSet>);
+
+impl Faults {
+    pub fn load(&self) -> FaultSet {
+        **self.0.load()
+    }
+
+    /// Replaces the injected faults.
+    pub fn replace(&self, faults: FaultSet) -> Result<(), Error> {
+        if faults.latency_ms > MAX_LATENCY_MS {
+            return Err(Error::invalid_argument(format!(
+                "latency_ms must be at most {MAX_LATENCY_MS}"
+            )));
+        }
+
+        if faults.is_empty() {
+            tracing::info!("chaos faults cleared");
+        } else {
+            tracing::warn!(?faults, "injecting chaos faults");
+        }
+        self.0.store(Arc::new(faults));
+        Ok(())
+    }
+
+    /// Fails if config writes are made to fail.
+    pub fn ensure_config_writable(&self) -> Result<(), Error> {
+        if self.load().config_write_failure {
+            return Err(Error::internal_error("simulated config write failure"));
+        }
+
+        Ok(())
+    }
+
+    /// Fails if GitLab is made unreachable.
+    pub fn ensure_gitlab_reachable(&self) -> Result<(), Error> {
+        if self.load().gitlab_outage {
+            return Err(Error::connection_failed(
+                "GitLab unreachable: simulated outage",
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Delays responses by the injected latency.
+pub async fn inject_latency(
+    State(faults): State<Arc<Faults>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let latency = faults.load().latency_ms;
+    if latency > 0 {
+        tokio::time::sleep(Duration::from_millis(latency)).await;
+    }
+
+    next.run(request).await
+}
@@ -0,0 +1,355 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{
+    collections::BTreeSet,
+    fmt::Write as _,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::Path,
+};
+
+use miette::IntoDiagnostic;
+
+use crate::{
+    app::{DEFAULT_CONFIG_PATH, DEFAULT_DATABASE_URL, DEFAULT_PORT},
+    listener,
+    settings::Settings,
+};
+
+/// The image of this version of runrs, as published on release.
+static IMAGE: &str = concat!("ghcr.io/bmc-labs/runrs:", env!("CARGO_PKG_VERSION"));
+static ENVIRONMENT_FILE: &str = "/etc/runrs/runrs.env";
+
+/// Environment variables runrs reads besides those of the [`Settings`], which are read at startup
+/// only. The set ones of both are rendered into the scaffolding, the others are left as
+/// commented-out hints.
+const STARTUP_ENV_VARS: &[&str] = &[
+    "LISTEN_ADDR",
+    "PORT",
+    "BIND_ADDRESS",
+    "REUSE_PORT",
+    "TLS_CERT_PATH",
+    "TLS_KEY_PATH",
+    "DATABASE_URL",
+    "CONFIG_PATH",
+    "CONFIG_TARGET_DIRS",
+    "RUST_LOG",
+    "LOG_FMT",
+    "SETTINGS_FILE",
+    "AUDIT_EXPORT_PATH",
+    "AUDIT_EXPORT_S3",
+    "METRICS_PUSHGATEWAY_URL",
+    "RUNNER_METRICS_URL",
+    "RELOAD_MODE",
+    "RELOAD_TARGET",
+    "VAULT_ADDR",
+    "AWS_REGION",
+    "BOOTSTRAP_RUNNER_URL",
+    "BOOTSTRAP_RUNNER_ID",
+    "BOOTSTRAP_RUNNER_NAME",
+    "BOOTSTRAP_DOCKER_IMAGE",
+];
+
+/// Environment variables holding secrets. Their values are never rendered; the unit reads them
+/// from an environment file, the compose service from the environment of `docker compose`.
+const SECRET_ENV_VARS: &[&str] = &[
+    "SECRET",
+    "VAULT_TOKEN",
+    "AWS_ACCESS_KEY_ID",
+    "AWS_SECRET_ACCESS_KEY",
+    "BOOTSTRAP_RUNNER_TOKEN",
+    "GITLAB_API_TOKENS",
+];
+
+/// The runtime configuration the deployment scaffolding is rendered from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deployment {
+    /// The variables of [`STARTUP_ENV_VARS`] and the settings, with their values if set
+    env: Vec<(String, Option<String>)>,
+}
+
+impl Deployment {
+    /// Reads the runtime configuration from the environment.
+    pub fn from_env() -> Self {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Self {
+        let keys = STARTUP_ENV_VARS
+            .iter()
+            .map(|key| key.to_string())
+            .chain(Settings::env_vars());
+
+        Self {
+            env: keys
+                .map(|key| {
+                    let value = lookup(&key);
+                    (key, value)
+                })
+                .collect(),
+        }
+    }
+
+    fn var(&self, key: &str) -> Option<&str> {
+        self.env
+            .iter()
+            .find(|(name, _)| *name == key)
+            .and_then(|(_, value)| value.as_deref())
+    }
+
+    /// Address runrs listens on; the default one if the variables are invalid, since runrs
+    /// refuses to start with those anyway.
+    fn bind_address(&self) -> SocketAddr {
+        listener::bind_address(|key| self.var(key).map(str::to_string))
+            .unwrap_or_else(|_| SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), DEFAULT_PORT))
+    }
+
+    fn port(&self) -> u16 {
+        self.bind_address().port()
+    }
+
+    /// Directories holding the database and the `gitlab-runner` config, which have to persist.
+    fn data_dirs(&self) -> BTreeSet<&str> {
+        [
+            self.var("DATABASE_URL").unwrap_or(DEFAULT_DATABASE_URL),
+            self.var("CONFIG_PATH").unwrap_or(DEFAULT_CONFIG_PATH),
+        ]
+        .into_iter()
+        .filter_map(|path| Path::new(path).parent()?.to_str())
+        .collect()
+    }
+
+    /// Renders a systemd unit running the `runrs` binary at `exec_start`.
+    pub fn systemd_unit(&self, exec_start: &Path) -> String {
+        let mut unit = String::new();
+
+        unit.push_str("[Unit]\n");
+        unit.push_str("Description=runrs - manage GitLab Runners via REST\n");
+        unit.push_str("Documentation=https://github.com/bmc-labs/runrs\n");
+        unit.push_str("Wants=network-online.target\n");
+        unit.push_str("After=network-online.target\n\n");
+
+        unit.push_str("[Service]\n");
+        let _ = writeln!(unit, "ExecStart={}", exec_start.display());
+        unit.push_str("Restart=always\n");
+        let _ = writeln!(
+            unit,
+            "# secrets ({}) go into this file, readable by root only",
+            SECRET_ENV_VARS.join(", ")
+        );
+        let _ = writeln!(unit, "EnvironmentFile=-{ENVIRONMENT_FILE}");
+        for (key, value) in &self.env {
+            match value {
+                Some(value) => {
+                    let _ = writeln!(unit, "Environment=\"{key}={}\"", escape_systemd(value));
+                }
+                None => {
+                    let _ = writeln!(unit, "#Environment=\"{key}=\"");
+                }
+            }
+        }
+        unit.push('\n');
+
+        unit.push_str("[Install]\n");
+        unit.push_str("WantedBy=multi-user.target\n");
+        unit
+    }
+
+    /// Renders a systemd socket unit listening on the bind address, to be installed next to the unit
+    /// of [`Deployment::systemd_unit`] with the same name. systemd then passes the socket to runrs,
+    /// so restarts don't refuse connections.
+    pub fn systemd_socket(&self) -> String {
+        let mut socket = String::new();
+
+        socket.push_str("[Unit]\n");
+        socket.push_str("Description=runrs - manage GitLab Runners via REST (socket)\n\n");
+
+        socket.push_str("[Socket]\n");
+        let _ = writeln!(socket, "ListenStream={}", self.bind_address());
+        socket.push_str("NoDelay=true\n\n");
+
+        socket.push_str("[Install]\n");
+        socket.push_str("WantedBy=sockets.target\n");
+        socket
+    }
+
+    /// Renders a compose file with a `runrs` service, health checked via `/readyz`.
+    pub fn compose(&self) -> String {
+        let data_dirs = self.data_dirs();
+        let mut compose = String::new();
+
+        compose.push_str("volumes:\n");
+        for volume in (0..data_dirs.len()).map(volume_name) {
+            let _ = writeln!(compose, "  {volume}:");
+        }
+        compose.push('\n');
+
+        compose.push_str("services:\n");
+        compose.push_str("  runrs:\n");
+        let _ = writeln!(compose, "    image: {IMAGE}");
+        compose.push_str("    restart: always\n");
+        compose.push_str("    environment:\n");
+        for (key, value) in &self.env {
+            match value {
+                Some(value) => {
+                    let _ = writeln!(compose, "      {key}: \"{}\"", escape_compose(value));
+                }
+                None => {
+                    let _ = writeln!(compose, "      # {key}: \"\"");
+                }
+            }
+        }
+        for key in SECRET_ENV_VARS {
+            let _ = writeln!(compose, "      {key}: \"${{{key}:-}}\"");
+        }
+        compose.push_str("    ports:\n");
+        let _ = writeln!(compose, "      - {0}:{0}", self.port());
+        compose.push_str("    volumes:\n");
+        for (index, dir) in data_dirs.iter().enumerate() {
+            let _ = writeln!(compose, "      - {}:{dir}", volume_name(index));
+        }
+        compose.push_str("    healthcheck:\n");
+        compose.push_str("      test: [\"CMD\", \"runrs\", \"--healthcheck\"]\n");
+        compose.push_str("      interval: 30s\n");
+        compose.push_str("      timeout: 5s\n");
+        compose.push_str("      retries: 3\n");
+        compose.push_str("      start_period: 10s\n");
+        compose
+    }
+
+    /// Requests `/readyz` from the runrs listening on this host, for container health checks.
+    pub async fn healthcheck(&self) -> miette::Result<()> {
+        let mut addr = self.bind_address();
+        // runrs listening on all addresses is reachable via loopback
+        if addr.ip().is_unspecified() {
+            addr.set_ip(match addr.ip() {
+                IpAddr::V4(_) => Ipv4Addr::LOCALHOST.into(),
+                IpAddr::V6(_) => Ipv6Addr::LOCALHOST.into(),
+            });
+        }
+        // the certificate is issued for the public name, not for the loopback address
+        let tls = self.var("TLS_CERT_PATH").is_some();
+        let scheme = if tls { "https" } else { "http" };
+        let url = format!("{scheme}://{addr}/readyz");
+        let client = reqwest::Client::builder()
+            .danger_accept_invalid_certs(tls)
+            .build()
+            .into_diagnostic()?;
+        let response = client.get(&url).send().await.into_diagnostic()?;
+
+        if !response.status().is_success() {
+            miette::bail!("{url} returned {}", response.status());
+        }
+
+        Ok(())
+    }
+}
+
+fn volume_name(index: usize) -> String {
+    match index {
+        0 => "data".to_string(),
+        n => format!("data-{n}"),
+    }
+}
+
+/// Escapes a value for a quoted `Environment=` assignment; `%` starts a specifier in systemd.
+fn escape_systemd(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+}
+
+/// Escapes a value for a double-quoted YAML string; `$` starts an interpolation in compose.
+fn escape_compose(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('$', "$$")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use pretty_assertions::assert_eq;
+
+    use super::Deployment;
+
+    fn deployment() -> Deployment {
+        Deployment::from_lookup(|key| match key {
+            "BIND_ADDRESS" => Some("0.0.0.0:8080".to_string()),
+            "DATABASE_URL" => Some("/var/lib/runrs/runrs.sqlite".to_string()),
+            "FREEZE_WINDOWS" => Some("Fri 18:00-Mon 06:00".to_string()),
+            "EVENTS_WEBHOOK_URL" => Some("https://hooks.example.com/?a=%41&b=$B".to_string()),
+            "SECRET" => Some("do-not-render".to_string()),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn render_systemd_unit() {
+        let unit = deployment().systemd_unit(Path::new("/usr/local/bin/runrs"));
+
+        assert!(unit.contains("ExecStart=/usr/local/bin/runrs\n"));
+        assert!(unit.contains("Environment=\"BIND_ADDRESS=0.0.0.0:8080\"\n"));
+        assert!(unit.contains("Environment=\"FREEZE_WINDOWS=Fri 18:00-Mon 06:00\"\n"));
+        assert!(unit.contains(
+            "Environment=\"EVENTS_WEBHOOK_URL=https://hooks.example.com/?a=%%41&b=$B\"\n"
+        ));
+        assert!(unit.contains("#Environment=\"QUOTA_MAX_RUNNERS=\"\n"));
+        assert!(unit.contains("#Environment=\"ROLLOUT_CANARY=\"\n"));
+        assert!(unit.contains("EnvironmentFile=-/etc/runrs/runrs.env\n"));
+        assert!(!unit.contains("do-not-render"));
+    }
+
+    #[test]
+    fn render_systemd_socket() {
+        let socket = deployment().systemd_socket();
+
+        assert!(socket.contains("[Socket]\nListenStream=0.0.0.0:8080\n"));
+        assert!(socket.contains("WantedBy=sockets.target\n"));
+
+        let socket = Deployment::from_lookup(|_| None).systemd_socket();
+        assert!(socket.contains("ListenStream=0.0.0.0:3000\n"));
+
+        let socket = Deployment::from_lookup(|key| match key {
+            "LISTEN_ADDR" => Some("127.0.0.1".to_string()),
+            "PORT" => Some("8081".to_string()),
+            _ => None,
+        })
+        .systemd_socket();
+        assert!(socket.contains("ListenStream=127.0.0.1:8081\n"));
+    }
+
+    #[test]
+    fn render_compose() {
+        let compose = deployment().compose();
+
+        assert!(compose.contains("      BIND_ADDRESS: \"0.0.0.0:8080\"\n"));
+        assert!(compose
+            .contains("      EVENTS_WEBHOOK_URL: \"https://hooks.example.com/?a=%41&b=$$B\"\n"));
+        assert!(compose.contains("      # QUOTA_MAX_RUNNERS: \"\"\n"));
+        assert!(compose.contains(concat!(
+            "    image: ghcr.io/bmc-labs/runrs:",
+            env!("CARGO_PKG_VERSION"),
+            "\n"
+        )));
+        assert!(compose.contains("      SECRET: \"${SECRET:-}\"\n"));
+        assert!(!compose.contains("do-not-render"));
+        assert!(compose.contains("      - 8080:8080\n"));
+        assert!(compose.contains("      - data:/etc/gitlab-runner\n"));
+        assert!(compose.contains("      - data-1:/var/lib/runrs\n"));
+        assert!(compose.contains("      test: [\"CMD\", \"runrs\", \"--healthcheck\"]\n"));
+    }
+
+    #[test]
+    fn defaults_without_environment() {
+        let deployment = Deployment::from_lookup(|_| None);
+
+        assert_eq!(deployment.port(), 3000);
+        assert_eq!(
+            deployment.data_dirs().into_iter().collect::<Vec<_>>(),
+            vec!["/etc/gitlab-runner", "/etc/runrs"]
+        );
+    }
+}
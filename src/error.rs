@@ -12,6 +12,8 @@ use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use utoipa::{ToResponse, ToSchema};
 
+use crate::audit::DenialReason;
+
 #[derive(Debug, PartialEq, Eq, Error, Diagnostic, Serialize, Deserialize, ToResponse, ToSchema)]
 #[non_exhaustive]
 pub enum ErrorType {
@@ -23,8 +25,14 @@ pub enum ErrorType {
     AlreadyExists,
     #[error("access forbidden")]
     Forbidden,
+    #[error("quota exceeded")]
+    QuotaExceeded,
     #[error("request effects no changes")]
     Unchanged,
+    #[error("change freeze in effect")]
+    Locked,
+    #[error("precondition failed")]
+    PreconditionFailed,
     #[error("runner not found")]
     NotFound,
     #[error("bad request")]
@@ -37,17 +45,135 @@ pub enum ErrorType {
     Other,
 }
 
+impl ErrorType {
+    /// All error types, in the order of the error code catalogue.
+    pub const ALL: [Self; 13] = [
+        Self::ConnectionFailed,
+        Self::InvalidArgument,
+        Self::AlreadyExists,
+        Self::Forbidden,
+        Self::QuotaExceeded,
+        Self::Unchanged,
+        Self::Locked,
+        Self::PreconditionFailed,
+        Self::NotFound,
+        Self::BadRequest,
+        Self::InternalError,
+        Self::Unimplemented,
+        Self::Other,
+    ];
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            Self::ConnectionFailed => ErrorCode::ConnectionFailed,
+            Self::InvalidArgument => ErrorCode::InvalidArgument,
+            Self::AlreadyExists => ErrorCode::AlreadyExists,
+            Self::Forbidden => ErrorCode::Forbidden,
+            Self::QuotaExceeded => ErrorCode::QuotaExceeded,
+            Self::Unchanged => ErrorCode::Unchanged,
+            Self::Locked => ErrorCode::Locked,
+            Self::PreconditionFailed => ErrorCode::PreconditionFailed,
+            Self::NotFound => ErrorCode::NotFound,
+            Self::BadRequest => ErrorCode::BadRequest,
+            Self::InternalError => ErrorCode::InternalError,
+            Self::Unimplemented => ErrorCode::Unimplemented,
+            Self::Other => ErrorCode::Other,
+        }
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidArgument | Self::AlreadyExists | Self::BadRequest => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::NotFound => StatusCode::NOT_FOUND,
+            Self::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+            Self::Forbidden | Self::QuotaExceeded => StatusCode::FORBIDDEN,
+            Self::Unchanged => StatusCode::NO_CONTENT,
+            Self::Locked => StatusCode::LOCKED,
+            Self::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            Self::ConnectionFailed | Self::InternalError | Self::Other => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+}
+
+/// Stable, machine-readable identity of an error. Codes are never changed or reused, so clients
+/// can branch on them instead of parsing error messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub enum ErrorCode {
+    #[serde(rename = "RUNRS-E-CONNECTION-FAILED")]
+    ConnectionFailed,
+    #[serde(rename = "RUNRS-E-INVALID-ARGUMENT")]
+    InvalidArgument,
+    #[serde(rename = "RUNRS-E-ALREADY-EXISTS")]
+    AlreadyExists,
+    #[serde(rename = "RUNRS-E-FORBIDDEN")]
+    Forbidden,
+    #[serde(rename = "RUNRS-E-QUOTA-EXCEEDED")]
+    QuotaExceeded,
+    #[serde(rename = "RUNRS-E-UNCHANGED")]
+    Unchanged,
+    #[serde(rename = "RUNRS-E-LOCKED")]
+    Locked,
+    #[serde(rename = "RUNRS-E-PRECONDITION-FAILED")]
+    PreconditionFailed,
+    #[serde(rename = "RUNRS-E-NOT-FOUND")]
+    NotFound,
+    #[serde(rename = "RUNRS-E-BAD-REQUEST")]
+    BadRequest,
+    #[serde(rename = "RUNRS-E-INTERNAL-ERROR")]
+    InternalError,
+    #[serde(rename = "RUNRS-E-UNIMPLEMENTED")]
+    Unimplemented,
+    #[serde(rename = "RUNRS-E-OTHER")]
+    Other,
+}
+
+/// Entry of the error code catalogue.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorCodeInfo {
+    code: ErrorCode,
+    err_type: ErrorType,
+    /// HTTP status code of responses carrying the error
+    #[schema(example = 400)]
+    status: u16,
+    #[schema(example = "runner already exists")]
+    description: String,
+}
+
+impl ErrorCodeInfo {
+    /// Returns the catalogue of all error codes.
+    pub fn catalogue() -> Vec<Self> {
+        ErrorType::ALL
+            .into_iter()
+            .map(|err_type| Self {
+                code: err_type.code(),
+                status: err_type.status_code().as_u16(),
+                description: err_type.to_string(),
+                err_type,
+            })
+            .collect()
+    }
+}
+
 #[derive(Debug, Error, Serialize, Deserialize, ToSchema)]
 #[error("API Error: {msg}")]
 pub struct Error {
     pub err_type: ErrorType,
+    pub code: ErrorCode,
     pub msg: String,
 }
 
 impl Error {
     pub fn new(err_type: ErrorType) -> Self {
         let msg = err_type.to_string();
-        Self { err_type, msg }
+        Self {
+            code: err_type.code(),
+            err_type,
+            msg,
+        }
     }
 
     pub fn with_description<T: Display>(mut self, desc: T) -> Self {
@@ -72,10 +198,22 @@ impl Error {
         Self::new(ErrorType::Forbidden).with_description(desc)
     }
 
+    pub fn quota_exceeded<T: Display>(desc: T) -> Self {
+        Self::new(ErrorType::QuotaExceeded).with_description(desc)
+    }
+
     pub fn unchanged<T: Display>(desc: T) -> Self {
         Self::new(ErrorType::Unchanged).with_description(desc)
     }
 
+    pub fn locked<T: Display>(desc: T) -> Self {
+        Self::new(ErrorType::Locked).with_description(desc)
+    }
+
+    pub fn precondition_failed<T: Display>(desc: T) -> Self {
+        Self::new(ErrorType::PreconditionFailed).with_description(desc)
+    }
+
     pub fn not_found<T: Display>(desc: T) -> Self {
         Self::new(ErrorType::NotFound).with_description(desc)
     }
@@ -157,20 +295,16 @@ impl From<atmosphere::Error> for Error {
 
 impl From<Error> for Response {
     fn from(err: Error) -> Self {
-        let status_code = match err.err_type {
-            ErrorType::InvalidArgument | ErrorType::AlreadyExists | ErrorType::BadRequest => {
-                StatusCode::BAD_REQUEST
-            }
-            ErrorType::NotFound => StatusCode::NOT_FOUND,
-            ErrorType::Unimplemented => StatusCode::NOT_IMPLEMENTED,
-            ErrorType::Forbidden => StatusCode::FORBIDDEN,
-            ErrorType::Unchanged => StatusCode::NO_CONTENT,
-            ErrorType::ConnectionFailed | ErrorType::InternalError | ErrorType::Other => {
-                StatusCode::INTERNAL_SERVER_ERROR
-            }
-        };
+        // denials, e.g. for a missing scope, are audited with their reason; an exceeded quota
+        // is no denial, the request was authorized, just too much
+        let status = err.err_type.status_code();
+        let reason = matches!(err.err_type, ErrorType::Forbidden).then(|| err.msg.clone());
 
-        (status_code, Json(err)).into_response()
+        let mut response = (status, Json(err)).into_response();
+        if let Some(reason) = reason {
+            response.extensions_mut().insert(DenialReason(reason));
+        }
+        response
     }
 }
 
@@ -195,4 +329,17 @@ mod tests {
         let err = err.with_description(desc);
         assert_eq!(err.msg, format!("unimplemented: {desc}"));
     }
+
+    #[test]
+    fn error_codes_are_unique() {
+        let codes: std::collections::HashSet<_> = ErrorType::ALL
+            .iter()
+            .map(|err_type| serde_json::to_string(&err_type.code()).unwrap())
+            .collect();
+        assert_eq!(codes.len(), ErrorType::ALL.len());
+
+        let err = serde_json::to_value(Error::already_exists("runner 42"))
+            .expect("error is serializable");
+        assert_eq!(err["code"], "RUNRS-E-ALREADY-EXISTS");
+    }
 }
@@ -0,0 +1,387 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{fmt, str::FromStr, sync::Arc};
+
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Datelike, NaiveTime, TimeDelta, Timelike, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::{Claims, Scope},
+    error::Error,
+    settings::SettingsStore,
+};
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+const MINUTES_PER_WEEK: u32 = 7 * MINUTES_PER_DAY;
+
+/// A point in the week, in UTC, e.g. `Fri 18:00`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct WeeklyTime {
+    weekday: Weekday,
+    time: NaiveTime,
+}
+
+impl WeeklyTime {
+    fn minute_of_week(&self) -> u32 {
+        self.weekday.num_days_from_monday() * MINUTES_PER_DAY
+            + self.time.hour() * 60
+            + self.time.minute()
+    }
+}
+
+impl FromStr for WeeklyTime {
+    type Err = String;
+
+    fn from_str(weekly_time: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("'{weekly_time}' is not a day and time like 'Fri 18:00'");
+
+        let (weekday, time) = weekly_time.trim().split_once(' ').ok_or_else(invalid)?;
+        Ok(Self {
+            weekday: weekday.parse().map_err(|_| invalid())?,
+            time: NaiveTime::parse_from_str(time.trim(), "%H:%M").map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl fmt::Display for WeeklyTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.weekday, self.time.format("%H:%M"))
+    }
+}
+
+/// A weekly recurring change freeze, e.g. `Fri 18:00-Mon 06:00`, in UTC. Windows may span the
+/// end of the week.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct FreezeWindow {
+    start: WeeklyTime,
+    end: WeeklyTime,
+}
+
+impl FreezeWindow {
+    /// Returns `true` if `at` lies within the window; the end is exclusive.
+    pub fn contains(&self, at: DateTime<Utc>) -> bool {
+        let at = WeeklyTime {
+            weekday: at.weekday(),
+            time: at.time(),
+        }
+        .minute_of_week();
+        let (start, end) = (self.start.minute_of_week(), self.end.minute_of_week());
+
+        if start < end {
+            (start..end).contains(&at)
+        } else {
+            at >= start || at < end
+        }
+    }
+
+    /// Returns the first time after `after` at which the window starts or ends.
+    pub fn next_boundary(&self, after: DateTime<Utc>) -> DateTime<Utc> {
+        let now = WeeklyTime {
+            weekday: after.weekday(),
+            time: after.time(),
+        }
+        .minute_of_week();
+        // a boundary right now is a week away
+        let minutes_until = |boundary: WeeklyTime| {
+            let minutes = (boundary.minute_of_week() + MINUTES_PER_WEEK - now) % MINUTES_PER_WEEK;
+            if minutes == 0 {
+                MINUTES_PER_WEEK
+            } else {
+                minutes
+            }
+        };
+        let minutes = minutes_until(self.start).min(minutes_until(self.end));
+
+        let minute = after
+            .with_second(0)
+            .and_then(|at| at.with_nanosecond(0))
+            .unwrap_or(after);
+        minute + TimeDelta::minutes(minutes.into())
+    }
+}
+
+impl FromStr for FreezeWindow {
+    type Err = String;
+
+    fn from_str(window: &str) -> Result<Self, Self::Err> {
+        let (start, end) = window
+            .split_once('-')
+            .ok_or_else(|| format!("'{window}' is not a window like 'Fri 18:00-Mon 06:00'"))?;
+        let window = Self {
+            start: start.parse()?,
+            end: end.parse()?,
+        };
+
+        if window.start == window.end {
+            return Err(format!("freeze window '{window}' is empty"));
+        }
+
+        Ok(window)
+    }
+}
+
+impl TryFrom<String> for FreezeWindow {
+    type Error = String;
+
+    fn try_from(window: String) -> Result<Self, Self::Error> {
+        window.parse()
+    }
+}
+
+impl From<FreezeWindow> for String {
+    fn from(window: FreezeWindow) -> Self {
+        window.to_string()
+    }
+}
+
+impl fmt::Display for FreezeWindow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+/// The configured change freezes; given in the environment as a comma-separated list.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct FreezeWindows(Vec<FreezeWindow>);
+
+impl FreezeWindows {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the window `at` lies within, if any.
+    pub fn active_at(&self, at: DateTime<Utc>) -> Option<&FreezeWindow> {
+        self.0.iter().find(|window| window.contains(at))
+    }
+
+    /// Returns `true` if changes are frozen right now, in which case `subsystem`, a background
+    /// task changing runners, leaves them alone until the freeze is over.
+    pub fn defers(&self, subsystem: &str) -> bool {
+        let Some(window) = self.active_at(Utc::now()) else {
+            return false;
+        };
+
+        tracing::debug!(subsystem, %window, "changes are frozen, not changing runners");
+        true
+    }
+}
+
+impl FromStr for FreezeWindows {
+    type Err = String;
+
+    fn from_str(windows: &str) -> Result<Self, Self::Err> {
+        windows
+            .split(',')
+            .filter(|window| !window.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+/// Rejects mutating requests with `423 Locked` while a change freeze is in effect, unless the
+/// token carries the `freeze_override` scope. Runs after authentication, which provides the
+/// token's claims.
+pub async fn enforce_freeze(
+    State(settings): State<Arc<SettingsStore>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) {
+        return next.run(request).await;
+    }
+
+    let settings = settings.load();
+    let Some(window) = settings.freeze_windows.active_at(Utc::now()) else {
+        return next.run(request).await;
+    };
+
+    let overridden = request
+        .extensions()
+        .get::<Claims>()
+        .is_some_and(|claims| claims.has_scope(Scope::FreezeOverride));
+    if !overridden {
+        return Error::locked(format!(
+            "changes are frozen during {window} (UTC); a token with the freeze_override scope \
+             is required"
+        ))
+        .into_response();
+    }
+
+    tracing::warn!(
+        %window,
+        method = %request.method(),
+        path = request.uri().path(),
+        "overriding change freeze"
+    );
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{
+        body::Body,
+        http::{self, Request, StatusCode},
+    };
+    use chrono::{DateTime, TimeDelta, Utc};
+    use pretty_assertions::assert_eq;
+    use tower::ServiceExt; // for `call`, `oneshot`, and `ready`
+
+    use super::{FreezeWindow, FreezeWindows};
+    use crate::{
+        app::{router, AppState},
+        auth::{self, AuthMode, Scope},
+        models::GitLabRunner,
+        settings::{Settings, SettingsStore},
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    fn at(timestamp: &str) -> DateTime<Utc> {
+        timestamp.parse().expect("given timestamp is valid")
+    }
+
+    /// A freeze window from an hour ago to an hour from now.
+    fn around_now() -> String {
+        let now = Utc::now();
+        let (start, end) = (now - TimeDelta::hours(1), now + TimeDelta::hours(1));
+        format!(
+            "{} {}-{} {}",
+            start.format("%a"),
+            start.format("%H:%M"),
+            end.format("%a"),
+            end.format("%H:%M")
+        )
+    }
+
+    #[test]
+    fn parse_windows() -> Result<()> {
+        let windows: FreezeWindows = "Fri 18:00-Mon 06:00, wed 12:00 - Wed 13:30".parse()?;
+        assert_eq!(
+            serde_json::to_value(&windows)?,
+            serde_json::json!(["Fri 18:00-Mon 06:00", "Wed 12:00-Wed 13:30"])
+        );
+        assert_eq!("".parse::<FreezeWindows>()?, FreezeWindows::default());
+
+        for invalid in [
+            "Fri 18:00",
+            "Fri-Mon",
+            "Fri 25:00-Mon 06:00",
+            "Fri 18:00-Fri 18:00",
+        ] {
+            assert!(invalid.parse::<FreezeWindow>().is_err(), "{invalid}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn windows_spanning_the_week() -> Result<()> {
+        let weekend: FreezeWindow = "Fri 18:00-Mon 06:00".parse()?;
+        // 2024-06-21 is a Friday
+        assert!(!weekend.contains(at("2024-06-21T17:59:00Z")));
+        assert!(weekend.contains(at("2024-06-21T18:00:00Z")));
+        assert!(weekend.contains(at("2024-06-23T12:00:00Z")));
+        assert!(weekend.contains(at("2024-06-24T05:59:00Z")));
+        assert!(!weekend.contains(at("2024-06-24T06:00:00Z")));
+
+        let lunch: FreezeWindow = "Wed 12:00-Wed 13:00".parse()?;
+        assert!(lunch.contains(at("2024-06-19T12:30:00Z")));
+        assert!(!lunch.contains(at("2024-06-20T12:30:00Z")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn subsystems_defer_during_freeze() -> Result<()> {
+        let window = around_now();
+
+        assert!(window.parse::<FreezeWindows>()?.defers("autoscaler"));
+        assert!(!FreezeWindows::default().defers("autoscaler"));
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    #[tracing_test::traced_test]
+    async fn mutations_locked_during_freeze(pool: atmosphere::Pool) -> Result<()> {
+        let secret = "test-secret".to_string();
+        let mut app_state = AppState::for_testing(pool);
+
+        let window = around_now();
+        app_state.settings = Arc::new(SettingsStore::new(Settings {
+            freeze_windows: window.parse()?,
+            ..Default::default()
+        }));
+
+        let runner = GitLabRunner::for_testing();
+        let create = |token: &str| -> Result<Request<Body>> {
+            Ok(Request::builder()
+                .method(http::Method::POST)
+                .uri("/gitlab-runners")
+                .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(Body::from(serde_json::to_string(&runner)?))?)
+        };
+
+        let token = auth::encode_token(&secret, Vec::new())?;
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(create(&token)?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::LOCKED);
+
+        // reads are not affected
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(
+                Request::builder()
+                    .uri("/gitlab-runners/list")
+                    .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let token = auth::encode_token(&secret, vec![Scope::FreezeOverride])?;
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(create(&token)?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        // without authentication, every request may override the freeze
+        app_state.settings = Arc::new(SettingsStore::new(Settings {
+            auth_mode: AuthMode::Disabled,
+            freeze_windows: window.parse()?,
+            ..Default::default()
+        }));
+        let response = router(String::new(), app_state.clone())
+            .await
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::DELETE)
+                    .uri(format!("/gitlab-runners/{}", runner.uuid()))
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        std::fs::remove_file(&*app_state.config_target.load())?;
+
+        Ok(())
+    }
+}
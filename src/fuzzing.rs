@@ -0,0 +1,220 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+//! Property-based robustness harness for the runner API. It generates arbitrary, mostly invalid
+//! `GitLabRunner` payloads - odd Unicode in names, garbage URLs and tokens, wrong types - and
+//! checks that the service never panics or fails with a server error, and that the config it
+//! writes is valid TOML no matter what it accepted.
+//!
+//! The harness is gated behind the `fuzzing` feature. Packagers can run it against their build
+//! with `runrs --fuzz`, developers with `cargo test --features fuzzing`. Set `PROPTEST_CASES` to
+//! run more cases.
+
+use axum::{
+    body::Body,
+    http::{self, Request},
+};
+use proptest::{collection, option, prelude::*, test_runner::TestRunner};
+use serde_json::{json, Map, Value};
+use sqlx::sqlite::SqlitePoolOptions;
+use tokio::runtime::Runtime;
+use tower::ServiceExt; // for `oneshot`
+
+use crate::{
+    app::{router, AppState},
+    auth,
+};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+static SECRET: &str = "fuzzing-secret";
+static DATE_TIME_REGEX_STR: &str =
+    "20[0-9]{2}-[01][0-9]-[0-3][0-9]T[0-2][0-9]:[0-5][0-9]:[0-5][0-9]Z";
+
+/// Strings biased towards the kinds of input which broke things before: control characters,
+/// combining characters, right-to-left text, emoji and characters outside the BMP.
+pub fn nasty_string() -> impl Strategy<Value = String> {
+    prop_oneof![
+        any::<String>(),
+        "[\\x00-\\x1f\\x7f]{0,8}",
+        "[ \\t\\n]{0,4}[\\p{Mn}\\p{Cf}\\p{Zs}]{0,16}[ \\t\\n]{0,4}",
+        "[\\p{Arabic}\\p{Hebrew}\\u{202e}]{1,16}",
+        "[\\u{1f300}-\\u{1faff}\\u{10000}-\\u{10ffff}]{1,16}",
+        "[\"'\\\\\\[\\]=#.]{1,16}",
+    ]
+}
+
+fn json_value() -> impl Strategy<Value = Value> {
+    prop_oneof![
+        Just(Value::Null),
+        any::<bool>().prop_map(Value::from),
+        any::<i64>().prop_map(Value::from),
+        any::<f64>().prop_map(|n| json!(n)),
+        nasty_string().prop_map(Value::from),
+    ]
+}
+
+/// Values for a field which are well-formed most of the time, and arbitrary otherwise.
+fn field(valid: impl Strategy<Value = Value> + 'static) -> impl Strategy<Value = Option<Value>> {
+    option::weighted(0.9, prop_oneof![3 => valid, 1 => json_value()])
+}
+
+/// Arbitrary `GitLabRunner` payloads, as they might be sent to `POST /gitlab-runners`.
+pub fn runner_payload() -> impl Strategy<Value = Value> {
+    (
+        field(any::<u32>().prop_map(Value::from)),
+        field(nasty_string().prop_map(Value::from)),
+        field(
+            prop_oneof![
+                Just("https://gitlab.your-company.com".to_string()),
+                "https?://[a-z0-9.-]{1,32}(:[0-9]{1,5})?(/[^ ]{0,16})?",
+            ]
+            .prop_map(Value::from),
+        ),
+        field("glrtr?-[\\w-]{16,32}".prop_map(Value::from)),
+        field(DATE_TIME_REGEX_STR.prop_map(Value::from)),
+        field(nasty_string().prop_map(Value::from)),
+        field(any::<bool>().prop_map(Value::from)),
+        field(
+            collection::btree_map(nasty_string(), nasty_string(), 0..4)
+                .prop_map(|labels| json!(labels)),
+        ),
+        field(nasty_string().prop_map(Value::from)),
+        collection::vec((nasty_string(), json_value()), 0..3),
+    )
+        .prop_map(
+            |(
+                id,
+                name,
+                url,
+                token,
+                token_obtained_at,
+                docker_image,
+                privileged,
+                labels,
+                notes,
+                extra,
+            )| {
+                let mut payload = Map::new();
+                let fields = [
+                    ("id", id),
+                    ("name", name),
+                    ("url", url),
+                    ("token", token),
+                    ("token_obtained_at", token_obtained_at),
+                    ("docker_image", docker_image),
+                    ("privileged", privileged),
+                    ("labels", labels),
+                    ("notes", notes),
+                ];
+                for (key, value) in fields {
+                    if let Some(value) = value {
+                        payload.insert(key.to_string(), value);
+                    }
+                }
+                payload.extend(extra);
+                Value::Object(payload)
+            },
+        )
+}
+
+/// Sends the body to `POST /gitlab-runners` and checks the invariants which must hold for any
+/// input: no server error, and a config file which is valid TOML and contains every runner.
+pub async fn check_create(
+    app_state: &AppState,
+    body: String,
+) -> std::result::Result<(), TestCaseError> {
+    let token = auth::encode_token(SECRET, vec![auth::Scope::Admin])
+        .map_err(|err| TestCaseError::fail(err.to_string()))?;
+
+    let request = Request::builder()
+        .method(http::Method::POST)
+        .uri("/gitlab-runners")
+        .header(http::header::AUTHORIZATION, format!("Bearer {token}"))
+        .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+        .body(Body::from(body))
+        .map_err(|err| TestCaseError::fail(err.to_string()))?;
+
+    let response = router(SECRET.to_string(), app_state.clone())
+        .await
+        .oneshot(request)
+        .await
+        .map_err(|err| TestCaseError::fail(err.to_string()))?;
+    prop_assert!(
+        !response.status().is_server_error(),
+        "server error: {}",
+        response.status()
+    );
+
+    if !response.status().is_success() {
+        return Ok(());
+    }
+
+    let config_toml = std::fs::read_to_string(&*app_state.config_target.load())
+        .map_err(|err| TestCaseError::fail(err.to_string()))?;
+    let config: toml::Table = toml::from_str(&config_toml)
+        .map_err(|err| TestCaseError::fail(format!("invalid TOML: {err}\n{config_toml}")))?;
+
+    let runners: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM gitlab_runners")
+        .fetch_one(&app_state.pool)
+        .await
+        .map_err(|err| TestCaseError::fail(err.to_string()))?;
+    let configured = config
+        .get("runners")
+        .and_then(toml::Value::as_array)
+        .map_or(0, Vec::len);
+    prop_assert_eq!(configured as i64, runners);
+
+    Ok(())
+}
+
+/// Runs the harness for arbitrary runner payloads and arbitrary bodies against an in-memory
+/// database; fails with the minimal failing input if an invariant doesn't hold.
+pub fn run() -> Result<()> {
+    check(runner_payload(), |payload| payload.to_string())?;
+    check(nasty_string(), |body| body)
+}
+
+fn check<S>(strategy: S, body: impl Fn(S::Value) -> String) -> Result<()>
+where
+    S: Strategy,
+    S::Value: Send + Sync + 'static,
+{
+    let runtime = Runtime::new()?;
+    let app_state = app_state(&runtime)?;
+
+    let result = TestRunner::default().run(&strategy, |value| {
+        runtime.block_on(check_create(&app_state, body(value)))
+    });
+
+    let _ = std::fs::remove_file(&*app_state.config_target.load());
+    Ok(result?)
+}
+
+fn app_state(runtime: &Runtime) -> Result<AppState> {
+    let pool = runtime.block_on(async {
+        // a single connection, so that every query sees the same in-memory database
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await?;
+        crate::MIGRATOR.run(&pool).await?;
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(pool)
+    })?;
+
+    Ok(AppState::for_testing(pool))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, nasty_string, runner_payload, Result};
+
+    #[test]
+    fn arbitrary_runner_payloads() -> Result<()> {
+        check(runner_payload(), |payload| payload.to_string())
+    }
+
+    #[test]
+    fn arbitrary_bodies() -> Result<()> {
+        check(nasty_string(), |body| body)
+    }
+}
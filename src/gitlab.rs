@@ -0,0 +1,758 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use glrcfg::runner::{DateTime, RegistrationToken, RunnerToken, Url};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[cfg(feature = "chaos")]
+use crate::chaos::Faults;
+use crate::{
+    error::Error,
+    secrets::{Credential, Secrets},
+    settings::env_opt,
+};
+
+/// How long the GitLab API may take to respond.
+const GITLAB_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the recent jobs of a runner are served from the cache.
+const JOBS_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// Number of recent jobs read per runner.
+const RECENT_JOBS: u32 = 20;
+
+/// Runner as registered with GitLab through the legacy registration flow.
+#[derive(Debug, Clone)]
+pub struct RegisteredRunner {
+    pub id: u32,
+    pub token: RunnerToken,
+    pub token_expires_at: Option<DateTime>,
+}
+
+/// Runner as returned by GitLab, before its token is checked.
+#[derive(Debug, Deserialize)]
+struct IssuedRunner {
+    id: u32,
+    token: String,
+    #[serde(default)]
+    token_expires_at: Option<DateTime>,
+}
+
+#[derive(Debug, Serialize)]
+struct RegisterRequest<'a> {
+    token: &'a str,
+    description: &'a str,
+    tag_list: String,
+    run_untagged: bool,
+}
+
+/// What a runner is registered for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunnerScope {
+    Instance,
+    Group(u64),
+    Project(u64),
+}
+
+#[derive(Debug, Serialize)]
+struct CreateRunnerRequest<'a> {
+    runner_type: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    group_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    project_id: Option<u64>,
+    description: &'a str,
+    tag_list: String,
+    run_untagged: bool,
+}
+
+impl<'a> CreateRunnerRequest<'a> {
+    fn new(scope: RunnerScope, description: &'a str, tags: &[String]) -> Self {
+        let (runner_type, group_id, project_id) = match scope {
+            RunnerScope::Instance => ("instance_type", None, None),
+            RunnerScope::Group(id) => ("group_type", Some(id), None),
+            RunnerScope::Project(id) => ("project_type", None, Some(id)),
+        };
+
+        Self {
+            runner_type,
+            group_id,
+            project_id,
+            description,
+            tag_list: tags.join(","),
+            run_untagged: tags.is_empty(),
+        }
+    }
+}
+
+/// A job a runner picked up, as reported by GitLab.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct RunnerJob {
+    #[schema(example = 4242)]
+    id: u64,
+    /// Status of the job, e.g. `running`, `success` or `failed`
+    #[schema(example = "success")]
+    status: String,
+    /// Project the job belongs to, with its namespace
+    #[schema(example = "payments/checkout")]
+    project: Option<String>,
+    /// Seconds the job ran for; not set for jobs which haven't started
+    #[schema(example = 93.2)]
+    duration: Option<f64>,
+    #[schema(value_type = Option<String>, format = DateTime, example = "2024-06-27T09:00:00Z")]
+    created_at: Option<chrono::DateTime<Utc>>,
+    #[schema(value_type = Option<String>, format = DateTime, example = "2024-06-27T09:01:33Z")]
+    finished_at: Option<chrono::DateTime<Utc>>,
+    #[schema(example = "https://gitlab.your-company.com/payments/checkout/-/jobs/4242")]
+    web_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitLabJob {
+    id: u64,
+    status: String,
+    #[serde(default)]
+    project: Option<GitLabProject>,
+    #[serde(default)]
+    duration: Option<f64>,
+    #[serde(default)]
+    created_at: Option<chrono::DateTime<Utc>>,
+    #[serde(default)]
+    finished_at: Option<chrono::DateTime<Utc>>,
+    #[serde(default)]
+    web_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitLabProject {
+    path_with_namespace: String,
+}
+
+impl From<GitLabJob> for RunnerJob {
+    fn from(job: GitLabJob) -> Self {
+        Self {
+            id: job.id,
+            status: job.status,
+            project: job.project.map(|project| project.path_with_namespace),
+            duration: job.duration,
+            created_at: job.created_at,
+            finished_at: job.finished_at,
+            web_url: job.web_url,
+        }
+    }
+}
+
+/// Jobs of a runner which are waiting to be picked up or running, as reported by GitLab.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JobCounts {
+    pub pending: u32,
+    pub running: u32,
+}
+
+/// A runner as reported by `GET /runners/:id`, with what decides which pending jobs it can pick
+/// up.
+#[derive(Debug, Deserialize)]
+struct RunnerDetails {
+    runner_type: String,
+    #[serde(default)]
+    run_untagged: bool,
+    #[serde(default)]
+    tag_list: Vec<String>,
+    /// Projects a project runner is assigned to
+    #[serde(default)]
+    projects: Vec<GitLabId>,
+    /// Groups a group runner is assigned to
+    #[serde(default)]
+    groups: Vec<GitLabId>,
+}
+
+impl RunnerDetails {
+    /// Returns whether the runner can pick up a job with the given tags.
+    fn picks_up(&self, job: &PendingJob) -> bool {
+        if job.tag_list.is_empty() {
+            return self.run_untagged;
+        }
+        job.tag_list.iter().all(|tag| self.tag_list.contains(tag))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GitLabId {
+    id: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct PendingJob {
+    #[serde(default)]
+    tag_list: Vec<String>,
+}
+
+/// Access tokens for the GitLab API, by instance URL, for API calls which a runner token doesn't
+/// allow, e.g. listing the jobs of a runner. Tokens can be given as secret references.
+#[derive(Debug, Clone, Default)]
+pub struct ApiTokens(BTreeMap<String, Credential<String>>);
+
+impl ApiTokens {
+    fn get(&self, url: &Url) -> Option<&Credential<String>> {
+        self.0.get(url.normalized().as_str())
+    }
+}
+
+impl FromStr for ApiTokens {
+    type Err = Error;
+
+    /// Parses a comma-separated list of `<instance URL>=<token>` pairs.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut tokens = BTreeMap::new();
+
+        for pair in value
+            .split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+        {
+            // the entry may contain a token, so it must not end up in the error
+            let Some((url, token)) = pair
+                .split_once('=')
+                .filter(|(_, token)| !token.trim().is_empty())
+            else {
+                return Err(Error::invalid_argument(
+                    "invalid GitLab API token; must look like <instance URL>=<token>",
+                ));
+            };
+            let url = Url::parse(url.trim()).map_err(|err| {
+                Error::invalid_argument(format!("invalid GitLab instance URL '{url}': {err}"))
+            })?;
+            tokens.insert(url.normalized().as_str().to_string(), token.trim().parse()?);
+        }
+
+        Ok(Self(tokens))
+    }
+}
+
+impl fmt::Display for ApiTokens {
+    /// Lists the instances only, so that tokens don't end up in logs.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let instances: Vec<_> = self.0.keys().map(String::as_str).collect();
+        f.write_str(&instances.join(","))
+    }
+}
+
+/// Recent jobs of runners, by instance URL and runner ID, with the time they were read.
+type JobsCache = BTreeMap<(String, u32), (Instant, Vec<RunnerJob>)>;
+
+/// Client for the parts of the GitLab REST API runrs uses.
+#[derive(Debug, Clone, Default)]
+pub struct GitLabClient {
+    http: reqwest::Client,
+    api_tokens: Arc<ApiTokens>,
+    jobs: Arc<Mutex<JobsCache>>,
+    #[cfg(feature = "chaos")]
+    faults: Arc<Faults>,
+}
+
+impl GitLabClient {
+    /// Sets up the client with the API tokens in `GITLAB_API_TOKENS`, if any.
+    pub fn from_env() -> miette::Result<Self> {
+        let api_tokens: Option<ApiTokens> = env_opt("GITLAB_API_TOKENS")?;
+        if let Some(api_tokens) = &api_tokens {
+            tracing::info!(instances = %api_tokens, "GitLab API tokens configured");
+        }
+
+        Ok(Self {
+            api_tokens: Arc::new(api_tokens.unwrap_or_default()),
+            ..Default::default()
+        })
+    }
+
+    #[cfg(test)]
+    pub fn with_api_tokens(api_tokens: ApiTokens) -> Self {
+        Self {
+            api_tokens: Arc::new(api_tokens),
+            ..Default::default()
+        }
+    }
+
+    /// Makes calls to GitLab fail while a GitLab outage is injected.
+    #[cfg(feature = "chaos")]
+    pub fn with_faults(self, faults: Arc<Faults>) -> Self {
+        Self { faults, ..self }
+    }
+
+    /// Fails if GitLab is made unreachable, before anything is sent.
+    fn ensure_reachable(&self) -> Result<(), Error> {
+        #[cfg(feature = "chaos")]
+        self.faults.ensure_gitlab_reachable()?;
+
+        Ok(())
+    }
+
+    /// Returns the most recent jobs of the runner with the given ID, newest first. Requires an
+    /// API token for the instance with access to the runner; jobs are cached for a minute.
+    pub async fn runner_jobs(
+        &self,
+        url: &Url,
+        id: u32,
+        secrets: &Secrets,
+    ) -> Result<Vec<RunnerJob>, Error> {
+        let key = (url.normalized().as_str().to_string(), id);
+        let cached = self
+            .cached_jobs()
+            .get(&key)
+            .filter(|(read_at, _)| read_at.elapsed() < JOBS_CACHE_TTL)
+            .map(|(_, jobs)| jobs.clone());
+        if let Some(jobs) = cached {
+            tracing::debug!(%url, id, "serving runner jobs from cache");
+            return Ok(jobs);
+        }
+
+        self.ensure_reachable()?;
+        let token = self.api_token(url, secrets).await?;
+
+        tracing::debug!(%url, id, "reading runner jobs");
+        let response = self
+            .http
+            .get(api_url(url, &format!("runners/{id}/jobs")))
+            .timeout(GITLAB_TIMEOUT)
+            .header("PRIVATE-TOKEN", token)
+            .query(&[
+                ("order_by", "id"),
+                ("sort", "desc"),
+                ("per_page", &RECENT_JOBS.to_string()),
+            ])
+            .send()
+            .await
+            .map_err(|err| Error::connection_failed(format!("GitLab unreachable: {err}")))?;
+
+        let jobs: Vec<GitLabJob> = match response.status() {
+            status if status.is_success() => response.json().await.map_err(|err| {
+                Error::internal_error(format!("unexpected response from GitLab: {err}"))
+            })?,
+            reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => {
+                return Err(Error::forbidden(format!(
+                    "GitLab rejected the API token for {url}"
+                )))
+            }
+            reqwest::StatusCode::NOT_FOUND => {
+                return Err(Error::not_found(format!("GitLab doesn't know runner {id}")))
+            }
+            status => {
+                return Err(Error::connection_failed(format!(
+                    "reading runner jobs from GitLab failed with status {status}"
+                )))
+            }
+        };
+
+        let jobs: Vec<RunnerJob> = jobs.into_iter().map(RunnerJob::from).collect();
+        let mut cache = self.cached_jobs();
+        cache.retain(|_, (read_at, _)| read_at.elapsed() < JOBS_CACHE_TTL);
+        cache.insert(key, (Instant::now(), jobs.clone()));
+
+        Ok(jobs)
+    }
+
+    /// Returns whether an API token is configured for the instance at `url`.
+    pub fn has_api_token(&self, url: &Url) -> bool {
+        self.api_tokens.get(url).is_some()
+    }
+
+    /// Returns the API token for the instance. Without one, reading from the instance isn't
+    /// available at all, which is up to the operator rather than the client, so it's not an
+    /// authorization error.
+    async fn api_token(&self, url: &Url, secrets: &Secrets) -> Result<String, Error> {
+        self.api_tokens
+            .get(url)
+            .ok_or_else(|| {
+                Error::unimplemented(format!(
+                    "no GitLab API token configured for {url}; set GITLAB_API_TOKENS"
+                ))
+            })?
+            .resolve(secrets)
+            .await
+    }
+
+    /// Counts the jobs the runner with the given ID could pick up which are waiting in the queues
+    /// of its projects, or of the projects of its groups, and the jobs running on it. Requires an
+    /// API token for the instance with access to the runner and its projects, or an
+    /// administrator's token for instance runners; unlike the recent jobs, the counts are never
+    /// cached.
+    pub async fn job_counts(
+        &self,
+        url: &Url,
+        id: u32,
+        secrets: &Secrets,
+    ) -> Result<JobCounts, Error> {
+        self.ensure_reachable()?;
+        let token = self.api_token(url, secrets).await?;
+
+        let runner: RunnerDetails = self
+            .api_get(url, &token, &format!("runners/{id}"), &[])
+            .await?;
+        Ok(JobCounts {
+            pending: self.count_pending(url, &token, &runner).await?,
+            running: self.count_running(url, id, &token).await?,
+        })
+    }
+
+    /// Counts the pending jobs the runner can pick up. Only the first page of each queue is read,
+    /// so the count is a lower bound for large queues, which suffices for scaling.
+    async fn count_pending(
+        &self,
+        url: &Url,
+        token: &str,
+        runner: &RunnerDetails,
+    ) -> Result<u32, Error> {
+        const PENDING: [(&str, &str); 2] = [("scope[]", "pending"), ("per_page", "100")];
+
+        let count = |jobs: Vec<PendingJob>| {
+            let pickable = jobs.iter().filter(|job| runner.picks_up(job)).count();
+            u32::try_from(pickable).unwrap_or(u32::MAX)
+        };
+
+        // instance runners pick up jobs of every project
+        if runner.runner_type == "instance_type" {
+            tracing::debug!(%url, "counting pending jobs of the instance");
+            let jobs = self.api_get(url, token, "jobs", &PENDING).await?;
+            return Ok(count(jobs));
+        }
+
+        let mut projects: Vec<u64> = runner.projects.iter().map(|project| project.id).collect();
+        for group in &runner.groups {
+            let group_projects: Vec<GitLabId> = self
+                .api_get(
+                    url,
+                    token,
+                    &format!("groups/{}/projects", group.id),
+                    &[
+                        ("include_subgroups", "true"),
+                        ("simple", "true"),
+                        ("per_page", "100"),
+                    ],
+                )
+                .await?;
+            projects.extend(group_projects.into_iter().map(|project| project.id));
+        }
+        projects.sort_unstable();
+        projects.dedup();
+
+        let mut pending = 0u32;
+        for project in projects {
+            tracing::debug!(%url, project, "counting pending jobs of project");
+            let jobs = self
+                .api_get(url, token, &format!("projects/{project}/jobs"), &PENDING)
+                .await?;
+            pending = pending.saturating_add(count(jobs));
+        }
+
+        Ok(pending)
+    }
+
+    async fn count_running(&self, url: &Url, id: u32, token: &str) -> Result<u32, Error> {
+        tracing::debug!(%url, id, "counting running runner jobs");
+        let jobs: Vec<serde_json::Value> = self
+            .api_get(
+                url,
+                token,
+                &format!("runners/{id}/jobs"),
+                &[("status", "running"), ("per_page", "100")],
+            )
+            .await?;
+
+        Ok(u32::try_from(jobs.len()).unwrap_or(u32::MAX))
+    }
+
+    /// Reads `path` from the API of the instance at `url`.
+    async fn api_get<T: DeserializeOwned>(
+        &self,
+        url: &Url,
+        token: &str,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<T, Error> {
+        let response = self
+            .http
+            .get(api_url(url, path))
+            .timeout(GITLAB_TIMEOUT)
+            .header("PRIVATE-TOKEN", token)
+            .query(query)
+            .send()
+            .await
+            .map_err(|err| Error::connection_failed(format!("GitLab unreachable: {err}")))?;
+
+        match response.status() {
+            status if status.is_success() => response.json().await.map_err(|err| {
+                Error::internal_error(format!("unexpected response from GitLab: {err}"))
+            }),
+            reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => Err(
+                Error::forbidden(format!("GitLab rejected the API token for {url}")),
+            ),
+            reqwest::StatusCode::NOT_FOUND => {
+                Err(Error::not_found(format!("GitLab doesn't know {path}")))
+            }
+            status => Err(Error::connection_failed(format!(
+                "reading {path} from GitLab failed with status {status}"
+            ))),
+        }
+    }
+
+    fn cached_jobs(&self) -> MutexGuard<'_, JobsCache> {
+        self.jobs
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Registers a runner using a registration token, i.e. the flow deprecated in GitLab 15.6,
+    /// and returns the runner token issued by GitLab. Runners without tags pick up untagged jobs.
+    pub async fn register_runner(
+        &self,
+        url: &Url,
+        registration_token: &RegistrationToken,
+        description: &str,
+        tags: &[String],
+    ) -> Result<RegisteredRunner, Error> {
+        tracing::debug!(%url, token = registration_token.masked(), "registering runner");
+        self.ensure_reachable()?;
+
+        let response = self
+            .http
+            .post(api_url(url, "runners"))
+            .timeout(GITLAB_TIMEOUT)
+            .json(&RegisterRequest {
+                token: registration_token.as_str(),
+                description,
+                tag_list: tags.join(","),
+                run_untagged: tags.is_empty(),
+            })
+            .send()
+            .await
+            .map_err(|err| Error::connection_failed(format!("GitLab unreachable: {err}")))?;
+
+        match response.status() {
+            status if status.is_success() => self.issued(url, response).await,
+            reqwest::StatusCode::FORBIDDEN => {
+                Err(Error::forbidden("GitLab rejected the registration token"))
+            }
+            status => Err(Error::connection_failed(format!(
+                "registering runner with GitLab failed with status {status}"
+            ))),
+        }
+    }
+
+    /// Creates a runner in GitLab using an access token with the `create_runner` scope, i.e. the
+    /// flow GitLab introduced in 15.10, and returns the authentication token issued by GitLab.
+    /// Runners without tags pick up untagged jobs.
+    pub async fn create_runner(
+        &self,
+        url: &Url,
+        access_token: &str,
+        scope: RunnerScope,
+        description: &str,
+        tags: &[String],
+    ) -> Result<RegisteredRunner, Error> {
+        tracing::debug!(%url, ?scope, "creating runner");
+        self.ensure_reachable()?;
+
+        let response = self
+            .http
+            .post(api_url(url, "user/runners"))
+            .timeout(GITLAB_TIMEOUT)
+            .header("PRIVATE-TOKEN", access_token)
+            .json(&CreateRunnerRequest::new(scope, description, tags))
+            .send()
+            .await
+            .map_err(|err| Error::connection_failed(format!("GitLab unreachable: {err}")))?;
+
+        match response.status() {
+            status if status.is_success() => self.issued(url, response).await,
+            reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => {
+                Err(Error::forbidden(
+                    "GitLab rejected the access token; it needs the create_runner scope and \
+                     access to the project or group",
+                ))
+            }
+            reqwest::StatusCode::BAD_REQUEST => {
+                let message = response.text().await.unwrap_or_default();
+                Err(Error::bad_request(format!(
+                    "GitLab rejected the runner: {message}"
+                )))
+            }
+            reqwest::StatusCode::NOT_FOUND => Err(Error::not_found(
+                "GitLab doesn't know the project or group, or the access token can't see it",
+            )),
+            status => Err(Error::connection_failed(format!(
+                "creating runner in GitLab failed with status {status}"
+            ))),
+        }
+    }
+
+    /// Reads the runner GitLab registered or created from its `response`. If GitLab issued a
+    /// token which isn't a valid runner token, the runner is deleted from GitLab again, since it
+    /// can't be stored and nothing else knows its token.
+    async fn issued(
+        &self,
+        url: &Url,
+        response: reqwest::Response,
+    ) -> Result<RegisteredRunner, Error> {
+        let issued: IssuedRunner = response.json().await.map_err(|err| {
+            Error::internal_error(format!("unexpected response from GitLab: {err}"))
+        })?;
+
+        match RunnerToken::parse(issued.token.as_str()) {
+            Ok(token) => Ok(RegisteredRunner {
+                id: issued.id,
+                token,
+                token_expires_at: issued.token_expires_at,
+            }),
+            Err(err) => {
+                if let Err(rollback_err) = self.delete_runner(url, &issued.token).await {
+                    tracing::error!(
+                        %rollback_err,
+                        runner_id = issued.id,
+                        "unregistering runner failed, it must be removed from GitLab manually"
+                    );
+                }
+                Err(Error::internal_error(format!(
+                    "GitLab issued an unusable token for runner {}: {err}",
+                    issued.id
+                )))
+            }
+        }
+    }
+
+    /// Deletes a runner from GitLab using its runner token, e.g. to roll back a registration.
+    pub async fn unregister_runner(&self, url: &Url, token: &RunnerToken) -> Result<(), Error> {
+        tracing::debug!(%url, token = token.masked(), "unregistering runner");
+        self.delete_runner(url, token.as_str()).await
+    }
+
+    async fn delete_runner(&self, url: &Url, token: &str) -> Result<(), Error> {
+        self.ensure_reachable()?;
+
+        self.http
+            .delete(api_url(url, "runners"))
+            .timeout(GITLAB_TIMEOUT)
+            .json(&serde_json::json!({ "token": token }))
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|err| {
+                Error::connection_failed(format!("unregistering runner failed: {err}"))
+            })?;
+
+        Ok(())
+    }
+
+    /// Asks GitLab whether the runner token is valid, i.e. the runner still exists and its token
+    /// hasn't expired or been reset.
+    pub async fn verify_runner(&self, url: &Url, token: &RunnerToken) -> Result<bool, Error> {
+        tracing::debug!(%url, token = token.masked(), "verifying runner");
+        self.ensure_reachable()?;
+
+        let response = self
+            .http
+            .post(api_url(url, "runners/verify"))
+            .timeout(GITLAB_TIMEOUT)
+            .json(&serde_json::json!({ "token": token.as_str() }))
+            .send()
+            .await
+            .map_err(|err| Error::connection_failed(format!("GitLab unreachable: {err}")))?;
+
+        match response.status() {
+            status if status.is_success() => Ok(true),
+            reqwest::StatusCode::FORBIDDEN => Ok(false),
+            status => Err(Error::connection_failed(format!(
+                "verifying runner with GitLab failed with status {status}"
+            ))),
+        }
+    }
+}
+
+/// Joins `path` to the API root of the GitLab instance at `url`.
+fn api_url(url: &Url, path: &str) -> String {
+    format!("{}/api/v4/{path}", url.as_str().trim_end_matches('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use glrcfg::runner::Url;
+    use pretty_assertions::assert_eq;
+
+    use super::{api_url, ApiTokens, PendingJob, RunnerDetails};
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[test]
+    fn join_api_url() -> Result<()> {
+        assert_eq!(
+            api_url(&Url::parse("https://gitlab.your-company.com")?, "runners"),
+            "https://gitlab.your-company.com/api/v4/runners"
+        );
+        assert_eq!(
+            api_url(&Url::parse("https://your-company.com/gitlab/")?, "runners"),
+            "https://your-company.com/gitlab/api/v4/runners"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn pending_jobs_by_tags() {
+        let job = |tags: &[&str]| PendingJob {
+            tag_list: tags.iter().map(ToString::to_string).collect(),
+        };
+        let runner = RunnerDetails {
+            runner_type: "group_type".to_string(),
+            run_untagged: false,
+            tag_list: vec!["docker".to_string(), "linux".to_string()],
+            projects: Vec::new(),
+            groups: Vec::new(),
+        };
+
+        assert!(runner.picks_up(&job(&["docker"])));
+        assert!(runner.picks_up(&job(&["linux", "docker"])));
+        assert!(!runner.picks_up(&job(&["docker", "gpu"])));
+        assert!(!runner.picks_up(&job(&[])));
+
+        let untagged = RunnerDetails {
+            run_untagged: true,
+            ..runner
+        };
+        assert!(untagged.picks_up(&job(&[])));
+    }
+
+    #[test]
+    fn parse_api_tokens() -> Result<()> {
+        let tokens: ApiTokens = "https://GitLab.your-company.com/=glpat-0123456789, \
+             https://gitlab.bmc-labs.com=vault:secret/gitlab#token"
+            .parse()?;
+        assert_eq!(
+            tokens.to_string(),
+            "https://gitlab.bmc-labs.com/,https://gitlab.your-company.com/"
+        );
+        assert_eq!(
+            tokens
+                .get(&Url::parse("https://gitlab.your-company.com")?)
+                .map(ToString::to_string)
+                .as_deref(),
+            Some("glpat-0123456789")
+        );
+        assert!(tokens
+            .get(&Url::parse("https://gitlab.example.com")?)
+            .is_none());
+
+        for invalid in ["glpat-0123456789", "https://gitlab.your-company.com="] {
+            let err = invalid.parse::<ApiTokens>().err().ok_or("parsed")?;
+            assert!(!err.to_string().contains("glpat"));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,753 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use atmosphere::Read as _;
+use axum::{
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use futures::{future::BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    error::Error,
+    models::{
+        Change, ConfigCache, ConfigSync, ConfigTarget, GitLabRunner, GitLabRunnerConfig,
+        SystemEvent, SystemEventKind,
+    },
+    secrets::Secrets,
+    settings::{GitOps, Settings, SettingsStore},
+    subsystems::{Shutdown, Subsystem},
+};
+
+/// How long a single `git` command may take, e.g. the initial clone.
+const GIT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How often the controller checks whether GitOps was turned on while it is off.
+const IDLE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Actor of the changes made by the controller, in the system events and the recycle bin.
+const GITOPS_ACTOR: &str = "gitops";
+
+/// File marking a checkout as cloned by runrs, within its `.git` directory where Git ignores it.
+/// Only such checkouts are ever removed, in case the checkout directory was misconfigured.
+const CHECKOUT_MARKER: &str = ".git/runrs-checkout";
+
+/// A runner is identified by its GitLab instance and its ID there, since declarations carry no
+/// UUID.
+pub(crate) type RunnerKey = (String, u32);
+
+/// A TOML or YAML file of the repository, declaring some of the runners, e.g. those of one team.
+#[derive(Debug, Deserialize)]
+struct Declaration {
+    #[serde(default)]
+    runners: Vec<GitLabRunner>,
+}
+
+/// A runner created, updated or deleted by a reconciliation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ReconciledRunner {
+    #[schema(value_type = String, format = Uuid)]
+    uuid: Uuid,
+    #[schema(example = "usain-bolt")]
+    name: String,
+}
+
+impl From<&GitLabRunner> for ReconciledRunner {
+    fn from(runner: &GitLabRunner) -> Self {
+        Self {
+            uuid: *runner.uuid(),
+            name: runner.name().to_string(),
+        }
+    }
+}
+
+/// A declaration which couldn't be applied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ReconcileFailure {
+    /// File of the declaration relative to the GitOps path, or name of the custom resource in
+    /// operator mode
+    #[schema(example = "payments.toml")]
+    file: String,
+    /// Position of the runner among the `[[runners]]` of the file, starting at 1; not set if the
+    /// file couldn't be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = 2)]
+    runner: Option<usize>,
+    #[schema(example = "Invalid argument: runner name must not be empty")]
+    error: String,
+}
+
+/// Outcome of a reconciliation of the runners with the repository.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ReconcileReport {
+    /// Commit the runners were reconciled with; not set in operator mode
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = "3f2a1c0b9d8e7f6a5b4c3d2e1f0a9b8c7d6e5f4a")]
+    pub(crate) commit: Option<String>,
+    pub(crate) created: Vec<ReconciledRunner>,
+    pub(crate) updated: Vec<ReconciledRunner>,
+    pub(crate) deleted: Vec<ReconciledRunner>,
+    pub(crate) failed: Vec<ReconcileFailure>,
+    /// Whether the config reflects the changes, if there were any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sync: Option<ConfigSync>,
+    /// Why the reconciliation was aborted, e.g. because the repository couldn't be pulled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[schema(value_type = String, format = DateTime, example = "2024-07-08T09:00:00Z")]
+    reconciled_at: DateTime<Utc>,
+}
+
+impl ReconcileReport {
+    /// Starts the report of a reconciliation starting now.
+    pub(crate) fn now() -> Self {
+        Self {
+            reconciled_at: Utc::now(),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn changed(&self) -> bool {
+        !(self.created.is_empty() && self.updated.is_empty() && self.deleted.is_empty())
+    }
+
+    /// Returns why the declarations of `file` couldn't be applied, if they couldn't.
+    pub(crate) fn failure(&self, file: &str) -> Option<&str> {
+        self.failed
+            .iter()
+            .find(|failure| failure.file == file)
+            .map(|failure| failure.error.as_str())
+    }
+}
+
+/// The outcome of the last reconciliation, for `GET /gitops`.
+#[derive(Debug, Default)]
+pub struct GitOpsLog(Mutex<Option<ReconcileReport>>);
+
+impl GitOpsLog {
+    fn record(&self, report: ReconcileReport) {
+        match &report.error {
+            Some(error) => tracing::warn!(%error, "GitOps reconciliation failed"),
+            None if report.changed() || !report.failed.is_empty() => tracing::info!(
+                commit = ?report.commit,
+                created = report.created.len(),
+                updated = report.updated.len(),
+                deleted = report.deleted.len(),
+                failed = report.failed.len(),
+                "reconciled runners with GitOps repository"
+            ),
+            None => tracing::debug!(commit = ?report.commit, "runners match GitOps repository"),
+        }
+
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = Some(report);
+    }
+
+    pub fn last(&self) -> Option<ReconcileReport> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl GitOps {
+    /// Brings the checkout up to date with the branch, cloning the repository first if it isn't
+    /// checked out yet, and returns the commit checked out.
+    async fn pull(&self) -> Result<String, Error> {
+        let Some(repo_url) = self.repo_url.as_deref() else {
+            return Err(Error::internal_error("no GitOps repository configured"));
+        };
+        let dir = &self.checkout_dir;
+
+        let checked_out = dir.join(".git").is_dir();
+        let origin = if checked_out {
+            git(dir, &["remote", "get-url", "origin"]).await.ok()
+        } else {
+            None
+        };
+
+        if origin.as_deref() == Some(repo_url) {
+            let fetch = [
+                "fetch",
+                "--quiet",
+                "--depth",
+                "1",
+                "origin",
+                self.branch.as_str(),
+            ];
+            git(dir, &fetch).await?;
+            git(dir, &["reset", "--quiet", "--hard", "FETCH_HEAD"]).await?;
+        } else {
+            if checked_out {
+                if !dir.join(CHECKOUT_MARKER).is_file() {
+                    return Err(Error::internal_error(format!(
+                        "{} is not a checkout of {repo_url} made by runrs; remove it or choose \
+                         another checkout directory",
+                        dir.display()
+                    )));
+                }
+                tracing::info!(?dir, "GitOps repository changed, checking it out anew");
+                std::fs::remove_dir_all(dir).map_err(Error::internal_error)?;
+            }
+            std::fs::create_dir_all(dir).map_err(Error::internal_error)?;
+            git(
+                dir,
+                &[
+                    "clone",
+                    "--quiet",
+                    "--depth",
+                    "1",
+                    "--branch",
+                    self.branch.as_str(),
+                    "--",
+                    repo_url,
+                    ".",
+                ],
+            )
+            .await?;
+            std::fs::write(dir.join(CHECKOUT_MARKER), repo_url).map_err(Error::internal_error)?;
+        }
+
+        git(dir, &["rev-parse", "HEAD"]).await
+    }
+}
+
+/// Runs `git` in `dir` and returns what it printed, trimmed.
+async fn git(dir: &Path, args: &[&str]) -> Result<String, Error> {
+    let output = tokio::time::timeout(
+        GIT_TIMEOUT,
+        Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            // fail instead of waiting for credentials nobody is going to enter
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .kill_on_drop(true)
+            .output(),
+    )
+    .await
+    .map_err(|_| Error::internal_error(format!("git {} timed out", args[0])))?
+    .map_err(|err| Error::internal_error(format!("running git failed: {err}")))?;
+
+    if !output.status.success() {
+        return Err(Error::internal_error(format!(
+            "git {} failed with {}: {}",
+            args[0],
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Reads the runners declared by the TOML and YAML files in `dir`, in order of their file names.
+fn read_declarations(dir: &Path) -> Result<Vec<(String, Result<Vec<GitLabRunner>, Error>)>, Error> {
+    // a missing directory must not be taken for a declaration of no runners at all
+    let entries = std::fs::read_dir(dir)
+        .map_err(|err| Error::internal_error(format!("reading {} failed: {err}", dir.display())))?;
+
+    let mut files = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(Error::internal_error)?.path();
+        let declaration = path
+            .extension()
+            .is_some_and(|ext| ext == "toml" || ext == "yaml" || ext == "yml");
+        if path.is_file() && declaration {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    Ok(files
+        .into_iter()
+        .map(|path| {
+            let file = path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+            let runners = std::fs::read_to_string(&path)
+                .map_err(Error::internal_error)
+                .and_then(|declaration| {
+                    if path.extension().is_some_and(|ext| ext == "toml") {
+                        toml::from_str::<Declaration>(&declaration).map_err(|err| err.to_string())
+                    } else {
+                        serde_yaml::from_str::<Declaration>(&declaration)
+                            .map_err(|err| err.to_string())
+                    }
+                    .map_err(|err| Error::invalid_argument(format!("invalid file: {err}")))
+                })
+                .map(|declaration| declaration.runners);
+            (file, runners)
+        })
+        .collect())
+}
+
+pub(crate) fn key(runner: &GitLabRunner) -> RunnerKey {
+    (runner.url().normalized().as_str().to_string(), runner.id())
+}
+
+/// Rejects changes to runners via the API while GitOps or operator mode is on, since they'd be
+/// reverted with the next reconciliation; runners are changed in the repository or the custom
+/// resources instead.
+pub async fn enforce_gitops(
+    State(settings): State<Arc<SettingsStore>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) || !changes_runners(request.uri().path())
+    {
+        return next.run(request).await;
+    }
+
+    let settings = settings.load();
+    let desc = if settings.gitops.enabled() {
+        "runners are managed via GitOps; change them in the repository instead"
+    } else if settings.operator.enabled() {
+        "runners are managed via Kubernetes; change their GitLabRunner resources instead"
+    } else {
+        return next.run(request).await;
+    };
+
+    Error::locked(desc).into_response()
+}
+
+fn changes_runners(path: &str) -> bool {
+    let runners = path.starts_with("/gitlab-runners") && !path.ends_with("/share");
+    runners || path == "/import" || path == "/config/orphans/adopt" || path == "/apply"
+}
+
+/// Reconciles the runners in the database with those declared in the GitOps repository: declared
+/// runners are created or updated, all others deleted. Runners are only deleted if every file of
+/// the repository could be read, so that a broken file doesn't delete the runners it declares, and
+/// if there was any file at all, unless that is explicitly allowed.
+#[derive(Debug, Clone)]
+pub struct GitOpsController {
+    pool: atmosphere::Pool,
+    target: Arc<ConfigTarget>,
+    cache: Arc<ConfigCache>,
+    settings: Arc<SettingsStore>,
+    secrets: Secrets,
+    log: Arc<GitOpsLog>,
+    actor: &'static str,
+}
+
+impl GitOpsController {
+    pub fn new(
+        pool: atmosphere::Pool,
+        target: Arc<ConfigTarget>,
+        cache: Arc<ConfigCache>,
+        settings: Arc<SettingsStore>,
+        secrets: Secrets,
+        log: Arc<GitOpsLog>,
+    ) -> Self {
+        Self {
+            pool,
+            target,
+            cache,
+            settings,
+            secrets,
+            log,
+            actor: GITOPS_ACTOR,
+        }
+    }
+
+    /// Attributes the changes made to `actor` instead, for other sources of declarations.
+    pub fn with_actor(self, actor: &'static str) -> Self {
+        Self { actor, ..self }
+    }
+
+    /// Pulls the repository and reconciles the runners with it.
+    async fn reconcile(&self, settings: &Settings) -> ReconcileReport {
+        let mut report = ReconcileReport::now();
+
+        let pulled = settings.gitops.pull().await.and_then(|commit| {
+            report.commit = Some(commit);
+            let declarations =
+                read_declarations(&settings.gitops.checkout_dir.join(&settings.gitops.path))?;
+            // an empty checkout or a mistyped path would otherwise delete every runner
+            if declarations.is_empty() && !settings.gitops.allow_empty {
+                return Err(Error::invalid_argument(format!(
+                    "no declarations found in '{}'; set `allow_empty` to delete all runners",
+                    settings.gitops.path
+                )));
+            }
+            Ok(declarations)
+        });
+        let applied = match pulled {
+            Ok(declarations) => self.apply(settings, declarations, &mut report).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = applied {
+            report.error = Some(err.msg);
+        }
+
+        report
+    }
+
+    /// Applies the declared runners to the database and writes the config if anything changed.
+    pub(crate) async fn apply(
+        &self,
+        settings: &Settings,
+        declarations: Vec<(String, Result<Vec<GitLabRunner>, Error>)>,
+        report: &mut ReconcileReport,
+    ) -> Result<(), Error> {
+        let mut complete = true;
+        let mut declared = Vec::new();
+        let mut keys = BTreeSet::new();
+        for (file, runners) in declarations {
+            let runners = match runners {
+                Ok(runners) => runners,
+                Err(err) => {
+                    complete = false;
+                    report.failed.push(ReconcileFailure {
+                        file,
+                        runner: None,
+                        error: err.msg,
+                    });
+                    continue;
+                }
+            };
+
+            for (idx, runner) in runners.into_iter().enumerate() {
+                if !keys.insert(key(&runner)) {
+                    report.failed.push(ReconcileFailure {
+                        file: file.clone(),
+                        runner: Some(idx + 1),
+                        error: format!(
+                            "runner {} of {} is declared more than once",
+                            runner.id(),
+                            runner.url()
+                        ),
+                    });
+                    continue;
+                }
+                declared.push((file.clone(), idx + 1, runner));
+            }
+        }
+
+        let mut current: BTreeMap<RunnerKey, GitLabRunner> = GitLabRunner::read_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|runner| (key(&runner), runner))
+            .collect();
+
+        // deleting first frees up quota for the runners replacing them
+        if complete {
+            let undeclared: Vec<_> = current
+                .keys()
+                .filter(|key| !keys.contains(*key))
+                .cloned()
+                .collect();
+            for key in undeclared {
+                let Some(mut runner) = current.remove(&key) else {
+                    continue;
+                };
+                runner
+                    .remove(&self.pool, Some(self.actor), settings.events.enabled())
+                    .await?;
+                report.deleted.push(ReconciledRunner::from(&runner));
+            }
+        } else {
+            tracing::warn!("not all declarations could be read, keeping undeclared runners");
+        }
+
+        for (file, position, mut runner) in declared {
+            let existing = current.get(&key(&runner));
+            match self.apply_declared(settings, existing, &mut runner).await {
+                Ok(Some(Change::Created)) => report.created.push(ReconciledRunner::from(&runner)),
+                Ok(Some(_)) => report.updated.push(ReconciledRunner::from(&runner)),
+                Ok(None) => {}
+                Err(err) => report.failed.push(ReconcileFailure {
+                    file,
+                    runner: Some(position),
+                    error: err.msg,
+                }),
+            }
+        }
+
+        if !report.changed() {
+            return Ok(());
+        }
+
+        self.cache.bump();
+        let sync = GitLabRunnerConfig::write_or_queue(
+            &self.pool,
+            &self.target.load(),
+            &self.cache,
+            &settings.render,
+        )
+        .await?;
+        report.sync = Some(sync);
+
+        let commit = report.commit.as_deref().unwrap_or_default();
+        let error = (!report.failed.is_empty())
+            .then(|| format!("{} declared runners failed", report.failed.len()));
+        SystemEvent::record(
+            &self.pool,
+            SystemEventKind::Reconcile,
+            self.actor,
+            commit,
+            error.as_deref(),
+        )
+        .await
+    }
+
+    /// Creates the declared runner, or updates `existing` if the declaration differs from it.
+    /// Returns the change made, if any; declared runners are checked like those created via the
+    /// API.
+    async fn apply_declared(
+        &self,
+        settings: &Settings,
+        existing: Option<&GitLabRunner>,
+        runner: &mut GitLabRunner,
+    ) -> Result<Option<Change>, Error> {
+        runner.normalize(settings)?;
+        let change = match existing {
+            Some(existing) => {
+                runner.declared_over(existing, settings.autoscaling.interval().is_some());
+                if runner == existing {
+                    return Ok(None);
+                }
+                Change::Updated
+            }
+            None => Change::Created,
+        };
+
+        runner.check_token(&self.secrets).await?;
+        runner.check_token_expiry(false)?;
+        runner
+            .ensure_unique_name(&self.pool, settings.name_uniqueness)
+            .await?;
+        runner
+            .ensure_within_quotas(&self.pool, &settings.quotas)
+            .await?;
+        runner
+            .apply(&self.pool, change, settings.events.enabled())
+            .await?;
+
+        Ok(Some(change))
+    }
+}
+
+impl Subsystem for GitOpsController {
+    fn name(&self) -> &'static str {
+        "gitops"
+    }
+
+    fn run(&self, mut shutdown: Shutdown) -> BoxFuture<'static, Result<(), Error>> {
+        let this = self.clone();
+
+        async move {
+            loop {
+                // the settings are read anew each time, so GitOps can be turned on and off via the
+                // settings API
+                let settings = this.settings.load();
+                let interval = settings.gitops.interval();
+                if interval.is_some() && !settings.freeze_windows.defers("gitops") {
+                    let report = this.reconcile(&settings).await;
+                    this.log.record(report);
+                }
+
+                tokio::select! {
+                    _ = tokio::time::sleep(interval.unwrap_or(IDLE_INTERVAL)) => {}
+                    _ = shutdown.requested() => return Ok(()),
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc};
+
+    use atmosphere::{Create as _, Pool, Read as _};
+    use axum::{
+        body::Body,
+        http::{self, Request, StatusCode},
+    };
+    use pretty_assertions::assert_eq;
+    use tower::ServiceExt; // for `call`, `oneshot`, and `ready`
+
+    use super::{read_declarations, GitOpsController, GitOpsLog, ReconciledRunner};
+    use crate::{
+        app::{router, AppState},
+        auth,
+        models::{ConfigSync, ConfigTarget, GitLabRunner},
+        settings::{GitOps, Settings, SettingsStore},
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    fn declaration(runners: &[&GitLabRunner]) -> Result<String> {
+        let runners = runners
+            .iter()
+            .map(|runner| toml::Value::try_from(runner))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let mut declaration = toml::Table::new();
+        declaration.insert("runners".to_string(), toml::Value::Array(runners));
+        Ok(toml::to_string(&declaration)?)
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn reconcile_declared_runners(pool: Pool) -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("runrs-gitops-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir)?;
+        let config_path = dir.join("config.toml");
+
+        let mut kept = GitLabRunner::for_testing();
+        kept.set_name("kept");
+        kept.create(&pool).await?;
+        let mut undeclared = GitLabRunner::for_testing();
+        undeclared.set_url("https://gitlab.bmc-labs.com/");
+        undeclared.create(&pool).await?;
+
+        let mut changed = kept.clone();
+        changed.set_job_limit(4);
+        let mut created = GitLabRunner::for_testing();
+        created.set_url("https://gitlab.example.com/");
+        created.set_name("created");
+        std::fs::write(dir.join("a.toml"), declaration(&[&changed])?)?;
+        std::fs::write(dir.join("b.toml"), declaration(&[&created, &changed])?)?;
+        std::fs::write(dir.join("README.md"), "not a declaration")?;
+
+        let controller = GitOpsController::new(
+            pool.clone(),
+            Arc::new(ConfigTarget::new(config_path.clone())),
+            Arc::default(),
+            Arc::default(),
+            Default::default(),
+            Arc::<GitOpsLog>::default(),
+        );
+        let settings = Settings::default();
+        let mut report = Default::default();
+        controller
+            .apply(&settings, read_declarations(&dir)?, &mut report)
+            .await?;
+
+        assert_eq!(report.deleted, vec![ReconciledRunner::from(&undeclared)]);
+        assert_eq!(report.updated, vec![ReconciledRunner::from(&kept)]);
+        assert_eq!(report.created.len(), 1);
+        assert_eq!(report.created[0].name, "created");
+        // the duplicate declaration is reported, the first one applied
+        assert_eq!(
+            (report.failed[0].file.as_str(), report.failed[0].runner),
+            ("b.toml", Some(2))
+        );
+        assert_eq!(report.sync, Some(ConfigSync::Synced));
+
+        let updated = GitLabRunner::read(&pool, kept.uuid()).await?;
+        assert_eq!(updated.job_limit(), Some(4));
+        assert!(GitLabRunner::find(&pool, undeclared.uuid())
+            .await?
+            .is_none());
+
+        // nothing changes as long as the declarations don't
+        let mut report = Default::default();
+        controller
+            .apply(&settings, read_declarations(&dir)?, &mut report)
+            .await?;
+        assert!(!report.changed());
+
+        // a broken file keeps the runners it declared
+        std::fs::write(dir.join("b.toml"), "[[runners]\n")?;
+        let mut report = Default::default();
+        controller
+            .apply(&settings, read_declarations(&dir)?, &mut report)
+            .await?;
+        assert!(report.deleted.is_empty());
+        assert_eq!(report.failed[0].runner, None);
+        assert_eq!(GitLabRunner::read_all(&pool).await?.len(), 2);
+
+        std::fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn yaml_declarations() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("runrs-gitops-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir)?;
+
+        let runner = GitLabRunner::for_testing();
+        let declaration = serde_yaml::to_string(&serde_json::json!({ "runners": [runner] }))?;
+        std::fs::write(dir.join("a.yaml"), &declaration)?;
+        std::fs::write(dir.join("b.yml"), "runners: [\n")?;
+
+        let declarations = read_declarations(&dir)?;
+        std::fs::remove_dir_all(&dir)?;
+
+        assert_eq!(declarations.len(), 2);
+        assert_eq!(declarations[0].0, "a.yaml");
+        assert_eq!(declarations[0].1.as_ref().ok(), Some(&vec![runner]));
+        assert!(declarations[1].1.is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn missing_directory() {
+        let dir = PathBuf::from("/nonexistent/runrs-gitops");
+        assert!(read_declarations(&dir).is_err());
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn mutations_locked_while_enabled(pool: Pool) -> Result<()> {
+        let secret = "test-secret".to_string();
+        let mut app_state = AppState::for_testing(pool);
+        app_state.settings = Arc::new(SettingsStore::new(Settings {
+            gitops: GitOps {
+                repo_url: Some("https://gitlab.bmc-labs.com/infra/runners.git".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }));
+        let token = auth::encode_token(&secret, vec![auth::Scope::Admin])?;
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/gitlab-runners")
+                    .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(serde_json::to_string(
+                        &GitLabRunner::for_testing(),
+                    )?))?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::LOCKED);
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(
+                Request::builder()
+                    .uri("/gitlab-runners/list")
+                    .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        Ok(())
+    }
+}
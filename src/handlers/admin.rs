@@ -0,0 +1,33 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response, Result},
+    Extension, Json,
+};
+
+use crate::{
+    app::AppState,
+    auth::{Claims, Scope},
+    error::Error,
+    subsystems::SubsystemStatus,
+};
+
+#[utoipa::path(
+    get,
+    path = "/admin/subsystems",
+    responses(
+        (status = StatusCode::OK, description = "Status of all background subsystems", body = [SubsystemStatus]),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope", body = Error)
+    )
+)]
+#[tracing::instrument(skip(supervisor, claims))]
+pub async fn subsystems(
+    State(AppState { supervisor, .. }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+
+    Ok((StatusCode::OK, Json(supervisor.statuses().await)).into_response())
+}
@@ -0,0 +1,345 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use axum::{
+    extract::{
+        ws::{rejection::WebSocketUpgradeRejection, WebSocketUpgrade},
+        Path, State,
+    },
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response, Result},
+    Extension, Json,
+};
+use futures::StreamExt;
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::gitlab_runners::Links;
+use crate::{
+    agents::{AgentStatus, Session},
+    app::AppState,
+    auth::{AgentTokens, Claims, Scope},
+    error::Error,
+    models::{Agent, AgentRegistration},
+};
+
+/// A newly registered agent along with its token.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RegisteredAgent {
+    #[serde(flatten)]
+    agent: Agent,
+    /// Token the agent connects with; it is only returned once
+    #[schema(example = "runrs-agent-...")]
+    token: String,
+    links: Links,
+}
+
+#[utoipa::path(
+    post,
+    path = "/agents",
+    request_body = AgentRegistration,
+    responses(
+        (status = StatusCode::CREATED, description = "Registered agent with its token; its URL is in the `Location` header and `links.self`", body = RegisteredAgent),
+        (status = StatusCode::BAD_REQUEST, description = "Invalid agent name, or an agent with this name exists already", body = Error),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope", body = Error),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, claims))]
+pub async fn register(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(registration): Json<AgentRegistration>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+
+    tracing::debug!("registering agent");
+    let (token, token_hash) = AgentTokens::issue();
+    let agent = Agent::register(&pool, &registration, &token_hash).await?;
+    let links = Links::agent(&agent);
+
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, links.self_url().to_string())],
+        Json(RegisteredAgent {
+            agent,
+            token,
+            links,
+        }),
+    )
+        .into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/agents",
+    responses(
+        (status = StatusCode::OK, description = "Registered agents with the state of their control channel", body = [AgentStatus]),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, agents))]
+pub async fn list(State(AppState { pool, agents, .. }): State<AppState>) -> Result<Response> {
+    tracing::debug!("reading agents from database");
+
+    let statuses: Vec<AgentStatus> = Agent::list(&pool)
+        .await?
+        .into_iter()
+        .map(|agent| agents.status(agent))
+        .collect();
+
+    Ok((StatusCode::OK, Json(statuses)).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/agents/{uuid}",
+    params(
+        ("uuid" = Uuid, Path, description = "Agent UUID")
+    ),
+    responses(
+        (status = StatusCode::OK, description = "Agent with the state of its control channel", body = AgentStatus),
+        (status = StatusCode::NOT_FOUND, description = "Agent not found", body = Error),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, agents))]
+pub async fn read(
+    State(AppState { pool, agents, .. }): State<AppState>,
+    Path(uuid): Path<Uuid>,
+) -> Result<Response> {
+    tracing::debug!("reading agent from database");
+    let agent = Agent::read(&pool, &uuid).await?;
+
+    Ok((StatusCode::OK, Json(agents.status(agent))).into_response())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/agents/{uuid}",
+    params(
+        ("uuid" = Uuid, Path, description = "Agent UUID")
+    ),
+    responses(
+        (status = StatusCode::OK, description = "Deleted agent; its token is revoked and its control channel closed", body = Agent),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope", body = Error),
+        (status = StatusCode::NOT_FOUND, description = "Agent not found", body = Error),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, agents, claims))]
+pub async fn delete(
+    State(AppState { pool, agents, .. }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(uuid): Path<Uuid>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+
+    tracing::debug!("deleting agent");
+    let agent = Agent::delete(&pool, &uuid).await?;
+    agents.revoke(&uuid);
+
+    Ok((StatusCode::OK, Json(agent)).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/agents/connect",
+    responses(
+        (status = StatusCode::SWITCHING_PROTOCOLS, description = "WebSocket control channel: runrs pushes `config_updated` messages with the config and its version, the agent answers with `ack` messages once it applied a version and reports `health` of gitlab-runner"),
+        (status = StatusCode::BAD_REQUEST, description = "Not a WebSocket upgrade request"),
+        (status = StatusCode::FORBIDDEN, description = "Missing or invalid agent token", body = Error),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, agents, rollout, headers, upgrade))]
+pub async fn connect(
+    State(AppState {
+        pool,
+        agents,
+        rollout,
+        ..
+    }): State<AppState>,
+    headers: HeaderMap,
+    upgrade: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+) -> Result<Response> {
+    let Some(token) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return Err(Error::forbidden("no agent token in request").into());
+    };
+
+    let Some(agent) = Agent::find_by_token(&pool, &AgentTokens::hash(token)).await? else {
+        tracing::warn!("agent token is invalid or revoked");
+        return Err(Error::forbidden("invalid agent token").into());
+    };
+
+    let upgrade = upgrade.map_err(IntoResponse::into_response)?;
+    let session = Session::new(pool, rollout, agents, agent);
+
+    Ok(upgrade.on_upgrade(|socket| async move {
+        let (outgoing, incoming) = socket.split();
+        if let Err(err) = session.run(outgoing, incoming).await {
+            tracing::warn!(%err, "agent control channel failed");
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{to_bytes, Body},
+        http::{self, Request, StatusCode},
+    };
+    use pretty_assertions::assert_eq;
+    use tower::ServiceExt; // for `call`, `oneshot`, and `ready`
+
+    use crate::{
+        app::{router, AppState},
+        auth,
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    #[tracing_test::traced_test]
+    async fn register_connect_delete(pool: atmosphere::Pool) -> Result<()> {
+        let secret = "test-secret".to_string();
+        let app_state = AppState::for_testing(pool);
+
+        let token = auth::encode_token(&secret, vec![auth::Scope::Admin])?;
+        let request = |method: http::Method, uri: &str, token: &str, body: Body| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(http::header::AUTHORIZATION, format!("Bearer {token}"))
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(body)
+        };
+
+        let unprivileged = auth::encode_token(&secret, Vec::new())?;
+        let body = || Body::from(r#"{"name": "ci-host-01"}"#);
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(request(
+                http::Method::POST,
+                "/agents",
+                &unprivileged,
+                body(),
+            )?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(request(http::Method::POST, "/agents", &token, body())?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let location = response.headers()[http::header::LOCATION]
+            .to_str()?
+            .to_string();
+        let registered: serde_json::Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        assert_eq!(registered["name"], "ci-host-01");
+        let agent_token = registered["token"].as_str().ok_or("token missing")?;
+        let uuid = registered["uuid"].as_str().ok_or("uuid missing")?;
+        assert_eq!(location, format!("/agents/{uuid}"));
+        assert_eq!(registered["links"]["self"], location);
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(request(
+                http::Method::GET,
+                &location,
+                &token,
+                Body::empty(),
+            )?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let agent: serde_json::Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        assert_eq!(agent["uuid"], uuid);
+        assert_eq!(agent["connected"], false);
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(request(http::Method::POST, "/agents", &token, body())?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // JWTs don't open a control channel, and agent tokens don't grant API access
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(request(
+                http::Method::GET,
+                "/agents/connect",
+                &token,
+                Body::empty(),
+            )?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(request(
+                http::Method::GET,
+                "/agents",
+                agent_token,
+                Body::empty(),
+            )?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // the agent token is accepted, even after rotating the secret, but a plain request can't
+        // be upgraded
+        let response = router("rotated-secret".to_string(), app_state.clone())
+            .await
+            .oneshot(request(
+                http::Method::GET,
+                "/agents/connect",
+                agent_token,
+                Body::empty(),
+            )?)
+            .await?;
+        assert!(response.status().is_client_error());
+        assert_ne!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(request(
+                http::Method::GET,
+                "/agents",
+                &token,
+                Body::empty(),
+            )?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let agents: serde_json::Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        assert_eq!(agents[0]["uuid"], uuid);
+        assert_eq!(agents[0]["connected"], false);
+        assert!(agents[0].get("token").is_none());
+
+        let path = format!("/agents/{uuid}");
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(request(http::Method::DELETE, &path, &token, Body::empty())?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // deleting the agent revokes its token
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(request(
+                http::Method::GET,
+                "/agents/connect",
+                agent_token,
+                Body::empty(),
+            )?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        Ok(())
+    }
+}
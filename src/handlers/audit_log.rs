@@ -0,0 +1,276 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response, Result},
+    Extension, Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{
+    app::AppState,
+    auth::{Claims, Scope},
+    error::Error,
+    models::{AuditEntry, AuditLogFilter, SystemEvent, SystemEventFilter},
+};
+
+/// A page of the audit log, newest entries first.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AuditLogPage {
+    entries: Vec<AuditEntry>,
+    #[schema(example = 1)]
+    page: u32,
+    #[schema(example = 50)]
+    per_page: u32,
+    /// Number of matching entries on all pages
+    #[schema(example = 123)]
+    total: u64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/audit-log",
+    params(AuditLogFilter),
+    responses(
+        (status = StatusCode::OK, description = "Page of the audit log", body = AuditLogPage),
+        (status = StatusCode::BAD_REQUEST, description = "Invalid page", body = Error),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope", body = Error),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, claims))]
+pub async fn list(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(filter): Query<AuditLogFilter>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+
+    tracing::debug!("reading audit log");
+    let (entries, total) = AuditEntry::list(&pool, &filter).await?;
+
+    let page = AuditLogPage {
+        entries,
+        page: filter.page,
+        per_page: filter.per_page,
+        total,
+    };
+
+    Ok((StatusCode::OK, Json(page)).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/audit-log/denials",
+    params(AuditLogFilter),
+    responses(
+        (status = StatusCode::OK, description = "Page of denied requests", body = AuditLogPage),
+        (status = StatusCode::BAD_REQUEST, description = "Invalid page", body = Error),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope", body = Error),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, claims))]
+pub async fn denials(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(filter): Query<AuditLogFilter>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+
+    tracing::debug!("reading denied requests from audit log");
+    let (entries, total) = AuditEntry::list_denials(&pool, &filter).await?;
+
+    let page = AuditLogPage {
+        entries,
+        page: filter.page,
+        per_page: filter.per_page,
+        total,
+    };
+
+    Ok((StatusCode::OK, Json(page)).into_response())
+}
+
+/// A page of system events, newest events first.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SystemEventPage {
+    events: Vec<SystemEvent>,
+    #[schema(example = 1)]
+    page: u32,
+    #[schema(example = 50)]
+    per_page: u32,
+    /// Number of matching events on all pages
+    #[schema(example = 12)]
+    total: u64,
+}
+
+#[utoipa::path(
+    get,
+    path = "/audit-log/system-events",
+    params(SystemEventFilter),
+    responses(
+        (status = StatusCode::OK, description = "Page of system events", body = SystemEventPage),
+        (status = StatusCode::BAD_REQUEST, description = "Invalid page", body = Error),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope", body = Error),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, claims))]
+pub async fn system_events(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(filter): Query<SystemEventFilter>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+
+    tracing::debug!("reading system events");
+    let (events, total) = SystemEvent::list(&pool, &filter).await?;
+
+    let page = SystemEventPage {
+        events,
+        page: filter.page,
+        per_page: filter.per_page,
+        total,
+    };
+
+    Ok((StatusCode::OK, Json(page)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{to_bytes, Body},
+        http::{self, Request, StatusCode},
+    };
+    use pretty_assertions::assert_eq;
+    use tower::ServiceExt; // for `call`, `oneshot`, and `ready`
+
+    use crate::{
+        app::{router, AppState},
+        auth,
+        models::GitLabRunner,
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn mutations_are_audited(pool: atmosphere::Pool) -> Result<()> {
+        let secret = "test-secret".to_string();
+        let app_state = AppState::for_testing(pool);
+
+        let token = auth::encode_token(&secret, vec![auth::Scope::Admin])?;
+
+        let runner = GitLabRunner::for_testing();
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/gitlab-runners")
+                    .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(serde_json::to_string(&runner)?))?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        // reads are not audited, unauthenticated requests are audited as denials
+        for (method, token) in [
+            (http::Method::GET, token.as_str()),
+            (http::Method::DELETE, ""),
+        ] {
+            router(secret.clone(), app_state.clone())
+                .await
+                .oneshot(
+                    Request::builder()
+                        .method(method)
+                        .uri(format!("/gitlab-runners/{}", runner.uuid()))
+                        .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                        .body(Body::empty())?,
+                )
+                .await?;
+        }
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::GET)
+                    .uri("/audit-log?path=/gitlab-runners&per_page=10")
+                    .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let page: serde_json::Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        assert_eq!(page["total"], 2);
+        assert_eq!(page["per_page"], 10);
+        assert_eq!(page["entries"][0]["method"], "DELETE");
+        assert_eq!(page["entries"][0]["status"], 401);
+        assert_eq!(page["entries"][1]["method"], "POST");
+        assert_eq!(page["entries"][1]["status"], 201);
+        assert_eq!(page["entries"][1]["actor"], "peripheral");
+        assert!(page["entries"][1].get("reason").is_none());
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn denials_are_audited(pool: atmosphere::Pool) -> Result<()> {
+        let secret = "test-secret".to_string();
+        let app_state = AppState::for_testing(pool);
+
+        let token = auth::encode_token(&secret, vec![auth::Scope::Admin])?;
+        let unscoped = auth::encode_token(&secret, Vec::new())?;
+        let forged = auth::encode_token("another-secret", vec![auth::Scope::Admin])?;
+
+        for (token, status) in [
+            (unscoped.as_str(), StatusCode::FORBIDDEN),
+            (forged.as_str(), StatusCode::UNAUTHORIZED),
+        ] {
+            let response = router(secret.clone(), app_state.clone())
+                .await
+                .oneshot(
+                    Request::builder()
+                        .method(http::Method::GET)
+                        .uri("/audit-log/denials")
+                        .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                        .body(Body::empty())?,
+                )
+                .await?;
+            assert_eq!(response.status(), status);
+        }
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::GET)
+                    .uri("/audit-log/denials")
+                    .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let page: serde_json::Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        assert_eq!(page["total"], 2);
+        assert_eq!(page["entries"][0]["path"], "/audit-log/denials");
+        assert_eq!(page["entries"][0]["actor"], "peripheral");
+        assert_eq!(page["entries"][0]["status"], 401);
+        assert!(page["entries"][0]["reason"]
+            .as_str()
+            .is_some_and(|reason| reason.starts_with("invalid token")));
+        assert_eq!(page["entries"][1]["actor"], "peripheral");
+        assert!(page["entries"][1]["reason"]
+            .as_str()
+            .is_some_and(|reason| reason.contains("Admin scope")));
+
+        Ok(())
+    }
+}
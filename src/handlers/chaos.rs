@@ -0,0 +1,173 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response, Result},
+    Extension, Json,
+};
+use utoipa::OpenApi;
+
+use crate::{
+    app::AppState,
+    auth::{Claims, Scope},
+    chaos::FaultSet,
+    error::Error,
+};
+
+/// The chaos endpoints, merged into the API documentation only if they are compiled in.
+#[derive(OpenApi)]
+#[openapi(paths(read, replace, reset), components(schemas(FaultSet)))]
+pub struct ChaosApi;
+
+#[utoipa::path(
+    get,
+    path = "/chaos",
+    responses(
+        (status = StatusCode::OK, description = "Faults currently injected", body = FaultSet),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope", body = Error)
+    )
+)]
+#[tracing::instrument(skip(faults, claims))]
+pub async fn read(
+    State(AppState { faults, .. }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+
+    Ok((StatusCode::OK, Json(faults.load())).into_response())
+}
+
+#[utoipa::path(
+    put,
+    path = "/chaos",
+    request_body = FaultSet,
+    responses(
+        (status = StatusCode::OK, description = "Faults injected from now on", body = FaultSet),
+        (status = StatusCode::BAD_REQUEST, description = "Latency out of range", body = Error),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope", body = Error)
+    )
+)]
+#[tracing::instrument(skip(faults, claims))]
+pub async fn replace(
+    State(AppState { faults, .. }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(fault_set): Json<FaultSet>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+
+    faults.replace(fault_set)?;
+
+    Ok((StatusCode::OK, Json(faults.load())).into_response())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/chaos",
+    responses(
+        (status = StatusCode::NO_CONTENT, description = "All faults cleared"),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope", body = Error)
+    )
+)]
+#[tracing::instrument(skip(faults, claims))]
+pub async fn reset(
+    State(AppState { faults, .. }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+
+    faults.replace(FaultSet::default())?;
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{self, Request, StatusCode},
+    };
+    use pretty_assertions::assert_eq;
+    use tower::ServiceExt; // for `call`, `oneshot`, and `ready`
+
+    use crate::{
+        app::{router, AppState},
+        auth,
+        chaos::FaultSet,
+        models::{GitLabRunner, Task, CONFIG_WRITE_TASK},
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn inject_config_write_failure(pool: atmosphere::Pool) -> Result<()> {
+        let secret = "test-secret".to_string();
+        let app_state = AppState::for_testing(pool);
+        let token = auth::encode_token(&secret, vec![auth::Scope::Admin])?;
+
+        let request = |method: http::Method, uri: &str, token: &str, body: Body| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(body)
+        };
+
+        let faults = FaultSet {
+            config_write_failure: true,
+            ..Default::default()
+        };
+        let faults_json = serde_json::to_string(&faults)?;
+        let body = || Body::from(faults_json.clone());
+
+        let unscoped = auth::encode_token(&secret, vec![])?;
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(request(http::Method::PUT, "/chaos", &unscoped, body())?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(app_state.faults.load(), FaultSet::default());
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(request(http::Method::PUT, "/chaos", &token, body())?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(app_state.faults.load(), faults);
+
+        // the runner is stored, but the config can't be written
+        let runner = Body::from(serde_json::to_string(&GitLabRunner::for_testing())?);
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(request(
+                http::Method::POST,
+                "/gitlab-runners",
+                &token,
+                runner,
+            )?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert!(!app_state.config_target.load().exists());
+        let task = Task::find(&app_state.pool, CONFIG_WRITE_TASK)
+            .await?
+            .ok_or("no queued write")?;
+        assert!(task
+            .last_error()
+            .is_some_and(|err| err.ends_with("simulated config write failure")));
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(request(
+                http::Method::DELETE,
+                "/chaos",
+                &token,
+                Body::empty(),
+            )?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(app_state.faults.load(), FaultSet::default());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,862 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::path::PathBuf;
+
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response, Result},
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    app::AppState,
+    auth::{Claims, Scope},
+    error::Error,
+    models::{
+        Adoption, ConfigRevision, ConfigTarget, GitLabRunnerConfig, GlobalConfig, OrphanRunner,
+        SessionServerConfig, Task, CONFIG_WRITE_TASK,
+    },
+};
+
+/// State of the config file with respect to the runners in the database.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ConfigStatus {
+    /// Data version of the runners in the database
+    #[schema(example = 42)]
+    data_version: u64,
+    /// Whether a failed config write is queued for retry
+    pending: bool,
+    /// The queued config write, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pending_write: Option<Task>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/config/status",
+    responses(
+        (status = StatusCode::OK, description = "Status of the config file", body = ConfigStatus),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, config_cache))]
+pub async fn status(
+    State(AppState {
+        pool, config_cache, ..
+    }): State<AppState>,
+) -> Result<Response> {
+    tracing::debug!("reading config status");
+
+    let pending_write = Task::find(&pool, CONFIG_WRITE_TASK).await?;
+
+    let status = ConfigStatus {
+        data_version: config_cache.version(),
+        pending: pending_write.is_some(),
+        pending_write,
+    };
+
+    Ok((StatusCode::OK, Json(status)).into_response())
+}
+
+/// Path the config is written to.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ConfigTargetStatus {
+    /// Path config writes currently go to
+    #[schema(example = "/etc/gitlab-runner/config.toml")]
+    path: String,
+    /// Path the service was started with, i.e. `CONFIG_PATH`
+    #[schema(example = "/etc/gitlab-runner/config.toml")]
+    initial_path: String,
+}
+
+impl ConfigTargetStatus {
+    fn new(config_target: &ConfigTarget) -> Self {
+        Self {
+            path: config_target.load().display().to_string(),
+            initial_path: config_target.initial().display().to_string(),
+        }
+    }
+}
+
+/// Request to switch the path the config is written to.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ConfigTargetUpdate {
+    /// Absolute path of the config file; its directory must exist and be the one of `CONFIG_PATH`
+    /// or listed in `CONFIG_TARGET_DIRS`
+    #[schema(example = "/etc/gitlab-runner-staging/config.toml")]
+    path: PathBuf,
+}
+
+#[utoipa::path(
+    get,
+    path = "/config/target",
+    responses(
+        (status = StatusCode::OK, description = "Path the config is written to", body = ConfigTargetStatus)
+    )
+)]
+#[tracing::instrument(skip(config_target))]
+pub async fn target(State(AppState { config_target, .. }): State<AppState>) -> Result<Response> {
+    Ok((
+        StatusCode::OK,
+        Json(ConfigTargetStatus::new(&config_target)),
+    )
+        .into_response())
+}
+
+#[utoipa::path(
+    put,
+    path = "/config/target",
+    request_body(
+        content = ConfigTargetUpdate, description = "Path to write the config to", content_type = "application/json"
+    ),
+    responses(
+        (status = StatusCode::OK, description = "Config written to the new path", body = ConfigTargetStatus),
+        (status = StatusCode::ACCEPTED, description = "Path switched, config write pending", body = ConfigTargetStatus),
+        (status = StatusCode::BAD_REQUEST, description = "Path is not absolute or its directory doesn't exist", body = Error),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, config_target, config_cache, settings, claims))]
+pub async fn switch_target(
+    State(AppState {
+        pool,
+        config_target,
+        config_cache,
+        settings,
+        ..
+    }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(update): Json<ConfigTargetUpdate>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+
+    let previous = config_target.switch(update.path)?;
+    let config_path = config_target.load();
+    tracing::info!(
+        target: "runrs::audit",
+        previous = %previous.display(),
+        path = %config_path.display(),
+        "switched config target"
+    );
+
+    // the new target must reflect the runners right away, not only after the next mutation
+    let sync = GitLabRunnerConfig::write_or_queue(
+        &pool,
+        &config_path,
+        &config_cache,
+        &settings.load().render,
+    )
+    .await?;
+
+    Ok((
+        sync.status_code(StatusCode::OK),
+        Json(ConfigTargetStatus::new(&config_target)),
+    )
+        .into_response())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/config/target",
+    responses(
+        (status = StatusCode::OK, description = "Config written to the initial path", body = ConfigTargetStatus),
+        (status = StatusCode::ACCEPTED, description = "Path reset, config write pending", body = ConfigTargetStatus),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, config_target, config_cache, settings, claims))]
+pub async fn reset_target(
+    State(AppState {
+        pool,
+        config_target,
+        config_cache,
+        settings,
+        ..
+    }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+
+    let previous = config_target.reset();
+    let config_path = config_target.load();
+    tracing::info!(
+        target: "runrs::audit",
+        previous = %previous.display(),
+        path = %config_path.display(),
+        "reset config target"
+    );
+
+    let sync = GitLabRunnerConfig::write_or_queue(
+        &pool,
+        &config_path,
+        &config_cache,
+        &settings.load().render,
+    )
+    .await?;
+
+    Ok((
+        sync.status_code(StatusCode::OK),
+        Json(ConfigTargetStatus::new(&config_target)),
+    )
+        .into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/config/global",
+    responses(
+        (status = StatusCode::OK, description = "Keys of the global section set via the API", body = GlobalConfig),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope, since the Sentry DSN carries its key", body = Error),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, claims))]
+pub async fn global(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+
+    tracing::debug!("reading global config");
+
+    Ok((StatusCode::OK, Json(GlobalConfig::read(&pool).await?)).into_response())
+}
+
+#[utoipa::path(
+    put,
+    path = "/config/global",
+    request_body(
+        content = GlobalConfig, description = "Keys of the global section to set; keys left out are reset", content_type = "application/json"
+    ),
+    responses(
+        (status = StatusCode::OK, description = "Global section updated and config written", body = GlobalConfig),
+        (status = StatusCode::ACCEPTED, description = "Global section updated, config write pending", body = GlobalConfig),
+        (status = StatusCode::BAD_REQUEST, description = "concurrent set while autoscaling is on", body = Error),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope", body = Error),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, config_target, config_cache, settings, claims))]
+pub async fn update_global(
+    State(AppState {
+        pool,
+        config_target,
+        config_cache,
+        settings,
+        ..
+    }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(config): Json<GlobalConfig>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+    let settings = settings.load();
+
+    config.store(&pool, &settings).await?;
+    tracing::info!(target: "runrs::audit", ?config, "updated global config");
+
+    config_cache.bump();
+    let sync = GitLabRunnerConfig::write_or_queue(
+        &pool,
+        &config_target.load(),
+        &config_cache,
+        &settings.render,
+    )
+    .await?;
+
+    Ok((sync.status_code(StatusCode::OK), Json(config)).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/config/session-server",
+    responses(
+        (status = StatusCode::OK, description = "Settings of the session server", body = SessionServerConfig),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool))]
+pub async fn session_server(State(AppState { pool, .. }): State<AppState>) -> Result<Response> {
+    tracing::debug!("reading session server config");
+    let config = SessionServerConfig::read(&pool).await?;
+
+    Ok((StatusCode::OK, Json(config)).into_response())
+}
+
+#[utoipa::path(
+    put,
+    path = "/config/session-server",
+    request_body(
+        content = SessionServerConfig, description = "Session server settings; without a listen_address, the session server is disabled", content_type = "application/json"
+    ),
+    responses(
+        (status = StatusCode::OK, description = "Session server updated and config written", body = SessionServerConfig),
+        (status = StatusCode::ACCEPTED, description = "Session server updated, config write pending", body = SessionServerConfig),
+        (status = StatusCode::BAD_REQUEST, description = "Settings given without a listen_address, or session_timeout is zero", body = Error),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope", body = Error),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, config_target, config_cache, settings, claims))]
+pub async fn update_session_server(
+    State(AppState {
+        pool,
+        config_target,
+        config_cache,
+        settings,
+        ..
+    }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(config): Json<SessionServerConfig>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+
+    config.store(&pool).await?;
+    tracing::info!(target: "runrs::audit", ?config, "updated session server config");
+
+    config_cache.bump();
+    let sync = GitLabRunnerConfig::write_or_queue(
+        &pool,
+        &config_target.load(),
+        &config_cache,
+        &settings.load().render,
+    )
+    .await?;
+
+    Ok((sync.status_code(StatusCode::OK), Json(config)).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/config/orphans",
+    responses(
+        (status = StatusCode::OK, description = "Runners in the config file missing from the database", body = [OrphanRunner]),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, config_target))]
+pub async fn orphans(
+    State(AppState {
+        pool,
+        config_target,
+        ..
+    }): State<AppState>,
+) -> Result<Response> {
+    tracing::debug!("finding orphaned runners");
+
+    let orphans = OrphanRunner::find(&pool, &config_target.load()).await?;
+
+    Ok((StatusCode::OK, Json(orphans)).into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/config/orphans/adopt",
+    responses(
+        (status = StatusCode::OK, description = "Orphans adopted into the database", body = Adoption),
+        (status = StatusCode::ACCEPTED, description = "Orphans adopted, config write pending", body = Adoption),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope", body = Error),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, config_target, config_cache, settings, claims))]
+pub async fn adopt_orphans(
+    State(AppState {
+        pool,
+        config_target,
+        config_cache,
+        settings,
+        ..
+    }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+    let settings = settings.load();
+    let config_path = config_target.load();
+
+    tracing::debug!("adopting orphaned runners");
+
+    let adoption = OrphanRunner::adopt(&pool, &config_path, &settings).await?;
+
+    config_cache.bump();
+    let sync =
+        GitLabRunnerConfig::write_or_queue(&pool, &config_path, &config_cache, &settings.render)
+            .await?;
+
+    Ok((sync.status_code(StatusCode::OK), Json(adoption)).into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/config/orphans/purge",
+    responses(
+        (status = StatusCode::OK, description = "Orphans removed from the config file", body = [OrphanRunner]),
+        (status = StatusCode::ACCEPTED, description = "Orphans are removed once the pending config write succeeds", body = [OrphanRunner]),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope", body = Error),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, config_target, config_cache, settings, claims))]
+pub async fn purge_orphans(
+    State(AppState {
+        pool,
+        config_target,
+        config_cache,
+        settings,
+        ..
+    }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+    let settings = settings.load();
+    let config_path = config_target.load();
+
+    let orphans = OrphanRunner::find(&pool, &config_path).await?;
+    tracing::info!(count = orphans.len(), "purging orphaned runners");
+
+    // the config is rendered from the database, so rewriting it drops the orphans
+    let sync =
+        GitLabRunnerConfig::write_or_queue(&pool, &config_path, &config_cache, &settings.render)
+            .await?;
+
+    Ok((sync.status_code(StatusCode::OK), Json(orphans)).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/config/revisions/at/{timestamp}",
+    params(
+        ("timestamp" = String, Path, format = DateTime, description = "Point in time, e.g. `2024-07-02T12:00:00Z`")
+    ),
+    responses(
+        (status = StatusCode::OK, description = "The config written last before the timestamp, with the time it was written as `Last-Modified`", body = String, content_type = "application/toml"),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope", body = Error),
+        (status = StatusCode::NOT_FOUND, description = "No config was written before the timestamp", body = Error),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, claims))]
+pub async fn revision_at(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(timestamp): Path<DateTime<Utc>>,
+) -> Result<Response> {
+    // like the config on disk, revisions contain the runner tokens in plain text
+    claims.require_scope(Scope::Admin)?;
+
+    tracing::debug!("reading config revision");
+
+    let revision = ConfigRevision::at(&pool, timestamp)
+        .await?
+        .ok_or_else(|| Error::not_found(format!("no config was written before {timestamp}")))?;
+
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "application/toml".to_string()),
+            (
+                header::LAST_MODIFIED,
+                revision
+                    .written_at()
+                    .format("%a, %d %b %Y %H:%M:%S GMT")
+                    .to_string(),
+            ),
+        ],
+        revision.config().to_string(),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{
+        body::{to_bytes, Body},
+        http::{self, Request, StatusCode},
+    };
+    use pretty_assertions::assert_eq;
+    use tower::ServiceExt; // for `call`, `oneshot`, and `ready`
+
+    use crate::{
+        app::{router, AppState},
+        auth,
+        models::{ConfigTarget, GitLabRunner},
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    #[tracing_test::traced_test]
+    async fn pending_write(pool: atmosphere::Pool) -> Result<()> {
+        let secret = "test-secret".to_string();
+        let mut app_state = AppState::for_testing(pool);
+        app_state.config_target = Arc::new(ConfigTarget::new(
+            "/nonexistent/gitlab-runner/config.toml".into(),
+        ));
+
+        let token = auth::encode_token(&secret, vec![auth::Scope::Admin])?;
+
+        let runner = GitLabRunner::for_testing();
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/gitlab-runners")
+                    .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(serde_json::to_string(&runner)?))?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::GET)
+                    .uri("/config/status")
+                    .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let status: serde_json::Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        assert_eq!(status["pending"], true);
+        assert_eq!(status["data_version"], 1);
+        assert_eq!(status["pending_write"]["attempts"], 1);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    #[tracing_test::traced_test]
+    async fn switch_target(pool: atmosphere::Pool) -> Result<()> {
+        let secret = "test-secret".to_string();
+        let app_state = AppState::for_testing(pool);
+        let initial_path = app_state.config_target.initial().to_path_buf();
+        let staging_path =
+            initial_path.with_file_name(format!("runrs-staging-{}.toml", uuid::Uuid::new_v4()));
+
+        let token = auth::encode_token(&secret, vec![auth::Scope::Admin])?;
+        let switch = |path: &str| -> Result<Request<Body>> {
+            Ok(Request::builder()
+                .method(http::Method::PUT)
+                .uri("/config/target")
+                .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(Body::from(serde_json::json!({ "path": path }).to_string()))?)
+        };
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(switch("relative/config.toml")?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // only the directory of the initial path is allowed without `CONFIG_TARGET_DIRS`
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(switch("/runrs-config.toml")?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(switch(&staging_path.display().to_string())?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let status: serde_json::Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        assert_eq!(status["path"], staging_path.display().to_string());
+        assert_eq!(status["initial_path"], initial_path.display().to_string());
+        assert!(staging_path.exists());
+        assert!(logs_contain("switched config target"));
+
+        // writes following the switch go to the new target
+        let runner = GitLabRunner::for_testing();
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/gitlab-runners")
+                    .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(serde_json::to_string(&runner)?))?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert!(std::fs::read_to_string(&staging_path)?.contains("Knows the meaning of life"));
+        assert!(!initial_path.exists());
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::DELETE)
+                    .uri("/config/target")
+                    .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(std::fs::read_to_string(&initial_path)?.contains("Knows the meaning of life"));
+
+        std::fs::remove_file(&staging_path)?;
+        std::fs::remove_file(&initial_path)?;
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    #[tracing_test::traced_test]
+    async fn update_global(pool: atmosphere::Pool) -> Result<()> {
+        let secret = "test-secret".to_string();
+        let mut app_state = AppState::for_testing(pool);
+        let config_path =
+            std::env::temp_dir().join(format!("runrs-global-{}.toml", uuid::Uuid::new_v4()));
+        app_state.config_target = Arc::new(ConfigTarget::new(config_path.clone()));
+
+        let token = auth::encode_token(&secret, vec![auth::Scope::Admin])?;
+        let update = |body: serde_json::Value| -> Result<Request<Body>> {
+            Ok(Request::builder()
+                .method(http::Method::PUT)
+                .uri("/config/global")
+                .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(Body::from(body.to_string()))?)
+        };
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(update(serde_json::json!({ "check_interval": 10 }))?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(std::fs::read_to_string(&config_path)?.contains("check_interval = 10"));
+        assert!(logs_contain("updated global config"));
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::GET)
+                    .uri("/config/global")
+                    .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let global: serde_json::Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        assert_eq!(global, serde_json::json!({ "check_interval": 10 }));
+
+        let unscoped = auth::encode_token(&secret, vec![])?;
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::GET)
+                    .uri("/config/global")
+                    .header(http::header::AUTHORIZATION, format!("Bearer {}", unscoped))
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(update(serde_json::json!({ "check_intervall": 10 }))?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        std::fs::remove_file(&config_path)?;
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn update_session_server(pool: atmosphere::Pool) -> Result<()> {
+        let secret = "test-secret".to_string();
+        let mut app_state = AppState::for_testing(pool);
+        let config_path =
+            std::env::temp_dir().join(format!("runrs-session-{}.toml", uuid::Uuid::new_v4()));
+        app_state.config_target = Arc::new(ConfigTarget::new(config_path.clone()));
+
+        let token = auth::encode_token(&secret, vec![auth::Scope::Admin])?;
+        let update = |body: serde_json::Value| -> Result<Request<Body>> {
+            Ok(Request::builder()
+                .method(http::Method::PUT)
+                .uri("/config/session-server")
+                .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(Body::from(body.to_string()))?)
+        };
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(update(serde_json::json!({ "session_timeout": 600 }))?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(update(serde_json::json!({
+                "listen_address": "http://[::]:8093",
+            }))?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(update(serde_json::json!({
+                "listen_address": "[::]:8093",
+                "session_timeout": 600,
+            }))?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let config_toml = std::fs::read_to_string(&config_path)?;
+        assert!(config_toml.contains("[session_server]"));
+        assert!(config_toml.contains("listen_address = \"[::]:8093\""));
+        assert!(config_toml.contains("session_timeout = 600"));
+
+        // without a listen address, the section is dropped again
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(update(serde_json::json!({}))?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!std::fs::read_to_string(&config_path)?.contains("[session_server]"));
+
+        std::fs::remove_file(&config_path)?;
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    #[tracing_test::traced_test]
+    async fn purge_orphans(pool: atmosphere::Pool) -> Result<()> {
+        let secret = "test-secret".to_string();
+        let mut app_state = AppState::for_testing(pool);
+        let config_path =
+            std::env::temp_dir().join(format!("runrs-purge-{}.toml", uuid::Uuid::new_v4()));
+        app_state.config_target = Arc::new(ConfigTarget::new(config_path.clone()));
+        std::fs::write(
+            &config_path,
+            r#"
+            [[runners]]
+              name = "orphan"
+              url = "https://gitlab.bmc-labs.com"
+              token = "glrt-warblgarblwarblgarbl"
+              executor = "shell"
+            "#,
+        )?;
+
+        let token = auth::encode_token(&secret, vec![auth::Scope::Admin])?;
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::GET)
+                    .uri("/config/orphans")
+                    .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let orphans: serde_json::Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        assert_eq!(orphans[0]["name"], "orphan");
+        assert_eq!(orphans[0]["token"], "glrt-****rbl");
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/config/orphans/purge")
+                    .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let config_toml = std::fs::read_to_string(&config_path)?;
+        assert!(!config_toml.contains("glrt-warblgarblwarblgarbl"));
+        std::fs::remove_file(&config_path)?;
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn revision_at(pool: atmosphere::Pool) -> Result<()> {
+        let secret = "test-secret".to_string();
+        let app_state = AppState::for_testing(pool);
+        let token = auth::encode_token(&secret, vec![auth::Scope::Admin])?;
+
+        let request = |uri: &str, token: &str| {
+            Request::builder()
+                .method(http::Method::GET)
+                .uri(uri)
+                .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                .body(Body::empty())
+        };
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/gitlab-runners")
+                    .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(serde_json::to_string(
+                        &GitLabRunner::for_testing(),
+                    )?))?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let now = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        let uri = format!("/config/revisions/at/{now}");
+
+        // revisions hold plain text tokens, so only admins may read them
+        let unscoped = auth::encode_token(&secret, vec![])?;
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(request(&uri, &unscoped)?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(request(&uri, &token)?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().contains_key(http::header::LAST_MODIFIED));
+        let config_toml = to_bytes(response.into_body(), usize::MAX).await?;
+        assert!(std::str::from_utf8(&config_toml)?.contains("Knows the meaning of life"));
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(request(
+                "/config/revisions/at/2000-01-01T00:00:00Z",
+                &token,
+            )?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        std::fs::remove_file(&*app_state.config_target.load())?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use crate::error::ErrorCodeInfo;
+
+#[utoipa::path(
+    get,
+    path = "/error-codes",
+    responses(
+        (status = StatusCode::OK, description = "Catalogue of the error codes in error responses", body = [ErrorCodeInfo])
+    ),
+    security(())
+)]
+#[tracing::instrument]
+pub async fn error_codes() -> Response {
+    (StatusCode::OK, Json(ErrorCodeInfo::catalogue())).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{to_bytes, Body},
+        http::{self, Request, StatusCode},
+    };
+    use pretty_assertions::assert_eq;
+    use tower::ServiceExt; // for `call`, `oneshot`, and `ready`
+
+    use crate::app::{router, AppState};
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn error_codes_without_token(pool: atmosphere::Pool) -> Result<()> {
+        let response = router("test-secret".to_string(), AppState::for_testing(pool))
+            .await
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::GET)
+                    .uri("/error-codes")
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let catalogue: serde_json::Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        let quota = catalogue
+            .as_array()
+            .and_then(|codes| {
+                codes
+                    .iter()
+                    .find(|code| code["code"] == "RUNRS-E-QUOTA-EXCEEDED")
+            })
+            .ok_or("quota code missing")?;
+        assert_eq!(quota["err_type"], "QuotaExceeded");
+        assert_eq!(quota["status"], 403);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,185 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response, Result},
+    Extension,
+};
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    app::AppState,
+    auth::{Claims, Scope},
+    error::Error,
+    models::{GitLabRunner, GitLabRunnerConfig},
+};
+
+/// Format of an export.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    /// Newline-delimited JSON, which `POST /import` takes as is
+    #[default]
+    Json,
+    /// The config file as `gitlab-runner` reads it, including runner tokens; paused runners are
+    /// listed in `[[paused_runners]]`, which `gitlab-runner` ignores but `POST /import` reads
+    Toml,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportOptions {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+#[utoipa::path(
+    get,
+    path = "/export",
+    params(ExportOptions),
+    responses(
+        (status = StatusCode::OK, description = "All GitLabRunners as newline-delimited JSON, or a gitlab-runner config.toml with the paused ones in [[paused_runners]]", body = GitLabRunner, content_type = ["application/x-ndjson", "application/toml"]),
+        (status = StatusCode::FORBIDDEN, description = "TOML export requested by a token lacking the admin scope", body = Error),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, config_cache, settings, secrets, claims))]
+pub async fn export(
+    State(AppState {
+        pool,
+        config_cache,
+        settings,
+        secrets,
+        ..
+    }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(options): Query<ExportOptions>,
+) -> Result<Response> {
+    tracing::debug!(format = ?options.format, "exporting runners");
+
+    match options.format {
+        ExportFormat::Json => Ok((
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "application/x-ndjson")],
+            Body::from_stream(GitLabRunner::stream_ndjson(pool)),
+        )
+            .into_response()),
+        ExportFormat::Toml => {
+            // unlike the JSON export, the config file contains the runner tokens in plain text
+            claims.require_scope(Scope::Admin)?;
+
+            let options = &settings.load().render;
+            let mut config_toml = config_cache.render(&pool, options).await?;
+            config_toml
+                .push_str(&GitLabRunnerConfig::render_paused(&pool, &secrets, options).await?);
+            Ok((
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, "application/toml")],
+                config_toml,
+            )
+                .into_response())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use atmosphere::{Create as _, Delete as _, Read as _};
+    use axum::{
+        body::{to_bytes, Body},
+        http::{self, Request, StatusCode},
+    };
+    use pretty_assertions::assert_eq;
+    use tower::ServiceExt; // for `call`, `oneshot`, and `ready`
+
+    use crate::{
+        app::{router, AppState},
+        auth,
+        models::GitLabRunner,
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    #[tracing_test::traced_test]
+    async fn export_toml_and_import(pool: atmosphere::Pool) -> Result<()> {
+        let secret = "test-secret".to_string();
+        let app_state = AppState::for_testing(pool.clone());
+        let token = auth::encode_token(&secret, vec![auth::Scope::Admin])?;
+
+        let mut runner = GitLabRunner::for_testing();
+        runner.set_job_limit(3);
+        runner.create(&pool).await?;
+
+        let mut paused = GitLabRunner::for_testing();
+        paused.set_token("glrt-paused_abcdefXYZ012");
+        paused.pause();
+        paused.create(&pool).await?;
+
+        // the config file holds plain text tokens, so only admins may export it
+        let unscoped = auth::encode_token(&secret, vec![])?;
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(
+                Request::builder()
+                    .uri("/export?format=toml")
+                    .header(http::header::AUTHORIZATION, format!("Bearer {}", unscoped))
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(
+                Request::builder()
+                    .uri("/export?format=toml")
+                    .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[http::header::CONTENT_TYPE],
+            "application/toml"
+        );
+        let config_toml = to_bytes(response.into_body(), usize::MAX).await?;
+        assert!(toml::from_str::<toml::Value>(std::str::from_utf8(&config_toml)?).is_ok());
+
+        // importing the export into an empty database yields the same runner
+        runner.delete(&pool).await?;
+        paused.delete(&pool).await?;
+        let response = router(secret, app_state.clone())
+            .await
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/import")
+                    .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                    .header(http::header::CONTENT_TYPE, "application/toml")
+                    .body(Body::from(config_toml))?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        to_bytes(response.into_body(), usize::MAX).await?;
+
+        let mut imported = GitLabRunner::read_all(&pool).await?;
+        imported.sort_by_key(GitLabRunner::paused);
+        assert_eq!(imported.len(), 2);
+        assert_eq!(
+            (imported[0].id(), imported[0].url(), imported[0].job_limit()),
+            (runner.id(), runner.url(), Some(3))
+        );
+        assert!(!imported[0].paused());
+        // the paused runner is not in `[[runners]]`, but comes back from `[[paused_runners]]`
+        assert_eq!(imported[1].masked_token(), paused.masked_token());
+        assert!(imported[1].paused());
+
+        std::fs::remove_file(&*app_state.config_target.load())?;
+
+        Ok(())
+    }
+}
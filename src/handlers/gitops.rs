@@ -0,0 +1,51 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{app::AppState, gitops::ReconcileReport};
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct GitOpsStatus {
+    /// Whether the runners are managed via GitOps, in which case they can't be changed via the
+    /// API
+    enabled: bool,
+    /// Branch the runners are read from
+    #[schema(example = "main")]
+    branch: String,
+    /// Outcome of the last reconciliation, if there was one since runrs started
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_reconciliation: Option<ReconcileReport>,
+}
+
+#[utoipa::path(
+    get,
+    path = "/gitops",
+    responses(
+        (status = StatusCode::OK, description = "Whether GitOps is on, and how the last reconciliation went", body = GitOpsStatus)
+    )
+)]
+#[tracing::instrument(skip(settings, gitops))]
+pub async fn status(
+    State(AppState {
+        settings, gitops, ..
+    }): State<AppState>,
+) -> Response {
+    let settings = settings.load();
+
+    (
+        StatusCode::OK,
+        Json(GitOpsStatus {
+            enabled: settings.gitops.enabled(),
+            branch: settings.gitops.branch.clone(),
+            last_reconciliation: gitops.last(),
+        }),
+    )
+        .into_response()
+}
@@ -0,0 +1,248 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{fs::OpenOptions, path::PathBuf};
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::app::AppState;
+
+/// Whether runrs, or one of its checks, is healthy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Ok,
+    Unavailable,
+}
+
+/// Outcome of a single readiness check.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HealthCheck {
+    /// What was checked, e.g. `database`
+    #[schema(example = "database")]
+    name: &'static str,
+    status: HealthStatus,
+    /// Why the check failed, if it did
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+impl HealthCheck {
+    fn new(name: &'static str, outcome: Result<(), String>) -> Self {
+        match outcome {
+            Ok(()) => Self {
+                name,
+                status: HealthStatus::Ok,
+                message: None,
+            },
+            Err(message) => {
+                tracing::error!(check = name, %message, "readiness check failed");
+                Self {
+                    name,
+                    status: HealthStatus::Unavailable,
+                    message: Some(message),
+                }
+            }
+        }
+    }
+}
+
+/// Health of runrs as a whole: it's only ok if all of its checks are.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Health {
+    status: HealthStatus,
+    checks: Vec<HealthCheck>,
+}
+
+impl Health {
+    fn new(checks: Vec<HealthCheck>) -> Self {
+        let status = if checks.iter().all(|check| check.status == HealthStatus::Ok) {
+            HealthStatus::Ok
+        } else {
+            HealthStatus::Unavailable
+        };
+
+        Self { status, checks }
+    }
+}
+
+impl IntoResponse for Health {
+    fn into_response(self) -> Response {
+        let status_code = match self.status {
+            HealthStatus::Ok => StatusCode::OK,
+            HealthStatus::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        };
+
+        (status_code, Json(self)).into_response()
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    responses(
+        (status = StatusCode::OK, description = "runrs is up; it doesn't check any dependencies", body = Health)
+    ),
+    security(())
+)]
+#[tracing::instrument]
+pub async fn healthz() -> Health {
+    Health::new(Vec::new())
+}
+
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    responses(
+        (status = StatusCode::OK, description = "The database is reachable and the config path writable", body = Health),
+        (status = StatusCode::SERVICE_UNAVAILABLE, description = "At least one check failed", body = Health)
+    ),
+    security(())
+)]
+#[tracing::instrument(skip(pool, config_target))]
+pub async fn readyz(
+    State(AppState {
+        pool,
+        config_target,
+        ..
+    }): State<AppState>,
+) -> Health {
+    let database = sqlx::query("SELECT 1")
+        .execute(&pool)
+        .await
+        .map(|_| ())
+        .map_err(|err| format!("database unreachable: {err}"));
+    let config_path = ensure_writable(config_target.load().to_path_buf()).await;
+
+    Health::new(vec![
+        HealthCheck::new("database", database),
+        HealthCheck::new("config_path", config_path),
+    ])
+}
+
+/// Checks that a config can be written to `path`, i.e. that a file can be created next to it and
+/// moved into place, the way config writes work. Each check uses a probe file of its own, so that
+/// concurrent probes don't remove each other's file.
+async fn ensure_writable(path: PathBuf) -> Result<(), String> {
+    let mut file_name = std::ffi::OsString::from(".");
+    file_name.push(path.file_name().unwrap_or_default());
+    file_name.push(format!(".readyz-{}", Uuid::new_v4()));
+    let probe = path.with_file_name(file_name);
+
+    tokio::task::spawn_blocking(move || {
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&probe)
+            .and_then(|_| std::fs::remove_file(&probe))
+            .map_err(|err| format!("{} not writable: {err}", probe.display()))
+    })
+    .await
+    .map_err(|err| format!("checking the config path failed: {err}"))?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc};
+
+    use axum::{
+        body::{to_bytes, Body},
+        http::{Request, StatusCode},
+    };
+    use pretty_assertions::assert_eq;
+    use tower::ServiceExt; // for `call`, `oneshot`, and `ready`
+
+    use super::ensure_writable;
+    use crate::{
+        app::{router, AppState},
+        models::ConfigTarget,
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn healthz_without_token(pool: atmosphere::Pool) -> Result<()> {
+        let app_state = AppState::for_testing(pool);
+        let response = router("test-secret".to_string(), app_state.clone())
+            .await
+            .oneshot(Request::builder().uri("/healthz").body(Body::empty())?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // liveness doesn't depend on the database
+        app_state.pool.close().await;
+        let response = router("test-secret".to_string(), app_state)
+            .await
+            .oneshot(Request::builder().uri("/healthz").body(Body::empty())?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn readyz_without_token(pool: atmosphere::Pool) -> Result<()> {
+        let mut app_state = AppState::for_testing(pool);
+        let response = router("test-secret".to_string(), app_state.clone())
+            .await
+            .oneshot(Request::builder().uri("/readyz").body(Body::empty())?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let health: serde_json::Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        assert_eq!(
+            health,
+            serde_json::json!({
+                "status": "ok",
+                "checks": [
+                    { "name": "database", "status": "ok" },
+                    { "name": "config_path", "status": "ok" },
+                ],
+            })
+        );
+
+        app_state.config_target = Arc::new(ConfigTarget::new(PathBuf::from(
+            "/nonexistent/gitlab-runner/config.toml",
+        )));
+        app_state.pool.close().await;
+        let response = router("test-secret".to_string(), app_state)
+            .await
+            .oneshot(Request::builder().uri("/readyz").body(Body::empty())?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let health: serde_json::Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        assert_eq!(health["status"], "unavailable");
+        assert_eq!(health["checks"][0]["status"], "unavailable");
+        assert_eq!(health["checks"][1]["status"], "unavailable");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn concurrent_probes() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("runrs-readyz-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir)?;
+        let path = dir.join("config.toml");
+
+        let probes = (0..16).map(|_| ensure_writable(path.clone()));
+        for probe in futures::future::join_all(probes).await {
+            assert_eq!(probe, Ok(()));
+        }
+        // every probe removed its own file
+        assert_eq!(std::fs::read_dir(&dir)?.count(), 0);
+
+        std::fs::remove_dir(&dir)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::convert::Infallible;
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response, Result,
+    },
+    Extension,
+};
+use futures::StreamExt;
+
+use crate::{
+    app::AppState,
+    auth::{Claims, Scope},
+    error::Error,
+    models::{GitLabRunner, Import, ImportFormat, ImportProgress},
+};
+
+#[utoipa::path(
+    post,
+    path = "/import",
+    request_body(
+        content = GitLabRunner, description = "GitLabRunners to import, as newline-delimited JSON, or as a `gitlab-runner` config.toml with content type `application/toml`", content_type = "application/x-ndjson"
+    ),
+    responses(
+        (status = StatusCode::OK, description = "Server-sent `progress` events after each committed chunk of runners, then a final `done` event", body = ImportProgress, content_type = "text/event-stream"),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope", body = Error)
+    )
+)]
+#[tracing::instrument(skip(
+    pool,
+    config_target,
+    config_cache,
+    settings,
+    secrets,
+    claims,
+    headers,
+    body
+))]
+pub async fn import(
+    State(AppState {
+        pool,
+        config_target,
+        config_cache,
+        settings,
+        secrets,
+        ..
+    }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+
+    let format = match headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(content_type) if content_type.starts_with("application/toml") => ImportFormat::Toml,
+        _ => ImportFormat::Ndjson,
+    };
+    tracing::debug!(?format, "importing runners");
+
+    let import = Import {
+        pool,
+        config_path: config_target.load(),
+        config_cache,
+        settings: settings.load(),
+        secrets,
+        format,
+    };
+    let events = import.run(body.into_data_stream()).map(|progress| {
+        let event = if progress.done() { "done" } else { "progress" };
+        Ok::<_, Infallible>(
+            Event::default()
+                .event(event)
+                .json_data(&progress)
+                .unwrap_or_else(|err| Event::default().event("error").data(err.to_string())),
+        )
+    });
+
+    Ok((
+        StatusCode::OK,
+        Sse::new(events).keep_alive(KeepAlive::default()),
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use atmosphere::Read as _;
+    use axum::{
+        body::{to_bytes, Body},
+        http::{self, Request, StatusCode},
+    };
+    use pretty_assertions::assert_eq;
+    use tower::ServiceExt; // for `call`, `oneshot`, and `ready`
+
+    use crate::{
+        app::{router, AppState},
+        auth,
+        models::GitLabRunner,
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn import_ndjson(pool: atmosphere::Pool) -> Result<()> {
+        let secret = "test-secret".to_string();
+        let app_state = AppState::for_testing(pool.clone());
+        let token = auth::encode_token(&secret, vec![auth::Scope::Admin])?;
+
+        let body = [GitLabRunner::for_testing(), GitLabRunner::for_testing()]
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<std::result::Result<Vec<_>, _>>()?
+            .join("\n");
+        let response = router(secret, app_state.clone())
+            .await
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/import")
+                    .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                    .header(http::header::CONTENT_TYPE, "application/x-ndjson")
+                    .body(Body::from(body))?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let events = String::from_utf8(to_bytes(response.into_body(), usize::MAX).await?.to_vec())?;
+        assert!(events.contains("event: progress"));
+        assert!(events.contains("event: done"));
+        assert!(events.contains("\"imported\":2"));
+        assert_eq!(GitLabRunner::read_all(&pool).await?.len(), 2);
+
+        std::fs::remove_file(&*app_state.config_target.load())?;
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn import_requires_admin(pool: atmosphere::Pool) -> Result<()> {
+        let secret = "test-secret".to_string();
+        let app_state = AppState::for_testing(pool.clone());
+        let token = auth::encode_token(&secret, vec![])?;
+
+        let body = serde_json::to_string(&GitLabRunner::for_testing())?;
+        let response = router(secret, app_state)
+            .await
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::POST)
+                    .uri("/import")
+                    .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                    .header(http::header::CONTENT_TYPE, "application/x-ndjson")
+                    .body(Body::from(body))?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(GitLabRunner::read_all(&pool).await?.is_empty());
+
+        Ok(())
+    }
+}
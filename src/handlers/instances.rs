@@ -0,0 +1,173 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response, Result},
+    Extension, Json,
+};
+use chrono::Utc;
+use glrcfg::runner::Url;
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::{
+    app::AppState,
+    auth::{Claims, Scope},
+    error::Error,
+    models::{Maintenance, MaintenanceWindow},
+};
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct InstanceQuery {
+    /// URL of the GitLab instance
+    #[param(value_type = String, example = "https://gitlab.your-company.com")]
+    url: Url,
+}
+
+#[utoipa::path(
+    get,
+    path = "/instances/maintenance",
+    responses(
+        (status = StatusCode::OK, description = "GitLab instances in maintenance, ordered by URL", body = [MaintenanceWindow]),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool))]
+pub async fn maintenance(State(AppState { pool, .. }): State<AppState>) -> Result<Response> {
+    tracing::debug!("reading maintenance windows");
+    let maintenance = Maintenance::active(&pool, Utc::now()).await?;
+
+    Ok((StatusCode::OK, Json(maintenance.windows())).into_response())
+}
+
+#[utoipa::path(
+    put,
+    path = "/instances/maintenance",
+    request_body(
+        content = MaintenanceWindow, description = "GitLab instance to put in maintenance, and until when", content_type = "application/json"
+    ),
+    responses(
+        (status = StatusCode::OK, description = "Started or extended maintenance window", body = MaintenanceWindow),
+        (status = StatusCode::BAD_REQUEST, description = "Window ends in the past", body = Error),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope", body = Error),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, claims))]
+pub async fn start_maintenance(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(mut window): Json<MaintenanceWindow>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+
+    window.start(&pool).await?;
+    tracing::info!(url = %window.url(), ends_at = %window.ends_at(), "instance in maintenance");
+
+    Ok((StatusCode::OK, Json(window)).into_response())
+}
+
+#[utoipa::path(
+    delete,
+    path = "/instances/maintenance",
+    params(InstanceQuery),
+    responses(
+        (status = StatusCode::OK, description = "Ended maintenance window", body = MaintenanceWindow),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope", body = Error),
+        (status = StatusCode::NOT_FOUND, description = "GitLab instance not in maintenance", body = Error),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, claims))]
+pub async fn end_maintenance(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Query(InstanceQuery { url }): Query<InstanceQuery>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+
+    let window = MaintenanceWindow::end(&pool, &url).await?;
+    tracing::info!(%url, "instance back from maintenance");
+
+    Ok((StatusCode::OK, Json(window)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{to_bytes, Body},
+        http::{self, Request, StatusCode},
+    };
+    use chrono::{TimeDelta, Utc};
+    use pretty_assertions::assert_eq;
+    use tower::ServiceExt; // for `call`, `oneshot`, and `ready`
+
+    use crate::{
+        app::{router, AppState},
+        auth,
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    #[tracing_test::traced_test]
+    async fn start_list_end(pool: atmosphere::Pool) -> Result<()> {
+        let secret = "test-secret".to_string();
+        let app_state = AppState::for_testing(pool);
+
+        let token = auth::encode_token(&secret, vec![auth::Scope::Admin])?;
+        let request = |method: http::Method, uri: &str, body: Body| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(body)
+        };
+
+        let window = serde_json::json!({
+            "url": "https://gitlab.your-company.com",
+            "ends_at": Utc::now() + TimeDelta::hours(2),
+            "reason": "upgrade",
+        });
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(request(
+                http::Method::PUT,
+                "/instances/maintenance",
+                Body::from(window.to_string()),
+            )?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(request(
+                http::Method::GET,
+                "/instances/maintenance",
+                Body::empty(),
+            )?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let windows: serde_json::Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        assert_eq!(windows[0]["reason"], "upgrade");
+
+        let end = "/instances/maintenance?url=https://gitlab.your-company.com/";
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(request(http::Method::DELETE, end, Body::empty())?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(request(http::Method::DELETE, end, Body::empty())?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+}
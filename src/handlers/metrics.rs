@@ -0,0 +1,59 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::{app::AppState, metrics::OPENMETRICS_CONTENT_TYPE};
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses(
+        (status = StatusCode::OK, description = "Metrics in the OpenMetrics text format", body = String, content_type = "application/openmetrics-text")
+    ),
+    security(())
+)]
+#[tracing::instrument(skip(metrics))]
+pub async fn metrics(State(AppState { metrics, .. }): State<AppState>) -> Response {
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, OPENMETRICS_CONTENT_TYPE)],
+        metrics.render(),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{to_bytes, Body},
+        http::{self, Request, StatusCode},
+    };
+    use pretty_assertions::assert_eq;
+    use tower::ServiceExt; // for `call`, `oneshot`, and `ready`
+
+    use crate::app::{router, AppState};
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn metrics_without_token(pool: atmosphere::Pool) -> Result<()> {
+        let response = router("test-secret".to_string(), AppState::for_testing(pool))
+            .await
+            .oneshot(Request::builder().uri("/metrics").body(Body::empty())?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[http::header::CONTENT_TYPE],
+            "application/openmetrics-text; version=1.0.0; charset=utf-8"
+        );
+
+        let body = to_bytes(response.into_body(), usize::MAX).await?;
+        assert!(body.ends_with(b"# EOF\n"));
+
+        Ok(())
+    }
+}
@@ -1,3 +1,22 @@
 // Copyright 2024 bmc::labs GmbH. All rights reserved.
 
+pub(crate) mod admin;
+pub(crate) mod agents;
+pub(crate) mod audit_log;
+#[cfg(feature = "chaos")]
+pub(crate) mod chaos;
+pub(crate) mod config;
+pub(crate) mod error_codes;
+pub(crate) mod export;
 pub(crate) mod gitlab_runners;
+pub(crate) mod gitops;
+pub(crate) mod health;
+pub(crate) mod import;
+pub(crate) mod instances;
+pub(crate) mod metrics;
+pub(crate) mod plan;
+pub(crate) mod rollout;
+pub(crate) mod runtime_settings;
+pub(crate) mod stats;
+pub(crate) mod tasks;
+pub(crate) mod version;
@@ -0,0 +1,177 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response, Result},
+    Extension, Json,
+};
+
+use crate::{
+    app::AppState,
+    auth::{Claims, Scope},
+    error::Error,
+    models::GitLabRunnerConfig,
+    plan::{DesiredRunners, Plan, PlanApply},
+};
+
+#[utoipa::path(
+    post,
+    path = "/plan",
+    request_body(
+        content = DesiredRunners, description = "Full set of runners there should be; stored runners which aren't in it are deleted", content_type = "application/json"
+    ),
+    responses(
+        (status = StatusCode::OK, description = "Runners which applying the plan creates, updates and deletes, along with the resulting config diff; the plan expires after an hour", body = Plan),
+        (status = StatusCode::BAD_REQUEST, description = "Invalid or duplicate runner, or expired token", body = Error),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope or quota exceeded", body = Error),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, settings, secrets, plans, claims, desired))]
+pub async fn plan(
+    State(AppState {
+        pool,
+        settings,
+        secrets,
+        plans,
+        ..
+    }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(desired): Json<DesiredRunners>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+
+    tracing::debug!(runners = desired.runners.len(), "planning runners");
+    let settings = settings.load();
+
+    let plan = Plan::make(&pool, &secrets, &settings, desired.runners).await?;
+    plans.insert(plan.clone());
+
+    Ok((StatusCode::OK, Json(plan)).into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/apply",
+    request_body(
+        content = PlanApply, description = "Plan to apply, as returned by `POST /plan`", content_type = "application/json"
+    ),
+    responses(
+        (status = StatusCode::OK, description = "Applied plan", body = Plan),
+        (status = StatusCode::ACCEPTED, description = "Applied plan, config write pending", body = Plan),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope or quota exceeded", body = Error),
+        (status = StatusCode::NOT_FOUND, description = "Plan not found, expired or applied already", body = Error),
+        (status = StatusCode::PRECONDITION_FAILED, description = "Runners changed since the plan was made", body = Error),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, config_target, config_cache, settings, plans, claims))]
+pub async fn apply(
+    State(AppState {
+        pool,
+        config_target,
+        config_cache,
+        settings,
+        plans,
+        ..
+    }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(PlanApply { plan_id }): Json<PlanApply>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+
+    tracing::debug!(%plan_id, "applying plan");
+    let settings = settings.load();
+
+    let plan = plans.take(&plan_id)?;
+    if plan.is_empty() {
+        return Ok((StatusCode::OK, Json(plan)).into_response());
+    }
+
+    plan.apply(&pool, &settings, Some(claims.issuer())).await?;
+    tracing::debug!("plan applied");
+
+    config_cache.bump();
+    let sync = GitLabRunnerConfig::write_or_queue(
+        &pool,
+        &config_target.load(),
+        &config_cache,
+        &settings.render,
+    )
+    .await?;
+    tracing::debug!(?sync, "runners config written or queued");
+
+    Ok((sync.status_code(StatusCode::OK), Json(plan)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use atmosphere::{Create as _, Read as _};
+    use axum::{
+        body::{to_bytes, Body},
+        http::{self, Request, StatusCode},
+    };
+    use pretty_assertions::assert_eq;
+    use tower::ServiceExt; // for `call`, `oneshot`, and `ready`
+
+    use crate::{
+        app::{router, AppState},
+        auth,
+        models::GitLabRunner,
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    #[tracing_test::traced_test]
+    async fn plan_apply(pool: atmosphere::Pool) -> Result<()> {
+        let secret = "test-secret".to_string();
+        let app_state = AppState::for_testing(pool);
+
+        let token = auth::encode_token(&secret, vec![auth::Scope::Admin])?;
+
+        let mut runner = GitLabRunner::for_testing();
+        runner.create(&app_state.pool).await?;
+
+        let post = |uri: &str, body: serde_json::Value| {
+            Request::builder()
+                .method(http::Method::POST)
+                .uri(uri)
+                .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .body(Body::from(body.to_string()))
+        };
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(post("/plan", serde_json::json!({"runners": []}))?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let plan: serde_json::Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        assert_eq!(plan["delete"][0]["uuid"], runner.uuid().to_string());
+        assert_eq!(plan["create"], serde_json::json!([]));
+
+        let apply = serde_json::json!({"plan_id": plan["id"]});
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(post("/apply", apply.clone())?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(GitLabRunner::find(&app_state.pool, runner.uuid())
+            .await?
+            .is_none());
+
+        // a plan is applied at most once
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(post("/apply", apply)?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        std::fs::remove_file(&*app_state.config_target.load())?;
+
+        Ok(())
+    }
+}
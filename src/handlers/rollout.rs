@@ -0,0 +1,75 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response, Result},
+    Extension, Json,
+};
+
+use crate::{
+    app::AppState,
+    auth::{Claims, Scope},
+    error::Error,
+    rollout::{RolloutOverview, RolloutStatus},
+};
+
+#[utoipa::path(
+    get,
+    path = "/rollout",
+    responses(
+        (status = StatusCode::OK, description = "Rollout policy along with the current or latest rollout of a config change to the agents", body = RolloutOverview)
+    )
+)]
+#[tracing::instrument(skip(settings, rollout))]
+pub async fn status(
+    State(AppState {
+        settings, rollout, ..
+    }): State<AppState>,
+) -> Result<Response> {
+    let overview = rollout.overview(settings.load().rollout.clone());
+
+    Ok((StatusCode::OK, Json(overview)).into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/rollout/promote",
+    responses(
+        (status = StatusCode::OK, description = "Promoted rollout; all agents receive its config", body = RolloutStatus),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope", body = Error),
+        (status = StatusCode::PRECONDITION_FAILED, description = "No rollout in progress", body = Error)
+    )
+)]
+#[tracing::instrument(skip(rollout, claims))]
+pub async fn promote(
+    State(AppState { rollout, .. }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+
+    Ok((StatusCode::OK, Json(rollout.promote()?)).into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/rollout/rollback",
+    responses(
+        (status = StatusCode::OK, description = "Rolled back rollout; its canaries return to the stable config", body = RolloutStatus),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope", body = Error),
+        (status = StatusCode::PRECONDITION_FAILED, description = "No rollout in progress", body = Error)
+    )
+)]
+#[tracing::instrument(skip(rollout, claims))]
+pub async fn roll_back(
+    State(AppState { rollout, .. }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+
+    Ok((
+        StatusCode::OK,
+        Json(rollout.roll_back("rolled back manually")?),
+    )
+        .into_response())
+}
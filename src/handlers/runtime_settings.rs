@@ -0,0 +1,177 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response, Result},
+    Extension, Json,
+};
+
+use crate::{
+    app::AppState,
+    auth::{Claims, Scope},
+    error::Error,
+    models::GitLabRunnerConfig,
+    settings::Settings,
+};
+
+#[utoipa::path(
+    get,
+    path = "/settings/runtime",
+    responses(
+        (status = StatusCode::OK, description = "Current runtime settings, with secrets redacted", body = Settings),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope", body = Error)
+    )
+)]
+#[tracing::instrument(skip(settings, claims))]
+pub async fn read(
+    State(AppState { settings, .. }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+
+    Ok((StatusCode::OK, Json(settings.load().redacted())).into_response())
+}
+
+#[utoipa::path(
+    put,
+    path = "/settings/runtime",
+    request_body(
+        content = Settings,
+        description = "Settings to apply; redacted secrets keep their current value",
+        content_type = "application/json"
+    ),
+    responses(
+        (status = StatusCode::OK, description = "Applied settings, with secrets redacted", body = Settings),
+        (status = StatusCode::BAD_REQUEST, description = "Settings can't be changed at runtime", body = Error),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, config_target, config_cache, settings, claims))]
+pub async fn update(
+    State(AppState {
+        pool,
+        config_target,
+        config_cache,
+        settings,
+        ..
+    }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Json(updated_settings): Json<serde_json::Value>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+
+    let previous = settings.load();
+    settings.replace(Settings::unredact(updated_settings, &previous)?)?;
+
+    // the config on disk must reflect changed render options right away
+    let render = &settings.load().render;
+    if *render != previous.render {
+        GitLabRunnerConfig::write_or_queue(&pool, &config_target.load(), &config_cache, render)
+            .await?;
+    }
+
+    Ok((StatusCode::OK, Json(settings.load().redacted())).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{to_bytes, Body},
+        http::{self, Request, StatusCode},
+    };
+    use pretty_assertions::assert_eq;
+    use tower::ServiceExt; // for `call`, `oneshot`, and `ready`
+
+    use crate::{
+        app::{router, AppState},
+        auth,
+        settings::{NameUniqueness, Settings},
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    #[tracing_test::traced_test]
+    async fn update_runtime_settings(pool: atmosphere::Pool) -> Result<()> {
+        let secret = "test-secret".to_string();
+        let app_state = AppState::for_testing(pool);
+
+        let token = auth::encode_token(&secret, vec![auth::Scope::Admin])?;
+
+        let settings = Settings {
+            name_uniqueness: NameUniqueness::Enforce,
+            ..Default::default()
+        };
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::PUT)
+                    .uri("/settings/runtime")
+                    .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(serde_json::to_string(&settings)?))?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(*app_state.settings.load(), settings);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    #[tracing_test::traced_test]
+    async fn read_runtime_settings_redacted(pool: atmosphere::Pool) -> Result<()> {
+        let secret = "test-secret".to_string();
+        let app_state = AppState::for_testing(pool);
+
+        let mut settings = Settings::default();
+        settings.events.webhook_url = Some("https://hooks.example.com/secret-path".to_string());
+        app_state.settings.replace(settings.clone())?;
+
+        let read = |token: String| {
+            Request::builder()
+                .method(http::Method::GET)
+                .uri("/settings/runtime")
+                .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                .body(Body::empty())
+        };
+
+        let token = auth::encode_token(&secret, Vec::new())?;
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(read(token)?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let token = auth::encode_token(&secret, vec![auth::Scope::Admin])?;
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(read(token.clone())?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut body: serde_json::Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        assert_eq!(body["events"]["webhook_url"], "****");
+
+        // sending the redacted settings back keeps the secret
+        body["name_max_length"] = serde_json::json!(100);
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::PUT)
+                    .uri("/settings/runtime")
+                    .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                    .header(http::header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                    .body(Body::from(serde_json::to_string(&body)?))?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        settings.name_max_length = 100;
+        assert_eq!(*app_state.settings.load(), settings);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response, Result},
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{
+    app::AppState,
+    autoscaling::AutoscalingReport,
+    error::Error,
+    models::{CachedResponse, QuotaUsage},
+};
+
+/// Statistics about the runners managed by the service.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Stats {
+    /// Usage of the configured quotas
+    quotas: QuotaUsage,
+    /// Job limits set by autoscaling, and how they came about
+    autoscaling: AutoscalingReport,
+}
+
+#[utoipa::path(
+    get,
+    path = "/stats",
+    responses(
+        (status = StatusCode::OK, description = "Statistics about the managed runners", body = Stats),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, settings, read_cache, autoscaling))]
+pub async fn stats(
+    State(AppState {
+        pool,
+        settings,
+        read_cache,
+        autoscaling,
+        ..
+    }): State<AppState>,
+) -> Result<Response> {
+    // quotas and autoscaling settings can be reloaded without the runners changing, so they're
+    // part of the key; autoscaling decisions always change the runners or settings
+    let settings = settings.load();
+    let quotas = settings.quotas.clone();
+    let key = format!(
+        "stats:{quotas:?}:{:?}:{}",
+        settings.autoscaling, settings.render.concurrent
+    );
+    let response = read_cache
+        .get_or_load(key, || async move {
+            tracing::debug!("reading stats from database");
+
+            let stats = Stats {
+                quotas: QuotaUsage::read(&pool, &quotas).await?,
+                autoscaling: autoscaling.report(&settings.autoscaling, settings.render.concurrent),
+            };
+
+            CachedResponse::json(&stats)
+        })
+        .await?;
+
+    Ok(response.into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use atmosphere::Create as _;
+    use axum::{
+        body::{to_bytes, Body},
+        http::{self, Request, StatusCode},
+    };
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+    use tower::ServiceExt; // for `call`, `oneshot`, and `ready`
+
+    use crate::{
+        app::{router, AppState},
+        auth,
+        models::GitLabRunner,
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    #[tracing_test::traced_test]
+    async fn quota_usage(pool: atmosphere::Pool) -> Result<()> {
+        let secret = "test-secret".to_string();
+        let app_state = AppState::for_testing(pool);
+
+        let token = auth::encode_token(&secret, vec![auth::Scope::Admin])?;
+
+        let mut runner = GitLabRunner::for_testing();
+        runner.create(&app_state.pool).await?;
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::GET)
+                    .uri("/stats")
+                    .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let stats: serde_json::Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        assert_eq!(
+            stats["quotas"],
+            json!({
+                "runners": { "used": 1, "limit": 50 },
+                "privileged": { "used": 0, "limit": 5 },
+                "instances": [
+                    { "url": "https://gitlab.your-company.com/", "used": 1, "limit": 10 }
+                ]
+            })
+        );
+        assert_eq!(
+            stats["autoscaling"],
+            json!({ "enabled": false, "concurrent": 1, "decisions": [] })
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response, Result},
+    Extension, Json,
+};
+
+use crate::{
+    app::AppState,
+    auth::{Claims, Scope},
+    error::Error,
+    models::DeadLetter,
+};
+
+#[utoipa::path(
+    get,
+    path = "/tasks/dead-letter",
+    responses(
+        (status = StatusCode::OK, description = "Work which exhausted its retries, most recently failed first", body = [DeadLetter]),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope", body = Error),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, claims))]
+pub async fn dead_letters(
+    State(AppState { pool, .. }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+
+    tracing::debug!("reading dead letters");
+    let dead_letters = DeadLetter::list(&pool).await?;
+
+    Ok((StatusCode::OK, Json(dead_letters)).into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/tasks/dead-letter/{id}/replay",
+    params(
+        ("id" = i64, Path, description = "Dead letter ID")
+    ),
+    responses(
+        (status = StatusCode::OK, description = "Replayed dead letter; events are queued again with their retries reset, notifications and unregistrations from GitLab are done right away", body = DeadLetter),
+        (status = StatusCode::FORBIDDEN, description = "Token lacks the admin scope", body = Error),
+        (status = StatusCode::NOT_FOUND, description = "Dead letter not found or replayed already", body = Error),
+        (status = StatusCode::INTERNAL_SERVER_ERROR, description = "Internal server error, or replaying failed again; the dead letter is kept", body = Error)
+    )
+)]
+#[tracing::instrument(skip(pool, settings, gitlab, claims))]
+pub async fn replay(
+    State(AppState {
+        pool,
+        settings,
+        gitlab,
+        ..
+    }): State<AppState>,
+    Extension(claims): Extension<Claims>,
+    Path(id): Path<i64>,
+) -> Result<Response> {
+    claims.require_scope(Scope::Admin)?;
+
+    tracing::debug!("replaying dead letter");
+    let notifications = &settings.load().notifications;
+    let dead_letter = DeadLetter::replay(&pool, id, &gitlab, notifications).await?;
+    tracing::debug!(kind = dead_letter.kind(), "dead letter replayed");
+
+    Ok((StatusCode::OK, Json(dead_letter)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{to_bytes, Body},
+        http::{self, Request, StatusCode},
+    };
+    use pretty_assertions::assert_eq;
+    use tower::ServiceExt; // for `call`, `oneshot`, and `ready`
+
+    use crate::{
+        app::{router, AppState},
+        auth,
+        models::{Change, GitLabRunner, OutboxEvent},
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    #[tracing_test::traced_test]
+    async fn list_replay(pool: atmosphere::Pool) -> Result<()> {
+        let secret = "test-secret".to_string();
+        let app_state = AppState::for_testing(pool);
+
+        let token = auth::encode_token(&secret, vec![auth::Scope::Admin])?;
+
+        let mut runner = GitLabRunner::for_testing();
+        runner.apply(&app_state.pool, Change::Created, true).await?;
+        let mut event = OutboxEvent::pending(&app_state.pool, 10).await?.remove(0);
+        event
+            .record_failure(&app_state.pool, "connection refused")
+            .await?;
+        event.bury(&app_state.pool).await?;
+
+        let request = |method: http::Method, uri: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                .body(Body::empty())
+        };
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(request(http::Method::GET, "/tasks/dead-letter")?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let dead: serde_json::Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        assert_eq!(dead[0]["kind"], "event");
+        assert_eq!(dead[0]["last_error"], "connection refused");
+        assert_eq!(dead[0]["payload"]["event"], "runner_created");
+
+        let replay = format!("/tasks/dead-letter/{}/replay", dead[0]["id"]);
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(request(http::Method::POST, &replay)?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(OutboxEvent::pending(&app_state.pool, 10).await?.len(), 1);
+
+        let response = router(secret.clone(), app_state.clone())
+            .await
+            .oneshot(request(http::Method::POST, &replay)?)
+            .await?;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+}
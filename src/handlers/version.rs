@@ -0,0 +1,109 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, SecondsFormat};
+use glrcfg::RunnerVersion;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Build and capability information about the running service.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct VersionInfo {
+    /// Version of runrs
+    #[schema(example = "0.6.3")]
+    version: &'static str,
+    /// Git commit runrs was built from, or `unknown`
+    #[schema(example = "fdcaddc0a1b2")]
+    git_sha: &'static str,
+    /// Time runrs was built at
+    #[schema(format = DateTime, example = "2024-06-18T14:10:00Z")]
+    build_date: String,
+    /// Cargo features runrs was built with
+    #[schema(example = json!([]))]
+    features: Vec<&'static str>,
+    /// Newest `gitlab-runner` version whose config keys runrs knows about; the generated config
+    /// is compatible with this version
+    #[schema(example = "17.0")]
+    gitlab_runner: String,
+}
+
+impl VersionInfo {
+    fn new() -> Self {
+        let build_date = env!("RUNRS_BUILD_TIMESTAMP")
+            .parse()
+            .ok()
+            .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0))
+            .map_or_else(
+                || "unknown".to_string(),
+                |date| date.to_rfc3339_opts(SecondsFormat::Secs, true),
+            );
+
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            git_sha: env!("RUNRS_GIT_SHA"),
+            build_date,
+            features: env!("RUNRS_FEATURES")
+                .split(',')
+                .filter(|feature| !feature.is_empty())
+                .collect(),
+            gitlab_runner: RunnerVersion::LATEST.to_string(),
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/version",
+    responses(
+        (status = StatusCode::OK, description = "Build and capability information", body = VersionInfo)
+    ),
+    security(())
+)]
+#[tracing::instrument]
+pub async fn version() -> Response {
+    (StatusCode::OK, Json(VersionInfo::new())).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{to_bytes, Body},
+        http::{self, Request, StatusCode},
+    };
+    use pretty_assertions::assert_eq;
+    use tower::ServiceExt; // for `call`, `oneshot`, and `ready`
+
+    use crate::app::{router, AppState};
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    #[tracing_test::traced_test]
+    async fn version_without_token(pool: atmosphere::Pool) -> Result<()> {
+        let response = router("test-secret".to_string(), AppState::for_testing(pool))
+            .await
+            .oneshot(
+                Request::builder()
+                    .method(http::Method::GET)
+                    .uri("/version")
+                    .body(Body::empty())?,
+            )
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let info: serde_json::Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await?)?;
+        assert_eq!(info["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(info["gitlab_runner"], "17.0");
+        assert_eq!(
+            info["features"].as_array().map(Vec::len),
+            Some(env!("RUNRS_FEATURES").split_terminator(',').count())
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,256 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    os::fd::{FromRawFd, RawFd},
+};
+
+use miette::IntoDiagnostic;
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::TcpListener;
+
+use crate::app::{DEFAULT_LISTEN_ADDR, DEFAULT_PORT};
+
+/// First file descriptor passed by the service manager, see `sd_listen_fds(3)`.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Backlog of listeners bound by runrs itself, the same `tokio` uses.
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// Returns the address to listen on, from `LISTEN_ADDR` and `PORT` or from `BIND_ADDRESS`, which
+/// sets both at once, as read via `lookup`.
+pub fn bind_address(lookup: impl Fn(&str) -> Option<String>) -> miette::Result<SocketAddr> {
+    let listen_addr = lookup("LISTEN_ADDR");
+    let port = lookup("PORT");
+
+    let Some(bind_address) = lookup("BIND_ADDRESS") else {
+        let ip = parse_ip(
+            "LISTEN_ADDR",
+            listen_addr.as_deref().unwrap_or(DEFAULT_LISTEN_ADDR),
+        )?;
+        let port = match port {
+            Some(port) => parse_port("PORT", &port)?,
+            None => DEFAULT_PORT,
+        };
+        return Ok(SocketAddr::new(ip, port));
+    };
+
+    if listen_addr.is_some() || port.is_some() {
+        miette::bail!(
+            "BIND_ADDRESS sets address and port at once; set either it or LISTEN_ADDR and PORT"
+        );
+    }
+    let Some((ip, port)) = bind_address.rsplit_once(':') else {
+        miette::bail!(
+            "invalid value for BIND_ADDRESS: '{bind_address}' must look like <address>:<port>"
+        );
+    };
+    // IPv6 addresses are bracketed, e.g. `[::1]:3000`
+    let ip = ip
+        .strip_prefix('[')
+        .and_then(|ip| ip.strip_suffix(']'))
+        .unwrap_or(ip);
+
+    Ok(SocketAddr::new(
+        parse_ip("BIND_ADDRESS", ip)?,
+        parse_port("BIND_ADDRESS", port)?,
+    ))
+}
+
+/// Returns whether to bind with `SO_REUSEPORT`, from `REUSE_PORT` as read via `lookup`.
+pub fn reuse_port(lookup: impl Fn(&str) -> Option<String>) -> miette::Result<bool> {
+    let Some(reuse_port) = lookup("REUSE_PORT") else {
+        return Ok(false);
+    };
+
+    reuse_port.parse().map_err(|_| {
+        miette::miette!("invalid value for REUSE_PORT: '{reuse_port}' must be true or false")
+    })
+}
+
+fn parse_ip(key: &str, ip: &str) -> miette::Result<IpAddr> {
+    if ip == "localhost" {
+        return Ok(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    }
+
+    ip.parse().map_err(|_| {
+        miette::miette!(
+            help = "use 127.0.0.1 to only accept connections from this host, 0.0.0.0 for all",
+            "invalid value for {key}: '{ip}' is not an IP address"
+        )
+    })
+}
+
+fn parse_port(key: &str, port: &str) -> miette::Result<u16> {
+    port.parse().map_err(|_| {
+        miette::miette!("invalid value for {key}: '{port}' is not a port between 0 and 65535")
+    })
+}
+
+/// Returns the listener to serve the API on: the socket passed by systemd socket activation if
+/// there is one, a new one bound to `bind_address` otherwise, with `SO_REUSEPORT` if `reuse_port`
+/// is set.
+///
+/// Either way, a new runrs can take over while the old one drains its in-flight requests on
+/// shutdown. With socket activation, systemd owns the listening socket and hands it to each runrs
+/// it starts; new connections wait in the socket's backlog for the next one instead of being
+/// refused. With `SO_REUSEPORT`, the new runrs binds the same address while the old one still
+/// listens, and the kernel spreads new connections across both until the old one stops listening;
+/// only connections still waiting in the old one's backlog at that moment are reset.
+pub async fn listen(bind_address: SocketAddr, reuse_port: bool) -> miette::Result<TcpListener> {
+    let lookup = |key: &str| std::env::var(key).ok();
+    let Some(fd) = activated_fd(lookup, std::process::id())? else {
+        let bound = if reuse_port {
+            bind_reusable(bind_address)
+        } else {
+            TcpListener::bind(bind_address).await
+        };
+        return bound.map_err(|err| match err.kind() {
+            ErrorKind::AddrInUse => miette::miette!(
+                help = "stop the process listening there, or set PORT to another port",
+                "can't listen on {bind_address}: the port is already in use"
+            ),
+            ErrorKind::PermissionDenied => miette::miette!(
+                help = "ports below 1024 require root or CAP_NET_BIND_SERVICE",
+                "can't listen on {bind_address}: permission denied"
+            ),
+            ErrorKind::AddrNotAvailable => miette::miette!(
+                help = "set LISTEN_ADDR to an address of this host",
+                "can't listen on {bind_address}: the address isn't available on this host"
+            ),
+            _ => miette::miette!("can't listen on {bind_address}: {err}"),
+        });
+    };
+
+    // SAFETY: the service manager passed this descriptor to this very process (as `LISTEN_PID`
+    // says), and nothing else in runrs takes ownership of it.
+    let inherited = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+    // inherited descriptors are left open across `exec`; the clone isn't, so commands spawned by
+    // runrs, e.g. to reload `gitlab-runner`, don't keep the socket open
+    let listener = inherited.try_clone().into_diagnostic()?;
+    drop(inherited);
+    listener.set_nonblocking(true).into_diagnostic()?;
+
+    tracing::info!("using socket passed by systemd, ignoring LISTEN_ADDR and PORT");
+    TcpListener::from_std(listener).into_diagnostic()
+}
+
+/// Binds a listener to `bind_address` with `SO_REUSEPORT`, so that other processes of the same user
+/// can bind it as well, e.g. the runrs taking over from this one.
+fn bind_reusable(bind_address: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(
+        Domain::for_address(bind_address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    socket.set_reuse_address(true)?;
+    socket.set_reuse_port(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&bind_address.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+/// Returns the descriptor of the socket passed to the process with `pid`, if the environment read
+/// via `lookup` says one was passed.
+fn activated_fd(
+    lookup: impl Fn(&str) -> Option<String>,
+    pid: u32,
+) -> miette::Result<Option<RawFd>> {
+    // the variables may have been inherited from a parent which was socket activated itself
+    let for_this_process = lookup("LISTEN_PID")
+        .and_then(|listen_pid| listen_pid.parse::<u32>().ok())
+        .is_some_and(|listen_pid| listen_pid == pid);
+    if !for_this_process {
+        return Ok(None);
+    }
+
+    let Some(listen_fds) = lookup("LISTEN_FDS") else {
+        return Ok(None);
+    };
+    match listen_fds.parse::<u32>() {
+        Ok(0) => Ok(None),
+        Ok(1) => Ok(Some(LISTEN_FDS_START)),
+        Ok(n) => miette::bail!("systemd passed {n} sockets; runrs listens on exactly one"),
+        Err(err) => miette::bail!("invalid LISTEN_FDS '{listen_fds}': {err}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::{activated_fd, bind_address, bind_reusable, reuse_port, LISTEN_FDS_START};
+
+    fn env<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        |key| {
+            vars.iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| value.to_string())
+        }
+    }
+
+    #[test]
+    fn activated_socket() -> miette::Result<()> {
+        assert_eq!(activated_fd(env(&[]), 42)?, None);
+
+        let activated = [("LISTEN_PID", "42"), ("LISTEN_FDS", "1")];
+        assert_eq!(activated_fd(env(&activated), 42)?, Some(LISTEN_FDS_START));
+        // meant for another process
+        assert_eq!(activated_fd(env(&activated), 43)?, None);
+
+        let none = [("LISTEN_PID", "42"), ("LISTEN_FDS", "0")];
+        assert_eq!(activated_fd(env(&none), 42)?, None);
+
+        let several = [("LISTEN_PID", "42"), ("LISTEN_FDS", "2")];
+        assert!(activated_fd(env(&several), 42).is_err());
+
+        let invalid = [("LISTEN_PID", "42"), ("LISTEN_FDS", "one")];
+        assert!(activated_fd(env(&invalid), 42).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reusable_port() -> Result<(), Box<dyn std::error::Error>> {
+        assert!(!reuse_port(env(&[]))?);
+        assert!(reuse_port(env(&[("REUSE_PORT", "true")]))?);
+        assert!(reuse_port(env(&[("REUSE_PORT", "yes")])).is_err());
+
+        // the next runrs binds the same port while this one still listens
+        let listener = bind_reusable("127.0.0.1:0".parse()?)?;
+        let next = bind_reusable(listener.local_addr()?)?;
+        assert_eq!(next.local_addr()?, listener.local_addr()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_bind_address() -> miette::Result<()> {
+        assert_eq!(bind_address(env(&[]))?.to_string(), "0.0.0.0:3000");
+
+        let local = [("LISTEN_ADDR", "127.0.0.1"), ("PORT", "8080")];
+        assert_eq!(bind_address(env(&local))?.to_string(), "127.0.0.1:8080");
+        let ipv6 = [("LISTEN_ADDR", "::1")];
+        assert_eq!(bind_address(env(&ipv6))?.to_string(), "[::1]:3000");
+        let localhost = [("LISTEN_ADDR", "localhost")];
+        assert_eq!(bind_address(env(&localhost))?.to_string(), "127.0.0.1:3000");
+
+        let bind = [("BIND_ADDRESS", "[::]:8080")];
+        assert_eq!(bind_address(env(&bind))?.to_string(), "[::]:8080");
+
+        for invalid in [
+            vec![("PORT", "65536")],
+            vec![("PORT", "http")],
+            vec![("LISTEN_ADDR", "example.com")],
+            vec![("BIND_ADDRESS", "0.0.0.0")],
+            vec![("BIND_ADDRESS", "0.0.0.0:8080"), ("PORT", "8081")],
+        ] {
+            assert!(bind_address(env(&invalid)).is_err(), "{invalid:?}");
+        }
+
+        Ok(())
+    }
+}
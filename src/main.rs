@@ -1,10 +1,40 @@
 // Copyright 2024 bmc::labs GmbH. All rights reserved.
 
+mod agents;
 mod app;
+mod audit;
 mod auth;
+mod autoscaling;
+#[cfg(feature = "benchmarks")]
+mod benchmarks;
+mod body_logging;
+#[cfg(feature = "chaos")]
+mod chaos;
+mod deploy;
 mod error;
+mod freeze;
+#[cfg(feature = "fuzzing")]
+mod fuzzing;
+mod gitlab;
+mod gitops;
 mod handlers;
+mod listener;
+mod metrics;
 mod models;
+mod notifications;
+#[cfg(feature = "kubernetes")]
+mod operator;
+mod plan;
+mod reload;
+mod rollout;
+mod secrets;
+mod settings;
+mod shutdown;
+mod subsystems;
+mod tls;
+mod webhooks;
+
+use std::net::SocketAddr;
 
 use miette::IntoDiagnostic;
 
@@ -13,34 +43,388 @@ pub(crate) static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!("./migratio
 
 #[tokio::main]
 async fn main() -> miette::Result<()> {
+    // print the API spec for client generation and exit, before logging can pollute stdout
+    if std::env::args().skip(1).any(|arg| arg == "--openapi-json") {
+        println!("{}", app::openapi_json()?);
+        return Ok(());
+    }
+    // render deployment scaffolding from the current environment, likewise before logging
+    if std::env::args()
+        .skip(1)
+        .any(|arg| arg == "--emit-systemd-unit")
+    {
+        let exec_start = std::env::current_exe().into_diagnostic()?;
+        print!(
+            "{}",
+            deploy::Deployment::from_env().systemd_unit(&exec_start)
+        );
+        return Ok(());
+    }
+    if std::env::args()
+        .skip(1)
+        .any(|arg| arg == "--emit-systemd-socket")
+    {
+        print!("{}", deploy::Deployment::from_env().systemd_socket());
+        return Ok(());
+    }
+    if std::env::args().skip(1).any(|arg| arg == "--emit-compose") {
+        print!("{}", deploy::Deployment::from_env().compose());
+        return Ok(());
+    }
+    #[cfg(feature = "kubernetes")]
+    if std::env::args()
+        .skip(1)
+        .any(|arg| arg == "--emit-kubernetes-crd")
+    {
+        use kube::CustomResourceExt as _;
+
+        let crd = serde_yaml::to_string(&operator::RunnerResource::crd()).into_diagnostic()?;
+        print!("{crd}");
+        return Ok(());
+    }
+    // run the property-based robustness harness against this build, e.g. for packagers
+    #[cfg(feature = "fuzzing")]
+    if std::env::args().skip(1).any(|arg| arg == "--fuzz") {
+        return tokio::task::spawn_blocking(fuzzing::run)
+            .await
+            .into_diagnostic()?
+            .map_err(|err| miette::miette!("{err}"));
+    }
+    // benchmark compiling the config, see `benchmarks` for why this isn't under `benches/`
+    #[cfg(feature = "benchmarks")]
+    if std::env::args().skip(1).any(|arg| arg == "--bench") {
+        return tokio::task::spawn_blocking(benchmarks::run)
+            .await
+            .into_diagnostic()?
+            .map_err(|err| miette::miette!("{err}"));
+    }
+    // probe /readyz of the running service, for container health checks
+    if std::env::args().skip(1).any(|arg| arg == "--healthcheck") {
+        return deploy::Deployment::from_env().healthcheck().await;
+    }
+    // print a token with the given comma-separated scopes, e.g. `--issue-token admin`
+    if let Some(idx) = std::env::args().position(|arg| arg == "--issue-token") {
+        let scopes = std::env::args()
+            .nth(idx + 1)
+            .map(|scopes| {
+                scopes
+                    .split(',')
+                    .map(str::parse)
+                    .collect::<Result<Vec<auth::Scope>, _>>()
+            })
+            .transpose()
+            .map_err(|err| miette::miette!(err))?
+            .unwrap_or_default();
+        println!("{}", auth::encode_token(&auth::init_secret()?, scopes)?);
+        return Ok(());
+    }
+
     // set envvar defaults and init tracing
     logging::init()?;
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000")
+    let bind_address = listener::bind_address(|key| std::env::var(key).ok())?;
+    let reuse_port = listener::reuse_port(|key| std::env::var(key).ok())?;
+    let listener = listener::listen(bind_address, reuse_port).await?;
+    let local_addr = listener.local_addr().into_diagnostic()?;
+
+    // serve via HTTPS if a certificate is configured; fails right away if it can't be loaded
+    let tls_files = tls::TlsFiles::from_env()?;
+    let tls_config = match &tls_files {
+        Some(files) => Some(files.load().await?),
+        None => None,
+    };
+    let scheme = if tls_config.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+
+    tracing::info!("REST API on {scheme}://{local_addr}");
+    tracing::info!("API docs on {scheme}://{local_addr}/api-docs");
+
+    let app_state = app::AppState::init().await?;
+
+    let auth_mode = app_state.settings.load().auth_mode;
+    auth::ensure_auth_mode_allowed(auth_mode, local_addr)?;
+
+    let secret = match auth_mode {
+        auth::AuthMode::Jwt => {
+            let secret = auth::init_secret()?;
+            // tokens for administering the service are only issued via `--issue-token`
+            let token = auth::encode_token(&secret, Vec::new())?;
+            tracing::info!(?token, "generated token");
+            // for emergency changes during a change freeze
+            if !app_state.settings.load().freeze_windows.is_empty() {
+                let token = auth::encode_token(&secret, vec![auth::Scope::FreezeOverride])?;
+                tracing::info!(?token, "generated freeze override token");
+            }
+            secret
+        }
+        auth::AuthMode::Disabled => {
+            tracing::warn!(
+                "AUTH_MODE=disabled: requests are NOT authenticated, for development only"
+            );
+            String::new()
+        }
+    };
+
+    // databases of runrs versions before the UUID schema keep their runners in a legacy table
+    let migration = models::LegacyMigration::run(&app_state.pool, &app_state.settings.load())
         .await
         .into_diagnostic()?;
+    if let Some(migration) = migration {
+        tracing::info!(
+            migrated = migration.migrated.len(),
+            skipped = ?migration.skipped,
+            "migrated runners from legacy schema; skipped ones remain in table runners for a retry"
+        );
+    }
 
-    tracing::info!(
-        "REST API on http://{}",
-        listener.local_addr().into_diagnostic()?
-    );
-    tracing::info!(
-        "API docs on http://{}/api-docs",
-        listener.local_addr().into_diagnostic()?
-    );
+    // runners from before the history was kept enter it as of their last change
+    let seeded = models::RunnerHistory::seed(&app_state.pool)
+        .await
+        .into_diagnostic()?;
+    if seeded > 0 {
+        tracing::info!(
+            seeded,
+            "entered runners into the history as of their last change"
+        );
+    }
 
-    let secret = auth::init_secret()?;
-    let _ = auth::encode_token(&secret)?;
+    // runners in the config file which aren't in the database are dropped on the next write
+    match models::OrphanRunner::find(&app_state.pool, app_state.config_target.initial()).await {
+        Ok(orphans) => {
+            for orphan in orphans {
+                tracing::warn!(
+                    name = orphan.name(),
+                    url = orphan.url(),
+                    "runner in config file is missing from the database and will be removed on \
+                     the next write; adopt or purge it via /config/orphans"
+                );
+            }
+        }
+        Err(err) => tracing::warn!(%err, "checking config file for orphaned runners failed"),
+    }
 
-    let app_state = app::AppState::init().await?;
+    // single-runner appliances bring their runner along in the environment
+    if let Some(bootstrap) = models::Bootstrap::from_env()? {
+        let settings = app_state.settings.load();
+        let bootstrapped = bootstrap
+            .apply(
+                &app_state.pool,
+                &app_state.config_target.load(),
+                &app_state.config_cache,
+                &settings,
+                &app_state.secrets,
+            )
+            .await
+            .into_diagnostic()?;
+        match bootstrapped {
+            Some(uuid) => tracing::info!(%uuid, "bootstrapped runner from the environment"),
+            None => tracing::info!("database already holds runners, skipping bootstrap"),
+        }
+    }
+
+    // retry config writes which failed, e.g. because the disk was full
+    app_state
+        .supervisor
+        .spawn(models::ConfigWriteRetry::new(
+            app_state.pool.clone(),
+            app_state.config_target.clone(),
+            app_state.config_cache.clone(),
+            app_state.settings.clone(),
+        ))
+        .await;
+    // notify owners of expiring runners, and pause or delete the runners once they expired
+    app_state
+        .supervisor
+        .spawn(models::ExpiryReaper::new(
+            app_state.pool.clone(),
+            app_state.config_target.clone(),
+            app_state.config_cache.clone(),
+            app_state.settings.clone(),
+        ))
+        .await;
+    // pause and resume runners according to their schedules
+    app_state
+        .supervisor
+        .spawn(models::ScheduleEnforcer::new(
+            app_state.pool.clone(),
+            app_state.config_target.clone(),
+            app_state.config_cache.clone(),
+            app_state.settings.clone(),
+        ))
+        .await;
+    // deliver runner change events queued in the outbox
+    app_state
+        .supervisor
+        .spawn(models::OutboxDispatcher::new(
+            app_state.pool.clone(),
+            app_state.settings.clone(),
+        ))
+        .await;
+    // prune the audit log according to the retention settings
+    app_state
+        .supervisor
+        .spawn(models::AuditPruner::new(
+            app_state.pool.clone(),
+            app_state.settings.clone(),
+            models::AuditExport::from_env().await?,
+        ))
+        .await;
+    // purge deleted runners once they were in the recycle bin long enough
+    app_state
+        .supervisor
+        .spawn(models::RecycleBinPurger::new(
+            app_state.pool.clone(),
+            app_state.settings.clone(),
+        ))
+        .await;
+    // prune the runner and config history according to the retention settings
+    app_state
+        .supervisor
+        .spawn(models::HistoryPruner::new(
+            app_state.pool.clone(),
+            app_state.settings.clone(),
+        ))
+        .await;
+    // read the runner gauges for /metrics from the database
+    app_state
+        .supervisor
+        .spawn(metrics::MetricsCollector::new(
+            app_state.pool.clone(),
+            app_state.metrics.clone(),
+        ))
+        .await;
+    // re-expose the job metrics of gitlab-runner under /metrics, if configured
+    app_state
+        .supervisor
+        .spawn(metrics::RunnerMetricsScraper::new(
+            app_state.metrics.clone(),
+            settings::env_opt("RUNNER_METRICS_URL")?,
+        ))
+        .await;
+    // roll config changes out to the agents, canaries first
+    app_state
+        .supervisor
+        .spawn(rollout::RolloutOrchestrator::new(
+            app_state.pool.clone(),
+            app_state.config_cache.clone(),
+            app_state.settings.clone(),
+            app_state.agents.clone(),
+            app_state.rollout.clone(),
+        ))
+        .await;
+    // scale the runners' job limits to their job queues in GitLab, if enabled
+    app_state
+        .supervisor
+        .spawn(autoscaling::Autoscaler::new(
+            app_state.pool.clone(),
+            app_state.config_target.clone(),
+            app_state.config_cache.clone(),
+            app_state.settings.clone(),
+            app_state.gitlab.clone(),
+            app_state.secrets.clone(),
+            app_state.autoscaling.clone(),
+        ))
+        .await;
+    // reconcile the runners with a Git repository, if GitOps is on
+    app_state
+        .supervisor
+        .spawn(gitops::GitOpsController::new(
+            app_state.pool.clone(),
+            app_state.config_target.clone(),
+            app_state.config_cache.clone(),
+            app_state.settings.clone(),
+            app_state.secrets.clone(),
+            app_state.gitops.clone(),
+        ))
+        .await;
+    // reconcile the runners with GitLabRunner resources, if operator mode is on
+    #[cfg(feature = "kubernetes")]
+    app_state
+        .supervisor
+        .spawn(operator::RunnerOperator::new(
+            app_state.pool.clone(),
+            app_state.config_target.clone(),
+            app_state.config_cache.clone(),
+            app_state.settings.clone(),
+            app_state.secrets.clone(),
+        ))
+        .await;
+    // notify operators of drift, failed config writes, expiring tokens and used up quotas
+    app_state
+        .supervisor
+        .spawn(notifications::Notifier::new(
+            app_state.pool.clone(),
+            app_state.config_target.clone(),
+            app_state.config_cache.clone(),
+            app_state.settings.clone(),
+        ))
+        .await;
+    // make gitlab-runner pick up written configs, if configured
+    app_state
+        .supervisor
+        .spawn(reload::Reloader::new(
+            app_state.config_cache.clone(),
+            reload::Reload::from_env()?,
+        ))
+        .await;
+    // reload settings on SIGHUP
+    app_state
+        .supervisor
+        .spawn(settings::SettingsReload::new(
+            app_state.pool.clone(),
+            app_state.config_target.clone(),
+            app_state.config_cache.clone(),
+            app_state.settings.clone(),
+        ))
+        .await;
+    // reload the TLS certificate on SIGHUP, e.g. after a renewal
+    if let (Some(files), Some(config)) = (tls_files, tls_config.clone()) {
+        app_state
+            .supervisor
+            .spawn(tls::CertificateReload::new(files, config))
+            .await;
+    }
+    let final_state = app_state.clone();
+    // where to push the final metrics on shutdown, if anywhere
+    let pushgateway: Option<reqwest::Url> = settings::env_opt("METRICS_PUSHGATEWAY_URL")?;
 
     // initialize router and run app
     let router = app::router(secret, app_state).await;
 
-    if let Err(err) = axum::serve(listener, router)
-        .with_graceful_shutdown(signals::handle_sigint_sigterm())
-        .await
-    {
+    let served = match tls_config {
+        Some(config) => {
+            let handle = axum_server::Handle::new();
+            let shutdown = handle.clone();
+            tokio::spawn(async move {
+                signals::handle_sigint_sigterm().await;
+                shutdown.graceful_shutdown(None);
+            });
+
+            let listener = listener.into_std().into_diagnostic()?;
+            axum_server::from_tcp_rustls(listener, config)
+                .handle(handle)
+                .serve(router.into_make_service_with_connect_info::<SocketAddr>())
+                .await
+        }
+        None => {
+            axum::serve(
+                listener,
+                router.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(signals::handle_sigint_sigterm())
+            .await
+        }
+    };
+
+    // stop background tasks whether the server stopped gracefully or not
+    final_state.supervisor.shutdown().await;
+    // make sure the config on disk matches the database before exiting
+    shutdown::finalize(&final_state, pushgateway.as_ref()).await;
+
+    if let Err(err) = served {
         tracing::error!(%err, "Server stopped");
         miette::bail!(err);
     }
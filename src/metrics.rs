@@ -0,0 +1,551 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use arc_swap::ArcSwap;
+use chrono::{DateTime, Utc};
+use futures::{future::BoxFuture, FutureExt};
+use glrcfg::runner::Url;
+use uuid::Uuid;
+
+use crate::{
+    error::Error,
+    models::Os,
+    subsystems::{Shutdown, Subsystem},
+};
+
+/// How often the collector reads the gauges from the database.
+const COLLECT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often the scraper reads the metrics of `gitlab-runner`.
+const SCRAPE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How long `gitlab-runner` may take to serve its metrics before the scrape counts as failed.
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Series of `gitlab-runner` which are re-exposed, prefixed with `runrs_`: the name of the
+/// samples, the name of their family in the OpenMetrics text format, its type and help text.
+const SCRAPED_FAMILIES: [(&str, &str, &str, &str); 2] = [
+    (
+        "gitlab_runner_jobs",
+        "runrs_gitlab_runner_jobs",
+        "gauge",
+        "Jobs currently executed by gitlab-runner.",
+    ),
+    (
+        "gitlab_runner_failed_jobs_total",
+        "runrs_gitlab_runner_failed_jobs",
+        "counter",
+        "Jobs which failed in gitlab-runner.",
+    ),
+];
+
+/// Content type of the OpenMetrics text format.
+pub const OPENMETRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Labels every metric carries, so that alerts can be routed to the team owning the instance:
+/// the host of the GitLab instance and the executor type.
+type Labels = (String, &'static str);
+
+fn labels(url: &Url, os: Os) -> Labels {
+    let instance = url.host_str().unwrap_or(url.as_str()).to_string();
+    (instance, os.executor())
+}
+
+/// Gauges for the runners sharing the same labels.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct RunnerGauges {
+    active: u64,
+    paused: u64,
+    /// Earliest expiry of the runners' tokens, if any of them expire
+    next_token_expiry: Option<DateTime<Utc>>,
+}
+
+/// A counter with the most recent occurrence as OpenMetrics exemplar, which leads from an alert
+/// straight to the runner at fault.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Counter {
+    value: u64,
+    runner: Uuid,
+    at: DateTime<Utc>,
+}
+
+/// A sample scraped from `gitlab-runner`, with its labels as given in the text format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ScrapedSample {
+    /// Index into [`SCRAPED_FAMILIES`]
+    family: usize,
+    labels: String,
+    value: String,
+}
+
+/// Result of the last scrape of `gitlab-runner`; no samples are kept if it failed, so that
+/// stale values don't look current.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Scrape {
+    up: bool,
+    samples: Vec<ScrapedSample>,
+}
+
+/// Metrics of the service, rendered in the OpenMetrics text format. Gauges are read from the
+/// database by the [`MetricsCollector`], counters are updated as events happen. If configured,
+/// the job metrics of `gitlab-runner` are scraped by the [`RunnerMetricsScraper`].
+#[derive(Debug, Default)]
+pub struct Metrics {
+    runners: ArcSwap<BTreeMap<Labels, RunnerGauges>>,
+    token_verify_failures: Mutex<BTreeMap<Labels, Counter>>,
+    read_cache_hits: AtomicU64,
+    read_cache_misses: AtomicU64,
+    gitlab_runner: ArcSwap<Option<Scrape>>,
+}
+
+impl Metrics {
+    /// Counts a runner token which couldn't be verified, e.g. because its secret reference
+    /// doesn't resolve.
+    pub fn token_verify_failed(&self, url: &Url, os: Os, runner: Uuid) {
+        let mut failures = self
+            .token_verify_failures
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let at = Utc::now();
+
+        failures
+            .entry(labels(url, os))
+            .and_modify(|counter| {
+                *counter = Counter {
+                    value: counter.value + 1,
+                    runner,
+                    at,
+                }
+            })
+            .or_insert(Counter {
+                value: 1,
+                runner,
+                at,
+            });
+    }
+
+    /// Counts a response served from the read cache.
+    pub fn read_cache_hit(&self) {
+        self.read_cache_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a response the read cache had to load from the database.
+    pub fn read_cache_miss(&self) {
+        self.read_cache_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Reads the runner gauges from the database.
+    pub async fn collect(&self, pool: &atmosphere::Pool) -> Result<(), Error> {
+        let rows: Vec<(Url, Os, bool, Option<glrcfg::runner::DateTime>)> = sqlx::query_as(
+            "SELECT url, os, paused OR off_schedule, token_expires_at FROM gitlab_runners",
+        )
+        .fetch_all(pool)
+        .await?;
+
+        let mut runners = BTreeMap::<Labels, RunnerGauges>::new();
+        for (url, os, paused, token_expires_at) in rows {
+            let gauges = runners.entry(labels(&url, os)).or_default();
+            if paused {
+                gauges.paused += 1;
+            } else {
+                gauges.active += 1;
+            }
+
+            let token_expires_at = token_expires_at.map(|at| *at.as_chrono());
+            gauges.next_token_expiry = match (gauges.next_token_expiry, token_expires_at) {
+                (Some(next), Some(at)) => Some(next.min(at)),
+                (next, at) => next.or(at),
+            };
+        }
+
+        self.runners.store(Arc::new(runners));
+        Ok(())
+    }
+
+    /// Reads the metrics of `gitlab-runner` from `url`, or forgets them if `url` is `None`, i.e.
+    /// scraping is disabled.
+    pub async fn scrape(&self, client: &reqwest::Client, url: Option<&str>) {
+        let Some(url) = url else {
+            self.gitlab_runner.store(Arc::new(None));
+            return;
+        };
+
+        let body = async {
+            client
+                .get(url)
+                .timeout(SCRAPE_TIMEOUT)
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await
+        }
+        .await
+        .map_err(|err| Error::connection_failed(format!("scraping {url} failed: {err}")));
+        self.store_scrape(body);
+    }
+
+    fn store_scrape(&self, body: Result<String, Error>) {
+        let scrape = match body {
+            Ok(body) => Scrape {
+                up: true,
+                samples: parse_samples(&body),
+            },
+            Err(err) => {
+                tracing::warn!(%err, "scraping gitlab-runner metrics failed");
+                Scrape::default()
+            }
+        };
+        self.gitlab_runner.store(Arc::new(Some(scrape)));
+    }
+
+    /// Renders all metrics in the OpenMetrics text format.
+    pub fn render(&self) -> String {
+        let runners = self.runners.load();
+        let mut out = String::new();
+
+        out.push_str("# TYPE runrs_runners gauge\n");
+        out.push_str("# HELP runrs_runners Number of runners.\n");
+        for ((instance, executor), gauges) in runners.iter() {
+            for (state, count) in [("active", gauges.active), ("paused", gauges.paused)] {
+                let _ = writeln!(
+                    out,
+                    "runrs_runners{{instance=\"{}\",executor=\"{executor}\",state=\"{state}\"}} \
+                     {count}",
+                    escape(instance)
+                );
+            }
+        }
+
+        out.push_str("# TYPE runrs_runner_token_next_expiry_timestamp_seconds gauge\n");
+        out.push_str("# UNIT runrs_runner_token_next_expiry_timestamp_seconds seconds\n");
+        out.push_str(
+            "# HELP runrs_runner_token_next_expiry_timestamp_seconds Earliest expiry of the \
+             runner tokens.\n",
+        );
+        for ((instance, executor), gauges) in runners.iter() {
+            if let Some(next) = gauges.next_token_expiry {
+                let _ = writeln!(
+                    out,
+                    "runrs_runner_token_next_expiry_timestamp_seconds{{instance=\"{}\",\
+                     executor=\"{executor}\"}} {}",
+                    escape(instance),
+                    next.timestamp()
+                );
+            }
+        }
+
+        out.push_str("# TYPE runrs_token_verify_failures counter\n");
+        out.push_str(
+            "# HELP runrs_token_verify_failures Runner tokens which couldn't be verified.\n",
+        );
+        let failures = self
+            .token_verify_failures
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        for ((instance, executor), counter) in failures.iter() {
+            let _ = writeln!(
+                out,
+                "runrs_token_verify_failures_total{{instance=\"{}\",executor=\"{executor}\"}} {} \
+                 # {{runner_uuid=\"{}\"}} 1 {}.{:03}",
+                escape(instance),
+                counter.value,
+                counter.runner,
+                counter.at.timestamp(),
+                counter.at.timestamp_subsec_millis()
+            );
+        }
+
+        out.push_str("# TYPE runrs_read_cache_requests counter\n");
+        out.push_str(
+            "# HELP runrs_read_cache_requests Requests to read endpoints, by whether they were \
+             served from the cache.\n",
+        );
+        for (result, counter) in [
+            ("hit", &self.read_cache_hits),
+            ("miss", &self.read_cache_misses),
+        ] {
+            let _ = writeln!(
+                out,
+                "runrs_read_cache_requests_total{{result=\"{result}\"}} {}",
+                counter.load(Ordering::Relaxed)
+            );
+        }
+
+        if let Some(scrape) = self.gitlab_runner.load().as_ref() {
+            out.push_str("# TYPE runrs_gitlab_runner_up gauge\n");
+            out.push_str(
+                "# HELP runrs_gitlab_runner_up Whether the metrics of gitlab-runner could be \
+                 scraped.\n",
+            );
+            let _ = writeln!(out, "runrs_gitlab_runner_up {}", u8::from(scrape.up));
+
+            for (family, (sample, name, kind, help)) in SCRAPED_FAMILIES.iter().enumerate() {
+                let _ = writeln!(out, "# TYPE {name} {kind}");
+                let _ = writeln!(out, "# HELP {name} {help}");
+                for scraped in scrape.samples.iter().filter(|s| s.family == family) {
+                    let _ = writeln!(out, "runrs_{sample}{} {}", scraped.labels, scraped.value);
+                }
+            }
+        }
+
+        out.push_str("# EOF\n");
+        out
+    }
+}
+
+/// Picks the samples of the [`SCRAPED_FAMILIES`] from metrics in the Prometheus text format.
+/// Timestamps are dropped, and lines which can't be parsed are skipped.
+fn parse_samples(text: &str) -> Vec<ScrapedSample> {
+    let mut samples = Vec::new();
+
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let name_end = line.find(['{', ' ']).unwrap_or(line.len());
+        let Some(family) = SCRAPED_FAMILIES
+            .iter()
+            .position(|(sample, ..)| *sample == &line[..name_end])
+        else {
+            continue;
+        };
+
+        let rest = &line[name_end..];
+        let (labels, rest) = if rest.starts_with('{') {
+            // label values may contain braces and escaped quotes
+            let mut quoted = false;
+            let mut escaped = false;
+            let Some(end) = rest.char_indices().find_map(|(i, c)| {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' if quoted => escaped = true,
+                    '"' => quoted = !quoted,
+                    '}' if !quoted => return Some(i),
+                    _ => {}
+                }
+                None
+            }) else {
+                continue;
+            };
+            rest.split_at(end + 1)
+        } else {
+            ("", rest)
+        };
+
+        let Some(value) = rest.split_whitespace().next() else {
+            continue;
+        };
+        if value.parse::<f64>().is_err() {
+            continue;
+        }
+
+        samples.push(ScrapedSample {
+            family,
+            labels: labels.to_string(),
+            value: value.to_string(),
+        });
+    }
+
+    samples
+}
+
+/// Escapes a label value as required by the text format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+/// Updates the gauges of the [`Metrics`] from the database.
+#[derive(Debug, Clone)]
+pub struct MetricsCollector {
+    pool: atmosphere::Pool,
+    metrics: Arc<Metrics>,
+}
+
+impl MetricsCollector {
+    pub fn new(pool: atmosphere::Pool, metrics: Arc<Metrics>) -> Self {
+        Self { pool, metrics }
+    }
+}
+
+impl Subsystem for MetricsCollector {
+    fn name(&self) -> &'static str {
+        "metrics-collector"
+    }
+
+    fn run(&self, mut shutdown: Shutdown) -> BoxFuture<'static, Result<(), Error>> {
+        let this = self.clone();
+
+        async move {
+            let mut interval = tokio::time::interval(COLLECT_INTERVAL);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => this.metrics.collect(&this.pool).await?,
+                    _ = shutdown.requested() => return Ok(()),
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+/// Scrapes the metrics of `gitlab-runner` for the [`Metrics`], if a scrape URL is configured. The
+/// URL is read from the environment only, so the API can't point runrs at arbitrary hosts.
+#[derive(Debug, Clone)]
+pub struct RunnerMetricsScraper {
+    metrics: Arc<Metrics>,
+    scrape_url: Option<String>,
+    client: reqwest::Client,
+}
+
+impl RunnerMetricsScraper {
+    pub fn new(metrics: Arc<Metrics>, scrape_url: Option<String>) -> Self {
+        Self {
+            metrics,
+            scrape_url,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    async fn scrape(&self) {
+        self.metrics
+            .scrape(&self.client, self.scrape_url.as_deref())
+            .await;
+    }
+}
+
+impl Subsystem for RunnerMetricsScraper {
+    fn name(&self) -> &'static str {
+        "runner-metrics-scraper"
+    }
+
+    fn run(&self, mut shutdown: Shutdown) -> BoxFuture<'static, Result<(), Error>> {
+        let this = self.clone();
+
+        async move {
+            let mut interval = tokio::time::interval(SCRAPE_INTERVAL);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => this.scrape().await,
+                    _ = shutdown.requested() => return Ok(()),
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use atmosphere::{Create as _, Pool};
+    use glrcfg::runner::{DateTime, Url};
+    use pretty_assertions::assert_eq;
+
+    use super::Metrics;
+    use crate::{
+        error::Error,
+        models::{GitLabRunner, Os},
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn render_labeled_metrics(pool: Pool) -> Result<()> {
+        let metrics = Metrics::default();
+        assert_eq!(
+            metrics.render().lines().last(),
+            Some("# EOF"),
+            "the text format must end with EOF"
+        );
+
+        let mut runner = GitLabRunner::for_testing();
+        runner.set_token_expires_at(DateTime::parse("2030-01-01T00:00:00Z")?);
+        runner.create(&pool).await?;
+        let mut other = GitLabRunner::for_testing();
+        other.set_url("https://gitlab.bmc-labs.com");
+        other.create(&pool).await?;
+        metrics.collect(&pool).await?;
+
+        let url = Url::parse("https://gitlab.bmc-labs.com")?;
+        metrics.token_verify_failed(&url, Os::Windows, *other.uuid());
+        metrics.token_verify_failed(&url, Os::Windows, *runner.uuid());
+
+        let rendered = metrics.render();
+        assert!(rendered.contains(
+            "runrs_runners{instance=\"gitlab.your-company.com\",executor=\"docker\",\
+             state=\"active\"} 1\n"
+        ));
+        assert!(rendered.contains(
+            "runrs_runner_token_next_expiry_timestamp_seconds{\
+             instance=\"gitlab.your-company.com\",executor=\"docker\"} 1893456000\n"
+        ));
+        assert!(!rendered.contains(
+            "runrs_runner_token_next_expiry_timestamp_seconds{instance=\"gitlab.bmc-labs.com\""
+        ));
+        assert!(rendered.contains(&format!(
+            "runrs_token_verify_failures_total{{instance=\"gitlab.bmc-labs.com\",\
+             executor=\"docker-windows\"}} 2 # {{runner_uuid=\"{}\"}} 1 ",
+            runner.uuid()
+        )));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reexpose_scraped_job_metrics() {
+        let metrics = Metrics::default();
+        assert!(!metrics.render().contains("gitlab_runner"));
+
+        let scraped = r#"# HELP gitlab_runner_jobs The current number of running builds.
+# TYPE gitlab_runner_jobs gauge
+gitlab_runner_jobs{executor_stage="build",runner="a1b2c3",state="running"} 2
+gitlab_runner_jobs{executor_stage="{\"weird\"}",runner="d4e5f6",state="running"} 1 1719392400000
+# TYPE gitlab_runner_failed_jobs_total counter
+gitlab_runner_failed_jobs_total{failure_reason="script_failure",runner="a1b2c3"} 3
+gitlab_runner_failed_jobs_total{failure_reason="broken"} NaN-ish
+# TYPE go_goroutines gauge
+go_goroutines 42
+"#;
+        metrics.store_scrape(Ok(scraped.to_string()));
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("runrs_gitlab_runner_up 1\n"));
+        assert!(rendered.contains(
+            "runrs_gitlab_runner_jobs{executor_stage=\"build\",runner=\"a1b2c3\",\
+             state=\"running\"} 2\n"
+        ));
+        assert!(rendered.contains(
+            "runrs_gitlab_runner_jobs{executor_stage=\"{\\\"weird\\\"}\",runner=\"d4e5f6\",\
+             state=\"running\"} 1\n"
+        ));
+        assert!(rendered.contains("# TYPE runrs_gitlab_runner_failed_jobs counter\n"));
+        assert!(rendered.contains(
+            "runrs_gitlab_runner_failed_jobs_total{failure_reason=\"script_failure\",\
+             runner=\"a1b2c3\"} 3\n"
+        ));
+        assert!(!rendered.contains("broken"));
+        assert!(!rendered.contains("go_goroutines"));
+        assert_eq!(rendered.lines().last(), Some("# EOF"));
+
+        metrics.store_scrape(Err(Error::connection_failed("connection refused")));
+        let rendered = metrics.render();
+        assert!(rendered.contains("runrs_gitlab_runner_up 0\n"));
+        assert!(!rendered.contains("a1b2c3"));
+    }
+}
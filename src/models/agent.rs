@@ -0,0 +1,206 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::error::Error;
+
+/// Maximum length of agent names.
+const AGENT_NAME_MAX_LENGTH: usize = 64;
+
+/// An agent on a runner host, which connects to runrs to receive the config and report on
+/// `gitlab-runner`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema, sqlx::FromRow)]
+pub struct Agent {
+    uuid: Uuid,
+    /// Name of the agent, usually the host it runs on; unique
+    #[schema(example = "ci-host-01")]
+    name: String,
+    #[schema(value_type = String, format = DateTime, example = "2024-07-02T09:00:00Z")]
+    registered_at: DateTime<Utc>,
+    /// When the agent last connected or sent a message
+    #[schema(value_type = String, format = DateTime, example = "2024-07-02T09:30:00Z")]
+    last_seen_at: Option<DateTime<Utc>>,
+}
+
+/// Request body registering an agent.
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct AgentRegistration {
+    /// Name of the agent, usually the host it runs on; unique
+    #[schema(example = "ci-host-01")]
+    name: String,
+}
+
+impl Agent {
+    pub fn uuid(&self) -> &Uuid {
+        &self.uuid
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Stores a new agent which connects with the token hashed to `token_hash`.
+    pub async fn register(
+        pool: &atmosphere::Pool,
+        registration: &AgentRegistration,
+        token_hash: &str,
+    ) -> Result<Self, Error> {
+        let name = registration.name.trim();
+        if name.is_empty() || name.len() > AGENT_NAME_MAX_LENGTH {
+            return Err(Error::invalid_argument(format!(
+                "agent name must be between 1 and {AGENT_NAME_MAX_LENGTH} characters long"
+            )));
+        }
+        if name.chars().any(char::is_control) {
+            return Err(Error::invalid_argument(
+                "agent name must not contain control characters",
+            ));
+        }
+
+        let agent = Self {
+            uuid: Uuid::new_v4(),
+            name: name.to_string(),
+            registered_at: Utc::now(),
+            last_seen_at: None,
+        };
+
+        sqlx::query(
+            "INSERT INTO agents (uuid, name, token_hash, registered_at) VALUES (?, ?, ?, ?)",
+        )
+        .bind(agent.uuid)
+        .bind(&agent.name)
+        .bind(token_hash)
+        .bind(agent.registered_at)
+        .execute(pool)
+        .await
+        .map_err(|err| match err {
+            sqlx::Error::Database(err) if err.is_unique_violation() => {
+                Error::already_exists(format!("agent '{}' exists already", agent.name))
+            }
+            err => err.into(),
+        })?;
+
+        Ok(agent)
+    }
+
+    /// Returns all agents, ordered by name.
+    pub async fn list(pool: &atmosphere::Pool) -> Result<Vec<Self>, Error> {
+        let agents = sqlx::query_as(
+            "SELECT uuid, name, registered_at, last_seen_at FROM agents ORDER BY name",
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(agents)
+    }
+
+    pub async fn read(pool: &atmosphere::Pool, uuid: &Uuid) -> Result<Self, Error> {
+        sqlx::query_as("SELECT uuid, name, registered_at, last_seen_at FROM agents WHERE uuid = ?")
+            .bind(uuid)
+            .fetch_optional(pool)
+            .await?
+            .ok_or_else(|| Error::not_found(format!("agent {uuid} not found")))
+    }
+
+    /// Returns the agent connecting with the token hashed to `token_hash`, if there is one.
+    pub async fn find_by_token(
+        pool: &atmosphere::Pool,
+        token_hash: &str,
+    ) -> Result<Option<Self>, Error> {
+        let agent = sqlx::query_as(
+            "SELECT uuid, name, registered_at, last_seen_at FROM agents WHERE token_hash = ?",
+        )
+        .bind(token_hash)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(agent)
+    }
+
+    /// Records that the agent was just seen.
+    pub async fn touch(pool: &atmosphere::Pool, uuid: &Uuid) -> Result<(), Error> {
+        sqlx::query("UPDATE agents SET last_seen_at = ? WHERE uuid = ?")
+            .bind(Utc::now())
+            .bind(uuid)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Removes the agent, revoking its token, and returns it.
+    pub async fn delete(pool: &atmosphere::Pool, uuid: &Uuid) -> Result<Self, Error> {
+        sqlx::query_as(
+            "DELETE FROM agents WHERE uuid = ? RETURNING uuid, name, registered_at, last_seen_at",
+        )
+        .bind(uuid)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| Error::not_found(format!("agent {uuid} not found")))
+    }
+}
+
+#[cfg(test)]
+impl AgentRegistration {
+    pub fn for_testing(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::{Agent, AgentRegistration};
+    use crate::error::ErrorType;
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn register_find_delete(pool: atmosphere::Pool) -> Result<()> {
+        let agent = Agent::register(
+            &pool,
+            &AgentRegistration::for_testing(" ci-host-01 "),
+            "hash",
+        )
+        .await?;
+        assert_eq!(agent.name(), "ci-host-01");
+
+        let duplicate = Agent::register(
+            &pool,
+            &AgentRegistration::for_testing("ci-host-01"),
+            "other",
+        )
+        .await;
+        assert_eq!(
+            duplicate.map_err(|err| err.err_type),
+            Err(ErrorType::AlreadyExists)
+        );
+        assert!(
+            Agent::register(&pool, &AgentRegistration::for_testing("\n"), "other")
+                .await
+                .is_err()
+        );
+
+        assert_eq!(
+            Agent::find_by_token(&pool, "hash").await?,
+            Some(agent.clone())
+        );
+        assert_eq!(Agent::find_by_token(&pool, "warbl").await?, None);
+
+        Agent::touch(&pool, agent.uuid()).await?;
+        assert!(Agent::list(&pool).await?[0].last_seen_at.is_some());
+        assert_eq!(Agent::read(&pool, agent.uuid()).await?.name(), "ci-host-01");
+
+        Agent::delete(&pool, agent.uuid()).await?;
+        assert!(Agent::list(&pool).await?.is_empty());
+        assert!(Agent::delete(&pool, agent.uuid()).await.is_err());
+
+        Ok(())
+    }
+}
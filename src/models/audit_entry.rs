@@ -0,0 +1,516 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+#[cfg(feature = "audit-s3")]
+mod s3;
+
+use std::{io::Write as _, path::PathBuf, sync::Arc, time::Duration};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use futures::{future::BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use sqlx::{QueryBuilder, Sqlite, SqliteExecutor};
+use utoipa::{IntoParams, ToSchema};
+
+#[cfg(feature = "audit-s3")]
+pub use self::s3::S3Export;
+use super::SystemEvent;
+use crate::{
+    error::Error,
+    settings::{env_opt, AuditRetention, SettingsStore},
+    subsystems::{Shutdown, Subsystem},
+};
+
+/// How often the pruner removes entries beyond the retention limits.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Maximum number of entries removed per run of the pruner, so that pruning a large backlog
+/// doesn't hold the database lock for long.
+const PRUNE_BATCH_SIZE: i64 = 500;
+
+/// Default and maximum number of entries per page of the audit log.
+const DEFAULT_PER_PAGE: u32 = 50;
+const MAX_PER_PAGE: u32 = 500;
+
+fn first_page() -> u32 {
+    1
+}
+
+fn default_per_page() -> u32 {
+    DEFAULT_PER_PAGE
+}
+
+/// A mutating or denied API request, as recorded in the audit log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, sqlx::FromRow, ToSchema)]
+pub struct AuditEntry {
+    #[schema(example = 4711)]
+    id: i64,
+    /// When the request was handled
+    #[schema(value_type = String, format = DateTime, example = "2024-06-24T09:00:00Z")]
+    at: DateTime<Utc>,
+    /// Issuer of the token the request was made with
+    #[schema(example = "peripheral")]
+    actor: Option<String>,
+    #[schema(example = "DELETE")]
+    method: String,
+    #[schema(example = "/gitlab-runners/be924fdd-fb28-468c-8c70-1f0ed3af4485")]
+    path: String,
+    /// Status code of the response
+    #[schema(example = 200)]
+    status: u16,
+    /// Why the request was denied; only set for 401 and 403 responses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = "token lacks the Admin scope")]
+    reason: Option<String>,
+}
+
+/// Criteria selecting a page of the audit log. All given criteria must match.
+#[derive(Debug, Clone, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AuditLogFilter {
+    /// HTTP method of the request
+    #[param(example = "DELETE")]
+    pub method: Option<String>,
+    /// Prefix of the request path
+    #[param(example = "/gitlab-runners")]
+    pub path: Option<String>,
+    /// Issuer of the token the request was made with
+    pub actor: Option<String>,
+    /// Only entries recorded at or after this time
+    #[param(value_type = Option<String>, format = DateTime)]
+    pub since: Option<DateTime<Utc>>,
+    /// Only entries recorded before this time
+    #[param(value_type = Option<String>, format = DateTime)]
+    pub until: Option<DateTime<Utc>>,
+    /// Page to return, starting at 1; entries are sorted newest first
+    #[serde(default = "first_page")]
+    pub page: u32,
+    /// Entries per page (default: 50, at most 500)
+    #[serde(default = "default_per_page")]
+    pub per_page: u32,
+}
+
+impl Default for AuditLogFilter {
+    fn default() -> Self {
+        Self {
+            method: None,
+            path: None,
+            actor: None,
+            since: None,
+            until: None,
+            page: first_page(),
+            per_page: default_per_page(),
+        }
+    }
+}
+
+impl AuditLogFilter {
+    /// Appends the criteria to a query which already contains a `WHERE` clause.
+    fn push_conditions<'a>(&'a self, query: &mut QueryBuilder<'a, Sqlite>) {
+        if let Some(method) = &self.method {
+            query
+                .push(" AND method = ")
+                .push_bind(method.to_ascii_uppercase());
+        }
+        if let Some(path) = &self.path {
+            query
+                .push(" AND instr(path, ")
+                .push_bind(path.as_str())
+                .push(") = 1");
+        }
+        if let Some(actor) = &self.actor {
+            query.push(" AND actor = ").push_bind(actor.as_str());
+        }
+        if let Some(since) = self.since {
+            query
+                .push(" AND datetime(at) >= datetime(")
+                .push_bind(since)
+                .push(")");
+        }
+        if let Some(until) = self.until {
+            query
+                .push(" AND datetime(at) < datetime(")
+                .push_bind(until)
+                .push(")");
+        }
+    }
+}
+
+impl AuditEntry {
+    pub async fn record<'c>(
+        conn: impl SqliteExecutor<'c>,
+        actor: Option<&str>,
+        method: &str,
+        path: &str,
+        status: u16,
+    ) -> Result<(), Error> {
+        Self::insert(conn, actor, method, path, status, None).await
+    }
+
+    /// Records a request denied with 401 or 403, along with the reason it was denied.
+    pub async fn record_denial<'c>(
+        conn: impl SqliteExecutor<'c>,
+        actor: Option<&str>,
+        method: &str,
+        path: &str,
+        status: u16,
+        reason: &str,
+    ) -> Result<(), Error> {
+        Self::insert(conn, actor, method, path, status, Some(reason)).await
+    }
+
+    async fn insert<'c>(
+        conn: impl SqliteExecutor<'c>,
+        actor: Option<&str>,
+        method: &str,
+        path: &str,
+        status: u16,
+        reason: Option<&str>,
+    ) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO audit_log (at, actor, method, path, status, reason) \
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(Utc::now())
+        .bind(actor)
+        .bind(method)
+        .bind(path)
+        .bind(status)
+        .bind(reason)
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns the requested page of entries matching the filter, and the number of matching
+    /// entries on all pages.
+    pub async fn list(
+        pool: &atmosphere::Pool,
+        filter: &AuditLogFilter,
+    ) -> Result<(Vec<Self>, u64), Error> {
+        Self::list_where(pool, filter, "1 = 1").await
+    }
+
+    /// Like [`list`](Self::list), but only returns entries of denied requests.
+    pub async fn list_denials(
+        pool: &atmosphere::Pool,
+        filter: &AuditLogFilter,
+    ) -> Result<(Vec<Self>, u64), Error> {
+        Self::list_where(pool, filter, "status IN (401, 403)").await
+    }
+
+    async fn list_where(
+        pool: &atmosphere::Pool,
+        filter: &AuditLogFilter,
+        condition: &str,
+    ) -> Result<(Vec<Self>, u64), Error> {
+        if filter.page == 0 {
+            return Err(Error::bad_request("pages start at 1"));
+        }
+        if !(1..=MAX_PER_PAGE).contains(&filter.per_page) {
+            return Err(Error::bad_request(format!(
+                "per_page must be between 1 and {MAX_PER_PAGE}"
+            )));
+        }
+
+        let mut count = QueryBuilder::<Sqlite>::new(format!(
+            "SELECT COUNT(*) FROM audit_log WHERE {condition}"
+        ));
+        filter.push_conditions(&mut count);
+        let total: i64 = count.build_query_scalar().fetch_one(pool).await?;
+
+        let mut query =
+            QueryBuilder::<Sqlite>::new(format!("SELECT * FROM audit_log WHERE {condition}"));
+        filter.push_conditions(&mut query);
+        query
+            .push(" ORDER BY id DESC LIMIT ")
+            .push_bind(filter.per_page)
+            .push(" OFFSET ")
+            .push_bind(i64::from(filter.page - 1) * i64::from(filter.per_page));
+        let entries = query.build_query_as().fetch_all(pool).await?;
+
+        Ok((entries, u64::try_from(total).unwrap_or_default()))
+    }
+
+    /// Removes up to `limit` of the oldest entries beyond the retention limits, exporting them
+    /// first. Returns the number of removed entries.
+    pub async fn prune(
+        pool: &atmosphere::Pool,
+        retention: &AuditRetention,
+        export: &AuditExport,
+        now: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<usize, Error> {
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT * FROM audit_log WHERE 0 = 1");
+        if retention.max_age_days > 0 {
+            let cutoff = now - TimeDelta::days(i64::from(retention.max_age_days));
+            query
+                .push(" OR datetime(at) < datetime(")
+                .push_bind(cutoff)
+                .push(")");
+        }
+        if retention.max_entries > 0 {
+            query
+                .push(" OR id <= (SELECT id FROM audit_log ORDER BY id DESC LIMIT 1 OFFSET ")
+                .push_bind(i64::try_from(retention.max_entries).unwrap_or(i64::MAX))
+                .push(")");
+        }
+        query.push(" ORDER BY id LIMIT ").push_bind(limit);
+
+        let entries: Vec<Self> = query.build_query_as().fetch_all(pool).await?;
+        if entries.is_empty() {
+            return Ok(0);
+        }
+
+        // entries which can't be exported are kept, so nothing is lost
+        export.export(&entries).await?;
+
+        let mut delete = QueryBuilder::<Sqlite>::new("DELETE FROM audit_log WHERE id IN (");
+        let mut ids = delete.separated(", ");
+        for entry in &entries {
+            ids.push_bind(entry.id);
+        }
+        delete.push(")");
+        delete.build().execute(pool).await?;
+
+        Ok(entries.len())
+    }
+}
+
+/// Where pruned entries are exported to before they are deleted, as newline-delimited JSON. Only
+/// set from the environment, since the API must not be able to choose where runrs writes to.
+#[derive(Debug, Clone, Default)]
+pub struct AuditExport {
+    /// File the entries are appended to
+    path: Option<PathBuf>,
+    /// S3 location each batch of entries is uploaded to as an object of its own
+    #[cfg(feature = "audit-s3")]
+    s3: Option<S3Export>,
+}
+
+impl AuditExport {
+    /// Reads the export destinations from `AUDIT_EXPORT_PATH` and `AUDIT_EXPORT_S3`, e.g.
+    /// `s3://audit-logs/runrs/`; the latter requires the `audit-s3` feature.
+    pub async fn from_env() -> miette::Result<Self> {
+        let s3: Option<String> = env_opt("AUDIT_EXPORT_S3")?;
+
+        #[cfg(feature = "audit-s3")]
+        let s3 = match s3 {
+            Some(location) => Some(S3Export::from_env(&location).await?),
+            None => None,
+        };
+        #[cfg(not(feature = "audit-s3"))]
+        if s3.is_some() {
+            miette::bail!("AUDIT_EXPORT_S3 requires runrs to be built with the audit-s3 feature");
+        }
+
+        Ok(Self {
+            path: env_opt("AUDIT_EXPORT_PATH")?,
+            #[cfg(feature = "audit-s3")]
+            s3,
+        })
+    }
+
+    /// Exports to the file at `path`.
+    pub fn to_file(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: Some(path.into()),
+            ..Default::default()
+        }
+    }
+
+    async fn export(&self, entries: &[AuditEntry]) -> Result<(), Error> {
+        let mut ndjson = Vec::new();
+        for entry in entries {
+            serde_json::to_writer(&mut ndjson, entry).map_err(Error::internal_error)?;
+            ndjson.push(b'\n');
+        }
+
+        #[cfg(feature = "audit-s3")]
+        if let Some(s3) = &self.s3 {
+            s3.upload(entries, ndjson.clone()).await?;
+        }
+
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|err| {
+                Error::internal_error(format!("opening {} failed: {err}", path.display()))
+            })?;
+        file.write_all(&ndjson)
+            .and_then(|()| file.sync_data())
+            .map_err(|err| {
+                Error::internal_error(format!("exporting to {} failed: {err}", path.display()))
+            })
+    }
+}
+
+/// Removes audit log entries and system events beyond the retention limits, a batch at a time.
+#[derive(Debug, Clone)]
+pub struct AuditPruner {
+    pool: atmosphere::Pool,
+    settings: Arc<SettingsStore>,
+    export: AuditExport,
+}
+
+impl AuditPruner {
+    pub fn new(pool: atmosphere::Pool, settings: Arc<SettingsStore>, export: AuditExport) -> Self {
+        Self {
+            pool,
+            settings,
+            export,
+        }
+    }
+
+    async fn prune(&self) -> Result<(), Error> {
+        let retention = &self.settings.load().audit;
+
+        let pruned = AuditEntry::prune(
+            &self.pool,
+            retention,
+            &self.export,
+            Utc::now(),
+            PRUNE_BATCH_SIZE,
+        )
+        .await?;
+        if pruned > 0 {
+            tracing::info!(pruned, "pruned audit log");
+        }
+
+        // system events aren't exported, they don't stand for anything anyone did
+        let pruned =
+            SystemEvent::prune(&self.pool, retention, Utc::now(), PRUNE_BATCH_SIZE).await?;
+        if pruned > 0 {
+            tracing::info!(pruned, "pruned system events");
+        }
+
+        Ok(())
+    }
+}
+
+impl Subsystem for AuditPruner {
+    fn name(&self) -> &'static str {
+        "audit-pruner"
+    }
+
+    fn run(&self, mut shutdown: Shutdown) -> BoxFuture<'static, Result<(), Error>> {
+        let this = self.clone();
+
+        async move {
+            let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => this.prune().await?,
+                    _ = shutdown.requested() => return Ok(()),
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use atmosphere::Pool;
+    use chrono::{TimeDelta, Utc};
+    use pretty_assertions::assert_eq;
+
+    use super::{AuditEntry, AuditExport, AuditLogFilter};
+    use crate::settings::AuditRetention;
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn list_filtered_pages(pool: Pool) -> Result<()> {
+        for path in [
+            "/gitlab-runners",
+            "/config/target",
+            "/gitlab-runners/register",
+        ] {
+            AuditEntry::record(&pool, Some("peripheral"), "POST", path, 201).await?;
+        }
+        AuditEntry::record_denial(&pool, None, "DELETE", "/gitlab-runners", 403, "no token")
+            .await?;
+
+        let filter = AuditLogFilter {
+            method: Some("post".to_string()),
+            path: Some("/gitlab-runners".to_string()),
+            per_page: 1,
+            ..Default::default()
+        };
+        let (entries, total) = AuditEntry::list(&pool, &filter).await?;
+        assert_eq!(total, 2);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path, "/gitlab-runners/register");
+
+        let (entries, _) = AuditEntry::list(&pool, &AuditLogFilter { page: 2, ..filter }).await?;
+        assert_eq!(entries[0].path, "/gitlab-runners");
+
+        let filter = AuditLogFilter {
+            since: Some(Utc::now() + TimeDelta::minutes(1)),
+            ..Default::default()
+        };
+        assert_eq!(AuditEntry::list(&pool, &filter).await?, (Vec::new(), 0));
+
+        let filter = AuditLogFilter {
+            per_page: 0,
+            ..Default::default()
+        };
+        assert!(AuditEntry::list(&pool, &filter).await.is_err());
+
+        let (entries, total) = AuditEntry::list_denials(&pool, &AuditLogFilter::default()).await?;
+        assert_eq!(total, 1);
+        assert_eq!(entries[0].reason.as_deref(), Some("no token"));
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn prune_and_export(pool: Pool) -> Result<()> {
+        let export_path =
+            std::env::temp_dir().join(format!("runrs-audit-{}.ndjson", uuid::Uuid::new_v4()));
+        for status in [200, 201, 202, 204] {
+            AuditEntry::record(&pool, None, "PUT", "/settings/runtime", status).await?;
+        }
+        sqlx::query("UPDATE audit_log SET at = ? WHERE status = 200")
+            .bind(Utc::now() - TimeDelta::days(100))
+            .execute(&pool)
+            .await?;
+
+        let retention = AuditRetention {
+            max_age_days: 90,
+            max_entries: 2,
+        };
+        let export = AuditExport::to_file(&export_path);
+        // pruning is limited to a batch at a time
+        for (limit, pruned) in [(1, 1), (10, 1), (10, 0)] {
+            assert_eq!(
+                AuditEntry::prune(&pool, &retention, &export, Utc::now(), limit).await?,
+                pruned
+            );
+        }
+
+        let (entries, total) = AuditEntry::list(&pool, &AuditLogFilter::default()).await?;
+        assert_eq!(total, 2);
+        assert_eq!(entries[0].status, 204);
+        assert_eq!(entries[1].status, 202);
+
+        let exported = std::fs::read_to_string(&export_path)?;
+        let exported: Vec<serde_json::Value> = exported
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<std::result::Result<_, _>>()?;
+        assert_eq!(exported.len(), 2);
+        assert_eq!(exported[0]["status"], 200);
+        assert_eq!(exported[1]["status"], 201);
+
+        std::fs::remove_file(&export_path)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,68 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use aws_sdk_s3::{primitives::ByteStream, Client};
+
+use super::AuditEntry;
+use crate::error::Error;
+
+/// Uploads pruned audit log entries to S3, each batch as an object of its own, named after the
+/// IDs of its first and last entry; uploading a batch again after a failed prune overwrites the
+/// object instead of duplicating entries.
+#[derive(Debug, Clone)]
+pub struct S3Export {
+    client: Client,
+    bucket: String,
+    prefix: String,
+}
+
+impl S3Export {
+    /// Parses a location like `s3://audit-logs/runrs/` and uses the default AWS credential chain,
+    /// i.e. the `AWS_*` environment variables, the shared config files or the instance role.
+    pub async fn from_env(location: &str) -> miette::Result<Self> {
+        let (bucket, prefix) = location
+            .strip_prefix("s3://")
+            .map(|location| location.split_once('/').unwrap_or((location, "")))
+            .filter(|(bucket, _)| !bucket.is_empty())
+            .ok_or_else(|| {
+                miette::miette!(
+                    "invalid value for AUDIT_EXPORT_S3: {location}; must look like \
+                     s3://<bucket>/<prefix>"
+                )
+            })?;
+
+        let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+        Ok(Self {
+            client: Client::new(&config),
+            bucket: bucket.to_string(),
+            prefix: prefix.to_string(),
+        })
+    }
+
+    pub(super) async fn upload(
+        &self,
+        entries: &[AuditEntry],
+        ndjson: Vec<u8>,
+    ) -> Result<(), Error> {
+        let (Some(first), Some(last)) = (entries.first(), entries.last()) else {
+            return Ok(());
+        };
+        let key = format!("{}audit-log-{}-{}.ndjson", self.prefix, first.id, last.id);
+
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .content_type("application/x-ndjson")
+            .body(ByteStream::from(ndjson))
+            .send()
+            .await
+            .map_err(|err| {
+                Error::connection_failed(format!(
+                    "exporting to s3://{}/{key} failed: {err}",
+                    self.bucket
+                ))
+            })?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::path::PathBuf;
+
+use glrcfg::runner::{ImageReference, RunnerToken, Url};
+use uuid::Uuid;
+
+use super::{Change, ConfigCache, GitLabRunner, GitLabRunnerConfig};
+use crate::{
+    error::Error,
+    secrets::{Credential, Secrets},
+    settings::{env_opt, env_or, Settings},
+};
+
+/// The variables which together configure the runner to bootstrap.
+const REQUIRED_VARS: [&str; 3] = [
+    "BOOTSTRAP_RUNNER_URL",
+    "BOOTSTRAP_RUNNER_TOKEN",
+    "BOOTSTRAP_DOCKER_IMAGE",
+];
+
+/// A runner created on startup if the database is empty, so that single-runner appliances are
+/// set up without a single API call. Given in the environment as `BOOTSTRAP_RUNNER_URL`,
+/// `BOOTSTRAP_RUNNER_TOKEN` and `BOOTSTRAP_DOCKER_IMAGE`, optionally along with
+/// `BOOTSTRAP_RUNNER_ID` and `BOOTSTRAP_RUNNER_NAME`.
+#[derive(Debug, Clone)]
+pub struct Bootstrap {
+    runner: GitLabRunner,
+}
+
+impl Bootstrap {
+    /// Reads the runner to bootstrap from the environment. Returns `None` if none is given, and
+    /// an error if only some of the required variables are set.
+    pub fn from_env() -> miette::Result<Option<Self>> {
+        let url: Option<Url> = env_opt("BOOTSTRAP_RUNNER_URL")?;
+        // read as string, since the parse error would contain the token
+        let token: Option<String> = env_opt("BOOTSTRAP_RUNNER_TOKEN")?;
+        let docker_image: Option<ImageReference> = env_opt("BOOTSTRAP_DOCKER_IMAGE")?;
+
+        let (url, token, docker_image) = match (url, token, docker_image) {
+            (Some(url), Some(token), Some(docker_image)) => (url, token, docker_image),
+            (None, None, None) => return Ok(None),
+            _ => miette::bail!(
+                "bootstrapping a runner requires all of {}",
+                REQUIRED_VARS.join(", ")
+            ),
+        };
+        let token: Credential<RunnerToken> = token
+            .parse()
+            .map_err(|_| miette::miette!("invalid value for BOOTSTRAP_RUNNER_TOKEN"))?;
+
+        Ok(Some(Self {
+            runner: GitLabRunner::bootstrap(
+                env_or("BOOTSTRAP_RUNNER_ID", 0)?,
+                env_opt("BOOTSTRAP_RUNNER_NAME")?,
+                url,
+                token,
+                docker_image,
+            ),
+        }))
+    }
+
+    /// Creates the runner and writes the config, unless the database already holds runners.
+    /// Returns the UUID of the created runner.
+    pub async fn apply(
+        mut self,
+        pool: &atmosphere::Pool,
+        config_path: &PathBuf,
+        config_cache: &ConfigCache,
+        settings: &Settings,
+        secrets: &Secrets,
+    ) -> Result<Option<Uuid>, Error> {
+        let (runners,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM gitlab_runners")
+            .fetch_one(pool)
+            .await?;
+        if runners > 0 {
+            return Ok(None);
+        }
+
+        self.runner.normalize(settings)?;
+        self.runner.check_token(secrets).await?;
+        self.runner
+            .apply(pool, Change::Created, settings.events.enabled())
+            .await?;
+
+        config_cache.bump();
+        let sync =
+            GitLabRunnerConfig::write_or_queue(pool, config_path, config_cache, &settings.render)
+                .await?;
+        tracing::debug!(?sync, "runners config written or queued");
+
+        Ok(Some(*self.runner.uuid()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use atmosphere::{Pool, Read as _};
+    use glrcfg::runner::{ImageReference, RunnerToken, Url};
+    use pretty_assertions::assert_eq;
+
+    use super::Bootstrap;
+    use crate::models::{ConfigCache, GitLabRunner};
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn bootstrap_into_empty_database(pool: Pool) -> Result<()> {
+        let bootstrap = Bootstrap {
+            runner: GitLabRunner::bootstrap(
+                7,
+                Some("appliance".to_string()),
+                Url::parse("https://gitlab.your-company.com")?,
+                RunnerToken::parse("glrt-0123456789_abcdefXYZ")?.into(),
+                ImageReference::parse("alpine:latest")?,
+            ),
+        };
+        let path = PathBuf::from(format!(
+            "/tmp/gitlab-runner-config-{}.toml",
+            uuid::Uuid::new_v4()
+        ));
+        let cache = ConfigCache::default();
+        let (settings, secrets) = (Default::default(), Default::default());
+
+        let uuid = bootstrap
+            .clone()
+            .apply(&pool, &path, &cache, &settings, &secrets)
+            .await?
+            .ok_or("runner was not bootstrapped")?;
+        let runner = GitLabRunner::find(&pool, &uuid)
+            .await?
+            .ok_or("runner not in database")?;
+        assert_eq!(runner.uuid(), &uuid);
+        assert!(std::fs::read_to_string(&path)?.contains("glrt-0123456789_abcdefXYZ"));
+
+        // once the database holds runners, bootstrapping does nothing
+        let uuid = bootstrap
+            .apply(&pool, &path, &cache, &settings, &secrets)
+            .await?;
+        assert_eq!(uuid, None);
+        assert_eq!(GitLabRunner::read_all(&pool).await?.len(), 1);
+
+        std::fs::remove_file(&path)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,317 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::collections::HashSet;
+
+use axum::http::StatusCode;
+use futures::{stream, StreamExt as _};
+use serde::Serialize;
+use sqlx::{Connection as _, SqliteConnection};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::{Change, GitLabRunner};
+use crate::{error::Error, gitlab::GitLabClient, secrets::Secrets, settings::Settings};
+
+/// Maximum number of runners in a single bulk request.
+pub const MAX_BULK_RUNNERS: usize = 1000;
+
+/// Maximum number of runners in a single bulk request whose tokens are verified with GitLab, so
+/// that verifying them all finishes well within the request timeout.
+pub const MAX_VERIFIED_BULK_RUNNERS: usize = 100;
+
+/// Outcome for a single runner of `POST /gitlab-runners/bulk`.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BulkResult {
+    /// Position of the runner in the request, starting at 0
+    #[schema(example = 0)]
+    index: usize,
+    #[schema(value_type = String, format = Uuid, example = "be924fdd-fb28-468c-8c70-1f0ed3af4485")]
+    uuid: Uuid,
+    /// Status the runner would have gotten from `POST /gitlab-runners` or
+    /// `PUT /gitlab-runners/:uuid`, i.e. 201 if it was created and 200 if it was updated
+    #[schema(example = 201)]
+    status: u16,
+    /// Warnings about the runner, e.g. about questionable configuration
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+    /// Why the runner was neither created nor updated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<Error>,
+}
+
+impl BulkResult {
+    fn stored(index: usize, runner: &GitLabRunner, change: Change, warnings: Vec<String>) -> Self {
+        let status = match change {
+            Change::Created => StatusCode::CREATED,
+            _ => StatusCode::OK,
+        };
+
+        Self {
+            index,
+            uuid: *runner.uuid(),
+            status: status.as_u16(),
+            warnings,
+            error: None,
+        }
+    }
+
+    fn skipped(index: usize, runner: &GitLabRunner, err: Error) -> Self {
+        tracing::debug!(index, uuid = %runner.uuid(), %err, "skipping runner");
+
+        Self {
+            index,
+            uuid: *runner.uuid(),
+            status: err.err_type.status_code().as_u16(),
+            warnings: Vec::new(),
+            error: Some(err),
+        }
+    }
+
+    pub fn applied(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+impl GitLabRunner {
+    /// Creates the runners which don't exist yet and updates the others, in a single
+    /// transaction. Each runner is checked like one created via `POST /gitlab-runners` or
+    /// updated via `PUT /gitlab-runners/:uuid`: a runner failing the checks is reported and
+    /// skipped, without affecting the others.
+    ///
+    /// Tokens are resolved from the secret stores, and verified with `gitlab` if given, before the
+    /// transaction begins, so the database isn't locked while waiting for them; up to
+    /// `settings.verification.parallelism` runners are checked at once. Only the checks against
+    /// the database run in the transaction, in a savepoint per runner.
+    pub async fn bulk_apply(
+        pool: &atmosphere::Pool,
+        runners: Vec<Self>,
+        settings: &Settings,
+        secrets: &Secrets,
+        gitlab: Option<&GitLabClient>,
+        allow_expired: bool,
+    ) -> Result<Vec<BulkResult>, Error> {
+        let max_runners = match gitlab {
+            Some(_) => MAX_VERIFIED_BULK_RUNNERS,
+            None => MAX_BULK_RUNNERS,
+        };
+        if runners.len() > max_runners {
+            return Err(Error::bad_request(format!(
+                "at most {max_runners} runners are allowed per request{}",
+                if gitlab.is_some() { " with verify" } else { "" }
+            )));
+        }
+
+        let existing: HashSet<Uuid> = sqlx::query_scalar("SELECT uuid FROM gitlab_runners")
+            .fetch_all(pool)
+            .await?
+            .into_iter()
+            .collect();
+
+        let checked: Vec<_> = stream::iter(runners)
+            .map(|mut runner| {
+                let change = if existing.contains(runner.uuid()) {
+                    Change::Updated
+                } else {
+                    Change::Created
+                };
+                async move {
+                    let warnings = runner
+                        .bulk_check(change, settings, secrets, gitlab, allow_expired)
+                        .await;
+                    (runner, change, warnings)
+                }
+            })
+            .buffered(settings.verification.parallelism.max(1))
+            .collect()
+            .await;
+
+        let mut tx = pool.begin().await?;
+        let mut results = Vec::with_capacity(checked.len());
+
+        for (index, (mut runner, change, warnings)) in checked.into_iter().enumerate() {
+            let warnings = match warnings {
+                Ok(warnings) => warnings,
+                Err(err) => {
+                    results.push(BulkResult::skipped(index, &runner, err));
+                    continue;
+                }
+            };
+
+            let mut savepoint = tx.begin().await?;
+            let result = match runner.bulk_store(&mut savepoint, change, settings).await {
+                Ok(()) => {
+                    savepoint.commit().await?;
+                    BulkResult::stored(index, &runner, change, warnings)
+                }
+                Err(err) => {
+                    savepoint.rollback().await?;
+                    BulkResult::skipped(index, &runner, err)
+                }
+            };
+            results.push(result);
+        }
+
+        tx.commit().await?;
+
+        Ok(results)
+    }
+
+    /// Checks the runner before it is stored, without the database, and returns the warnings
+    /// about it.
+    async fn bulk_check(
+        &mut self,
+        change: Change,
+        settings: &Settings,
+        secrets: &Secrets,
+        gitlab: Option<&GitLabClient>,
+        allow_expired: bool,
+    ) -> Result<Vec<String>, Error> {
+        if change == Change::Created {
+            if let Some(preset) = self.preset() {
+                self.apply_preset(preset);
+            }
+        }
+
+        self.normalize(settings)?;
+        self.check_token(secrets).await?;
+        let mut warnings = Vec::from_iter(self.check_token_expiry(allow_expired)?);
+        warnings.extend(self.lint().iter().map(ToString::to_string));
+
+        // a token GitLab rejects would only flood the `gitlab-runner` logs with auth errors
+        if let Some(gitlab) = gitlab {
+            if !self.verify(gitlab, secrets).await? {
+                return Err(Error::invalid_argument(format!(
+                    "GitLab at {} rejected the runner token",
+                    self.url()
+                )));
+            }
+        }
+
+        Ok(warnings)
+    }
+
+    /// Stores the checked runner, unless another request created or deleted it since.
+    async fn bulk_store(
+        &mut self,
+        conn: &mut SqliteConnection,
+        change: Change,
+        settings: &Settings,
+    ) -> Result<(), Error> {
+        let existing: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM gitlab_runners WHERE uuid = ?")
+                .bind(self.uuid())
+                .fetch_one(&mut *conn)
+                .await?;
+        match (change, existing > 0) {
+            (Change::Created, true) => {
+                return Err(Error::already_exists(format!(
+                    "runner {} was created by another request meanwhile",
+                    self.uuid()
+                )))
+            }
+            (Change::Updated, false) => {
+                return Err(Error::not_found(format!(
+                    "runner {} was deleted by another request meanwhile",
+                    self.uuid()
+                )))
+            }
+            _ => {}
+        }
+
+        self.apply_checked_in(conn, change, settings).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use atmosphere::{Create as _, Pool, Read as _};
+    use pretty_assertions::assert_eq;
+
+    use super::MAX_VERIFIED_BULK_RUNNERS;
+    use crate::{
+        gitlab::GitLabClient,
+        models::GitLabRunner,
+        secrets::Secrets,
+        settings::{Quotas, Settings},
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn create_update_skip(pool: Pool) -> Result<()> {
+        let mut existing = GitLabRunner::for_testing();
+        existing.create(&pool).await?;
+        existing.set_name("renamed");
+
+        let mut created = GitLabRunner::for_testing();
+        created.set_url("https://gitlab.bmc-labs.com");
+        let mut over_quota = GitLabRunner::for_testing();
+        over_quota.set_url("https://gitlab.com");
+
+        let settings = Settings {
+            quotas: Quotas {
+                max_runners: 2,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let results = GitLabRunner::bulk_apply(
+            &pool,
+            vec![existing.clone(), created.clone(), over_quota.clone()],
+            &settings,
+            &Secrets::default(),
+            None,
+            false,
+        )
+        .await?;
+
+        let statuses: Vec<_> = results.iter().map(|result| result.status).collect();
+        assert_eq!(statuses, vec![200, 201, 403]);
+        assert!(!results[2].applied());
+
+        let stored = GitLabRunner::find(&pool, existing.uuid()).await?;
+        assert_eq!(
+            stored.map(|runner| runner.name().to_string()).as_deref(),
+            Some("renamed")
+        );
+        assert!(GitLabRunner::find(&pool, created.uuid()).await?.is_some());
+        assert!(GitLabRunner::find(&pool, over_quota.uuid())
+            .await?
+            .is_none());
+
+        Ok(())
+    }
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn fewer_runners_with_verify(pool: Pool) -> Result<()> {
+        let runners: Vec<_> = (0..=MAX_VERIFIED_BULK_RUNNERS)
+            .map(|_| GitLabRunner::for_testing())
+            .collect();
+
+        // verifying this many tokens wouldn't finish within the request timeout
+        let gitlab = GitLabClient::default();
+        let err = GitLabRunner::bulk_apply(
+            &pool,
+            runners.clone(),
+            &Settings::default(),
+            &Secrets::default(),
+            Some(&gitlab),
+            false,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.msg.contains("with verify"));
+
+        let results = GitLabRunner::bulk_apply(
+            &pool,
+            runners,
+            &Settings::default(),
+            &Secrets::default(),
+            None,
+            false,
+        )
+        .await?;
+        assert_eq!(results.len(), MAX_VERIFIED_BULK_RUNNERS + 1);
+
+        Ok(())
+    }
+}
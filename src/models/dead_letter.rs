@@ -0,0 +1,415 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use chrono::{DateTime, Utc};
+use glrcfg::runner::{RunnerToken, Url};
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteExecutor;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::OutboxEvent;
+use crate::{
+    error::Error,
+    gitlab::GitLabClient,
+    notifications::{self, Notification},
+    settings::{Notifications, REDACTED},
+    webhooks::WebhookClient,
+};
+
+/// Kind of the dead letters which are runner change events from the outbox.
+pub(super) const EVENT_KIND: &str = "event";
+
+/// Kind of the dead letters which are notifications a channel didn't accept.
+const NOTIFICATION_KIND: &str = "notification";
+
+/// Kind of the dead letters which are runners GitLab couldn't be told to unregister.
+const UNREGISTER_KIND: &str = "gitlab_unregister";
+
+/// A notification which couldn't be sent via a channel.
+#[derive(Debug, Serialize, Deserialize)]
+struct UndeliveredNotification {
+    channel: String,
+    notification: Notification,
+}
+
+/// A runner which is known to GitLab but not to runrs, e.g. because storing its registration
+/// failed, and which GitLab couldn't be told to unregister.
+#[derive(Debug, Serialize, Deserialize)]
+struct FailedUnregistration {
+    url: Url,
+    id: u32,
+    token: RunnerToken,
+}
+
+/// Work which exhausted its retries, e.g. an event the webhook receiver never accepted. Dead
+/// letters are kept along with why they failed until they are replayed, so nothing is lost
+/// silently.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct DeadLetter {
+    #[schema(example = 1)]
+    id: i64,
+    /// What failed; `event` for runner change events, `notification` for notifications and
+    /// `gitlab_unregister` for runners which are still registered with GitLab
+    #[schema(example = "event")]
+    kind: String,
+    /// Key the work is deduplicated by, which is kept when it is replayed
+    #[schema(example = "6f1c2b9e-4a8d-4e3f-b5c7-2d9a8e1f0b34")]
+    dedup_key: String,
+    /// The work as JSON, e.g. the event as it is sent to the receiver; runner tokens are redacted
+    #[schema(value_type = Object)]
+    payload: serde_json::Value,
+    /// Number of failed attempts
+    #[schema(example = 10)]
+    attempts: u32,
+    /// Reason the last attempt failed
+    #[schema(example = "error sending request for url (https://hooks.your-company.com/runrs)")]
+    last_error: String,
+    /// When the work was queued originally
+    #[schema(value_type = String, format = DateTime, example = "2024-07-06T09:00:00Z")]
+    created_at: DateTime<Utc>,
+    /// When the work was given up on
+    #[schema(value_type = String, format = DateTime, example = "2024-07-06T09:17:00Z")]
+    failed_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow)]
+struct DeadLetterRow {
+    id: i64,
+    kind: String,
+    dedup_key: String,
+    payload: String,
+    attempts: u32,
+    last_error: String,
+    created_at: DateTime<Utc>,
+    failed_at: DateTime<Utc>,
+}
+
+impl TryFrom<DeadLetterRow> for DeadLetter {
+    type Error = Error;
+
+    fn try_from(row: DeadLetterRow) -> Result<Self, Self::Error> {
+        let mut payload: serde_json::Value =
+            serde_json::from_str(&row.payload).map_err(Error::internal_error)?;
+        // the token is kept for the replay, but never shown
+        if let Some(token) = payload.get_mut("token") {
+            *token = REDACTED.into();
+        }
+
+        Ok(Self {
+            id: row.id,
+            kind: row.kind,
+            dedup_key: row.dedup_key,
+            payload,
+            attempts: row.attempts,
+            last_error: row.last_error,
+            created_at: row.created_at,
+            failed_at: row.failed_at,
+        })
+    }
+}
+
+impl DeadLetter {
+    /// Keeps the event which exhausted its retries; it must be removed from the outbox in the same
+    /// transaction.
+    pub(super) async fn record<'c>(
+        conn: impl SqliteExecutor<'c>,
+        event: &OutboxEvent,
+    ) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO dead_letters \
+             (kind, dedup_key, payload, attempts, last_error, created_at, failed_at) \
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(EVENT_KIND)
+        .bind(&event.dedup_key)
+        .bind(&event.payload)
+        .bind(event.attempts)
+        .bind(event.last_error.as_deref().unwrap_or_default())
+        .bind(event.created_at)
+        .bind(Utc::now())
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Keeps the notification `channel` didn't accept. Notifications are sent once, so it counts
+    /// as having exhausted its retries right away.
+    pub async fn notification(
+        pool: &atmosphere::Pool,
+        channel: &str,
+        notification: &Notification,
+        error: &str,
+    ) -> Result<(), Error> {
+        let undelivered = UndeliveredNotification {
+            channel: channel.to_string(),
+            notification: notification.clone(),
+        };
+        Self::insert(pool, NOTIFICATION_KIND, &undelivered, error).await
+    }
+
+    /// Keeps the runner GitLab couldn't be told to unregister, so that it isn't left registered
+    /// without anybody knowing.
+    pub async fn unregistration(
+        pool: &atmosphere::Pool,
+        url: &Url,
+        id: u32,
+        token: &RunnerToken,
+        error: &str,
+    ) -> Result<(), Error> {
+        let unregistration = FailedUnregistration {
+            url: url.clone(),
+            id,
+            token: token.clone(),
+        };
+        Self::insert(pool, UNREGISTER_KIND, &unregistration, error).await
+    }
+
+    /// Keeps work which was attempted once, outside of the outbox.
+    async fn insert(
+        pool: &atmosphere::Pool,
+        kind: &str,
+        payload: &impl Serialize,
+        error: &str,
+    ) -> Result<(), Error> {
+        let payload = serde_json::to_string(payload).map_err(Error::internal_error)?;
+        let now = Utc::now();
+
+        sqlx::query(
+            "INSERT INTO dead_letters \
+             (kind, dedup_key, payload, attempts, last_error, created_at, failed_at) \
+             VALUES (?, ?, ?, 1, ?, ?, ?)",
+        )
+        .bind(kind)
+        .bind(Uuid::new_v4().to_string())
+        .bind(payload)
+        .bind(error)
+        .bind(now)
+        .bind(now)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns all dead letters, most recently failed first.
+    pub async fn list(pool: &atmosphere::Pool) -> Result<Vec<Self>, Error> {
+        let rows: Vec<DeadLetterRow> =
+            sqlx::query_as("SELECT * FROM dead_letters ORDER BY failed_at DESC, id DESC")
+                .fetch_all(pool)
+                .await?;
+
+        rows.into_iter().map(Self::try_from).collect()
+    }
+
+    /// Replays the dead letter and removes it. Events are queued again with their retries reset
+    /// and their `dedup_key` kept, so receivers which got them after all recognize them as
+    /// duplicates. Notifications are sent via their channel as configured now, and runners are
+    /// unregistered from GitLab right away; if that fails again, the dead letter is kept.
+    pub async fn replay(
+        pool: &atmosphere::Pool,
+        id: i64,
+        gitlab: &GitLabClient,
+        notifications: &Notifications,
+    ) -> Result<Self, Error> {
+        let row: Option<DeadLetterRow> = sqlx::query_as("SELECT * FROM dead_letters WHERE id = ?")
+            .bind(id)
+            .fetch_optional(pool)
+            .await?;
+        let Some(row) = row else {
+            return Err(Error::not_found(format!("no dead letter {id}")));
+        };
+
+        match row.kind.as_str() {
+            EVENT_KIND => {
+                let mut tx = pool.begin().await?;
+                // a dead letter replayed concurrently is queued only once
+                let deleted = sqlx::query("DELETE FROM dead_letters WHERE id = ?")
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+                if deleted.rows_affected() == 0 {
+                    return Err(Error::not_found(format!("no dead letter {id}")));
+                }
+                OutboxEvent::requeue(&mut *tx, &row.dedup_key, &row.payload, row.created_at)
+                    .await?;
+                tx.commit().await?;
+            }
+            // the work isn't queued but done right away, so the database isn't locked meanwhile
+            NOTIFICATION_KIND => {
+                let undelivered: UndeliveredNotification =
+                    serde_json::from_str(&row.payload).map_err(Error::internal_error)?;
+                notifications::send_via(
+                    notifications,
+                    &WebhookClient::default(),
+                    &undelivered.channel,
+                    &undelivered.notification,
+                )
+                .await?;
+                Self::remove(pool, id).await?;
+            }
+            UNREGISTER_KIND => {
+                let unregistration: FailedUnregistration =
+                    serde_json::from_str(&row.payload).map_err(Error::internal_error)?;
+                gitlab
+                    .unregister_runner(&unregistration.url, &unregistration.token)
+                    .await?;
+                Self::remove(pool, id).await?;
+            }
+            kind => {
+                return Err(Error::internal_error(format!(
+                    "dead letter {id} is of unknown kind {kind}"
+                )))
+            }
+        }
+
+        Self::try_from(row)
+    }
+
+    async fn remove(pool: &atmosphere::Pool, id: i64) -> Result<(), Error> {
+        sqlx::query("DELETE FROM dead_letters WHERE id = ?")
+            .bind(id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+
+    pub fn dedup_key(&self) -> &str {
+        &self.dedup_key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use atmosphere::Pool;
+    use axum::{extract::State, routing::post, Json, Router};
+    use glrcfg::runner::{RunnerToken, Url};
+    use pretty_assertions::assert_eq;
+
+    use super::DeadLetter;
+    use crate::{
+        gitlab::GitLabClient,
+        models::{Change, GitLabRunner, OutboxEvent},
+        notifications::{ChannelConfig, Notification},
+        settings::{Notifications, REDACTED},
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn bury_and_replay(pool: Pool) -> Result<()> {
+        let mut runner = GitLabRunner::for_testing();
+        runner.apply(&pool, Change::Created, true).await?;
+
+        let mut event = OutboxEvent::pending(&pool, 10).await?.remove(0);
+        event.record_failure(&pool, "connection refused").await?;
+        event.bury(&pool).await?;
+        assert!(OutboxEvent::pending(&pool, 10).await?.is_empty());
+
+        let dead = DeadLetter::list(&pool).await?;
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].kind(), "event");
+        assert_eq!(dead[0].attempts, 1);
+        assert_eq!(dead[0].last_error, "connection refused");
+        assert_eq!(dead[0].payload["event"], "runner_created");
+        assert_eq!(dead[0].payload["uuid"], runner.uuid().to_string());
+
+        let (gitlab, notifications) = (GitLabClient::default(), Notifications::default());
+        let replayed = DeadLetter::replay(&pool, dead[0].id, &gitlab, &notifications).await?;
+        assert_eq!(replayed, dead[0]);
+        assert!(DeadLetter::list(&pool).await?.is_empty());
+
+        let pending = OutboxEvent::pending(&pool, 10).await?;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].dedup_key, replayed.dedup_key());
+        assert_eq!(pending[0].attempts, 0);
+        assert!(pending[0].is_due());
+
+        // a dead letter is replayed at most once
+        assert!(
+            DeadLetter::replay(&pool, dead[0].id, &gitlab, &notifications)
+                .await
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn replay_notification(pool: Pool) -> Result<()> {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let receiver = Router::new()
+            .route(
+                "/",
+                post(
+                    |State(received): State<Arc<Mutex<Vec<serde_json::Value>>>>,
+                     Json(payload): Json<serde_json::Value>| async move {
+                        received.lock().expect("lock is not poisoned").push(payload);
+                    },
+                ),
+            )
+            .with_state(received.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let receiver_url = format!("http://{}/", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, receiver).await });
+
+        let notification: Notification = serde_json::from_value(serde_json::json!({
+            "kind": "config_drift",
+            "subject": "Runner config drifted",
+            "message": "The config doesn't match the runners in the database.",
+            "raised_at": "2024-07-06T09:00:00Z",
+        }))?;
+        DeadLetter::notification(&pool, "ops", &notification, "connection refused").await?;
+        let id = DeadLetter::list(&pool).await?[0].id;
+
+        // the channel is gone, so the notification is kept
+        let gitlab = GitLabClient::default();
+        let mut notifications = Notifications::default();
+        assert!(DeadLetter::replay(&pool, id, &gitlab, &notifications)
+            .await
+            .is_err());
+        assert_eq!(DeadLetter::list(&pool).await?.len(), 1);
+
+        notifications.channels.insert(
+            "ops".to_string(),
+            ChannelConfig::Generic { url: receiver_url },
+        );
+        let replayed = DeadLetter::replay(&pool, id, &gitlab, &notifications).await?;
+        assert_eq!(replayed.kind(), "notification");
+        assert!(DeadLetter::list(&pool).await?.is_empty());
+
+        let received = received.lock().expect("lock is not poisoned").clone();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0]["subject"], "Runner config drifted");
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn unregistration_token_redacted(pool: Pool) -> Result<()> {
+        let url = Url::parse("https://gitlab.your-company.com")?;
+        let token = RunnerToken::parse("glrt-0123456789_abcdefXYZ")?;
+        DeadLetter::unregistration(&pool, &url, 42, &token, "connection refused").await?;
+
+        let dead = DeadLetter::list(&pool).await?;
+        assert_eq!(dead[0].kind(), "gitlab_unregister");
+        assert_eq!(dead[0].attempts, 1);
+        assert_eq!(dead[0].payload["id"], 42);
+        assert_eq!(dead[0].payload["token"], REDACTED);
+
+        // the token itself is kept for the replay
+        let payload: String = sqlx::query_scalar("SELECT payload FROM dead_letters")
+            .fetch_one(&pool)
+            .await?;
+        assert!(payload.contains(token.as_str()));
+
+        Ok(())
+    }
+}
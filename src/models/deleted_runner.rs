@@ -0,0 +1,250 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{sync::Arc, time::Duration};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use futures::{future::BoxFuture, FutureExt};
+use serde::{Serialize, Serializer};
+use sqlx::SqliteExecutor;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::{GitLabRunner, RunnerHistory, SystemEvent, SystemEventKind};
+use crate::{
+    error::Error,
+    settings::{RecycleBin, SettingsStore},
+    subsystems::{Shutdown, Subsystem},
+};
+
+/// How often the purger removes deleted runners beyond the retention.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Maximum number of deleted runners removed per run of the purger.
+const PURGE_BATCH_SIZE: i64 = 500;
+
+/// Actor of the system events recorded for purged runners.
+const PURGE_ACTOR: &str = "runrs";
+
+/// A deleted runner, kept in the recycle bin until it is purged.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct DeletedRunner {
+    /// The runner as it was when it was deleted, with its token masked
+    #[serde(serialize_with = "serialize_masked")]
+    runner: GitLabRunner,
+    /// When the runner was deleted
+    #[schema(value_type = String, format = DateTime, example = "2024-06-27T09:00:00Z")]
+    deleted_at: DateTime<Utc>,
+    /// Issuer of the token the runner was deleted with, or `runrs` if it expired
+    #[schema(example = "peripheral")]
+    deleted_by: Option<String>,
+}
+
+/// Serializes a deleted runner with its token masked; the recycle bin is for reference only, a
+/// deleted runner's token must not be usable by whoever reads it.
+fn serialize_masked<S: Serializer>(
+    runner: &GitLabRunner,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut value = serde_json::to_value(runner).map_err(serde::ser::Error::custom)?;
+    value["token"] = runner.masked_token().into();
+    value.serialize(serializer)
+}
+
+#[derive(sqlx::FromRow)]
+struct DeletedRunnerRow {
+    runner: String,
+    deleted_at: DateTime<Utc>,
+    deleted_by: Option<String>,
+}
+
+impl TryFrom<DeletedRunnerRow> for DeletedRunner {
+    type Error = Error;
+
+    fn try_from(row: DeletedRunnerRow) -> Result<Self, Self::Error> {
+        Ok(Self {
+            runner: serde_json::from_str(&row.runner).map_err(Error::internal_error)?,
+            deleted_at: row.deleted_at,
+            deleted_by: row.deleted_by,
+        })
+    }
+}
+
+impl DeletedRunner {
+    /// Moves the runner to the recycle bin; it must be deleted from `gitlab_runners` in the same
+    /// transaction.
+    pub async fn record<'c>(
+        conn: impl SqliteExecutor<'c>,
+        runner: &GitLabRunner,
+        actor: Option<&str>,
+    ) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO deleted_gitlab_runners (uuid, runner, deleted_at, deleted_by) \
+             VALUES (?, ?, ?, ?)",
+        )
+        .bind(runner.uuid())
+        .bind(serde_json::to_string(runner).map_err(Error::internal_error)?)
+        .bind(Utc::now())
+        .bind(actor)
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns all runners in the recycle bin, most recently deleted first.
+    pub async fn list(pool: &atmosphere::Pool) -> Result<Vec<Self>, Error> {
+        let rows: Vec<DeletedRunnerRow> = sqlx::query_as(
+            "SELECT runner, deleted_at, deleted_by FROM deleted_gitlab_runners ORDER BY id DESC",
+        )
+        .fetch_all(pool)
+        .await?;
+
+        rows.into_iter().map(Self::try_from).collect()
+    }
+
+    /// Permanently removes up to `limit` of the runners deleted longer than the retention ago,
+    /// recording each as a system event in the same transaction. Returns the UUIDs of the purged
+    /// runners.
+    pub async fn purge(
+        pool: &atmosphere::Pool,
+        retention: &RecycleBin,
+        now: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<Uuid>, Error> {
+        if retention.retention_days == 0 {
+            return Ok(Vec::new());
+        }
+
+        let cutoff = now - TimeDelta::days(i64::from(retention.retention_days));
+        let mut tx = pool.begin().await?;
+        let purged: Vec<Uuid> = sqlx::query_scalar(
+            "DELETE FROM deleted_gitlab_runners WHERE id IN (SELECT id FROM deleted_gitlab_runners \
+             WHERE datetime(deleted_at) < datetime(?) ORDER BY id LIMIT ?) RETURNING uuid",
+        )
+        .bind(cutoff)
+        .bind(limit)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for uuid in &purged {
+            RunnerHistory::forget(&mut *tx, uuid).await?;
+            let path = format!("/gitlab-runners/deleted/{uuid}");
+            SystemEvent::record(&mut *tx, SystemEventKind::Purge, PURGE_ACTOR, &path, None).await?;
+        }
+        tx.commit().await?;
+
+        Ok(purged)
+    }
+}
+
+/// Permanently removes deleted runners once they were in the recycle bin for longer than the
+/// retention, a batch at a time.
+#[derive(Debug, Clone)]
+pub struct RecycleBinPurger {
+    pool: atmosphere::Pool,
+    settings: Arc<SettingsStore>,
+}
+
+impl RecycleBinPurger {
+    pub fn new(pool: atmosphere::Pool, settings: Arc<SettingsStore>) -> Self {
+        Self { pool, settings }
+    }
+
+    async fn purge(&self) -> Result<(), Error> {
+        let retention = &self.settings.load().recycle_bin;
+
+        let purged =
+            DeletedRunner::purge(&self.pool, retention, Utc::now(), PURGE_BATCH_SIZE).await?;
+        if !purged.is_empty() {
+            tracing::info!(?purged, "purged deleted runners");
+        }
+
+        Ok(())
+    }
+}
+
+impl Subsystem for RecycleBinPurger {
+    fn name(&self) -> &'static str {
+        "recycle-bin-purger"
+    }
+
+    fn run(&self, mut shutdown: Shutdown) -> BoxFuture<'static, Result<(), Error>> {
+        let this = self.clone();
+
+        async move {
+            let mut interval = tokio::time::interval(PURGE_INTERVAL);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => this.purge().await?,
+                    _ = shutdown.requested() => return Ok(()),
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use atmosphere::{Create as _, Pool};
+    use chrono::{TimeDelta, Utc};
+    use pretty_assertions::assert_eq;
+
+    use super::DeletedRunner;
+    use crate::{
+        models::{GitLabRunner, SystemEvent, SystemEventFilter},
+        settings::RecycleBin,
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn purge_after_retention(pool: Pool) -> Result<()> {
+        let mut old = GitLabRunner::for_testing();
+        old.create(&pool).await?;
+        old.remove(&pool, Some("peripheral"), false).await?;
+        sqlx::query("UPDATE deleted_gitlab_runners SET deleted_at = ?")
+            .bind(Utc::now() - TimeDelta::days(31))
+            .execute(&pool)
+            .await?;
+
+        let mut recent = GitLabRunner::for_testing();
+        recent.create(&pool).await?;
+        recent.remove(&pool, None, false).await?;
+
+        let deleted = DeletedRunner::list(&pool).await?;
+        assert_eq!(deleted.len(), 2);
+        assert_eq!(deleted[0].runner.uuid(), recent.uuid());
+        assert_eq!(deleted[1].deleted_by.as_deref(), Some("peripheral"));
+
+        let forever = RecycleBin { retention_days: 0 };
+        assert!(DeletedRunner::purge(&pool, &forever, Utc::now(), 10)
+            .await?
+            .is_empty());
+
+        let retention = RecycleBin { retention_days: 30 };
+        let purged = DeletedRunner::purge(&pool, &retention, Utc::now(), 10).await?;
+        assert_eq!(purged, vec![*old.uuid()]);
+
+        let deleted = DeletedRunner::list(&pool).await?;
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(deleted[0].runner.uuid(), recent.uuid());
+
+        // the history of a purged runner goes with it
+        let revisions: Vec<uuid::Uuid> =
+            sqlx::query_scalar("SELECT DISTINCT uuid FROM gitlab_runner_revisions")
+                .fetch_all(&pool)
+                .await?;
+        assert_eq!(revisions, vec![*recent.uuid()]);
+
+        let (events, _) = SystemEvent::list(&pool, &SystemEventFilter::default()).await?;
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            serde_json::to_value(&events[0])?["subject"],
+            format!("/gitlab-runners/deleted/{}", old.uuid())
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,356 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{sync::Arc, time::Duration};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use futures::{future::BoxFuture, FutureExt};
+use serde::Serialize;
+use sqlx::{QueryBuilder, Sqlite};
+use uuid::Uuid;
+
+use super::{Change, ConfigCache, ConfigTarget, DeletedRunner, GitLabRunner, GitLabRunnerConfig};
+use crate::{
+    error::Error,
+    settings::{ExpiryAction, SettingsStore},
+    subsystems::{Shutdown, Subsystem},
+    webhooks::{Event, WebhookClient},
+};
+
+/// How often the reaper looks for expiring runners.
+const REAPER_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Who deleted expired runners, as noted in the recycle bin.
+const EXPIRY_ACTOR: &str = "runrs";
+
+/// Tells the owner of a runner that it is about to be paused or deleted. Doesn't contain the
+/// runner token, since the receiver has no business knowing it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, sqlx::FromRow)]
+pub struct ExpiryNotice {
+    uuid: Uuid,
+    id: u32,
+    name: String,
+    url: String,
+    owner_email: Option<String>,
+    expires_at: DateTime<Utc>,
+    #[sqlx(skip)]
+    action: ExpiryAction,
+}
+
+impl ExpiryNotice {
+    /// Finds the runners expiring before `deadline` whose owners haven't been notified yet.
+    /// Runners which are paused already are skipped if expired runners are only paused.
+    pub async fn due(
+        pool: &atmosphere::Pool,
+        deadline: DateTime<Utc>,
+        action: ExpiryAction,
+    ) -> Result<Vec<Self>, Error> {
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT r.uuid, r.id, r.name, r.url, r.owner_email, r.expires_at \
+             FROM gitlab_runners r \
+             LEFT JOIN expiry_notices n ON n.uuid = r.uuid AND n.expires_at = r.expires_at \
+             WHERE n.uuid IS NULL AND r.expires_at IS NOT NULL \
+             AND datetime(r.expires_at) <= datetime(",
+        );
+        query.push_bind(deadline).push(")");
+        if action == ExpiryAction::Pause {
+            query.push(" AND r.paused = 0");
+        }
+        query.push(" ORDER BY r.expires_at");
+
+        let notices: Vec<Self> = query.build_query_as().fetch_all(pool).await?;
+
+        Ok(notices
+            .into_iter()
+            .map(|notice| Self { action, ..notice })
+            .collect())
+    }
+
+    /// Records that the notice was delivered. If the runner's expiry is changed afterwards, the
+    /// owner is notified again.
+    pub async fn delivered(&self, pool: &atmosphere::Pool) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO expiry_notices (uuid, expires_at) VALUES (?, ?) \
+             ON CONFLICT (uuid) DO UPDATE SET expires_at = excluded.expires_at",
+        )
+        .bind(self.uuid)
+        .bind(self.expires_at)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Pauses or deletes the runners which expired before `now` and returns their UUIDs. If
+/// `notice_required` is set, only runners whose owners were notified are cleaned up. If `events`
+/// is set, the change events are queued in the same transaction.
+pub async fn reap_expired(
+    pool: &atmosphere::Pool,
+    now: DateTime<Utc>,
+    action: ExpiryAction,
+    notice_required: bool,
+    events: bool,
+) -> Result<Vec<Uuid>, Error> {
+    let mut query = QueryBuilder::<Sqlite>::new(
+        "SELECT * FROM gitlab_runners \
+         WHERE expires_at IS NOT NULL AND datetime(expires_at) <= datetime(",
+    );
+    query.push_bind(now).push(")");
+    if action == ExpiryAction::Pause {
+        query.push(" AND paused = 0");
+    }
+    if notice_required {
+        query.push(
+            " AND EXISTS (SELECT 1 FROM expiry_notices n \
+             WHERE n.uuid = gitlab_runners.uuid AND n.expires_at = gitlab_runners.expires_at)",
+        );
+    }
+
+    let mut tx = pool.begin().await?;
+    let mut runners: Vec<GitLabRunner> = query.build_query_as().fetch_all(&mut *tx).await?;
+    for runner in &mut runners {
+        match action {
+            ExpiryAction::Pause => {
+                runner.pause();
+                runner.apply_in(&mut tx, Change::Updated, events).await?;
+            }
+            ExpiryAction::Delete => {
+                DeletedRunner::record(&mut *tx, runner, Some(EXPIRY_ACTOR)).await?;
+                runner.apply_in(&mut tx, Change::Deleted, events).await?;
+            }
+        }
+    }
+    // notices of deleted runners are of no further use
+    sqlx::query("DELETE FROM expiry_notices WHERE uuid NOT IN (SELECT uuid FROM gitlab_runners)")
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(runners.iter().map(|runner| *runner.uuid()).collect())
+}
+
+/// Notifies the owners of expiring runners via the expiry webhook, and pauses or deletes the
+/// runners once they expired.
+#[derive(Debug, Clone)]
+pub struct ExpiryReaper {
+    pool: atmosphere::Pool,
+    target: Arc<ConfigTarget>,
+    cache: Arc<ConfigCache>,
+    settings: Arc<SettingsStore>,
+    webhooks: WebhookClient,
+}
+
+impl ExpiryReaper {
+    pub fn new(
+        pool: atmosphere::Pool,
+        target: Arc<ConfigTarget>,
+        cache: Arc<ConfigCache>,
+        settings: Arc<SettingsStore>,
+    ) -> Self {
+        Self {
+            pool,
+            target,
+            cache,
+            settings,
+            webhooks: WebhookClient::default(),
+        }
+    }
+
+    async fn reap(&self) -> Result<(), Error> {
+        let settings = self.settings.load();
+        let expiry = &settings.expiry;
+        let now = Utc::now();
+
+        if let Some(webhook_url) = &expiry.webhook_url {
+            let notify_before = i64::try_from(expiry.notify_before_secs)
+                .ok()
+                .and_then(TimeDelta::try_seconds)
+                .unwrap_or(TimeDelta::MAX);
+            let deadline = now
+                .checked_add_signed(notify_before)
+                .unwrap_or(DateTime::<Utc>::MAX_UTC);
+
+            for notice in ExpiryNotice::due(&self.pool, deadline, expiry.action).await? {
+                match self
+                    .webhooks
+                    .post(webhook_url, &Event::RunnerExpiring(&notice))
+                    .await
+                {
+                    Ok(()) => notice.delivered(&self.pool).await?,
+                    // the notice is due again on the next run
+                    Err(err) => {
+                        tracing::warn!(%err, uuid = %notice.uuid, "sending expiry notice failed")
+                    }
+                }
+            }
+        }
+
+        // runners expiring during a freeze are cleaned up once it is over
+        if settings.freeze_windows.defers("expiry-reaper") {
+            return Ok(());
+        }
+
+        let reaped = reap_expired(
+            &self.pool,
+            now,
+            expiry.action,
+            expiry.webhook_url.is_some(),
+            settings.events.enabled(),
+        )
+        .await?;
+        if reaped.is_empty() {
+            return Ok(());
+        }
+
+        tracing::info!(?reaped, action = ?expiry.action, "cleaned up expired runners");
+        self.cache.bump();
+        let path = self.target.load();
+        GitLabRunnerConfig::write_or_queue(&self.pool, &path, &self.cache, &settings.render)
+            .await
+            .map(drop)
+    }
+}
+
+impl Subsystem for ExpiryReaper {
+    fn name(&self) -> &'static str {
+        "expiry-reaper"
+    }
+
+    fn run(&self, mut shutdown: Shutdown) -> BoxFuture<'static, Result<(), Error>> {
+        let this = self.clone();
+
+        async move {
+            let mut interval = tokio::time::interval(REAPER_INTERVAL);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => this.reap().await?,
+                    _ = shutdown.requested() => return Ok(()),
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use atmosphere::{Create as _, Pool, Read as _};
+    use axum::{extract::State, routing::post, Json, Router};
+    use chrono::{TimeDelta, Utc};
+    use pretty_assertions::assert_eq;
+
+    use super::ExpiryReaper;
+    use crate::{
+        models::{ConfigCache, ConfigTarget, GitLabRunner, OutboxEvent},
+        settings::{Events, Expiry, ExpiryAction, Settings, SettingsStore},
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    fn reaper(pool: Pool, expiry: Expiry) -> ExpiryReaper {
+        let path = std::env::temp_dir().join(format!("runrs-expiry-{}.toml", uuid::Uuid::new_v4()));
+        let settings = SettingsStore::new(Settings {
+            expiry,
+            // queued only, since nothing delivers them
+            events: Events {
+                webhook_url: Some("http://127.0.0.1:1/".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+
+        ExpiryReaper::new(
+            pool,
+            Arc::new(ConfigTarget::new(path)),
+            Arc::new(ConfigCache::default()),
+            Arc::new(settings),
+        )
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn pause_expired(pool: Pool) -> Result<()> {
+        let mut expired = GitLabRunner::for_testing();
+        expired.set_expires_at(Utc::now() - TimeDelta::minutes(1));
+        expired.create(&pool).await?;
+
+        let mut expiring = GitLabRunner::for_testing();
+        expiring.set_url("https://gitlab.bmc-labs.com");
+        expiring.set_expires_at(Utc::now() + TimeDelta::hours(1));
+        expiring.create(&pool).await?;
+
+        let reaper = reaper(pool.clone(), Expiry::default());
+        reaper.reap().await?;
+
+        let expired = GitLabRunner::find(&pool, expired.uuid()).await?;
+        assert!(expired.is_some_and(|runner| runner.paused()));
+        let expiring = GitLabRunner::find(&pool, expiring.uuid()).await?;
+        assert!(expiring.is_some_and(|runner| !runner.paused()));
+        assert_eq!(OutboxEvent::pending(&pool, 10).await?.len(), 1);
+
+        let config_toml = std::fs::read_to_string(&*reaper.target.load())?;
+        assert!(!config_toml.contains("Knows the meaning of life"));
+        std::fs::remove_file(&*reaper.target.load())?;
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn notify_before_delete(pool: Pool) -> Result<()> {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let receiver = Router::new()
+            .route(
+                "/",
+                post(
+                    |State(received): State<Arc<Mutex<Vec<serde_json::Value>>>>,
+                     Json(event): Json<serde_json::Value>| async move {
+                        received.lock().expect("lock is not poisoned").push(event);
+                    },
+                ),
+            )
+            .with_state(received.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let webhook_url = format!("http://{}/", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, receiver).await });
+
+        let mut runner = GitLabRunner::for_testing();
+        runner.set_expires_at(Utc::now() - TimeDelta::minutes(1));
+        runner.create(&pool).await?;
+
+        // an unreachable receiver keeps the runner from being deleted
+        let unreachable = reaper(
+            pool.clone(),
+            Expiry {
+                action: ExpiryAction::Delete,
+                webhook_url: Some("http://127.0.0.1:1/".to_string()),
+                ..Default::default()
+            },
+        );
+        unreachable.reap().await?;
+        assert!(GitLabRunner::find(&pool, runner.uuid()).await?.is_some());
+
+        let reaper = reaper(
+            pool.clone(),
+            Expiry {
+                action: ExpiryAction::Delete,
+                webhook_url: Some(webhook_url),
+                ..Default::default()
+            },
+        );
+        reaper.reap().await?;
+        assert_eq!(GitLabRunner::find(&pool, runner.uuid()).await?, None);
+
+        let received = received.lock().expect("lock is not poisoned").clone();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0]["event"], "runner_expiring");
+        assert_eq!(received[0]["uuid"], runner.uuid().to_string());
+        assert_eq!(received[0]["action"], "delete");
+        assert!(received[0].get("token").is_none());
+
+        std::fs::remove_file(&*reaper.target.load())?;
+
+        Ok(())
+    }
+}
@@ -1,12 +1,74 @@
 // Copyright 2024 bmc::labs GmbH. All rights reserved.
 
-use atmosphere::{table, Schema, Table as _};
-use glrcfg::runner::{DateTime, Docker, Executor, Runner, RunnerToken, Url};
+use atmosphere::{table, Create as _, Delete as _, Schema, Table as _, Update as _};
+use bytes::Bytes;
+use chrono::Utc;
+use futures::StreamExt;
+use glrcfg::runner::{
+    DateTime, Docker, DockerHost, Executor, ImageReference, Runner, RunnerToken, Service, Shell,
+    Url, WINDOWS_DOCKER_HOST,
+};
 use names::{Generator, Name};
 use serde::{Deserialize, Serialize};
+use sqlx::{QueryBuilder, Sqlite, SqliteConnection, SqliteExecutor};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
 use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
+use super::{
+    legacy_runner::LegacyRunner,
+    orphan_runner::{timestamp, OnDiskRunner},
+    output_limit::DEFAULT_OUTPUT_LIMIT,
+    Change, ChangedRunner, DeletedRunner, GitLabRunnerFilter, GitLabRunnerPatch, Labels, Os,
+    OutboxEvent, OutputLimit, Pagination, Preset, RunnerHistory, RunnerRegistration, Schedule,
+    Tags,
+};
+use crate::{
+    error::Error,
+    gitlab::{GitLabClient, RegisteredRunner},
+    secrets::{Credential, Secrets},
+    settings::{NameUniqueness, Quotas, RenderOptions, Settings},
+};
+
+mod lint;
+
+pub use lint::Lint;
+
+/// Number of serialized runners buffered ahead of a slow client when streaming.
+const NDJSON_STREAM_BUFFER: usize = 64;
+
+/// Helper image for Windows hosts, without the suffix naming the Windows version; `gitlab-runner`
+/// fills in its own revision. The Linux helper image is picked by `gitlab-runner` itself.
+const WINDOWS_HELPER_IMAGE: &str =
+    "registry.gitlab.com/gitlab-org/gitlab-runner/gitlab-runner-helper:x86_64-${CI_RUNNER_REVISION}";
+
+/// Windows versions as found in tags of job images, with the suffix of the matching helper image.
+const WINDOWS_HELPER_FLAVORS: &[(&str, &str)] = &[
+    ("ltsc2019", "servercore1809"),
+    ("1809", "servercore1809"),
+    ("ltsc2022", "servercore21H2"),
+    ("21h2", "servercore21H2"),
+];
+
+/// Derives the helper image of a Windows runner from its job image, since both must match the
+/// Windows version of the host. Without a known version in the tag, `gitlab-runner` picks the
+/// helper image for the host itself.
+fn windows_helper_image(image: &ImageReference) -> Option<ImageReference> {
+    let tag = image.tag()?.to_lowercase();
+    let (_, flavor) = WINDOWS_HELPER_FLAVORS
+        .iter()
+        .find(|(version, _)| tag.contains(version))?;
+
+    Some(
+        ImageReference::parse(format!("{WINDOWS_HELPER_IMAGE}-{flavor}"))
+            .expect("given string is a valid image reference"),
+    )
+}
+
+/// Token expiry `gitlab-runner` writes for tokens which never expire.
+const TOKEN_NEVER_EXPIRES: &str = "0001-01-01T00:00:00Z";
+
 fn default_name() -> String {
     let mut generator = Generator::with_naming(Name::Numbered);
     generator.next().unwrap_or_else(|| "usain-bolt".to_string())
@@ -34,45 +96,1105 @@ pub struct GitLabRunner {
     uuid: Uuid,
     /// ID of the runner within the GitLab instance; unique for that GitLab instance
     id: u32,
-    /// Runner name (default: Docker-style random name)
-    #[serde(alias = "description", default = "default_name")]
+    /// Runner name, identifying the runner within runrs (default: Docker-style random name)
+    #[serde(default = "default_name")]
     #[schema(example = "usain-bolt")]
     name: String,
-    /// GitLab instance URL
+    /// Runner description as shown in GitLab; written to the config in place of the name if set
+    #[serde(default)]
+    #[schema(example = "Usain Bolt, the fastest runner on the track")]
+    description: Option<String>,
+    /// GitLab instance URL; stored normalized, i.e. without trailing slash, query or fragment
     #[schema(value_type = String, format = Uri, example = "https://gitlab.your-company.com")]
     url: Url,
     /// Runner token, obtained from the GitLab instance. See [documentation of the `glrcfg`
     /// crate](https://docs.rs/glrcfg/latest/glrcfg/runner/struct.RunnerToken.html) for details.
+    /// Instead of the token, a reference to a secret store holding it can be given, i.e.
+    /// `vault:<mount>/<path>#<key>` or `aws-sm:<name>[#<key>]`; it is resolved when the config
+    /// is written.
     #[schema(value_type = String, example = "glrt-0123456789_abcdefXYZ")]
-    token: RunnerToken,
+    token: Credential<RunnerToken>,
     #[serde(default = "DateTime::now")]
     #[schema(value_type = String, format = DateTime, example = "2023-08-23T23:23:23Z")]
     token_obtained_at: DateTime,
-    /// Docker image to be used
-    #[schema(example = "alpine:latest")]
-    docker_image: String,
+    /// When the runner token expires (default: never)
+    #[serde(default)]
+    #[schema(value_type = Option<String>, format = DateTime, example = "2024-08-23T23:23:23Z")]
+    #[param(value_type = Option<String>, format = DateTime)]
+    token_expires_at: Option<DateTime>,
+    /// Docker image to be used, as `[registry/]repository[:tag][@digest]`
+    #[schema(value_type = String, example = "alpine:latest")]
+    #[param(value_type = String)]
+    docker_image: ImageReference,
+    /// Whether to run the Docker containers in privileged mode (default: false)
+    #[serde(default)]
+    privileged: bool,
+    /// Free-form metadata, e.g. the owning team or cost center
+    #[serde(default)]
+    #[schema(value_type = Object, example = json!({"team": "payments"}))]
+    labels: Labels,
+    /// Tags of the jobs the runner picks up, as registered with GitLab; set on registration
+    #[serde(default)]
+    #[schema(value_type = Vec<String>, example = json!(["docker", "linux"]))]
+    #[param(value_type = Vec<String>)]
+    tags: Tags,
+    /// Free-form notes
+    #[serde(default)]
+    #[schema(example = "Owned by team payments; ask in #payments-infra before changing")]
+    notes: String,
+    /// Email address of the person responsible for the runner
+    #[serde(default)]
+    #[schema(example = "jane.doe@your-company.com")]
+    owner_email: Option<String>,
+    /// When the runner expires; expired runners are paused or deleted, depending on the settings
+    #[serde(default)]
+    #[schema(value_type = Option<String>, format = DateTime, example = "2024-07-01T00:00:00Z")]
+    #[param(value_type = Option<String>, format = DateTime)]
+    expires_at: Option<chrono::DateTime<Utc>>,
+    /// Whether the runner is left out of the config, so it doesn't pick up jobs (default: false)
+    #[serde(default)]
+    paused: bool,
+    /// Operating system of the runner host (default: linux); Windows runners use the
+    /// `docker-windows` executor
+    #[serde(default)]
+    os: Os,
+    /// Built-in preset whose Docker settings the runner is rendered with, e.g. `dind`; only
+    /// available for Linux runners
+    #[serde(default)]
+    #[schema(example = "dind")]
+    preset: Option<Preset>,
+    /// Maximum job log size in KiB, between 1 and 1048576 (default: `output_limit` of the render
+    /// settings)
+    #[serde(default)]
+    #[schema(value_type = Option<u32>, example = 16384)]
+    #[param(value_type = Option<u32>)]
+    output_limit: Option<OutputLimit>,
+    /// Maximum number of jobs the runner runs at once, rendered as its `limit` (default: no
+    /// limit); adjusted by runrs if the runner is `autoscaled` and autoscaling is enabled
+    #[serde(default)]
+    #[schema(example = 4)]
+    job_limit: Option<u32>,
+    /// Whether runrs scales `job_limit` to the runner's job queues while autoscaling is enabled
+    /// (default: false)
+    #[serde(default)]
+    autoscaled: bool,
+    /// Weekly windows in UTC during which the runner picks up jobs, e.g. `Mon 08:00-Fri 18:00`;
+    /// outside of them, runrs pauses the runner (default: always)
+    #[serde(default)]
+    #[schema(value_type = Option<Vec<String>>, example = json!(["Mon 08:00-Fri 18:00"]))]
+    #[param(value_type = Option<Vec<String>>)]
+    schedule: Option<Schedule>,
+    /// Whether the runner is paused because it's outside of the windows of its schedule; set by
+    /// runrs, independently of `paused`
+    #[serde(default)]
+    #[schema(read_only)]
+    off_schedule: bool,
+    /// When the schedule pauses or resumes the runner next; set by runrs
+    #[serde(default)]
+    #[schema(value_type = Option<String>, format = DateTime, read_only, example = "2024-07-05T18:00:00Z")]
+    #[param(value_type = Option<String>, format = DateTime)]
+    next_transition_at: Option<chrono::DateTime<Utc>>,
+    /// When the runner was last created or changed; set by runrs
+    #[serde(default = "Utc::now")]
+    #[schema(value_type = String, format = DateTime, read_only, example = "2024-06-26T10:00:00Z")]
+    #[param(value_type = String, format = DateTime)]
+    updated_at: chrono::DateTime<Utc>,
 }
 
 impl GitLabRunner {
     pub fn compatible_with(&self, other: &Self) -> bool {
         self.uuid == other.uuid
     }
-}
 
-impl From<GitLabRunner> for Runner {
-    fn from(runner: GitLabRunner) -> Self {
+    /// Creates the runner resulting from a registration with GitLab.
+    pub fn registered(registration: RunnerRegistration, registered: RegisteredRunner) -> Self {
+        let (url, details) = registration.into_parts();
+
         Self {
+            uuid: Uuid::new_v4(),
+            id: registered.id,
+            name: details.name.unwrap_or_else(|| details.description.clone()),
+            description: Some(details.description),
+            url,
+            token: registered.token.into(),
+            token_obtained_at: DateTime::now(),
+            token_expires_at: registered.token_expires_at,
+            docker_image: details.docker_image,
+            privileged: details.privileged,
+            labels: details.labels,
+            tags: details.tags.into(),
+            notes: details.notes,
+            owner_email: None,
+            expires_at: None,
+            paused: false,
+            os: details.os,
+            preset: None,
+            output_limit: None,
+            job_limit: None,
+            autoscaled: false,
+            schedule: None,
+            off_schedule: false,
+            next_transition_at: None,
+            updated_at: Utc::now(),
+        }
+    }
+
+    /// Creates a runner from one found in the config file on disk, e.g. one registered with
+    /// `gitlab-runner register`. Only runners using one of the Docker executors can be adopted.
+    pub(super) fn adopt(runner: &OnDiskRunner) -> Result<Self, Error> {
+        let os = match &runner.executor[..] {
+            "docker" => Os::Linux,
+            "docker-windows" => Os::Windows,
+            executor => {
+                return Err(Error::invalid_argument(format!(
+                    "runner uses the '{executor}' executor, only 'docker' and 'docker-windows' \
+                     are supported"
+                )))
+            }
+        };
+        let Some((image, privileged)) = runner.docker.as_ref().and_then(|docker| {
+            docker
+                .image
+                .as_ref()
+                .map(|image| (image.clone(), docker.privileged))
+        }) else {
+            return Err(Error::invalid_argument("runner has no Docker image"));
+        };
+        let image = ImageReference::parse(image).map_err(Error::invalid_argument)?;
+        // `gitlab-runner` writes its default output limit, which runners are better off
+        // inheriting from the render settings
+        let output_limit = runner
+            .output_limit
+            .filter(|&kib| kib != DEFAULT_OUTPUT_LIMIT)
+            .map(OutputLimit::try_from)
+            .transpose()
+            .map_err(Error::invalid_argument)?;
+
+        let parse_timestamp = |value: &Option<toml::Value>| {
+            value
+                .as_ref()
+                .and_then(timestamp)
+                .map(DateTime::parse)
+                .transpose()
+                .map_err(Error::invalid_argument)
+        };
+
+        Ok(Self {
+            uuid: Uuid::new_v4(),
+            id: runner.id,
+            name: runner.name.clone(),
+            description: None,
+            url: Url::parse(&runner.url).map_err(Error::invalid_argument)?,
+            // the parse error contains the token, so it must not end up in the error message
+            token: RunnerToken::parse(runner.token.clone())
+                .map_err(|_| Error::invalid_argument("runner token is invalid"))?
+                .into(),
+            token_obtained_at: parse_timestamp(&runner.token_obtained_at)?
+                .unwrap_or_else(DateTime::now),
+            token_expires_at: parse_timestamp(&runner.token_expires_at)?,
+            docker_image: image,
+            privileged,
+            labels: Labels::default(),
+            tags: Tags::default(),
+            notes: String::new(),
+            owner_email: None,
+            expires_at: None,
+            paused: false,
+            os,
+            preset: None,
+            output_limit,
+            // a limit of 0 means no limit
+            job_limit: (runner.limit > 0).then_some(runner.limit),
+            autoscaled: false,
+            schedule: None,
+            off_schedule: false,
+            next_transition_at: None,
+            updated_at: Utc::now(),
+        })
+    }
+
+    /// Creates a runner from a `[[runners]]` entry of a config file as read by glrcfg, e.g. one
+    /// exported by runrs or written by `gitlab-runner register`. Only runners using one of the
+    /// Docker executors can be imported.
+    pub(super) fn from_config(runner: Runner) -> Result<Self, Error> {
+        let (os, docker) = match runner.executor {
+            Executor::Docker { docker } => (Os::Linux, docker),
+            Executor::DockerWindows { docker } => (Os::Windows, docker),
+            executor => {
+                return Err(Error::invalid_argument(format!(
+                    "runner uses the '{executor}' executor, only 'docker' and 'docker-windows' \
+                     are supported"
+                )))
+            }
+        };
+        // `gitlab-runner` writes its default output limit, which runners are better off
+        // inheriting from the render settings
+        let output_limit = (runner.output_limit != DEFAULT_OUTPUT_LIMIT)
+            .then(|| OutputLimit::try_from(runner.output_limit))
+            .transpose()
+            .map_err(Error::invalid_argument)?;
+
+        Ok(Self {
+            uuid: Uuid::new_v4(),
+            id: runner.id,
             name: runner.name,
+            description: None,
             url: runner.url,
-            token: runner.token,
+            token: runner.token.into(),
             token_obtained_at: runner.token_obtained_at,
-            executor: Executor::Docker {
-                docker: Docker {
-                    image: runner.docker_image,
+            // the expiry of tokens which don't expire is dropped when the runner is normalized
+            token_expires_at: Some(runner.token_expires_at),
+            docker_image: docker.image,
+            privileged: docker.privileged,
+            labels: Labels::default(),
+            tags: Tags::default(),
+            notes: String::new(),
+            owner_email: None,
+            expires_at: None,
+            paused: false,
+            os,
+            preset: None,
+            output_limit,
+            // a limit of 0 means no limit
+            job_limit: (runner.limit > 0).then_some(runner.limit),
+            autoscaled: false,
+            schedule: None,
+            off_schedule: false,
+            next_transition_at: None,
+            updated_at: Utc::now(),
+        })
+    }
+
+    /// Creates a runner from one of the legacy schema, which identified runners by their ID. The
+    /// values are parsed leniently, e.g. a token with surrounding whitespace is accepted; values
+    /// which can't be fixed up are replaced by defaults where possible, with a warning.
+    pub(super) fn migrate(runner: &LegacyRunner) -> Result<Self, Error> {
+        let id = u32::try_from(runner.id)
+            .map_err(|_| Error::invalid_argument(format!("invalid runner ID {}", runner.id)))?;
+        let url = runner
+            .url
+            .as_deref()
+            .ok_or_else(|| Error::invalid_argument("runner has no URL"))
+            .and_then(|url| Url::parse(url.trim()).map_err(Error::invalid_argument))?;
+        // the parse error contains the token, so it must not end up in the error message
+        let token: Credential<RunnerToken> = runner
+            .token
+            .as_deref()
+            .map(|token| token.trim().trim_matches('"'))
+            .and_then(|token| token.parse().ok())
+            .ok_or_else(|| Error::invalid_argument("runner token is missing or invalid"))?;
+        let docker_image = runner
+            .docker_image
+            .as_deref()
+            .ok_or_else(|| Error::invalid_argument("runner has no Docker image"))
+            .and_then(|image| {
+                ImageReference::parse(image.trim()).map_err(Error::invalid_argument)
+            })?;
+
+        let parse_timestamp = |field: &str, value: &Option<String>| {
+            let value = value.as_deref()?;
+            DateTime::parse(value.trim())
+                .inspect_err(|err| {
+                    tracing::warn!(id, field, value, %err, "ignoring invalid legacy timestamp");
+                })
+                .ok()
+        };
+
+        Ok(Self {
+            token_obtained_at: parse_timestamp("token_obtained_at", &runner.token_obtained_at)
+                .unwrap_or_else(DateTime::now),
+            token_expires_at: parse_timestamp("token_expires_at", &runner.token_expires_at),
+            privileged: runner.privileged,
+            description: runner.description.clone(),
+            ..Self::bootstrap(id, runner.name.clone(), url, token, docker_image)
+        })
+    }
+
+    /// Creates the runner given in the environment for bootstrapping; see [`Bootstrap`].
+    ///
+    /// [`Bootstrap`]: super::Bootstrap
+    pub(super) fn bootstrap(
+        id: u32,
+        name: Option<String>,
+        url: Url,
+        token: Credential<RunnerToken>,
+        docker_image: ImageReference,
+    ) -> Self {
+        Self {
+            uuid: Uuid::new_v4(),
+            id,
+            name: name.unwrap_or_else(default_name),
+            description: None,
+            url,
+            token,
+            token_obtained_at: DateTime::now(),
+            token_expires_at: None,
+            docker_image,
+            privileged: false,
+            labels: Labels::default(),
+            tags: Tags::default(),
+            notes: String::new(),
+            owner_email: None,
+            expires_at: None,
+            paused: false,
+            os: Os::Linux,
+            preset: None,
+            output_limit: None,
+            job_limit: None,
+            autoscaled: false,
+            schedule: None,
+            off_schedule: false,
+            next_transition_at: None,
+            updated_at: Utc::now(),
+        }
+    }
+
+    /// Checks that a token given as a secret reference can be resolved, so that a broken
+    /// reference is rejected right away instead of failing every subsequent config write.
+    pub async fn check_token(&self, secrets: &Secrets) -> Result<(), Error> {
+        self.token.resolve(secrets).await.map(drop)
+    }
+
+    /// Asks GitLab whether the runner token is still valid.
+    pub async fn verify(&self, gitlab: &GitLabClient, secrets: &Secrets) -> Result<bool, Error> {
+        let token = self.token.resolve(secrets).await?;
+        gitlab.verify_runner(&self.url, &token).await
+    }
+
+    pub fn uuid(&self) -> &Uuid {
+        &self.uuid
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    pub fn os(&self) -> Os {
+        self.os
+    }
+
+    pub fn token_expires_at(&self) -> Option<&DateTime> {
+        self.token_expires_at.as_ref()
+    }
+
+    /// Returns the token masked for display, e.g. `glrt-****XYZ`, or the reference to the secret
+    /// holding it.
+    pub fn masked_token(&self) -> String {
+        match &self.token {
+            Credential::Plain(token) => token.masked(),
+            Credential::Secret(secret) => secret.to_string(),
+        }
+    }
+
+    pub fn preset(&self) -> Option<Preset> {
+        self.preset
+    }
+
+    /// Applies the preset, filling in the Docker settings it needs. The remaining settings are
+    /// rendered from the preset when the config is written.
+    pub fn apply_preset(&mut self, preset: Preset) {
+        self.privileged = preset.config().privileged;
+        self.preset = Some(preset);
+    }
+
+    /// When the runner was last created or changed, as sent in its `Last-Modified` header.
+    pub fn updated_at(&self) -> chrono::DateTime<Utc> {
+        self.updated_at
+    }
+
+    /// Paused runners are kept in the database, but left out of the config. A runner is paused if
+    /// it was paused, e.g. by hand or because it expired, or if its schedule pauses it.
+    pub fn paused(&self) -> bool {
+        self.paused || self.off_schedule
+    }
+
+    /// Pauses the runner, e.g. because it expired.
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn next_transition_at(&self) -> Option<chrono::DateTime<Utc>> {
+        self.next_transition_at
+    }
+
+    pub fn job_limit(&self) -> Option<u32> {
+        self.job_limit
+    }
+
+    pub fn set_job_limit(&mut self, job_limit: u32) {
+        self.job_limit = Some(job_limit);
+    }
+
+    pub fn autoscaled(&self) -> bool {
+        self.autoscaled
+    }
+
+    /// Pauses or resumes the runner according to its schedule, if it has one, and notes when
+    /// that happens next. Whether the runner was paused otherwise, e.g. by hand, is kept.
+    pub(super) fn apply_schedule(&mut self, now: chrono::DateTime<Utc>) {
+        let Some(schedule) = &self.schedule else {
+            self.off_schedule = false;
+            self.next_transition_at = None;
+            return;
+        };
+
+        self.off_schedule = !schedule.is_active_at(now);
+        self.next_transition_at = schedule.next_transition(now);
+    }
+
+    /// Stores what [`GitLabRunner::apply_schedule`] changed. Unlike changes made on request, this
+    /// keeps `updated_at`, so clients using `If-Unmodified-Since` aren't told the runner changed
+    /// when it merely reached the boundary of a window. Returns `false` if the runner's schedule
+    /// was changed or removed in the meantime, in which case nothing is stored.
+    pub(super) async fn store_schedule_state(
+        &self,
+        pool: &atmosphere::Pool,
+    ) -> Result<bool, Error> {
+        let stored = sqlx::query(
+            "UPDATE gitlab_runners SET off_schedule = ?, next_transition_at = ? \
+             WHERE uuid = ? AND schedule = ?",
+        )
+        .bind(self.off_schedule)
+        .bind(self.next_transition_at)
+        .bind(self.uuid)
+        .bind(&self.schedule)
+        .execute(pool)
+        .await?;
+
+        Ok(stored.rows_affected() > 0)
+    }
+
+    /// Takes over from `current`, the stored state of the runner declared by `self`, what runrs
+    /// manages itself: the UUID, when the runner was changed and its token obtained, whether its
+    /// schedule or expiry paused it and, if `autoscaled`, its job limit. Afterwards, the runner
+    /// equals `current` unless the declaration changed.
+    pub fn declared_over(&mut self, current: &Self, autoscaled: bool) {
+        self.uuid = current.uuid;
+        self.updated_at = current.updated_at;
+        self.off_schedule = current.off_schedule;
+        self.next_transition_at = current.next_transition_at;
+        if self.token == current.token {
+            self.token_obtained_at = current.token_obtained_at.clone();
+        }
+        if autoscaled {
+            self.job_limit = current.job_limit;
+        }
+
+        let expired = current.expires_at.is_some_and(|at| at <= Utc::now());
+        if expired {
+            self.paused = current.paused;
+        }
+    }
+
+    /// Trims the runner name and description and validates them against the configured length
+    /// limit; an empty description is dropped.
+    pub fn normalize(&mut self, settings: &Settings) -> Result<(), Error> {
+        let name = self.name.trim();
+
+        if name.is_empty() {
+            return Err(Error::invalid_argument("runner name must not be empty"));
+        }
+
+        if name.chars().any(char::is_control) {
+            return Err(Error::invalid_argument(
+                "runner name must not contain control characters",
+            ));
+        }
+
+        let length = name.chars().count();
+        if length > settings.name_max_length {
+            return Err(Error::invalid_argument(format!(
+                "runner name is {length} characters long, at most {} are allowed",
+                settings.name_max_length
+            )));
+        }
+
+        if let Some(description) = &self.description {
+            let description = description.trim();
+            if description.chars().any(char::is_control) {
+                return Err(Error::invalid_argument(
+                    "runner description must not contain control characters",
+                ));
+            }
+
+            let length = description.chars().count();
+            if length > settings.name_max_length {
+                return Err(Error::invalid_argument(format!(
+                    "runner description is {length} characters long, at most {} are allowed",
+                    settings.name_max_length
+                )));
+            }
+
+            self.description = Some(description.to_string()).filter(|d| !d.is_empty());
+        }
+
+        self.labels.validate()?;
+
+        if let Some(preset) = self.preset.filter(|_| self.os != Os::Linux) {
+            return Err(Error::invalid_argument(format!(
+                "preset '{preset}' is only available for linux runners"
+            )));
+        }
+        // a preset's settings stick with the runner, whatever an update says
+        if let Some(preset) = self.preset {
+            self.privileged |= preset.config().privileged;
+        }
+
+        // runners of the same GitLab instance must be recognized as such by filters and quotas
+        self.url = self.url.normalized();
+
+        // accept the expiry as found in config files written by `gitlab-runner`
+        if self
+            .token_expires_at
+            .as_ref()
+            .is_some_and(|expires_at| expires_at.to_iso8601() == TOKEN_NEVER_EXPIRES)
+        {
+            self.token_expires_at = None;
+        }
+
+        if let Some(owner_email) = &mut self.owner_email {
+            let trimmed = owner_email.trim();
+            let valid = trimmed.split_once('@').is_some_and(|(local, domain)| {
+                !local.is_empty() && domain.contains('.') && !domain.contains('@')
+            });
+            if !valid || trimmed.chars().any(char::is_whitespace) {
+                return Err(Error::invalid_argument(format!(
+                    "'{trimmed}' is not a valid owner email address"
+                )));
+            }
+            *owner_email = trimmed.to_string();
+        }
+
+        self.name = name.to_string();
+        Ok(())
+    }
+
+    /// Checks that the runner token hasn't expired, since `gitlab-runner` can't pick up jobs with
+    /// an expired token. With `allow_expired`, an expired token is accepted, and a warning is
+    /// returned instead.
+    pub fn check_token_expiry(&self, allow_expired: bool) -> Result<Option<String>, Error> {
+        let Some(expires_at) = self.token_expires_at.as_ref().filter(|at| at.is_past()) else {
+            return Ok(None);
+        };
+
+        let msg = format!(
+            "runner token expired at {}, the runner won't pick up jobs",
+            expires_at.to_iso8601()
+        );
+        if !allow_expired {
+            return Err(Error::invalid_argument(format!(
+                "{msg}; pass allow_expired=true to store it anyway"
+            )));
+        }
+
+        tracing::warn!(uuid = %self.uuid, "{msg}");
+        Ok(Some(msg))
+    }
+
+    /// Reads all runners matching the filter, in order of creation.
+    pub async fn list(
+        pool: &atmosphere::Pool,
+        filter: &GitLabRunnerFilter,
+    ) -> Result<Vec<Self>, Error> {
+        Self::list_page(pool, filter, &Pagination::default()).await
+    }
+
+    /// Reads a page of the runners matching the filter, in order of creation.
+    pub async fn list_page(
+        pool: &atmosphere::Pool,
+        filter: &GitLabRunnerFilter,
+        page: &Pagination,
+    ) -> Result<Vec<Self>, Error> {
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT * FROM gitlab_runners WHERE 1 = 1");
+        filter.push_conditions(&mut query);
+        query.push(" ORDER BY rowid");
+        page.push_window(&mut query)?;
+
+        Ok(query.build_query_as().fetch_all(pool).await?)
+    }
+
+    /// Converts the runner into its `gitlab-runner` config representation, reading its token from
+    /// the secret store if it's given as a reference.
+    pub async fn into_runner(
+        self,
+        secrets: &Secrets,
+        options: &RenderOptions,
+    ) -> Result<Runner, Error> {
+        let token = self.token.resolve(secrets).await?;
+        let container_labels = if options.container_labels {
+            self.labels
+                .select(&options.container_label_keys)
+                .to_key_value_pairs()
+        } else {
+            Vec::new()
+        };
+
+        let preset = self.preset.map(|preset| preset.config());
+
+        // gitlab-runner detects neither the shell nor the helper image of Windows hosts reliably
+        let (executor, shell) = match self.os {
+            Os::Linux => {
+                let mut docker = Docker {
+                    image: self.docker_image,
+                    // runners stored before their preset was enforced may lack its settings
+                    privileged: self.privileged
+                        || preset.as_ref().is_some_and(|preset| preset.privileged),
+                    container_labels,
                     ..Default::default()
+                };
+                if let Some(preset) = &preset {
+                    docker.volumes.clone_from(&preset.volumes);
+                    docker.services = preset
+                        .services
+                        .iter()
+                        .map(|image| Service {
+                            name: image.clone(),
+                            alias: None,
+                            entrypoint: None,
+                            command: None,
+                            environment: None,
+                        })
+                        .collect();
+                }
+
+                (Executor::Docker { docker }, None)
+            }
+            Os::Windows => (
+                Executor::DockerWindows {
+                    docker: Docker {
+                        helper_image: windows_helper_image(&self.docker_image),
+                        image: self.docker_image,
+                        privileged: self.privileged,
+                        container_labels,
+                        host: Some(
+                            DockerHost::parse(WINDOWS_DOCKER_HOST)
+                                .expect("given string is a valid Docker host"),
+                        ),
+                        ..Docker::windows()
+                    },
                 },
-            },
+                Some(Shell::Pwsh),
+            ),
+        };
+
+        let (unhealthy_requests_limit, unhealthy_interval) = options.unhealthy_thresholds();
+
+        // `gitlab-runner` has no separate description, its name is what GitLab shows
+        let mut runner = Runner {
+            id: self.id,
+            name: self.description.unwrap_or(self.name),
+            url: self.url,
+            token,
+            token_obtained_at: self.token_obtained_at,
+            executor,
+            shell,
+            environment: preset.map(|preset| preset.environment).unwrap_or_default(),
+            output_limit: self.output_limit.unwrap_or(options.output_limit).as_kib(),
+            limit: self.job_limit.unwrap_or(0),
+            unhealthy_requests_limit,
+            unhealthy_interval,
             ..Default::default()
+        };
+        if let Some(token_expires_at) = self.token_expires_at {
+            runner.token_expires_at = token_expires_at;
+        }
+
+        Ok(runner)
+    }
+
+    /// Finds all runners whose name, URL, token or Docker image contain the given fragment, best
+    /// matches first. The fragment must be at least three characters long.
+    pub async fn search(pool: &atmosphere::Pool, fragment: &str) -> Result<Vec<Self>, Error> {
+        let fragment = fragment.trim();
+
+        if fragment.chars().count() < 3 {
+            return Err(Error::invalid_argument(
+                "search query must be at least 3 characters long",
+            ));
+        }
+
+        // quoting the fragment as an FTS5 string makes it match literally
+        let phrase = format!("\"{}\"", fragment.replace('"', "\"\""));
+
+        Ok(sqlx::query_as(
+            "SELECT gitlab_runners.* FROM gitlab_runners \
+             JOIN gitlab_runners_fts ON gitlab_runners_fts.rowid = gitlab_runners.rowid \
+             WHERE gitlab_runners_fts MATCH ? ORDER BY rank",
+        )
+        .bind(phrase)
+        .fetch_all(pool)
+        .await?)
+    }
+
+    /// Streams all runners from the database as NDJSON lines, i.e. one JSON object followed by a
+    /// newline per runner, without loading them into memory all at once. The stream ends early if
+    /// the receiving end is dropped.
+    pub fn stream_ndjson(pool: atmosphere::Pool) -> ReceiverStream<Result<Bytes, Error>> {
+        let (tx, rx) = mpsc::channel(NDJSON_STREAM_BUFFER);
+
+        tokio::spawn(async move {
+            let mut rows = sqlx::query_as::<_, Self>("SELECT * FROM gitlab_runners ORDER BY rowid")
+                .fetch(&pool);
+
+            while let Some(row) = rows.next().await {
+                let line = row.map_err(Error::from).and_then(|runner| {
+                    let mut line = serde_json::to_vec(&runner).map_err(Error::internal_error)?;
+                    line.push(b'\n');
+                    Ok(Bytes::from(line))
+                });
+
+                if tx.send(line).await.is_err() {
+                    tracing::debug!("receiver of runner stream dropped");
+                    break;
+                }
+            }
+        });
+
+        ReceiverStream::new(rx)
+    }
+
+    /// Creates, updates or deletes the runner. If `events` is set, the change event is queued in
+    /// the same transaction, so it is delivered if and only if the change is committed.
+    pub async fn apply(
+        &mut self,
+        pool: &atmosphere::Pool,
+        change: Change,
+        events: bool,
+    ) -> Result<(), Error> {
+        let mut tx = pool.begin().await?;
+        self.apply_in(&mut tx, change, events).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Like [`GitLabRunner::apply`], but within a transaction of the caller, e.g. to commit
+    /// several changes at once.
+    pub async fn apply_in(
+        &mut self,
+        conn: &mut SqliteConnection,
+        change: Change,
+        events: bool,
+    ) -> Result<(), Error> {
+        if change != Change::Deleted {
+            self.updated_at = Utc::now();
+            self.apply_schedule(self.updated_at);
+        }
+        match change {
+            Change::Created => self.create(&mut *conn).await?,
+            Change::Updated => self.update(&mut *conn).await?,
+            Change::Deleted => self.delete(&mut *conn).await?,
+        };
+        let state = (change != Change::Deleted).then_some(&*self);
+        RunnerHistory::record(&mut *conn, &self.uuid, state).await?;
+        if events {
+            OutboxEvent::enqueue(conn, &change.event(&ChangedRunner::from(&*self))).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Creates or updates the runner like [`GitLabRunner::apply`], after checking its name and the
+    /// quotas of `settings` in the same transaction, so concurrent writes can't both pass them.
+    pub async fn apply_checked(
+        &mut self,
+        pool: &atmosphere::Pool,
+        change: Change,
+        settings: &Settings,
+    ) -> Result<(), Error> {
+        let mut tx = pool.begin().await?;
+        self.apply_checked_in(&mut tx, change, settings).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Like [`GitLabRunner::apply_checked`], but within a transaction of the caller.
+    pub async fn apply_checked_in(
+        &mut self,
+        conn: &mut SqliteConnection,
+        change: Change,
+        settings: &Settings,
+    ) -> Result<(), Error> {
+        self.ensure_unique_name(&mut *conn, settings.name_uniqueness)
+            .await?;
+        self.ensure_within_quotas(&mut *conn, &settings.quotas)
+            .await?;
+        self.apply_in(conn, change, settings.events.enabled()).await
+    }
+
+    /// Fails unless the stored runner is unchanged since `since`, at the resolution of seconds of
+    /// HTTP dates. The check is an `UPDATE` conditioned on `updated_at`, which takes the write
+    /// lock of the transaction, so no other change can get in between it and the caller's write.
+    pub async fn ensure_unmodified_since(
+        conn: &mut SqliteConnection,
+        uuid: &Uuid,
+        since: chrono::DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let unmodified = sqlx::query(
+            "UPDATE gitlab_runners SET updated_at = updated_at \
+             WHERE uuid = ? AND datetime(updated_at) <= datetime(?)",
+        )
+        .bind(uuid)
+        .bind(since)
+        .execute(conn)
+        .await?
+        .rows_affected();
+        if unmodified == 0 {
+            return Err(Error::precondition_failed(format!(
+                "runner was modified after {}",
+                since.to_rfc2822()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Deletes the runner and moves it to the recycle bin, noting who deleted it. If `events` is
+    /// set, the change event is queued in the same transaction.
+    pub async fn remove(
+        &mut self,
+        pool: &atmosphere::Pool,
+        actor: Option<&str>,
+        events: bool,
+    ) -> Result<(), Error> {
+        let mut tx = pool.begin().await?;
+        self.remove_in(&mut tx, actor, events).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Like [`GitLabRunner::remove`], but within a transaction of the caller.
+    pub async fn remove_in(
+        &mut self,
+        conn: &mut SqliteConnection,
+        actor: Option<&str>,
+        events: bool,
+    ) -> Result<(), Error> {
+        DeletedRunner::record(&mut *conn, self, actor).await?;
+        self.apply_in(conn, Change::Deleted, events).await
+    }
+
+    /// Deletes all runners matching the filter in a single transaction, moves them to the recycle
+    /// bin and returns their UUIDs. If `events` is set, a change event is queued for each of them
+    /// in the same transaction.
+    pub async fn delete_by_filter(
+        pool: &atmosphere::Pool,
+        filter: &GitLabRunnerFilter,
+        actor: Option<&str>,
+        events: bool,
+    ) -> Result<Vec<Uuid>, Error> {
+        let mut query = QueryBuilder::<Sqlite>::new("DELETE FROM gitlab_runners WHERE 1 = 1");
+        filter.push_conditions(&mut query);
+        query.push(" RETURNING *");
+
+        let mut tx = pool.begin().await?;
+        let deleted: Vec<Self> = query.build_query_as().fetch_all(&mut *tx).await?;
+        for runner in &deleted {
+            DeletedRunner::record(&mut *tx, runner, actor).await?;
+            RunnerHistory::record(&mut *tx, &runner.uuid, None).await?;
+            if events {
+                let runner = ChangedRunner::from(runner);
+                OutboxEvent::enqueue(&mut tx, &Change::Deleted.event(&runner)).await?;
+            }
+        }
+        tx.commit().await?;
+
+        Ok(deleted.into_iter().map(|runner| runner.uuid).collect())
+    }
+
+    /// Applies the patch to all runners matching the filter in a single transaction and returns
+    /// the patched runners along with the warnings about each of them. Each of them is checked
+    /// like a runner updated via `PUT /gitlab-runners/:id`; if any check fails, none of the runners
+    /// is changed. Since the patch can't change tokens, expired ones are only warned about. With
+    /// `dry_run`, the transaction is rolled back, so nothing is changed either way.
+    pub async fn batch_update(
+        pool: &atmosphere::Pool,
+        filter: &GitLabRunnerFilter,
+        patch: &GitLabRunnerPatch,
+        settings: &Settings,
+        dry_run: bool,
+    ) -> Result<Vec<(Self, Vec<String>)>, Error> {
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT * FROM gitlab_runners WHERE 1 = 1");
+        filter.push_conditions(&mut query);
+        query.push(" ORDER BY rowid");
+
+        let mut tx = pool.begin().await?;
+        let runners: Vec<Self> = query.build_query_as().fetch_all(&mut *tx).await?;
+
+        let mut patched = Vec::with_capacity(runners.len());
+        for mut runner in runners {
+            runner.patch(patch);
+            runner.normalize(settings).map_err(|err| {
+                Error::invalid_argument(format!("runner {}: {}", runner.uuid, err.msg))
+            })?;
+            let mut warnings = Vec::from_iter(runner.check_token_expiry(true)?);
+            warnings.extend(runner.lint().iter().map(ToString::to_string));
+            runner
+                .ensure_within_quotas(&mut *tx, &settings.quotas)
+                .await?;
+            runner
+                .apply_in(&mut tx, Change::Updated, settings.events.enabled())
+                .await?;
+            patched.push((runner, warnings));
+        }
+
+        if dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+
+        Ok(patched)
+    }
+
+    fn patch(&mut self, patch: &GitLabRunnerPatch) {
+        if let Some(docker_image) = &patch.docker_image {
+            self.docker_image.clone_from(docker_image);
+        }
+        if let Some(privileged) = patch.privileged {
+            self.privileged = privileged;
+        }
+        if let Some(labels) = &patch.labels {
+            self.labels.merge(labels);
+        }
+        if let Some(notes) = &patch.notes {
+            self.notes.clone_from(notes);
+        }
+        if let Some(owner_email) = &patch.owner_email {
+            self.owner_email = Some(owner_email.clone());
+        }
+        if let Some(description) = &patch.description {
+            self.description = Some(description.clone());
+        }
+        if let Some(output_limit) = patch.output_limit {
+            self.output_limit = Some(output_limit);
+        }
+        if let Some(job_limit) = patch.job_limit {
+            self.job_limit = Some(job_limit);
+        }
+        if let Some(autoscaled) = patch.autoscaled {
+            self.autoscaled = autoscaled;
+        }
+        if let Some(windows) = &patch.schedule {
+            // an empty list removes the schedule
+            self.schedule = Schedule::try_from(windows.clone()).ok();
+        }
+        if let Some(paused) = patch.paused {
+            self.paused = paused;
+        }
+    }
+
+    /// Checks that storing this runner doesn't exceed any of the given quotas. The runner itself
+    /// is not counted, so this works for runners which are being created as well as updated. Only
+    /// changes adding to the usage are checked, so that runners stored before a quota was lowered
+    /// can still be edited, as long as the edit doesn't make matters worse.
+    pub async fn ensure_within_quotas<'c>(
+        &self,
+        conn: impl SqliteExecutor<'c>,
+        quotas: &Quotas,
+    ) -> Result<(), Error> {
+        let (runners, instance_runners, privileged, stored, stored_at_instance, stored_privileged): (
+            i64,
+            i64,
+            i64,
+            i64,
+            i64,
+            i64,
+        ) = sqlx::query_as(
+            "SELECT COALESCE(SUM(uuid != ?), 0), COALESCE(SUM(uuid != ? AND url = ?), 0), \
+                    COALESCE(SUM(uuid != ? AND privileged), 0), COALESCE(SUM(uuid = ?), 0), \
+                    COALESCE(SUM(uuid = ? AND url = ?), 0), \
+                    COALESCE(SUM(uuid = ? AND privileged), 0) \
+             FROM gitlab_runners",
+        )
+        .bind(self.uuid)
+        .bind(self.uuid)
+        .bind(self.url.as_str())
+        .bind(self.uuid)
+        .bind(self.uuid)
+        .bind(self.uuid)
+        .bind(self.url.as_str())
+        .bind(self.uuid)
+        .fetch_one(conn)
+        .await?;
+
+        if stored == 0 && runners >= quotas.max_runners.into() {
+            return Err(Error::quota_exceeded(format!(
+                "at most {} runners are allowed",
+                quotas.max_runners
+            )));
+        }
+        if stored_at_instance == 0 && instance_runners >= quotas.max_runners_per_instance.into() {
+            return Err(Error::quota_exceeded(format!(
+                "at most {} runners are allowed for {}",
+                quotas.max_runners_per_instance, self.url
+            )));
+        }
+        if self.privileged && stored_privileged == 0 && privileged >= quotas.max_privileged.into() {
+            return Err(Error::quota_exceeded(format!(
+                "at most {} privileged runners are allowed",
+                quotas.max_privileged
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether another runner of the same GitLab instance has the same name and acts
+    /// according to the given policy.
+    pub async fn ensure_unique_name<'c>(
+        &self,
+        conn: impl SqliteExecutor<'c>,
+        policy: NameUniqueness,
+    ) -> Result<(), Error> {
+        if policy == NameUniqueness::Off {
+            return Ok(());
+        }
+
+        let duplicates: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM gitlab_runners WHERE name = ? AND url = ? AND uuid != ?",
+        )
+        .bind(&self.name)
+        .bind(self.url.as_str())
+        .bind(self.uuid)
+        .fetch_one(conn)
+        .await?;
+
+        match (duplicates, policy) {
+            (0, _) | (_, NameUniqueness::Off) => Ok(()),
+            (_, NameUniqueness::Warn) => {
+                tracing::warn!(name = %self.name, url = %self.url, "runner name is not unique");
+                Ok(())
+            }
+            (_, NameUniqueness::Enforce) => Err(Error::already_exists(format!(
+                "a runner named '{}' already exists for {}",
+                self.name, self.url
+            ))),
+        }
+    }
+}
+
+impl From<&GitLabRunner> for ChangedRunner {
+    fn from(runner: &GitLabRunner) -> Self {
+        Self {
+            uuid: runner.uuid,
+            id: runner.id,
+            name: runner.name.clone(),
+            url: runner.url.to_string(),
         }
     }
 }
@@ -84,22 +1206,68 @@ impl GitLabRunner {
             uuid: Uuid::new_v4(),
             id: 42,
             name: "Knows the meaning of life".to_string(),
+            description: None,
             url: Url::parse("https://gitlab.your-company.com").expect("given string is a URL"),
             token: RunnerToken::parse("glrt-0123456789_abcdefXYZ")
-                .expect("given string is a valid token"),
+                .expect("given string is a valid token")
+                .into(),
             token_obtained_at: DateTime::parse("2023-08-23T23:23:23Z")
                 .expect("given ISO8601 timestamp is valid"),
-            docker_image: "alpine:latest".to_string(),
+            token_expires_at: None,
+            docker_image: ImageReference::parse("alpine:latest")
+                .expect("given string is a valid image reference"),
+            privileged: false,
+            labels: Labels::default(),
+            tags: Tags::default(),
+            notes: String::new(),
+            owner_email: None,
+            expires_at: None,
+            paused: false,
+            os: Os::Linux,
+            preset: None,
+            output_limit: None,
+            job_limit: None,
+            autoscaled: false,
+            schedule: None,
+            off_schedule: false,
+            next_transition_at: None,
+            updated_at: Utc::now(),
         }
     }
 
-    pub fn uuid(&self) -> &Uuid {
-        &self.uuid
+    pub fn set_name(&mut self, name: &str) {
+        self.name = name.to_string();
     }
 
     pub fn set_url(&mut self, url: &str) {
         self.url = Url::parse(url).expect("given string is not a URL");
     }
+
+    pub fn set_token(&mut self, token: &str) {
+        self.token = RunnerToken::parse(token)
+            .expect("given string is a valid token")
+            .into();
+    }
+
+    pub fn set_labels(&mut self, labels: Labels) {
+        self.labels = labels;
+    }
+
+    pub fn set_token_expires_at(&mut self, token_expires_at: DateTime) {
+        self.token_expires_at = Some(token_expires_at);
+    }
+
+    pub fn set_expires_at(&mut self, expires_at: chrono::DateTime<Utc>) {
+        self.expires_at = Some(expires_at);
+    }
+
+    pub fn set_updated_at(&mut self, updated_at: chrono::DateTime<Utc>) {
+        self.updated_at = updated_at;
+    }
+
+    pub fn set_schedule(&mut self, schedule: Schedule) {
+        self.schedule = Some(schedule);
+    }
 }
 
 #[cfg(test)]
@@ -107,10 +1275,672 @@ mod tests {
     use atmosphere::{query, Create as _, Delete as _, Error, Pool, Read as _, Update as _};
     use pretty_assertions::assert_eq;
 
+    use glrcfg::runner::{DateTime, Executor, ImageReference, Runner, Shell, WINDOWS_DOCKER_HOST};
+
     use super::GitLabRunner;
+    use crate::{
+        models::{Change, GitLabRunnerConfig, GitLabRunnerFilter, Labels, Os, Pagination},
+        secrets::Secrets,
+        settings::{NameUniqueness, Quotas, RenderOptions, Settings},
+    };
 
     type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
+    #[test]
+    fn normalize_name() {
+        let settings = Settings {
+            name_max_length: 8,
+            ..Default::default()
+        };
+
+        let mut runner = GitLabRunner::for_testing();
+        runner.name = "  warbl \t".to_string();
+        assert!(runner.normalize(&settings).is_ok());
+        assert_eq!(runner.name, "warbl");
+
+        runner.name = "   ".to_string();
+        assert!(runner.normalize(&settings).is_err());
+
+        runner.name = "warbl\u{0}".to_string();
+        assert!(runner.normalize(&settings).is_err());
+
+        runner.name = "wärblgärbl".to_string();
+        assert!(runner.normalize(&settings).is_err());
+    }
+
+    #[test]
+    fn token_expires_at() -> Result<()> {
+        let mut json = serde_json::to_value(GitLabRunner::for_testing())?;
+        assert_eq!(json["token_expires_at"], serde_json::Value::Null);
+
+        json["token_expires_at"] = "2024-08-23T23:23:23Z".into();
+        let runner: GitLabRunner = serde_json::from_value(json.clone())?;
+        assert_eq!(
+            serde_json::to_value(&runner)?["token_expires_at"],
+            "2024-08-23T23:23:23Z"
+        );
+        assert_eq!(
+            runner
+                .into_runner(&RenderOptions::default())
+                .token_expires_at,
+            DateTime::parse("2024-08-23T23:23:23Z")?
+        );
+
+        json["token_expires_at"] = "next tuesday".into();
+        assert!(serde_json::from_value::<GitLabRunner>(json).is_err());
+
+        let mut runner = GitLabRunner::for_testing();
+        runner.token_expires_at = Some(DateTime::parse("0001-01-01T00:00:00Z")?);
+        runner.normalize(&Settings::default())?;
+        assert_eq!(runner.token_expires_at, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn token_expiry() -> Result<()> {
+        let mut runner = GitLabRunner::for_testing();
+        assert_eq!(runner.check_token_expiry(false)?, None);
+
+        runner.token_expires_at = Some(DateTime::parse("2023-08-24T23:23:23Z")?);
+        assert!(runner.check_token_expiry(false).is_err());
+        assert!(runner.check_token_expiry(true)?.is_some());
+
+        runner.token_expires_at = Some((Utc::now() + TimeDelta::days(1)).into());
+        assert_eq!(runner.check_token_expiry(false)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn docker_image_reference() -> Result<()> {
+        let mut json = serde_json::to_value(GitLabRunner::for_testing())?;
+
+        json["docker_image"] = "registry.local:5000/alpine:3.20".into();
+        let runner: GitLabRunner = serde_json::from_value(json.clone())?;
+        assert_eq!(runner.docker_image.tag(), Some("3.20"));
+
+        // typos are rejected instead of failing every job the runner picks up
+        json["docker_image"] = "alpine latest".into();
+        assert!(serde_json::from_value::<GitLabRunner>(json).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn normalize_owner_email() {
+        let settings = Settings::default();
+
+        let mut runner = GitLabRunner::for_testing();
+        runner.owner_email = Some(" jane.doe@your-company.com ".to_string());
+        assert!(runner.normalize(&settings).is_ok());
+        assert_eq!(
+            runner.owner_email.as_deref(),
+            Some("jane.doe@your-company.com")
+        );
+
+        for invalid in [
+            "jane.doe",
+            "@your-company.com",
+            "jane@localhost",
+            "ja ne@x.com",
+            "a@b@c.de",
+        ] {
+            runner.owner_email = Some(invalid.to_string());
+            assert!(runner.normalize(&settings).is_err(), "{invalid}");
+        }
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn search(pool: Pool) -> Result<()> {
+        let mut runner = GitLabRunner::for_testing();
+        runner.create(&pool).await?;
+
+        assert_eq!(
+            GitLabRunner::search(&pool, "meaning").await?,
+            vec![runner.clone()]
+        );
+        assert_eq!(
+            GitLabRunner::search(&pool, "your-company").await?,
+            vec![runner.clone()]
+        );
+        assert_eq!(
+            GitLabRunner::search(&pool, "defxyz").await?,
+            vec![runner.clone()]
+        );
+        assert_eq!(
+            GitLabRunner::search(&pool, "alpine").await?,
+            vec![runner.clone()]
+        );
+        assert!(GitLabRunner::search(&pool, "warbl").await?.is_empty());
+        assert!(GitLabRunner::search(&pool, "al").await.is_err());
+
+        runner.delete(&pool).await?;
+        assert!(GitLabRunner::search(&pool, "alpine").await?.is_empty());
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn read_invalid_image(pool: Pool) -> Result<()> {
+        let mut runner = GitLabRunner::for_testing();
+        runner.create(&pool).await?;
+
+        // stored before image references were validated
+        sqlx::query("UPDATE gitlab_runners SET docker_image = 'alpine latest'")
+            .execute(&pool)
+            .await?;
+
+        let runner = GitLabRunner::read(&pool, runner.uuid()).await?;
+        assert_eq!(runner.docker_image.as_str(), "alpine latest");
+        assert!(runner
+            .lint()
+            .iter()
+            .any(|lint| lint.to_string().contains("invalid-image")));
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn unmodified_since(pool: Pool) -> Result<()> {
+        let mut runner = GitLabRunner::for_testing();
+        runner.set_updated_at("2024-06-26T10:00:00.5Z".parse()?);
+        runner.create(&pool).await?;
+
+        let mut tx = pool.begin().await?;
+        // HTTP dates have a resolution of seconds
+        GitLabRunner::ensure_unmodified_since(
+            &mut tx,
+            runner.uuid(),
+            "2024-06-26T10:00:00Z".parse()?,
+        )
+        .await?;
+        let modified = GitLabRunner::ensure_unmodified_since(
+            &mut tx,
+            runner.uuid(),
+            "2024-06-26T09:59:59Z".parse()?,
+        )
+        .await;
+        assert!(modified.is_err_and(|err| err.msg.contains("modified")));
+
+        // the check doesn't change the runner
+        tx.commit().await?;
+        assert_eq!(GitLabRunner::read(&pool, runner.uuid()).await?, runner);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn unique_name(pool: Pool) -> Result<()> {
+        let mut runner = GitLabRunner::for_testing();
+        runner.create(&pool).await?;
+
+        let mut other = GitLabRunner::for_testing();
+        other.id = 23;
+        other.token = "glrt-warblgarblwarblgarbl".parse()?;
+
+        assert!(other
+            .ensure_unique_name(&pool, NameUniqueness::Warn)
+            .await
+            .is_ok());
+        assert!(other
+            .ensure_unique_name(&pool, NameUniqueness::Enforce)
+            .await
+            .is_err());
+        assert!(runner
+            .ensure_unique_name(&pool, NameUniqueness::Enforce)
+            .await
+            .is_ok());
+
+        let settings = Settings {
+            name_uniqueness: NameUniqueness::Enforce,
+            ..Default::default()
+        };
+        assert!(other
+            .apply_checked(&pool, Change::Created, &settings)
+            .await
+            .is_err());
+        assert!(GitLabRunner::find(&pool, &other.uuid).await?.is_none());
+
+        other.url = "https://gitlab.bmc-labs.com".parse()?;
+        assert!(other
+            .ensure_unique_name(&pool, NameUniqueness::Enforce)
+            .await
+            .is_ok());
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn quotas(pool: Pool) -> Result<()> {
+        let quotas = Quotas {
+            max_runners: 3,
+            max_runners_per_instance: 2,
+            max_privileged: 1,
+        };
+
+        let mut runner = GitLabRunner::for_testing();
+        runner.privileged = true;
+        runner.ensure_within_quotas(&pool, &quotas).await?;
+        runner.create(&pool).await?;
+
+        // updating a runner doesn't count it twice
+        runner.ensure_within_quotas(&pool, &quotas).await?;
+
+        let mut other = GitLabRunner::for_testing();
+        other.privileged = true;
+        assert!(other.ensure_within_quotas(&pool, &quotas).await.is_err());
+
+        other.privileged = false;
+        other.ensure_within_quotas(&pool, &quotas).await?;
+        other.create(&pool).await?;
+
+        let mut third = GitLabRunner::for_testing();
+        assert!(third.ensure_within_quotas(&pool, &quotas).await.is_err());
+
+        third.url = "https://gitlab.bmc-labs.com".parse()?;
+        third.ensure_within_quotas(&pool, &quotas).await?;
+        third.create(&pool).await?;
+
+        let mut fourth = GitLabRunner::for_testing();
+        fourth.url = "https://gitlab.bmc-labs.com".parse()?;
+        assert!(fourth.ensure_within_quotas(&pool, &quotas).await.is_err());
+
+        // runners already over a lowered quota can still be edited, but not made privileged
+        let lowered = Quotas {
+            max_runners: 1,
+            max_runners_per_instance: 1,
+            max_privileged: 0,
+        };
+        runner.notes = "over quota".to_string();
+        runner.ensure_within_quotas(&pool, &lowered).await?;
+        other.ensure_within_quotas(&pool, &lowered).await?;
+        other.privileged = true;
+        assert!(other.ensure_within_quotas(&pool, &lowered).await.is_err());
+
+        // moving a runner to an instance at its quota is still rejected
+        third.url = runner.url.clone();
+        assert!(third.ensure_within_quotas(&pool, &quotas).await.is_err());
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn same_instance_despite_url_spelling(pool: Pool) -> Result<()> {
+        let quotas = Quotas {
+            max_runners_per_instance: 1,
+            ..Default::default()
+        };
+
+        let mut runner = GitLabRunner::for_testing();
+        runner.url = "HTTPS://GitLab.your-company.com:443/gitlab/".parse()?;
+        runner.normalize(&Settings::default())?;
+        assert_eq!(
+            runner.url.as_str(),
+            "https://gitlab.your-company.com/gitlab"
+        );
+        runner.create(&pool).await?;
+
+        let mut other = GitLabRunner::for_testing();
+        other.url = "https://gitlab.your-company.com/gitlab".parse()?;
+        assert!(other.ensure_within_quotas(&pool, &quotas).await.is_err());
+
+        let filter = GitLabRunnerFilter {
+            url: Some("https://GITLAB.your-company.com/gitlab/".parse()?),
+            ..Default::default()
+        };
+        assert_eq!(GitLabRunner::list(&pool, &filter).await?, vec![runner]);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn list_by_label(pool: Pool) -> Result<()> {
+        let mut runner = GitLabRunner::for_testing();
+        runner.labels = Labels::from([("team", "payments"), ("cost-center", "42")]);
+        runner.create(&pool).await?;
+
+        let mut other = GitLabRunner::for_testing();
+        other.url = "https://gitlab.bmc-labs.com".parse()?;
+        other.labels = Labels::from([("team", "search")]);
+        other.create(&pool).await?;
+
+        let filter = |label: &str| GitLabRunnerFilter {
+            label: Some(label.parse().expect("label selector is valid")),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            GitLabRunner::list(&pool, &filter("team=payments")).await?,
+            vec![runner.clone()]
+        );
+        assert_eq!(
+            GitLabRunner::list(&pool, &filter("team")).await?,
+            vec![runner.clone(), other.clone()]
+        );
+        assert_eq!(
+            GitLabRunner::list(&pool, &filter("cost-center")).await?,
+            vec![runner]
+        );
+        assert!(GitLabRunner::list(&pool, &filter("team=warbl"))
+            .await?
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn list_by_tag(pool: Pool) -> Result<()> {
+        let mut runner = GitLabRunner::for_testing();
+        runner.tags = Tags::from(vec!["docker".to_string(), "linux".to_string()]);
+        runner.create(&pool).await?;
+
+        let mut other = GitLabRunner::for_testing();
+        other.url = "https://gitlab.bmc-labs.com".parse()?;
+        other.create(&pool).await?;
+
+        let filter = |tag: &str| GitLabRunnerFilter {
+            tag: Some(tag.to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            GitLabRunner::list(&pool, &filter("linux")).await?,
+            vec![runner]
+        );
+        assert!(GitLabRunner::list(&pool, &filter("windows"))
+            .await?
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn batch_update_over_quota(pool: Pool) -> Result<()> {
+        let mut privileged = GitLabRunner::for_testing();
+        privileged.tags = Tags::from(vec!["docker".to_string()]);
+        privileged.privileged = true;
+        privileged.create(&pool).await?;
+
+        let mut runner = GitLabRunner::for_testing();
+        runner.tags = Tags::from(vec!["docker".to_string()]);
+        runner.create(&pool).await?;
+
+        let mut untagged = GitLabRunner::for_testing();
+        untagged.create(&pool).await?;
+
+        // the quotas were lowered after the runners were created
+        let settings = Settings {
+            quotas: Quotas {
+                max_runners: 1,
+                max_runners_per_instance: 1,
+                max_privileged: 1,
+            },
+            ..Default::default()
+        };
+        let filter = GitLabRunnerFilter {
+            tag: Some("docker".to_string()),
+            ..Default::default()
+        };
+
+        // changes which don't add to the usage are fine, whatever the quotas say
+        let pause = serde_json::from_value(serde_json::json!({"paused": true}))?;
+        let patched = GitLabRunner::batch_update(&pool, &filter, &pause, &settings, false).await?;
+        assert_eq!(patched.len(), 2);
+        assert!(!GitLabRunner::read(&pool, untagged.uuid()).await?.paused());
+
+        // another privileged runner would exceed the quota, so none is changed
+        let privilege = serde_json::from_value(serde_json::json!({"privileged": true}))?;
+        assert!(
+            GitLabRunner::batch_update(&pool, &filter, &privilege, &settings, false)
+                .await
+                .is_err()
+        );
+        assert!(!GitLabRunner::read(&pool, runner.uuid()).await?.privileged);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn batch_update_schedule(pool: Pool) -> Result<()> {
+        let mut runner = GitLabRunner::for_testing();
+        runner.create(&pool).await?;
+        let settings = Settings::default();
+        let filter = GitLabRunnerFilter::default();
+
+        let schedule =
+            serde_json::from_value(serde_json::json!({"schedule": ["Mon 08:00-Fri 18:00"]}))?;
+        GitLabRunner::batch_update(&pool, &filter, &schedule, &settings, false).await?;
+        let scheduled = GitLabRunner::read(&pool, runner.uuid()).await?;
+        assert!(scheduled.schedule.is_some());
+        assert!(scheduled.next_transition_at().is_some());
+
+        // an empty list removes the schedule
+        let unschedule = serde_json::from_value(serde_json::json!({"schedule": []}))?;
+        GitLabRunner::batch_update(&pool, &filter, &unschedule, &settings, false).await?;
+        let unscheduled = GitLabRunner::read(&pool, runner.uuid()).await?;
+        assert!(unscheduled.schedule.is_none());
+        assert!(unscheduled.next_transition_at().is_none());
+        assert!(!unscheduled.paused());
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn list_pages_by_name(pool: Pool) -> Result<()> {
+        let mut runners = Vec::new();
+        for name in ["usain-bolt", "Bolt-2", "mo_farah", "mo-farah"] {
+            let mut runner = GitLabRunner::for_testing();
+            runner.name = name.to_string();
+            runner.create(&pool).await?;
+            runners.push(runner);
+        }
+
+        let filter = |fragment: &str| GitLabRunnerFilter {
+            name_contains: Some(fragment.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            GitLabRunner::list(&pool, &filter("BOLT")).await?,
+            runners[..2].to_vec()
+        );
+        // `_` is no wildcard
+        assert_eq!(
+            GitLabRunner::list(&pool, &filter("mo_")).await?,
+            vec![runners[2].clone()]
+        );
+
+        let page = |limit, offset| Pagination { limit, offset };
+        let everything = GitLabRunnerFilter::default();
+        assert_eq!(
+            GitLabRunner::list_page(&pool, &everything, &page(Some(2), 1)).await?,
+            runners[1..3].to_vec()
+        );
+        assert_eq!(
+            GitLabRunner::list_page(&pool, &everything, &page(None, 3)).await?,
+            runners[3..].to_vec()
+        );
+        assert_eq!(
+            GitLabRunner::list_page(&pool, &filter("farah"), &page(Some(1), 1)).await?,
+            runners[3..].to_vec()
+        );
+        assert!(
+            GitLabRunner::list_page(&pool, &everything, &page(Some(0), 0))
+                .await
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn render_container_labels() -> Result<()> {
+        let mut runner = GitLabRunner::for_testing();
+        runner.labels = Labels::from([("team", "payments")]);
+        let secrets = Secrets::default();
+
+        let container_labels = |runner: Runner| match runner.executor {
+            Executor::Docker { docker } => docker.container_labels,
+            _ => panic!("runner must use the Docker executor"),
+        };
+
+        let rendered = runner
+            .clone()
+            .into_runner(&secrets, &RenderOptions::default())
+            .await?;
+        assert!(container_labels(rendered).is_empty());
+
+        let options = RenderOptions {
+            container_labels: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            container_labels(runner.clone().into_runner(&secrets, &options).await?),
+            vec!["team=payments".to_string()]
+        );
+
+        // only the allowed labels are rendered
+        runner.labels = Labels::from([("team", "payments"), ("cost-center", "42"), ("tmp", "x")]);
+        let options = RenderOptions {
+            container_labels: true,
+            container_label_keys: "cost-center,team".parse()?,
+            ..Default::default()
+        };
+        assert_eq!(
+            container_labels(runner.into_runner(&secrets, &options).await?),
+            vec!["cost-center=42".to_string(), "team=payments".to_string()]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn render_description() -> Result<()> {
+        let mut json = serde_json::to_value(GitLabRunner::for_testing())?;
+        json["description"] = " Usain Bolt ".into();
+        let mut runner: GitLabRunner = serde_json::from_value(json)?;
+        runner.normalize(&Settings::default())?;
+
+        // both are kept and emitted, so payloads round-trip
+        let json = serde_json::to_value(&runner)?;
+        assert_eq!(json["name"], "Knows the meaning of life");
+        assert_eq!(json["description"], "Usain Bolt");
+
+        let rendered = runner
+            .clone()
+            .into_runner(&Secrets::default(), &RenderOptions::default())
+            .await?;
+        assert_eq!(rendered.name, "Usain Bolt");
+        // the ID tells runners of the same instance apart, e.g. in config diffs
+        assert_eq!(rendered.id, 42);
+
+        runner.description = Some("  ".to_string());
+        runner.normalize(&Settings::default())?;
+        assert_eq!(runner.description, None);
+        let rendered = runner
+            .into_runner(&Secrets::default(), &RenderOptions::default())
+            .await?;
+        assert_eq!(rendered.name, "Knows the meaning of life");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn render_output_limit() -> Result<()> {
+        let mut runner = GitLabRunner::for_testing();
+        let options = RenderOptions {
+            output_limit: "8192".parse()?,
+            ..Default::default()
+        };
+
+        let rendered = runner
+            .clone()
+            .into_runner(&Secrets::default(), &options)
+            .await?;
+        assert_eq!(rendered.output_limit, 8192);
+
+        runner.output_limit = Some("65536".parse()?);
+        let rendered = runner.into_runner(&Secrets::default(), &options).await?;
+        assert_eq!(rendered.output_limit, 65536);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn render_job_limit() -> Result<()> {
+        let mut runner = GitLabRunner::for_testing();
+        let rendered = runner
+            .clone()
+            .into_runner(&Secrets::default(), &RenderOptions::default())
+            .await?;
+        assert_eq!(
+            rendered.limit, 0,
+            "runners without a job limit are unlimited"
+        );
+
+        runner.set_job_limit(4);
+        let rendered = runner
+            .into_runner(&Secrets::default(), &RenderOptions::default())
+            .await?;
+        assert_eq!(rendered.limit, 4);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn render_secret_token() -> Result<()> {
+        let mut runner = GitLabRunner::for_testing();
+        runner.token = "vault:kv/runners/usain-bolt#token".parse()?;
+
+        // the token can't be rendered without a provider for the secret reference
+        let rendered = runner
+            .clone()
+            .into_runner(&Secrets::default(), &RenderOptions::default())
+            .await;
+        assert!(rendered.is_err());
+
+        // nor does it keep the other runners out of the config
+        let (secrets, options) = (Secrets::default(), RenderOptions::default());
+        let compile = |runners| GitLabRunnerConfig::compile_runners(runners, &secrets, &options);
+        let empty = compile(Vec::new()).await?;
+        let config = compile(vec![runner, GitLabRunner::for_testing()]).await?;
+        assert_eq!(empty.diff(&config).added.len(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn render_windows_runner() -> Result<()> {
+        let mut runner = GitLabRunner::for_testing();
+        runner.os = Os::Windows;
+        runner.docker_image =
+            ImageReference::parse("mcr.microsoft.com/windows/servercore:ltsc2019")?;
+
+        let runner = runner
+            .into_runner(&Secrets::default(), &RenderOptions::default())
+            .await?;
+        assert_eq!(runner.shell, Some(Shell::Pwsh));
+        match runner.executor {
+            Executor::DockerWindows { docker } => {
+                assert_eq!(
+                    docker.host.as_ref().map(|host| host.as_str()),
+                    Some(WINDOWS_DOCKER_HOST)
+                );
+                assert_eq!(docker.volumes, vec!["c:\\cache".to_string()]);
+                assert_eq!(
+                    docker.helper_image.as_ref().map(|image| image.as_str()),
+                    Some(
+                        "registry.gitlab.com/gitlab-org/gitlab-runner/gitlab-runner-helper:\
+                         x86_64-${CI_RUNNER_REVISION}-servercore1809"
+                    )
+                );
+            }
+            _ => panic!("runner must use the docker-windows executor"),
+        }
+
+        Ok(())
+    }
+
     #[sqlx::test(migrator = "crate::MIGRATOR")]
     async fn create_delete(pool: Pool) -> Result<()> {
         let mut runner = GitLabRunner::for_testing();
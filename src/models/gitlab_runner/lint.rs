@@ -0,0 +1,189 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::fmt;
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use super::GitLabRunner;
+
+/// Runner tokens expiring within this many days are reported, so they can be rotated in time.
+const TOKEN_EXPIRY_WARNING_DAYS: i64 = 7;
+
+/// A check for a configuration which is valid, but likely not what the runner's owner wants.
+/// The check returns an actionable message if the runner violates the rule.
+struct Rule {
+    name: &'static str,
+    check: fn(&GitLabRunner, DateTime<Utc>) -> Option<String>,
+}
+
+/// The rules runners are linted with. To add a rule, write its check and list it here.
+static RULES: &[Rule] = &[
+    Rule {
+        name: "invalid-image",
+        check: invalid_image,
+    },
+    Rule {
+        name: "privileged-unpinned-image",
+        check: privileged_unpinned_image,
+    },
+    Rule {
+        name: "token-expiring-soon",
+        check: token_expiring_soon,
+    },
+    Rule {
+        name: "expiry-without-owner",
+        check: expiry_without_owner,
+    },
+];
+
+/// A rule the runner violates.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Lint {
+    #[schema(example = "token-expiring-soon")]
+    rule: &'static str,
+    #[schema(example = "runner token expires at 2024-08-23T23:23:23Z; rotate it before then")]
+    message: String,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.rule)
+    }
+}
+
+impl GitLabRunner {
+    /// Checks the runner against all lint rules. Unlike validation errors, lints don't keep the
+    /// runner from being stored.
+    pub fn lint(&self) -> Vec<Lint> {
+        self.lint_at(Utc::now())
+    }
+
+    fn lint_at(&self, now: DateTime<Utc>) -> Vec<Lint> {
+        RULES
+            .iter()
+            .filter_map(|rule| {
+                (rule.check)(self, now).map(|message| Lint {
+                    rule: rule.name,
+                    message,
+                })
+            })
+            .collect()
+    }
+}
+
+fn invalid_image(runner: &GitLabRunner, _: DateTime<Utc>) -> Option<String> {
+    let image = &runner.docker_image;
+
+    (!image.is_valid()).then(|| {
+        format!(
+            "runner was stored with the invalid image '{image}', which Docker won't pull; \
+             update it to a valid image reference"
+        )
+    })
+}
+
+fn privileged_unpinned_image(runner: &GitLabRunner, _: DateTime<Utc>) -> Option<String> {
+    if !runner.privileged {
+        return None;
+    }
+
+    let image = &runner.docker_image;
+    let pinned = image.digest().is_some() || image.tag().is_some_and(|tag| tag != "latest");
+
+    (!pinned).then(|| {
+        format!(
+            "privileged runner uses the unpinned image '{image}', so whatever is pushed as its \
+             latest version runs with full access to the host; pin a version tag or digest"
+        )
+    })
+}
+
+fn token_expiring_soon(runner: &GitLabRunner, now: DateTime<Utc>) -> Option<String> {
+    let expires_at = runner.token_expires_at.as_ref()?;
+    let remaining = *expires_at.as_chrono() - now;
+    let soon =
+        remaining > TimeDelta::zero() && remaining <= TimeDelta::days(TOKEN_EXPIRY_WARNING_DAYS);
+
+    soon.then(|| {
+        format!(
+            "runner token expires at {}; rotate it before then",
+            expires_at.to_iso8601()
+        )
+    })
+}
+
+fn expiry_without_owner(runner: &GitLabRunner, _: DateTime<Utc>) -> Option<String> {
+    let expires_at = runner.expires_at?;
+
+    runner.owner_email.is_none().then(|| {
+        format!(
+            "runner expires at {}, but has no owner_email to tell anyone about it",
+            expires_at.to_rfc3339()
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeDelta, Utc};
+    use glrcfg::runner::DateTime;
+    use pretty_assertions::assert_eq;
+
+    use super::GitLabRunner;
+
+    fn rules(runner: &GitLabRunner) -> Vec<&'static str> {
+        runner.lint().iter().map(|lint| lint.rule).collect()
+    }
+
+    #[test]
+    fn privileged_unpinned_image() {
+        let mut runner = GitLabRunner::for_testing();
+        assert!(runner.lint().is_empty());
+
+        runner.privileged = true;
+        for unpinned in ["alpine:latest", "alpine", "registry.local:5000/alpine"] {
+            runner.docker_image = unpinned.parse().unwrap();
+            assert_eq!(
+                rules(&runner),
+                vec!["privileged-unpinned-image"],
+                "{unpinned}"
+            );
+        }
+        let digest = "sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+        for pinned in [
+            "alpine:3.20".to_string(),
+            "registry.local:5000/alpine:3.20".to_string(),
+            format!("alpine@{digest}"),
+        ] {
+            runner.docker_image = pinned.parse().unwrap();
+            assert!(runner.lint().is_empty(), "{pinned}");
+        }
+    }
+
+    #[test]
+    fn token_expiring_soon() {
+        let mut runner = GitLabRunner::for_testing();
+
+        runner.set_token_expires_at(DateTime::from(Utc::now() + TimeDelta::days(30)));
+        assert!(runner.lint().is_empty());
+
+        runner.set_token_expires_at(DateTime::from(Utc::now() + TimeDelta::days(3)));
+        assert_eq!(rules(&runner), vec!["token-expiring-soon"]);
+
+        // expired tokens are rejected or warned about by `check_token_expiry`
+        runner.set_token_expires_at(DateTime::from(Utc::now() - TimeDelta::days(3)));
+        assert!(runner.lint().is_empty());
+    }
+
+    #[test]
+    fn expiry_without_owner() {
+        let mut runner = GitLabRunner::for_testing();
+        runner.set_expires_at(Utc::now() + TimeDelta::days(3));
+        assert_eq!(rules(&runner), vec!["expiry-without-owner"]);
+
+        runner.owner_email = Some("jane.doe@your-company.com".to_string());
+        assert!(runner.lint().is_empty());
+    }
+}
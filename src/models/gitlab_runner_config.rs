@@ -1,33 +1,812 @@
 // Copyright 2024 bmc::labs GmbH. All rights reserved.
 
-use std::path::PathBuf;
+use std::{
+    io::{Read as _, Seek as _, Write as _},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
+use arc_swap::ArcSwap;
 use atmosphere::Read;
-use glrcfg::{runner::Runner, Config};
+use axum::http::StatusCode;
+use futures::{future::BoxFuture, FutureExt};
+use glrcfg::{runner::Runner, Config, ConfigDiff, GlobalSection};
+use serde::Serialize;
+use tokio::sync::{watch, Mutex};
+use utoipa::ToSchema;
 
-use super::GitLabRunner;
-use crate::error::Error;
+use super::{ConfigRevision, GitLabRunner, GlobalConfig, SessionServerConfig, Task};
+#[cfg(feature = "chaos")]
+use crate::chaos::Faults;
+use crate::{
+    error::Error,
+    secrets::Secrets,
+    settings::{RenderOptions, RunnerOrder, SettingsStore},
+    subsystems::{Shutdown, Subsystem},
+};
+
+/// Key of the section the TOML export lists paused runners in.
+pub(super) const PAUSED_RUNNERS_KEY: &str = "paused_runners";
+
+/// Key (and kind) of the task queued when writing the config fails.
+pub const CONFIG_WRITE_TASK: &str = "config_write";
+
+/// How often the background task checks whether a queued config write is due.
+const RETRY_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Whether the config on disk reflects a mutation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfigSync {
+    /// The config was written to disk.
+    Synced,
+    /// Writing the config failed; the write is queued and retried in the background.
+    Pending,
+    /// Retrying the queued write failed as well; it is still retried, with growing backoff.
+    Failed,
+}
+
+impl ConfigSync {
+    /// Returns `status` if the config was written, and `202 Accepted` if the write is pending.
+    pub fn status_code(self, status: StatusCode) -> StatusCode {
+        match self {
+            Self::Synced => status,
+            Self::Pending | Self::Failed => StatusCode::ACCEPTED,
+        }
+    }
+}
+
+/// Whether the config on disk is up to date, as returned along with a runner. The config is
+/// written as a whole, so while a write is queued, no runner is known to be on disk as stored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ConfigSyncStatus {
+    state: ConfigSync,
+    /// Reason the last attempt at the queued write failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = "No space left on device (os error 28)")]
+    last_error: Option<String>,
+}
+
+impl ConfigSyncStatus {
+    /// How often [`ConfigSyncStatus::wait`] checks whether the queued write succeeded.
+    const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// Derives the status from the queued config write, if there is one.
+    pub async fn current(pool: &atmosphere::Pool) -> Result<Self, Error> {
+        let Some(task) = Task::find(pool, CONFIG_WRITE_TASK).await? else {
+            return Ok(Self {
+                state: ConfigSync::Synced,
+                last_error: None,
+            });
+        };
+
+        Ok(Self {
+            state: if task.attempts() > 1 {
+                ConfigSync::Failed
+            } else {
+                ConfigSync::Pending
+            },
+            last_error: task.last_error().map(str::to_string),
+        })
+    }
+
+    /// Waits until the queued config write succeeded, for at most `timeout`, and returns the
+    /// status by then.
+    pub async fn wait(pool: &atmosphere::Pool, timeout: Duration) -> Result<Self, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let status = Self::current(pool).await?;
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if status.state == ConfigSync::Synced || remaining.is_zero() {
+                return Ok(status);
+            }
+            tokio::time::sleep(remaining.min(Self::POLL_INTERVAL)).await;
+        }
+    }
+
+    pub fn state(&self) -> ConfigSync {
+        self.state
+    }
+
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+}
 
 #[derive(Debug)]
 pub struct GitLabRunnerConfig(Config);
 
 impl GitLabRunnerConfig {
-    pub async fn compile(pool: &atmosphere::Pool) -> Result<Self, Error> {
-        let runners = GitLabRunner::read_all(pool)
+    pub async fn compile(
+        pool: &atmosphere::Pool,
+        secrets: &Secrets,
+        options: &RenderOptions,
+    ) -> Result<Self, Error> {
+        let stored = GitLabRunner::read_all(pool).await?;
+        let Self(mut config) = Self::compile_runners(stored, secrets, options).await?;
+        GlobalConfig::read(pool)
             .await?
-            .into_iter()
-            .map(Runner::from)
-            .collect();
+            .apply(&mut config.global, options.concurrent_autoscaled);
+        config.session_server = SessionServerConfig::read(pool).await?.session_server();
+
+        Ok(Self(config))
+    }
+
+    /// Compiles the config from the given runners instead of those in the database, e.g. to
+    /// preview the config for a planned set of runners.
+    pub async fn compile_runners(
+        mut stored: Vec<GitLabRunner>,
+        secrets: &Secrets,
+        options: &RenderOptions,
+    ) -> Result<Self, Error> {
+        stored.retain(|runner| !runner.paused());
+        let runners = Self::render_runners(stored, secrets, options).await?;
 
-        let config = Config::builder().with_runners(runners).build();
+        let config = Config::builder()
+            .with_global(GlobalSection {
+                log_level: options.log_level,
+                log_format: options.log_format,
+                sentry_dsn: options.sentry_dsn.clone(),
+                listen_address: options.listen_address.clone(),
+                concurrent: options.concurrent,
+                ..Default::default()
+            })
+            .with_runners(runners)
+            .with_defaults(options.defaults)
+            // set by `compile` from the stored session server settings, if there are any
+            .without_session_server()
+            .build();
 
         Ok(Self(config))
     }
 
-    pub async fn write(pool: &atmosphere::Pool, path: &PathBuf) -> Result<(), Error> {
-        let Self(config) = Self::compile(pool).await?;
+    /// Renders the paused runners, which the config leaves out, as `[[paused_runners]]` entries.
+    /// `gitlab-runner` ignores the section, so the TOML export can still be used as a config
+    /// file, while importing the export restores the paused runners.
+    pub async fn render_paused(
+        pool: &atmosphere::Pool,
+        secrets: &Secrets,
+        options: &RenderOptions,
+    ) -> Result<String, Error> {
+        let mut stored = GitLabRunner::read_all(pool).await?;
+        stored.retain(GitLabRunner::paused);
+        if stored.is_empty() {
+            return Ok(String::new());
+        }
+
+        let runners = Self::render_runners(stored, secrets, options)
+            .await?
+            .iter()
+            .map(toml::Value::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| Error::internal_error(format!("rendering runner failed: {err}")))?;
+        let section =
+            toml::Table::from_iter([(PAUSED_RUNNERS_KEY.to_string(), toml::Value::Array(runners))]);
+
+        toml::to_string_pretty(&section)
+            .map_err(|err| Error::internal_error(format!("rendering runners failed: {err}")))
+    }
+
+    /// Renders the given runners in config order.
+    async fn render_runners(
+        mut stored: Vec<GitLabRunner>,
+        secrets: &Secrets,
+        options: &RenderOptions,
+    ) -> Result<Vec<Runner>, Error> {
+        // the row order of the database is arbitrary; sorting keeps diffs between writes minimal
+        stored.sort_by(|a, b| Self::compare(a, b, options.order));
+
+        let mut runners = Vec::with_capacity(stored.len());
+        for runner in stored {
+            let (uuid, name) = (*runner.uuid(), runner.name().to_string());
+            match runner.into_runner(secrets, options).await {
+                Ok(runner) => runners.push(runner),
+                // one runner whose token can't be resolved mustn't keep the others out of the
+                // config; an unreachable secret store fails the write instead, so the config on
+                // disk stays as it is
+                Err(err) if err.status_code().is_client_error() => {
+                    tracing::error!(%err, %uuid, name, "can't render runner, leaving it out");
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(runners)
+    }
+
+    /// Compares this config (the old one) to `other` (the new one).
+    pub fn diff(&self, other: &Self) -> ConfigDiff {
+        self.0.diff(&other.0)
+    }
+
+    /// Orders runners for the config, falling back to the UUID to make the order total.
+    fn compare(a: &GitLabRunner, b: &GitLabRunner, order: RunnerOrder) -> std::cmp::Ordering {
+        let instance = |runner: &GitLabRunner| (runner.url().as_str().to_owned(), runner.id());
+        match order {
+            RunnerOrder::Instance => instance(a)
+                .cmp(&instance(b))
+                .then_with(|| a.name().cmp(b.name())),
+            RunnerOrder::Name => a
+                .name()
+                .cmp(b.name())
+                .then_with(|| instance(a).cmp(&instance(b))),
+        }
+        .then_with(|| a.uuid().cmp(b.uuid()))
+    }
+
+    pub async fn write(
+        pool: &atmosphere::Pool,
+        path: &PathBuf,
+        cache: &ConfigCache,
+        options: &RenderOptions,
+    ) -> Result<(), Error> {
+        let config_toml = cache.render(pool, options).await?;
+
+        tracing::debug!(?config_toml, "writing config to disk");
+        cache.ensure_writable()?;
+        Self::write_atomically(path, &config_toml)?;
+        cache.written();
+        Self::record_revision(pool, &config_toml).await;
+
+        Ok(())
+    }
+
+    /// Writes the config with [`glrcfg::write_atomically`], so `gitlab-runner` never reads a
+    /// partially written config, and concurrent writes neither interleave nor expose the tokens
+    /// to other users. The temporary file is read back and parsed before it replaces the config,
+    /// so that a short write doesn't leave a truncated or invalid config behind.
+    fn write_atomically(path: &Path, config_toml: &str) -> Result<(), Error> {
+        glrcfg::write_atomically(path, |file| {
+            file.write_all(config_toml.as_bytes())?;
+            Self::validate_written(file, config_toml)
+        })
+        .map_err(|err| Error::internal_error(format!("writing {} failed: {err}", path.display())))
+    }
+
+    /// Reads the written file back and checks that it holds the complete, parseable config.
+    fn validate_written(file: &mut std::fs::File, config_toml: &str) -> std::io::Result<()> {
+        let mut on_disk = String::new();
+        file.rewind()?;
+        file.read_to_string(&mut on_disk)?;
+
+        let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+        if on_disk != config_toml {
+            return Err(invalid(
+                "config read back differs from the one written".to_string(),
+            ));
+        }
+        toml::from_str::<toml::Table>(&on_disk)
+            .map_err(|err| invalid(format!("written config is not valid TOML: {err}")))?;
+
+        Ok(())
+    }
+
+    /// Keeps the written config for `GET /config/revisions/at/:timestamp`. The config is on disk
+    /// already at this point, so failing to record it doesn't fail the write.
+    async fn record_revision(pool: &atmosphere::Pool, config_toml: &str) {
+        if let Err(err) = ConfigRevision::record(pool, config_toml).await {
+            tracing::warn!(%err, "recording config revision failed");
+        }
+    }
+
+    /// Compiles the config from the database and writes it, unless the config on disk already
+    /// matches it. Returns `true` if the config on disk had drifted and was written.
+    pub async fn flush(
+        pool: &atmosphere::Pool,
+        path: &PathBuf,
+        cache: &ConfigCache,
+        options: &RenderOptions,
+    ) -> Result<bool, Error> {
+        // don't trust the cached rendering, it might have missed a mutation
+        cache.bump();
+        let config_toml = cache.render(pool, options).await?;
+
+        let drifted = std::fs::read_to_string(path).map_or(true, |on_disk| on_disk != config_toml);
+        if drifted {
+            tracing::debug!(?config_toml, "writing config to disk");
+            cache.ensure_writable()?;
+            Self::write_atomically(path, &config_toml)?;
+            cache.written();
+            Self::record_revision(pool, &config_toml).await;
+        }
+
+        // either way, the config on disk is up-to-date, so a queued write has nothing left to do
+        Task::complete(pool, CONFIG_WRITE_TASK).await?;
+        cache.sync_changed();
+        Ok(drifted)
+    }
+
+    /// Writes the config to disk like [`GitLabRunnerConfig::write`], but doesn't fail if that
+    /// doesn't work: the mutation is already committed to the database at this point, so the
+    /// write is queued durably instead and retried by [`ConfigWriteRetry`].
+    pub async fn write_or_queue(
+        pool: &atmosphere::Pool,
+        path: &PathBuf,
+        cache: &ConfigCache,
+        options: &RenderOptions,
+    ) -> Result<ConfigSync, Error> {
+        match Self::write(pool, path, cache, options).await {
+            Ok(()) => {
+                // the config on disk is up-to-date, so a queued write has nothing left to do
+                Task::complete(pool, CONFIG_WRITE_TASK).await?;
+                cache.sync_changed();
+                Ok(ConfigSync::Synced)
+            }
+            Err(err) => {
+                tracing::warn!(%err, "writing config failed, queueing retry");
+                Task::record_failure(pool, CONFIG_WRITE_TASK, CONFIG_WRITE_TASK, &err.msg).await?;
+                cache.sync_changed();
+                Ok(ConfigSync::Pending)
+            }
+        }
+    }
+}
+
+/// Retries queued config writes with backoff until they succeed.
+#[derive(Debug, Clone)]
+pub struct ConfigWriteRetry {
+    pool: atmosphere::Pool,
+    target: Arc<ConfigTarget>,
+    cache: Arc<ConfigCache>,
+    settings: Arc<SettingsStore>,
+}
+
+impl ConfigWriteRetry {
+    pub fn new(
+        pool: atmosphere::Pool,
+        target: Arc<ConfigTarget>,
+        cache: Arc<ConfigCache>,
+        settings: Arc<SettingsStore>,
+    ) -> Self {
+        Self {
+            pool,
+            target,
+            cache,
+            settings,
+        }
+    }
+
+    /// Attempts the queued write, if there is one and it is due.
+    async fn retry(&self) -> Result<(), Error> {
+        let Some(task) = Task::find(&self.pool, CONFIG_WRITE_TASK).await? else {
+            return Ok(());
+        };
+        if !task.is_due() {
+            return Ok(());
+        }
+
+        let options = &self.settings.load().render;
+        let path = self.target.load();
+        let written = GitLabRunnerConfig::write(&self.pool, &path, &self.cache, options).await;
+        let recorded = match written {
+            Ok(()) => {
+                tracing::info!(attempts = task.attempts(), "queued config write succeeded");
+                Task::complete(&self.pool, CONFIG_WRITE_TASK).await
+            }
+            Err(err) => {
+                tracing::warn!(%err, attempts = task.attempts(), "queued config write failed");
+                Task::record_failure(&self.pool, CONFIG_WRITE_TASK, CONFIG_WRITE_TASK, &err.msg)
+                    .await
+                    .map(drop)
+            }
+        };
+        self.cache.sync_changed();
+
+        recorded
+    }
+}
+
+impl Subsystem for ConfigWriteRetry {
+    fn name(&self) -> &'static str {
+        "config-write-retry"
+    }
+
+    fn run(&self, mut shutdown: Shutdown) -> BoxFuture<'static, Result<(), Error>> {
+        let this = self.clone();
+
+        async move {
+            let mut interval = tokio::time::interval(RETRY_POLL_INTERVAL);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => this.retry().await?,
+                    _ = shutdown.requested() => return Ok(()),
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+/// Keeps track of a monotonic data version, which must be bumped after every mutation of the
+/// runners in the database, and caches the config rendered for the latest data version. This way,
+/// the config is only compiled from the database if the data (or the render options) actually
+/// changed. Since secret references are resolved while compiling, secrets rotated in the secret
+/// store are picked up with the next mutation.
+#[derive(Debug, Default)]
+pub struct ConfigCache {
+    version: AtomicU64,
+    /// Number of changes of the queued config write, for responses telling whether the config on
+    /// disk is up to date
+    sync_version: AtomicU64,
+    rendered: Mutex<Option<(u64, RenderOptions, String)>>,
+    secrets: Secrets,
+    /// Number of configs written to disk, for subsystems acting on writes
+    writes: watch::Sender<u64>,
+    #[cfg(feature = "chaos")]
+    faults: Arc<Faults>,
+}
+
+impl ConfigCache {
+    /// Creates a cache which resolves secret references with `secrets`.
+    pub fn new(secrets: Secrets) -> Self {
+        Self {
+            secrets,
+            ..Default::default()
+        }
+    }
+
+    /// Makes config writes fail while a config write failure is injected.
+    #[cfg(feature = "chaos")]
+    pub fn with_faults(self, faults: Arc<Faults>) -> Self {
+        Self { faults, ..self }
+    }
+
+    /// Records that the runners in the database changed; call this after committing a mutation.
+    pub fn bump(&self) -> u64 {
+        self.version.fetch_add(1, Ordering::AcqRel) + 1
+    }
+
+    /// Returns the current data version.
+    pub fn version(&self) -> u64 {
+        self.version.load(Ordering::Acquire)
+    }
+
+    /// Records that the queued config write was queued, attempted or completed.
+    pub fn sync_changed(&self) {
+        self.sync_version.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Returns the number of changes of the queued config write.
+    pub fn sync_version(&self) -> u64 {
+        self.sync_version.load(Ordering::Acquire)
+    }
+
+    /// Returns a receiver which is marked changed whenever a config was written to disk.
+    pub fn subscribe_writes(&self) -> watch::Receiver<u64> {
+        self.writes.subscribe()
+    }
+
+    /// Fails if config writes are made to fail, before anything is written.
+    fn ensure_writable(&self) -> Result<(), Error> {
+        #[cfg(feature = "chaos")]
+        self.faults.ensure_config_writable()?;
+
+        Ok(())
+    }
+
+    fn written(&self) {
+        self.writes.send_modify(|writes| *writes += 1);
+    }
+
+    /// Returns the config rendered as TOML for the current data version, compiling it only if
+    /// the cached rendering is outdated.
+    pub async fn render(
+        &self,
+        pool: &atmosphere::Pool,
+        options: &RenderOptions,
+    ) -> Result<String, Error> {
+        // holding the lock while compiling makes concurrent callers wait for the result instead
+        // of compiling the same config several times
+        let mut rendered = self.rendered.lock().await;
+        let version = self.version();
+
+        if let Some((cached_version, cached_options, config_toml)) = rendered.as_ref() {
+            if *cached_version == version && cached_options == options {
+                tracing::debug!(version, "using cached config");
+                return Ok(config_toml.clone());
+            }
+        }
+
+        let GitLabRunnerConfig(config) =
+            GitLabRunnerConfig::compile(pool, &self.secrets, options).await?;
+        let config_toml = config.to_toml_string();
+        *rendered = Some((version, options.clone(), config_toml.clone()));
+
+        Ok(config_toml)
+    }
+}
+
+/// Path the config is written to. It is set from `CONFIG_PATH` at startup, but can be switched at
+/// runtime, e.g. to write to a staging config during a blue/green deployment. Every write reads
+/// the path once, so a write in flight while the target is switched goes entirely to one file.
+#[derive(Debug)]
+pub struct ConfigTarget {
+    current: ArcSwap<PathBuf>,
+    initial: PathBuf,
+    /// Directories the target can be switched to; the one of the initial path is always allowed
+    allowed_dirs: Vec<PathBuf>,
+}
+
+impl ConfigTarget {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            current: ArcSwap::from_pointee(path.clone()),
+            allowed_dirs: path.parent().map(Path::to_path_buf).into_iter().collect(),
+            initial: path,
+        }
+    }
+
+    /// Allows switching the target to files in `dirs` as well, e.g. as set in
+    /// `CONFIG_TARGET_DIRS`.
+    pub fn with_allowed_dirs(mut self, dirs: impl IntoIterator<Item = PathBuf>) -> Self {
+        self.allowed_dirs.extend(dirs);
+        self
+    }
+
+    /// Returns the path config writes currently go to.
+    pub fn load(&self) -> Arc<PathBuf> {
+        self.current.load_full()
+    }
+
+    /// Returns the path the service was started with.
+    pub fn initial(&self) -> &Path {
+        &self.initial
+    }
+
+    /// Switches config writes to `path` and returns the previous path. The path must be absolute
+    /// and its directory must exist and be one of the allowed directories; the file itself is
+    /// created by the next write.
+    pub fn switch(&self, path: PathBuf) -> Result<Arc<PathBuf>, Error> {
+        if !path.is_absolute() {
+            return Err(Error::invalid_argument(format!(
+                "config path {} is not absolute",
+                path.display()
+            )));
+        }
+        let Some(dir) = path.parent().and_then(|dir| dir.canonicalize().ok()) else {
+            return Err(Error::invalid_argument(format!(
+                "directory of config path {} does not exist",
+                path.display()
+            )));
+        };
+        // symlinks and `..` are resolved, so they can't lead out of the allowed directories
+        let allowed = self
+            .allowed_dirs
+            .iter()
+            .filter_map(|allowed| allowed.canonicalize().ok())
+            .any(|allowed| allowed == dir);
+        if !allowed || path.file_name().is_none() {
+            return Err(Error::invalid_argument(format!(
+                "config path {} is not in a directory config targets are allowed in",
+                path.display()
+            )));
+        }
+
+        Ok(self.current.swap(Arc::new(path)))
+    }
+
+    /// Switches config writes back to the path the service was started with.
+    pub fn reset(&self) -> Arc<PathBuf> {
+        self.current.swap(Arc::new(self.initial.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        time::{Duration, Instant},
+    };
+
+    use atmosphere::{Create as _, Pool};
+    use glrcfg::{Defaults, LogFormat};
+    use pretty_assertions::assert_eq;
+
+    use super::{ConfigCache, ConfigSync, GitLabRunnerConfig, CONFIG_WRITE_TASK};
+    use crate::{
+        models::{GitLabRunner, Labels, Task},
+        settings::{RenderOptions, RunnerOrder},
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn render_cached(pool: Pool) -> Result<()> {
+        let cache = ConfigCache::default();
+        let options = RenderOptions::default();
+        let empty = cache.render(&pool, &options).await?;
+
+        let mut runner = GitLabRunner::for_testing();
+        runner.set_labels(Labels::from([("team", "payments")]));
+        runner.create(&pool).await?;
+
+        // the data version has not been bumped, so the cached config is returned
+        assert_eq!(cache.render(&pool, &options).await?, empty);
+
+        assert_eq!(cache.bump(), 1);
+        let config_toml = cache.render(&pool, &options).await?;
+        assert_ne!(config_toml, empty);
+        assert!(config_toml.contains("Knows the meaning of life"));
+        assert!(!config_toml.contains("team=payments"));
+
+        // changed render options invalidate the cached config
+        let options = RenderOptions {
+            container_labels: true,
+            log_format: LogFormat::Text,
+            ..Default::default()
+        };
+        assert!(cache
+            .render(&pool, &options)
+            .await?
+            .contains("team=payments"));
+        assert!(cache
+            .render(&pool, &options)
+            .await?
+            .contains("log_format = \"text\""));
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn runners_ordered(pool: Pool) -> Result<()> {
+        let cache = ConfigCache::default();
+
+        let mut alpha = GitLabRunner::for_testing();
+        alpha.set_name("alpha");
+        alpha.set_url("https://gitlab.your-company.com");
+        alpha.create(&pool).await?;
+        let mut zulu = GitLabRunner::for_testing();
+        zulu.set_name("zulu");
+        zulu.set_url("https://gitlab.bmc-labs.com");
+        zulu.create(&pool).await?;
+
+        let position = |config_toml: &str, name: &str| config_toml.find(name).unwrap_or_default();
+
+        // by default, runners are ordered by GitLab instance
+        let config_toml = cache.render(&pool, &RenderOptions::default()).await?;
+        assert!(position(&config_toml, "zulu") < position(&config_toml, "alpha"));
+
+        let options = RenderOptions {
+            order: RunnerOrder::Name,
+            ..Default::default()
+        };
+        let config_toml = cache.render(&pool, &options).await?;
+        assert!(position(&config_toml, "alpha") < position(&config_toml, "zulu"));
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn minimal_defaults(pool: Pool) -> Result<()> {
+        let cache = ConfigCache::default();
+        GitLabRunner::for_testing().create(&pool).await?;
+
+        let config_toml = cache.render(&pool, &RenderOptions::default()).await?;
+        assert!(config_toml.contains("network_mtu = 0"));
+
+        let options = RenderOptions {
+            defaults: Defaults::Minimal,
+            ..Default::default()
+        };
+        let config_toml = cache.render(&pool, &options).await?;
+        assert!(!config_toml.contains("network_mtu"));
+        assert!(config_toml.contains("Knows the meaning of life"));
+
+        Ok(())
+    }
+
+    /// Compiling and serializing the config for this many runners must stay within
+    /// [`RENDER_BUDGET`] in release builds; run with `cargo test --release -- --ignored`.
+    const BUDGET_RUNNERS: usize = 5_000;
+    const RENDER_BUDGET: Duration = Duration::from_secs(1);
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    #[ignore = "performance budget, only meaningful in release builds"]
+    async fn render_within_budget(pool: Pool) -> Result<()> {
+        for idx in 0..BUDGET_RUNNERS {
+            let mut runner = GitLabRunner::for_testing();
+            runner.set_name(&format!("runner-{idx}"));
+            runner.create(&pool).await?;
+        }
+
+        let start = Instant::now();
+        let config_toml = ConfigCache::default()
+            .render(&pool, &RenderOptions::default())
+            .await?;
+        let elapsed = start.elapsed();
+
+        assert_eq!(config_toml.matches("[[runners]]").count(), BUDGET_RUNNERS);
+        assert!(
+            elapsed < RENDER_BUDGET,
+            "rendering {BUDGET_RUNNERS} runners took {elapsed:?}"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn write_atomically() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("runrs-atomic-{}.toml", uuid::Uuid::new_v4()));
+
+        GitLabRunnerConfig::write_atomically(&path, "concurrent = 1\n")?;
+        // a config which doesn't parse never replaces the one on disk
+        assert!(GitLabRunnerConfig::write_atomically(&path, "concurrent = \n").is_err());
+        assert_eq!(std::fs::read_to_string(&path)?, "concurrent = 1\n");
+
+        // concurrent writes each read back their own config, and one of them ends up on disk
+        let configs: Vec<String> = (0..8)
+            .map(|idx| format!("concurrent = {idx}\n").repeat(100))
+            .collect();
+        std::thread::scope(|scope| {
+            let writes: Vec<_> = configs
+                .iter()
+                .map(|config_toml| {
+                    scope.spawn(|| GitLabRunnerConfig::write_atomically(&path, config_toml))
+                })
+                .collect();
+            writes
+                .into_iter()
+                .all(|write| write.join().is_ok_and(|written| written.is_ok()))
+        })
+        .then_some(())
+        .ok_or("concurrent write failed")?;
+        assert!(configs.contains(&std::fs::read_to_string(&path)?));
+
+        // the config holds tokens, so only its owner may read it
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt as _;
+            assert_eq!(
+                std::fs::metadata(&path)?.permissions().mode() & 0o777,
+                0o600
+            );
+        }
+
+        std::fs::remove_file(&path)?;
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn write_or_queue(pool: Pool) -> Result<()> {
+        let cache = ConfigCache::default();
+
+        let unwritable = PathBuf::from("/nonexistent/gitlab-runner/config.toml");
+        let sync =
+            GitLabRunnerConfig::write_or_queue(&pool, &unwritable, &cache, &Default::default())
+                .await?;
+        assert_eq!(sync, ConfigSync::Pending);
+
+        let task = Task::find(&pool, CONFIG_WRITE_TASK).await?;
+        assert_eq!(task.map(|task| task.attempts()), Some(1));
+
+        let path = PathBuf::from(format!(
+            "/tmp/gitlab-runner-config-{}.toml",
+            uuid::Uuid::new_v4()
+        ));
+        let sync =
+            GitLabRunnerConfig::write_or_queue(&pool, &path, &cache, &Default::default()).await?;
+        assert_eq!(sync, ConfigSync::Synced);
+        assert_eq!(Task::find(&pool, CONFIG_WRITE_TASK).await?, None);
+        // the config is moved into place, no temporary file is left behind
+        let tmp_prefix = format!(
+            ".{}.",
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or_default()
+        );
+        assert!(path.exists());
+        assert!(!std::fs::read_dir("/tmp")?
+            .filter_map(|entry| entry.ok())
+            .any(|entry| entry.file_name().to_string_lossy().starts_with(&tmp_prefix)));
+
+        std::fs::remove_file(&path)?;
 
-        tracing::debug!(?config, "writing config to disk");
-        config.write(path).map_err(Error::internal_error)
+        Ok(())
     }
 }
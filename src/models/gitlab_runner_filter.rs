@@ -0,0 +1,123 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use glrcfg::runner::Url;
+use serde::Deserialize;
+use sqlx::{QueryBuilder, Sqlite};
+use utoipa::IntoParams;
+
+use super::LabelSelector;
+use crate::error::Error;
+
+/// Criteria selecting a set of runners. All given criteria must match; criteria which are not
+/// given match every runner.
+#[derive(Debug, Clone, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct GitLabRunnerFilter {
+    /// GitLab instance URL the runners belong to
+    #[param(value_type = Option<String>, format = Uri, example = "https://gitlab.your-company.com")]
+    pub url: Option<Url>,
+    /// Exact runner name
+    pub name: Option<String>,
+    /// Part of the runner name, matched case-insensitively
+    #[param(example = "bolt")]
+    pub name_contains: Option<String>,
+    /// Exact Docker image
+    pub docker_image: Option<String>,
+    /// Label, either as `key=value` or as `key` to match any value
+    #[param(value_type = Option<String>, example = "team=payments")]
+    pub label: Option<LabelSelector>,
+    /// Tag the runner is registered with
+    #[param(example = "docker")]
+    pub tag: Option<String>,
+}
+
+impl GitLabRunnerFilter {
+    /// Returns `true` if no criteria are set, i.e. the filter matches every runner.
+    pub fn is_empty(&self) -> bool {
+        self.url.is_none()
+            && self.name.is_none()
+            && self.name_contains.is_none()
+            && self.docker_image.is_none()
+            && self.label.is_none()
+            && self.tag.is_none()
+    }
+
+    /// Appends the criteria to a query which already contains a `WHERE` clause.
+    pub fn push_conditions<'a>(&'a self, query: &mut QueryBuilder<'a, Sqlite>) {
+        if let Some(url) = &self.url {
+            query
+                .push(" AND url = ")
+                .push_bind(url.normalized().as_str().to_string());
+        }
+        if let Some(name) = &self.name {
+            query.push(" AND name = ").push_bind(name.as_str());
+        }
+        if let Some(fragment) = &self.name_contains {
+            // `instr` rather than `LIKE`, so that `%` and `_` in the fragment match literally
+            query
+                .push(" AND instr(lower(name), lower(")
+                .push_bind(fragment.as_str())
+                .push(")) > 0");
+        }
+        if let Some(docker_image) = &self.docker_image {
+            query
+                .push(" AND docker_image = ")
+                .push_bind(docker_image.as_str());
+        }
+        if let Some(label) = &self.label {
+            query
+                .push(" AND EXISTS (SELECT 1 FROM json_each(labels) WHERE key = ")
+                .push_bind(label.key.as_str());
+            if let Some(value) = &label.value {
+                query.push(" AND value = ").push_bind(value.as_str());
+            }
+            query.push(")");
+        }
+        if let Some(tag) = &self.tag {
+            query
+                .push(" AND EXISTS (SELECT 1 FROM json_each(tags) WHERE value = ")
+                .push_bind(tag.as_str())
+                .push(")");
+        }
+    }
+}
+
+/// Largest page of runners returned at once.
+pub const MAX_PAGE_SIZE: u32 = 1000;
+
+/// Window of a list of runners. Without a `limit`, all runners from `offset` on are returned.
+#[derive(Debug, Clone, Copy, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct Pagination {
+    /// Maximum number of runners returned, at most 1000
+    #[param(example = 100)]
+    pub limit: Option<u32>,
+    /// Number of runners skipped
+    #[serde(default)]
+    #[param(example = 200)]
+    pub offset: u32,
+}
+
+impl Pagination {
+    /// Appends `LIMIT` and `OFFSET` clauses to a query, if a window is set.
+    pub fn push_window(&self, query: &mut QueryBuilder<'_, Sqlite>) -> Result<(), Error> {
+        match self.limit {
+            Some(limit) if limit == 0 || limit > MAX_PAGE_SIZE => {
+                return Err(Error::invalid_argument(format!(
+                    "limit must be between 1 and {MAX_PAGE_SIZE}"
+                )));
+            }
+            Some(limit) => {
+                query.push(" LIMIT ").push_bind(limit);
+            }
+            // SQLite only takes an offset along with a limit, with -1 meaning none
+            None if self.offset > 0 => {
+                query.push(" LIMIT -1");
+            }
+            None => return Ok(()),
+        }
+        query.push(" OFFSET ").push_bind(self.offset);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,52 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use glrcfg::runner::ImageReference;
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+use super::{Labels, OutputLimit};
+use crate::freeze::FreezeWindow;
+
+/// Attributes to change on a set of runners; attributes which are not given are left as they are.
+/// Attributes identifying a runner, like its name, URL or token, can't be changed in batches.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct GitLabRunnerPatch {
+    /// Docker image to be used, as `[registry/]repository[:tag][@digest]`
+    #[schema(value_type = Option<String>, example = "alpine:3.20")]
+    pub docker_image: Option<ImageReference>,
+    /// Whether to run the Docker containers in privileged mode
+    pub privileged: Option<bool>,
+    /// Labels to set; existing labels with other keys are kept
+    #[schema(value_type = Option<Object>, example = json!({"team": "payments"}))]
+    pub labels: Option<Labels>,
+    /// Free-form notes, replacing the existing ones
+    pub notes: Option<String>,
+    /// Email address of the person responsible for the runners
+    #[schema(example = "jane.doe@your-company.com")]
+    pub owner_email: Option<String>,
+    /// Runner description shown in GitLab, replacing the existing one
+    pub description: Option<String>,
+    /// Maximum job log size in KiB
+    #[schema(value_type = Option<u32>, example = 16384)]
+    pub output_limit: Option<OutputLimit>,
+    /// Maximum number of jobs each of the runners runs at once
+    #[schema(example = 4)]
+    pub job_limit: Option<u32>,
+    /// Whether runrs scales the job limits of the runners to their job queues
+    pub autoscaled: Option<bool>,
+    /// Weekly windows in UTC during which the runners pick up jobs, replacing the existing ones;
+    /// an empty list removes the schedule
+    #[schema(value_type = Option<Vec<String>>, example = json!(["Mon 08:00-Fri 18:00"]))]
+    pub schedule: Option<Vec<FreezeWindow>>,
+    /// Whether the runners are left out of the config; a paused runner stays paused whatever its
+    /// schedule says
+    pub paused: Option<bool>,
+}
+
+impl GitLabRunnerPatch {
+    /// Returns `true` if the patch doesn't change anything.
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
@@ -0,0 +1,154 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::num::NonZeroU32;
+
+use glrcfg::{GlobalSection, GolangDuration, ListenAddress, LogFormat, LogLevel, SentryDsn};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::single_row;
+use crate::{error::Error, settings::Settings};
+
+/// Keys of the global section of the config set via the API. They take precedence over the
+/// render options; keys which aren't set keep the value from the render options, or the default
+/// of `gitlab-runner` if the render options don't cover them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct GlobalConfig {
+    /// Maximum number of jobs `gitlab-runner` runs at once across all runners; can't be set while
+    /// autoscaling manages it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<u32>, minimum = 1, example = 4)]
+    pub concurrent: Option<NonZeroU32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<LogLevel>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_format: Option<LogFormat>,
+    /// Seconds between two requests for new jobs to GitLab
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = 3)]
+    pub check_interval: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>, format = Uri, example = "https://key@o42.ingest.sentry.io/4711")]
+    pub sentry_dsn: Option<SentryDsn>,
+    /// Golang duration after which connections to GitLab are closed and reopened
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>, example = "15m")]
+    pub connection_max_age: Option<GolangDuration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>, example = ":9252")]
+    pub listen_address: Option<ListenAddress>,
+    /// Seconds `gitlab-runner` waits for running jobs when shutting down
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = 30)]
+    pub shutdown_timeout: Option<u32>,
+}
+
+impl GlobalConfig {
+    /// Reads the stored keys; none are set if the global section was never changed via the API.
+    pub async fn read(pool: &atmosphere::Pool) -> Result<Self, Error> {
+        single_row::read(pool, "global_config").await
+    }
+
+    /// Replaces the stored keys.
+    pub async fn store(&self, pool: &atmosphere::Pool, settings: &Settings) -> Result<(), Error> {
+        // the autoscaler would overwrite `concurrent` with every scaling decision otherwise
+        if self.concurrent.is_some() && settings.autoscaling.interval().is_some() {
+            return Err(Error::invalid_argument(
+                "concurrent is managed by the autoscaler while autoscaling is on",
+            ));
+        }
+
+        single_row::store(pool, "global_config", self).await
+    }
+
+    /// Overrides the keys of `global` which are set, except for `concurrent` while the autoscaler
+    /// manages it: autoscaling may have been turned on after `concurrent` was stored.
+    pub fn apply(&self, global: &mut GlobalSection, concurrent_autoscaled: bool) {
+        if let Some(concurrent) = self.concurrent.filter(|_| !concurrent_autoscaled) {
+            global.concurrent = concurrent;
+        }
+        if let Some(log_level) = self.log_level {
+            global.log_level = log_level;
+        }
+        if let Some(log_format) = self.log_format {
+            global.log_format = log_format;
+        }
+        if let Some(check_interval) = self.check_interval {
+            global.check_interval = check_interval;
+        }
+        if let Some(sentry_dsn) = &self.sentry_dsn {
+            global.sentry_dsn = Some(sentry_dsn.clone());
+        }
+        if let Some(connection_max_age) = &self.connection_max_age {
+            global.connection_max_age = connection_max_age.clone();
+        }
+        if let Some(listen_address) = &self.listen_address {
+            global.listen_address = Some(listen_address.clone());
+        }
+        if let Some(shutdown_timeout) = self.shutdown_timeout {
+            global.shutdown_timeout = shutdown_timeout;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use atmosphere::Pool;
+    use glrcfg::{GlobalSection, LogLevel};
+    use pretty_assertions::assert_eq;
+
+    use super::GlobalConfig;
+    use crate::settings::{Autoscaling, Settings};
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn store_read_apply(pool: Pool) -> Result<()> {
+        assert_eq!(GlobalConfig::read(&pool).await?, GlobalConfig::default());
+
+        let config = GlobalConfig {
+            check_interval: Some(10),
+            log_level: Some(LogLevel::Debug),
+            ..Default::default()
+        };
+        config.store(&pool, &Settings::default()).await?;
+        assert_eq!(GlobalConfig::read(&pool).await?, config);
+
+        let mut global = GlobalSection::default();
+        config.apply(&mut global, false);
+        assert_eq!(global.check_interval, 10);
+        assert_eq!(global.log_level, LogLevel::Debug);
+        // keys which aren't set are left alone
+        assert_eq!(
+            global.shutdown_timeout,
+            GlobalSection::default().shutdown_timeout
+        );
+
+        // `concurrent` is off limits while autoscaling
+        let settings = Settings {
+            autoscaling: Autoscaling {
+                interval_secs: 30,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let config = GlobalConfig {
+            concurrent: NonZeroU32::new(8),
+            ..Default::default()
+        };
+        assert!(config.store(&pool, &settings).await.is_err());
+        assert!(config.store(&pool, &Settings::default()).await.is_ok());
+
+        // nor does it override the autoscaler once autoscaling was turned on after storing it
+        let mut global = GlobalSection::default();
+        config.apply(&mut global, true);
+        assert_eq!(global.concurrent, GlobalSection::default().concurrent);
+        config.apply(&mut global, false);
+        assert_eq!(global.concurrent, NonZeroU32::new(8).unwrap());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,411 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{sync::Arc, time::Duration};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use futures::{future::BoxFuture, FutureExt};
+use sqlx::{QueryBuilder, Sqlite, SqliteExecutor};
+use uuid::Uuid;
+
+use super::{GitLabRunner, Pagination};
+use crate::{
+    error::Error,
+    settings::{HistoryRetention, SettingsStore},
+    subsystems::{Shutdown, Subsystem},
+};
+
+/// How often revisions beyond the retention are pruned.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Number of revisions pruned at most per table and run, so the database isn't locked for long.
+const PRUNE_BATCH_SIZE: i64 = 1_000;
+
+/// Every state the runners were in, so the runner set can be reconstructed for any point in time
+/// since the history is kept. Tokens are kept masked, like in the recycle bin, since the history
+/// outlives the runners.
+#[derive(Debug)]
+pub struct RunnerHistory;
+
+impl RunnerHistory {
+    /// Records the state of the runner with `uuid` after a change, `None` if it was deleted. Must
+    /// run in the transaction of the change.
+    pub async fn record<'c>(
+        conn: impl SqliteExecutor<'c>,
+        uuid: &Uuid,
+        runner: Option<&GitLabRunner>,
+    ) -> Result<(), Error> {
+        Self::insert(conn, uuid, runner, Utc::now()).await
+    }
+
+    async fn insert<'c>(
+        conn: impl SqliteExecutor<'c>,
+        uuid: &Uuid,
+        runner: Option<&GitLabRunner>,
+        at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let runner = runner
+            .map(|runner| {
+                let mut value = serde_json::to_value(runner)?;
+                value["token"] = runner.masked_token().into();
+                serde_json::to_string(&value)
+            })
+            .transpose()
+            .map_err(Error::internal_error)?;
+        sqlx::query("INSERT INTO gitlab_runner_revisions (uuid, runner, at) VALUES (?, ?, ?)")
+            .bind(uuid)
+            .bind(runner)
+            .bind(at)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Enters the runners which have no history yet, e.g. because they were created by a runrs
+    /// version which didn't keep one, as of their last change. Returns how many were entered.
+    pub async fn seed(pool: &atmosphere::Pool) -> Result<usize, Error> {
+        let mut tx = pool.begin().await?;
+        let runners: Vec<GitLabRunner> = sqlx::query_as(
+            "SELECT * FROM gitlab_runners \
+             WHERE uuid NOT IN (SELECT uuid FROM gitlab_runner_revisions) ORDER BY rowid",
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+        for runner in &runners {
+            Self::insert(&mut *tx, runner.uuid(), Some(runner), runner.updated_at()).await?;
+        }
+        tx.commit().await?;
+
+        Ok(runners.len())
+    }
+
+    /// Reconstructs a page of the runners as they were at `at`, in order of creation. The runners
+    /// are returned as JSON, since their tokens are masked.
+    pub async fn as_of(
+        pool: &atmosphere::Pool,
+        at: DateTime<Utc>,
+        page: &Pagination,
+    ) -> Result<Vec<serde_json::Value>, Error> {
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT runner FROM gitlab_runner_revisions AS revision WHERE id IN \
+             (SELECT MAX(id) FROM gitlab_runner_revisions WHERE datetime(at) <= datetime(",
+        );
+        query.push_bind(at).push(
+            ") GROUP BY uuid) AND runner IS NOT NULL ORDER BY \
+                 (SELECT MIN(id) FROM gitlab_runner_revisions WHERE uuid = revision.uuid)",
+        );
+        page.push_window(&mut query)?;
+
+        let runners: Vec<String> = query.build_query_scalar().fetch_all(pool).await?;
+        runners
+            .iter()
+            .map(|runner| serde_json::from_str(runner).map_err(Error::internal_error))
+            .collect()
+    }
+
+    /// Removes the whole history of the runner with `uuid`, once it's purged for good. Must run
+    /// in the transaction of the purge.
+    pub async fn forget<'c>(conn: impl SqliteExecutor<'c>, uuid: &Uuid) -> Result<(), Error> {
+        sqlx::query("DELETE FROM gitlab_runner_revisions WHERE uuid = ?")
+            .bind(uuid)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Removes up to `limit` of the revisions older than the retention. The last revision of each
+    /// runner before the cutoff is kept, since the runner was in that state at the cutoff, unless
+    /// the runner was deleted by then. Returns the number of removed revisions.
+    pub async fn prune(
+        pool: &atmosphere::Pool,
+        retention: &HistoryRetention,
+        now: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<u64, Error> {
+        if retention.max_age_days == 0 {
+            return Ok(0);
+        }
+
+        let cutoff = now - TimeDelta::days(i64::from(retention.max_age_days));
+        let pruned = sqlx::query(
+            "DELETE FROM gitlab_runner_revisions WHERE id IN (SELECT id \
+             FROM gitlab_runner_revisions AS revision WHERE datetime(at) < datetime(?) \
+             AND (runner IS NULL OR id < (SELECT MAX(id) FROM gitlab_runner_revisions \
+             WHERE uuid = revision.uuid AND datetime(at) < datetime(?))) ORDER BY id LIMIT ?)",
+        )
+        .bind(cutoff)
+        .bind(cutoff)
+        .bind(limit)
+        .execute(pool)
+        .await?;
+
+        Ok(pruned.rows_affected())
+    }
+}
+
+/// A config as it was written to disk.
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct ConfigRevision {
+    config: String,
+    at: DateTime<Utc>,
+}
+
+impl ConfigRevision {
+    /// Records a config written to disk, unless it's the same as the previous one.
+    pub async fn record<'c>(conn: impl SqliteExecutor<'c>, config: &str) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO config_revisions (config, at) SELECT ?, ? \
+             WHERE ? IS NOT (SELECT config FROM config_revisions ORDER BY id DESC LIMIT 1)",
+        )
+        .bind(config)
+        .bind(Utc::now())
+        .bind(config)
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns the config which was in force at `at`, i.e. the last one written before.
+    pub async fn at(pool: &atmosphere::Pool, at: DateTime<Utc>) -> Result<Option<Self>, Error> {
+        Ok(sqlx::query_as(
+            "SELECT config, at FROM config_revisions WHERE datetime(at) <= datetime(?) \
+             ORDER BY id DESC LIMIT 1",
+        )
+        .bind(at)
+        .fetch_optional(pool)
+        .await?)
+    }
+
+    /// Removes up to `limit` of the oldest configs beyond the retention limits; the config in
+    /// force at the age cutoff is kept. Returns the number of removed configs.
+    pub async fn prune(
+        pool: &atmosphere::Pool,
+        retention: &HistoryRetention,
+        now: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<u64, Error> {
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "DELETE FROM config_revisions WHERE id IN (SELECT id FROM config_revisions WHERE 0 = 1",
+        );
+        if retention.max_age_days > 0 {
+            let cutoff = now - TimeDelta::days(i64::from(retention.max_age_days));
+            query
+                .push(
+                    " OR id < (SELECT MAX(id) FROM config_revisions WHERE datetime(at) < datetime(",
+                )
+                .push_bind(cutoff)
+                .push("))");
+        }
+        if retention.max_config_revisions > 0 {
+            query
+                .push(" OR id <= (SELECT id FROM config_revisions ORDER BY id DESC LIMIT 1 OFFSET ")
+                .push_bind(i64::try_from(retention.max_config_revisions).unwrap_or(i64::MAX))
+                .push(")");
+        }
+        query.push(" ORDER BY id LIMIT ").push_bind(limit).push(")");
+
+        Ok(query.build().execute(pool).await?.rows_affected())
+    }
+
+    pub fn config(&self) -> &str {
+        &self.config
+    }
+
+    pub fn written_at(&self) -> DateTime<Utc> {
+        self.at
+    }
+}
+
+/// Removes runner and config revisions beyond the retention limits, a batch at a time.
+#[derive(Debug, Clone)]
+pub struct HistoryPruner {
+    pool: atmosphere::Pool,
+    settings: Arc<SettingsStore>,
+}
+
+impl HistoryPruner {
+    pub fn new(pool: atmosphere::Pool, settings: Arc<SettingsStore>) -> Self {
+        Self { pool, settings }
+    }
+
+    async fn prune(&self) -> Result<(), Error> {
+        let retention = &self.settings.load().history;
+
+        let pruned =
+            RunnerHistory::prune(&self.pool, retention, Utc::now(), PRUNE_BATCH_SIZE).await?;
+        if pruned > 0 {
+            tracing::info!(pruned, "pruned runner history");
+        }
+
+        let pruned =
+            ConfigRevision::prune(&self.pool, retention, Utc::now(), PRUNE_BATCH_SIZE).await?;
+        if pruned > 0 {
+            tracing::info!(pruned, "pruned config history");
+        }
+
+        Ok(())
+    }
+}
+
+impl Subsystem for HistoryPruner {
+    fn name(&self) -> &'static str {
+        "history-pruner"
+    }
+
+    fn run(&self, mut shutdown: Shutdown) -> BoxFuture<'static, Result<(), Error>> {
+        let this = self.clone();
+
+        async move {
+            let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => this.prune().await?,
+                    _ = shutdown.requested() => return Ok(()),
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use atmosphere::{Create as _, Pool};
+    use chrono::{TimeDelta, Utc};
+    use pretty_assertions::assert_eq;
+
+    use super::{ConfigRevision, RunnerHistory};
+    use crate::{
+        models::{Change, GitLabRunner, Pagination},
+        settings::HistoryRetention,
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn reconstruct_runners(pool: Pool) -> Result<()> {
+        let mut runner = GitLabRunner::for_testing();
+        runner.apply(&pool, Change::Created, false).await?;
+        let created = Utc::now();
+
+        sqlx::query("UPDATE gitlab_runner_revisions SET at = ?")
+            .bind(created - TimeDelta::hours(2))
+            .execute(&pool)
+            .await?;
+        runner.set_job_limit(3);
+        runner.apply(&pool, Change::Updated, false).await?;
+        sqlx::query(
+            "UPDATE gitlab_runner_revisions SET at = ? \
+             WHERE id = (SELECT MAX(id) FROM gitlab_runner_revisions)",
+        )
+        .bind(created - TimeDelta::hours(1))
+        .execute(&pool)
+        .await?;
+        runner.apply(&pool, Change::Deleted, false).await?;
+
+        let page = Pagination::default();
+        let before = RunnerHistory::as_of(&pool, created - TimeDelta::hours(3), &page).await?;
+        assert!(before.is_empty());
+
+        let created_only =
+            RunnerHistory::as_of(&pool, created - TimeDelta::minutes(90), &page).await?;
+        assert_eq!(created_only.len(), 1);
+        assert_eq!(created_only[0]["job_limit"], serde_json::Value::Null);
+        assert_eq!(created_only[0]["token"], runner.masked_token());
+
+        let updated = RunnerHistory::as_of(&pool, created - TimeDelta::minutes(30), &page).await?;
+        assert_eq!(updated.len(), 1);
+        assert_eq!(updated[0]["job_limit"], 3);
+
+        let deleted = RunnerHistory::as_of(&pool, Utc::now(), &page).await?;
+        assert!(deleted.is_empty());
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn seed_runners_without_history(pool: Pool) -> Result<()> {
+        let mut runner = GitLabRunner::for_testing();
+        runner.set_updated_at(Utc::now() - TimeDelta::days(1));
+        runner.create(&pool).await?;
+
+        assert_eq!(RunnerHistory::seed(&pool).await?, 1);
+        assert_eq!(RunnerHistory::seed(&pool).await?, 0);
+
+        let yesterday = Utc::now() - TimeDelta::hours(12);
+        let runners = RunnerHistory::as_of(&pool, yesterday, &Pagination::default()).await?;
+        assert_eq!(runners.len(), 1);
+        assert_eq!(runners[0]["uuid"], runner.uuid().to_string());
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn skip_unchanged_config(pool: Pool) -> Result<()> {
+        let before = Utc::now() - TimeDelta::minutes(1);
+        assert_eq!(ConfigRevision::at(&pool, before).await?, None);
+
+        ConfigRevision::record(&pool, "concurrent = 1\n").await?;
+        ConfigRevision::record(&pool, "concurrent = 1\n").await?;
+        ConfigRevision::record(&pool, "concurrent = 2\n").await?;
+
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM config_revisions")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(count, 2);
+
+        let current = ConfigRevision::at(&pool, Utc::now())
+            .await?
+            .ok_or("no config")?;
+        assert_eq!(current.config(), "concurrent = 2\n");
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn prune_beyond_retention(pool: Pool) -> Result<()> {
+        let now = Utc::now();
+        let mut runner = GitLabRunner::for_testing();
+        runner.apply(&pool, Change::Created, false).await?;
+        runner.set_job_limit(3);
+        runner.apply(&pool, Change::Updated, false).await?;
+        let mut deleted = GitLabRunner::for_testing();
+        deleted.set_token("glrt-deleted_abcdefXYZ012");
+        deleted.apply(&pool, Change::Created, false).await?;
+        deleted.apply(&pool, Change::Deleted, false).await?;
+        sqlx::query("UPDATE gitlab_runner_revisions SET at = ?")
+            .bind(now - TimeDelta::days(100))
+            .execute(&pool)
+            .await?;
+        runner.set_job_limit(4);
+        runner.apply(&pool, Change::Updated, false).await?;
+
+        let retention = HistoryRetention {
+            max_age_days: 90,
+            max_config_revisions: 2,
+        };
+        // the first revision of the kept runner and both of the deleted one are gone
+        assert_eq!(RunnerHistory::prune(&pool, &retention, now, 10).await?, 3);
+        assert_eq!(RunnerHistory::prune(&pool, &retention, now, 10).await?, 0);
+
+        // the runner is still known as it was at the cutoff
+        let page = Pagination::default();
+        let at_cutoff = RunnerHistory::as_of(&pool, now - TimeDelta::days(90), &page).await?;
+        assert_eq!(at_cutoff.len(), 1);
+        assert_eq!(at_cutoff[0]["job_limit"], 3);
+
+        for config in ["concurrent = 1\n", "concurrent = 2\n", "concurrent = 3\n"] {
+            ConfigRevision::record(&pool, config).await?;
+        }
+        assert_eq!(ConfigRevision::prune(&pool, &retention, now, 10).await?, 1);
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM config_revisions")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(count, 2);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,529 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{path::PathBuf, sync::Arc};
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use glrcfg::runner::{redact_tokens, Runner};
+use serde::{Deserialize as _, Serialize};
+use sqlx::Connection as _;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use utoipa::ToSchema;
+
+use super::{
+    gitlab_runner_config::PAUSED_RUNNERS_KEY, Change, ConfigCache, ConfigSync, GitLabRunner,
+    GitLabRunnerConfig,
+};
+use crate::{error::Error, secrets::Secrets, settings::Settings};
+
+/// Number of runners committed in one transaction; progress is reported after each chunk.
+const IMPORT_CHUNK_SIZE: usize = 100;
+
+/// Number of progress reports buffered ahead of a slow client.
+const PROGRESS_BUFFER: usize = 16;
+
+/// Maximum size of the request body. The body is processed while it is uploaded, but a client
+/// mustn't be able to keep an import going forever.
+const MAX_IMPORT_BYTES: usize = 64 * 1024 * 1024;
+
+/// Maximum size of a single line of newline-delimited JSON, which is buffered until it is complete.
+const MAX_LINE_BYTES: usize = 1024 * 1024;
+
+/// Format of the runners to import.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ImportFormat {
+    /// Newline-delimited JSON, as produced by `GET /gitlab-runners/stream`
+    #[default]
+    Ndjson,
+    /// A `gitlab-runner` config file; only runners using one of the Docker executors are imported
+    Toml,
+}
+
+/// A runner read from the request body, yet to be checked and stored.
+#[derive(Debug)]
+enum ImportItem {
+    Json(Vec<u8>),
+    /// A `[[runners]]` entry, or a `[[paused_runners]]` one of a TOML export
+    Toml {
+        runner: toml::Value,
+        paused: bool,
+    },
+}
+
+/// A line of the import which was not imported.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ImportFailure {
+    /// Line of the request body, or for a config file the position of the `[[runners]]` entry;
+    /// starting at 1
+    #[schema(example = 42)]
+    line: usize,
+    #[schema(example = "Invalid argument: runner name must not be empty")]
+    error: String,
+}
+
+/// Progress of an import, reported after each chunk of runners is committed. The last report is
+/// marked `done`; it carries the state of the config as well.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, ToSchema)]
+pub struct ImportProgress {
+    /// Runners read so far, i.e. non-empty lines
+    processed: usize,
+    /// Runners committed to the database so far
+    imported: usize,
+    /// Runners which were not imported
+    failed: Vec<ImportFailure>,
+    /// Whether the import finished; it is aborted early if the request body can't be read
+    done: bool,
+    /// Whether the config reflects the import, once it is done
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sync: Option<ConfigSync>,
+    /// Why the import was aborted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl ImportProgress {
+    pub fn done(&self) -> bool {
+        self.done
+    }
+}
+
+/// Imports runners given as newline-delimited JSON, as produced by `GET /gitlab-runners/stream`,
+/// or as a `gitlab-runner` config file. Each runner is checked like one created via
+/// `POST /gitlab-runners`; runners failing the checks are reported and skipped. Runners are
+/// committed in chunks, and the config is written once all of them are imported.
+#[derive(Debug)]
+pub struct Import {
+    pub pool: atmosphere::Pool,
+    pub config_path: Arc<PathBuf>,
+    pub config_cache: Arc<ConfigCache>,
+    pub settings: Arc<Settings>,
+    pub secrets: Secrets,
+    pub format: ImportFormat,
+}
+
+impl Import {
+    /// Imports the runners read from `body` in the background and reports the progress.
+    pub fn run<S, E>(self, body: S) -> ReceiverStream<ImportProgress>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Send + Unpin + 'static,
+        E: std::fmt::Display,
+    {
+        let (tx, rx) = mpsc::channel(PROGRESS_BUFFER);
+
+        tokio::spawn(async move {
+            let mut progress = ImportProgress::default();
+            if let Err(err) = self.import(body, &mut progress, &tx).await {
+                tracing::error!(%err, "import aborted");
+                progress.error = Some(err.msg);
+            }
+
+            // whatever was committed must end up in the config
+            self.config_cache.bump();
+            let sync = GitLabRunnerConfig::write_or_queue(
+                &self.pool,
+                &self.config_path,
+                &self.config_cache,
+                &self.settings.render,
+            )
+            .await;
+            match sync {
+                Ok(sync) => progress.sync = Some(sync),
+                Err(err) => progress.error = progress.error.or(Some(err.msg)),
+            }
+
+            progress.done = true;
+            tracing::info!(
+                imported = progress.imported,
+                failed = progress.failed.len(),
+                "import finished"
+            );
+            if tx.send(progress).await.is_err() {
+                tracing::debug!("receiver of import progress dropped");
+            }
+        });
+
+        ReceiverStream::new(rx)
+    }
+
+    async fn import<S, E>(
+        &self,
+        body: S,
+        progress: &mut ImportProgress,
+        tx: &mpsc::Sender<ImportProgress>,
+    ) -> Result<(), Error>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+        E: std::fmt::Display,
+    {
+        match self.format {
+            ImportFormat::Ndjson => self.import_ndjson(body, progress, tx).await,
+            ImportFormat::Toml => self.import_toml(body, progress, tx).await,
+        }
+    }
+
+    async fn import_ndjson<S, E>(
+        &self,
+        mut body: S,
+        progress: &mut ImportProgress,
+        tx: &mpsc::Sender<ImportProgress>,
+    ) -> Result<(), Error>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+        E: std::fmt::Display,
+    {
+        let mut buffer = Vec::new();
+        let mut chunk = Vec::with_capacity(IMPORT_CHUNK_SIZE);
+        let mut line = 0;
+        let mut read = 0;
+
+        loop {
+            let bytes = next_bytes(&mut body, &mut read).await?;
+            let eof = bytes.is_none();
+            buffer.extend_from_slice(&bytes.unwrap_or_default());
+
+            // at the end of the body, the last line needs no trailing newline
+            let mut start = 0;
+            while let Some(end) = buffer[start..]
+                .iter()
+                .position(|byte| *byte == b'\n')
+                .map(|pos| start + pos)
+                .or((eof && start < buffer.len()).then_some(buffer.len()))
+            {
+                line += 1;
+                let runner = &buffer[start..end];
+                start = (end + 1).min(buffer.len());
+                if !runner.iter().all(u8::is_ascii_whitespace) {
+                    chunk.push((line, ImportItem::Json(runner.to_vec())));
+                }
+
+                if chunk.len() == IMPORT_CHUNK_SIZE {
+                    self.commit(&mut chunk, progress).await?;
+                    // a client which went away doesn't stop the import
+                    let _ = tx.send(progress.clone()).await;
+                }
+            }
+            buffer.drain(..start);
+
+            if buffer.len() > MAX_LINE_BYTES {
+                return Err(Error::bad_request(format!(
+                    "line {} exceeds {MAX_LINE_BYTES} bytes",
+                    line + 1
+                )));
+            }
+            if eof {
+                break;
+            }
+        }
+
+        if !chunk.is_empty() {
+            self.commit(&mut chunk, progress).await?;
+            let _ = tx.send(progress.clone()).await;
+        }
+
+        Ok(())
+    }
+
+    /// Imports the runners of a config file, including the paused ones listed by a TOML export.
+    /// The file can only be parsed as a whole, so it is read completely before the first runner
+    /// is imported; each entry is read with glrcfg on its own, so an invalid one fails alone.
+    async fn import_toml<S, E>(
+        &self,
+        mut body: S,
+        progress: &mut ImportProgress,
+        tx: &mpsc::Sender<ImportProgress>,
+    ) -> Result<(), Error>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+        E: std::fmt::Display,
+    {
+        let mut config_toml = Vec::new();
+        let mut read = 0;
+        while let Some(bytes) = next_bytes(&mut body, &mut read).await? {
+            config_toml.extend_from_slice(&bytes);
+        }
+        let config_toml = String::from_utf8(config_toml)
+            .map_err(|err| Error::bad_request(format!("config file is not UTF-8: {err}")))?;
+        // the parse error may quote the line it failed at, which may contain a token
+        let mut config: toml::Table = toml::from_str(&config_toml).map_err(|err| {
+            Error::bad_request(format!(
+                "invalid config file: {}",
+                redact_tokens(&err.to_string())
+            ))
+        })?;
+
+        let mut entries = |key: &str, paused: bool| -> Result<Vec<ImportItem>, Error> {
+            match config.remove(key) {
+                Some(toml::Value::Array(runners)) => Ok(runners
+                    .into_iter()
+                    .map(|runner| ImportItem::Toml { runner, paused })
+                    .collect()),
+                Some(_) => Err(Error::bad_request(format!(
+                    "invalid config file: `{key}` must be an array of tables"
+                ))),
+                None => Ok(Vec::new()),
+            }
+        };
+        let runners = entries("runners", false)?;
+        let paused = entries(PAUSED_RUNNERS_KEY, true)?;
+
+        let mut items = runners
+            .into_iter()
+            .chain(paused)
+            .enumerate()
+            .map(|(idx, runner)| (idx + 1, runner));
+        loop {
+            let mut chunk: Vec<_> = items.by_ref().take(IMPORT_CHUNK_SIZE).collect();
+            if chunk.is_empty() {
+                break;
+            }
+            self.commit(&mut chunk, progress).await?;
+            let _ = tx.send(progress.clone()).await;
+        }
+
+        Ok(())
+    }
+
+    /// Imports a chunk of runners in a single transaction. Tokens are resolved from the secret
+    /// stores before the transaction begins, so the database isn't locked while waiting for them;
+    /// each runner is stored in a savepoint of its own, so a failing one leaves nothing behind.
+    async fn commit(
+        &self,
+        chunk: &mut Vec<(usize, ImportItem)>,
+        progress: &mut ImportProgress,
+    ) -> Result<(), Error> {
+        let mut checked = Vec::with_capacity(chunk.len());
+        for (line, runner) in chunk.drain(..) {
+            progress.processed += 1;
+            checked.push((line, self.check_runner(&runner).await));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        let mut imported = 0;
+
+        for (line, runner) in checked {
+            let stored = match runner {
+                Ok(mut runner) => {
+                    let mut savepoint = tx.begin().await?;
+                    let stored = runner
+                        .apply_checked_in(&mut savepoint, Change::Created, &self.settings)
+                        .await;
+                    match stored {
+                        Ok(()) => savepoint.commit().await?,
+                        Err(_) => savepoint.rollback().await?,
+                    }
+                    stored
+                }
+                Err(err) => Err(err),
+            };
+
+            match stored {
+                Ok(()) => imported += 1,
+                Err(err) => {
+                    tracing::debug!(line, %err, "runner not imported");
+                    progress.failed.push(ImportFailure {
+                        line,
+                        error: err.msg,
+                    });
+                }
+            }
+        }
+
+        tx.commit().await?;
+        progress.imported += imported;
+        tracing::debug!(imported = progress.imported, "import chunk committed");
+
+        Ok(())
+    }
+
+    /// Checks a runner like one created via `POST /gitlab-runners`, without the database.
+    async fn check_runner(&self, runner: &ImportItem) -> Result<GitLabRunner, Error> {
+        let mut runner = match runner {
+            ImportItem::Json(runner) => serde_json::from_slice(runner)
+                .map_err(|err| Error::invalid_argument(format!("invalid runner: {err}")))?,
+            ImportItem::Toml { runner, paused } => {
+                // the error may quote the value it failed at, which may be a token
+                let config = Runner::deserialize(runner.clone()).map_err(|err| {
+                    Error::invalid_argument(format!(
+                        "invalid runner: {}",
+                        redact_tokens(&err.to_string())
+                    ))
+                })?;
+                let mut runner = GitLabRunner::from_config(config)?;
+                if *paused {
+                    runner.pause();
+                }
+                runner
+            }
+        };
+
+        runner.normalize(&self.settings)?;
+        runner.check_token(&self.secrets).await?;
+        runner.check_token_expiry(false)?;
+
+        Ok(runner)
+    }
+}
+
+/// Reads the next bytes of the request body, counting them towards [`MAX_IMPORT_BYTES`].
+async fn next_bytes<S, E>(body: &mut S, read: &mut usize) -> Result<Option<Bytes>, Error>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: std::fmt::Display,
+{
+    let bytes = body
+        .next()
+        .await
+        .transpose()
+        .map_err(|err| Error::bad_request(format!("reading request body failed: {err}")))?;
+
+    *read += bytes.as_ref().map_or(0, Bytes::len);
+    if *read > MAX_IMPORT_BYTES {
+        return Err(Error::bad_request(format!(
+            "request body exceeds {MAX_IMPORT_BYTES} bytes"
+        )));
+    }
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc};
+
+    use atmosphere::{Create as _, Pool, Read as _};
+    use bytes::Bytes;
+    use futures::StreamExt;
+    use pretty_assertions::assert_eq;
+
+    use super::{Import, ImportFormat, IMPORT_CHUNK_SIZE};
+    use crate::{
+        models::{ConfigSync, GitLabRunner},
+        settings::{Quotas, Settings},
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn import_in_chunks(pool: Pool) -> Result<()> {
+        let existing = GitLabRunner::for_testing();
+        let mut duplicate = existing.clone();
+        duplicate.create(&pool).await?;
+
+        let mut body = String::new();
+        for _ in 0..IMPORT_CHUNK_SIZE + 1 {
+            let mut runner = GitLabRunner::for_testing();
+            runner.set_url("https://gitlab.bmc-labs.com/");
+            body.push_str(&serde_json::to_string(&runner)?);
+            body.push('\n');
+        }
+        body.push_str("\n{\"not\": \"a runner\"}\n");
+        // same UUID as a runner in the database
+        body.push_str(&serde_json::to_string(&existing)?);
+
+        let path = PathBuf::from(format!(
+            "/tmp/gitlab-runner-config-{}.toml",
+            uuid::Uuid::new_v4()
+        ));
+        let import = Import {
+            pool: pool.clone(),
+            config_path: Arc::new(path.clone()),
+            config_cache: Arc::default(),
+            settings: Arc::new(Settings {
+                quotas: Quotas {
+                    max_runners: 1000,
+                    max_runners_per_instance: 1000,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }),
+            secrets: Default::default(),
+            format: ImportFormat::Ndjson,
+        };
+
+        // split the body at odd places, like a network would
+        let chunks: Vec<_> = body
+            .as_bytes()
+            .chunks(1000)
+            .map(|chunk| Ok::<_, std::io::Error>(Bytes::copy_from_slice(chunk)))
+            .collect();
+        let reports: Vec<_> = import.run(futures::stream::iter(chunks)).collect().await;
+
+        // one report per chunk of runners, and a final one
+        assert_eq!(reports.len(), 3);
+        assert_eq!(reports[0].imported, IMPORT_CHUNK_SIZE);
+        let done = reports.last().ok_or("no final report")?;
+        assert!(done.done());
+        assert_eq!(
+            (done.processed, done.imported),
+            (IMPORT_CHUNK_SIZE + 3, IMPORT_CHUNK_SIZE + 1)
+        );
+        let lines: Vec<_> = done.failed.iter().map(|failure| failure.line).collect();
+        assert_eq!(lines, vec![IMPORT_CHUNK_SIZE + 3, IMPORT_CHUNK_SIZE + 4]);
+        assert_eq!(done.sync, Some(ConfigSync::Synced));
+
+        assert_eq!(
+            GitLabRunner::read_all(&pool).await?.len(),
+            IMPORT_CHUNK_SIZE + 2
+        );
+        assert!(std::fs::read_to_string(&path)?.contains("https://gitlab.bmc-labs.com"));
+
+        std::fs::remove_file(&path)?;
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn import_config_file(pool: Pool) -> Result<()> {
+        let config_toml = r#"
+            concurrent = 4
+
+            [[runners]]
+              name = "hand-managed"
+              url = "https://gitlab.bmc-labs.com"
+              id = 23
+              token = "glrt-warblgarblwarblgarbl"
+              token_obtained_at = 2024-06-01T12:00:00Z
+              limit = 2
+              output_limit = 4096
+              executor = "docker"
+              [runners.docker]
+                image = "rust:latest"
+
+            [[runners]]
+              name = "shell"
+              url = "https://gitlab.bmc-labs.com"
+              id = 24
+              token = "glrt-shellshellshellshell"
+              executor = "shell"
+        "#;
+
+        let path = PathBuf::from(format!(
+            "/tmp/gitlab-runner-config-{}.toml",
+            uuid::Uuid::new_v4()
+        ));
+        let import = Import {
+            pool: pool.clone(),
+            config_path: Arc::new(path.clone()),
+            config_cache: Arc::default(),
+            settings: Arc::default(),
+            secrets: Default::default(),
+            format: ImportFormat::Toml,
+        };
+        let body = futures::stream::iter([Ok::<_, std::io::Error>(Bytes::from(config_toml))]);
+        let reports: Vec<_> = import.run(body).collect().await;
+
+        let done = reports.last().ok_or("no final report")?;
+        assert_eq!((done.processed, done.imported), (2, 1));
+        assert_eq!(done.failed[0].line, 2);
+
+        let runners = GitLabRunner::read_all(&pool).await?;
+        assert_eq!(runners.len(), 1);
+        assert_eq!(runners[0].id(), 23);
+        assert_eq!(runners[0].job_limit(), Some(2));
+
+        std::fs::remove_file(&path)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    str::FromStr,
+};
+
+use serde::{Deserialize, Serialize};
+use sqlx::Sqlite;
+
+use crate::error::Error;
+
+/// Free-form key-value metadata attached to a runner, e.g. `team = "payments"`. Stored as a JSON
+/// object.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Labels(BTreeMap<String, String>);
+
+impl Labels {
+    /// Checks that all keys are non-empty and free of `=` and control characters, so that every
+    /// label can be selected with a [`LabelSelector`] and rendered as `key=value`.
+    pub fn validate(&self) -> Result<(), Error> {
+        for key in self.0.keys() {
+            if key.is_empty() || key.contains('=') || key.chars().any(char::is_control) {
+                return Err(Error::invalid_argument(format!(
+                    "invalid label key '{}'; must be non-empty and must not contain '=' or \
+                     control characters",
+                    key.escape_debug()
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Sets all of the given labels, overwriting the values of existing keys.
+    pub fn merge(&mut self, labels: &Labels) {
+        self.0.extend(labels.0.clone());
+    }
+
+    /// Returns the labels with the given keys only, or all labels if no keys are given.
+    pub fn select(&self, keys: &LabelKeys) -> Self {
+        if keys.0.is_empty() {
+            return self.clone();
+        }
+
+        Self(
+            self.0
+                .iter()
+                .filter(|(key, _)| keys.0.contains(*key))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        )
+    }
+
+    /// Returns the labels formatted as `key=value`, ordered by key.
+    pub fn to_key_value_pairs(&self) -> Vec<String> {
+        self.0
+            .iter()
+            .map(|(key, value)| format!("{key}={value}"))
+            .collect()
+    }
+}
+
+impl<const N: usize> From<[(&str, &str); N]> for Labels {
+    fn from(labels: [(&str, &str); N]) -> Self {
+        Self(
+            labels
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        )
+    }
+}
+
+/// Label keys, e.g. the ones rendered into container labels; given as a comma-separated list.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct LabelKeys(BTreeSet<String>);
+
+impl FromStr for LabelKeys {
+    type Err = String;
+
+    fn from_str(keys: &str) -> Result<Self, Self::Err> {
+        Ok(Self(
+            keys.split(',')
+                .map(str::trim)
+                .filter(|key| !key.is_empty())
+                .map(str::to_string)
+                .collect(),
+        ))
+    }
+}
+
+impl sqlx::Type<Sqlite> for Labels {
+    fn type_info() -> <Sqlite as sqlx::Database>::TypeInfo {
+        <String as sqlx::Type<Sqlite>>::type_info()
+    }
+
+    fn compatible(ty: &<Sqlite as sqlx::Database>::TypeInfo) -> bool {
+        <String as sqlx::Type<Sqlite>>::compatible(ty)
+    }
+}
+
+impl<'q> sqlx::Encode<'q, Sqlite> for Labels {
+    fn encode_by_ref(
+        &self,
+        buf: &mut <Sqlite as sqlx::database::HasArguments<'q>>::ArgumentBuffer,
+    ) -> sqlx::encode::IsNull {
+        let json = serde_json::to_string(&self.0)
+            .expect("serializing a map of strings to JSON must not fail");
+        <String as sqlx::Encode<Sqlite>>::encode(json, buf)
+    }
+}
+
+impl<'r> sqlx::Decode<'r, Sqlite> for Labels {
+    fn decode(
+        value: <Sqlite as sqlx::database::HasValueRef<'r>>::ValueRef,
+    ) -> Result<Self, sqlx::error::BoxDynError> {
+        let json = <&str as sqlx::Decode<Sqlite>>::decode(value)?;
+        Ok(Self(serde_json::from_str(json)?))
+    }
+}
+
+/// Selects runners by label: `key=value` matches runners with the label `key` set to `value`,
+/// while `key` alone matches runners which have the label `key`, whatever its value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelSelector {
+    pub key: String,
+    pub value: Option<String>,
+}
+
+impl FromStr for LabelSelector {
+    type Err = Error;
+
+    fn from_str(selector: &str) -> Result<Self, Self::Err> {
+        let (key, value) = match selector.split_once('=') {
+            Some((key, value)) => (key, Some(value.to_string())),
+            None => (selector, None),
+        };
+
+        if key.is_empty() {
+            return Err(Error::invalid_argument(format!(
+                "invalid label selector '{selector}'; must look like key=value or key"
+            )));
+        }
+
+        Ok(Self {
+            key: key.to_string(),
+            value,
+        })
+    }
+}
+
+impl fmt::Display for LabelSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{}={value}", self.key),
+            None => self.key.fmt(f),
+        }
+    }
+}
+
+impl<'a> Deserialize<'a> for LabelSelector {
+    fn deserialize<D>(deserializer: D) -> Result<LabelSelector, D::Error>
+    where
+        D: serde::Deserializer<'a>,
+    {
+        let selector = String::deserialize(deserializer)?;
+        selector.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::{LabelKeys, LabelSelector, Labels};
+
+    #[test]
+    fn parse_label_selectors() {
+        assert_eq!(
+            "team=payments".parse::<LabelSelector>().ok(),
+            Some(LabelSelector {
+                key: "team".to_string(),
+                value: Some("payments".to_string())
+            })
+        );
+        assert_eq!(
+            "cost-center".parse::<LabelSelector>().ok(),
+            Some(LabelSelector {
+                key: "cost-center".to_string(),
+                value: None
+            })
+        );
+        assert_eq!(
+            "a=b=c".parse::<LabelSelector>().ok().and_then(|s| s.value),
+            Some("b=c".to_string())
+        );
+        assert!("=payments".parse::<LabelSelector>().is_err());
+    }
+
+    #[test]
+    fn validate_labels() {
+        assert!(Labels::from([("team", "payments")]).validate().is_ok());
+        assert!(Labels::from([("", "payments")]).validate().is_err());
+        assert!(Labels::from([("te=am", "payments")]).validate().is_err());
+        assert!(Labels::from([("te\nam", "payments")]).validate().is_err());
+    }
+
+    #[test]
+    fn key_value_pairs() {
+        let labels = Labels::from([("team", "payments"), ("cost-center", "42")]);
+        assert_eq!(
+            labels.to_key_value_pairs(),
+            vec!["cost-center=42".to_string(), "team=payments".to_string()]
+        );
+    }
+
+    #[test]
+    fn select_labels() -> Result<(), String> {
+        let labels = Labels::from([("team", "payments"), ("cost-center", "42"), ("os", "linux")]);
+
+        let keys: LabelKeys = "team, cost-center,,unset".parse()?;
+        assert_eq!(
+            labels.select(&keys),
+            Labels::from([("team", "payments"), ("cost-center", "42")])
+        );
+        assert_eq!(labels.select(&LabelKeys::default()), labels);
+
+        Ok(())
+    }
+}
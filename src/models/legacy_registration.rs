@@ -0,0 +1,23 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use glrcfg::runner::{RegistrationToken, Url};
+use serde::Deserialize;
+use utoipa::ToSchema;
+
+use super::RegistrationDetails;
+use crate::secrets::Credential;
+
+/// Request to register a runner with a registration token, as older GitLab instances require.
+/// runrs performs the registration with GitLab and stores the resulting runner.
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+pub struct LegacyRegistration {
+    /// GitLab instance URL
+    #[schema(value_type = String, format = Uri, example = "https://gitlab.your-company.com")]
+    pub url: Url,
+    /// Registration token of the project, group or instance the runner is registered for, or a
+    /// reference to a secret store holding it
+    #[schema(value_type = String, example = "GR1348941abcdefghij0123456789")]
+    pub registration_token: Credential<RegistrationToken>,
+    #[serde(flatten)]
+    pub details: RegistrationDetails,
+}
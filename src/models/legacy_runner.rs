@@ -0,0 +1,232 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use sqlx::{sqlite::SqliteRow, Connection as _, Decode, Row as _, Sqlite, SqliteConnection, Type};
+use uuid::Uuid;
+
+use super::{Change, GitLabRunner, SystemEvent, SystemEventKind};
+use crate::{error::Error, settings::Settings};
+
+/// Table runrs versions before the UUID schema kept their runners in, keyed by runner ID.
+const LEGACY_TABLE: &str = "runners";
+
+/// Actor of the system events recorded for migrated runners.
+const MIGRATION_ACTOR: &str = "runrs";
+
+/// A runner as stored in the legacy `runners` table. Column names changed between the legacy
+/// versions, and some columns didn't exist in all of them, so everything is optional.
+#[derive(Debug, Default)]
+pub(super) struct LegacyRunner {
+    rowid: i64,
+    pub(super) id: i64,
+    pub(super) name: Option<String>,
+    pub(super) description: Option<String>,
+    pub(super) url: Option<String>,
+    pub(super) token: Option<String>,
+    pub(super) token_obtained_at: Option<String>,
+    pub(super) token_expires_at: Option<String>,
+    pub(super) docker_image: Option<String>,
+    pub(super) privileged: bool,
+}
+
+impl LegacyRunner {
+    fn from_row(row: &SqliteRow) -> Self {
+        Self {
+            rowid: column(row, &["legacy_rowid"]).unwrap_or_default(),
+            id: column(row, &["id"]).unwrap_or_default(),
+            name: column(row, &["name", "description"]),
+            description: column(row, &["description"]),
+            url: column(row, &["url"]),
+            token: column(row, &["token"]),
+            token_obtained_at: column(row, &["token_obtained_at"]),
+            token_expires_at: column(row, &["token_expires_at"]),
+            docker_image: column(row, &["docker_image", "image"]),
+            privileged: column(row, &["privileged"]).unwrap_or_default(),
+        }
+    }
+}
+
+/// Reads the first of the given columns the row has a value of the expected type in.
+fn column<'r, T>(row: &'r SqliteRow, names: &[&str]) -> Option<T>
+where
+    T: Decode<'r, Sqlite> + Type<Sqlite>,
+{
+    names
+        .iter()
+        .find_map(|name| row.try_get::<Option<T>, _>(*name).ok().flatten())
+}
+
+/// Outcome of moving the runners of the legacy schema into `gitlab_runners`.
+#[derive(Debug, Default)]
+pub struct LegacyMigration {
+    /// UUIDs the migrated runners were given
+    pub migrated: Vec<Uuid>,
+    /// IDs of the runners which couldn't be migrated; they remain in the legacy table
+    pub skipped: Vec<i64>,
+}
+
+impl LegacyMigration {
+    /// Moves the runners from the `runners` table of runrs versions before the UUID schema into
+    /// `gitlab_runners`, recording each as a system event. Migrated runners are removed from the
+    /// legacy table, which is dropped once it's empty. Runners which can't be migrated, e.g.
+    /// because of an invalid token, are skipped with a warning and stay in the legacy table, so
+    /// the migration is retried on the next start. Returns `None` if there is no legacy table.
+    pub async fn run(pool: &atmosphere::Pool, settings: &Settings) -> Result<Option<Self>, Error> {
+        let (tables,): (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?")
+                .bind(LEGACY_TABLE)
+                .fetch_one(pool)
+                .await?;
+        if tables == 0 {
+            return Ok(None);
+        }
+
+        let mut tx = pool.begin().await?;
+        let rows = sqlx::query(&format!(
+            "SELECT rowid AS legacy_rowid, * FROM {LEGACY_TABLE} ORDER BY rowid"
+        ))
+        .fetch_all(&mut *tx)
+        .await?;
+        tracing::info!(count = rows.len(), "migrating runners from legacy schema");
+
+        let mut migration = Self::default();
+        for row in &rows {
+            let legacy = LegacyRunner::from_row(row);
+
+            // each runner in its own savepoint, so a conflicting one doesn't abort the others
+            let mut savepoint = tx.begin().await?;
+            let migrated = Self::migrate(&mut savepoint, &legacy, settings).await;
+
+            match migrated {
+                Ok(runner) => {
+                    savepoint.commit().await?;
+                    let path = format!("/gitlab-runners/{}", runner.uuid());
+                    SystemEvent::record(
+                        &mut *tx,
+                        SystemEventKind::Migrate,
+                        MIGRATION_ACTOR,
+                        &path,
+                        None,
+                    )
+                    .await?;
+                    migration.migrated.push(*runner.uuid());
+                }
+                Err(err) => {
+                    savepoint.rollback().await?;
+                    tracing::warn!(id = legacy.id, %err, "skipping legacy runner");
+                    let path = format!("/{LEGACY_TABLE}/{}", legacy.id);
+                    SystemEvent::record(
+                        &mut *tx,
+                        SystemEventKind::Migrate,
+                        MIGRATION_ACTOR,
+                        &path,
+                        Some(&err.to_string()),
+                    )
+                    .await?;
+                    migration.skipped.push(legacy.id);
+                }
+            }
+        }
+
+        if migration.skipped.is_empty() {
+            sqlx::query(&format!("DROP TABLE {LEGACY_TABLE}"))
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(Some(migration))
+    }
+
+    async fn migrate(
+        conn: &mut SqliteConnection,
+        legacy: &LegacyRunner,
+        settings: &Settings,
+    ) -> Result<GitLabRunner, Error> {
+        let mut runner = GitLabRunner::migrate(legacy)?;
+        runner.normalize(settings)?;
+        runner
+            .apply_in(&mut *conn, Change::Created, settings.events.enabled())
+            .await?;
+        sqlx::query(&format!("DELETE FROM {LEGACY_TABLE} WHERE rowid = ?"))
+            .bind(legacy.rowid)
+            .execute(conn)
+            .await?;
+
+        Ok(runner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use atmosphere::{Pool, Read as _};
+    use pretty_assertions::assert_eq;
+
+    use super::LegacyMigration;
+    use crate::{
+        models::{GitLabRunner, SystemEvent, SystemEventFilter},
+        settings::Settings,
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn migrate_legacy_runners(pool: Pool) -> Result<()> {
+        let settings = Settings::default();
+        assert!(LegacyMigration::run(&pool, &settings).await?.is_none());
+
+        sqlx::query(
+            "CREATE TABLE runners (id INTEGER PRIMARY KEY, description TEXT, url TEXT, \
+             token TEXT, token_obtained_at TEXT, image TEXT)",
+        )
+        .execute(&pool)
+        .await?;
+        sqlx::query(
+            "INSERT INTO runners VALUES \
+             (7, 'legacy', 'https://gitlab.your-company.com/', ' glrt-0123456789_abcdefXYZ ', \
+              'not a timestamp', 'alpine:3.20'), \
+             (8, 'broken', 'https://gitlab.your-company.com', 'warblgarbl', NULL, 'alpine')",
+        )
+        .execute(&pool)
+        .await?;
+
+        let migration = LegacyMigration::run(&pool, &settings)
+            .await?
+            .ok_or("legacy runners were not migrated")?;
+        assert_eq!(migration.migrated.len(), 1);
+        assert_eq!(migration.skipped, vec![8]);
+
+        let runner = GitLabRunner::find(&pool, &migration.migrated[0])
+            .await?
+            .ok_or("migrated runner not in database")?;
+        let runner = serde_json::to_value(runner)?;
+        assert_eq!(runner["id"], 7);
+        assert_eq!(runner["name"], "legacy");
+        assert_eq!(runner["description"], "legacy");
+        assert_eq!(runner["url"], "https://gitlab.your-company.com/");
+        assert_eq!(runner["token"], "glrt-0123456789_abcdefXYZ");
+        assert_eq!(runner["docker_image"], "alpine:3.20");
+
+        let (events, _) = SystemEvent::list(&pool, &SystemEventFilter::default()).await?;
+        assert_eq!(events.len(), 2);
+
+        // runners which couldn't be migrated stay in the legacy table and are retried
+        let (legacy,): (i64,) = sqlx::query_as("SELECT id FROM runners")
+            .fetch_one(&pool)
+            .await?;
+        assert_eq!(legacy, 8);
+
+        sqlx::query("UPDATE runners SET token = 'glrt-abcdefghij_0123456789' WHERE id = 8")
+            .execute(&pool)
+            .await?;
+        let migration = LegacyMigration::run(&pool, &settings)
+            .await?
+            .ok_or("legacy runners were not migrated")?;
+        assert_eq!(migration.migrated.len(), 1);
+        assert!(migration.skipped.is_empty());
+
+        // the emptied legacy table is dropped, so the migration doesn't run again
+        assert!(LegacyMigration::run(&pool, &settings).await?.is_none());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use glrcfg::runner::Url;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::error::Error;
+
+/// A GitLab instance in maintenance, e.g. during an upgrade. Until the window ends, background
+/// integrations like verification and autoscaling leave the runners of the instance alone, and
+/// report the window instead of failing.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema, sqlx::FromRow)]
+pub struct MaintenanceWindow {
+    /// URL of the GitLab instance
+    #[schema(value_type = String, example = "https://gitlab.your-company.com")]
+    url: Url,
+    /// When the maintenance ends; the window is ignored after that
+    #[schema(value_type = String, format = DateTime, example = "2024-07-07T18:00:00Z")]
+    ends_at: DateTime<Utc>,
+    /// Why the instance is in maintenance
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = "upgrade to GitLab 17.2")]
+    reason: Option<String>,
+}
+
+impl MaintenanceWindow {
+    pub fn new(url: Url, ends_at: DateTime<Utc>, reason: Option<String>) -> Self {
+        Self {
+            url,
+            ends_at,
+            reason,
+        }
+    }
+
+    /// Puts the instance in maintenance until `ends_at`, replacing a window it is in already.
+    pub async fn start(&mut self, pool: &atmosphere::Pool) -> Result<(), Error> {
+        if self.ends_at <= Utc::now() {
+            return Err(Error::invalid_argument(format!(
+                "maintenance of {} must end in the future, not at {}",
+                self.url, self.ends_at
+            )));
+        }
+        self.url = self.url.normalized();
+
+        sqlx::query(
+            "INSERT INTO maintenance_windows (url, ends_at, reason) VALUES (?, ?, ?) \
+             ON CONFLICT (url) DO UPDATE SET ends_at = excluded.ends_at, reason = excluded.reason",
+        )
+        .bind(&self.url)
+        .bind(self.ends_at)
+        .bind(&self.reason)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Ends the maintenance of the instance at `url` early, returning the window it was in.
+    pub async fn end(pool: &atmosphere::Pool, url: &Url) -> Result<Self, Error> {
+        let window: Option<Self> = sqlx::query_as(
+            "DELETE FROM maintenance_windows WHERE url = ? \
+             AND datetime(ends_at) > datetime(?) RETURNING *",
+        )
+        .bind(url.normalized())
+        .bind(Utc::now())
+        .fetch_optional(pool)
+        .await?;
+
+        window.ok_or_else(|| Error::not_found(format!("{url} is not in maintenance")))
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    pub fn ends_at(&self) -> DateTime<Utc> {
+        self.ends_at
+    }
+}
+
+/// The maintenance windows which haven't ended yet, to look up whether an instance is in one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Maintenance(BTreeMap<String, MaintenanceWindow>);
+
+impl Maintenance {
+    /// Reads the windows which haven't ended at `now`; those which ended are removed.
+    pub async fn active(pool: &atmosphere::Pool, now: DateTime<Utc>) -> Result<Self, Error> {
+        sqlx::query("DELETE FROM maintenance_windows WHERE datetime(ends_at) <= datetime(?)")
+            .bind(now)
+            .execute(pool)
+            .await?;
+
+        let windows: Vec<MaintenanceWindow> =
+            sqlx::query_as("SELECT * FROM maintenance_windows ORDER BY url")
+                .fetch_all(pool)
+                .await?;
+
+        Ok(windows.into_iter().collect())
+    }
+
+    /// Returns the window the instance at `url` is in, if it is in maintenance.
+    pub fn get(&self, url: &Url) -> Option<&MaintenanceWindow> {
+        self.0.get(url.normalized().as_str())
+    }
+
+    /// Returns all windows, ordered by URL.
+    pub fn windows(&self) -> Vec<MaintenanceWindow> {
+        self.0.values().cloned().collect()
+    }
+}
+
+impl FromIterator<MaintenanceWindow> for Maintenance {
+    fn from_iter<I: IntoIterator<Item = MaintenanceWindow>>(windows: I) -> Self {
+        Self(
+            windows
+                .into_iter()
+                .map(|window| (window.url.normalized().as_str().to_string(), window))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use atmosphere::Pool;
+    use chrono::{TimeDelta, Utc};
+    use glrcfg::runner::Url;
+    use pretty_assertions::assert_eq;
+
+    use super::{Maintenance, MaintenanceWindow};
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn start_end_expire(pool: Pool) -> Result<()> {
+        let gitlab = Url::parse("https://gitlab.your-company.com/")?;
+        let mut window = MaintenanceWindow::new(
+            Url::parse("https://GitLab.your-company.com")?,
+            Utc::now() + TimeDelta::hours(2),
+            Some("upgrade".to_string()),
+        );
+        window.start(&pool).await?;
+
+        let maintenance = Maintenance::active(&pool, Utc::now()).await?;
+        assert_eq!(maintenance.get(&gitlab), Some(&window));
+        let other = Url::parse("https://gitlab.bmc-labs.com")?;
+        assert_eq!(maintenance.get(&other), None);
+
+        // the window is over once it ended
+        let later = Utc::now() + TimeDelta::hours(3);
+        assert_eq!(
+            Maintenance::active(&pool, later).await?,
+            Maintenance::default()
+        );
+
+        window.start(&pool).await?;
+        assert_eq!(MaintenanceWindow::end(&pool, &gitlab).await?, window);
+        assert!(MaintenanceWindow::end(&pool, &gitlab).await.is_err());
+
+        // windows which ended already are rejected
+        window.ends_at = Utc::now() - TimeDelta::minutes(1);
+        assert!(window.start(&pool).await.is_err());
+
+        Ok(())
+    }
+}
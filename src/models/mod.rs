@@ -1,7 +1,71 @@
 // Copyright 2024 bmc::labs GmbH. All rights reserved.
 
+mod agent;
+mod audit_entry;
+mod bootstrap;
+mod bulk;
+mod dead_letter;
+mod deleted_runner;
+mod expiry;
 mod gitlab_runner;
 mod gitlab_runner_config;
+mod gitlab_runner_filter;
+mod gitlab_runner_patch;
+mod global_config;
+mod history;
+mod import;
+mod labels;
+mod legacy_registration;
+mod legacy_runner;
+mod maintenance;
+mod orphan_runner;
+mod os;
+mod outbox;
+mod output_limit;
+mod preset;
+mod quota_usage;
+mod read_cache;
+mod registration;
+mod schedule;
+mod session_server_config;
+mod single_row;
+mod system_event;
+mod tags;
+mod task;
+mod verification;
 
-pub use gitlab_runner::GitLabRunner;
-pub use gitlab_runner_config::GitLabRunnerConfig;
+pub use agent::{Agent, AgentRegistration};
+pub use audit_entry::{AuditEntry, AuditExport, AuditLogFilter, AuditPruner};
+pub use bootstrap::Bootstrap;
+pub use bulk::{BulkResult, MAX_BULK_RUNNERS};
+pub use dead_letter::DeadLetter;
+pub use deleted_runner::{DeletedRunner, RecycleBinPurger};
+pub use expiry::{ExpiryNotice, ExpiryReaper};
+pub use gitlab_runner::{GitLabRunner, Lint};
+pub use gitlab_runner_config::{
+    ConfigCache, ConfigSync, ConfigSyncStatus, ConfigTarget, ConfigWriteRetry, GitLabRunnerConfig,
+    CONFIG_WRITE_TASK,
+};
+pub use gitlab_runner_filter::{GitLabRunnerFilter, Pagination};
+pub use gitlab_runner_patch::GitLabRunnerPatch;
+pub use global_config::GlobalConfig;
+pub use history::{ConfigRevision, HistoryPruner, RunnerHistory};
+pub use import::{Import, ImportFailure, ImportFormat, ImportProgress};
+pub use labels::{LabelKeys, LabelSelector, Labels};
+pub use legacy_registration::LegacyRegistration;
+pub use legacy_runner::LegacyMigration;
+pub use maintenance::{Maintenance, MaintenanceWindow};
+pub use orphan_runner::{Adoption, OrphanRunner};
+pub use os::Os;
+pub use outbox::{Change, ChangedRunner, OutboxDispatcher, OutboxEvent};
+pub use output_limit::OutputLimit;
+pub use preset::{Preset, PresetConfig};
+pub use quota_usage::{InstanceUsage, QuotaUsage, Usage};
+pub use read_cache::{CachedResponse, ReadCache};
+pub use registration::{Registration, RegistrationDetails, RunnerRegistration};
+pub use schedule::{Schedule, ScheduleEnforcer};
+pub use session_server_config::SessionServerConfig;
+pub use system_event::{SystemEvent, SystemEventFilter, SystemEventKind};
+pub use tags::Tags;
+pub use task::Task;
+pub use verification::{RunnerVerification, VerificationReport, VerificationStatus};
@@ -0,0 +1,292 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{collections::HashSet, path::Path};
+
+use glrcfg::runner::{redact_tokens, Url};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{Change, GitLabRunner};
+use crate::{error::Error, secrets::SecretRef, settings::Settings};
+
+/// The parts of the config file on disk needed to identify and adopt runners. Everything else in
+/// the file is ignored, so that files written by `gitlab-runner` itself can be read as well.
+#[derive(Debug, Default, Deserialize)]
+struct OnDiskConfig {
+    #[serde(default)]
+    runners: Vec<OnDiskRunner>,
+}
+
+/// A `[[runners]]` entry of the config file on disk.
+#[derive(Debug, Clone, Deserialize)]
+pub(super) struct OnDiskRunner {
+    #[serde(default)]
+    pub id: u32,
+    #[serde(default)]
+    pub name: String,
+    pub url: String,
+    pub token: String,
+    /// `gitlab-runner` writes TOML datetimes, runrs writes strings
+    pub token_obtained_at: Option<toml::Value>,
+    pub token_expires_at: Option<toml::Value>,
+    #[serde(default)]
+    pub limit: u32,
+    pub output_limit: Option<u32>,
+    #[serde(default)]
+    pub executor: String,
+    pub docker: Option<OnDiskDocker>,
+}
+
+/// Reads the `[[runners]]` entries of a config file.
+pub(super) fn parse_runners(config_toml: &str) -> Result<Vec<OnDiskRunner>, toml::de::Error> {
+    toml::from_str::<OnDiskConfig>(config_toml).map(|config| config.runners)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(super) struct OnDiskDocker {
+    pub image: Option<String>,
+    #[serde(default)]
+    pub privileged: bool,
+}
+
+/// Returns a TOML datetime or string as string.
+pub(super) fn timestamp(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::Datetime(datetime) => Some(datetime.to_string()),
+        toml::Value::String(string) => Some(string.clone()),
+        _ => None,
+    }
+}
+
+/// A runner in the config file on disk which is not in the database. Since the config file is
+/// rendered from the database, orphans are removed on the next write unless they are adopted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct OrphanRunner {
+    /// ID of the runner within the GitLab instance
+    #[schema(example = 42)]
+    id: u32,
+    #[schema(example = "usain-bolt")]
+    name: String,
+    /// GitLab instance URL
+    #[schema(example = "https://gitlab.your-company.com")]
+    url: String,
+    /// Runner token, masked
+    #[schema(example = "glrt-****XYZ")]
+    token: String,
+    #[schema(example = "docker")]
+    executor: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = "alpine:latest")]
+    docker_image: Option<String>,
+}
+
+impl From<&OnDiskRunner> for OrphanRunner {
+    fn from(runner: &OnDiskRunner) -> Self {
+        Self {
+            id: runner.id,
+            name: runner.name.clone(),
+            url: runner.url.clone(),
+            token: redact_tokens(&runner.token).into_owned(),
+            executor: runner.executor.clone(),
+            docker_image: runner
+                .docker
+                .as_ref()
+                .and_then(|docker| docker.image.clone()),
+        }
+    }
+}
+
+/// Result of adopting the orphaned runners.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Adoption {
+    /// Runners now stored in the database
+    adopted: Vec<GitLabRunner>,
+    /// Orphans which can't be managed by runrs, e.g. because they don't use the Docker executor
+    skipped: Vec<OrphanRunner>,
+}
+
+impl OrphanRunner {
+    /// Finds the runners in the config file at `path` which are not in the database. A missing
+    /// config file contains no orphans.
+    pub async fn find(pool: &atmosphere::Pool, path: &Path) -> Result<Vec<Self>, Error> {
+        Ok(orphans(pool, path).await?.iter().map(Self::from).collect())
+    }
+
+    /// Imports the orphans into the database, so they are kept in the config file. They are
+    /// stored in one transaction, each checked against the quotas like a created runner; orphans
+    /// which can't be stored are skipped.
+    pub async fn adopt(
+        pool: &atmosphere::Pool,
+        path: &Path,
+        settings: &Settings,
+    ) -> Result<Adoption, Error> {
+        let mut adoption = Adoption {
+            adopted: Vec::new(),
+            skipped: Vec::new(),
+        };
+
+        let orphans = orphans(pool, path).await?;
+        let mut tx = pool.begin().await?;
+
+        for orphan in orphans {
+            let adopted = GitLabRunner::adopt(&orphan).and_then(|mut runner| {
+                runner.normalize(settings)?;
+                Ok(runner)
+            });
+            let mut runner = match adopted {
+                Ok(runner) => runner,
+                Err(err) => {
+                    tracing::warn!(%err, name = orphan.name, "can't adopt orphaned runner");
+                    adoption.skipped.push(Self::from(&orphan));
+                    continue;
+                }
+            };
+
+            let mut savepoint = tx.begin().await?;
+            match runner
+                .apply_checked_in(&mut savepoint, Change::Created, settings)
+                .await
+            {
+                Ok(()) => {
+                    savepoint.commit().await?;
+                    adoption.adopted.push(runner);
+                }
+                Err(err) => {
+                    savepoint.rollback().await?;
+                    tracing::warn!(%err, name = orphan.name, "can't adopt orphaned runner");
+                    adoption.skipped.push(Self::from(&orphan));
+                }
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(adoption)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+async fn orphans(pool: &atmosphere::Pool, path: &Path) -> Result<Vec<OnDiskRunner>, Error> {
+    let config_toml = match std::fs::read_to_string(path) {
+        Ok(config_toml) => config_toml,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(Error::internal_error(err)),
+    };
+    let runners = parse_runners(&config_toml).map_err(|err| {
+        Error::internal_error(format!("config file {} is invalid: {err}", path.display()))
+    })?;
+
+    // runners whose token is a secret reference are written with the resolved token, so they are
+    // recognized by URL and name instead; the description is written as name if there is one
+    let mut tokens = HashSet::new();
+    let mut secret_refs = HashSet::new();
+    let rows: Vec<(String, String, String)> =
+        sqlx::query_as("SELECT token, url, COALESCE(description, name) FROM gitlab_runners")
+            .fetch_all(pool)
+            .await?;
+    for (token, url, name) in rows {
+        if SecretRef::is_secret_ref(&token) {
+            secret_refs.insert((normalized_url(&url), name));
+        } else {
+            tokens.insert(token);
+        }
+    }
+
+    Ok(runners
+        .into_iter()
+        .filter(|runner| {
+            !tokens.contains(&runner.token)
+                && !secret_refs.contains(&(normalized_url(&runner.url), runner.name.clone()))
+        })
+        .collect())
+}
+
+/// Normalizes a URL found in the config file or the database for comparison; URLs which don't
+/// parse are compared as they are.
+fn normalized_url(url: &str) -> String {
+    Url::parse(url).map_or_else(|_| url.to_string(), |url| url.normalized().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use atmosphere::{Create as _, Pool};
+    use pretty_assertions::assert_eq;
+
+    use super::OrphanRunner;
+    use crate::{
+        models::{ConfigCache, GitLabRunner, GitLabRunnerConfig},
+        settings::Settings,
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    // as written by `gitlab-runner register`
+    const ORPHANS_TOML: &str = r#"
+        [[runners]]
+          name = "adoptee"
+          url = "https://gitlab.bmc-labs.com"
+          id = 23
+          token = "glrt-warblgarblwarblgarbl"
+          token_obtained_at = 2024-06-01T12:00:00Z
+          token_expires_at = 0001-01-01T00:00:00Z
+          executor = "docker"
+          [runners.docker]
+            image = "rust:latest"
+            privileged = true
+
+        [[runners]]
+          name = "shell"
+          url = "https://gitlab.bmc-labs.com"
+          id = 24
+          token = "glrt-shellshellshellshell"
+          executor = "shell"
+    "#;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn find_and_adopt(pool: Pool) -> Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("runrs-orphans-{}.toml", uuid::Uuid::new_v4()));
+        assert!(OrphanRunner::find(&pool, &path).await?.is_empty());
+
+        // runners written by runrs are not orphans
+        let mut runner = GitLabRunner::for_testing();
+        runner.create(&pool).await?;
+        let cache = ConfigCache::default();
+        GitLabRunnerConfig::write(&pool, &path, &cache, &Default::default()).await?;
+        assert!(OrphanRunner::find(&pool, &path).await?.is_empty());
+
+        let config_toml = std::fs::read_to_string(&path)?;
+        std::fs::write(&path, format!("{config_toml}\n{ORPHANS_TOML}"))?;
+
+        let orphans = OrphanRunner::find(&pool, &path).await?;
+        assert_eq!(orphans.len(), 2);
+        assert_eq!(orphans[0].name(), "adoptee");
+        assert_eq!(orphans[0].token, "glrt-****rbl");
+
+        // adopted runners count towards the quotas
+        let mut settings = Settings::default();
+        settings.quotas.max_privileged = 0;
+        let adoption = OrphanRunner::adopt(&pool, &path, &settings).await?;
+        assert!(adoption.adopted.is_empty());
+        assert_eq!(adoption.skipped, orphans);
+
+        let adoption = OrphanRunner::adopt(&pool, &path, &Settings::default()).await?;
+        assert_eq!(adoption.adopted.len(), 1);
+        assert_eq!(adoption.skipped, vec![orphans[1].clone()]);
+
+        let orphans = OrphanRunner::find(&pool, &path).await?;
+        assert_eq!(orphans.len(), 1);
+        assert_eq!(orphans[0].name(), "shell");
+
+        std::fs::remove_file(&path)?;
+
+        Ok(())
+    }
+}
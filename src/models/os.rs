@@ -0,0 +1,84 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use sqlx::Sqlite;
+use utoipa::ToSchema;
+
+use crate::error::Error;
+
+/// Operating system of the host a runner executes jobs on. Determines the executor and the
+/// defaults `gitlab-runner` can't infer on its own, like the shell and the helper image.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Os {
+    #[default]
+    Linux,
+    Windows,
+}
+
+impl Os {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Linux => "linux",
+            Self::Windows => "windows",
+        }
+    }
+
+    /// Name of the executor runners on this OS use, as in the `gitlab-runner` config.
+    pub fn executor(&self) -> &'static str {
+        match self {
+            Self::Linux => "docker",
+            Self::Windows => "docker-windows",
+        }
+    }
+}
+
+impl fmt::Display for Os {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+impl FromStr for Os {
+    type Err = Error;
+
+    fn from_str(os: &str) -> Result<Self, Self::Err> {
+        match os {
+            "linux" => Ok(Self::Linux),
+            "windows" => Ok(Self::Windows),
+            _ => Err(Error::invalid_argument(format!(
+                "invalid OS '{os}'; must be one of linux, windows"
+            ))),
+        }
+    }
+}
+
+impl sqlx::Type<Sqlite> for Os {
+    fn type_info() -> <Sqlite as sqlx::Database>::TypeInfo {
+        <String as sqlx::Type<Sqlite>>::type_info()
+    }
+
+    fn compatible(ty: &<Sqlite as sqlx::Database>::TypeInfo) -> bool {
+        <String as sqlx::Type<Sqlite>>::compatible(ty)
+    }
+}
+
+impl<'q> sqlx::Encode<'q, Sqlite> for Os {
+    fn encode_by_ref(
+        &self,
+        buf: &mut <Sqlite as sqlx::database::HasArguments<'q>>::ArgumentBuffer,
+    ) -> sqlx::encode::IsNull {
+        <&str as sqlx::Encode<Sqlite>>::encode(self.as_str(), buf)
+    }
+}
+
+impl<'r> sqlx::Decode<'r, Sqlite> for Os {
+    fn decode(
+        value: <Sqlite as sqlx::database::HasValueRef<'r>>::ValueRef,
+    ) -> Result<Self, sqlx::error::BoxDynError> {
+        let os = <&str as sqlx::Decode<Sqlite>>::decode(value)?;
+        Ok(os.parse()?)
+    }
+}
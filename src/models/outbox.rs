@@ -0,0 +1,394 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use futures::{future::BoxFuture, FutureExt};
+use serde::Serialize;
+use sqlx::SqliteConnection;
+use uuid::Uuid;
+
+use super::{task::backoff, DeadLetter};
+use crate::{
+    error::Error,
+    settings::SettingsStore,
+    subsystems::{Shutdown, Subsystem},
+    webhooks::{Event, WebhookClient},
+};
+
+/// How often the dispatcher looks for events to deliver.
+const DISPATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum number of events delivered per run of the dispatcher.
+const DISPATCH_BATCH_SIZE: i64 = 100;
+
+/// Kind of change made to a runner.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl Change {
+    pub fn event(self, runner: &ChangedRunner) -> Event<'_> {
+        match self {
+            Self::Created => Event::RunnerCreated(runner),
+            Self::Updated => Event::RunnerUpdated(runner),
+            Self::Deleted => Event::RunnerDeleted(runner),
+        }
+    }
+}
+
+/// Tells webhook receivers which runner was changed. Doesn't contain the runner token, since the
+/// receiver has no business knowing it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, sqlx::FromRow)]
+pub struct ChangedRunner {
+    pub(super) uuid: Uuid,
+    pub(super) id: u32,
+    pub(super) name: String,
+    pub(super) url: String,
+}
+
+/// An event waiting to be delivered to the events webhook. Events are queued in the transaction
+/// which makes the change they describe, so that they are neither lost if runrs crashes after the
+/// commit, nor sent for changes which were rolled back. Delivery is at least once; receivers
+/// recognize duplicates by the `dedup_key`, which is sent along with the event and in the
+/// `Idempotency-Key` header. Events which exhaust their retries are moved to the dead letters.
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct OutboxEvent {
+    id: i64,
+    pub(super) dedup_key: String,
+    /// The event as JSON
+    pub(super) payload: String,
+    /// Number of failed delivery attempts so far
+    pub(super) attempts: u32,
+    /// Reason the last delivery attempt failed
+    pub(super) last_error: Option<String>,
+    pub(super) created_at: DateTime<Utc>,
+    next_attempt_at: DateTime<Utc>,
+}
+
+impl OutboxEvent {
+    /// Queues the event for delivery. `conn` is meant to be the transaction making the change.
+    pub async fn enqueue(conn: &mut SqliteConnection, event: &Event<'_>) -> Result<(), Error> {
+        let payload = serde_json::to_string(event).map_err(Error::internal_error)?;
+        let now = Utc::now();
+
+        sqlx::query(
+            "INSERT INTO outbox (dedup_key, payload, attempts, created_at, next_attempt_at) \
+             VALUES (?, ?, 0, ?, ?)",
+        )
+        .bind(Uuid::new_v4().to_string())
+        .bind(payload)
+        .bind(now)
+        .bind(now)
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Queues a dead event again, keeping its `dedup_key`; it is delivered after the events which
+    /// are queued already.
+    pub(super) async fn requeue(
+        conn: &mut SqliteConnection,
+        dedup_key: &str,
+        payload: &str,
+        created_at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO outbox (dedup_key, payload, attempts, created_at, next_attempt_at) \
+             VALUES (?, ?, 0, ?, ?)",
+        )
+        .bind(dedup_key)
+        .bind(payload)
+        .bind(created_at)
+        .bind(Utc::now())
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns the oldest undelivered events, in the order they were queued.
+    pub async fn pending(pool: &atmosphere::Pool, limit: i64) -> Result<Vec<Self>, Error> {
+        Ok(sqlx::query_as("SELECT * FROM outbox ORDER BY id LIMIT ?")
+            .bind(limit)
+            .fetch_all(pool)
+            .await?)
+    }
+
+    /// Removes the event from the outbox once the receiver accepted it.
+    pub async fn delivered(&self, pool: &atmosphere::Pool) -> Result<(), Error> {
+        sqlx::query("DELETE FROM outbox WHERE id = ?")
+            .bind(self.id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Records a failed delivery attempt and schedules the next one with exponential backoff.
+    pub async fn record_failure(
+        &mut self,
+        pool: &atmosphere::Pool,
+        error: &str,
+    ) -> Result<(), Error> {
+        self.attempts += 1;
+        self.last_error = Some(error.to_string());
+        self.next_attempt_at = Utc::now() + backoff(self.attempts);
+
+        sqlx::query(
+            "UPDATE outbox SET attempts = ?, last_error = ?, next_attempt_at = ? WHERE id = ?",
+        )
+        .bind(self.attempts)
+        .bind(&self.last_error)
+        .bind(self.next_attempt_at)
+        .bind(self.id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Moves the event from the outbox to the dead letters, once it exhausted its retries.
+    pub async fn bury(&self, pool: &atmosphere::Pool) -> Result<(), Error> {
+        let mut tx = pool.begin().await?;
+        DeadLetter::record(&mut *tx, self).await?;
+        sqlx::query("DELETE FROM outbox WHERE id = ?")
+            .bind(self.id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Returns `true` if the next delivery attempt is due.
+    pub fn is_due(&self) -> bool {
+        self.next_attempt_at <= Utc::now()
+    }
+
+    /// Returns the event as sent to the receiver, i.e. with its `dedup_key`.
+    fn body(&self) -> Result<serde_json::Value, Error> {
+        let mut body: serde_json::Value =
+            serde_json::from_str(&self.payload).map_err(Error::internal_error)?;
+        body["dedup_key"] = self.dedup_key.clone().into();
+
+        Ok(body)
+    }
+}
+
+/// Delivers the events in the outbox to the events webhook, in the order they were queued.
+#[derive(Debug, Clone)]
+pub struct OutboxDispatcher {
+    pool: atmosphere::Pool,
+    settings: Arc<SettingsStore>,
+    webhooks: WebhookClient,
+}
+
+impl OutboxDispatcher {
+    pub fn new(pool: atmosphere::Pool, settings: Arc<SettingsStore>) -> Self {
+        Self {
+            pool,
+            settings,
+            webhooks: WebhookClient::default(),
+        }
+    }
+
+    async fn dispatch(&self) -> Result<(), Error> {
+        let settings = self.settings.load();
+        let max_attempts = settings.events.max_attempts;
+        let Some(webhook_url) = &settings.events.webhook_url else {
+            return Ok(());
+        };
+
+        for mut event in OutboxEvent::pending(&self.pool, DISPATCH_BATCH_SIZE).await? {
+            // later events wait for earlier ones, so the receiver sees changes in order
+            if !event.is_due() {
+                break;
+            }
+
+            let delivery = self
+                .webhooks
+                .post_idempotent(webhook_url, &event.dedup_key, &event.body()?)
+                .await;
+            match delivery {
+                // if runrs stops before this, the event is delivered again with the same key
+                Ok(()) => event.delivered(&self.pool).await?,
+                Err(err) => {
+                    tracing::warn!(%err, dedup_key = event.dedup_key, "delivering event failed");
+                    event.record_failure(&self.pool, &err.to_string()).await?;
+                    if max_attempts == 0 || event.attempts < max_attempts {
+                        break;
+                    }
+
+                    // a dead event no longer holds up the ones after it
+                    tracing::error!(
+                        attempts = event.attempts,
+                        dedup_key = event.dedup_key,
+                        "giving up on event, moving it to the dead letters"
+                    );
+                    event.bury(&self.pool).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Subsystem for OutboxDispatcher {
+    fn name(&self) -> &'static str {
+        "outbox-dispatcher"
+    }
+
+    fn run(&self, mut shutdown: Shutdown) -> BoxFuture<'static, Result<(), Error>> {
+        let this = self.clone();
+
+        async move {
+            let mut interval = tokio::time::interval(DISPATCH_INTERVAL);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => this.dispatch().await?,
+                    _ = shutdown.requested() => return Ok(()),
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use atmosphere::{Pool, Read as _};
+    use axum::{extract::State, http::HeaderMap, routing::post, Json, Router};
+    use pretty_assertions::assert_eq;
+
+    use super::{Change, OutboxDispatcher, OutboxEvent};
+    use crate::{
+        models::{DeadLetter, GitLabRunner},
+        settings::{Events, Settings, SettingsStore},
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    type Received = Arc<Mutex<Vec<(String, serde_json::Value)>>>;
+
+    fn dispatcher(pool: Pool, webhook_url: &str, max_attempts: u32) -> OutboxDispatcher {
+        let settings = SettingsStore::new(Settings {
+            events: Events {
+                webhook_url: Some(webhook_url.to_string()),
+                max_attempts,
+            },
+            ..Default::default()
+        });
+
+        OutboxDispatcher::new(pool, Arc::new(settings))
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn changes_are_queued_with_the_change(pool: Pool) -> Result<()> {
+        let mut runner = GitLabRunner::for_testing();
+        runner.apply(&pool, Change::Created, true).await?;
+        runner.apply(&pool, Change::Deleted, true).await?;
+        assert_eq!(GitLabRunner::find(&pool, runner.uuid()).await?, None);
+
+        let events = OutboxEvent::pending(&pool, 10).await?;
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].body()?["event"], "runner_created");
+        assert_eq!(events[1].body()?["event"], "runner_deleted");
+        assert_ne!(events[0].dedup_key, events[1].dedup_key);
+        assert!(!events[0].payload.contains("glrt-"));
+
+        // a change which fails doesn't queue an event
+        let mut duplicate = GitLabRunner::for_testing();
+        duplicate.apply(&pool, Change::Created, true).await?;
+        assert!(duplicate.apply(&pool, Change::Created, true).await.is_err());
+        assert_eq!(OutboxEvent::pending(&pool, 10).await?.len(), 3);
+
+        // without a receiver, no events are queued
+        duplicate.apply(&pool, Change::Deleted, false).await?;
+        assert_eq!(OutboxEvent::pending(&pool, 10).await?.len(), 3);
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn deliver_at_least_once_in_order(pool: Pool) -> Result<()> {
+        let received = Received::default();
+        let receiver = Router::new()
+            .route(
+                "/",
+                post(
+                    |State(received): State<Received>,
+                     headers: HeaderMap,
+                     Json(event): Json<serde_json::Value>| async move {
+                        let key = headers["idempotency-key"].to_str().unwrap_or_default();
+                        received
+                            .lock()
+                            .expect("lock is not poisoned")
+                            .push((key.to_string(), event));
+                    },
+                ),
+            )
+            .with_state(received.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let webhook_url = format!("http://{}/", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, receiver).await });
+
+        let mut runner = GitLabRunner::for_testing();
+        runner.apply(&pool, Change::Created, true).await?;
+        runner.apply(&pool, Change::Updated, true).await?;
+
+        // an unreachable receiver keeps the events queued and delays the next attempt
+        dispatcher(pool.clone(), "http://127.0.0.1:1/", 0)
+            .dispatch()
+            .await?;
+        let events = OutboxEvent::pending(&pool, 10).await?;
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].attempts, 1);
+        assert!(!events[0].is_due());
+        assert_eq!(events[1].attempts, 0);
+
+        sqlx::query("UPDATE outbox SET next_attempt_at = created_at")
+            .execute(&pool)
+            .await?;
+        dispatcher(pool.clone(), &webhook_url, 0).dispatch().await?;
+        assert!(OutboxEvent::pending(&pool, 10).await?.is_empty());
+
+        let received = received.lock().expect("lock is not poisoned").clone();
+        assert_eq!(received.len(), 2);
+        assert_eq!(received[0].1["event"], "runner_created");
+        assert_eq!(received[1].1["event"], "runner_updated");
+        assert_eq!(received[0].1["uuid"], runner.uuid().to_string());
+        assert_eq!(received[0].0, events[0].dedup_key);
+        assert_eq!(received[0].1["dedup_key"], events[0].dedup_key);
+        assert!(received[0].1.get("token").is_none());
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn bury_after_max_attempts(pool: Pool) -> Result<()> {
+        let mut runner = GitLabRunner::for_testing();
+        runner.apply(&pool, Change::Created, true).await?;
+        runner.apply(&pool, Change::Updated, true).await?;
+
+        // both events give up on their only attempt, rather than the first blocking the second
+        dispatcher(pool.clone(), "http://127.0.0.1:1/", 1)
+            .dispatch()
+            .await?;
+        assert!(OutboxEvent::pending(&pool, 10).await?.is_empty());
+
+        let dead = DeadLetter::list(&pool).await?;
+        assert_eq!(dead.len(), 2);
+        assert_eq!(dead[1].kind(), "event");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use sqlx::Sqlite;
+use utoipa::ToSchema;
+
+/// Default maximum job log size in KiB, the same as `gitlab-runner` uses.
+pub const DEFAULT_OUTPUT_LIMIT: u32 = 4096;
+
+/// Largest accepted maximum job log size in KiB, i.e. 1 GiB; jobs beyond that belong in artifacts.
+pub const MAX_OUTPUT_LIMIT: u32 = 1024 * 1024;
+
+/// Maximum size of a job log in KiB, i.e. the `output_limit` of a `gitlab-runner` runner; between
+/// 1 KiB and 1 GiB. GitLab cuts off the log of jobs exceeding it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(try_from = "u32", into = "u32")]
+#[schema(value_type = u32, example = 16384)]
+pub struct OutputLimit(u32);
+
+impl OutputLimit {
+    /// Returns the limit in KiB.
+    pub fn as_kib(&self) -> u32 {
+        self.0
+    }
+}
+
+impl Default for OutputLimit {
+    fn default() -> Self {
+        Self(DEFAULT_OUTPUT_LIMIT)
+    }
+}
+
+impl TryFrom<u32> for OutputLimit {
+    type Error = String;
+
+    fn try_from(kib: u32) -> Result<Self, Self::Error> {
+        if kib == 0 || kib > MAX_OUTPUT_LIMIT {
+            return Err(format!(
+                "invalid output limit {kib}; must be between 1 and {MAX_OUTPUT_LIMIT} KiB"
+            ));
+        }
+
+        Ok(Self(kib))
+    }
+}
+
+impl From<OutputLimit> for u32 {
+    fn from(limit: OutputLimit) -> Self {
+        limit.0
+    }
+}
+
+impl fmt::Display for OutputLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for OutputLimit {
+    type Err = String;
+
+    fn from_str(kib: &str) -> Result<Self, Self::Err> {
+        kib.parse::<u32>()
+            .map_err(|err| format!("invalid output limit '{kib}': {err}"))?
+            .try_into()
+    }
+}
+
+impl sqlx::Type<Sqlite> for OutputLimit {
+    fn type_info() -> <Sqlite as sqlx::Database>::TypeInfo {
+        <u32 as sqlx::Type<Sqlite>>::type_info()
+    }
+
+    fn compatible(ty: &<Sqlite as sqlx::Database>::TypeInfo) -> bool {
+        <u32 as sqlx::Type<Sqlite>>::compatible(ty)
+    }
+}
+
+impl<'q> sqlx::Encode<'q, Sqlite> for OutputLimit {
+    fn encode_by_ref(
+        &self,
+        buf: &mut <Sqlite as sqlx::database::HasArguments<'q>>::ArgumentBuffer,
+    ) -> sqlx::encode::IsNull {
+        <u32 as sqlx::Encode<Sqlite>>::encode(self.0, buf)
+    }
+}
+
+impl<'r> sqlx::Decode<'r, Sqlite> for OutputLimit {
+    fn decode(
+        value: <Sqlite as sqlx::database::HasValueRef<'r>>::ValueRef,
+    ) -> Result<Self, sqlx::error::BoxDynError> {
+        let kib = <u32 as sqlx::Decode<Sqlite>>::decode(value)?;
+        Ok(Self::try_from(kib)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::{OutputLimit, MAX_OUTPUT_LIMIT};
+
+    #[test]
+    fn bounds() {
+        assert_eq!(
+            "16384".parse::<OutputLimit>().map(|l| l.as_kib()),
+            Ok(16384)
+        );
+        assert!("0".parse::<OutputLimit>().is_err());
+        assert!((MAX_OUTPUT_LIMIT + 1)
+            .to_string()
+            .parse::<OutputLimit>()
+            .is_err());
+        assert!("lots".parse::<OutputLimit>().is_err());
+
+        assert!(serde_json::from_str::<OutputLimit>("4096").is_ok());
+        assert!(serde_json::from_str::<OutputLimit>("0").is_err());
+    }
+}
@@ -0,0 +1,150 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{fmt, str::FromStr};
+
+use glrcfg::runner::ImageReference;
+use serde::{Deserialize, Serialize};
+use sqlx::Sqlite;
+use utoipa::ToSchema;
+
+use crate::error::Error;
+
+/// Image of the Docker daemon service for presets building images with Docker.
+const DIND_SERVICE: &str = "docker:27-dind";
+
+/// Built-in preset for a common CI/CD scenario, filling in the Docker settings the scenario
+/// needs. Runners created with a preset keep it, so their config is rendered with these settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    /// Docker-in-Docker with TLS, for jobs using the `docker` CLI
+    Dind,
+    /// Image builds with kaniko, which doesn't need a Docker daemon or privileged containers
+    Kaniko,
+    /// Docker-in-Docker with BuildKit, for jobs using `docker buildx`
+    Buildx,
+}
+
+/// The Docker settings a preset expands to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct PresetConfig {
+    #[schema(example = "dind")]
+    pub preset: Preset,
+    /// Whether the Docker containers run in privileged mode
+    pub privileged: bool,
+    /// Volumes mounted into the job containers
+    #[schema(example = json!(["/cache", "/certs/client"]))]
+    pub volumes: Vec<String>,
+    /// Images of the services started next to each job
+    #[schema(value_type = Vec<String>, example = json!(["docker:27-dind"]))]
+    pub services: Vec<ImageReference>,
+    /// Environment variables set for each job
+    #[schema(example = json!(["DOCKER_HOST=tcp://docker:2376"]))]
+    pub environment: Vec<String>,
+}
+
+impl Preset {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Dind => "dind",
+            Self::Kaniko => "kaniko",
+            Self::Buildx => "buildx",
+        }
+    }
+
+    /// Returns the Docker settings of the preset.
+    pub fn config(&self) -> PresetConfig {
+        // the service is reachable as `docker`, its TLS client certificates are shared with the
+        // job through the `/certs/client` volume
+        let dind_environment = [
+            "DOCKER_HOST=tcp://docker:2376",
+            "DOCKER_TLS_CERTDIR=/certs",
+            "DOCKER_TLS_VERIFY=1",
+            "DOCKER_CERT_PATH=/certs/client",
+        ];
+
+        let (privileged, volumes, services, environment) = match self {
+            Self::Dind => (
+                true,
+                vec!["/cache", "/certs/client"],
+                vec![DIND_SERVICE],
+                dind_environment.to_vec(),
+            ),
+            Self::Kaniko => (
+                false,
+                vec!["/cache"],
+                Vec::new(),
+                vec!["DOCKER_CONFIG=/kaniko/.docker"],
+            ),
+            Self::Buildx => (
+                true,
+                vec!["/cache", "/certs/client"],
+                vec![DIND_SERVICE],
+                [dind_environment.as_slice(), &["DOCKER_BUILDKIT=1"]].concat(),
+            ),
+        };
+
+        let owned = |strs: Vec<&str>| strs.into_iter().map(str::to_string).collect();
+        PresetConfig {
+            preset: *self,
+            privileged,
+            volumes: owned(volumes),
+            services: services
+                .into_iter()
+                .map(|image| {
+                    ImageReference::parse(image).expect("given string is a valid image reference")
+                })
+                .collect(),
+            environment: owned(environment),
+        }
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+impl FromStr for Preset {
+    type Err = Error;
+
+    fn from_str(preset: &str) -> Result<Self, Self::Err> {
+        match preset {
+            "dind" => Ok(Self::Dind),
+            "kaniko" => Ok(Self::Kaniko),
+            "buildx" => Ok(Self::Buildx),
+            _ => Err(Error::invalid_argument(format!(
+                "invalid preset '{preset}'; must be one of dind, kaniko, buildx"
+            ))),
+        }
+    }
+}
+
+impl sqlx::Type<Sqlite> for Preset {
+    fn type_info() -> <Sqlite as sqlx::Database>::TypeInfo {
+        <String as sqlx::Type<Sqlite>>::type_info()
+    }
+
+    fn compatible(ty: &<Sqlite as sqlx::Database>::TypeInfo) -> bool {
+        <String as sqlx::Type<Sqlite>>::compatible(ty)
+    }
+}
+
+impl<'q> sqlx::Encode<'q, Sqlite> for Preset {
+    fn encode_by_ref(
+        &self,
+        buf: &mut <Sqlite as sqlx::database::HasArguments<'q>>::ArgumentBuffer,
+    ) -> sqlx::encode::IsNull {
+        <&str as sqlx::Encode<Sqlite>>::encode(self.as_str(), buf)
+    }
+}
+
+impl<'r> sqlx::Decode<'r, Sqlite> for Preset {
+    fn decode(
+        value: <Sqlite as sqlx::database::HasValueRef<'r>>::ValueRef,
+    ) -> Result<Self, sqlx::error::BoxDynError> {
+        let preset = <&str as sqlx::Decode<Sqlite>>::decode(value)?;
+        Ok(preset.parse()?)
+    }
+}
@@ -0,0 +1,109 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{error::Error, settings::Quotas};
+
+/// Number of runners counting towards a quota, and the quota itself.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct Usage {
+    #[schema(example = 7)]
+    used: u32,
+    #[schema(example = 10)]
+    limit: u32,
+}
+
+/// Usage of the per-instance quota by a single GitLab instance.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct InstanceUsage {
+    #[schema(example = "https://gitlab.your-company.com")]
+    url: String,
+    #[serde(flatten)]
+    usage: Usage,
+}
+
+/// Usage of all quotas.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct QuotaUsage {
+    /// Runners in total
+    runners: Usage,
+    /// Privileged runners
+    privileged: Usage,
+    /// Runners per GitLab instance, ordered by URL
+    instances: Vec<InstanceUsage>,
+}
+
+impl QuotaUsage {
+    pub async fn read(pool: &atmosphere::Pool, quotas: &Quotas) -> Result<Self, Error> {
+        let (runners, privileged): (u32, u32) =
+            sqlx::query_as("SELECT COUNT(*), COALESCE(SUM(privileged), 0) FROM gitlab_runners")
+                .fetch_one(pool)
+                .await?;
+
+        let instances: Vec<(String, u32)> =
+            sqlx::query_as("SELECT url, COUNT(*) FROM gitlab_runners GROUP BY url ORDER BY url")
+                .fetch_all(pool)
+                .await?;
+
+        Ok(Self {
+            runners: Usage {
+                used: runners,
+                limit: quotas.max_runners,
+            },
+            privileged: Usage {
+                used: privileged,
+                limit: quotas.max_privileged,
+            },
+            instances: instances
+                .into_iter()
+                .map(|(url, used)| InstanceUsage {
+                    url,
+                    usage: Usage {
+                        used,
+                        limit: quotas.max_runners_per_instance,
+                    },
+                })
+                .collect(),
+        })
+    }
+
+    /// Returns the quotas which are used up, so that no more runners counting towards them can be
+    /// created, by key along with a description like `10 of 10 runners of <url>`. Quotas of 0
+    /// forbid such runners altogether, so they are never reported.
+    pub fn exhausted(&self) -> Vec<(String, String)> {
+        let exhausted = |usage: &Usage| usage.limit > 0 && usage.used >= usage.limit;
+
+        let mut quotas = Vec::new();
+        if exhausted(&self.runners) {
+            quotas.push((
+                "runners".to_string(),
+                format!("{} of {} runners", self.runners.used, self.runners.limit),
+            ));
+        }
+        if exhausted(&self.privileged) {
+            quotas.push((
+                "privileged".to_string(),
+                format!(
+                    "{} of {} privileged runners",
+                    self.privileged.used, self.privileged.limit
+                ),
+            ));
+        }
+        for instance in self
+            .instances
+            .iter()
+            .filter(|instance| exhausted(&instance.usage))
+        {
+            quotas.push((
+                format!("instance:{}", instance.url),
+                format!(
+                    "{} of {} runners of {}",
+                    instance.usage.used, instance.usage.limit, instance.url
+                ),
+            ));
+        }
+
+        quotas
+    }
+}
@@ -0,0 +1,179 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use serde::Serialize;
+
+use super::ConfigCache;
+use crate::{error::Error, metrics::Metrics};
+
+/// Maximum number of cached responses. Dashboards poll a handful of queries, so once the cache is
+/// full, entries of outdated data versions are dropped, or all entries if there are none.
+const MAX_ENTRIES: usize = 256;
+
+/// A JSON response body as cached by the [`ReadCache`], with the `Last-Modified` header if the
+/// response has one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedResponse {
+    body: Bytes,
+    last_modified: Option<String>,
+}
+
+impl CachedResponse {
+    /// Serializes `value` as the response body.
+    pub fn json<T: Serialize>(value: &T) -> Result<Self, Error> {
+        let body = serde_json::to_vec(value).map_err(Error::internal_error)?;
+
+        Ok(Self {
+            body: body.into(),
+            last_modified: None,
+        })
+    }
+
+    pub fn with_last_modified(mut self, last_modified: String) -> Self {
+        self.last_modified = Some(last_modified);
+        self
+    }
+}
+
+impl IntoResponse for CachedResponse {
+    fn into_response(self) -> Response {
+        let mut response = (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())],
+            self.body,
+        )
+            .into_response();
+        if let Some(last_modified) = self.last_modified.and_then(|value| value.parse().ok()) {
+            response
+                .headers_mut()
+                .insert(header::LAST_MODIFIED, last_modified);
+        }
+        response
+    }
+}
+
+/// Data version and config sync version of the [`ConfigCache`] a response was loaded at.
+type Version = (u64, u64);
+
+/// Read-through cache for the responses of read endpoints, which dashboards tend to poll. Entries
+/// are tagged with the data version of the [`ConfigCache`], which every mutation bumps, so they
+/// are never served once the runners changed, and with its sync version, so responses telling
+/// whether the config on disk is up to date are never served once a queued write changed.
+#[derive(Debug)]
+pub struct ReadCache {
+    config_cache: Arc<ConfigCache>,
+    metrics: Arc<Metrics>,
+    entries: Mutex<HashMap<String, (Version, CachedResponse)>>,
+}
+
+impl ReadCache {
+    pub fn new(config_cache: Arc<ConfigCache>, metrics: Arc<Metrics>) -> Self {
+        Self {
+            config_cache,
+            metrics,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Returns the response cached for `key` if it's up to date, and otherwise loads and caches
+    /// it. Errors are not cached.
+    pub async fn get_or_load<F, Fut>(&self, key: String, load: F) -> Result<CachedResponse, Error>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<CachedResponse, Error>>,
+    {
+        // the version is read before loading, so a mutation committed meanwhile invalidates the
+        // entry right away instead of it being cached as current
+        let version = (
+            self.config_cache.version(),
+            self.config_cache.sync_version(),
+        );
+
+        let cached = self
+            .entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&key)
+            .filter(|(cached_version, _)| *cached_version == version)
+            .map(|(_, response)| response.clone());
+        if let Some(response) = cached {
+            tracing::debug!(key, ?version, "serving cached response");
+            self.metrics.read_cache_hit();
+            return Ok(response);
+        }
+        self.metrics.read_cache_miss();
+
+        let response = load().await?;
+
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        if entries.len() >= MAX_ENTRIES {
+            entries.retain(|_, (cached_version, _)| *cached_version == version);
+            if entries.len() >= MAX_ENTRIES {
+                entries.clear();
+            }
+        }
+        entries.insert(key, (version, response.clone()));
+
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use pretty_assertions::assert_eq;
+
+    use super::{CachedResponse, ReadCache};
+    use crate::{error::Error, models::ConfigCache};
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[tokio::test]
+    async fn invalidated_by_mutations() -> Result<()> {
+        let config_cache = Arc::new(ConfigCache::default());
+        let cache = ReadCache::new(config_cache.clone(), Arc::default());
+        let loads = &AtomicUsize::new(0);
+        let load = move || async move {
+            loads.fetch_add(1, Ordering::SeqCst);
+            CachedResponse::json(&loads.load(Ordering::SeqCst))
+        };
+
+        let first = cache.get_or_load("list".to_string(), load).await?;
+        assert_eq!(cache.get_or_load("list".to_string(), load).await?, first);
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+
+        config_cache.bump();
+        assert_ne!(cache.get_or_load("list".to_string(), load).await?, first);
+        assert_eq!(loads.load(Ordering::SeqCst), 2);
+
+        // so are changes of the queued config write
+        config_cache.sync_changed();
+        cache.get_or_load("list".to_string(), load).await?;
+        assert_eq!(loads.load(Ordering::SeqCst), 3);
+
+        // errors are passed on, but not cached
+        let failing = || async { Err(Error::not_found("warbl")) };
+        assert!(cache
+            .get_or_load("read".to_string(), failing)
+            .await
+            .is_err());
+        cache.get_or_load("read".to_string(), load).await?;
+        assert_eq!(loads.load(Ordering::SeqCst), 4);
+
+        Ok(())
+    }
+}
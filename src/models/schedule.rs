@@ -0,0 +1,345 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{fmt, str::FromStr, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use futures::{future::BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use sqlx::Sqlite;
+
+use super::{ConfigCache, ConfigTarget, GitLabRunner, GitLabRunnerConfig};
+use crate::{
+    error::Error,
+    freeze::FreezeWindow,
+    settings::SettingsStore,
+    subsystems::{Shutdown, Subsystem},
+};
+
+/// How often the enforcer looks for runners whose schedule pauses or resumes them.
+const ENFORCER_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Weekly windows in UTC during which a runner picks up jobs, e.g. `Mon 08:00-Fri 18:00`, in the
+/// same format as change freezes; outside of them, the runner is paused. Given as a list in JSON
+/// and stored as a comma-separated list.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "Vec<FreezeWindow>", into = "Vec<FreezeWindow>")]
+pub struct Schedule(Vec<FreezeWindow>);
+
+impl Schedule {
+    /// Returns `true` if `at` lies within one of the windows.
+    pub fn is_active_at(&self, at: DateTime<Utc>) -> bool {
+        self.0.iter().any(|window| window.contains(at))
+    }
+
+    /// Returns the first time after `after` at which the runner is paused or resumed, or `None`
+    /// if the windows cover the whole week.
+    pub fn next_transition(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let active = self.is_active_at(after);
+
+        // the runner can only be paused or resumed at a boundary of a window; every window has two
+        // boundaries a week
+        let mut at = after;
+        for _ in 0..self.0.len() * 2 {
+            at = self.0.iter().map(|window| window.next_boundary(at)).min()?;
+            if self.is_active_at(at) != active {
+                return Some(at);
+            }
+        }
+
+        None
+    }
+}
+
+impl TryFrom<Vec<FreezeWindow>> for Schedule {
+    type Error = String;
+
+    fn try_from(windows: Vec<FreezeWindow>) -> Result<Self, Self::Error> {
+        if windows.is_empty() {
+            return Err("schedule must contain at least one window".to_string());
+        }
+
+        Ok(Self(windows))
+    }
+}
+
+impl From<Schedule> for Vec<FreezeWindow> {
+    fn from(schedule: Schedule) -> Self {
+        schedule.0
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(schedule: &str) -> Result<Self, Self::Err> {
+        schedule
+            .split(',')
+            .filter(|window| !window.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<_>, _>>()?
+            .try_into()
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let windows: Vec<String> = self.0.iter().map(ToString::to_string).collect();
+        f.write_str(&windows.join(","))
+    }
+}
+
+impl sqlx::Type<Sqlite> for Schedule {
+    fn type_info() -> <Sqlite as sqlx::Database>::TypeInfo {
+        <String as sqlx::Type<Sqlite>>::type_info()
+    }
+
+    fn compatible(ty: &<Sqlite as sqlx::Database>::TypeInfo) -> bool {
+        <String as sqlx::Type<Sqlite>>::compatible(ty)
+    }
+}
+
+impl<'q> sqlx::Encode<'q, Sqlite> for Schedule {
+    fn encode_by_ref(
+        &self,
+        buf: &mut <Sqlite as sqlx::database::HasArguments<'q>>::ArgumentBuffer,
+    ) -> sqlx::encode::IsNull {
+        <String as sqlx::Encode<Sqlite>>::encode(self.to_string(), buf)
+    }
+}
+
+impl<'r> sqlx::Decode<'r, Sqlite> for Schedule {
+    fn decode(
+        value: <Sqlite as sqlx::database::HasValueRef<'r>>::ValueRef,
+    ) -> Result<Self, sqlx::error::BoxDynError> {
+        let schedule = <&str as sqlx::Decode<Sqlite>>::decode(value)?;
+        Ok(schedule.parse::<Self>()?)
+    }
+}
+
+/// Pauses and resumes runners with a schedule at the boundaries of their windows, and rewrites
+/// the config accordingly. During a change freeze, due transitions are held back until it's over.
+#[derive(Debug, Clone)]
+pub struct ScheduleEnforcer {
+    pool: atmosphere::Pool,
+    target: Arc<ConfigTarget>,
+    cache: Arc<ConfigCache>,
+    settings: Arc<SettingsStore>,
+}
+
+impl ScheduleEnforcer {
+    pub fn new(
+        pool: atmosphere::Pool,
+        target: Arc<ConfigTarget>,
+        cache: Arc<ConfigCache>,
+        settings: Arc<SettingsStore>,
+    ) -> Self {
+        Self {
+            pool,
+            target,
+            cache,
+            settings,
+        }
+    }
+
+    async fn enforce(&self) -> Result<(), Error> {
+        self.enforce_at(Utc::now()).await
+    }
+
+    /// Applies the transitions due at `now`.
+    async fn enforce_at(&self, now: DateTime<Utc>) -> Result<(), Error> {
+        let settings = self.settings.load();
+        // due transitions stay due, so they are applied once the freeze is over
+        if let Some(window) = settings.freeze_windows.active_at(now) {
+            tracing::debug!(
+                subsystem = self.name(),
+                %window,
+                "changes are frozen, not changing runners"
+            );
+            return Ok(());
+        }
+
+        let due: Vec<GitLabRunner> = sqlx::query_as(
+            "SELECT * FROM gitlab_runners WHERE schedule IS NOT NULL \
+             AND datetime(next_transition_at) <= datetime(?) ORDER BY rowid",
+        )
+        .bind(now)
+        .fetch_all(&self.pool)
+        .await?;
+        if due.is_empty() {
+            return Ok(());
+        }
+
+        for mut runner in due {
+            runner.apply_schedule(now);
+            if runner.store_schedule_state(&self.pool).await? {
+                tracing::info!(
+                    uuid = %runner.uuid(),
+                    paused = runner.paused(),
+                    "applied runner schedule"
+                );
+            }
+        }
+
+        self.cache.bump();
+        let path = self.target.load();
+        GitLabRunnerConfig::write_or_queue(&self.pool, &path, &self.cache, &settings.render)
+            .await
+            .map(drop)
+    }
+}
+
+impl Subsystem for ScheduleEnforcer {
+    fn name(&self) -> &'static str {
+        "schedule-enforcer"
+    }
+
+    fn run(&self, mut shutdown: Shutdown) -> BoxFuture<'static, Result<(), Error>> {
+        let this = self.clone();
+
+        async move {
+            let mut interval = tokio::time::interval(ENFORCER_INTERVAL);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => this.enforce().await?,
+                    _ = shutdown.requested() => return Ok(()),
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use atmosphere::{Create as _, Pool, Read as _};
+    use chrono::{DateTime, Utc};
+    use pretty_assertions::assert_eq;
+
+    use super::{Schedule, ScheduleEnforcer};
+    use crate::{
+        models::{ConfigCache, ConfigTarget, GitLabRunner},
+        settings::{Settings, SettingsStore},
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    fn at(timestamp: &str) -> DateTime<Utc> {
+        timestamp.parse().expect("given timestamp is valid")
+    }
+
+    #[test]
+    fn transitions() -> Result<()> {
+        // 2024-07-01 is a Monday
+        let work_hours: Schedule = "Mon 08:00-Mon 18:00, Tue 08:00-Tue 18:00".parse()?;
+        assert!(work_hours.is_active_at(at("2024-07-01T12:00:00Z")));
+        assert!(!work_hours.is_active_at(at("2024-07-01T18:00:00Z")));
+
+        assert_eq!(
+            work_hours.next_transition(at("2024-07-01T12:00:30Z")),
+            Some(at("2024-07-01T18:00:00Z"))
+        );
+        assert_eq!(
+            work_hours.next_transition(at("2024-07-01T18:00:00Z")),
+            Some(at("2024-07-02T08:00:00Z"))
+        );
+        // after the last window of the week, the next one is the first one of the next week
+        assert_eq!(
+            work_hours.next_transition(at("2024-07-03T09:00:00Z")),
+            Some(at("2024-07-08T08:00:00Z"))
+        );
+
+        // adjoining windows don't pause the runner in between
+        let adjoining: Schedule = "Mon 08:00-Mon 12:00,Mon 12:00-Mon 18:00".parse()?;
+        assert_eq!(
+            adjoining.next_transition(at("2024-07-01T09:00:00Z")),
+            Some(at("2024-07-01T18:00:00Z"))
+        );
+
+        assert_eq!(
+            work_hours.to_string(),
+            "Mon 08:00-Mon 18:00,Tue 08:00-Tue 18:00"
+        );
+        assert_eq!(
+            serde_json::to_value(&work_hours)?,
+            serde_json::json!(["Mon 08:00-Mon 18:00", "Tue 08:00-Tue 18:00"])
+        );
+        assert!("".parse::<Schedule>().is_err());
+        assert!(serde_json::from_str::<Schedule>("[]").is_err());
+
+        Ok(())
+    }
+
+    async fn stored(pool: &Pool, runner: &GitLabRunner) -> Result<GitLabRunner> {
+        Ok(GitLabRunner::find(pool, runner.uuid())
+            .await?
+            .ok_or("runner missing")?)
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    #[tracing_test::traced_test]
+    async fn pause_and_resume(pool: Pool) -> Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("runrs-schedule-{}.toml", uuid::Uuid::new_v4()));
+        let settings = Settings {
+            freeze_windows: "Mon 18:00-Mon 19:00".parse()?,
+            ..Default::default()
+        };
+        let enforcer = ScheduleEnforcer::new(
+            pool.clone(),
+            Arc::new(ConfigTarget::new(path)),
+            Arc::new(ConfigCache::default()),
+            Arc::new(SettingsStore::new(settings)),
+        );
+
+        // 2024-07-01 is a Monday
+        let mut runner = GitLabRunner::for_testing();
+        runner.set_schedule("Mon 08:00-Mon 18:00".parse()?);
+        runner.apply_schedule(at("2024-07-01T12:00:00Z"));
+        runner.create(&pool).await?;
+        assert!(!runner.paused());
+        assert_eq!(
+            runner.next_transition_at(),
+            Some(at("2024-07-01T18:00:00Z"))
+        );
+
+        // nothing is due yet
+        enforcer.enforce_at(at("2024-07-01T17:59:00Z")).await?;
+        assert!(!stored(&pool, &runner).await?.paused());
+
+        // the window closed during a change freeze, so the runner is only paused after it
+        enforcer.enforce_at(at("2024-07-01T18:30:00Z")).await?;
+        assert!(!stored(&pool, &runner).await?.paused());
+        enforcer.enforce_at(at("2024-07-01T19:00:00Z")).await?;
+
+        // the runner is left out of the config, but counts as unchanged to clients
+        let paused = stored(&pool, &runner).await?;
+        assert!(paused.paused());
+        assert_eq!(
+            paused.next_transition_at(),
+            Some(at("2024-07-08T08:00:00Z"))
+        );
+        assert_eq!(paused.updated_at(), runner.updated_at());
+        let config_toml = std::fs::read_to_string(&*enforcer.target.load())?;
+        assert!(!config_toml.contains("Knows the meaning of life"));
+
+        // a runner paused by hand stays paused when its window opens
+        sqlx::query("UPDATE gitlab_runners SET paused = 1 WHERE uuid = ?")
+            .bind(runner.uuid())
+            .execute(&pool)
+            .await?;
+        enforcer.enforce_at(at("2024-07-08T08:00:00Z")).await?;
+        let resumed = stored(&pool, &runner).await?;
+        assert!(resumed.paused());
+        assert_eq!(
+            resumed.next_transition_at(),
+            Some(at("2024-07-08T18:00:00Z"))
+        );
+
+        std::fs::remove_file(&*enforcer.target.load())?;
+
+        Ok(())
+    }
+}
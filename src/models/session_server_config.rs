@@ -0,0 +1,109 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use glrcfg::{session_server::SessionServer, ListenAddress};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::single_row;
+use crate::error::Error;
+
+/// Settings of the `[session_server]` section, which enables the interactive web terminal of
+/// jobs. The section is only written to the config while `listen_address` is set.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SessionServerConfig {
+    /// Address the session server of `gitlab-runner` listens on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>, example = "[::]:8093")]
+    pub listen_address: Option<ListenAddress>,
+    /// Address GitLab reaches the session server at, if it differs from `listen_address`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>, example = "runner.your-company.com:8093")]
+    pub advertise_address: Option<ListenAddress>,
+    /// Seconds a terminal session stays open after the job finished
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(minimum = 1, example = 1800)]
+    pub session_timeout: Option<u32>,
+}
+
+impl SessionServerConfig {
+    /// Reads the stored settings; none are set if the session server was never configured.
+    pub async fn read(pool: &atmosphere::Pool) -> Result<Self, Error> {
+        single_row::read(pool, "session_server").await
+    }
+
+    /// Replaces the stored settings.
+    pub async fn store(&self, pool: &atmosphere::Pool) -> Result<(), Error> {
+        if self.listen_address.is_none()
+            && (self.advertise_address.is_some() || self.session_timeout.is_some())
+        {
+            return Err(Error::invalid_argument(
+                "advertise_address and session_timeout require a listen_address",
+            ));
+        }
+        if self.session_timeout == Some(0) {
+            return Err(Error::invalid_argument("session_timeout must be positive"));
+        }
+
+        single_row::store(pool, "session_server", self).await
+    }
+
+    /// The section to write to the config, if the session server is enabled.
+    pub fn session_server(&self) -> Option<SessionServer> {
+        let listen_address = self.listen_address.clone()?;
+        let default = SessionServer::default();
+
+        Some(SessionServer {
+            listen_address: Some(listen_address),
+            advertise_address: self.advertise_address.clone(),
+            session_timeout: self.session_timeout.unwrap_or(default.session_timeout),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use atmosphere::Pool;
+    use glrcfg::{Config, ListenAddress};
+    use pretty_assertions::assert_eq;
+
+    use super::SessionServerConfig;
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    #[tracing_test::traced_test]
+    async fn store_read(pool: Pool) -> Result<()> {
+        let config = SessionServerConfig::read(&pool).await?;
+        assert_eq!(config, SessionServerConfig::default());
+        assert!(config.session_server().is_none());
+
+        let config = SessionServerConfig {
+            listen_address: Some(ListenAddress::parse("[::]:8093")?),
+            ..Default::default()
+        };
+        config.store(&pool).await?;
+        assert_eq!(SessionServerConfig::read(&pool).await?, config);
+
+        let session_server = config.session_server().expect("listen_address is set");
+        assert_eq!(session_server.listen_address, config.listen_address);
+        assert_eq!(session_server.session_timeout, 1800);
+
+        // `gitlab-runner` listens on the address as is, so it must be rendered as `host:port`
+        let config_toml = Config::builder()
+            .with_session_server(session_server)
+            .build()
+            .to_toml_string();
+        assert!(config_toml.contains("listen_address = \"[::]:8093\"\n"));
+        assert!(!config_toml.contains("advertise_address"));
+
+        // the other settings are meaningless without a listen address
+        let config = SessionServerConfig {
+            session_timeout: Some(600),
+            ..Default::default()
+        };
+        assert!(config.store(&pool).await.is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+//! Settings kept as JSON in the only row of their table, e.g. `global_config`.
+
+use chrono::Utc;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::error::Error;
+
+/// Reads the settings stored in `table`, or their defaults if they were never stored.
+pub(super) async fn read<T>(pool: &atmosphere::Pool, table: &str) -> Result<T, Error>
+where
+    T: DeserializeOwned + Default,
+{
+    let config: Option<String> =
+        sqlx::query_scalar(&format!("SELECT config FROM {table} WHERE id = 1"))
+            .fetch_optional(pool)
+            .await?;
+
+    config.map_or_else(
+        || Ok(T::default()),
+        |config| serde_json::from_str(&config).map_err(Error::internal_error),
+    )
+}
+
+/// Replaces the settings stored in `table`.
+pub(super) async fn store<T>(pool: &atmosphere::Pool, table: &str, config: &T) -> Result<(), Error>
+where
+    T: Serialize,
+{
+    sqlx::query(&format!(
+        "INSERT INTO {table} (id, config, updated_at) VALUES (1, ?, ?) \
+         ON CONFLICT (id) DO UPDATE SET config = excluded.config, \
+         updated_at = excluded.updated_at"
+    ))
+    .bind(serde_json::to_string(config).map_err(Error::internal_error)?)
+    .bind(Utc::now())
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,229 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{QueryBuilder, Sqlite, SqliteExecutor};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{error::Error, settings::AuditRetention};
+
+/// Default and maximum number of events per page.
+const DEFAULT_PER_PAGE: u32 = 50;
+const MAX_PER_PAGE: u32 = 500;
+
+fn first_page() -> u32 {
+    1
+}
+
+fn default_per_page() -> u32 {
+    DEFAULT_PER_PAGE
+}
+
+/// Something runrs did on its own rather than on request. Kept apart from the audit log, whose
+/// entries all stand for requests made with a token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(rename_all = "snake_case")]
+pub enum SystemEventKind {
+    /// runrs shut down; the subject is the config target the final snapshot was written to
+    Shutdown,
+    /// A deleted runner was purged from the recycle bin
+    Purge,
+    /// A runner was migrated from the legacy schema
+    Migrate,
+    /// GitOps reconciled the declared runners; the subject is the commit
+    Reconcile,
+}
+
+/// A system event, as recorded in the `system_events` table.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, sqlx::FromRow, ToSchema)]
+pub struct SystemEvent {
+    #[schema(example = 42)]
+    id: i64,
+    /// When the event happened
+    #[schema(value_type = String, format = DateTime, example = "2024-07-10T09:00:00Z")]
+    at: DateTime<Utc>,
+    kind: SystemEventKind,
+    /// Part of runrs the event came from
+    #[schema(example = "runrs")]
+    actor: String,
+    /// What the event is about, e.g. the path of a runner
+    #[schema(example = "/gitlab-runners/deleted/be924fdd-fb28-468c-8c70-1f0ed3af4485")]
+    subject: String,
+    /// Why the event failed; not set if it succeeded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Criteria selecting a page of system events.
+#[derive(Debug, Clone, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SystemEventFilter {
+    /// Kind of the event
+    pub kind: Option<SystemEventKind>,
+    /// Page to return, starting at 1; events are sorted newest first
+    #[serde(default = "first_page")]
+    pub page: u32,
+    /// Events per page (default: 50, at most 500)
+    #[serde(default = "default_per_page")]
+    pub per_page: u32,
+}
+
+impl Default for SystemEventFilter {
+    fn default() -> Self {
+        Self {
+            kind: None,
+            page: first_page(),
+            per_page: default_per_page(),
+        }
+    }
+}
+
+impl SystemEvent {
+    pub async fn record<'c>(
+        conn: impl SqliteExecutor<'c>,
+        kind: SystemEventKind,
+        actor: &str,
+        subject: &str,
+        error: Option<&str>,
+    ) -> Result<(), Error> {
+        sqlx::query(
+            "INSERT INTO system_events (at, kind, actor, subject, error) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(Utc::now())
+        .bind(kind)
+        .bind(actor)
+        .bind(subject)
+        .bind(error)
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns the requested page of events matching the filter, and the number of matching
+    /// events on all pages.
+    pub async fn list(
+        pool: &atmosphere::Pool,
+        filter: &SystemEventFilter,
+    ) -> Result<(Vec<Self>, u64), Error> {
+        if filter.page == 0 {
+            return Err(Error::bad_request("pages start at 1"));
+        }
+        if !(1..=MAX_PER_PAGE).contains(&filter.per_page) {
+            return Err(Error::bad_request(format!(
+                "per_page must be between 1 and {MAX_PER_PAGE}"
+            )));
+        }
+
+        let mut count = QueryBuilder::<Sqlite>::new("SELECT COUNT(*) FROM system_events");
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT * FROM system_events");
+        if let Some(kind) = filter.kind {
+            count.push(" WHERE kind = ").push_bind(kind);
+            query.push(" WHERE kind = ").push_bind(kind);
+        }
+        let total: i64 = count.build_query_scalar().fetch_one(pool).await?;
+
+        query
+            .push(" ORDER BY id DESC LIMIT ")
+            .push_bind(filter.per_page)
+            .push(" OFFSET ")
+            .push_bind(i64::from(filter.page - 1) * i64::from(filter.per_page));
+        let events = query.build_query_as().fetch_all(pool).await?;
+
+        Ok((events, u64::try_from(total).unwrap_or_default()))
+    }
+
+    /// Removes up to `limit` of the oldest events beyond the retention limits of the audit log.
+    /// Returns the number of removed events.
+    pub async fn prune(
+        pool: &atmosphere::Pool,
+        retention: &AuditRetention,
+        now: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<u64, Error> {
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "DELETE FROM system_events WHERE id IN (SELECT id FROM system_events WHERE 0 = 1",
+        );
+        if retention.max_age_days > 0 {
+            let cutoff = now - TimeDelta::days(i64::from(retention.max_age_days));
+            query
+                .push(" OR datetime(at) < datetime(")
+                .push_bind(cutoff)
+                .push(")");
+        }
+        if retention.max_entries > 0 {
+            query
+                .push(" OR id <= (SELECT id FROM system_events ORDER BY id DESC LIMIT 1 OFFSET ")
+                .push_bind(i64::try_from(retention.max_entries).unwrap_or(i64::MAX))
+                .push(")");
+        }
+        query.push(" ORDER BY id LIMIT ").push_bind(limit).push(")");
+
+        Ok(query.build().execute(pool).await?.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use atmosphere::Pool;
+    use chrono::{TimeDelta, Utc};
+    use pretty_assertions::assert_eq;
+
+    use super::{SystemEvent, SystemEventFilter, SystemEventKind};
+    use crate::settings::AuditRetention;
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn record_list_and_prune(pool: Pool) -> Result<()> {
+        SystemEvent::record(&pool, SystemEventKind::Migrate, "migration", "/1", None).await?;
+        SystemEvent::record(
+            &pool,
+            SystemEventKind::Migrate,
+            "migration",
+            "/2",
+            Some("invalid runner"),
+        )
+        .await?;
+        SystemEvent::record(
+            &pool,
+            SystemEventKind::Shutdown,
+            "runrs",
+            "/config.toml",
+            None,
+        )
+        .await?;
+
+        let filter = SystemEventFilter {
+            kind: Some(SystemEventKind::Migrate),
+            ..Default::default()
+        };
+        let (events, total) = SystemEvent::list(&pool, &filter).await?;
+        assert_eq!(total, 2);
+        assert_eq!(events[0].subject, "/2");
+        assert_eq!(events[0].error.as_deref(), Some("invalid runner"));
+        assert_eq!(events[1].error, None);
+
+        let retention = AuditRetention {
+            max_age_days: 90,
+            max_entries: 1,
+        };
+        assert_eq!(
+            SystemEvent::prune(&pool, &retention, Utc::now(), 10).await?,
+            2
+        );
+        let (events, _) = SystemEvent::list(&pool, &SystemEventFilter::default()).await?;
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, SystemEventKind::Shutdown);
+
+        let retention = AuditRetention {
+            max_age_days: 90,
+            max_entries: 0,
+        };
+        let later = Utc::now() + TimeDelta::days(91);
+        assert_eq!(SystemEvent::prune(&pool, &retention, later, 10).await?, 1);
+
+        Ok(())
+    }
+}
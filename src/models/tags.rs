@@ -0,0 +1,48 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+use sqlx::Sqlite;
+
+/// Tags of the jobs a runner picks up, as registered with GitLab, e.g. `docker` or `linux`.
+/// Stored as a JSON array.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Tags(BTreeSet<String>);
+
+impl From<Vec<String>> for Tags {
+    fn from(tags: Vec<String>) -> Self {
+        Self(tags.into_iter().collect())
+    }
+}
+
+impl sqlx::Type<Sqlite> for Tags {
+    fn type_info() -> <Sqlite as sqlx::Database>::TypeInfo {
+        <String as sqlx::Type<Sqlite>>::type_info()
+    }
+
+    fn compatible(ty: &<Sqlite as sqlx::Database>::TypeInfo) -> bool {
+        <String as sqlx::Type<Sqlite>>::compatible(ty)
+    }
+}
+
+impl<'q> sqlx::Encode<'q, Sqlite> for Tags {
+    fn encode_by_ref(
+        &self,
+        buf: &mut <Sqlite as sqlx::database::HasArguments<'q>>::ArgumentBuffer,
+    ) -> sqlx::encode::IsNull {
+        let json = serde_json::to_string(&self.0)
+            .expect("serializing a set of strings to JSON must not fail");
+        <String as sqlx::Encode<Sqlite>>::encode(json, buf)
+    }
+}
+
+impl<'r> sqlx::Decode<'r, Sqlite> for Tags {
+    fn decode(
+        value: <Sqlite as sqlx::database::HasValueRef<'r>>::ValueRef,
+    ) -> Result<Self, sqlx::error::BoxDynError> {
+        let json = <&str as sqlx::Decode<Sqlite>>::decode(value)?;
+        Ok(Self(serde_json::from_str(json)?))
+    }
+}
@@ -0,0 +1,132 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use atmosphere::{table, Schema, Table as _, Update as _};
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::error::Error;
+
+/// Upper bound for the delay between two attempts at a task.
+const MAX_BACKOFF_SECS: i64 = 300;
+
+/// Work which failed and is retried in the background until it succeeds. Tasks are identified by
+/// a key, so that the same work is never queued twice; e.g. there is at most one pending config
+/// write, no matter how many writes failed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Schema, ToSchema)]
+#[table(schema = "public", name = "tasks")]
+pub struct Task {
+    #[sql(pk)]
+    #[schema(example = "config_write")]
+    key: String,
+    #[schema(example = "config_write")]
+    kind: String,
+    /// Number of failed attempts so far
+    attempts: u32,
+    /// Reason the last attempt failed
+    last_error: Option<String>,
+    #[schema(value_type = String, format = DateTime, example = "2024-06-17T10:15:00Z")]
+    created_at: DateTime<Utc>,
+    #[schema(value_type = String, format = DateTime, example = "2024-06-17T10:15:08Z")]
+    next_attempt_at: DateTime<Utc>,
+}
+
+impl Task {
+    pub async fn find(pool: &atmosphere::Pool, key: &str) -> Result<Option<Self>, Error> {
+        Ok(sqlx::query_as("SELECT * FROM tasks WHERE key = ?")
+            .bind(key)
+            .fetch_optional(pool)
+            .await?)
+    }
+
+    /// Records a failed attempt at the task, creating the task if it isn't queued yet, and
+    /// schedules the next attempt with exponential backoff.
+    pub async fn record_failure(
+        pool: &atmosphere::Pool,
+        key: &str,
+        kind: &str,
+        error: &str,
+    ) -> Result<Self, Error> {
+        let now = Utc::now();
+        let mut task = Self::find(pool, key).await?.unwrap_or_else(|| Self {
+            key: key.to_string(),
+            kind: kind.to_string(),
+            attempts: 0,
+            last_error: None,
+            created_at: now,
+            next_attempt_at: now,
+        });
+
+        task.attempts += 1;
+        task.last_error = Some(error.to_string());
+        task.next_attempt_at = now + backoff(task.attempts);
+        task.upsert(pool).await?;
+
+        Ok(task)
+    }
+
+    /// Removes the task from the queue, if it is queued.
+    pub async fn complete(pool: &atmosphere::Pool, key: &str) -> Result<(), Error> {
+        sqlx::query("DELETE FROM tasks WHERE key = ?")
+            .bind(key)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    /// Returns `true` if the next attempt at the task is due.
+    pub fn is_due(&self) -> bool {
+        self.next_attempt_at <= Utc::now()
+    }
+}
+
+pub(super) fn backoff(attempts: u32) -> TimeDelta {
+    let secs = 2_i64.saturating_pow(attempts).min(MAX_BACKOFF_SECS);
+    TimeDelta::seconds(secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use atmosphere::Pool;
+    use chrono::TimeDelta;
+    use pretty_assertions::assert_eq;
+
+    use super::{backoff, Task};
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[test]
+    fn exponential_backoff() {
+        assert_eq!(backoff(1), TimeDelta::seconds(2));
+        assert_eq!(backoff(4), TimeDelta::seconds(16));
+        assert_eq!(backoff(42), TimeDelta::seconds(300));
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn record_failure_complete(pool: Pool) -> Result<()> {
+        assert_eq!(Task::find(&pool, "warbl").await?, None);
+
+        let task = Task::record_failure(&pool, "warbl", "garbl", "disk full").await?;
+        assert_eq!(task.attempts(), 1);
+        assert!(!task.is_due());
+
+        let task = Task::record_failure(&pool, "warbl", "garbl", "still full").await?;
+        assert_eq!(task.attempts(), 2);
+        assert_eq!(task.last_error.as_deref(), Some("still full"));
+        assert_eq!(Task::find(&pool, "warbl").await?, Some(task));
+
+        Task::complete(&pool, "warbl").await?;
+        assert_eq!(Task::find(&pool, "warbl").await?, None);
+
+        Ok(())
+    }
+}
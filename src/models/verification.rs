@@ -0,0 +1,335 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use futures::{stream, StreamExt};
+use glrcfg::runner::Url;
+use serde::Serialize;
+use tokio::time::Instant;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use super::{GitLabRunner, Maintenance, MaintenanceWindow};
+use crate::{gitlab::GitLabClient, metrics::Metrics, secrets::Secrets, settings::Verification};
+
+/// Outcome of verifying a single runner token with GitLab.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum VerificationStatus {
+    /// GitLab knows the runner and accepts its token.
+    Valid,
+    /// GitLab rejects the token, e.g. because the runner was deleted or its token reset.
+    Invalid,
+    /// The token couldn't be verified, e.g. because GitLab is unreachable.
+    Failed,
+    /// The sweep timed out before the runner was verified.
+    Unchecked,
+    /// The runner wasn't verified, since its GitLab instance is in maintenance.
+    Maintenance,
+}
+
+/// Result of verifying a single runner.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct RunnerVerification {
+    #[schema(value_type = String, format = Uuid, example = "be924fdd-fb28-468c-8c70-1f0ed3af4485")]
+    uuid: Uuid,
+    #[schema(example = "usain-bolt")]
+    name: String,
+    #[schema(example = "https://gitlab.your-company.com")]
+    url: String,
+    status: VerificationStatus,
+    /// Why the verification failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = "GitLab unreachable: connection refused")]
+    error: Option<String>,
+}
+
+/// Results of verifying a set of runners with GitLab. If the sweep times out, the runners which
+/// were verified so far are reported, the others are `unchecked`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct VerificationReport {
+    /// Whether all runners were verified before the sweep timed out
+    complete: bool,
+    valid: usize,
+    invalid: usize,
+    failed: usize,
+    unchecked: usize,
+    /// Runners which weren't verified since their GitLab instance is in maintenance
+    in_maintenance: usize,
+    /// The runners, in the order they were given
+    runners: Vec<RunnerVerification>,
+    /// Maintenance windows of the GitLab instances of the runners
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    maintenance: Vec<MaintenanceWindow>,
+}
+
+impl VerificationReport {
+    /// Verifies the runners concurrently, with at most `limits.parallelism` verifications in
+    /// flight and at most `limits.rate_per_instance` requests per second to each GitLab instance.
+    /// Runners with invalid tokens are counted in the `metrics`. Runners of instances in
+    /// `maintenance` are skipped.
+    pub async fn sweep(
+        runners: &[GitLabRunner],
+        gitlab: &GitLabClient,
+        secrets: &Secrets,
+        metrics: &Metrics,
+        limits: &Verification,
+        maintenance: &Maintenance,
+    ) -> Self {
+        let windows = maintenance
+            .windows()
+            .into_iter()
+            .filter(|window| {
+                runners
+                    .iter()
+                    .any(|runner| maintenance.get(runner.url()) == Some(window))
+            })
+            .collect();
+
+        let deadline = Instant::now() + Duration::from_secs(limits.timeout_secs);
+        let limiter = &RateLimiter::new(limits.rate_per_instance);
+
+        let mut results: Vec<(usize, RunnerVerification)> =
+            stream::iter(runners.iter().enumerate())
+                .map(|(index, runner)| async move {
+                    let verification = |status, error| RunnerVerification {
+                        uuid: *runner.uuid(),
+                        name: runner.name().to_string(),
+                        url: runner.url().to_string(),
+                        status,
+                        error,
+                    };
+                    if maintenance.get(runner.url()).is_some() {
+                        return (index, verification(VerificationStatus::Maintenance, None));
+                    }
+
+                    let verified = tokio::time::timeout_at(deadline, async {
+                        limiter.wait(runner.url()).await;
+                        runner.verify(gitlab, secrets).await
+                    })
+                    .await;
+
+                    let (status, error) = match verified {
+                        Ok(Ok(true)) => (VerificationStatus::Valid, None),
+                        Ok(Ok(false)) => {
+                            metrics.token_verify_failed(runner.url(), runner.os(), *runner.uuid());
+                            (VerificationStatus::Invalid, None)
+                        }
+                        Ok(Err(err)) => (VerificationStatus::Failed, Some(err.msg)),
+                        Err(_) => (VerificationStatus::Unchecked, None),
+                    };
+
+                    (index, verification(status, error))
+                })
+                .buffer_unordered(limits.parallelism.max(1))
+                .collect()
+                .await;
+        results.sort_by_key(|(index, _)| *index);
+
+        let runners: Vec<_> = results
+            .into_iter()
+            .map(|(_, verification)| verification)
+            .collect();
+        let count = |status| {
+            runners
+                .iter()
+                .filter(|verification| verification.status == status)
+                .count()
+        };
+
+        let unchecked = count(VerificationStatus::Unchecked);
+        if unchecked > 0 {
+            tracing::warn!(
+                unchecked,
+                "verification sweep timed out, reporting partial results"
+            );
+        }
+
+        Self {
+            complete: unchecked == 0,
+            valid: count(VerificationStatus::Valid),
+            invalid: count(VerificationStatus::Invalid),
+            failed: count(VerificationStatus::Failed),
+            unchecked,
+            in_maintenance: count(VerificationStatus::Maintenance),
+            runners,
+            maintenance: windows,
+        }
+    }
+}
+
+/// Spaces out the requests to each GitLab instance evenly.
+#[derive(Debug)]
+struct RateLimiter {
+    interval: Duration,
+    /// When the next request to each instance may be sent
+    next: Mutex<HashMap<String, Instant>>,
+}
+
+impl RateLimiter {
+    fn new(rate_per_second: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / rate_per_second.max(1),
+            next: Mutex::default(),
+        }
+    }
+
+    /// Waits until the next request to the instance at `url` may be sent.
+    async fn wait(&self, url: &Url) {
+        let at = {
+            let mut next = self
+                .next
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let now = Instant::now();
+            let slot = next
+                .entry(url.normalized().as_str().to_string())
+                .or_insert(now);
+            let at = (*slot).max(now);
+            *slot = at + self.interval;
+            at
+        };
+
+        tokio::time::sleep_until(at).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::{routing::post, Json, Router};
+    use glrcfg::runner::Url;
+    use pretty_assertions::assert_eq;
+    use tokio::time::Instant;
+
+    use super::{RateLimiter, VerificationReport, VerificationStatus};
+    use crate::{
+        gitlab::GitLabClient,
+        metrics::Metrics,
+        models::{GitLabRunner, Maintenance, MaintenanceWindow},
+        secrets::Secrets,
+        settings::Verification,
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    /// Serves a GitLab which only accepts the token of [`GitLabRunner::for_testing`].
+    async fn fake_gitlab() -> Result<String> {
+        let gitlab = Router::new().route(
+            "/api/v4/runners/verify",
+            post(|Json(body): Json<serde_json::Value>| async move {
+                if body["token"] == "glrt-0123456789_abcdefXYZ" {
+                    axum::http::StatusCode::OK
+                } else {
+                    axum::http::StatusCode::FORBIDDEN
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, gitlab).await });
+
+        Ok(url)
+    }
+
+    #[tokio::test]
+    async fn sweep_reports_each_runner() -> Result<()> {
+        let url = fake_gitlab().await?;
+
+        let mut valid = GitLabRunner::for_testing();
+        valid.set_url(&url);
+        let mut invalid = valid.clone();
+        invalid.set_token("glrt-reset_0123456789abc");
+        let mut unreachable = GitLabRunner::for_testing();
+        unreachable.set_url("http://127.0.0.1:1");
+        let runners = vec![valid, invalid, unreachable];
+
+        let metrics = Metrics::default();
+        let report = VerificationReport::sweep(
+            &runners,
+            &GitLabClient::default(),
+            &Secrets::default(),
+            &metrics,
+            &Verification::default(),
+            &Maintenance::default(),
+        )
+        .await;
+
+        assert!(report.complete);
+        assert_eq!((report.valid, report.invalid, report.failed), (1, 1, 1));
+        let statuses: Vec<_> = report.runners.iter().map(|runner| runner.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                VerificationStatus::Valid,
+                VerificationStatus::Invalid,
+                VerificationStatus::Failed
+            ]
+        );
+        assert!(report.runners[2].error.is_some());
+        assert!(metrics
+            .render()
+            .contains("runrs_token_verify_failures_total{instance=\"127.0.0.1\""));
+
+        // runners not verified in time are reported as unchecked
+        let limits = Verification {
+            timeout_secs: 0,
+            ..Default::default()
+        };
+        let report = VerificationReport::sweep(
+            &runners,
+            &GitLabClient::default(),
+            &Secrets::default(),
+            &metrics,
+            &limits,
+            &Maintenance::default(),
+        )
+        .await;
+        assert!(!report.complete);
+        assert_eq!(report.unchecked, 3);
+
+        // runners of instances in maintenance are skipped, and the window is reported instead
+        let window = MaintenanceWindow::new(
+            Url::parse("http://127.0.0.1:1")?,
+            "2024-07-01T12:00:00Z".parse()?,
+            None,
+        );
+        let maintenance: Maintenance = [window.clone()].into_iter().collect();
+        let report = VerificationReport::sweep(
+            &runners,
+            &GitLabClient::default(),
+            &Secrets::default(),
+            &metrics,
+            &Verification::default(),
+            &maintenance,
+        )
+        .await;
+        assert_eq!((report.failed, report.in_maintenance), (0, 1));
+        assert_eq!(report.runners[2].status, VerificationStatus::Maintenance);
+        assert_eq!(report.maintenance, vec![window]);
+
+        Ok(())
+    }
+
+    // on a paused clock, so that the waits are exact however busy the machine is
+    #[tokio::test(start_paused = true)]
+    async fn rate_limit_per_instance() -> Result<()> {
+        let limiter = RateLimiter::new(10);
+        let (gitlab, other) = (
+            Url::parse("https://gitlab.your-company.com")?,
+            Url::parse("https://gitlab.bmc-labs.com")?,
+        );
+
+        let start = Instant::now();
+        limiter.wait(&gitlab).await;
+        limiter.wait(&other).await;
+        assert_eq!(start.elapsed(), Duration::ZERO);
+
+        limiter.wait(&gitlab).await;
+        limiter.wait(&gitlab).await;
+        assert_eq!(start.elapsed(), Duration::from_millis(200));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,26 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use futures::{future::BoxFuture, FutureExt};
+
+use super::{Channel, Notification};
+use crate::{error::Error, webhooks::WebhookClient};
+
+/// POSTs notifications as JSON with their `kind`, `subject`, `message` and `raised_at`, for
+/// receivers like incident management tools.
+#[derive(Debug)]
+pub struct Generic {
+    webhooks: WebhookClient,
+    url: String,
+}
+
+impl Generic {
+    pub fn new(webhooks: WebhookClient, url: String) -> Self {
+        Self { webhooks, url }
+    }
+}
+
+impl Channel for Generic {
+    fn send<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<(), Error>> {
+        self.webhooks.post(&self.url, notification).boxed()
+    }
+}
@@ -0,0 +1,104 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use futures::{future::BoxFuture, FutureExt};
+use serde_json::json;
+use uuid::Uuid;
+
+use super::{Channel, Notification};
+use crate::{error::Error, webhooks::WebhookClient};
+
+/// Sends notifications as text messages to a Matrix room, via the client-server API of the
+/// homeserver.
+#[derive(Debug)]
+pub struct Matrix {
+    webhooks: WebhookClient,
+    homeserver_url: String,
+    room_id: String,
+    access_token: String,
+}
+
+impl Matrix {
+    pub fn new(
+        webhooks: WebhookClient,
+        homeserver_url: String,
+        room_id: String,
+        access_token: String,
+    ) -> Self {
+        Self {
+            webhooks,
+            homeserver_url,
+            room_id,
+            access_token,
+        }
+    }
+
+    /// URL a message event is sent to. The transaction ID is new for every notification, since
+    /// the homeserver drops events with a transaction ID it has seen before.
+    fn send_url(&self) -> Result<reqwest::Url, Error> {
+        let mut url = reqwest::Url::parse(&self.homeserver_url).map_err(|err| {
+            Error::invalid_argument(format!(
+                "invalid Matrix homeserver URL '{}': {err}",
+                self.homeserver_url
+            ))
+        })?;
+        url.path_segments_mut()
+            .map_err(|()| {
+                Error::invalid_argument(format!(
+                    "invalid Matrix homeserver URL '{}'",
+                    self.homeserver_url
+                ))
+            })?
+            .pop_if_empty()
+            // room IDs contain `!` and `:`, which the segments are percent-encoded for
+            .extend([
+                "_matrix",
+                "client",
+                "v3",
+                "rooms",
+                &self.room_id,
+                "send",
+                "m.room.message",
+                &Uuid::new_v4().to_string(),
+            ]);
+
+        Ok(url)
+    }
+}
+
+impl Channel for Matrix {
+    fn send<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let body = format!("{}\n{}", notification.subject, notification.message);
+            self.webhooks
+                .put_authorized(
+                    self.send_url()?,
+                    &self.access_token,
+                    &json!({ "msgtype": "m.text", "body": body }),
+                )
+                .await
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Matrix;
+    use crate::webhooks::WebhookClient;
+
+    #[test]
+    fn encode_room_id() {
+        let matrix = Matrix::new(
+            WebhookClient::default(),
+            "https://matrix.your-company.com/".to_string(),
+            "!ops:your-company.com".to_string(),
+            "syt_warblgarbl".to_string(),
+        );
+        let url = matrix.send_url().unwrap();
+
+        assert!(url.as_str().starts_with(
+            "https://matrix.your-company.com/_matrix/client/v3/rooms/!ops:your-company.com/send/\
+             m.room.message/"
+        ));
+    }
+}
@@ -0,0 +1,597 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+mod generic;
+mod matrix;
+mod slack;
+mod smtp;
+
+use std::{collections::HashSet, fmt, sync::Arc, time::Duration};
+
+use atmosphere::Read as _;
+use chrono::{DateTime, TimeDelta, Utc};
+use futures::{future::BoxFuture, FutureExt};
+use lettre::transport::smtp::authentication::Credentials;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use self::{generic::Generic, matrix::Matrix, slack::Slack, smtp::Smtp};
+use crate::{
+    error::Error,
+    models::{
+        ConfigCache, ConfigSync, ConfigSyncStatus, ConfigTarget, DeadLetter, GitLabRunner,
+        QuotaUsage,
+    },
+    settings::{Notifications, Settings, SettingsStore, REDACTED},
+    subsystems::{Shutdown, Subsystem},
+    webhooks::WebhookClient,
+};
+
+/// How often the notifier checks whether it was turned on while it is off.
+const IDLE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Kind of condition operators are notified of.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    /// The config on disk differs from the database although no write is pending, e.g. because
+    /// it was edited by hand
+    ConfigDrift,
+    /// Writing the config failed, and retrying the write failed as well
+    ConfigWriteFailed,
+    /// A runner token expires soon, or has expired
+    TokenExpiring,
+    /// A quota is used up, so no more runners counting towards it can be created
+    QuotaReached,
+}
+
+impl fmt::Display for NotificationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            Self::ConfigDrift => "config_drift",
+            Self::ConfigWriteFailed => "config_write_failed",
+            Self::TokenExpiring => "token_expiring",
+            Self::QuotaReached => "quota_reached",
+        };
+        f.write_str(kind)
+    }
+}
+
+/// A human-readable alert about a condition.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Notification {
+    kind: NotificationKind,
+    /// Identifies the condition, so that it is notified of only once while it persists
+    #[serde(skip)]
+    key: String,
+    subject: String,
+    message: String,
+    raised_at: DateTime<Utc>,
+}
+
+impl Notification {
+    fn new(kind: NotificationKind, key: String, subject: String, message: String) -> Self {
+        Self {
+            kind,
+            key,
+            subject,
+            message,
+            raised_at: Utc::now(),
+        }
+    }
+}
+
+/// Destination of notifications, as configured in the `notifications` settings.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChannelConfig {
+    /// Slack incoming webhook
+    Slack { webhook_url: String },
+    /// Matrix room, posted to as the user the access token belongs to
+    Matrix {
+        homeserver_url: String,
+        room_id: String,
+        access_token: String,
+    },
+    /// Plain text email via an SMTP relay at `host` or `host:port`, authenticated with
+    /// `username` and `password` if they are set
+    Smtp {
+        relay: String,
+        #[serde(default)]
+        tls: SmtpTls,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        username: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        password: Option<String>,
+        from: String,
+        to: Vec<String>,
+    },
+    /// Any URL the notification is POSTed to as JSON
+    Generic { url: String },
+}
+
+/// Like the settings API, the Debug output leaves out the secrets, since the settings are logged
+/// whenever they are replaced.
+impl fmt::Debug for ChannelConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Slack { .. } => f
+                .debug_struct("Slack")
+                .field("webhook_url", &REDACTED)
+                .finish(),
+            Self::Matrix {
+                homeserver_url,
+                room_id,
+                ..
+            } => f
+                .debug_struct("Matrix")
+                .field("homeserver_url", homeserver_url)
+                .field("room_id", room_id)
+                .field("access_token", &REDACTED)
+                .finish(),
+            Self::Smtp {
+                relay,
+                tls,
+                username,
+                password,
+                from,
+                to,
+            } => f
+                .debug_struct("Smtp")
+                .field("relay", relay)
+                .field("tls", tls)
+                .field("username", username)
+                .field("password", &password.as_ref().map(|_| REDACTED))
+                .field("from", from)
+                .field("to", to)
+                .finish(),
+            Self::Generic { .. } => f.debug_struct("Generic").field("url", &REDACTED).finish(),
+        }
+    }
+}
+
+/// How the session with an SMTP relay is encrypted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpTls {
+    /// Plain text, e.g. for the local MTA
+    None,
+    /// STARTTLS if the relay offers it, plain text otherwise
+    #[default]
+    Opportunistic,
+    /// STARTTLS, failing if the relay doesn't offer it; the relay's port defaults to 587
+    Starttls,
+    /// TLS from the start; the relay's port defaults to 465
+    Tls,
+}
+
+impl ChannelConfig {
+    /// Sets up the channel described by the config.
+    fn channel(&self, webhooks: &WebhookClient) -> Result<Box<dyn Channel>, Error> {
+        let channel: Box<dyn Channel> = match self.clone() {
+            Self::Slack { webhook_url } => Box::new(Slack::new(webhooks.clone(), webhook_url)),
+            Self::Matrix {
+                homeserver_url,
+                room_id,
+                access_token,
+            } => Box::new(Matrix::new(
+                webhooks.clone(),
+                homeserver_url,
+                room_id,
+                access_token,
+            )),
+            Self::Smtp {
+                relay,
+                tls,
+                username,
+                password,
+                from,
+                to,
+            } => {
+                let credentials = match (username, password) {
+                    (Some(username), Some(password)) => Some(Credentials::new(username, password)),
+                    (None, None) => None,
+                    _ => {
+                        return Err(Error::invalid_argument(
+                            "SMTP channels need both a username and a password, or neither",
+                        ))
+                    }
+                };
+                Box::new(Smtp::new(&relay, tls, credentials, &from, &to)?)
+            }
+            Self::Generic { url } => Box::new(Generic::new(webhooks.clone(), url)),
+        };
+
+        Ok(channel)
+    }
+
+    /// Checks that the channel can be set up, e.g. that email addresses are valid.
+    pub fn validate(&self) -> Result<(), Error> {
+        self.channel(&WebhookClient::default()).map(|_| ())
+    }
+}
+
+/// Delivers notifications to a destination.
+pub trait Channel: fmt::Debug + Send + Sync {
+    fn send<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<(), Error>>;
+}
+
+/// Checks for conditions needing the attention of operators, and notifies them via the channels
+/// routed to. Each condition is notified of once; if it clears and arises again, it is notified
+/// of again.
+#[derive(Debug, Clone)]
+pub struct Notifier {
+    pool: atmosphere::Pool,
+    target: Arc<ConfigTarget>,
+    cache: Arc<ConfigCache>,
+    settings: Arc<SettingsStore>,
+    webhooks: WebhookClient,
+}
+
+impl Notifier {
+    pub fn new(
+        pool: atmosphere::Pool,
+        target: Arc<ConfigTarget>,
+        cache: Arc<ConfigCache>,
+        settings: Arc<SettingsStore>,
+    ) -> Self {
+        Self {
+            pool,
+            target,
+            cache,
+            settings,
+            webhooks: WebhookClient::default(),
+        }
+    }
+
+    /// Notifies of the conditions which arose since the last check; `active` holds the keys of
+    /// the conditions already notified of.
+    async fn check(&self, active: &mut HashSet<String>) -> Result<(), Error> {
+        let settings = self.settings.load();
+
+        let current = self.conditions(&settings).await?;
+        active.retain(|key| current.iter().any(|notification| notification.key == *key));
+
+        for notification in current {
+            if active.insert(notification.key.clone()) {
+                self.dispatch(&settings.notifications, &notification).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns a notification for every condition which currently needs attention.
+    async fn conditions(&self, settings: &Settings) -> Result<Vec<Notification>, Error> {
+        let mut notifications = Vec::new();
+
+        let sync = ConfigSyncStatus::current(&self.pool).await?;
+        match sync.state() {
+            ConfigSync::Failed => notifications.push(Notification::new(
+                NotificationKind::ConfigWriteFailed,
+                "config_write_failed".to_string(),
+                "Writing the runner config keeps failing".to_string(),
+                format!(
+                    "The config at {} can't be written: {}. The write is retried in the \
+                     background; until it succeeds, gitlab-runner doesn't see runner changes.",
+                    self.target.load().display(),
+                    sync.last_error().unwrap_or("unknown error")
+                ),
+            )),
+            // while a write is pending, the config on disk is expected to be outdated
+            ConfigSync::Pending => {}
+            ConfigSync::Synced => notifications.extend(self.drift(settings).await),
+        }
+
+        let expiring_before = i64::try_from(settings.notifications.token_expiry_secs)
+            .ok()
+            .and_then(TimeDelta::try_seconds)
+            .and_then(|expiry| Utc::now().checked_add_signed(expiry))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        for runner in GitLabRunner::read_all(&self.pool).await? {
+            let Some(expires_at) = runner
+                .token_expires_at()
+                .filter(|expires_at| *expires_at.as_chrono() <= expiring_before)
+            else {
+                continue;
+            };
+            let expires = if expires_at.is_past() {
+                "expired"
+            } else {
+                "expires"
+            };
+            notifications.push(Notification::new(
+                NotificationKind::TokenExpiring,
+                format!(
+                    "token_expiring:{}:{}",
+                    runner.uuid(),
+                    expires_at.to_iso8601()
+                ),
+                format!("Token of runner '{}' {expires}", runner.name()),
+                format!(
+                    "The token of runner '{}' ({}) of {} {expires} at {}. Renew it in GitLab \
+                     and update the runner, or it won't pick up jobs.",
+                    runner.name(),
+                    runner.uuid(),
+                    runner.url(),
+                    expires_at.to_iso8601()
+                ),
+            ));
+        }
+
+        let usage = QuotaUsage::read(&self.pool, &settings.quotas).await?;
+        for (quota, description) in usage.exhausted() {
+            notifications.push(Notification::new(
+                NotificationKind::QuotaReached,
+                format!("quota_reached:{quota}"),
+                "Runner quota reached".to_string(),
+                format!(
+                    "{description} are in use; creating more is rejected until runners are \
+                     deleted or the quota is raised."
+                ),
+            ));
+        }
+
+        Ok(notifications)
+    }
+
+    /// Compares the config on disk with the config rendered from the database.
+    async fn drift(&self, settings: &Settings) -> Option<Notification> {
+        let rendered = match self.cache.render(&self.pool, &settings.render).await {
+            Ok(rendered) => rendered,
+            // e.g. a secret store being unreachable must not keep other conditions from being
+            // notified of
+            Err(err) => {
+                tracing::warn!(%err, "rendering config for drift check failed");
+                return None;
+            }
+        };
+
+        let path = self.target.load();
+        let on_disk = tokio::fs::read_to_string(&*path).await.ok();
+        (on_disk.as_deref() != Some(rendered.as_str())).then(|| {
+            Notification::new(
+                NotificationKind::ConfigDrift,
+                "config_drift".to_string(),
+                "Runner config drifted".to_string(),
+                format!(
+                    "The config at {} doesn't match the runners in the database. Changes made \
+                     to it by hand are overwritten with the next change to the runners.",
+                    path.display()
+                ),
+            )
+        })
+    }
+
+    /// Sends the notification to the channels routed to for its kind. Delivery is attempted
+    /// once; failures are logged and moved to the dead letters, so that an unreachable channel
+    /// doesn't hold up the others.
+    async fn dispatch(&self, notifications: &Notifications, notification: &Notification) {
+        let Some(channels) = notifications.routes.get(&notification.kind) else {
+            return;
+        };
+
+        for name in channels {
+            if !notifications.channels.contains_key(name) {
+                continue;
+            }
+            match send_via(notifications, &self.webhooks, name, notification).await {
+                Ok(()) => {
+                    tracing::info!(kind = %notification.kind, channel = %name, "notification sent")
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        %err,
+                        kind = %notification.kind,
+                        channel = %name,
+                        "sending notification failed"
+                    );
+                    let dead =
+                        DeadLetter::notification(&self.pool, name, notification, &err.msg).await;
+                    if let Err(err) = dead {
+                        tracing::error!(%err, "keeping undelivered notification failed");
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Sends the notification via the channel called `name`, as it is configured now.
+pub(crate) async fn send_via(
+    notifications: &Notifications,
+    webhooks: &WebhookClient,
+    name: &str,
+    notification: &Notification,
+) -> Result<(), Error> {
+    let Some(config) = notifications.channels.get(name) else {
+        return Err(Error::invalid_argument(format!(
+            "notification channel '{name}' doesn't exist (anymore)"
+        )));
+    };
+
+    config.channel(webhooks)?.send(notification).await
+}
+
+impl Subsystem for Notifier {
+    fn name(&self) -> &'static str {
+        "notifier"
+    }
+
+    fn run(&self, mut shutdown: Shutdown) -> BoxFuture<'static, Result<(), Error>> {
+        let this = self.clone();
+
+        async move {
+            let mut active = HashSet::new();
+            loop {
+                // the interval is read anew each time, so notifications can be turned on and off
+                // via the settings API
+                let interval = this.settings.load().notifications.interval();
+                if interval.is_some() {
+                    this.check(&mut active).await?;
+                }
+
+                tokio::select! {
+                    _ = tokio::time::sleep(interval.unwrap_or(IDLE_INTERVAL)) => {}
+                    _ = shutdown.requested() => return Ok(()),
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeMap, HashSet},
+        sync::{Arc, Mutex},
+    };
+
+    use atmosphere::{Create as _, Pool};
+    use axum::{extract::State, routing::post, Json, Router};
+    use chrono::{TimeDelta, Utc};
+    use pretty_assertions::assert_eq;
+
+    use super::{ChannelConfig, NotificationKind, Notifier, SmtpTls};
+    use crate::{
+        models::{ConfigCache, ConfigTarget, GitLabRunner},
+        settings::{Notifications, Quotas, Settings, SettingsStore, REDACTED},
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn notify_once_per_condition(pool: Pool) -> Result<()> {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let receiver = Router::new()
+            .route(
+                "/:channel",
+                post(
+                    |State(received): State<Arc<Mutex<Vec<serde_json::Value>>>>,
+                     Json(payload): Json<serde_json::Value>| async move {
+                        received.lock().expect("lock is not poisoned").push(payload);
+                    },
+                ),
+            )
+            .with_state(received.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let receiver_url = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, receiver).await });
+
+        let mut runner = GitLabRunner::for_testing();
+        runner.set_token_expires_at((Utc::now() + TimeDelta::hours(1)).into());
+        runner.create(&pool).await?;
+
+        let settings = Settings {
+            quotas: Quotas {
+                max_runners: 1,
+                ..Default::default()
+            },
+            notifications: Notifications {
+                channels: BTreeMap::from([
+                    (
+                        "ops-slack".to_string(),
+                        ChannelConfig::Slack {
+                            webhook_url: format!("{receiver_url}/slack"),
+                        },
+                    ),
+                    (
+                        "inventory".to_string(),
+                        ChannelConfig::Generic {
+                            url: format!("{receiver_url}/generic"),
+                        },
+                    ),
+                ]),
+                routes: BTreeMap::from([
+                    (
+                        NotificationKind::TokenExpiring,
+                        vec!["ops-slack".to_string()],
+                    ),
+                    (
+                        NotificationKind::QuotaReached,
+                        vec!["inventory".to_string()],
+                    ),
+                ]),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        settings.notifications.validate()?;
+        let notifier = Notifier::new(
+            pool.clone(),
+            Arc::new(ConfigTarget::new(
+                std::env::temp_dir().join(format!("runrs-notify-{}.toml", uuid::Uuid::new_v4())),
+            )),
+            Arc::new(ConfigCache::default()),
+            Arc::new(SettingsStore::new(settings)),
+        );
+
+        let mut active = HashSet::new();
+        notifier.check(&mut active).await?;
+        // the config was never written, so it drifted, but that kind isn't routed anywhere
+        assert!(active.contains("config_drift"));
+
+        let payloads = received.lock().expect("lock is not poisoned").clone();
+        assert_eq!(payloads.len(), 2);
+        let slack = payloads
+            .iter()
+            .find(|payload| payload.get("text").is_some())
+            .ok_or("no Slack message")?;
+        assert!(slack["text"]
+            .as_str()
+            .is_some_and(|text| text.contains("Knows the meaning of life")));
+        let generic = payloads
+            .iter()
+            .find(|payload| payload.get("kind").is_some())
+            .ok_or("no generic notification")?;
+        assert_eq!(generic["kind"], "quota_reached");
+
+        // conditions which persist are not notified of again
+        notifier.check(&mut active).await?;
+        assert_eq!(received.lock().expect("lock is not poisoned").len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_routes_to_unknown_channels() {
+        let notifications = Notifications {
+            routes: BTreeMap::from([(NotificationKind::ConfigDrift, vec!["warbl".to_string()])]),
+            ..Default::default()
+        };
+        assert!(notifications.validate().is_err());
+    }
+
+    #[test]
+    fn debug_masks_secrets() {
+        let channels = [
+            ChannelConfig::Slack {
+                webhook_url: "https://hooks.slack.com/services/T0/B0/secret".to_string(),
+            },
+            ChannelConfig::Matrix {
+                homeserver_url: "https://matrix.your-company.com".to_string(),
+                room_id: "!ops:your-company.com".to_string(),
+                access_token: "syt_secret".to_string(),
+            },
+            ChannelConfig::Smtp {
+                relay: "mail.your-company.com".to_string(),
+                tls: SmtpTls::Starttls,
+                username: Some("runrs".to_string()),
+                password: Some("secret".to_string()),
+                from: "runrs@your-company.com".to_string(),
+                to: vec!["ops@your-company.com".to_string()],
+            },
+            ChannelConfig::Generic {
+                url: "https://inventory.your-company.com/hook?key=secret".to_string(),
+            },
+        ];
+
+        for channel in channels {
+            let debug = format!("{channel:?}");
+            assert!(!debug.contains("secret"), "{debug}");
+            assert!(debug.contains(REDACTED), "{debug}");
+        }
+    }
+}
@@ -0,0 +1,35 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use futures::{future::BoxFuture, FutureExt};
+use serde_json::json;
+
+use super::{Channel, Notification};
+use crate::{error::Error, webhooks::WebhookClient};
+
+/// Posts notifications to a Slack channel via an incoming webhook.
+#[derive(Debug)]
+pub struct Slack {
+    webhooks: WebhookClient,
+    webhook_url: String,
+}
+
+impl Slack {
+    pub fn new(webhooks: WebhookClient, webhook_url: String) -> Self {
+        Self {
+            webhooks,
+            webhook_url,
+        }
+    }
+}
+
+impl Channel for Slack {
+    fn send<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let text = format!("*{}*\n{}", notification.subject, notification.message);
+            self.webhooks
+                .post(&self.webhook_url, &json!({ "text": text }))
+                .await
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,241 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::time::Duration;
+
+use futures::{future::BoxFuture, FutureExt};
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::{
+        authentication::Credentials,
+        client::{Tls, TlsParameters},
+        extension::ClientId,
+    },
+    AsyncSmtpTransport, AsyncTransport as _, Message, Tokio1Executor,
+};
+
+use super::{Channel, Notification, SmtpTls};
+use crate::error::Error;
+
+/// How long a whole SMTP session may take before delivery counts as failed.
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Sends notifications as plain text email via an SMTP relay. lettre speaks the protocol, so
+/// the session is encrypted and authenticated as configured, and headers are encoded as needed.
+#[derive(Debug)]
+pub struct Smtp {
+    relay: String,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+impl Smtp {
+    pub fn new(
+        relay: &str,
+        tls: SmtpTls,
+        credentials: Option<Credentials>,
+        from: &str,
+        to: &[String],
+    ) -> Result<Self, Error> {
+        if credentials.is_some() && matches!(tls, SmtpTls::None | SmtpTls::Opportunistic) {
+            return Err(Error::invalid_argument(
+                "SMTP credentials are only sent with `tls` set to `starttls` or `tls`",
+            ));
+        }
+
+        let (host, port) = host_port(relay, tls)?;
+        let parameters = || {
+            TlsParameters::new(host.to_string()).map_err(|err| {
+                Error::invalid_argument(format!("setting up TLS for {host} failed: {err}"))
+            })
+        };
+        let tls = match tls {
+            SmtpTls::None => Tls::None,
+            SmtpTls::Opportunistic => Tls::Opportunistic(parameters()?),
+            SmtpTls::Starttls => Tls::Required(parameters()?),
+            SmtpTls::Tls => Tls::Wrapper(parameters()?),
+        };
+
+        let mut transport = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+            .port(port)
+            .tls(tls)
+            .hello_name(ClientId::Domain("runrs".to_string()));
+        if let Some(credentials) = credentials {
+            transport = transport.credentials(credentials);
+        }
+
+        Ok(Self {
+            relay: relay.to_string(),
+            transport: transport.build(),
+            from: mailbox(from)?,
+            to: to.iter().map(|to| mailbox(to)).collect::<Result<_, _>>()?,
+        })
+    }
+
+    fn message(&self, notification: &Notification) -> Result<Message, Error> {
+        let mut message = Message::builder()
+            .from(self.from.clone())
+            .subject(format!("[runrs] {}", notification.subject))
+            .header(ContentType::TEXT_PLAIN);
+        for to in &self.to {
+            message = message.to(to.clone());
+        }
+
+        message
+            .body(notification.message.clone())
+            .map_err(Error::internal_error)
+    }
+}
+
+impl Channel for Smtp {
+    fn send<'a>(&'a self, notification: &'a Notification) -> BoxFuture<'a, Result<(), Error>> {
+        async move {
+            let message = self.message(notification)?;
+            tokio::time::timeout(SMTP_TIMEOUT, self.transport.send(message))
+                .await
+                .map_err(|_| {
+                    Error::connection_failed(format!("SMTP session with {} timed out", self.relay))
+                })?
+                .map_err(|err| {
+                    Error::connection_failed(format!(
+                        "sending mail via {} failed: {err}",
+                        self.relay
+                    ))
+                })?;
+
+            Ok(())
+        }
+        .boxed()
+    }
+}
+
+/// Splits the relay into host and port, defaulting to the port commonly used with `tls`.
+fn host_port(relay: &str, tls: SmtpTls) -> Result<(&str, u16), Error> {
+    let Some((host, port)) = relay
+        .rsplit_once(':')
+        .filter(|(host, _)| !host.ends_with(':'))
+    else {
+        let port = match tls {
+            SmtpTls::None | SmtpTls::Opportunistic => 25,
+            SmtpTls::Starttls => 587,
+            SmtpTls::Tls => 465,
+        };
+        return Ok((relay, port));
+    };
+
+    let port = port
+        .parse()
+        .map_err(|_| Error::invalid_argument(format!("invalid port in SMTP relay '{relay}'")))?;
+    Ok((host.trim_start_matches('[').trim_end_matches(']'), port))
+}
+
+fn mailbox(address: &str) -> Result<Mailbox, Error> {
+    address
+        .parse()
+        .map_err(|err| Error::invalid_argument(format!("invalid email address '{address}': {err}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use lettre::transport::smtp::authentication::Credentials;
+    use pretty_assertions::assert_eq;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    use super::{host_port, Smtp};
+    use crate::notifications::{Channel, Notification, NotificationKind, SmtpTls};
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[tokio::test]
+    async fn send_via_relay() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let relay = listener.local_addr()?.to_string();
+
+        let received = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await?;
+            let mut stream = BufReader::new(stream);
+            let mut lines = Vec::new();
+            stream.get_mut().write_all(b"220 relay ready\r\n").await?;
+            loop {
+                let mut line = String::new();
+                if stream.read_line(&mut line).await? == 0 {
+                    break;
+                }
+                let reply: &[u8] = match line.as_str() {
+                    "EHLO runrs\r\n" => b"250-relay\r\n250 8BITMIME\r\n",
+                    "DATA\r\n" => b"354 go ahead\r\n",
+                    ".\r\n" => b"250 queued\r\n",
+                    "QUIT\r\n" => b"221 bye\r\n",
+                    _ if line.starts_with("MAIL") || line.starts_with("RCPT") => b"250 ok\r\n",
+                    _ => b"",
+                };
+                lines.push(line);
+                stream.get_mut().write_all(reply).await?;
+            }
+            std::io::Result::Ok(lines)
+        });
+
+        let smtp = Smtp::new(
+            &relay,
+            SmtpTls::default(),
+            None,
+            "runrs@your-company.com",
+            &["ops@your-company.com".to_string()],
+        )?;
+        let notification = Notification::new(
+            NotificationKind::ConfigDrift,
+            "config_drift".to_string(),
+            "Runner config geändert\r\nBcc: drifted".to_string(),
+            "first line\n.second line".to_string(),
+        );
+        smtp.send(&notification).await?;
+
+        let lines = received.await??;
+        assert_eq!(lines[1], "MAIL FROM:<runrs@your-company.com>\r\n");
+        assert_eq!(lines[2], "RCPT TO:<ops@your-company.com>\r\n");
+        // the subject is encoded as per RFC 2047, so it can't inject headers
+        assert!(lines
+            .iter()
+            .any(|line| line.starts_with("Subject: [runrs] ")));
+        assert!(lines.concat().to_ascii_lowercase().contains("=?utf-8?b?"));
+        assert!(!lines.iter().any(|line| line.starts_with("Bcc:")));
+        assert!(lines.contains(&"..second line\r\n".to_string()));
+        assert_eq!(lines[lines.len() - 2..], [".\r\n", "QUIT\r\n"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn credentials_need_tls() {
+        let credentials = Credentials::new("runrs".to_string(), "hunter2".to_string());
+        for tls in [SmtpTls::None, SmtpTls::Opportunistic] {
+            assert!(Smtp::new(
+                "mail.your-company.com",
+                tls,
+                Some(credentials.clone()),
+                "runrs@your-company.com",
+                &[],
+            )
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn relay_ports() -> Result<()> {
+        assert_eq!(
+            host_port("mail.your-company.com", SmtpTls::Starttls)?,
+            ("mail.your-company.com", 587)
+        );
+        assert_eq!(
+            host_port("mail.your-company.com:2525", SmtpTls::Tls)?,
+            ("mail.your-company.com", 2525)
+        );
+        assert_eq!(host_port("[::1]:25", SmtpTls::None)?, ("::1", 25));
+        assert!(host_port("mail.your-company.com:smtp", SmtpTls::None).is_err());
+
+        Ok(())
+    }
+}
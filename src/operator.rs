@@ -0,0 +1,507 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use futures::{future::BoxFuture, FutureExt, StreamExt};
+use k8s_openapi::api::core::v1::Secret;
+use kube::{
+    api::{ObjectMeta, Patch, PatchParams},
+    runtime::{
+        controller::{self, Action, Controller},
+        finalizer::{self, finalizer},
+        reflector::Store,
+        watcher,
+    },
+    Api, Client, CustomResource, ResourceExt,
+};
+use schemars::{
+    gen::SchemaGenerator,
+    schema::{InstanceType, Schema, SchemaObject},
+    JsonSchema,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::{
+    error::Error,
+    gitops::{self, GitOpsController, ReconcileReport, RunnerKey},
+    models::{ConfigCache, ConfigTarget, GitLabRunner},
+    secrets::Secrets,
+    settings::{Settings, SettingsStore},
+    subsystems::{Shutdown, Subsystem},
+};
+
+/// How often the operator checks whether operator mode was turned on, off or moved to another
+/// namespace.
+const IDLE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long the controller waits for more changes before reconciling, so that e.g. applying a
+/// directory of resources reconciles the runners once rather than once per resource.
+const DEBOUNCE: Duration = Duration::from_secs(1);
+
+/// Actor of the changes made by the operator, in the system events and the recycle bin.
+const OPERATOR_ACTOR: &str = "kubernetes";
+
+/// Field manager of the config Secret, for server-side apply.
+const FIELD_MANAGER: &str = "runrs";
+
+/// Finalizer keeping a deleted resource around until its runner is deleted from the database.
+const FINALIZER: &str = "runrs.bmc-labs.com/runner";
+
+/// Spec of a `GitLabRunner` custom resource, declaring one runner with the same fields as
+/// `POST /gitlab-runners`. It is checked by runrs rather than the schema, so that errors are
+/// reported in the status like those of runners created via the API.
+#[derive(CustomResource, Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[kube(
+    group = "runrs.bmc-labs.com",
+    version = "v1",
+    kind = "GitLabRunner",
+    struct = "RunnerResource",
+    namespaced,
+    status = "RunnerStatus",
+    shortname = "glr",
+    printcolumn = r#"{"name": "Phase", "type": "string", "jsonPath": ".status.phase"}"#,
+    printcolumn = r#"{"name": "UUID", "type": "string", "jsonPath": ".status.uuid"}"#,
+    printcolumn = r#"{"name": "Message", "type": "string", "jsonPath": ".status.message"}"#
+)]
+pub struct RunnerSpec {
+    #[serde(flatten)]
+    fields: serde_json::Map<String, serde_json::Value>,
+}
+
+impl JsonSchema for RunnerSpec {
+    fn schema_name() -> String {
+        "RunnerSpec".to_string()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        Schema::Object(SchemaObject {
+            instance_type: Some(InstanceType::Object.into()),
+            extensions: [(
+                "x-kubernetes-preserve-unknown-fields".to_string(),
+                serde_json::Value::Bool(true),
+            )]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum Phase {
+    /// The runner in the database matches the spec.
+    Ready,
+    /// The spec couldn't be applied; the runner in the database, if any, is left as it was.
+    Failed,
+}
+
+/// Status of a `GitLabRunner` resource, as reported back by the operator.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RunnerStatus {
+    phase: Phase,
+    /// UUID of the runner in the database, if there is one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    uuid: Option<Uuid>,
+    /// Why the spec couldn't be applied
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    observed_generation: Option<i64>,
+}
+
+impl RunnerResource {
+    fn runner(&self) -> Result<GitLabRunner, Error> {
+        serde_json::from_value(serde_json::Value::Object(self.spec.fields.clone()))
+            .map_err(|err| Error::invalid_argument(format!("invalid spec: {err}")))
+    }
+
+    /// Returns the status of the resource after `report`, given the UUIDs of the runners in the
+    /// database.
+    fn status(&self, report: &ReconcileReport, uuids: &BTreeMap<RunnerKey, Uuid>) -> RunnerStatus {
+        let uuid = self
+            .runner()
+            .ok()
+            .and_then(|runner| uuids.get(&gitops::key(&runner)).copied());
+        let (phase, message) = match (report.failure(&self.name_any()), uuid) {
+            (None, Some(_)) => (Phase::Ready, None),
+            (Some(error), _) => (Phase::Failed, Some(error.to_string())),
+            (None, None) => (Phase::Failed, Some("runner wasn't created".to_string())),
+        };
+
+        RunnerStatus {
+            phase,
+            uuid,
+            message,
+            observed_generation: self.metadata.generation,
+        }
+    }
+}
+
+/// Turns an error of the Kubernetes API into one of ours, pointing at the RBAC rules if access was
+/// denied.
+fn kube_error(action: &'static str) -> impl Fn(kube::Error) -> Error {
+    move |err| match err {
+        kube::Error::Api(response) if matches!(response.code, 401 | 403) => Error::forbidden(
+            format!("Kubernetes denied {action}; check the RBAC rules of the service account"),
+        ),
+        err => Error::connection_failed(format!("{action} failed: {err}")),
+    }
+}
+
+/// Reconciles the runners in the database with the `GitLabRunner` resources in the configured
+/// namespace, the same way as GitOps does with the files of its repository: each resource is a
+/// declaration of one runner. The config is written to a Secret for `gitlab-runner` to mount, and
+/// the outcome is reported in the status of each resource.
+#[derive(Debug, Clone)]
+pub struct RunnerOperator {
+    pool: atmosphere::Pool,
+    cache: Arc<ConfigCache>,
+    settings: Arc<SettingsStore>,
+    reconciler: GitOpsController,
+}
+
+/// What the reconciliations of one controller share.
+struct Context {
+    operator: RunnerOperator,
+    resources: Api<RunnerResource>,
+    secrets: Api<Secret>,
+    /// All resources in the namespace, as seen by the controller
+    store: Store<RunnerResource>,
+    /// The config written to the Secret last
+    written: Mutex<Option<String>>,
+}
+
+impl RunnerOperator {
+    pub fn new(
+        pool: atmosphere::Pool,
+        target: Arc<ConfigTarget>,
+        cache: Arc<ConfigCache>,
+        settings: Arc<SettingsStore>,
+        secrets: Secrets,
+    ) -> Self {
+        let reconciler = GitOpsController::new(
+            pool.clone(),
+            target,
+            cache.clone(),
+            settings.clone(),
+            secrets,
+            Arc::default(),
+        )
+        .with_actor(OPERATOR_ACTOR);
+
+        Self {
+            pool,
+            cache,
+            settings,
+            reconciler,
+        }
+    }
+
+    /// Runs a controller watching the resources in `namespace` until shutdown is requested or
+    /// the settings no longer point the operator at `namespace`.
+    async fn control(&self, client: Client, namespace: String, mut shutdown: Shutdown) {
+        let resources: Api<RunnerResource> = Api::namespaced(client.clone(), &namespace);
+        let controller = Controller::new(resources.clone(), watcher::Config::default())
+            // each reconciliation covers all resources, so running them in parallel only races
+            .with_config(
+                controller::Config::default()
+                    .debounce(DEBOUNCE)
+                    .concurrency(1),
+            );
+        let context = Arc::new(Context {
+            operator: self.clone(),
+            resources,
+            secrets: Api::namespaced(client, &namespace),
+            store: controller.store(),
+            written: Mutex::default(),
+        });
+
+        let settings = self.settings.clone();
+        let stop = async move {
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(IDLE_INTERVAL) => {
+                        if settings.load().operator.namespace.as_ref() != Some(&namespace) {
+                            return;
+                        }
+                    }
+                    _ = shutdown.requested() => return,
+                }
+            }
+        };
+
+        controller
+            .graceful_shutdown_on(stop)
+            .run(reconcile, error_policy, context)
+            .for_each(|result| async move {
+                match result {
+                    Ok((resource, _)) => tracing::debug!(name = %resource.name, "reconciled"),
+                    Err(err) => tracing::warn!(%err, "reconciling GitLabRunner resources failed"),
+                }
+            })
+            .await;
+    }
+
+    /// Applies the resources to the database. Returns the report and the status of each resource,
+    /// in the order of `resources`.
+    async fn apply(
+        &self,
+        settings: &Settings,
+        resources: &[Arc<RunnerResource>],
+    ) -> Result<(ReconcileReport, Vec<RunnerStatus>), Error> {
+        let mut report = ReconcileReport::now();
+
+        let declarations = resources
+            .iter()
+            .map(|resource| {
+                let runner = resource.runner().map(|runner| vec![runner]);
+                (resource.name_any(), runner)
+            })
+            .collect();
+        self.reconciler
+            .apply(settings, declarations, &mut report)
+            .await?;
+
+        let uuids: BTreeMap<RunnerKey, Uuid> = GitLabRunner::read_all(&self.pool)
+            .await?
+            .iter()
+            .map(|runner| (gitops::key(runner), *runner.uuid()))
+            .collect();
+        let statuses = resources
+            .iter()
+            .map(|resource| resource.status(&report, &uuids))
+            .collect();
+
+        Ok((report, statuses))
+    }
+}
+
+impl Context {
+    /// Reconciles the runners with all resources which aren't being deleted and reports back.
+    /// The config is only written to the Secret if it differs from the one written last.
+    async fn reconcile(&self, settings: &Settings) -> Result<(), Error> {
+        // reconciling before the first full list would delete the runners of unseen resources
+        self.store
+            .wait_until_ready()
+            .await
+            .map_err(Error::internal_error)?;
+        let mut resources: Vec<_> = self
+            .store
+            .state()
+            .into_iter()
+            .filter(|resource| resource.metadata.deletion_timestamp.is_none())
+            .collect();
+        resources.sort_by_key(|resource| resource.name_any());
+
+        let operator = &self.operator;
+        let (report, statuses) = operator.apply(settings, &resources).await?;
+        if report.changed() || !report.failed.is_empty() {
+            tracing::info!(
+                created = report.created.len(),
+                updated = report.updated.len(),
+                deleted = report.deleted.len(),
+                failed = report.failed.len(),
+                "reconciled runners with GitLabRunner resources"
+            );
+        }
+
+        for (resource, status) in resources.iter().zip(statuses) {
+            if resource.status.as_ref() == Some(&status) {
+                continue;
+            }
+            let name = resource.name_any();
+            let patch = Patch::Merge(serde_json::json!({ "status": status }));
+            if let Err(err) = self
+                .resources
+                .patch_status(&name, &PatchParams::default(), &patch)
+                .await
+                .map_err(kube_error("updating GitLabRunner status"))
+            {
+                tracing::warn!(%err, name, "updating GitLabRunner status failed");
+            }
+        }
+
+        let config_toml = operator
+            .cache
+            .render(&operator.pool, &settings.render)
+            .await?;
+        let mut written = self.written.lock().await;
+        if written.as_ref() != Some(&config_toml) {
+            let secret = Secret {
+                metadata: ObjectMeta {
+                    name: Some(settings.operator.config_secret.clone()),
+                    ..Default::default()
+                },
+                type_: Some("Opaque".to_string()),
+                string_data: Some(BTreeMap::from([(
+                    "config.toml".to_string(),
+                    config_toml.clone(),
+                )])),
+                ..Default::default()
+            };
+            self.secrets
+                .patch(
+                    &settings.operator.config_secret,
+                    &PatchParams::apply(FIELD_MANAGER).force(),
+                    &Patch::Apply(secret),
+                )
+                .await
+                .map_err(kube_error("writing the config Secret"))?;
+            *written = Some(config_toml);
+        }
+
+        Ok(())
+    }
+}
+
+/// Reconciles the runners with the resources after `resource` changed. Whichever resource changed,
+/// all of them are reconciled at once, like the files of a GitOps repository; the finalizer makes
+/// sure a deleted resource's runner is deleted before the resource is gone.
+async fn reconcile(
+    resource: Arc<RunnerResource>,
+    context: Arc<Context>,
+) -> Result<Action, finalizer::Error<Error>> {
+    let settings = context.operator.settings.load();
+    let interval = settings.operator.interval().unwrap_or(IDLE_INTERVAL);
+
+    finalizer(&context.resources, FINALIZER, resource, |_| async {
+        context.reconcile(&settings).await?;
+        Ok(Action::requeue(interval))
+    })
+    .await
+}
+
+fn error_policy(
+    _resource: Arc<RunnerResource>,
+    _err: &finalizer::Error<Error>,
+    context: Arc<Context>,
+) -> Action {
+    // the error is logged where the controller's results are consumed
+    Action::requeue(
+        context
+            .operator
+            .settings
+            .load()
+            .operator
+            .interval()
+            .unwrap_or(IDLE_INTERVAL),
+    )
+}
+
+impl Subsystem for RunnerOperator {
+    fn name(&self) -> &'static str {
+        "operator"
+    }
+
+    fn run(&self, mut shutdown: Shutdown) -> BoxFuture<'static, Result<(), Error>> {
+        let this = self.clone();
+
+        async move {
+            loop {
+                let namespace = this.settings.load().operator.namespace.clone();
+                if let Some(namespace) = namespace {
+                    match Client::try_default().await {
+                        Ok(client) => {
+                            this.control(client, namespace, shutdown.clone()).await;
+                            if shutdown.is_requested() {
+                                return Ok(());
+                            }
+                            continue;
+                        }
+                        Err(err) => tracing::warn!(%err, "setting up Kubernetes client failed"),
+                    }
+                }
+
+                tokio::select! {
+                    _ = tokio::time::sleep(IDLE_INTERVAL) => {}
+                    _ = shutdown.requested() => return Ok(()),
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use atmosphere::{Create as _, Pool};
+    use kube::CustomResourceExt as _;
+    use pretty_assertions::assert_eq;
+
+    use super::{Phase, RunnerOperator, RunnerResource, RunnerSpec};
+    use crate::{
+        models::{ConfigTarget, GitLabRunner},
+        settings::Settings,
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    fn resource(name: &str, generation: i64, spec: serde_json::Value) -> Result<RunnerResource> {
+        let mut resource = RunnerResource::new(name, serde_json::from_value::<RunnerSpec>(spec)?);
+        resource.metadata.generation = Some(generation);
+        Ok(resource)
+    }
+
+    #[test]
+    fn crd() {
+        let crd = RunnerResource::crd();
+        assert_eq!(
+            crd.metadata.name.as_deref(),
+            Some("gitlabrunners.runrs.bmc-labs.com")
+        );
+        assert_eq!(crd.spec.names.short_names, Some(vec!["glr".to_string()]));
+        assert!(crd.spec.versions[0]
+            .subresources
+            .as_ref()
+            .is_some_and(|subresources| subresources.status.is_some()));
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn reconcile_resources(pool: Pool) -> Result<()> {
+        let config_path = std::env::temp_dir().join(format!("runrs-{}.toml", uuid::Uuid::new_v4()));
+
+        let mut undeclared = GitLabRunner::for_testing();
+        undeclared.set_url("https://gitlab.bmc-labs.com/");
+        undeclared.create(&pool).await?;
+
+        let mut declared = GitLabRunner::for_testing();
+        declared.set_name("declared");
+        let resources = vec![
+            Arc::new(resource("declared", 2, serde_json::to_value(&declared)?)?),
+            Arc::new(resource(
+                "broken",
+                1,
+                serde_json::json!({ "name": "no-url" }),
+            )?),
+        ];
+
+        let operator = RunnerOperator::new(
+            pool.clone(),
+            Arc::new(ConfigTarget::new(config_path)),
+            Arc::default(),
+            Arc::default(),
+            Default::default(),
+        );
+        let (report, statuses) = operator.apply(&Settings::default(), &resources).await?;
+
+        assert_eq!(report.created.len(), 1);
+        // the broken resource keeps the undeclared runner around
+        assert!(report.deleted.is_empty());
+
+        assert_eq!(statuses[0].phase, Phase::Ready);
+        assert!(statuses[0].uuid.is_some());
+        assert_eq!(statuses[0].observed_generation, Some(2));
+        assert_eq!(statuses[1].phase, Phase::Failed);
+        assert!(statuses[1]
+            .message
+            .as_deref()
+            .is_some_and(|message| message.contains("invalid spec")));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,316 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Mutex, PoisonError},
+};
+
+use atmosphere::Read as _;
+use chrono::{DateTime, TimeDelta, Utc};
+use glrcfg::runner::redact_tokens;
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    error::Error,
+    gitops::{key, ReconciledRunner, RunnerKey},
+    models::{Change, DeletedRunner, GitLabRunner, GitLabRunnerConfig},
+    secrets::Secrets,
+    settings::Settings,
+};
+
+/// How long a plan can be applied after it was made.
+const PLAN_VALIDITY: TimeDelta = TimeDelta::hours(1);
+
+/// The full set of runners there should be, for `POST /plan`. Runners are matched up with the
+/// stored ones by their GitLab instance and ID, like GitOps declarations.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct DesiredRunners {
+    pub runners: Vec<GitLabRunner>,
+}
+
+/// Reference to a plan to execute, for `POST /apply`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct PlanApply {
+    #[schema(value_type = String, format = Uuid, example = "0b7e2f4c-3d1a-4c5e-9f8b-7a6d5c4b3a21")]
+    pub plan_id: Uuid,
+}
+
+/// Changes which turn the stored runners into a desired set of runners. A plan is only applied
+/// if the runners didn't change since it was made, and at most once.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Plan {
+    #[schema(value_type = String, format = Uuid, example = "0b7e2f4c-3d1a-4c5e-9f8b-7a6d5c4b3a21")]
+    id: Uuid,
+    create: Vec<ReconciledRunner>,
+    update: Vec<ReconciledRunner>,
+    delete: Vec<ReconciledRunner>,
+    /// Difference between the current config and the config after applying the plan, with
+    /// tokens masked
+    #[schema(
+        example = "~ runner \"usain-bolt\" (https://gitlab.your-company.com/)\n    ~ limit: 2 -> 4\n"
+    )]
+    config_diff: String,
+    /// Until when the plan can be applied
+    #[schema(value_type = String, format = DateTime, example = "2024-07-10T13:00:00Z")]
+    expires_at: DateTime<Utc>,
+    /// Runners as they are stored by the plan, along with how
+    #[serde(skip)]
+    changes: Vec<(Change, GitLabRunner)>,
+    /// When each of the runners was last changed at the time the plan was made
+    #[serde(skip)]
+    base: Vec<(Uuid, DateTime<Utc>)>,
+}
+
+impl Plan {
+    /// Plans the changes turning the stored runners into `desired`. Desired runners are checked
+    /// like those created via the API, and the plan is tried out in a transaction which is rolled
+    /// back, so a plan which breaks quotas or name uniqueness is rejected right away.
+    pub async fn make(
+        pool: &atmosphere::Pool,
+        secrets: &Secrets,
+        settings: &Settings,
+        desired: Vec<GitLabRunner>,
+    ) -> Result<Self, Error> {
+        let current = GitLabRunner::read_all(pool).await?;
+        let stored: BTreeMap<RunnerKey, &GitLabRunner> =
+            current.iter().map(|runner| (key(runner), runner)).collect();
+
+        let mut keys = BTreeSet::new();
+        let mut planned = Vec::with_capacity(desired.len());
+        let mut declared = Vec::new();
+        for (idx, mut runner) in desired.into_iter().enumerate() {
+            let invalid =
+                |err: Error| Error::invalid_argument(format!("runner {}: {}", idx + 1, err.msg));
+
+            runner.normalize(settings).map_err(invalid)?;
+            if !keys.insert(key(&runner)) {
+                return Err(Error::invalid_argument(format!(
+                    "runner {} of {} is declared more than once",
+                    runner.id(),
+                    runner.url()
+                )));
+            }
+
+            let change = match stored.get(&key(&runner)) {
+                Some(existing) => {
+                    runner.declared_over(existing, settings.autoscaling.interval().is_some());
+                    (runner != **existing).then_some(Change::Updated)
+                }
+                None => Some(Change::Created),
+            };
+            if let Some(change) = change {
+                runner.check_token(secrets).await.map_err(invalid)?;
+                runner.check_token_expiry(false).map_err(invalid)?;
+                declared.push((change, runner.clone()));
+            }
+            planned.push(runner);
+        }
+
+        // deleting first frees up quota for the runners replacing them
+        let mut changes: Vec<_> = current
+            .iter()
+            .filter(|runner| !keys.contains(&key(runner)))
+            .map(|runner| (Change::Deleted, runner.clone()))
+            .collect();
+        changes.extend(declared);
+
+        let old = GitLabRunnerConfig::compile_runners(current.clone(), secrets, &settings.render);
+        let new = GitLabRunnerConfig::compile_runners(planned, secrets, &settings.render);
+        let config_diff = old.await?.diff(&new.await?).to_string();
+
+        let mut base: Vec<_> = current
+            .iter()
+            .map(|runner| (*runner.uuid(), runner.updated_at()))
+            .collect();
+        base.sort();
+
+        let runners = |kind: Change| {
+            changes
+                .iter()
+                .filter(|(change, _)| *change == kind)
+                .map(|(_, runner)| ReconciledRunner::from(runner))
+                .collect()
+        };
+        let plan = Self {
+            id: Uuid::new_v4(),
+            create: runners(Change::Created),
+            update: runners(Change::Updated),
+            delete: runners(Change::Deleted),
+            config_diff: redact_tokens(&config_diff).into_owned(),
+            expires_at: Utc::now() + PLAN_VALIDITY,
+            changes,
+            base,
+        };
+
+        let mut tx = pool.begin().await?;
+        plan.execute(&mut tx, settings, None).await?;
+        tx.rollback().await?;
+
+        Ok(plan)
+    }
+
+    /// Whether the plan changes anything at all.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Applies the plan in a single transaction, unless the runners changed since it was made.
+    /// Deleted runners are moved to the recycle bin, noting `actor` as who deleted them.
+    pub async fn apply(
+        &self,
+        pool: &atmosphere::Pool,
+        settings: &Settings,
+        actor: Option<&str>,
+    ) -> Result<(), Error> {
+        let mut tx = pool.begin().await?;
+
+        let mut base: Vec<(Uuid, DateTime<Utc>)> =
+            sqlx::query_as("SELECT uuid, updated_at FROM gitlab_runners")
+                .fetch_all(&mut *tx)
+                .await?;
+        base.sort();
+        if base != self.base {
+            return Err(Error::precondition_failed(
+                "runners changed since the plan was made; make a new plan",
+            ));
+        }
+
+        self.execute(&mut tx, settings, actor).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Makes the planned changes within the transaction of the caller.
+    async fn execute(
+        &self,
+        conn: &mut SqliteConnection,
+        settings: &Settings,
+        actor: Option<&str>,
+    ) -> Result<(), Error> {
+        for (change, runner) in &self.changes {
+            let mut runner = runner.clone();
+            if *change == Change::Deleted {
+                DeletedRunner::record(&mut *conn, &runner, actor).await?;
+            } else {
+                runner
+                    .ensure_unique_name(&mut *conn, settings.name_uniqueness)
+                    .await?;
+                runner
+                    .ensure_within_quotas(&mut *conn, &settings.quotas)
+                    .await?;
+            }
+            runner
+                .apply_in(&mut *conn, *change, settings.events.enabled())
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Plans which were made, but not applied yet, by ID.
+#[derive(Debug, Default)]
+pub struct Plans(Mutex<BTreeMap<Uuid, Plan>>);
+
+impl Plans {
+    /// Keeps the plan for `POST /apply`, dropping plans which expired in the meantime.
+    pub fn insert(&self, plan: Plan) {
+        let mut plans = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Utc::now();
+        plans.retain(|_, plan| plan.expires_at > now);
+        plans.insert(plan.id, plan);
+    }
+
+    /// Hands out the plan with the given ID for applying it; it can't be applied again after.
+    pub fn take(&self, id: &Uuid) -> Result<Plan, Error> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(id)
+            .filter(|plan| plan.expires_at > Utc::now())
+            .ok_or_else(|| Error::not_found(format!("no plan {id}, or it expired or was applied")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use atmosphere::{Create as _, Pool, Read as _};
+    use pretty_assertions::assert_eq;
+
+    use super::{Plan, Plans};
+    use crate::{gitops::ReconciledRunner, models::GitLabRunner, settings::Settings};
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn plan_and_apply(pool: Pool) -> Result<()> {
+        let settings = Settings::default();
+
+        let mut kept = GitLabRunner::for_testing();
+        kept.create(&pool).await?;
+        let mut undeclared = GitLabRunner::for_testing();
+        undeclared.set_url("https://gitlab.bmc-labs.com/");
+        undeclared.set_token("glrt-undeclared_0123456789");
+        undeclared.create(&pool).await?;
+
+        let mut changed = kept.clone();
+        changed.set_job_limit(4);
+        let mut created = GitLabRunner::for_testing();
+        created.set_url("https://gitlab.example.com/");
+        created.set_name("created");
+        created.set_token("glrt-created_0123456789ab");
+
+        let plan = Plan::make(
+            &pool,
+            &Default::default(),
+            &settings,
+            vec![changed.clone(), created.clone()],
+        )
+        .await?;
+        assert_eq!(plan.delete, vec![ReconciledRunner::from(&undeclared)]);
+        assert_eq!(plan.update, vec![ReconciledRunner::from(&kept)]);
+        assert_eq!(plan.create, vec![ReconciledRunner::from(&created)]);
+        assert!(plan.config_diff.contains("~ limit: 0 -> 4"));
+        assert!(plan.config_diff.contains("+ runner \"created\""));
+        // making the plan doesn't change anything yet
+        assert_eq!(GitLabRunner::read_all(&pool).await?.len(), 2);
+
+        let plans = Plans::default();
+        plans.insert(plan.clone());
+        plans.take(&plan.id)?.apply(&pool, &settings, None).await?;
+        assert!(plans.take(&plan.id).is_err());
+
+        assert_eq!(
+            GitLabRunner::read(&pool, kept.uuid()).await?.job_limit(),
+            Some(4)
+        );
+        assert!(GitLabRunner::find(&pool, undeclared.uuid())
+            .await?
+            .is_none());
+        assert!(GitLabRunner::find(&pool, created.uuid()).await?.is_some());
+
+        // the runners changed since, so the plan is outdated
+        assert!(plan.apply(&pool, &settings, None).await.is_err());
+
+        Ok(())
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn reject_duplicates(pool: Pool) -> Result<()> {
+        let runner = GitLabRunner::for_testing();
+        let planned = Plan::make(
+            &pool,
+            &Default::default(),
+            &Settings::default(),
+            vec![runner.clone(), GitLabRunner::for_testing()],
+        )
+        .await;
+        assert!(planned.is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,256 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{str::FromStr, sync::Arc, time::Duration};
+
+use futures::{future::BoxFuture, FutureExt};
+use tokio::process::Command;
+
+use crate::{
+    error::Error,
+    models::ConfigCache,
+    settings::env_or,
+    subsystems::{Shutdown, Subsystem},
+};
+
+/// How long the reloader waits for further writes before reloading, so that bursts of writes,
+/// e.g. during an import, cause a single reload.
+const RELOAD_DEBOUNCE: Duration = Duration::from_secs(1);
+
+/// How long `pkill`, `systemctl` or `docker` may take before the reload counts as failed.
+const RELOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub static DEFAULT_RELOAD_TARGET: &str = "gitlab-runner";
+
+/// How `gitlab-runner` is made to pick up a written config. Each mode sends `SIGHUP` one way or
+/// another, which makes `gitlab-runner` reread its config without interrupting running jobs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReloadMode {
+    /// `gitlab-runner` isn't reloaded by runrs, e.g. because it watches the config itself.
+    #[default]
+    None,
+    /// The `gitlab-runner` process is sent `SIGHUP`.
+    Sighup,
+    /// The systemd unit of `gitlab-runner` is reloaded.
+    Systemd,
+    /// The main process of the Docker container of `gitlab-runner` is sent `SIGHUP`.
+    Docker,
+}
+
+impl FromStr for ReloadMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "none" => Ok(Self::None),
+            "sighup" => Ok(Self::Sighup),
+            "systemd" => Ok(Self::Systemd),
+            "docker" => Ok(Self::Docker),
+            _ => Err(format!(
+                "invalid reload mode '{mode}'; must be one of sighup, systemd, docker, none"
+            )),
+        }
+    }
+}
+
+/// Reloading of `gitlab-runner` after config writes. It runs commands on the host, so it's only
+/// set from the environment, never via the settings API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reload {
+    /// How `gitlab-runner` is reloaded
+    pub mode: ReloadMode,
+    /// Name of the process sent `SIGHUP`, of the systemd unit or of the Docker container
+    pub target: String,
+}
+
+impl Default for Reload {
+    fn default() -> Self {
+        Self {
+            mode: ReloadMode::default(),
+            target: DEFAULT_RELOAD_TARGET.to_string(),
+        }
+    }
+}
+
+impl Reload {
+    /// Reads the reload settings from the environment, falling back to defaults for unset
+    /// variables.
+    pub fn from_env() -> miette::Result<Self> {
+        let defaults = Self::default();
+
+        let reload = Self {
+            mode: env_or("RELOAD_MODE", defaults.mode)?,
+            target: env_or("RELOAD_TARGET", defaults.target)?,
+        };
+        reload
+            .validate()
+            .map_err(|err| miette::miette!("invalid value for RELOAD_TARGET: {err}"))?;
+
+        Ok(reload)
+    }
+
+    /// Checks that the target can be passed to `pkill`, `systemctl` or `docker` as a name.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.target.is_empty() || self.target.starts_with('-') {
+            return Err(Error::invalid_argument(format!(
+                "invalid reload target '{}'; must be a process, unit or container name",
+                self.target
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the program and arguments reloading `gitlab-runner`, if it is reloaded at all.
+    fn command(&self) -> Option<(&'static str, Vec<&str>)> {
+        match self.mode {
+            ReloadMode::None => None,
+            ReloadMode::Sighup => Some(("pkill", vec!["-HUP", "-x", &self.target])),
+            ReloadMode::Systemd => Some(("systemctl", vec!["reload", "--", &self.target])),
+            ReloadMode::Docker => {
+                Some(("docker", vec!["kill", "--signal=HUP", "--", &self.target]))
+            }
+        }
+    }
+
+    /// Makes `gitlab-runner` pick up the config on disk.
+    async fn run(&self) -> Result<(), Error> {
+        let Some((program, args)) = self.command() else {
+            return Ok(());
+        };
+
+        let output = tokio::time::timeout(
+            RELOAD_TIMEOUT,
+            Command::new(program)
+                .args(&args)
+                .kill_on_drop(true)
+                .output(),
+        )
+        .await
+        .map_err(|_| Error::internal_error(format!("{program} timed out")))?
+        .map_err(|err| Error::internal_error(format!("running {program} failed: {err}")))?;
+
+        if !output.status.success() {
+            return Err(Error::internal_error(format!(
+                "{program} {} failed with {}: {}",
+                args.join(" "),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Reloads `gitlab-runner` after the config was written, as configured by `RELOAD_MODE`.
+#[derive(Debug, Clone)]
+pub struct Reloader {
+    cache: Arc<ConfigCache>,
+    reload: Reload,
+}
+
+impl Reloader {
+    pub fn new(cache: Arc<ConfigCache>, reload: Reload) -> Self {
+        Self { cache, reload }
+    }
+}
+
+impl Subsystem for Reloader {
+    fn name(&self) -> &'static str {
+        "reloader"
+    }
+
+    fn run(&self, mut shutdown: Shutdown) -> BoxFuture<'static, Result<(), Error>> {
+        let this = self.clone();
+
+        async move {
+            let mut writes = this.cache.subscribe_writes();
+            loop {
+                tokio::select! {
+                    changed = writes.changed() => if changed.is_err() { return Ok(()) },
+                    _ = shutdown.requested() => return Ok(()),
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(RELOAD_DEBOUNCE) => {}
+                    _ = shutdown.requested() => return Ok(()),
+                }
+                // writes during the debounce are covered by this reload
+                writes.borrow_and_update();
+
+                match this.reload.run().await {
+                    Ok(()) if this.reload.mode == ReloadMode::None => {}
+                    Ok(()) => tracing::info!(mode = ?this.reload.mode, "reloaded gitlab-runner"),
+                    // the config on disk is correct, so this is retried with the next write
+                    Err(err) => tracing::warn!(%err, "reloading gitlab-runner failed"),
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use atmosphere::Pool;
+    use pretty_assertions::assert_eq;
+
+    use super::{Reload, ReloadMode};
+    use crate::{
+        models::{ConfigCache, GitLabRunnerConfig},
+        settings::RenderOptions,
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[test]
+    fn reload_commands() {
+        let reload = |mode| Reload {
+            mode,
+            ..Default::default()
+        };
+
+        assert_eq!(reload(ReloadMode::None).command(), None);
+        assert_eq!(
+            reload(ReloadMode::Sighup).command(),
+            Some(("pkill", vec!["-HUP", "-x", "gitlab-runner"]))
+        );
+        assert_eq!(
+            reload(ReloadMode::Systemd).command(),
+            Some(("systemctl", vec!["reload", "--", "gitlab-runner"]))
+        );
+        assert_eq!(
+            reload(ReloadMode::Docker).command(),
+            Some((
+                "docker",
+                vec!["kill", "--signal=HUP", "--", "gitlab-runner"]
+            ))
+        );
+
+        let invalid = Reload {
+            target: "-9".to_string(),
+            ..reload(ReloadMode::Sighup)
+        };
+        assert!(invalid.validate().is_err());
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn notify_writes(pool: Pool) -> Result<()> {
+        let cache = Arc::<ConfigCache>::default();
+        let mut writes = cache.subscribe_writes();
+        let path = std::env::temp_dir().join(format!("runrs-reload-{}.toml", uuid::Uuid::new_v4()));
+
+        GitLabRunnerConfig::write(&pool, &path, &cache, &RenderOptions::default()).await?;
+        assert!(writes.has_changed()?);
+        writes.borrow_and_update();
+
+        // flushing an up-to-date config doesn't write it
+        GitLabRunnerConfig::flush(&pool, &path, &cache, &RenderOptions::default()).await?;
+        assert!(!writes.has_changed()?);
+
+        std::fs::remove_file(&path)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,568 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use futures::{future::BoxFuture, FutureExt};
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    agents::AgentRegistry,
+    error::Error,
+    models::{Agent, ConfigCache},
+    settings::{Canary, RolloutPolicy, SettingsStore},
+    subsystems::{Shutdown, Subsystem},
+};
+
+/// How often the orchestrator checks whether the config changed and what the canaries report.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A rendered config along with the data version it was rendered for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Release {
+    pub version: u64,
+    pub config: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RolloutPhase {
+    /// Only the canaries receive the changed config
+    Canary,
+    /// A canary reported a failure and automatic rollback is off; the canaries keep the changed
+    /// config until the rollout is promoted or rolled back
+    Halted,
+    /// All agents receive the changed config
+    Promoted,
+    /// The canaries went back to the previous config
+    RolledBack,
+}
+
+/// Rollout of a changed config to the agents.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct RolloutStatus {
+    /// Data version of the config being rolled out
+    #[schema(example = 42)]
+    version: u64,
+    phase: RolloutPhase,
+    /// Names of the agents receiving the config first
+    #[schema(example = json!(["ci-host-01"]))]
+    canaries: Vec<String>,
+    #[schema(value_type = String, format = DateTime, example = "2024-07-03T09:00:00Z")]
+    started_at: DateTime<Utc>,
+    /// When all canaries had applied the config; the health gate starts then
+    #[schema(value_type = String, format = DateTime, example = "2024-07-03T09:00:05Z")]
+    applied_at: Option<DateTime<Utc>>,
+    #[schema(value_type = String, format = DateTime, example = "2024-07-03T09:01:05Z")]
+    finished_at: Option<DateTime<Utc>>,
+    /// Why the rollout halted or was rolled back
+    reason: Option<String>,
+}
+
+/// The rollout policy along with the current or latest rollout.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct RolloutOverview {
+    policy: RolloutPolicy,
+    /// Data version of the config all agents receive, except for the canaries of a rollout in
+    /// progress
+    stable_version: Option<u64>,
+    /// The current or latest rollout
+    rollout: Option<RolloutStatus>,
+}
+
+#[derive(Debug)]
+struct Candidate {
+    release: Release,
+    canaries: Vec<Uuid>,
+    status: RolloutStatus,
+}
+
+impl Candidate {
+    fn in_progress(&self) -> bool {
+        matches!(
+            self.status.phase,
+            RolloutPhase::Canary | RolloutPhase::Halted
+        )
+    }
+
+    fn finish(&mut self, phase: RolloutPhase, reason: Option<String>) {
+        self.status.phase = phase;
+        self.status.finished_at = Some(Utc::now());
+        if reason.is_some() {
+            self.status.reason = reason;
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    stable: Option<Release>,
+    candidate: Option<Candidate>,
+}
+
+/// Decides which config every agent receives: the stable config, or the changed config if the
+/// agent is a canary of a rollout in progress. The [`RolloutOrchestrator`] drives rollouts.
+#[derive(Debug, Default)]
+pub struct ConfigRollout {
+    state: Mutex<State>,
+}
+
+impl ConfigRollout {
+    /// Returns the config the agent is supposed to run; `None` until the orchestrator saw the
+    /// config for the first time.
+    pub fn release_for(&self, agent: &Uuid) -> Option<Release> {
+        let state = self.lock();
+        match &state.candidate {
+            Some(candidate) if candidate.in_progress() && candidate.canaries.contains(agent) => {
+                Some(candidate.release.clone())
+            }
+            _ => state.stable.clone(),
+        }
+    }
+
+    pub fn overview(&self, policy: RolloutPolicy) -> RolloutOverview {
+        let state = self.lock();
+
+        RolloutOverview {
+            policy,
+            stable_version: state.stable.as_ref().map(|stable| stable.version),
+            rollout: state
+                .candidate
+                .as_ref()
+                .map(|candidate| candidate.status.clone()),
+        }
+    }
+
+    /// Rolls the config of the rollout in progress out to all agents right away.
+    pub fn promote(&self) -> Result<RolloutStatus, Error> {
+        let mut state = self.lock();
+        let State { stable, candidate } = &mut *state;
+
+        match candidate {
+            Some(candidate) if candidate.in_progress() => {
+                tracing::info!(version = candidate.release.version, "promoting rollout");
+                candidate.finish(RolloutPhase::Promoted, None);
+                *stable = Some(candidate.release.clone());
+                Ok(candidate.status.clone())
+            }
+            _ => Err(Error::precondition_failed("no rollout in progress")),
+        }
+    }
+
+    /// Sends the canaries of the rollout in progress back to the stable config.
+    pub fn roll_back(&self, reason: &str) -> Result<RolloutStatus, Error> {
+        let mut state = self.lock();
+
+        match &mut state.candidate {
+            Some(candidate) if candidate.in_progress() => {
+                tracing::info!(version = candidate.release.version, reason, "rolling back");
+                candidate.finish(RolloutPhase::RolledBack, Some(reason.to_string()));
+                Ok(candidate.status.clone())
+            }
+            _ => Err(Error::precondition_failed("no rollout in progress")),
+        }
+    }
+
+    /// Starts a rollout if the config changed, and promotes or rolls back the rollout in
+    /// progress depending on what its canaries report.
+    pub async fn reconcile(
+        &self,
+        pool: &atmosphere::Pool,
+        config_cache: &ConfigCache,
+        settings: &SettingsStore,
+        registry: &AgentRegistry,
+    ) -> Result<(), Error> {
+        let settings = settings.load();
+        let policy = &settings.rollout;
+        let release = Release {
+            version: config_cache.version(),
+            config: config_cache.render(pool, &settings.render).await?,
+        };
+
+        // agents are only read from the database if a rollout starts
+        let starts = policy.canary.is_some() && self.lock().starts_rollout(&release);
+        let agents = match starts {
+            true => Some(Agent::list(pool).await?),
+            false => None,
+        };
+
+        self.lock().advance(release, agents, policy, registry);
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl State {
+    /// Returns `true` if `release` differs from both the stable config and the latest rollout.
+    fn starts_rollout(&self, release: &Release) -> bool {
+        let Some(stable) = &self.stable else {
+            return false;
+        };
+
+        stable.config != release.config
+            && self
+                .candidate
+                .as_ref()
+                .map_or(true, |candidate| candidate.release.config != release.config)
+    }
+
+    fn advance(
+        &mut self,
+        release: Release,
+        agents: Option<Vec<Agent>>,
+        policy: &RolloutPolicy,
+        registry: &AgentRegistry,
+    ) {
+        let Some(stable) = &self.stable else {
+            // there's nothing to roll back to yet, e.g. right after starting
+            self.stable = Some(release);
+            return;
+        };
+
+        let Some(canary) = &policy.canary else {
+            // without canaries, changes reach all agents at once
+            if stable.config != release.config {
+                if let Some(candidate) = self.candidate.as_mut().filter(|c| c.in_progress()) {
+                    candidate.finish(RolloutPhase::Promoted, None);
+                }
+                self.stable = Some(release);
+            }
+            return;
+        };
+
+        if stable.config == release.config {
+            if let Some(candidate) = self.candidate.as_mut().filter(|c| c.in_progress()) {
+                candidate.finish(
+                    RolloutPhase::RolledBack,
+                    Some("the config was changed back".to_string()),
+                );
+            }
+            return;
+        }
+
+        if let Some(agents) = agents {
+            self.start(release, canary, agents);
+            return;
+        }
+
+        let Some(candidate) = self
+            .candidate
+            .as_mut()
+            .filter(|candidate| candidate.status.phase == RolloutPhase::Canary)
+        else {
+            return;
+        };
+
+        let connections: Vec<_> = candidate
+            .canaries
+            .iter()
+            .map(|uuid| registry.connection(uuid))
+            .collect();
+
+        let failure =
+            candidate
+                .status
+                .canaries
+                .iter()
+                .zip(&connections)
+                .find_map(|(name, connection)| {
+                    let failure = connection
+                        .as_ref()?
+                        .failure_since(candidate.status.started_at)?;
+                    Some(format!("canary '{name}' reported: {failure}"))
+                });
+        if let Some(reason) = failure {
+            tracing::warn!(version = candidate.release.version, %reason, "rollout failed");
+            match policy.auto_rollback {
+                true => candidate.finish(RolloutPhase::RolledBack, Some(reason)),
+                false => {
+                    candidate.status.phase = RolloutPhase::Halted;
+                    candidate.status.reason = Some(reason);
+                }
+            }
+            return;
+        }
+
+        let version = candidate.release.version;
+        let applied = connections.iter().all(|connection| {
+            connection
+                .as_ref()
+                .is_some_and(|connection| connection.applied_version() == Some(version))
+        });
+        if !applied {
+            return;
+        }
+
+        let applied_at = *candidate.status.applied_at.get_or_insert_with(Utc::now);
+        let healthy_for = (Utc::now() - applied_at).to_std().unwrap_or_default();
+        if healthy_for >= Duration::from_secs(policy.health_gate_secs) {
+            tracing::info!(version, "canaries are healthy, promoting rollout");
+            candidate.finish(RolloutPhase::Promoted, None);
+            self.stable = Some(candidate.release.clone());
+        }
+    }
+
+    /// Starts rolling out `release` to the canaries among `agents`, superseding the rollout in
+    /// progress, if any.
+    fn start(&mut self, release: Release, canary: &Canary, agents: Vec<Agent>) {
+        if agents.is_empty() {
+            // there are no agents to protect
+            self.stable = Some(release);
+            return;
+        }
+
+        let canaries: Vec<Agent> = match canary {
+            Canary::Percent(percent) => {
+                // the first agents by name, so the canaries are the same for every rollout
+                let count = (agents.len() * usize::from(*percent)).div_ceil(100);
+                agents.into_iter().take(count).collect()
+            }
+            Canary::Hosts(hosts) => agents
+                .into_iter()
+                .filter(|agent| hosts.iter().any(|host| host == agent.name()))
+                .collect(),
+        };
+
+        let (phase, reason) = match canaries.is_empty() {
+            true => (
+                RolloutPhase::Halted,
+                Some("none of the canaries is a registered agent".to_string()),
+            ),
+            false => (RolloutPhase::Canary, None),
+        };
+        tracing::info!(version = release.version, ?phase, "starting rollout");
+
+        self.candidate = Some(Candidate {
+            status: RolloutStatus {
+                version: release.version,
+                phase,
+                canaries: canaries
+                    .iter()
+                    .map(|agent| agent.name().to_string())
+                    .collect(),
+                started_at: Utc::now(),
+                applied_at: None,
+                finished_at: None,
+                reason,
+            },
+            canaries: canaries.iter().map(|agent| *agent.uuid()).collect(),
+            release,
+        });
+    }
+}
+
+/// Rolls config changes out to the agents according to the rollout policy.
+#[derive(Debug, Clone)]
+pub struct RolloutOrchestrator {
+    pool: atmosphere::Pool,
+    config_cache: Arc<ConfigCache>,
+    settings: Arc<SettingsStore>,
+    registry: Arc<AgentRegistry>,
+    rollout: Arc<ConfigRollout>,
+}
+
+impl RolloutOrchestrator {
+    pub fn new(
+        pool: atmosphere::Pool,
+        config_cache: Arc<ConfigCache>,
+        settings: Arc<SettingsStore>,
+        registry: Arc<AgentRegistry>,
+        rollout: Arc<ConfigRollout>,
+    ) -> Self {
+        Self {
+            pool,
+            config_cache,
+            settings,
+            registry,
+            rollout,
+        }
+    }
+}
+
+impl Subsystem for RolloutOrchestrator {
+    fn name(&self) -> &'static str {
+        "config-rollout"
+    }
+
+    fn run(&self, mut shutdown: Shutdown) -> BoxFuture<'static, Result<(), Error>> {
+        let this = self.clone();
+
+        async move {
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        this.rollout
+                            .reconcile(&this.pool, &this.config_cache, &this.settings, &this.registry)
+                            .await?;
+                    }
+                    _ = shutdown.requested() => return Ok(()),
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use atmosphere::{Create as _, Delete as _};
+    use axum::extract::ws::Message;
+    use futures::channel::mpsc;
+    use pretty_assertions::assert_eq;
+
+    use super::{ConfigRollout, RolloutPhase};
+    use crate::{
+        agents::{AgentMessage, AgentRegistry, Session},
+        models::{Agent, AgentRegistration, ConfigCache, GitLabRunner},
+        settings::{Canary, RolloutPolicy, Settings, SettingsStore},
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    async fn send(
+        incoming: &mpsc::UnboundedSender<std::result::Result<Message, axum::Error>>,
+        message: &AgentMessage,
+    ) -> Result<()> {
+        incoming.unbounded_send(Ok(Message::Text(serde_json::to_string(message)?)))?;
+        Ok(())
+    }
+
+    /// Waits until the session of the agent handled the messages sent to it.
+    async fn handled(
+        registry: &AgentRegistry,
+        agent: &Agent,
+        condition: impl Fn(&AgentRegistry) -> bool,
+    ) {
+        for _ in 0..50 {
+            if condition(registry) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("agent {} did not report in time", agent.name());
+    }
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    #[tracing_test::traced_test]
+    async fn promote_and_roll_back(pool: atmosphere::Pool) -> Result<()> {
+        let config_cache = Arc::<ConfigCache>::default();
+        let settings = SettingsStore::new(Settings {
+            rollout: RolloutPolicy {
+                canary: Some(Canary::Hosts(vec!["ci-host-01".to_string()])),
+                health_gate_secs: 0,
+                auto_rollback: true,
+            },
+            ..Default::default()
+        });
+        let registry = Arc::<AgentRegistry>::default();
+        let rollout = Arc::<ConfigRollout>::default();
+        let reconcile = || rollout.reconcile(&pool, &config_cache, &settings, &registry);
+
+        let canary =
+            Agent::register(&pool, &AgentRegistration::for_testing("ci-host-01"), "01").await?;
+        let other =
+            Agent::register(&pool, &AgentRegistration::for_testing("ci-host-02"), "02").await?;
+
+        // the config found at startup is stable right away
+        assert_eq!(rollout.release_for(canary.uuid()), None);
+        reconcile().await?;
+        let stable = rollout
+            .release_for(other.uuid())
+            .ok_or("no stable config")?;
+        assert_eq!(rollout.release_for(canary.uuid()), Some(stable.clone()));
+
+        let (outgoing, _pushes) = mpsc::unbounded::<Message>();
+        let (reports, incoming) = mpsc::unbounded();
+        let session = Session::new(
+            pool.clone(),
+            rollout.clone(),
+            registry.clone(),
+            canary.clone(),
+        );
+        tokio::spawn(session.run(outgoing, incoming));
+
+        // a change reaches the canary first
+        let mut runner = GitLabRunner::for_testing();
+        runner.create(&pool).await?;
+        let version = config_cache.bump();
+        reconcile().await?;
+        let overview = rollout.overview(settings.load().rollout.clone());
+        let status = overview.rollout.ok_or("no rollout")?;
+        assert_eq!(status.phase, RolloutPhase::Canary);
+        assert_eq!(status.canaries, vec!["ci-host-01".to_string()]);
+        assert_eq!(
+            rollout.release_for(canary.uuid()).map(|r| r.version),
+            Some(version)
+        );
+        assert_eq!(rollout.release_for(other.uuid()), Some(stable.clone()));
+
+        // the rollout is promoted once the canary applied the change and stayed healthy
+        send(&reports, &AgentMessage::Ack { version }).await?;
+        handled(&registry, &canary, |registry| {
+            registry
+                .connection(canary.uuid())
+                .is_some_and(|connection| connection.applied_version() == Some(version))
+        })
+        .await;
+        reconcile().await?;
+        let overview = rollout.overview(settings.load().rollout.clone());
+        assert_eq!(overview.stable_version, Some(version));
+        assert_eq!(
+            overview.rollout.map(|status| status.phase),
+            Some(RolloutPhase::Promoted)
+        );
+        let promoted = rollout
+            .release_for(other.uuid())
+            .ok_or("no stable config")?;
+        assert_eq!(promoted.version, version);
+
+        // a change the canary reports failures for is rolled back
+        runner.delete(&pool).await?;
+        config_cache.bump();
+        reconcile().await?;
+        send(
+            &reports,
+            &AgentMessage::Health {
+                healthy: false,
+                message: Some("gitlab-runner crashed".to_string()),
+            },
+        )
+        .await?;
+        handled(&registry, &canary, |registry| {
+            registry
+                .connection(canary.uuid())
+                .and_then(|connection| connection.failure_since(chrono::DateTime::UNIX_EPOCH))
+                .is_some()
+        })
+        .await;
+        reconcile().await?;
+        let status = rollout
+            .overview(settings.load().rollout.clone())
+            .rollout
+            .ok_or("no rollout")?;
+        assert_eq!(status.phase, RolloutPhase::RolledBack);
+        assert_eq!(
+            status.reason.as_deref(),
+            Some("canary 'ci-host-01' reported: gitlab-runner crashed")
+        );
+        assert_eq!(rollout.release_for(canary.uuid()), Some(promoted));
+
+        // there's nothing left to promote or roll back
+        assert!(rollout.promote().is_err());
+        assert!(rollout.roll_back("warbl").is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use aws_sdk_secretsmanager::Client;
+use futures::{future::BoxFuture, FutureExt};
+
+use super::{SecretProvider, SecretRef};
+use crate::error::Error;
+
+/// Reads secrets from AWS Secrets Manager.
+#[derive(Debug, Clone)]
+pub struct AwsSecretsManager(Client);
+
+impl AwsSecretsManager {
+    /// Uses the default AWS credential chain, i.e. the `AWS_*` environment variables, the shared
+    /// config files or the instance role.
+    pub async fn from_env() -> Self {
+        let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+        Self(Client::new(&config))
+    }
+}
+
+impl SecretProvider for AwsSecretsManager {
+    fn resolve<'a>(&'a self, secret: &'a SecretRef) -> BoxFuture<'a, Result<String, Error>> {
+        async move {
+            let SecretRef::AwsSecretsManager { name, key } = secret else {
+                return Err(Error::internal_error(format!(
+                    "AWS Secrets Manager can't resolve secret reference '{secret}'"
+                )));
+            };
+
+            let output = self
+                .0
+                .get_secret_value()
+                .secret_id(name)
+                .send()
+                .await
+                .map_err(|err| {
+                    Error::connection_failed(format!("reading secret '{secret}' failed: {err}"))
+                })?;
+            let value = output.secret_string().ok_or_else(|| {
+                Error::invalid_argument(format!("secret '{secret}' is not a string"))
+            })?;
+
+            let Some(key) = key else {
+                return Ok(value.to_string());
+            };
+            // the secret itself must not end up in the error message
+            let json: serde_json::Value = serde_json::from_str(value).map_err(|_| {
+                Error::invalid_argument(format!("secret '{secret}' is not a JSON object"))
+            })?;
+            json.get(key)
+                .and_then(serde_json::Value::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| Error::not_found(format!("secret '{secret}' does not exist")))
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,370 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+#[cfg(feature = "aws-sm")]
+mod aws;
+#[cfg(feature = "vault")]
+mod vault;
+
+use std::{collections::BTreeMap, fmt, str::FromStr, sync::Arc};
+
+#[cfg(feature = "aws-sm")]
+pub use aws::AwsSecretsManager;
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use sqlx::Sqlite;
+#[cfg(feature = "vault")]
+pub use vault::Vault;
+
+use crate::error::Error;
+
+const VAULT_PREFIX: &str = "vault:";
+const AWS_SM_PREFIX: &str = "aws-sm:";
+
+/// Reference to a secret kept outside of the database, given instead of the secret itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecretRef {
+    /// `vault:<mount>/<path>#<key>`, read from a KV version 2 secrets engine
+    Vault {
+        mount: String,
+        path: String,
+        key: String,
+    },
+    /// `aws-sm:<name>`, or `aws-sm:<name>#<key>` to read a key of a JSON secret
+    AwsSecretsManager { name: String, key: Option<String> },
+}
+
+impl SecretRef {
+    /// Whether `value` is meant as a secret reference rather than as the secret itself.
+    pub fn is_secret_ref(value: &str) -> bool {
+        value.starts_with(VAULT_PREFIX) || value.starts_with(AWS_SM_PREFIX)
+    }
+
+    /// Name of the provider resolving the reference, i.e. its prefix.
+    pub fn provider(&self) -> &'static str {
+        match self {
+            Self::Vault { .. } => "vault",
+            Self::AwsSecretsManager { .. } => "aws-sm",
+        }
+    }
+}
+
+impl fmt::Display for SecretRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Vault { mount, path, key } => write!(f, "{VAULT_PREFIX}{mount}/{path}#{key}"),
+            Self::AwsSecretsManager { name, key: None } => write!(f, "{AWS_SM_PREFIX}{name}"),
+            Self::AwsSecretsManager {
+                name,
+                key: Some(key),
+            } => write!(f, "{AWS_SM_PREFIX}{name}#{key}"),
+        }
+    }
+}
+
+impl FromStr for SecretRef {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Some(reference) = value.strip_prefix(VAULT_PREFIX) {
+            let parsed = reference.split_once('#').and_then(|(location, key)| {
+                let (mount, path) = location.split_once('/')?;
+                (!mount.is_empty() && !path.is_empty() && !key.is_empty()).then(|| Self::Vault {
+                    mount: mount.to_string(),
+                    path: path.to_string(),
+                    key: key.to_string(),
+                })
+            });
+            return parsed.ok_or_else(|| {
+                Error::invalid_argument(format!(
+                    "invalid secret reference '{value}'; must look like vault:<mount>/<path>#<key>"
+                ))
+            });
+        }
+
+        if let Some(reference) = value.strip_prefix(AWS_SM_PREFIX) {
+            let (name, key) = match reference.split_once('#') {
+                Some((name, key)) => (name, Some(key)),
+                None => (reference, None),
+            };
+            if name.is_empty() || key.is_some_and(str::is_empty) {
+                return Err(Error::invalid_argument(format!(
+                    "invalid secret reference '{value}'; must look like aws-sm:<name> or \
+                     aws-sm:<name>#<key>"
+                )));
+            }
+            return Ok(Self::AwsSecretsManager {
+                name: name.to_string(),
+                key: key.map(str::to_string),
+            });
+        }
+
+        Err(Error::invalid_argument(format!(
+            "invalid secret reference '{value}'; must start with {VAULT_PREFIX} or {AWS_SM_PREFIX}"
+        )))
+    }
+}
+
+/// Reads secrets from a secret store.
+pub trait SecretProvider: fmt::Debug + Send + Sync {
+    /// Returns the value of the secret `secret` refers to.
+    fn resolve<'a>(&'a self, secret: &'a SecretRef) -> BoxFuture<'a, Result<String, Error>>;
+}
+
+/// The secret providers available for resolving secret references, by prefix.
+#[derive(Debug, Clone, Default)]
+pub struct Secrets(BTreeMap<&'static str, Arc<dyn SecretProvider>>);
+
+impl Secrets {
+    /// Sets up the providers enabled at compile time from the environment: Vault if `VAULT_ADDR`
+    /// is set, and AWS Secrets Manager with the default AWS credential chain.
+    pub async fn init() -> miette::Result<Self> {
+        #[allow(unused_mut)]
+        let mut secrets = Self::default();
+
+        #[cfg(feature = "vault")]
+        if let Some(vault) = Vault::from_env()? {
+            secrets = secrets.with_provider("vault", vault);
+        }
+        #[cfg(feature = "aws-sm")]
+        {
+            secrets = secrets.with_provider("aws-sm", AwsSecretsManager::from_env().await);
+        }
+
+        Ok(secrets)
+    }
+
+    /// Registers `provider` for the secret references with the given prefix.
+    pub fn with_provider<P>(mut self, prefix: &'static str, provider: P) -> Self
+    where
+        P: SecretProvider + 'static,
+    {
+        self.0.insert(prefix, Arc::new(provider));
+        self
+    }
+
+    /// Returns the value of the secret `secret` refers to.
+    pub async fn resolve(&self, secret: &SecretRef) -> Result<String, Error> {
+        let provider = self.0.get(secret.provider()).ok_or_else(|| {
+            Error::invalid_argument(format!(
+                "can't resolve secret reference '{secret}'; the {} secret provider is not \
+                 configured",
+                secret.provider()
+            ))
+        })?;
+
+        tracing::debug!(%secret, "resolving secret");
+        provider.resolve(secret).await
+    }
+}
+
+/// A credential like a runner token, given either directly or as a reference to a secret store.
+/// Only the reference is stored; the secret is resolved whenever it's needed, e.g. when the
+/// config is rendered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Credential<T> {
+    Plain(T),
+    Secret(SecretRef),
+}
+
+impl<T> Credential<T>
+where
+    T: Clone + FromStr,
+{
+    /// Returns the credential, reading it from the secret store if needed.
+    pub async fn resolve(&self, secrets: &Secrets) -> Result<T, Error> {
+        match self {
+            Self::Plain(credential) => Ok(credential.clone()),
+            Self::Secret(secret) => {
+                // the parse error would contain the secret, so it must not be passed on
+                secrets.resolve(secret).await?.parse().map_err(|_| {
+                    Error::invalid_argument(format!("secret '{secret}' is not a valid credential"))
+                })
+            }
+        }
+    }
+}
+
+impl<T> From<T> for Credential<T> {
+    fn from(credential: T) -> Self {
+        Self::Plain(credential)
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Credential<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Plain(credential) => credential.fmt(f),
+            Self::Secret(secret) => secret.fmt(f),
+        }
+    }
+}
+
+impl<T> FromStr for Credential<T>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if SecretRef::is_secret_ref(value) {
+            return value.parse().map(Self::Secret);
+        }
+
+        value
+            .parse()
+            .map(Self::Plain)
+            .map_err(Error::invalid_argument)
+    }
+}
+
+impl<T: fmt::Display> Serialize for Credential<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for Credential<T>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl<T> sqlx::Type<Sqlite> for Credential<T> {
+    fn type_info() -> <Sqlite as sqlx::Database>::TypeInfo {
+        <String as sqlx::Type<Sqlite>>::type_info()
+    }
+
+    fn compatible(ty: &<Sqlite as sqlx::Database>::TypeInfo) -> bool {
+        <String as sqlx::Type<Sqlite>>::compatible(ty)
+    }
+}
+
+impl<'q, T: fmt::Display> sqlx::Encode<'q, Sqlite> for Credential<T> {
+    fn encode_by_ref(
+        &self,
+        buf: &mut <Sqlite as sqlx::database::HasArguments<'q>>::ArgumentBuffer,
+    ) -> sqlx::encode::IsNull {
+        <String as sqlx::Encode<Sqlite>>::encode(self.to_string(), buf)
+    }
+}
+
+impl<'r, T> sqlx::Decode<'r, Sqlite> for Credential<T>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    fn decode(
+        value: <Sqlite as sqlx::database::HasValueRef<'r>>::ValueRef,
+    ) -> Result<Self, sqlx::error::BoxDynError> {
+        let value = <&str as sqlx::Decode<Sqlite>>::decode(value)?;
+        Ok(value.parse()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{future::BoxFuture, FutureExt};
+    use glrcfg::runner::RunnerToken;
+    use pretty_assertions::assert_eq;
+
+    use super::{Credential, SecretProvider, SecretRef, Secrets};
+    use crate::error::Error;
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[derive(Debug)]
+    struct FakeVault;
+
+    impl SecretProvider for FakeVault {
+        fn resolve<'a>(&'a self, secret: &'a SecretRef) -> BoxFuture<'a, Result<String, Error>> {
+            async move {
+                match secret {
+                    SecretRef::Vault { path, .. } if path == "runners/usain-bolt" => {
+                        Ok("glrt-warblgarblwarblgarbl".to_string())
+                    }
+                    _ => Err(Error::not_found(format!("no secret at {secret}"))),
+                }
+            }
+            .boxed()
+        }
+    }
+
+    #[test]
+    fn parse_secret_refs() {
+        assert_eq!(
+            "vault:kv/runners/usain-bolt#token".parse::<SecretRef>(),
+            Ok(SecretRef::Vault {
+                mount: "kv".to_string(),
+                path: "runners/usain-bolt".to_string(),
+                key: "token".to_string(),
+            })
+        );
+        assert_eq!(
+            "aws-sm:runners/usain-bolt".parse::<SecretRef>(),
+            Ok(SecretRef::AwsSecretsManager {
+                name: "runners/usain-bolt".to_string(),
+                key: None,
+            })
+        );
+
+        for valid in [
+            "vault:kv/runners#token",
+            "aws-sm:runner",
+            "aws-sm:runner#token",
+        ] {
+            assert_eq!(
+                valid.parse::<SecretRef>().map(|s| s.to_string()),
+                Ok(valid.to_string())
+            );
+        }
+        for invalid in [
+            "vault:kv#token",
+            "vault:kv/runners",
+            "vault:/runners#token",
+            "aws-sm:",
+        ] {
+            assert!(invalid.parse::<SecretRef>().is_err(), "{invalid}");
+        }
+    }
+
+    #[tokio::test]
+    async fn resolve_credentials() -> Result<()> {
+        let secrets = Secrets::default().with_provider("vault", FakeVault);
+
+        let plain: Credential<RunnerToken> = "glrt-0123456789_abcdefXYZ".parse()?;
+        assert_eq!(
+            plain.resolve(&secrets).await?.as_str(),
+            "glrt-0123456789_abcdefXYZ"
+        );
+
+        let secret: Credential<RunnerToken> = "vault:kv/runners/usain-bolt#token".parse()?;
+        assert_eq!(secret.to_string(), "vault:kv/runners/usain-bolt#token");
+        assert_eq!(
+            secret.resolve(&secrets).await?.as_str(),
+            "glrt-warblgarblwarblgarbl"
+        );
+
+        let missing: Credential<RunnerToken> = "vault:kv/runners/missing#token".parse()?;
+        assert!(missing.resolve(&secrets).await.is_err());
+
+        // no provider for AWS Secrets Manager is configured
+        let aws: Credential<RunnerToken> = "aws-sm:usain-bolt".parse()?;
+        assert!(aws.resolve(&secrets).await.is_err());
+
+        assert!("glrt-".parse::<Credential<RunnerToken>>().is_err());
+
+        Ok(())
+    }
+}
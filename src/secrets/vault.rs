@@ -0,0 +1,126 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::time::Duration;
+
+use futures::{future::BoxFuture, FutureExt};
+use miette::IntoDiagnostic;
+use serde::Deserialize;
+
+use super::{SecretProvider, SecretRef};
+use crate::error::Error;
+
+/// How long Vault may take to respond.
+const VAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Deserialize)]
+struct KvResponse {
+    data: KvData,
+}
+
+#[derive(Debug, Deserialize)]
+struct KvData {
+    data: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Reads secrets from the KV version 2 secrets engine of a HashiCorp Vault.
+#[derive(Debug, Clone)]
+pub struct Vault {
+    client: reqwest::Client,
+    addr: String,
+    token: String,
+}
+
+impl Vault {
+    pub fn new(addr: impl Into<String>, token: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::default(),
+            addr: addr.into(),
+            token: token.into(),
+        }
+    }
+
+    /// Connects to the Vault at `VAULT_ADDR` with `VAULT_TOKEN`, like the Vault CLI does. Returns
+    /// `None` if `VAULT_ADDR` is not set.
+    pub fn from_env() -> miette::Result<Option<Self>> {
+        let Ok(addr) = std::env::var("VAULT_ADDR") else {
+            return Ok(None);
+        };
+        let token = std::env::var("VAULT_TOKEN")
+            .into_diagnostic()
+            .map_err(|err| err.context("VAULT_ADDR is set, so VAULT_TOKEN must be set as well"))?;
+
+        tracing::info!(%addr, "resolving vault: secret references with Vault");
+        Ok(Some(Self::new(addr, token)))
+    }
+}
+
+impl SecretProvider for Vault {
+    fn resolve<'a>(&'a self, secret: &'a SecretRef) -> BoxFuture<'a, Result<String, Error>> {
+        async move {
+            let SecretRef::Vault { mount, path, key } = secret else {
+                return Err(Error::internal_error(format!(
+                    "Vault can't resolve secret reference '{secret}'"
+                )));
+            };
+
+            let url = format!("{}/v1/{mount}/data/{path}", self.addr.trim_end_matches('/'));
+            let response = self
+                .client
+                .get(url)
+                .header("X-Vault-Token", &self.token)
+                .timeout(VAULT_TIMEOUT)
+                .send()
+                .await
+                .and_then(reqwest::Response::error_for_status)
+                .map_err(|err| {
+                    Error::connection_failed(format!("reading secret '{secret}' failed: {err}"))
+                })?;
+            let KvResponse { data } = response.json().await.map_err(|err| {
+                Error::internal_error(format!("unexpected response from Vault: {err}"))
+            })?;
+
+            data.data
+                .get(key)
+                .and_then(serde_json::Value::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| Error::not_found(format!("secret '{secret}' does not exist")))
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::Path, http::HeaderMap, routing::get, Json, Router};
+    use pretty_assertions::assert_eq;
+
+    use super::Vault;
+    use crate::secrets::{SecretProvider, SecretRef};
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[tokio::test]
+    async fn read_kv_secret() -> Result<()> {
+        let vault = Router::new().route(
+            "/v1/kv/data/*path",
+            get(|Path(path): Path<String>, headers: HeaderMap| async move {
+                assert_eq!(headers["x-vault-token"], "hvs.warbl");
+                Json(serde_json::json!({
+                    "data": { "data": { "token": format!("glrt-{}", path.replace('/', "_")) } }
+                }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, vault).await });
+
+        let vault = Vault::new(addr, "hvs.warbl");
+        let secret: SecretRef = "vault:kv/runners/usain-bolt#token".parse()?;
+        assert_eq!(vault.resolve(&secret).await?, "glrt-runners_usain-bolt");
+
+        let secret: SecretRef = "vault:kv/runners/usain-bolt#missing".parse()?;
+        assert!(vault.resolve(&secret).await.is_err());
+
+        Ok(())
+    }
+}
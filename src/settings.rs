@@ -0,0 +1,1677 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fmt::Display,
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use arc_swap::ArcSwap;
+use futures::{future::BoxFuture, FutureExt};
+use glrcfg::{
+    Defaults, GlobalSection, GolangDuration, ListenAddress, LogFormat, LogLevel, SentryDsn,
+};
+use miette::IntoDiagnostic;
+use serde::{Deserialize, Serialize};
+use tokio::signal::unix::{signal, SignalKind};
+use utoipa::ToSchema;
+
+use crate::{
+    auth::AuthMode,
+    error::Error,
+    freeze::FreezeWindows,
+    models::{ConfigCache, ConfigTarget, GitLabRunnerConfig, LabelKeys, OutputLimit},
+    notifications::{ChannelConfig, NotificationKind},
+    subsystems::{Shutdown, Subsystem},
+};
+
+pub static DEFAULT_NAME_MAX_LENGTH: usize = 255;
+pub static DEFAULT_MAX_RUNNERS: u32 = 50;
+pub static DEFAULT_MAX_RUNNERS_PER_INSTANCE: u32 = 10;
+pub static DEFAULT_MAX_PRIVILEGED: u32 = 5;
+pub static DEFAULT_LOG_BODY_MAX_BYTES: usize = 4096;
+pub static DEFAULT_EXPIRY_NOTIFY_BEFORE_SECS: u64 = 24 * 60 * 60;
+pub static DEFAULT_AUDIT_MAX_AGE_DAYS: u32 = 90;
+pub static DEFAULT_AUDIT_MAX_ENTRIES: u64 = 100_000;
+pub static DEFAULT_RECYCLE_BIN_RETENTION_DAYS: u32 = 30;
+pub static DEFAULT_HISTORY_MAX_AGE_DAYS: u32 = 90;
+pub static DEFAULT_HISTORY_MAX_CONFIG_REVISIONS: u64 = 1_000;
+pub static DEFAULT_EVENTS_MAX_ATTEMPTS: u32 = 0;
+pub static DEFAULT_VERIFY_PARALLELISM: usize = 8;
+pub static DEFAULT_VERIFY_RATE_PER_INSTANCE: u32 = 10;
+pub static DEFAULT_VERIFY_TIMEOUT_SECS: u64 = 10;
+pub static DEFAULT_ROLLOUT_HEALTH_GATE_SECS: u64 = 60;
+pub static DEFAULT_AUTOSCALING_MIN_LIMIT: u32 = 1;
+pub static DEFAULT_AUTOSCALING_MAX_LIMIT: u32 = 10;
+pub static DEFAULT_AUTOSCALING_HYSTERESIS: u32 = 2;
+pub static DEFAULT_UNHEALTHY_REQUESTS_LIMIT: u32 = 3;
+pub static DEFAULT_UNHEALTHY_INTERVAL: &str = "1h";
+pub static DEFAULT_CONFIG_SYNC_TIMEOUT_SECS: u64 = 10;
+pub static DEFAULT_NOTIFY_INTERVAL_SECS: u64 = 60;
+pub static DEFAULT_NOTIFY_TOKEN_EXPIRY_SECS: u64 = 7 * 24 * 60 * 60;
+pub static DEFAULT_GITOPS_BRANCH: &str = "main";
+pub static DEFAULT_GITOPS_PATH: &str = "runners";
+pub static DEFAULT_GITOPS_INTERVAL_SECS: u64 = 300;
+pub static DEFAULT_GITOPS_CHECKOUT_DIR: &str = "/etc/runrs/gitops";
+pub static DEFAULT_OPERATOR_INTERVAL_SECS: u64 = 30;
+pub static DEFAULT_OPERATOR_CONFIG_SECRET: &str = "runrs-config";
+
+/// How to handle runners which share their name with another runner on the same GitLab instance.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum NameUniqueness {
+    /// Duplicate names are accepted silently.
+    #[default]
+    Off,
+    /// Duplicate names are accepted, but a warning is logged.
+    Warn,
+    /// Duplicate names are rejected.
+    Enforce,
+}
+
+impl FromStr for NameUniqueness {
+    type Err = String;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy {
+            "off" => Ok(Self::Off),
+            "warn" => Ok(Self::Warn),
+            "enforce" => Ok(Self::Enforce),
+            _ => Err(format!(
+                "invalid name uniqueness policy '{policy}'; must be one of off, warn, enforce"
+            )),
+        }
+    }
+}
+
+/// Limits on the number of runners managed by the service.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct Quotas {
+    /// Maximum number of runners in total
+    #[schema(example = 50)]
+    pub max_runners: u32,
+    /// Maximum number of runners per GitLab instance
+    #[schema(example = 10)]
+    pub max_runners_per_instance: u32,
+    /// Maximum number of privileged runners
+    #[schema(example = 5)]
+    pub max_privileged: u32,
+}
+
+impl Default for Quotas {
+    fn default() -> Self {
+        Self {
+            max_runners: DEFAULT_MAX_RUNNERS,
+            max_runners_per_instance: DEFAULT_MAX_RUNNERS_PER_INSTANCE,
+            max_privileged: DEFAULT_MAX_PRIVILEGED,
+        }
+    }
+}
+
+impl Quotas {
+    /// Reads the quotas from the environment, falling back to defaults for unset variables.
+    pub fn from_env() -> miette::Result<Self> {
+        let defaults = Self::default();
+
+        Ok(Self {
+            max_runners: env_or("QUOTA_MAX_RUNNERS", defaults.max_runners)?,
+            max_runners_per_instance: env_or(
+                "QUOTA_MAX_RUNNERS_PER_INSTANCE",
+                defaults.max_runners_per_instance,
+            )?,
+            max_privileged: env_or("QUOTA_MAX_PRIVILEGED", defaults.max_privileged)?,
+        })
+    }
+}
+
+/// Debug logging of request and response bodies, with secrets redacted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct BodyLogging {
+    /// Whether bodies are logged; they are logged at debug level only
+    pub enabled: bool,
+    /// Maximum number of bytes logged per body
+    #[schema(example = 4096)]
+    pub max_bytes: usize,
+}
+
+impl Default for BodyLogging {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_bytes: DEFAULT_LOG_BODY_MAX_BYTES,
+        }
+    }
+}
+
+impl BodyLogging {
+    /// Reads the body logging settings from the environment, falling back to defaults for unset
+    /// variables.
+    pub fn from_env() -> miette::Result<Self> {
+        let defaults = Self::default();
+
+        Ok(Self {
+            enabled: env_or("LOG_BODIES", defaults.enabled)?,
+            max_bytes: env_or("LOG_BODY_MAX_BYTES", defaults.max_bytes)?,
+        })
+    }
+}
+
+/// What happens to runners once they expire.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExpiryAction {
+    /// Expired runners are paused, i.e. left out of the config, but kept in the database.
+    #[default]
+    Pause,
+    /// Expired runners are deleted.
+    Delete,
+}
+
+impl FromStr for ExpiryAction {
+    type Err = String;
+
+    fn from_str(action: &str) -> Result<Self, Self::Err> {
+        match action {
+            "pause" => Ok(Self::Pause),
+            "delete" => Ok(Self::Delete),
+            _ => Err(format!(
+                "invalid expiry action '{action}'; must be one of pause, delete"
+            )),
+        }
+    }
+}
+
+/// Cleanup of runners with an `expires_at` timestamp.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct Expiry {
+    /// What happens to expired runners
+    pub action: ExpiryAction,
+    /// How long before expiry the owner is notified via the webhook, in seconds
+    #[schema(example = 86400)]
+    pub notify_before_secs: u64,
+    /// URL which expiry notices are POSTed to; expired runners are only cleaned up after their
+    /// notice was delivered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = "https://hooks.your-company.com/runrs")]
+    pub webhook_url: Option<String>,
+}
+
+impl Default for Expiry {
+    fn default() -> Self {
+        Self {
+            action: ExpiryAction::default(),
+            notify_before_secs: DEFAULT_EXPIRY_NOTIFY_BEFORE_SECS,
+            webhook_url: None,
+        }
+    }
+}
+
+impl Expiry {
+    /// Reads the expiry settings from the environment, falling back to defaults for unset
+    /// variables.
+    pub fn from_env() -> miette::Result<Self> {
+        let defaults = Self::default();
+
+        Ok(Self {
+            action: env_or("EXPIRY_ACTION", defaults.action)?,
+            notify_before_secs: env_or("EXPIRY_NOTIFY_BEFORE_SECS", defaults.notify_before_secs)?,
+            webhook_url: env_opt("EXPIRY_WEBHOOK_URL")?,
+        })
+    }
+}
+
+/// Delivery of runner change events.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct Events {
+    /// URL which runner change events are POSTed to; events are only queued while it is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = "https://hooks.your-company.com/runrs")]
+    pub webhook_url: Option<String>,
+    /// Failed deliveries after which an event is moved to the dead letters; 0 retries forever,
+    /// keeping all events in order. With a limit, events after a dead one are delivered before it,
+    /// and a replayed event arrives after them.
+    #[serde(default = "default_events_max_attempts")]
+    #[schema(example = 0)]
+    pub max_attempts: u32,
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self {
+            webhook_url: None,
+            max_attempts: DEFAULT_EVENTS_MAX_ATTEMPTS,
+        }
+    }
+}
+
+fn default_events_max_attempts() -> u32 {
+    DEFAULT_EVENTS_MAX_ATTEMPTS
+}
+
+impl Events {
+    /// Reads the event settings from the environment, falling back to defaults for unset
+    /// variables.
+    pub fn from_env() -> miette::Result<Self> {
+        let defaults = Self::default();
+
+        Ok(Self {
+            webhook_url: env_opt("EVENTS_WEBHOOK_URL")?,
+            max_attempts: env_or("EVENTS_MAX_ATTEMPTS", defaults.max_attempts)?,
+        })
+    }
+
+    /// Returns `true` if change events are queued for delivery.
+    pub fn enabled(&self) -> bool {
+        self.webhook_url.is_some()
+    }
+}
+
+/// Retention of the audit log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct AuditRetention {
+    /// Entries older than this many days are pruned; 0 keeps them regardless of age
+    #[schema(example = 90)]
+    pub max_age_days: u32,
+    /// Only the newest entries up to this number are kept; 0 keeps any number of entries
+    #[schema(example = 100000)]
+    pub max_entries: u64,
+}
+
+impl Default for AuditRetention {
+    fn default() -> Self {
+        Self {
+            max_age_days: DEFAULT_AUDIT_MAX_AGE_DAYS,
+            max_entries: DEFAULT_AUDIT_MAX_ENTRIES,
+        }
+    }
+}
+
+impl AuditRetention {
+    /// Reads the retention settings from the environment, falling back to defaults for unset
+    /// variables.
+    pub fn from_env() -> miette::Result<Self> {
+        let defaults = Self::default();
+
+        Ok(Self {
+            max_age_days: env_or("AUDIT_RETENTION_DAYS", defaults.max_age_days)?,
+            max_entries: env_or("AUDIT_RETENTION_ENTRIES", defaults.max_entries)?,
+        })
+    }
+}
+
+/// Retention of deleted runners, which are kept in the recycle bin for a while.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct RecycleBin {
+    /// Deleted runners older than this many days are purged; 0 keeps them regardless of age
+    #[schema(example = 30)]
+    pub retention_days: u32,
+}
+
+impl Default for RecycleBin {
+    fn default() -> Self {
+        Self {
+            retention_days: DEFAULT_RECYCLE_BIN_RETENTION_DAYS,
+        }
+    }
+}
+
+impl RecycleBin {
+    /// Reads the retention from the environment, falling back to the default if it's unset.
+    pub fn from_env() -> miette::Result<Self> {
+        let defaults = Self::default();
+
+        Ok(Self {
+            retention_days: env_or("RECYCLE_BIN_RETENTION_DAYS", defaults.retention_days)?,
+        })
+    }
+}
+
+/// Retention of the history of runners and configs, from which `as_of` queries are answered.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct HistoryRetention {
+    /// Revisions older than this many days are pruned, except those still in force then; 0 keeps
+    /// them regardless of age
+    #[schema(example = 90)]
+    pub max_age_days: u32,
+    /// Only the newest configs written up to this number are kept; 0 keeps any number of them
+    #[schema(example = 1000)]
+    pub max_config_revisions: u64,
+}
+
+impl Default for HistoryRetention {
+    fn default() -> Self {
+        Self {
+            max_age_days: DEFAULT_HISTORY_MAX_AGE_DAYS,
+            max_config_revisions: DEFAULT_HISTORY_MAX_CONFIG_REVISIONS,
+        }
+    }
+}
+
+impl HistoryRetention {
+    /// Reads the retention settings from the environment, falling back to defaults for unset
+    /// variables.
+    pub fn from_env() -> miette::Result<Self> {
+        let defaults = Self::default();
+
+        Ok(Self {
+            max_age_days: env_or("HISTORY_RETENTION_DAYS", defaults.max_age_days)?,
+            max_config_revisions: env_or(
+                "HISTORY_MAX_CONFIG_REVISIONS",
+                defaults.max_config_revisions,
+            )?,
+        })
+    }
+}
+
+/// Limits for verifying runner tokens with GitLab, so that large fleets are verified quickly
+/// without hammering a GitLab instance.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct Verification {
+    /// Number of runners verified concurrently
+    #[schema(example = 8)]
+    pub parallelism: usize,
+    /// Requests per second sent to a single GitLab instance
+    #[schema(example = 10)]
+    pub rate_per_instance: u32,
+    /// Seconds after which a sweep reports the runners verified so far
+    #[schema(example = 10)]
+    pub timeout_secs: u64,
+}
+
+impl Default for Verification {
+    fn default() -> Self {
+        Self {
+            parallelism: DEFAULT_VERIFY_PARALLELISM,
+            rate_per_instance: DEFAULT_VERIFY_RATE_PER_INSTANCE,
+            timeout_secs: DEFAULT_VERIFY_TIMEOUT_SECS,
+        }
+    }
+}
+
+impl Verification {
+    /// Reads the verification limits from the environment, falling back to defaults for unset
+    /// variables.
+    pub fn from_env() -> miette::Result<Self> {
+        let defaults = Self::default();
+
+        Ok(Self {
+            parallelism: env_or("VERIFY_PARALLELISM", defaults.parallelism)?,
+            rate_per_instance: env_or("VERIFY_RATE_PER_INSTANCE", defaults.rate_per_instance)?,
+            timeout_secs: env_or("VERIFY_TIMEOUT_SECS", defaults.timeout_secs)?,
+        })
+    }
+}
+
+/// Agents which receive a changed config before all others; given as a share of the registered
+/// agents, e.g. `10%`, or as a comma-separated list of agent names.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Canary {
+    /// A share of the registered agents, in percent; at least one agent is a canary
+    Percent(u8),
+    /// The agents with the given names
+    Hosts(Vec<String>),
+}
+
+impl FromStr for Canary {
+    type Err = String;
+
+    fn from_str(canary: &str) -> Result<Self, Self::Err> {
+        if let Some(percent) = canary.trim().strip_suffix('%') {
+            return match percent.trim().parse() {
+                Ok(percent @ 1..=100) => Ok(Self::Percent(percent)),
+                _ => Err(format!(
+                    "invalid canary share '{canary}'; must be between 1% and 100%"
+                )),
+            };
+        }
+
+        let hosts: Vec<String> = canary
+            .split(',')
+            .map(str::trim)
+            .filter(|host| !host.is_empty())
+            .map(str::to_string)
+            .collect();
+        if hosts.is_empty() {
+            return Err("canary must be a share like '10%' or a list of agent names".to_string());
+        }
+
+        Ok(Self::Hosts(hosts))
+    }
+}
+
+impl TryFrom<String> for Canary {
+    type Error = String;
+
+    fn try_from(canary: String) -> Result<Self, Self::Error> {
+        canary.parse()
+    }
+}
+
+impl From<Canary> for String {
+    fn from(canary: Canary) -> Self {
+        match canary {
+            Canary::Percent(percent) => format!("{percent}%"),
+            Canary::Hosts(hosts) => hosts.join(","),
+        }
+    }
+}
+
+/// Staged rollout of config changes to agents: canaries receive a changed config first, and all
+/// other agents only once the canaries applied it and reported no failures for a while.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct RolloutPolicy {
+    /// Agents receiving a changed config first, e.g. `10%` or `ci-host-01,ci-host-02`; all agents
+    /// receive changes at once while it is unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>, example = "10%")]
+    pub canary: Option<Canary>,
+    /// Seconds the canaries must run a changed config without reporting failures before it is
+    /// rolled out to all agents
+    #[schema(example = 60)]
+    pub health_gate_secs: u64,
+    /// Whether the canaries are rolled back to the previous config once one of them reports a
+    /// failure; otherwise the rollout halts until it is promoted or rolled back manually
+    pub auto_rollback: bool,
+}
+
+impl Default for RolloutPolicy {
+    fn default() -> Self {
+        Self {
+            canary: None,
+            health_gate_secs: DEFAULT_ROLLOUT_HEALTH_GATE_SECS,
+            auto_rollback: true,
+        }
+    }
+}
+
+impl RolloutPolicy {
+    /// Reads the rollout policy from the environment, falling back to defaults for unset
+    /// variables.
+    pub fn from_env() -> miette::Result<Self> {
+        let defaults = Self::default();
+
+        Ok(Self {
+            canary: env_opt("ROLLOUT_CANARY")?,
+            health_gate_secs: env_or("ROLLOUT_HEALTH_GATE_SECS", defaults.health_gate_secs)?,
+            auto_rollback: env_or("ROLLOUT_AUTO_ROLLBACK", defaults.auto_rollback)?,
+        })
+    }
+}
+
+/// Adjustment of the runners' job limits and of `concurrent` to the number of jobs waiting for
+/// them in GitLab. Only runners of instances with a GitLab API token are scaled.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct Autoscaling {
+    /// Seconds between two reads of the job queues; 0 turns autoscaling off
+    #[schema(example = 30)]
+    pub interval_secs: u64,
+    /// Job limit runners are scaled down to at most
+    #[schema(example = 1)]
+    pub min_limit: u32,
+    /// Job limit runners are scaled up to at most
+    #[schema(example = 10)]
+    pub max_limit: u32,
+    /// Number of jobs by which the demand must differ from a runner's job limit before the limit
+    /// is changed, so that it doesn't flap
+    #[schema(example = 2)]
+    pub hysteresis: u32,
+}
+
+impl Default for Autoscaling {
+    fn default() -> Self {
+        Self {
+            interval_secs: 0,
+            min_limit: DEFAULT_AUTOSCALING_MIN_LIMIT,
+            max_limit: DEFAULT_AUTOSCALING_MAX_LIMIT,
+            hysteresis: DEFAULT_AUTOSCALING_HYSTERESIS,
+        }
+    }
+}
+
+impl Autoscaling {
+    /// Reads the autoscaling settings from the environment, falling back to defaults for unset
+    /// variables.
+    pub fn from_env() -> miette::Result<Self> {
+        let defaults = Self::default();
+
+        Ok(Self {
+            interval_secs: env_or("AUTOSCALING_INTERVAL_SECS", defaults.interval_secs)?,
+            min_limit: env_or("AUTOSCALING_MIN_LIMIT", defaults.min_limit)?,
+            max_limit: env_or("AUTOSCALING_MAX_LIMIT", defaults.max_limit)?,
+            hysteresis: env_or("AUTOSCALING_HYSTERESIS", defaults.hysteresis)?,
+        })
+    }
+
+    /// Returns the time between two reads of the job queues, or `None` if autoscaling is off.
+    pub fn interval(&self) -> Option<Duration> {
+        (self.interval_secs > 0).then(|| Duration::from_secs(self.interval_secs))
+    }
+
+    /// Returns the bounds of the job limits; a job limit of 0 would mean no limit, so the lower
+    /// bound is at least 1, and the upper bound at least the lower one.
+    pub fn bounds(&self) -> (u32, u32) {
+        let min = self.min_limit.max(1);
+        (min, self.max_limit.max(min))
+    }
+}
+
+/// Human-readable alerts to operators, e.g. in Slack, about conditions needing their attention.
+/// Each kind of notification is routed to the channels listed for it; kinds without a route are
+/// not sent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct Notifications {
+    /// Seconds between two checks for conditions to notify of; 0 turns notifications off
+    #[serde(default = "default_notify_interval_secs")]
+    #[schema(example = 60)]
+    pub interval_secs: u64,
+    /// How long before a runner token expires a notification is sent, in seconds
+    #[serde(default = "default_notify_token_expiry_secs")]
+    #[schema(example = 604800)]
+    pub token_expiry_secs: u64,
+    /// Channels by name
+    #[serde(default)]
+    pub channels: BTreeMap<String, ChannelConfig>,
+    /// Names of the channels each kind of notification is sent to
+    #[serde(default)]
+    #[schema(value_type = BTreeMap<String, Vec<String>>, example = json!({"config_write_failed": ["ops-slack"]}))]
+    pub routes: BTreeMap<NotificationKind, Vec<String>>,
+}
+
+impl Default for Notifications {
+    fn default() -> Self {
+        Self {
+            interval_secs: DEFAULT_NOTIFY_INTERVAL_SECS,
+            token_expiry_secs: DEFAULT_NOTIFY_TOKEN_EXPIRY_SECS,
+            channels: BTreeMap::new(),
+            routes: BTreeMap::new(),
+        }
+    }
+}
+
+fn default_notify_interval_secs() -> u64 {
+    DEFAULT_NOTIFY_INTERVAL_SECS
+}
+
+fn default_notify_token_expiry_secs() -> u64 {
+    DEFAULT_NOTIFY_TOKEN_EXPIRY_SECS
+}
+
+impl Notifications {
+    /// Reads the notification timing from the environment, falling back to defaults for unset
+    /// variables. Channels and routes are only configured via the settings file or API.
+    pub fn from_env() -> miette::Result<Self> {
+        let defaults = Self::default();
+
+        Ok(Self {
+            interval_secs: env_or("NOTIFY_INTERVAL_SECS", defaults.interval_secs)?,
+            token_expiry_secs: env_or("NOTIFY_TOKEN_EXPIRY_SECS", defaults.token_expiry_secs)?,
+            ..defaults
+        })
+    }
+
+    /// Returns the time between two checks, or `None` if nothing would be sent anyway.
+    pub fn interval(&self) -> Option<Duration> {
+        (self.interval_secs > 0 && !self.routes.is_empty())
+            .then(|| Duration::from_secs(self.interval_secs))
+    }
+
+    /// Checks that every channel can be set up and every route refers to a configured channel.
+    pub fn validate(&self) -> Result<(), Error> {
+        for (name, channel) in &self.channels {
+            channel.validate().map_err(|err| {
+                Error::invalid_argument(format!("invalid channel '{name}': {err}"))
+            })?;
+        }
+        for (kind, channels) in &self.routes {
+            if let Some(unknown) = channels
+                .iter()
+                .find(|name| !self.channels.contains_key(*name))
+            {
+                return Err(Error::invalid_argument(format!(
+                    "route for {kind} refers to unknown channel '{unknown}'"
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Reconciliation of the runners with those declared in a Git repository. While it is on, the
+/// runners can't be changed via the API, since changes would be reverted anyway.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct GitOps {
+    /// Repository declaring the runners, as understood by `git clone`; GitOps is off without one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = "git@gitlab.your-company.com:infra/runners.git")]
+    pub repo_url: Option<String>,
+    /// Branch the runners are read from
+    #[schema(example = "main")]
+    pub branch: String,
+    /// Directory within the repository holding the TOML and YAML files declaring the runners
+    #[schema(example = "runners")]
+    pub path: String,
+    /// Whether a path without any declarations deletes all runners; otherwise such a
+    /// reconciliation fails, since the path is more likely wrong than meant to be empty
+    #[schema(example = false)]
+    pub allow_empty: bool,
+    /// Seconds between two reconciliations
+    #[schema(example = 300)]
+    pub interval_secs: u64,
+    /// Directory the repository is checked out to
+    #[schema(value_type = String, example = "/etc/runrs/gitops")]
+    pub checkout_dir: PathBuf,
+}
+
+impl Default for GitOps {
+    fn default() -> Self {
+        Self {
+            repo_url: None,
+            branch: DEFAULT_GITOPS_BRANCH.to_string(),
+            path: DEFAULT_GITOPS_PATH.to_string(),
+            allow_empty: false,
+            interval_secs: DEFAULT_GITOPS_INTERVAL_SECS,
+            checkout_dir: PathBuf::from(DEFAULT_GITOPS_CHECKOUT_DIR),
+        }
+    }
+}
+
+impl GitOps {
+    /// Reads the GitOps settings from the environment, falling back to defaults for unset
+    /// variables.
+    pub fn from_env() -> miette::Result<Self> {
+        let defaults = Self::default();
+
+        Ok(Self {
+            repo_url: env_opt("GITOPS_REPO_URL")?,
+            branch: env_or("GITOPS_BRANCH", defaults.branch)?,
+            path: env_or("GITOPS_PATH", defaults.path)?,
+            allow_empty: env_or("GITOPS_ALLOW_EMPTY", defaults.allow_empty)?,
+            interval_secs: env_or("GITOPS_INTERVAL_SECS", defaults.interval_secs)?,
+            checkout_dir: env_or("GITOPS_CHECKOUT_DIR", defaults.checkout_dir)?,
+        })
+    }
+
+    /// Whether the runners are managed via GitOps, i.e. not via the API.
+    pub fn enabled(&self) -> bool {
+        self.repo_url.is_some()
+    }
+
+    /// Returns the time between two reconciliations, or `None` if GitOps is off.
+    pub fn interval(&self) -> Option<Duration> {
+        self.enabled()
+            .then(|| Duration::from_secs(self.interval_secs.max(1)))
+    }
+
+    /// Checks that the repository and branch can be passed to `git` as such, and that the path
+    /// stays within the repository.
+    pub fn validate(&self) -> Result<(), Error> {
+        let invalid = |value: &str| value.is_empty() || value.starts_with('-');
+        if let Some(repo_url) = self.repo_url.as_deref().filter(|url| invalid(url)) {
+            return Err(Error::invalid_argument(format!(
+                "invalid GitOps repository '{repo_url}'"
+            )));
+        }
+
+        if invalid(&self.branch) {
+            return Err(Error::invalid_argument(format!(
+                "invalid GitOps branch '{}'",
+                self.branch
+            )));
+        }
+
+        let path = Path::new(&self.path);
+        if path.is_absolute()
+            || path
+                .components()
+                .any(|component| component == std::path::Component::ParentDir)
+        {
+            return Err(Error::invalid_argument(format!(
+                "invalid GitOps path '{}'; must be relative to the repository",
+                self.path
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Kubernetes operator mode: the runners are declared as `GitLabRunner` custom resources, and the
+/// config is written to a Secret for `gitlab-runner` to mount. Like with GitOps, the runners can't
+/// be changed via the API while it is on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct Operator {
+    /// Namespace whose `GitLabRunner` resources declare the runners; operator mode is off without
+    /// one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(example = "gitlab-runner")]
+    pub namespace: Option<String>,
+    /// Seconds between two reconciliations while no resource changes
+    #[schema(example = 30)]
+    pub interval_secs: u64,
+    /// Secret in the namespace the config is written to, under the key `config.toml`
+    #[schema(example = "runrs-config")]
+    pub config_secret: String,
+}
+
+impl Default for Operator {
+    fn default() -> Self {
+        Self {
+            namespace: None,
+            interval_secs: DEFAULT_OPERATOR_INTERVAL_SECS,
+            config_secret: DEFAULT_OPERATOR_CONFIG_SECRET.to_string(),
+        }
+    }
+}
+
+impl Operator {
+    /// Reads the operator settings from the environment, falling back to defaults for unset
+    /// variables.
+    pub fn from_env() -> miette::Result<Self> {
+        let defaults = Self::default();
+
+        Ok(Self {
+            namespace: env_opt("OPERATOR_NAMESPACE")?,
+            interval_secs: env_or("OPERATOR_INTERVAL_SECS", defaults.interval_secs)?,
+            config_secret: env_or("OPERATOR_CONFIG_SECRET", defaults.config_secret)?,
+        })
+    }
+
+    /// Whether the runners are managed via custom resources, i.e. not via the API.
+    pub fn enabled(&self) -> bool {
+        self.namespace.is_some()
+    }
+
+    /// Returns the time between two reconciliations while no resource changes, or `None` if
+    /// operator mode is off.
+    pub fn interval(&self) -> Option<Duration> {
+        self.enabled()
+            .then(|| Duration::from_secs(self.interval_secs.max(1)))
+    }
+
+    /// Checks that runrs was built with operator support if it is on, and that the namespace and
+    /// Secret are valid Kubernetes names.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.enabled() && !cfg!(feature = "kubernetes") {
+            return Err(Error::invalid_argument(
+                "operator mode requires runrs to be built with the kubernetes feature",
+            ));
+        }
+
+        // RFC 1123 labels, as Kubernetes requires for namespaces and Secrets
+        let invalid = |name: &str| {
+            name.is_empty()
+                || name.len() > 63
+                || name.starts_with('-')
+                || name.ends_with('-')
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        };
+        if let Some(namespace) = self.namespace.as_deref().filter(|name| invalid(name)) {
+            return Err(Error::invalid_argument(format!(
+                "invalid operator namespace '{namespace}'"
+            )));
+        }
+        if invalid(&self.config_secret) {
+            return Err(Error::invalid_argument(format!(
+                "invalid operator config Secret '{}'",
+                self.config_secret
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Order of the runners in the generated config. Either way, runners compare equal in the
+/// leading keys are ordered by UUID, so the order doesn't depend on the database.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RunnerOrder {
+    /// By GitLab instance URL, then by ID and name.
+    #[default]
+    Instance,
+    /// By name, then by GitLab instance URL and ID.
+    Name,
+}
+
+impl FromStr for RunnerOrder {
+    type Err = String;
+
+    fn from_str(order: &str) -> Result<Self, Self::Err> {
+        match order {
+            "instance" => Ok(Self::Instance),
+            "name" => Ok(Self::Name),
+            _ => Err(format!(
+                "invalid runner order '{order}'; must be one of instance, name"
+            )),
+        }
+    }
+}
+
+/// Options for rendering the runners into the `gitlab-runner` config.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct RenderOptions {
+    /// Whether runner labels are added to the job containers as Docker labels
+    pub container_labels: bool,
+    /// Keys of the runner labels added to the job containers, e.g. `team,cost-center`; all labels
+    /// are added while it is empty
+    #[serde(default)]
+    #[schema(value_type = Vec<String>, example = json!(["team", "cost-center"]))]
+    pub container_label_keys: LabelKeys,
+    /// Log level of `gitlab-runner`
+    pub log_level: LogLevel,
+    /// Log format of `gitlab-runner`; `text` suits journald, since it contains no color codes
+    pub log_format: LogFormat,
+    /// Sentry DSN `gitlab-runner` reports errors to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>, format = Uri, example = "https://key@o42.ingest.sentry.io/4711")]
+    pub sentry_dsn: Option<SentryDsn>,
+    /// Address (`host:port`) `gitlab-runner` serves its Prometheus metrics on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>, example = ":9252")]
+    pub listen_address: Option<ListenAddress>,
+    /// Order of the runners in the config
+    #[serde(default)]
+    pub order: RunnerOrder,
+    /// Which keys still holding their default value are written to the config; `minimal` only
+    /// writes keys which differ from what `gitlab-runner` assumes anyway, which keeps diffs small
+    #[serde(default)]
+    pub defaults: Defaults,
+    /// Maximum job log size in KiB of runners which don't set their own
+    #[serde(default)]
+    pub output_limit: OutputLimit,
+    /// Maximum number of jobs `gitlab-runner` runs at once across all runners; set by runrs if
+    /// autoscaling is enabled
+    #[serde(default = "default_concurrent")]
+    #[schema(value_type = u32, minimum = 1, example = 4)]
+    pub concurrent: NonZeroU32,
+    /// Whether `concurrent` is managed by the autoscaler, so `concurrent` set via
+    /// `PUT /config/global` doesn't override it; derived from the autoscaling settings
+    #[serde(skip)]
+    pub concurrent_autoscaled: bool,
+    /// Number of consecutive failed requests to GitLab after which a runner is considered
+    /// unhealthy and stops requesting jobs for `unhealthy_interval`
+    #[serde(default = "default_unhealthy_requests_limit")]
+    #[schema(value_type = u32, minimum = 1, example = 3)]
+    pub unhealthy_requests_limit: NonZeroU32,
+    /// Golang duration for which an unhealthy runner stops requesting jobs, e.g. `30m`
+    #[serde(default = "default_unhealthy_interval")]
+    #[schema(value_type = String, example = "1h")]
+    pub unhealthy_interval: GolangDuration,
+}
+
+fn default_concurrent() -> NonZeroU32 {
+    GlobalSection::default().concurrent
+}
+
+fn default_unhealthy_requests_limit() -> NonZeroU32 {
+    NonZeroU32::new(DEFAULT_UNHEALTHY_REQUESTS_LIMIT).expect("default is not zero")
+}
+
+fn default_unhealthy_interval() -> GolangDuration {
+    GolangDuration::parse(DEFAULT_UNHEALTHY_INTERVAL).expect("default is a valid duration")
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        let global = GlobalSection::default();
+
+        Self {
+            container_labels: false,
+            container_label_keys: LabelKeys::default(),
+            log_level: global.log_level,
+            log_format: global.log_format,
+            sentry_dsn: global.sentry_dsn,
+            listen_address: global.listen_address,
+            order: RunnerOrder::default(),
+            defaults: Defaults::default(),
+            output_limit: OutputLimit::default(),
+            concurrent: global.concurrent,
+            concurrent_autoscaled: false,
+            unhealthy_requests_limit: default_unhealthy_requests_limit(),
+            unhealthy_interval: default_unhealthy_interval(),
+        }
+    }
+}
+
+impl RenderOptions {
+    /// Reads the render options from the environment, falling back to defaults for unset
+    /// variables.
+    pub fn from_env() -> miette::Result<Self> {
+        let defaults = Self::default();
+
+        Ok(Self {
+            container_labels: env_or("RENDER_CONTAINER_LABELS", defaults.container_labels)?,
+            container_label_keys: env_or(
+                "RENDER_CONTAINER_LABEL_KEYS",
+                defaults.container_label_keys,
+            )?,
+            log_level: env_or("RUNNER_LOG_LEVEL", defaults.log_level)?,
+            log_format: env_or("RUNNER_LOG_FORMAT", defaults.log_format)?,
+            sentry_dsn: env_opt("RUNNER_SENTRY_DSN")?,
+            listen_address: env_opt("RUNNER_LISTEN_ADDRESS")?,
+            order: env_or("RENDER_RUNNER_ORDER", defaults.order)?,
+            defaults: env_or("RENDER_DEFAULTS", defaults.defaults)?,
+            output_limit: env_or("RUNNER_OUTPUT_LIMIT", defaults.output_limit)?,
+            concurrent: env_or("RUNNER_CONCURRENT", defaults.concurrent)?,
+            concurrent_autoscaled: defaults.concurrent_autoscaled,
+            unhealthy_requests_limit: env_or(
+                "RUNNER_UNHEALTHY_REQUESTS_LIMIT",
+                defaults.unhealthy_requests_limit,
+            )?,
+            unhealthy_interval: env_or("RUNNER_UNHEALTHY_INTERVAL", defaults.unhealthy_interval)?,
+        })
+    }
+
+    /// Checks the options which can't be checked while parsing them.
+    pub fn validate(&self) -> Result<(), Error> {
+        // `gitlab-runner` silently falls back to its default for intervals which aren't positive
+        let interval = self.unhealthy_interval.as_str();
+        if interval.starts_with('-') || !interval.chars().any(|c| c.is_ascii_digit() && c != '0') {
+            return Err(Error::invalid_argument(format!(
+                "unhealthy_interval must be positive, not '{interval}'"
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the unhealthy thresholds the runners are rendered with; each is `None` if it is the
+    /// `gitlab-runner` default, so that configs for versions without them don't change.
+    pub fn unhealthy_thresholds(&self) -> (Option<u32>, Option<GolangDuration>) {
+        let limit = self.unhealthy_requests_limit.get();
+        let interval = &self.unhealthy_interval;
+
+        (
+            (limit != DEFAULT_UNHEALTHY_REQUESTS_LIMIT).then_some(limit),
+            (*interval != default_unhealthy_interval()).then(|| interval.clone()),
+        )
+    }
+}
+
+/// Settings which control the behavior of the service, as opposed to the generated config.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(default)]
+pub struct Settings {
+    /// Policy for runner names shared by several runners of one GitLab instance
+    pub name_uniqueness: NameUniqueness,
+    /// Maximum length of runner names, in characters
+    #[schema(example = 255)]
+    pub name_max_length: usize,
+    /// Limits on the number of runners
+    pub quotas: Quotas,
+    /// Logging of request and response bodies
+    pub body_logging: BodyLogging,
+    /// Whether requests must be authenticated
+    pub auth_mode: AuthMode,
+    /// How the config is rendered
+    pub render: RenderOptions,
+    /// Cleanup of expired runners
+    pub expiry: Expiry,
+    /// Delivery of runner change events
+    #[serde(default)]
+    pub events: Events,
+    /// Retention of the audit log
+    #[serde(default)]
+    pub audit: AuditRetention,
+    /// Retention of deleted runners
+    #[serde(default)]
+    pub recycle_bin: RecycleBin,
+    /// Retention of the runner and config history
+    #[serde(default)]
+    pub history: HistoryRetention,
+    /// Weekly change freezes in UTC, e.g. `Fri 18:00-Mon 06:00`, during which changes require a
+    /// token with the `freeze_override` scope
+    #[serde(default)]
+    #[schema(value_type = Vec<String>, example = json!(["Fri 18:00-Mon 06:00"]))]
+    pub freeze_windows: FreezeWindows,
+    /// Limits for verifying runner tokens with GitLab
+    #[serde(default)]
+    pub verification: Verification,
+    /// Staged rollout of config changes to agents
+    #[serde(default)]
+    pub rollout: RolloutPolicy,
+    /// Scaling of the job limits to the job queues in GitLab
+    #[serde(default)]
+    pub autoscaling: Autoscaling,
+    /// Seconds a request with `wait_for_sync` waits at most for a queued config write
+    #[serde(default = "default_config_sync_timeout_secs")]
+    #[schema(example = 10)]
+    pub config_sync_timeout_secs: u64,
+    /// Alerts to operators via Slack, Matrix, email or webhooks
+    #[serde(default)]
+    pub notifications: Notifications,
+    /// Reconciliation of the runners with a Git repository
+    #[serde(default)]
+    pub gitops: GitOps,
+    /// Management of the runners via Kubernetes custom resources
+    #[serde(default)]
+    pub operator: Operator,
+}
+
+fn default_config_sync_timeout_secs() -> u64 {
+    DEFAULT_CONFIG_SYNC_TIMEOUT_SECS
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            name_uniqueness: NameUniqueness::default(),
+            name_max_length: DEFAULT_NAME_MAX_LENGTH,
+            quotas: Quotas::default(),
+            body_logging: BodyLogging::default(),
+            auth_mode: AuthMode::default(),
+            render: RenderOptions::default(),
+            expiry: Expiry::default(),
+            events: Events::default(),
+            audit: AuditRetention::default(),
+            recycle_bin: RecycleBin::default(),
+            history: HistoryRetention::default(),
+            freeze_windows: FreezeWindows::default(),
+            verification: Verification::default(),
+            rollout: RolloutPolicy::default(),
+            autoscaling: Autoscaling::default(),
+            config_sync_timeout_secs: DEFAULT_CONFIG_SYNC_TIMEOUT_SECS,
+            notifications: Notifications::default(),
+            gitops: GitOps::default(),
+            operator: Operator::default(),
+        }
+    }
+}
+
+impl Settings {
+    /// Reads the settings from the environment, falling back to defaults for unset variables.
+    pub fn from_env() -> miette::Result<Self> {
+        let defaults = Self::default();
+
+        Ok(Self {
+            name_uniqueness: env_or("NAME_UNIQUENESS", defaults.name_uniqueness)?,
+            name_max_length: env_or("NAME_MAX_LENGTH", defaults.name_max_length)?,
+            quotas: Quotas::from_env()?,
+            body_logging: BodyLogging::from_env()?,
+            auth_mode: env_or("AUTH_MODE", defaults.auth_mode)?,
+            render: RenderOptions::from_env()?,
+            expiry: Expiry::from_env()?,
+            events: Events::from_env()?,
+            audit: AuditRetention::from_env()?,
+            recycle_bin: RecycleBin::from_env()?,
+            history: HistoryRetention::from_env()?,
+            freeze_windows: env_or("FREEZE_WINDOWS", defaults.freeze_windows)?,
+            verification: Verification::from_env()?,
+            rollout: RolloutPolicy::from_env()?,
+            autoscaling: Autoscaling::from_env()?,
+            config_sync_timeout_secs: env_or(
+                "CONFIG_SYNC_TIMEOUT_SECS",
+                defaults.config_sync_timeout_secs,
+            )?,
+            notifications: Notifications::from_env()?,
+            gitops: GitOps::from_env()?,
+            operator: Operator::from_env()?,
+        })
+    }
+
+    /// Returns the environment variables the settings are read from, in the order they're read.
+    pub fn env_vars() -> Vec<String> {
+        RECORDED_ENV_VARS.with_borrow_mut(|keys| *keys = Some(Vec::new()));
+        // nothing is read while recording, and the defaults are valid
+        let _ = Self::from_env();
+        RECORDED_ENV_VARS
+            .with_borrow_mut(Option::take)
+            .unwrap_or_default()
+    }
+}
+
+impl Settings {
+    /// Reads the settings from the environment and overlays them with the settings in `file`, if
+    /// given. The file is TOML and may contain any subset of the settings.
+    pub fn load(file: Option<&Path>) -> miette::Result<Self> {
+        let settings = Self::from_env()?;
+        let Some(file) = file else {
+            settings
+                .validate()
+                .map_err(|err| miette::miette!("invalid settings: {}", err.msg))?;
+            return Ok(settings);
+        };
+
+        let overlay: toml::Table =
+            toml::from_str(&std::fs::read_to_string(file).into_diagnostic()?).into_diagnostic()?;
+        let mut merged = toml::Table::try_from(settings).into_diagnostic()?;
+        merge(&mut merged, overlay);
+
+        let settings: Self = merged.try_into().into_diagnostic()?;
+        settings
+            .validate()
+            .map_err(|err| miette::miette!("invalid settings file: {}", err.msg))?;
+        Ok(settings)
+    }
+
+    /// Checks the settings which can't be checked while parsing them.
+    pub fn validate(&self) -> Result<(), Error> {
+        self.render.validate()?;
+        self.notifications.validate()?;
+        self.gitops.validate()?;
+        self.operator.validate()?;
+
+        // both would delete the runners the other one declares
+        if self.gitops.enabled() && self.operator.enabled() {
+            return Err(Error::invalid_argument(
+                "GitOps and operator mode can't be on at the same time",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Sets the settings which are derived from others rather than set themselves.
+    fn derive(&mut self) {
+        self.render.concurrent_autoscaled = self.autoscaling.interval().is_some();
+    }
+}
+
+fn merge(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => merge(base, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Placeholder for the secrets in settings returned by the API.
+pub static REDACTED: &str = "****";
+
+/// Settings holding secrets, or revealing more of the host than API clients need to know; `*`
+/// matches any key, e.g. any channel name.
+static SECRET_SETTINGS: &[&[&str]] = &[
+    &["expiry", "webhook_url"],
+    &["events", "webhook_url"],
+    &["gitops", "repo_url"],
+    &["render", "sentry_dsn"],
+    &["notifications", "channels", "*", "webhook_url"],
+    &["notifications", "channels", "*", "access_token"],
+    &["notifications", "channels", "*", "url"],
+    &["notifications", "channels", "*", "password"],
+];
+
+impl Settings {
+    /// Returns the settings as JSON with every secret replaced by [`REDACTED`].
+    pub fn redacted(&self) -> serde_json::Value {
+        let mut json =
+            serde_json::to_value(self).expect("serializing settings to JSON must not fail");
+        for pointer in secret_pointers(&json) {
+            if let Some(secret) = json.pointer_mut(&pointer) {
+                *secret = serde_json::Value::String(REDACTED.to_string());
+            }
+        }
+        json
+    }
+
+    /// Reads settings sent to the API, in which secrets still [`REDACTED`] keep their `current`
+    /// value, so that settings read from the API can be sent back with some of them changed.
+    pub fn unredact(mut json: serde_json::Value, current: &Self) -> Result<Self, Error> {
+        let current = serde_json::to_value(current).map_err(Error::internal_error)?;
+        for pointer in secret_pointers(&json) {
+            let Some(secret) = json.pointer_mut(&pointer) else {
+                continue;
+            };
+            if secret.as_str() != Some(REDACTED) {
+                continue;
+            }
+            *secret = current.pointer(&pointer).cloned().ok_or_else(|| {
+                Error::invalid_argument(format!("{pointer} is redacted, but isn't set"))
+            })?;
+        }
+
+        serde_json::from_value(json).map_err(Error::invalid_argument)
+    }
+}
+
+/// Returns JSON pointers to the secrets which are set in `json`.
+fn secret_pointers(json: &serde_json::Value) -> Vec<String> {
+    fn collect(json: &serde_json::Value, path: &[&str], pointer: String, out: &mut Vec<String>) {
+        let Some((head, rest)) = path.split_first() else {
+            if !json.is_null() {
+                out.push(pointer);
+            }
+            return;
+        };
+        let Some(object) = json.as_object() else {
+            return;
+        };
+        for (key, value) in object.iter().filter(|(key, _)| *head == "*" || key == head) {
+            let key = key.replace('~', "~0").replace('/', "~1");
+            collect(value, rest, format!("{pointer}/{key}"), out);
+        }
+    }
+
+    let mut pointers = Vec::new();
+    for path in SECRET_SETTINGS {
+        collect(json, path, String::new(), &mut pointers);
+    }
+    pointers
+}
+
+/// Holds the current settings, which can be swapped at runtime. Consumers [`load`](Self::load) a
+/// snapshot whenever they need the settings, instead of holding on to one.
+#[derive(Debug)]
+pub struct SettingsStore {
+    current: ArcSwap<Settings>,
+    file: Option<PathBuf>,
+    /// `concurrent` as set by the autoscaler, which is kept when the settings are replaced while
+    /// autoscaling is on; the lock also serializes changes of the settings.
+    autoscaled_concurrent: Mutex<Option<NonZeroU32>>,
+}
+
+impl Default for SettingsStore {
+    fn default() -> Self {
+        Self::new(Settings::default())
+    }
+}
+
+impl SettingsStore {
+    pub fn new(mut settings: Settings) -> Self {
+        settings.derive();
+
+        Self {
+            current: ArcSwap::from_pointee(settings),
+            file: None,
+            autoscaled_concurrent: Mutex::default(),
+        }
+    }
+
+    /// Loads the settings from the environment and the file at `SETTINGS_FILE`, if set.
+    pub fn init() -> miette::Result<Self> {
+        let file = std::env::var_os("SETTINGS_FILE").map(PathBuf::from);
+        let mut settings = Settings::load(file.as_deref())?;
+        settings.derive();
+
+        Ok(Self {
+            current: ArcSwap::from_pointee(settings),
+            file,
+            autoscaled_concurrent: Mutex::default(),
+        })
+    }
+
+    /// Returns a snapshot of the current settings.
+    pub fn load(&self) -> Arc<Settings> {
+        self.current.load_full()
+    }
+
+    /// Replaces the current settings. The auth mode can't be changed at runtime, since that would
+    /// require rebuilding the router. While autoscaling is on, `concurrent` stays as the autoscaler
+    /// set it.
+    pub fn replace(&self, mut settings: Settings) -> Result<(), Error> {
+        let autoscaled = self
+            .autoscaled_concurrent
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        if settings.auth_mode != self.load().auth_mode {
+            return Err(Error::invalid_argument(
+                "auth_mode can only be changed by restarting the service",
+            ));
+        }
+        settings.validate()?;
+        settings.derive();
+        if let Some(concurrent) = *autoscaled {
+            if settings.render.concurrent_autoscaled {
+                settings.render.concurrent = concurrent;
+            }
+        }
+
+        tracing::info!(?settings, "replacing settings");
+        self.current.store(Arc::new(settings));
+        Ok(())
+    }
+
+    /// Sets `concurrent` on behalf of the autoscaler, leaving the other settings as they are.
+    pub fn set_autoscaled_concurrent(&self, concurrent: NonZeroU32) {
+        let mut autoscaled = self
+            .autoscaled_concurrent
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *autoscaled = Some(concurrent);
+
+        let mut settings = Settings::clone(&self.load());
+        settings.render.concurrent = concurrent;
+        self.current.store(Arc::new(settings));
+    }
+
+    /// Re-reads the settings from the environment and the settings file.
+    pub fn reload(&self) -> Result<(), Error> {
+        let settings = Settings::load(self.file.as_deref()).map_err(Error::invalid_argument)?;
+        self.replace(settings)
+    }
+}
+
+/// Reloads the settings whenever the process receives SIGHUP, and rewrites the config if the
+/// render options changed.
+#[derive(Debug, Clone)]
+pub struct SettingsReload {
+    pool: atmosphere::Pool,
+    target: Arc<ConfigTarget>,
+    cache: Arc<ConfigCache>,
+    store: Arc<SettingsStore>,
+}
+
+impl SettingsReload {
+    pub fn new(
+        pool: atmosphere::Pool,
+        target: Arc<ConfigTarget>,
+        cache: Arc<ConfigCache>,
+        store: Arc<SettingsStore>,
+    ) -> Self {
+        Self {
+            pool,
+            target,
+            cache,
+            store,
+        }
+    }
+
+    async fn reload(&self) -> Result<(), Error> {
+        let previous = self.store.load();
+        self.store.reload()?;
+
+        let render = &self.store.load().render;
+        if *render != previous.render {
+            GitLabRunnerConfig::write_or_queue(
+                &self.pool,
+                &self.target.load(),
+                &self.cache,
+                render,
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Subsystem for SettingsReload {
+    fn name(&self) -> &'static str {
+        "settings-reload"
+    }
+
+    fn run(&self, mut shutdown: Shutdown) -> BoxFuture<'static, Result<(), Error>> {
+        let this = self.clone();
+
+        async move {
+            let mut sighup = signal(SignalKind::hangup()).map_err(Error::internal_error)?;
+
+            loop {
+                tokio::select! {
+                    _ = sighup.recv() => {
+                        tracing::info!("received SIGHUP, reloading settings");
+                        // a broken settings file must not take down the subsystem
+                        if let Err(err) = this.reload().await {
+                            tracing::error!(%err, "reloading settings failed, keeping current ones");
+                        }
+                    }
+                    _ = shutdown.requested() => return Ok(()),
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+thread_local! {
+    /// Keys of the environment variables read while [`Settings::env_vars`] records them; none
+    /// of them is read then, so that the settings are their defaults.
+    static RECORDED_ENV_VARS: RefCell<Option<Vec<String>>> = const { RefCell::new(None) };
+}
+
+/// Reads the environment variable `key`, unless keys are being recorded.
+fn env_var(key: &str) -> Result<String, std::env::VarError> {
+    let recorded = RECORDED_ENV_VARS.with_borrow_mut(|keys| {
+        keys.as_mut()
+            .map(|keys| keys.push(key.to_string()))
+            .is_some()
+    });
+    if recorded {
+        return Err(std::env::VarError::NotPresent);
+    }
+
+    std::env::var(key)
+}
+
+/// Parses the environment variable `key`, or returns `default` if it isn't set.
+pub(crate) fn env_or<T>(key: &str, default: T) -> miette::Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match env_var(key) {
+        Ok(value) => value
+            .parse()
+            .map_err(|err| miette::miette!("invalid value for {key}: {err}")),
+        Err(std::env::VarError::NotPresent) => Ok(default),
+        Err(err) => Err(err).into_diagnostic(),
+    }
+}
+
+/// Parses the environment variable `key`, or returns `None` if it isn't set.
+pub(crate) fn env_opt<T>(key: &str) -> miette::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match env_var(key) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|err| miette::miette!("invalid value for {key}: {err}")),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(err) => Err(err).into_diagnostic(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use pretty_assertions::assert_eq;
+
+    use super::{
+        Autoscaling, Canary, ExpiryAction, NameUniqueness, RenderOptions, Settings, SettingsStore,
+    };
+    use crate::auth::AuthMode;
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[test]
+    fn parse_name_uniqueness() {
+        assert_eq!("off".parse(), Ok(NameUniqueness::Off));
+        assert_eq!("warn".parse(), Ok(NameUniqueness::Warn));
+        assert_eq!("enforce".parse(), Ok(NameUniqueness::Enforce));
+        assert!("Enforce".parse::<NameUniqueness>().is_err());
+    }
+
+    #[test]
+    fn parse_expiry_action() {
+        assert_eq!("pause".parse(), Ok(ExpiryAction::Pause));
+        assert_eq!("delete".parse(), Ok(ExpiryAction::Delete));
+        assert!("purge".parse::<ExpiryAction>().is_err());
+    }
+
+    #[test]
+    fn parse_canary() {
+        assert_eq!("10%".parse(), Ok(Canary::Percent(10)));
+        assert_eq!(
+            "ci-host-01, ci-host-02".parse(),
+            Ok(Canary::Hosts(vec![
+                "ci-host-01".to_string(),
+                "ci-host-02".to_string()
+            ]))
+        );
+        assert_eq!(String::from(Canary::Percent(25)), "25%");
+        assert!("0%".parse::<Canary>().is_err());
+        assert!("101%".parse::<Canary>().is_err());
+        assert!(" , ".parse::<Canary>().is_err());
+    }
+
+    #[test]
+    fn load_settings_file() -> Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("runrs-settings-{}.toml", uuid::Uuid::new_v4()));
+        std::fs::write(
+            &path,
+            "name_uniqueness = \"enforce\"\n[quotas]\nmax_privileged = 1\n",
+        )?;
+
+        let settings = Settings::load(Some(&path))?;
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(settings.name_uniqueness, NameUniqueness::Enforce);
+        assert_eq!(settings.quotas.max_privileged, 1);
+        // settings not in the file keep their values
+        assert_eq!(
+            settings.quotas.max_runners,
+            Settings::default().quotas.max_runners
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn load_runner_globals() -> Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("runrs-settings-{}.toml", uuid::Uuid::new_v4()));
+
+        std::fs::write(&path, "[render]\nlisten_address = \"localhost\"\n")?;
+        assert!(Settings::load(Some(&path)).is_err());
+
+        std::fs::write(
+            &path,
+            "[render]\nlisten_address = \":9252\"\nsentry_dsn = \"https://key@sentry.io/42\"\n",
+        )?;
+        let settings = Settings::load(Some(&path))?;
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(
+            settings
+                .render
+                .listen_address
+                .as_ref()
+                .map(|addr| addr.as_str()),
+            Some(":9252")
+        );
+        assert!(settings.render.sentry_dsn.is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn unhealthy_thresholds() -> Result<()> {
+        let options = RenderOptions::default();
+        assert_eq!(options.unhealthy_thresholds(), (None, None));
+
+        let options = RenderOptions {
+            unhealthy_requests_limit: NonZeroU32::new(5).unwrap(),
+            unhealthy_interval: "30m".parse()?,
+            ..Default::default()
+        };
+        assert!(options.validate().is_ok());
+        assert_eq!(
+            options.unhealthy_thresholds(),
+            (Some(5), Some("30m".parse()?))
+        );
+
+        for interval in ["0", "0s", "-5m"] {
+            let options = RenderOptions {
+                unhealthy_interval: interval.parse()?,
+                ..Default::default()
+            };
+            assert!(options.validate().is_err());
+        }
+        let mut options = serde_json::to_value(RenderOptions::default())?;
+        options["unhealthy_requests_limit"] = 0.into();
+        assert!(serde_json::from_value::<RenderOptions>(options).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn replace_settings() {
+        let store = SettingsStore::default();
+        let snapshot = store.load();
+
+        let settings = Settings {
+            name_max_length: 42,
+            ..Default::default()
+        };
+        assert!(store.replace(settings.clone()).is_ok());
+        assert_eq!(*store.load(), settings);
+        // snapshots taken before are unaffected
+        assert_eq!(
+            snapshot.name_max_length,
+            Settings::default().name_max_length
+        );
+
+        let settings = Settings {
+            auth_mode: AuthMode::Disabled,
+            ..Default::default()
+        };
+        assert!(store.replace(settings).is_err());
+    }
+
+    #[test]
+    fn autoscaled_concurrent() -> Result<()> {
+        let autoscaling = Settings {
+            autoscaling: Autoscaling {
+                interval_secs: 30,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let store = SettingsStore::new(autoscaling.clone());
+        let concurrent = NonZeroU32::new(7).ok_or("zero")?;
+        store.set_autoscaled_concurrent(concurrent);
+        assert_eq!(store.load().render.concurrent, concurrent);
+
+        // replacing the settings keeps `concurrent` while autoscaling is on ...
+        let settings = Settings {
+            name_max_length: 42,
+            ..autoscaling
+        };
+        assert!(store.replace(settings).is_ok());
+        assert_eq!(store.load().name_max_length, 42);
+        assert_eq!(store.load().render.concurrent, concurrent);
+
+        // ... and not once it is turned off
+        assert!(store.load().render.concurrent_autoscaled);
+        assert!(store.replace(Settings::default()).is_ok());
+        assert!(!store.load().render.concurrent_autoscaled);
+        assert_eq!(
+            store.load().render.concurrent,
+            Settings::default().render.concurrent
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::time::Duration;
+
+use reqwest::Url;
+
+use crate::{
+    app::AppState,
+    models::{GitLabRunnerConfig, SystemEvent, SystemEventKind},
+};
+
+/// Actor of the system event recorded on shutdown.
+const SHUTDOWN_ACTOR: &str = "runrs";
+
+/// How long pushing the final metrics may take, so a gateway which is down doesn't hold up the
+/// shutdown.
+const PUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Leaves things in order before runrs exits: writes a final config snapshot if the config on
+/// disk drifted from the database, records the shutdown as a system event and flushes the final
+/// metrics, to the Prometheus Pushgateway at `pushgateway` if given and to the log. Call this once
+/// the server and the subsystems stopped, so that nothing changes underneath it. Failures are
+/// logged, since there's nobody left to report them to.
+pub async fn finalize(app_state: &AppState, pushgateway: Option<&Url>) {
+    let AppState {
+        pool,
+        config_target,
+        config_cache,
+        settings,
+        metrics,
+        ..
+    } = app_state;
+    let settings = settings.load();
+    let path = config_target.load();
+
+    let flushed = GitLabRunnerConfig::flush(pool, &path, config_cache, &settings.render).await;
+    let error = match flushed {
+        Ok(true) => {
+            tracing::warn!(
+                ?path,
+                "config drifted from the database, wrote final snapshot"
+            );
+            None
+        }
+        Ok(false) => {
+            tracing::debug!(?path, "config matches the database");
+            None
+        }
+        Err(err) => {
+            tracing::error!(%err, ?path, "writing final config snapshot failed");
+            Some(format!("writing final config snapshot failed: {err}"))
+        }
+    };
+
+    let recorded = SystemEvent::record(
+        pool,
+        SystemEventKind::Shutdown,
+        SHUTDOWN_ACTOR,
+        &path.display().to_string(),
+        error.as_deref(),
+    )
+    .await;
+    if let Err(err) = recorded {
+        tracing::error!(%err, "recording shutdown failed");
+    }
+
+    if let Err(err) = metrics.collect(pool).await {
+        tracing::error!(%err, "collecting final metrics failed");
+        return;
+    }
+    let rendered = metrics.render();
+    if let Some(url) = pushgateway {
+        match push(url, rendered.clone()).await {
+            Ok(()) => tracing::info!(%url, "pushed final metrics"),
+            Err(err) => tracing::error!(%err, %url, "pushing final metrics failed"),
+        }
+    }
+    tracing::info!(target: "runrs::metrics", metrics = rendered, "final metrics");
+}
+
+/// Replaces the metrics of the `runrs` job on the Pushgateway at `url` with `metrics`, in the
+/// Prometheus text format.
+async fn push(url: &Url, metrics: String) -> Result<(), reqwest::Error> {
+    let url = format!("{}/metrics/job/runrs", url.as_str().trim_end_matches('/'));
+
+    reqwest::Client::new()
+        .put(url)
+        .timeout(PUSH_TIMEOUT)
+        .header(reqwest::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(metrics)
+        .send()
+        .await?
+        .error_for_status()
+        .map(drop)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use atmosphere::{Create as _, Pool};
+    use axum::{extract::State, routing::put};
+    use pretty_assertions::assert_eq;
+
+    use super::finalize;
+    use crate::{
+        app::AppState,
+        models::{AuditEntry, AuditLogFilter, GitLabRunner, SystemEvent, SystemEventFilter},
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    #[tracing_test::traced_test]
+    async fn finalize_flushes_drifted_config(pool: Pool) -> Result<()> {
+        // stands in for a Prometheus Pushgateway
+        let pushed = Arc::new(Mutex::new(None));
+        let gateway = axum::Router::new()
+            .route(
+                "/metrics/job/runrs",
+                put(
+                    |State(pushed): State<Arc<Mutex<Option<String>>>>, body: String| async move {
+                        *pushed.lock().unwrap() = Some(body);
+                    },
+                ),
+            )
+            .with_state(pushed.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let gateway_url = format!("http://{}/", listener.local_addr()?).parse()?;
+        tokio::spawn(async move { axum::serve(listener, gateway).await });
+
+        let app_state = AppState::for_testing(pool.clone());
+        let path = app_state.config_target.load();
+
+        // a runner the config cache doesn't know about, so the config on disk drifted
+        let mut runner = GitLabRunner::for_testing();
+        runner.create(&pool).await?;
+        std::fs::write(&*path, "")?;
+
+        finalize(&app_state, Some(&gateway_url)).await;
+        assert!(std::fs::read_to_string(&*path)?.contains("Knows the meaning of life"));
+
+        // the shutdown is no request, so it's not in the audit log
+        assert_eq!(
+            AuditEntry::list(&pool, &AuditLogFilter::default()).await?.1,
+            0
+        );
+        let (events, total) = SystemEvent::list(&pool, &SystemEventFilter::default()).await?;
+        assert_eq!(total, 1);
+        let event = serde_json::to_value(&events[0])?;
+        assert_eq!(event["kind"], "shutdown");
+        assert_eq!(event["actor"], "runrs");
+        assert_eq!(event["subject"], path.display().to_string());
+        assert!(event.get("error").is_none());
+
+        let pushed = pushed.lock().unwrap().take().ok_or("no metrics pushed")?;
+        assert!(pushed.contains("runrs_runners{"));
+
+        std::fs::remove_file(&*path)?;
+
+        Ok(())
+    }
+}
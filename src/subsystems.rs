@@ -0,0 +1,258 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use serde::Serialize;
+use tokio::{
+    sync::{watch, Mutex, RwLock},
+    task::JoinSet,
+    time::Instant,
+};
+use utoipa::ToSchema;
+
+use crate::error::Error;
+
+/// Upper bound for the delay before restarting a failed subsystem. A subsystem which ran for
+/// longer than this before failing is restarted right away.
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Delay before restarting a subsystem which failed for the first time; doubled for every
+/// consecutive failure.
+const INITIAL_RESTART_BACKOFF: Duration = Duration::from_secs(1);
+
+/// How long [`Supervisor::shutdown`] waits for subsystems to finish.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A long-running background task, managed by the [`Supervisor`].
+pub trait Subsystem: Send + Sync + 'static {
+    /// Name of the subsystem; must be unique.
+    fn name(&self) -> &'static str;
+
+    /// Runs the subsystem until `shutdown` is signalled. Returning an error (or panicking) makes
+    /// the supervisor restart the subsystem with backoff; returning `Ok` stops it for good.
+    fn run(&self, shutdown: Shutdown) -> BoxFuture<'static, Result<(), Error>>;
+}
+
+/// Signals subsystems to shut down.
+#[derive(Debug, Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    /// Completes once shutdown is requested.
+    pub async fn requested(&mut self) {
+        // an error means the supervisor is gone, which is as good as a shutdown request
+        let _ = self.0.wait_for(|requested| *requested).await;
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.0.borrow()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SubsystemState {
+    Running,
+    /// The subsystem failed and is waiting to be restarted
+    Restarting,
+    Stopped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct SubsystemStatus {
+    #[schema(example = "config-write-retry")]
+    name: &'static str,
+    state: SubsystemState,
+    /// Number of times the subsystem was restarted after failing
+    restarts: u32,
+    /// Reason the subsystem failed last
+    last_error: Option<String>,
+    #[schema(value_type = String, format = DateTime, example = "2024-06-19T08:00:00Z")]
+    started_at: DateTime<Utc>,
+}
+
+/// Spawns, monitors, restarts and shuts down all [`Subsystem`]s.
+#[derive(Debug)]
+pub struct Supervisor {
+    shutdown: watch::Sender<bool>,
+    statuses: Arc<RwLock<BTreeMap<&'static str, SubsystemStatus>>>,
+    tasks: Mutex<JoinSet<()>>,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Self {
+            shutdown: watch::Sender::new(false),
+            statuses: Arc::default(),
+            tasks: Mutex::default(),
+        }
+    }
+}
+
+impl Supervisor {
+    /// Spawns the subsystem and restarts it whenever it fails, until shutdown.
+    pub async fn spawn<S: Subsystem>(&self, subsystem: S) {
+        let subsystem = Arc::new(subsystem);
+        let statuses = self.statuses.clone();
+        let shutdown = Shutdown(self.shutdown.subscribe());
+
+        self.tasks
+            .lock()
+            .await
+            .spawn(supervise(subsystem, statuses, shutdown));
+    }
+
+    /// Returns the status of every subsystem, ordered by name.
+    pub async fn statuses(&self) -> Vec<SubsystemStatus> {
+        self.statuses.read().await.values().cloned().collect()
+    }
+
+    /// Signals all subsystems to shut down and waits for them to finish, for a while.
+    pub async fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+
+        let mut tasks = self.tasks.lock().await;
+        let finished = async { while tasks.join_next().await.is_some() {} };
+
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, finished)
+            .await
+            .is_err()
+        {
+            tracing::warn!("subsystems did not shut down in time, aborting them");
+            tasks.abort_all();
+        }
+    }
+}
+
+async fn supervise<S: Subsystem>(
+    subsystem: Arc<S>,
+    statuses: Arc<RwLock<BTreeMap<&'static str, SubsystemStatus>>>,
+    mut shutdown: Shutdown,
+) {
+    let name = subsystem.name();
+    let mut backoff = INITIAL_RESTART_BACKOFF;
+
+    statuses.write().await.insert(
+        name,
+        SubsystemStatus {
+            name,
+            state: SubsystemState::Running,
+            restarts: 0,
+            last_error: None,
+            started_at: Utc::now(),
+        },
+    );
+
+    loop {
+        tracing::info!(name, "starting subsystem");
+        let started = Instant::now();
+
+        // running the subsystem in a task of its own turns panics into errors
+        let error = match tokio::spawn(subsystem.run(shutdown.clone())).await {
+            Ok(Ok(())) => break,
+            Ok(Err(err)) => err.msg,
+            Err(err) => err.to_string(),
+        };
+
+        if shutdown.is_requested() {
+            break;
+        }
+
+        if started.elapsed() > MAX_RESTART_BACKOFF {
+            backoff = INITIAL_RESTART_BACKOFF;
+        }
+        tracing::error!(name, %error, ?backoff, "subsystem failed, restarting");
+
+        if let Some(status) = statuses.write().await.get_mut(name) {
+            status.state = SubsystemState::Restarting;
+            status.restarts += 1;
+            status.last_error = Some(error);
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => {}
+            _ = shutdown.requested() => break,
+        }
+        backoff = (backoff * 2).min(MAX_RESTART_BACKOFF);
+
+        if let Some(status) = statuses.write().await.get_mut(name) {
+            status.state = SubsystemState::Running;
+            status.started_at = Utc::now();
+        }
+    }
+
+    tracing::info!(name, "subsystem stopped");
+    if let Some(status) = statuses.write().await.get_mut(name) {
+        status.state = SubsystemState::Stopped;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use futures::{future::BoxFuture, FutureExt};
+    use pretty_assertions::assert_eq;
+
+    use super::{Shutdown, Subsystem, SubsystemState, Supervisor, INITIAL_RESTART_BACKOFF};
+    use crate::error::Error;
+
+    /// Fails on its first run, then runs until shutdown.
+    #[derive(Default)]
+    struct Flaky(Arc<AtomicU32>);
+
+    impl Subsystem for Flaky {
+        fn name(&self) -> &'static str {
+            "flaky"
+        }
+
+        fn run(&self, mut shutdown: Shutdown) -> BoxFuture<'static, Result<(), Error>> {
+            let runs = self.0.fetch_add(1, Ordering::AcqRel);
+
+            async move {
+                if runs == 0 {
+                    return Err(Error::internal_error("warbl"));
+                }
+                shutdown.requested().await;
+                Ok(())
+            }
+            .boxed()
+        }
+    }
+
+    // with the clock paused, sleeps complete in order as soon as all tasks are idle, so the test
+    // doesn't depend on how fast the machine is
+    #[tokio::test(start_paused = true)]
+    async fn restart_failed_subsystem() {
+        let supervisor = Supervisor::default();
+        let runs = Arc::new(AtomicU32::new(0));
+        supervisor.spawn(Flaky(runs.clone())).await;
+
+        // let the first run fail and the backoff elapse
+        tokio::time::sleep(INITIAL_RESTART_BACKOFF + Duration::from_millis(500)).await;
+        assert_eq!(runs.load(Ordering::Acquire), 2);
+
+        let statuses = supervisor.statuses().await;
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0].state, SubsystemState::Running);
+        assert_eq!(statuses[0].restarts, 1);
+        assert_eq!(
+            statuses[0].last_error.as_deref(),
+            Some("internal error: warbl")
+        );
+
+        supervisor.shutdown().await;
+        assert_eq!(
+            supervisor.statuses().await[0].state,
+            SubsystemState::Stopped
+        );
+    }
+}
@@ -0,0 +1,145 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::path::PathBuf;
+
+use axum_server::tls_rustls::RustlsConfig;
+use futures::{future::BoxFuture, FutureExt};
+use miette::IntoDiagnostic;
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::{
+    error::Error,
+    subsystems::{Shutdown, Subsystem},
+};
+
+/// Certificate and private key the API is served with via HTTPS, both PEM encoded. The
+/// certificate file may hold the full chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsFiles {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+impl TlsFiles {
+    /// Returns the files from `TLS_CERT_PATH` and `TLS_KEY_PATH` as read via `lookup`, or `None`
+    /// if neither is set, in which case the API is served via plain HTTP.
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> miette::Result<Option<Self>> {
+        match (lookup("TLS_CERT_PATH"), lookup("TLS_KEY_PATH")) {
+            (Some(cert_path), Some(key_path)) => Ok(Some(Self {
+                cert_path: cert_path.into(),
+                key_path: key_path.into(),
+            })),
+            (None, None) => Ok(None),
+            _ => miette::bail!(
+                help = "set both to serve the API via HTTPS, or neither to serve it via HTTP",
+                "TLS_CERT_PATH and TLS_KEY_PATH must be set together"
+            ),
+        }
+    }
+
+    pub fn from_env() -> miette::Result<Option<Self>> {
+        Self::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    /// Reads the certificate and key, failing if either is missing or invalid.
+    pub async fn load(&self) -> miette::Result<RustlsConfig> {
+        RustlsConfig::from_pem_file(&self.cert_path, &self.key_path)
+            .await
+            .map_err(|err| {
+                miette::miette!(
+                    "can't load TLS certificate {} with key {}: {err}",
+                    self.cert_path.display(),
+                    self.key_path.display()
+                )
+            })
+    }
+}
+
+/// Reloads the TLS certificate and key whenever the process receives SIGHUP, so that renewed
+/// certificates, e.g. from Let's Encrypt, are used for new connections without a restart.
+#[derive(Clone)]
+pub struct CertificateReload {
+    files: TlsFiles,
+    config: RustlsConfig,
+}
+
+impl CertificateReload {
+    pub fn new(files: TlsFiles, config: RustlsConfig) -> Self {
+        Self { files, config }
+    }
+
+    async fn reload(&self) -> miette::Result<()> {
+        self.config
+            .reload_from_pem_file(&self.files.cert_path, &self.files.key_path)
+            .await
+            .into_diagnostic()
+    }
+}
+
+impl Subsystem for CertificateReload {
+    fn name(&self) -> &'static str {
+        "certificate-reload"
+    }
+
+    fn run(&self, mut shutdown: Shutdown) -> BoxFuture<'static, Result<(), Error>> {
+        let this = self.clone();
+
+        async move {
+            let mut sighup = signal(SignalKind::hangup()).map_err(Error::internal_error)?;
+
+            loop {
+                tokio::select! {
+                    _ = sighup.recv() => {
+                        tracing::info!("received SIGHUP, reloading TLS certificate");
+                        // a half-written renewal must not take down the subsystem
+                        match this.reload().await {
+                            Ok(()) => tracing::info!("TLS certificate reloaded"),
+                            Err(err) => tracing::error!(
+                                %err,
+                                "reloading TLS certificate failed, keeping current one"
+                            ),
+                        }
+                    }
+                    _ = shutdown.requested() => return Ok(()),
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    use super::TlsFiles;
+
+    #[test]
+    fn files_from_env() -> miette::Result<()> {
+        assert_eq!(TlsFiles::from_lookup(|_| None)?, None);
+
+        let files = TlsFiles::from_lookup(|key| match key {
+            "TLS_CERT_PATH" => Some("/etc/runrs/tls/fullchain.pem".to_string()),
+            "TLS_KEY_PATH" => Some("/etc/runrs/tls/privkey.pem".to_string()),
+            _ => None,
+        })?;
+        assert_eq!(
+            files.map(|files| files.key_path),
+            Some("/etc/runrs/tls/privkey.pem".into())
+        );
+
+        let cert_only = |key: &str| (key == "TLS_CERT_PATH").then(|| "cert.pem".to_string());
+        assert!(TlsFiles::from_lookup(cert_only).is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn missing_files() {
+        let files = TlsFiles {
+            cert_path: "/nonexistent/cert.pem".into(),
+            key_path: "/nonexistent/key.pem".into(),
+        };
+        assert!(files.load().await.is_err());
+    }
+}
@@ -0,0 +1,72 @@
+// Copyright 2024 bmc::labs GmbH. All rights reserved.
+
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::{
+    error::Error,
+    models::{ChangedRunner, ExpiryNotice},
+};
+
+/// How long a webhook receiver may take to respond before delivery counts as failed.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Delivers events to webhook receivers as JSON via `POST`.
+#[derive(Debug, Clone, Default)]
+pub struct WebhookClient(reqwest::Client);
+
+impl WebhookClient {
+    /// Posts the event to `url`. Delivery only succeeds if the receiver responds with a success
+    /// status code.
+    pub async fn post<T: Serialize>(&self, url: &str, event: &T) -> Result<(), Error> {
+        send(self.0.post(url), event).await
+    }
+
+    /// Like [`post`](Self::post), but sends `dedup_key` in the `Idempotency-Key` header, so that
+    /// receivers can drop events which are delivered more than once.
+    pub async fn post_idempotent<T: Serialize>(
+        &self,
+        url: &str,
+        dedup_key: &str,
+        event: &T,
+    ) -> Result<(), Error> {
+        send(self.0.post(url).header("Idempotency-Key", dedup_key), event).await
+    }
+
+    /// Sends `body` to `url` via `PUT`, authenticated with `token` as bearer token.
+    pub async fn put_authorized<T: Serialize>(
+        &self,
+        url: reqwest::Url,
+        token: &str,
+        body: &T,
+    ) -> Result<(), Error> {
+        send(self.0.put(url).bearer_auth(token), body).await
+    }
+}
+
+async fn send<T: Serialize>(request: reqwest::RequestBuilder, event: &T) -> Result<(), Error> {
+    request
+        .timeout(WEBHOOK_TIMEOUT)
+        .json(event)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|err| Error::connection_failed(format!("delivering webhook failed: {err}")))?;
+
+    Ok(())
+}
+
+/// Events delivered to webhook receivers, tagged with their kind in the `event` field.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// A runner is about to expire, or has expired, and is paused or deleted afterwards.
+    RunnerExpiring(&'a ExpiryNotice),
+    /// A runner was created or registered.
+    RunnerCreated(&'a ChangedRunner),
+    /// A runner was updated.
+    RunnerUpdated(&'a ChangedRunner),
+    /// A runner was deleted.
+    RunnerDeleted(&'a ChangedRunner),
+}